@@ -0,0 +1,79 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+#[allow(dead_code)]
+#[path = "../src/utilities.rs"]
+mod utilities;
+
+const EMAIL: &str = "player@example.com";
+const PLAYER_TOKEN: &str = "d0a6c5f1-8b8e-4f1e-9c37-2f4b1b7e90aa";
+const USERDATA_AUTH: &str = "userdata-auth-secret";
+const DISCORD_ID: &str = "123456789012345678";
+
+/// the token encoding the update path used before, kept to compare against
+fn joined_user_token(email: &str, token: &str, userdata_auth: &str) -> String {
+    let mut user_token = Hmac::<Sha1>::new_from_slice(userdata_auth.as_bytes()).unwrap();
+    user_token.update(email.as_bytes());
+    user_token.update(token.as_bytes());
+
+    user_token
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x?}", byte))
+        .collect::<Vec<String>>()
+        .join("")
+}
+
+fn log_message(discord_id: &str) -> String {
+    format!(
+        "user with ID {} had a successful request but gained no roles",
+        discord_id
+    )
+}
+
+/// the old path, the token is cloned for every log and the throttled log message is always built
+fn eager_update(email: &str) -> String {
+    let user_token = joined_user_token(email, PLAYER_TOKEN, USERDATA_AUTH);
+    let logged_tokens = [user_token.to_owned(), user_token.to_owned(), user_token];
+    let logged_roles = log_message(DISCORD_ID);
+    black_box((&logged_tokens, &logged_roles));
+
+    serde_json::json!({ "message": "The request was successful" }).to_string()
+}
+
+/// the current path, logs borrow the token and the throttled log message is never built
+fn lazy_update(email: &str) -> String {
+    let user_token = utilities::encode_user_token(email, PLAYER_TOKEN, USERDATA_AUTH);
+    let logged_tokens = [&user_token[..], &user_token[..], &user_token[..]];
+    let throttled = true;
+    let logged_roles = (!throttled).then(|| log_message(DISCORD_ID));
+    black_box((&logged_tokens, &logged_roles));
+
+    serde_json::json!({ "message": "The request was successful" }).to_string()
+}
+
+fn token_derivation(c: &mut Criterion) {
+    assert_eq!(
+        joined_user_token(EMAIL, PLAYER_TOKEN, USERDATA_AUTH),
+        utilities::encode_user_token(EMAIL, PLAYER_TOKEN, USERDATA_AUTH)
+    );
+
+    let mut group = c.benchmark_group("token_derivation");
+    group.bench_function("joined", |b| {
+        b.iter(|| joined_user_token(black_box(EMAIL), PLAYER_TOKEN, USERDATA_AUTH))
+    });
+    group.bench_function("preallocated", |b| {
+        b.iter(|| utilities::encode_user_token(black_box(EMAIL), PLAYER_TOKEN, USERDATA_AUTH))
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("update_response");
+    group.bench_function("eager", |b| b.iter(|| eager_update(black_box(EMAIL))));
+    group.bench_function("lazy", |b| b.iter(|| lazy_update(black_box(EMAIL))));
+    group.finish();
+}
+
+criterion_group!(benches, token_derivation);
+criterion_main!(benches);
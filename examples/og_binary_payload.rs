@@ -0,0 +1,42 @@
+use serde::Serialize;
+
+#[allow(dead_code)]
+#[path = "../src/og_binary.rs"]
+mod og_binary;
+
+/// the fields of the OG payload in the order they're encoded in, a client keeps its own copy
+#[derive(Serialize)]
+struct OgPayload<'a> {
+    player_token: &'a str,
+    beta_tester: Option<bool>,
+    metabits: f64,
+    dino_rank: i32,
+    prestige_rank: i32,
+    beyond_rank: i32,
+    singularity_speedrun_time: Option<f64>,
+    all_sharks_obtained: bool,
+    all_hidden_achievements_obtained: bool,
+}
+
+/// Prints the body a client sends to `POST userdata?playerId=...` with
+/// `Content-Type: application/octet-stream`. Responses to `Accept: application/octet-stream` are
+/// read with `og_binary::decode_frame` into a copy of `OGBinaryResponse`, fields in the same order.
+fn main() {
+    let payload = OgPayload {
+        player_token: "d0a6c5f1-8b8e-4f1e-9c37-2f4b1b7e90aa",
+        beta_tester: Some(false),
+        metabits: 1_500_000.0,
+        dino_rank: 40,
+        prestige_rank: 2,
+        beyond_rank: 0,
+        singularity_speedrun_time: Some(1234.5),
+        all_sharks_obtained: true,
+        all_hidden_achievements_obtained: false,
+    };
+    let frame = og_binary::encode_frame(&payload).unwrap();
+    let hex = frame
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    println!("request body ({} bytes): {}", frame.len(), hex);
+}
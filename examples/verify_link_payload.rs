@@ -0,0 +1,34 @@
+#[allow(dead_code)]
+#[path = "../src/link_codes.rs"]
+mod link_codes;
+
+use link_codes::{verify_link_payload, LinkPayload, LinkPayloadError};
+use std::time::SystemTime;
+
+/// Checks the payload of a QR code the bot shows the way the game does before it asks the player
+/// for their credentials, `src/link_codes.rs` only needs `hmac`, `sha2` and `base64`. The payload
+/// is then sent as the `link_code` of `POST v2/userdata`, which uses the code up.
+fn main() {
+    let secret = std::env::var("PORTABILITY_SECRET").unwrap_or_else(|_| "secret".to_owned());
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let payload = std::env::args().nth(1).unwrap_or_else(|| {
+        LinkPayload {
+            code: "ABCD2345".to_owned(),
+            discord_id: "80351110224678912".to_owned(),
+            expires_at: now + 600,
+        }
+        .sign(&secret)
+    });
+
+    match verify_link_payload(&payload, &secret, now) {
+        Ok(payload) => println!(
+            "link the player with discord id {} using code {}",
+            payload.discord_id, payload.code
+        ),
+        Err(LinkPayloadError::Expired) => println!("the code expired, ask the bot for a new one"),
+        Err(error) => println!("this isn't a link code from the bot: {:?}", error),
+    }
+}
@@ -0,0 +1,26 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/utilities.rs"]
+mod utilities;
+
+// the Authorization header of every v2 request, `headers::Authorization::parse` hands it to this
+fuzz_target!(|header: &str| {
+    let auth = match utilities::safe_basic_auth_decoder(header) {
+        Ok(auth) => auth,
+        Err(_) => return,
+    };
+
+    // the credentials are split at the colons, so neither half can have one
+    assert!(!auth.email.contains(':'), "{:?}", auth);
+    assert!(!auth.token.contains(':'), "{:?}", auth);
+
+    // what was read is read the same way again
+    let encoded = format!(
+        "Basic {}",
+        base64::encode(format!("{}:{}", auth.email, auth.token))
+    );
+    assert_eq!(utilities::safe_basic_auth_decoder(&encoded).unwrap(), auth);
+});
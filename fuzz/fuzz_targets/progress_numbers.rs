@@ -0,0 +1,41 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use serde::{Deserialize, Serialize};
+
+#[allow(dead_code)]
+#[path = "../../src/large_numbers.rs"]
+mod large_numbers;
+
+/// the number fields of the progress payloads, read the way `UpdateUserData` and `PatchUserData`
+/// read them
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct Payload {
+    #[serde(deserialize_with = "large_numbers::number_or_string_f64")]
+    metabits: f64,
+    #[serde(
+        default,
+        deserialize_with = "large_numbers::option_number_or_string_f64"
+    )]
+    patched_metabits: Option<f64>,
+    dino_rank: Option<i32>,
+    singularity_speedrun_time: Option<f64>,
+}
+
+/// the metabits of the stored `UserData`, which are written as strings
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+struct Stored {
+    #[serde(with = "large_numbers::string_i64")]
+    metabits: i64,
+}
+
+fuzz_target!(|json: &[u8]| {
+    let _ = serde_json::from_slice::<Payload>(json);
+
+    if let Ok(stored) = serde_json::from_slice::<Stored>(json) {
+        let written = serde_json::to_string(&stored).unwrap();
+        assert_eq!(written, format!("{{\"metabits\":\"{}\"}}", stored.metabits));
+        assert_eq!(serde_json::from_str::<Stored>(&written).unwrap(), stored);
+    }
+});
@@ -0,0 +1,40 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/utilities.rs"]
+mod utilities;
+
+fn is_lowercase_hex(encoded: &str) -> bool {
+    encoded
+        .bytes()
+        .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+}
+
+fn hex_decode(encoded: &str) -> Vec<u8> {
+    (0..encoded.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(&encoded[at..at + 2], 16).unwrap())
+        .collect()
+}
+
+// the og endpoint takes the player id and token from the request as they are
+fuzz_target!(|input: (&[u8], &str, &str, &str)| {
+    let (bytes, player_id, player_token, secret) = input;
+
+    let encoded = utilities::hex_encode(bytes);
+    assert_eq!(encoded.len(), bytes.len() * 2);
+    assert!(is_lowercase_hex(&encoded), "{}", encoded);
+    assert_eq!(hex_decode(&encoded), bytes);
+
+    let tokens = utilities::derive_og_user_tokens(player_id, player_token, secret);
+    assert_eq!(tokens.v2.len(), 64);
+    assert_eq!(tokens.legacy.len(), 40);
+    assert!(is_lowercase_hex(&tokens.v2) && is_lowercase_hex(&tokens.legacy));
+    // both endpoints have to find the same row
+    assert_eq!(
+        tokens,
+        utilities::derive_user_tokens(player_id, player_token, secret)
+    );
+});
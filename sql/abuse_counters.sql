@@ -0,0 +1,7 @@
+CREATE TABLE "AbuseCounters" (
+    "counter" TEXT NOT NULL,
+    "key" TEXT NOT NULL,
+    "value" BIGINT NOT NULL,
+    "expires_timestamp" TIMESTAMP(3) NOT NULL,
+    CONSTRAINT "AbuseCounters_pkey" PRIMARY KEY ("counter", "key")
+);
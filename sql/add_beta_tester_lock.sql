@@ -0,0 +1,3 @@
+ALTER TABLE "UserData"
+ADD COLUMN "beta_tester_locked" BOOLEAN NOT NULL DEFAULT false;
+-- a locked beta_tester flag was set by an admin and syncs leave it alone until it's unlocked
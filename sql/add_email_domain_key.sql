@@ -0,0 +1,3 @@
+ALTER TABLE "UserData"
+ADD COLUMN "email_domain_key" TEXT NOT NULL DEFAULT '';
+-- links from before domains were counted keep the empty key and aren't counted
@@ -0,0 +1,2 @@
+ALTER TABLE "UserData"
+ADD COLUMN "flagged_for_review" BOOLEAN NOT NULL DEFAULT false;
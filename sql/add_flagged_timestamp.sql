@@ -0,0 +1,2 @@
+ALTER TABLE "UserData"
+ADD COLUMN "flagged_timestamp" TIMESTAMP(3);
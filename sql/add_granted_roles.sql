@@ -0,0 +1,2 @@
+ALTER TABLE "UserData"
+ADD COLUMN "granted_roles" JSONB NOT NULL DEFAULT '{}';
\ No newline at end of file
@@ -0,0 +1,3 @@
+ALTER TABLE "UserData"
+ADD COLUMN "last_seen_timestamp" TIMESTAMP(3);
+-- POST user/touch bumps it without changing edited_timestamp, which stays the time of the last write
@@ -0,0 +1,3 @@
+-- users opt out of the progress tier prefix in front of their nickname with PATCH v2/userdata/privacy
+ALTER TABLE "UserData"
+ADD COLUMN "nickname_prefix" BOOLEAN NOT NULL DEFAULT true;
\ No newline at end of file
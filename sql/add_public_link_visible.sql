@@ -0,0 +1,2 @@
+ALTER TABLE "UserData"
+ADD COLUMN "public_link_visible" BOOLEAN NOT NULL DEFAULT false;
\ No newline at end of file
@@ -0,0 +1,4 @@
+ALTER TABLE "UserData"
+ADD COLUMN "last_sync_week" BIGINT,
+  ADD COLUMN "current_streak" INTEGER NOT NULL DEFAULT 0,
+  ADD COLUMN "longest_streak" INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+ALTER TABLE "UserData"
+ADD COLUMN "token_fingerprint" TEXT NOT NULL DEFAULT '';
+-- the same prefix token_fingerprint takes, new and rekeyed rows get theirs from the application
+UPDATE "UserData"
+SET "token_fingerprint" = LEFT("token", 8);
+CREATE INDEX "UserData_token_fingerprint_idx" ON "UserData" ("token_fingerprint");
\ No newline at end of file
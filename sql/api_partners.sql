@@ -0,0 +1,11 @@
+CREATE TABLE "ApiPartners" (
+    "id" BIGSERIAL NOT NULL,
+    "key_hash" TEXT NOT NULL,
+    "label" TEXT NOT NULL,
+    "multiplier" INTEGER NOT NULL,
+    "expires_at" TIMESTAMP(3),
+    "created_timestamp" TIMESTAMP(3) NOT NULL,
+    CONSTRAINT "ApiPartners_pkey" PRIMARY KEY ("id"),
+    CONSTRAINT "ApiPartners_key_hash_key" UNIQUE ("key_hash"),
+    CONSTRAINT "ApiPartners_multiplier_check" CHECK ("multiplier" >= 1)
+);
@@ -0,0 +1,10 @@
+CREATE TABLE "AuditLog" (
+    "id" BIGSERIAL NOT NULL,
+    "actor" TEXT NOT NULL,
+    "key_label" TEXT NOT NULL,
+    "endpoint" TEXT NOT NULL,
+    "target_discord_id" TEXT,
+    "parameters" JSONB NOT NULL DEFAULT '{}',
+    "created_timestamp" TIMESTAMP(3) NOT NULL,
+    CONSTRAINT "AuditLog_pkey" PRIMARY KEY ("id")
+);
@@ -0,0 +1,4 @@
+-- primary tokens are moved to their v2 derivation when their user comes by, the recovery token has to follow
+ALTER TABLE "RecoveryTokens"
+DROP CONSTRAINT "RecoveryTokens_primary_token_fkey",
+ADD CONSTRAINT "RecoveryTokens_primary_token_fkey" FOREIGN KEY ("primary_token") REFERENCES "UserData" ("token") ON DELETE CASCADE ON UPDATE CASCADE;
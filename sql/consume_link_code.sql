@@ -0,0 +1,6 @@
+UPDATE "LinkCodes"
+SET "consumed_timestamp" = $2
+WHERE "code" = $1
+    AND "consumed_timestamp" IS NULL
+    AND "expires_timestamp" > $2
+RETURNING "discord_id";
\ No newline at end of file
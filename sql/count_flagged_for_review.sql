@@ -0,0 +1,4 @@
+SELECT COUNT(*)
+FROM "UserData"
+WHERE "flagged_timestamp" >= $1
+  AND "flagged_timestamp" < $2;
@@ -0,0 +1,27 @@
+-- a rebound discord id keeps the previous account's key until it's replaced here, so the previous
+-- domain gives up the link and the new one gets it, a rebind within the same domain changes nothing
+WITH previous AS (
+    SELECT "email_domain_key"
+    FROM "UserData"
+    WHERE "discord_id" = $1
+),
+released AS (
+    UPDATE "DomainCounts"
+    SET "linked" = GREATEST("linked" - 1, 0)
+    WHERE "domain_key" = (SELECT "email_domain_key" FROM previous)
+        AND "domain_key" <> $2
+),
+relinked AS (
+    UPDATE "UserData"
+    SET "email_domain_key" = $2
+    WHERE "discord_id" = $1
+)
+INSERT INTO "DomainCounts" ("domain_key", "linked")
+SELECT $2, 1
+WHERE NOT EXISTS (
+        SELECT 1
+        FROM previous
+        WHERE "email_domain_key" = $2
+    ) ON CONFLICT ("domain_key") DO
+UPDATE
+SET "linked" = "DomainCounts"."linked" + 1;
@@ -0,0 +1,8 @@
+SELECT COUNT(*)
+FROM (
+    SELECT MIN("edited_timestamp") AS "first_timestamp"
+    FROM "UserDataSnapshots"
+    GROUP BY "discord_id"
+  ) AS "FirstSnapshots"
+WHERE "first_timestamp" >= $1
+  AND "first_timestamp" < $2;
\ No newline at end of file
@@ -0,0 +1,2 @@
+SELECT COUNT(*)
+FROM "UserData";
\ No newline at end of file
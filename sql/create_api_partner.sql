@@ -0,0 +1,3 @@
+INSERT INTO "ApiPartners" ("key_hash", "label", "multiplier", "expires_at", "created_timestamp")
+VALUES ($1, $2, $3, $4, $5)
+RETURNING *;
@@ -0,0 +1,9 @@
+INSERT INTO "AuditLog" (
+    "actor",
+    "key_label",
+    "endpoint",
+    "target_discord_id",
+    "parameters",
+    "created_timestamp"
+  )
+VALUES ($1, $2, $3, $4, $5::TEXT::JSONB, $6);
\ No newline at end of file
@@ -0,0 +1,5 @@
+INSERT INTO "IdempotencyKeys" ("token", "idempotency_key", "response", "created_timestamp")
+VALUES ($1, $2, $3, $4)
+ON CONFLICT ("token", "idempotency_key") DO UPDATE
+SET "response" = EXCLUDED."response",
+    "created_timestamp" = EXCLUDED."created_timestamp";
\ No newline at end of file
@@ -0,0 +1,2 @@
+INSERT INTO "ImportFailures" ("job_id", "line", "reason", "row", "created_timestamp")
+VALUES ($1, $2, $3, $4, $5);
\ No newline at end of file
@@ -0,0 +1,2 @@
+INSERT INTO "LinkCodes" ("code", "discord_id", "expires_timestamp")
+VALUES ($1, $2, $3);
\ No newline at end of file
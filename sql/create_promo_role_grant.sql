@@ -0,0 +1,2 @@
+INSERT INTO "PromoRoleGrants" ("rule_id", "discord_id", "created_timestamp")
+VALUES ($1, $2, $3) ON CONFLICT ("rule_id", "discord_id") DO NOTHING;
@@ -0,0 +1,3 @@
+INSERT INTO "PromoRoleRules" ("role_id", "name", "starts_at", "ends_at", "created_timestamp")
+VALUES ($1, $2, $3, $4, $5)
+RETURNING *;
@@ -0,0 +1,2 @@
+INSERT INTO "TokenTransitions" ("discord_id", "token", "created_timestamp")
+VALUES ($1, $2, $3) ON CONFLICT ("discord_id", "token") DO NOTHING;
\ No newline at end of file
@@ -9,7 +9,8 @@ INSERT INTO "UserData" (
     "singularity_speedrun_time",
     "all_sharks_obtained",
     "all_hidden_achievements_obtained",
-    "edited_timestamp"
+    "edited_timestamp",
+    "token_fingerprint"
   )
 VALUES (
     $1,
@@ -22,11 +23,15 @@ VALUES (
     $8,
     $9,
     $10,
-    $11
+    $11,
+    $12
   ) ON CONFLICT ("discord_id") DO
 UPDATE
 SET "token" = $1,
-  "beta_tester" = $3,
+  "beta_tester" = CASE
+    WHEN "UserData"."beta_tester_locked" THEN "UserData"."beta_tester"
+    ELSE $3
+  END,
   "metabits" = $4,
   "dino_rank" = $5,
   "prestige_rank" = $6,
@@ -34,6 +39,8 @@ SET "token" = $1,
   "singularity_speedrun_time" = $8,
   "all_sharks_obtained" = $9,
   "all_hidden_achievements_obtained" = $10,
-  "edited_timestamp" = $11
+  "edited_timestamp" = $11,
+  "token_fingerprint" = $12
 WHERE "UserData"."discord_id" = $2
+  AND "UserData"."token" <> $1
 RETURNING *;
\ No newline at end of file
@@ -0,0 +1,14 @@
+INSERT INTO "UserDataSnapshots" (
+    "token",
+    "discord_id",
+    "beta_tester",
+    "metabits",
+    "dino_rank",
+    "prestige_rank",
+    "beyond_rank",
+    "singularity_speedrun_time",
+    "all_sharks_obtained",
+    "all_hidden_achievements_obtained",
+    "edited_timestamp"
+  )
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11);
\ No newline at end of file
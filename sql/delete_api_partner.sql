@@ -0,0 +1,3 @@
+DELETE FROM "ApiPartners"
+WHERE "id" = $1
+RETURNING *;
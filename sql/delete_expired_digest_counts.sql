@@ -0,0 +1,2 @@
+DELETE FROM "DigestCounts"
+WHERE "hour" < $1;
@@ -0,0 +1,2 @@
+DELETE FROM "ImportFailures"
+WHERE "created_timestamp" < $1;
\ No newline at end of file
@@ -0,0 +1,2 @@
+DELETE FROM "LinkCodes"
+WHERE "expires_timestamp" < $1;
\ No newline at end of file
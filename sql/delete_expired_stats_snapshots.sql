@@ -0,0 +1,2 @@
+DELETE FROM "StatsSnapshots"
+WHERE "day" < $1;
\ No newline at end of file
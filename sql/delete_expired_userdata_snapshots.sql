@@ -0,0 +1,7 @@
+DELETE FROM "UserDataSnapshots"
+WHERE "edited_timestamp" < $1
+  AND "edited_timestamp" > (
+    SELECT MIN("first"."edited_timestamp")
+    FROM "UserDataSnapshots" AS "first"
+    WHERE "first"."discord_id" = "UserDataSnapshots"."discord_id"
+  );
\ No newline at end of file
@@ -0,0 +1,2 @@
+DELETE FROM "LinkCodes"
+WHERE "discord_id" = $1;
\ No newline at end of file
@@ -0,0 +1,3 @@
+DELETE FROM "PromoRoleGrants"
+WHERE "rule_id" = $1
+  AND "discord_id" = $2;
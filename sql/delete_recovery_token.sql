@@ -0,0 +1,2 @@
+DELETE FROM "RecoveryTokens"
+WHERE "primary_token" = $1;
@@ -0,0 +1,3 @@
+DELETE FROM "SupportCodes"
+WHERE "v2_token" = $1
+  OR "legacy_token" = $1;
\ No newline at end of file
@@ -0,0 +1,2 @@
+DELETE FROM "TokenTransitions"
+WHERE "discord_id" = $1;
\ No newline at end of file
@@ -0,0 +1,2 @@
+DELETE FROM "UserActivity"
+WHERE "discord_id" = $1;
\ No newline at end of file
@@ -1,3 +1,12 @@
-DELETE FROM "UserData"
-WHERE "token" = $token
-RETURNING *;
\ No newline at end of file
+WITH deleted AS (
+    DELETE FROM "UserData"
+    WHERE $condition
+    RETURNING *
+),
+released AS (
+    UPDATE "DomainCounts"
+    SET "linked" = GREATEST("linked" - 1, 0)
+    WHERE "domain_key" = (SELECT "email_domain_key" FROM deleted)
+)
+SELECT *
+FROM deleted;
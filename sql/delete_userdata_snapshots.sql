@@ -0,0 +1,2 @@
+DELETE FROM "UserDataSnapshots"
+WHERE "discord_id" = $1;
\ No newline at end of file
@@ -0,0 +1,7 @@
+CREATE TABLE "DeprecationUsage" (
+    "feature" TEXT NOT NULL,
+    "day" DATE NOT NULL,
+    "fingerprint" TEXT NOT NULL,
+    "uses" BIGINT NOT NULL,
+    CONSTRAINT "DeprecationUsage_pkey" PRIMARY KEY ("feature", "day", "fingerprint")
+);
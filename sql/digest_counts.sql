@@ -0,0 +1,7 @@
+CREATE TABLE "DigestCounts" (
+    "kind" TEXT NOT NULL,
+    "name" TEXT NOT NULL,
+    "hour" TIMESTAMP(3) NOT NULL,
+    "count" BIGINT NOT NULL,
+    CONSTRAINT "DigestCounts_pkey" PRIMARY KEY ("kind", "name", "hour")
+);
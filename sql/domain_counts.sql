@@ -0,0 +1,5 @@
+CREATE TABLE "DomainCounts" (
+    "domain_key" TEXT NOT NULL,
+    "linked" BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY ("domain_key")
+);
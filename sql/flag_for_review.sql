@@ -0,0 +1,6 @@
+UPDATE "UserData"
+SET "flagged_for_review" = true,
+  "flagged_timestamp" = $2
+WHERE "discord_id" = ANY($1)
+  AND NOT "flagged_for_review"
+RETURNING "discord_id";
\ No newline at end of file
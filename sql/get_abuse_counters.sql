@@ -0,0 +1,6 @@
+SELECT "counter",
+  "key",
+  "value",
+  "expires_timestamp"
+FROM "AbuseCounters"
+WHERE "expires_timestamp" > $1;
\ No newline at end of file
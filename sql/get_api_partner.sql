@@ -0,0 +1,3 @@
+SELECT *
+FROM "ApiPartners"
+WHERE "key_hash" = $1;
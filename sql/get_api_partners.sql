@@ -0,0 +1,3 @@
+SELECT *
+FROM "ApiPartners"
+ORDER BY "id";
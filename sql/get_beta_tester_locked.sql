@@ -0,0 +1,3 @@
+SELECT "beta_tester_locked"
+FROM "UserData"
+WHERE "discord_id" = $1;
\ No newline at end of file
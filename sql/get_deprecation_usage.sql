@@ -0,0 +1,9 @@
+SELECT "feature",
+  "day"::TEXT AS "day",
+  SUM("uses")::BIGINT AS "uses",
+  COUNT(DISTINCT "fingerprint") AS "clients"
+FROM "DeprecationUsage"
+GROUP BY "feature",
+  "day"
+ORDER BY "feature" ASC,
+  "day" DESC;
@@ -0,0 +1,10 @@
+SELECT "kind",
+  "name",
+  SUM("count")::BIGINT AS "count"
+FROM "DigestCounts"
+WHERE "hour" >= $1
+  AND "hour" < $2
+GROUP BY "kind",
+  "name"
+ORDER BY "count" DESC,
+  "name" ASC;
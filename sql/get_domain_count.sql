@@ -0,0 +1,3 @@
+SELECT "linked"
+FROM "DomainCounts"
+WHERE "domain_key" = $1;
@@ -0,0 +1,3 @@
+SELECT "granted_roles"::TEXT
+FROM "UserData"
+WHERE "discord_id" = $1;
\ No newline at end of file
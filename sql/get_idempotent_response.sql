@@ -0,0 +1,3 @@
+SELECT "response"
+FROM "IdempotencyKeys"
+WHERE "token" = $1 AND "idempotency_key" = $2 AND "created_timestamp" >= $3;
\ No newline at end of file
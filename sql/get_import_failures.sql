@@ -0,0 +1,4 @@
+SELECT *
+FROM "ImportFailures"
+WHERE "job_id" = $1
+ORDER BY "line" ASC;
\ No newline at end of file
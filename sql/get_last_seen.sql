@@ -0,0 +1,3 @@
+SELECT "last_seen_timestamp"
+FROM "UserData"
+WHERE "discord_id" = $1;
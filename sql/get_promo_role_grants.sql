@@ -0,0 +1,4 @@
+SELECT "discord_id"
+FROM "PromoRoleGrants"
+WHERE "rule_id" = $1
+LIMIT $2;
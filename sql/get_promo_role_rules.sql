@@ -0,0 +1,4 @@
+SELECT *
+FROM "PromoRoleRules"
+WHERE "swept" = false
+ORDER BY "starts_at";
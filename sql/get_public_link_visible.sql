@@ -0,0 +1,3 @@
+SELECT "public_link_visible"
+FROM "UserData"
+WHERE "discord_id" = $1;
\ No newline at end of file
@@ -0,0 +1,3 @@
+SELECT "primary_token"
+FROM "RecoveryTokens"
+WHERE "recovery_token" = $1;
@@ -0,0 +1,3 @@
+SELECT "recovery_token"
+FROM "RecoveryTokens"
+WHERE "primary_token" = $1;
@@ -0,0 +1,3 @@
+SELECT COUNT(*),
+  COUNT(*) FILTER (WHERE "beta_tester")$milestone_counts
+FROM "UserData";
\ No newline at end of file
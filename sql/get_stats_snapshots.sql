@@ -0,0 +1,6 @@
+SELECT "day",
+  "value"
+FROM "StatsSnapshots"
+WHERE "metric" = $1
+  AND "day" >= $2
+ORDER BY "day";
\ No newline at end of file
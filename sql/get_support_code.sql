@@ -0,0 +1,3 @@
+SELECT *
+FROM "SupportCodes"
+WHERE "support_code" = $1;
\ No newline at end of file
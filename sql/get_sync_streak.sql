@@ -0,0 +1,5 @@
+SELECT "last_sync_week",
+  "current_streak",
+  "longest_streak"
+FROM "UserData"
+WHERE "discord_id" = $1;
@@ -0,0 +1,20 @@
+SELECT "snapshots"."discord_id",
+  LEFT("snapshots"."token", 8) AS "token_fingerprint",
+  MIN("snapshots"."edited_timestamp") AS "first_seen",
+  MAX("snapshots"."edited_timestamp") AS "last_seen",
+  BOOL_OR("transitions"."token" IS NOT NULL) AS "whitelisted"
+FROM "UserDataSnapshots" AS "snapshots"
+  LEFT JOIN "TokenTransitions" AS "transitions" ON "transitions"."discord_id" = "snapshots"."discord_id"
+  AND "transitions"."token" = "snapshots"."token"
+WHERE "snapshots"."edited_timestamp" >= $1
+  AND "snapshots"."discord_id" IN (
+    SELECT "discord_id"
+    FROM "UserDataSnapshots"
+    WHERE "edited_timestamp" >= $1
+    GROUP BY "discord_id"
+    HAVING COUNT(DISTINCT "token") > 1
+  )
+GROUP BY "snapshots"."discord_id",
+  "snapshots"."token"
+ORDER BY "snapshots"."discord_id",
+  "first_seen";
\ No newline at end of file
@@ -0,0 +1,7 @@
+SELECT "discord_id",
+  "token_fingerprint",
+  "edited_timestamp",
+  "beta_tester_locked"
+FROM "UserData"
+WHERE "token_fingerprint" = $1
+ORDER BY "discord_id";
\ No newline at end of file
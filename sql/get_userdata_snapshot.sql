@@ -0,0 +1,6 @@
+SELECT *
+FROM "UserDataSnapshots"
+WHERE "discord_id" = $1
+  AND "edited_timestamp" <= $2
+ORDER BY "edited_timestamp" DESC
+LIMIT 1;
\ No newline at end of file
@@ -0,0 +1,4 @@
+SELECT *
+FROM "UserDataSnapshots"
+WHERE "discord_id" = $1
+ORDER BY "edited_timestamp" ASC;
\ No newline at end of file
@@ -0,0 +1,9 @@
+-- the responses of creates that were sent with an Idempotency-Key, so a retry gets the original one
+CREATE TABLE "IdempotencyKeys" (
+    "token" TEXT NOT NULL,
+    "idempotency_key" TEXT NOT NULL,
+    "response" TEXT NOT NULL,
+    "created_timestamp" TIMESTAMP(3) NOT NULL,
+    CONSTRAINT "IdempotencyKeys_pkey" PRIMARY KEY ("token", "idempotency_key")
+);
+CREATE INDEX "IdempotencyKeys_created_timestamp_idx" ON "IdempotencyKeys" ("created_timestamp");
\ No newline at end of file
@@ -0,0 +1,10 @@
+CREATE TABLE "ImportFailures" (
+    "id" BIGSERIAL NOT NULL,
+    "job_id" TEXT NOT NULL,
+    "line" INTEGER NOT NULL,
+    "reason" TEXT NOT NULL,
+    "row" TEXT NOT NULL,
+    "created_timestamp" TIMESTAMP(3) NOT NULL,
+    CONSTRAINT "ImportFailures_pkey" PRIMARY KEY ("id")
+);
+CREATE INDEX "ImportFailures_job_id_idx" ON "ImportFailures" ("job_id");
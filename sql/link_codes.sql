@@ -0,0 +1,9 @@
+-- single-use codes the bot hands out so a player can link by scanning them instead of typing the discord id
+CREATE TABLE "LinkCodes" (
+    "code" TEXT NOT NULL,
+    "discord_id" TEXT NOT NULL,
+    "expires_timestamp" TIMESTAMP(3) NOT NULL,
+    "consumed_timestamp" TIMESTAMP(3),
+    CONSTRAINT "LinkCodes_pkey" PRIMARY KEY ("code")
+);
+CREATE INDEX "LinkCodes_expires_timestamp_idx" ON "LinkCodes" ("expires_timestamp");
\ No newline at end of file
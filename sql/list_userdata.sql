@@ -0,0 +1,6 @@
+SELECT *
+FROM "UserData"
+WHERE $2::TEXT IS NULL
+  OR "discord_id" > $2
+ORDER BY "discord_id"
+LIMIT $1;
\ No newline at end of file
@@ -0,0 +1,4 @@
+SELECT *
+FROM "UserData"
+WHERE "token" = $token
+FOR UPDATE;
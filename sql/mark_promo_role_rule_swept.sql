@@ -0,0 +1,3 @@
+UPDATE "PromoRoleRules"
+SET "swept" = true
+WHERE "id" = $1;
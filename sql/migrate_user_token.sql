@@ -0,0 +1,21 @@
+WITH "migrated" AS (
+    UPDATE "UserData"
+    SET "token" = $2, "token_fingerprint" = $3
+    WHERE "token" = $1
+        AND NOT EXISTS (SELECT 1 FROM "UserData" WHERE "token" = $2)
+    RETURNING "discord_id"
+), "transition" AS (
+    INSERT INTO "TokenTransitions" ("discord_id", "token", "created_timestamp")
+    SELECT "discord_id", $2, $4 FROM "migrated"
+    ON CONFLICT ("discord_id", "token") DO NOTHING
+), "recovered" AS (
+    UPDATE "RecoveryTokens"
+    SET "recovery_token" = $2
+    WHERE "recovery_token" = $1
+        AND NOT EXISTS (SELECT 1 FROM "migrated")
+        AND NOT EXISTS (SELECT 1 FROM "RecoveryTokens" WHERE "recovery_token" = $2)
+    RETURNING "primary_token"
+)
+SELECT
+    EXISTS (SELECT 1 FROM "migrated") AS "migrated",
+    (SELECT "primary_token" FROM "recovered") AS "recovery_of";
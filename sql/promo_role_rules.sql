@@ -0,0 +1,17 @@
+CREATE TABLE "PromoRoleRules" (
+    "id" BIGSERIAL NOT NULL,
+    "role_id" TEXT NOT NULL,
+    "name" TEXT NOT NULL,
+    "starts_at" TIMESTAMP(3) NOT NULL,
+    "ends_at" TIMESTAMP(3) NOT NULL,
+    "swept" BOOLEAN NOT NULL DEFAULT false,
+    "created_timestamp" TIMESTAMP(3) NOT NULL,
+    CONSTRAINT "PromoRoleRules_pkey" PRIMARY KEY ("id")
+);
+CREATE TABLE "PromoRoleGrants" (
+    "rule_id" BIGINT NOT NULL,
+    "discord_id" TEXT NOT NULL,
+    "created_timestamp" TIMESTAMP(3) NOT NULL,
+    CONSTRAINT "PromoRoleGrants_pkey" PRIMARY KEY ("rule_id", "discord_id"),
+    CONSTRAINT "PromoRoleGrants_rule_id_fkey" FOREIGN KEY ("rule_id") REFERENCES "PromoRoleRules" ("id") ON DELETE CASCADE
+);
@@ -0,0 +1,7 @@
+CREATE TABLE "RecoveryTokens" (
+    "primary_token" TEXT NOT NULL,
+    "recovery_token" TEXT NOT NULL UNIQUE,
+    "edited_timestamp" TIMESTAMP(3) NOT NULL,
+    CONSTRAINT "RecoveryTokens_pkey" PRIMARY KEY ("primary_token"),
+    CONSTRAINT "RecoveryTokens_primary_token_fkey" FOREIGN KEY ("primary_token") REFERENCES "UserData" ("token") ON DELETE CASCADE
+);
@@ -0,0 +1,21 @@
+WITH "rows" AS (
+  SELECT *
+  FROM UNNEST($1::TEXT[], $2::TEXT[], $3::BIGINT[], $4::TIMESTAMP(3)[]) AS "rows" ("counter", "key", "value", "expires_timestamp")
+),
+"stale" AS (
+  DELETE FROM "AbuseCounters"
+  WHERE ("counter", "key") NOT IN (
+      SELECT "counter",
+        "key"
+      FROM "rows"
+    )
+)
+INSERT INTO "AbuseCounters" ("counter", "key", "value", "expires_timestamp")
+SELECT "counter",
+  "key",
+  "value",
+  "expires_timestamp"
+FROM "rows" ON CONFLICT ("counter", "key") DO
+UPDATE
+SET "value" = EXCLUDED."value",
+  "expires_timestamp" = EXCLUDED."expires_timestamp";
\ No newline at end of file
@@ -0,0 +1,6 @@
+CREATE TABLE "RoleGrantCounts" (
+    "role" TEXT NOT NULL,
+    "count" BIGINT NOT NULL,
+    "edited_timestamp" TIMESTAMP(3) NOT NULL,
+    CONSTRAINT "RoleGrantCounts_pkey" PRIMARY KEY ("role")
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS "SchemaMigrations" (
+    "version" INTEGER NOT NULL,
+    "applied_at" TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY ("version")
+);
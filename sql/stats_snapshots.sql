@@ -0,0 +1,9 @@
+-- the aggregates GET stats/history charts, one row per metric for every UTC day the scheduler ran
+CREATE TABLE "StatsSnapshots" (
+    "day" BIGINT NOT NULL,
+    "metric" TEXT NOT NULL,
+    "value" BIGINT NOT NULL,
+    "created_timestamp" TIMESTAMP(3) NOT NULL,
+    CONSTRAINT "StatsSnapshots_pkey" PRIMARY KEY ("metric", "day")
+);
+CREATE INDEX "StatsSnapshots_day_idx" ON "StatsSnapshots" ("day");
\ No newline at end of file
@@ -0,0 +1,10 @@
+-- support codes keep the tokens the player's credentials derive to instead of the credentials, the
+-- codes registered with the credentials can't be converted and are registered again by the game
+DELETE FROM "SupportCodes";
+ALTER TABLE "SupportCodes"
+DROP COLUMN "email",
+DROP COLUMN "token",
+ADD COLUMN "v2_token" TEXT NOT NULL,
+ADD COLUMN "legacy_token" TEXT NOT NULL;
+CREATE INDEX "SupportCodes_v2_token_idx" ON "SupportCodes" ("v2_token");
+CREATE INDEX "SupportCodes_legacy_token_idx" ON "SupportCodes" ("legacy_token");
\ No newline at end of file
@@ -0,0 +1,7 @@
+CREATE TABLE "SupportCodes" (
+    "support_code" TEXT NOT NULL,
+    "email" TEXT NOT NULL,
+    "token" TEXT NOT NULL,
+    "edited_timestamp" TIMESTAMP(3) NOT NULL,
+    CONSTRAINT "SupportCodes_pkey" PRIMARY KEY ("support_code")
+);
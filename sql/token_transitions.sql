@@ -0,0 +1,6 @@
+CREATE TABLE "TokenTransitions" (
+    "discord_id" TEXT NOT NULL,
+    "token" TEXT NOT NULL,
+    "created_timestamp" TIMESTAMP(3) NOT NULL,
+    CONSTRAINT "TokenTransitions_pkey" PRIMARY KEY ("discord_id", "token")
+);
@@ -0,0 +1,7 @@
+UPDATE "UserData"
+SET "last_seen_timestamp" = $2
+WHERE "token" = $1
+RETURNING "discord_id",
+  "last_sync_week",
+  "current_streak",
+  "longest_streak";
@@ -0,0 +1,7 @@
+UPDATE "UserData"
+SET "beta_tester" = $2,
+  "beta_tester_locked" = $3
+WHERE "discord_id" = $1
+RETURNING "discord_id",
+  "beta_tester",
+  "beta_tester_locked";
\ No newline at end of file
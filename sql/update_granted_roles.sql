@@ -0,0 +1,3 @@
+UPDATE "UserData"
+SET "granted_roles" = $2::TEXT::JSONB
+WHERE "discord_id" = $1;
\ No newline at end of file
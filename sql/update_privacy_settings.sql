@@ -0,0 +1,5 @@
+UPDATE "UserData"
+SET "public_link_visible" = COALESCE($2, "public_link_visible"),
+    "nickname_prefix" = COALESCE($3, "nickname_prefix")
+WHERE "token" = $1
+RETURNING "public_link_visible", "nickname_prefix";
\ No newline at end of file
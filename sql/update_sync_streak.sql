@@ -0,0 +1,5 @@
+UPDATE "UserData"
+SET "last_sync_week" = $2,
+  "current_streak" = $3,
+  "longest_streak" = $4
+WHERE "discord_id" = $1;
\ No newline at end of file
@@ -1,12 +1,18 @@
 UPDATE "UserData"
-SET "beta_tester" = $1,
-  "metabits" = $2,
-  "dino_rank" = $3,
-  "prestige_rank" = $4,
-  "beyond_rank" = $5,
-  "singularity_speedrun_time" = $6,
-  "all_sharks_obtained" = $7,
-  "all_hidden_achievements_obtained" = $8,
-  "edited_timestamp" = $9
+SET "beta_tester" = CASE
+    WHEN "beta_tester_locked" THEN "beta_tester"
+    ELSE COALESCE($1, "beta_tester")
+  END,
+  "metabits" = COALESCE($2, "metabits"),
+  "dino_rank" = COALESCE($3, "dino_rank"),
+  "prestige_rank" = COALESCE($4, "prestige_rank"),
+  "beyond_rank" = COALESCE($5, "beyond_rank"),
+  "singularity_speedrun_time" = CASE
+    WHEN $7 THEN $6
+    ELSE "singularity_speedrun_time"
+  END,
+  "all_sharks_obtained" = COALESCE($8, "all_sharks_obtained"),
+  "all_hidden_achievements_obtained" = COALESCE($9, "all_hidden_achievements_obtained"),
+  "edited_timestamp" = $10
 WHERE "token" = $token
 RETURNING *;
\ No newline at end of file
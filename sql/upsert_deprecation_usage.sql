@@ -0,0 +1,5 @@
+INSERT INTO "DeprecationUsage" ("feature", "day", "fingerprint", "uses")
+SELECT "feature", DATE '1970-01-01' + "day"::INTEGER, "fingerprint", "uses"
+FROM UNNEST($1::TEXT[], $2::BIGINT[], $3::TEXT[], $4::BIGINT[]) AS "rows" ("feature", "day", "fingerprint", "uses") ON CONFLICT ("feature", "day", "fingerprint") DO
+UPDATE
+SET "uses" = "DeprecationUsage"."uses" + EXCLUDED."uses";
@@ -0,0 +1,5 @@
+INSERT INTO "DigestCounts" ("kind", "name", "hour", "count")
+SELECT "kind", "name", TIMESTAMP '1970-01-01' + "hour" * INTERVAL '1 hour', "count"
+FROM UNNEST($1::TEXT[], $2::BIGINT[], $3::TEXT[], $4::BIGINT[]) AS "rows" ("kind", "hour", "name", "count") ON CONFLICT ("kind", "name", "hour") DO
+UPDATE
+SET "count" = "DigestCounts"."count" + EXCLUDED."count";
@@ -0,0 +1,5 @@
+INSERT INTO "RecoveryTokens" ("primary_token", "recovery_token", "edited_timestamp")
+VALUES ($1, $2, $3) ON CONFLICT ("primary_token") DO
+UPDATE
+SET "recovery_token" = $2,
+  "edited_timestamp" = $3;
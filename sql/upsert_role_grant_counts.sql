@@ -0,0 +1,6 @@
+INSERT INTO "RoleGrantCounts" ("role", "count", "edited_timestamp")
+SELECT "role", "count", $3
+FROM UNNEST($1::TEXT[], $2::BIGINT[]) AS "rows" ("role", "count") ON CONFLICT ("role") DO
+UPDATE
+SET "count" = "RoleGrantCounts"."count" + EXCLUDED."count",
+  "edited_timestamp" = $3;
@@ -0,0 +1,6 @@
+INSERT INTO "StatsSnapshots" ("day", "metric", "value", "created_timestamp")
+SELECT $1, "metric", "value", $4
+FROM UNNEST($2::TEXT[], $3::BIGINT[]) AS "rows" ("metric", "value") ON CONFLICT ("metric", "day") DO
+UPDATE
+SET "value" = EXCLUDED."value",
+  "created_timestamp" = $4;
\ No newline at end of file
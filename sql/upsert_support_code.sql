@@ -0,0 +1,7 @@
+INSERT INTO "SupportCodes" ("support_code", "v2_token", "legacy_token", "edited_timestamp")
+VALUES ($1, $2, $3, $4) ON CONFLICT ("support_code") DO
+UPDATE
+SET "v2_token" = $2,
+  "legacy_token" = $3,
+  "edited_timestamp" = $4
+RETURNING *;
\ No newline at end of file
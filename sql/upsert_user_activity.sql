@@ -0,0 +1,11 @@
+INSERT INTO "UserActivity" ("discord_id", "syncs", "edited_timestamp")
+SELECT "discord_id", "syncs", $3
+FROM UNNEST($1::TEXT[], $2::BIGINT[]) AS "rows" ("discord_id", "syncs")
+WHERE EXISTS (
+    SELECT 1
+    FROM "UserData"
+    WHERE "UserData"."discord_id" = "rows"."discord_id"
+  ) ON CONFLICT ("discord_id") DO
+UPDATE
+SET "syncs" = "UserActivity"."syncs" + EXCLUDED."syncs",
+  "edited_timestamp" = $3;
@@ -0,0 +1,6 @@
+CREATE TABLE "UserActivity" (
+    "discord_id" TEXT NOT NULL,
+    "syncs" BIGINT NOT NULL,
+    "edited_timestamp" TIMESTAMP(3) NOT NULL,
+    CONSTRAINT "UserActivity_pkey" PRIMARY KEY ("discord_id")
+);
@@ -1,5 +1,6 @@
 CREATE TABLE "UserData" (
     "token" TEXT NOT NULL,
+    "token_fingerprint" TEXT NOT NULL DEFAULT '',
     "discord_id" TEXT NOT NULL UNIQUE,
     "metabits" BIGINT NOT NULL DEFAULT 0,
     "dino_rank" INTEGER NOT NULL DEFAULT 0,
@@ -9,6 +10,12 @@ CREATE TABLE "UserData" (
     "all_sharks_obtained" BOOLEAN NOT NULL DEFAULT false,
     "all_hidden_achievements_obtained" BOOLEAN NOT NULL DEFAULT false,
     "beta_tester" BOOLEAN NOT NULL DEFAULT false,
+    "public_link_visible" BOOLEAN NOT NULL DEFAULT false,
+    "granted_roles" JSONB NOT NULL DEFAULT '{}',
+    "last_sync_week" BIGINT,
+    "current_streak" INTEGER NOT NULL DEFAULT 0,
+    "longest_streak" INTEGER NOT NULL DEFAULT 0,
     "edited_timestamp" TIMESTAMP(3) NOT NULL,
     CONSTRAINT "UserData_pkey" PRIMARY KEY ("token")
-);
\ No newline at end of file
+);
+CREATE INDEX "UserData_token_fingerprint_idx" ON "UserData" ("token_fingerprint");
\ No newline at end of file
@@ -0,0 +1,295 @@
+use deadpool_postgres::Pool;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{
+    constants::ErrorLogType,
+    db,
+    errors::{ConvertResultErrorToMyError, LogMyError, MyError},
+    link_attempts::{link_attempts, LinkAttempts},
+    models::AbuseCounterRow,
+    negative_cache::{negative_cache, NegativeCache},
+};
+
+/// how many entries of each counter are kept over a restart, the rest are the least abusive
+pub const MAX_SNAPSHOT_ENTRIES: usize = 1_000;
+
+/// The counters attackers would otherwise get reset by waiting for a deploy. Regular request rate
+/// limits aren't among them, they start over with every restart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AbuseCounter {
+    /// failed creates per email key
+    LinkAttempts,
+    /// derived tokens that turned out not to be linked
+    NegativeTokens,
+}
+
+impl AbuseCounter {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AbuseCounter::LinkAttempts => "link_attempts",
+            AbuseCounter::NegativeTokens => "negative_tokens",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<AbuseCounter> {
+        match name {
+            "link_attempts" => Some(AbuseCounter::LinkAttempts),
+            "negative_tokens" => Some(AbuseCounter::NegativeTokens),
+            _ => None,
+        }
+    }
+}
+
+/// the hottest entries of every abuse counter, `wall_now` is when `now` is on the wall clock
+pub fn take_snapshot(
+    attempts: &LinkAttempts,
+    negatives: &NegativeCache,
+    now: Instant,
+    wall_now: SystemTime,
+) -> Vec<AbuseCounterRow> {
+    let row =
+        |counter: AbuseCounter, key: String, value: i64, remaining: Duration| AbuseCounterRow {
+            counter: counter.name().to_owned(),
+            key,
+            value,
+            expires_timestamp: wall_now + remaining,
+        };
+
+    attempts
+        .hot_entries(now, MAX_SNAPSHOT_ENTRIES)
+        .into_iter()
+        .map(|(key, failures, remaining)| {
+            row(AbuseCounter::LinkAttempts, key, failures as i64, remaining)
+        })
+        .chain(
+            negatives
+                .hot_entries(now, MAX_SNAPSHOT_ENTRIES)
+                .into_iter()
+                .map(|(token, remaining)| row(AbuseCounter::NegativeTokens, token, 1, remaining)),
+        )
+        .collect()
+}
+
+/// puts the snapshot's entries back with the part of their window that's left, returns how many
+/// were restored. Entries that expired while the service was down are left out.
+pub fn restore_snapshot(
+    rows: Vec<AbuseCounterRow>,
+    attempts: &mut LinkAttempts,
+    negatives: &NegativeCache,
+    now: Instant,
+    wall_now: SystemTime,
+) -> usize {
+    let mut restored = 0;
+    for row in rows {
+        let remaining = match row.expires_timestamp.duration_since(wall_now) {
+            Ok(remaining) if !remaining.is_zero() => remaining,
+            _ => continue,
+        };
+        match AbuseCounter::from_name(&row.counter) {
+            Some(AbuseCounter::LinkAttempts) => {
+                attempts.restore(&row.key, row.value as u32, remaining, now)
+            }
+            Some(AbuseCounter::NegativeTokens) => negatives.restore(&row.key, remaining, now),
+            None => continue,
+        }
+        restored += 1;
+    }
+
+    restored
+}
+
+/// stores the hot entries, the write-behind flusher does this along with every flush
+pub async fn write_snapshot(pool: &Pool) -> Result<(), MyError> {
+    let rows = take_snapshot(
+        &link_attempts().lock().unwrap(),
+        negative_cache(),
+        Instant::now(),
+        SystemTime::now(),
+    );
+
+    let client = pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "snapshotting abuse counters failed at creating database client",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let mut counters = Vec::new();
+    let mut keys = Vec::new();
+    let mut values = Vec::new();
+    let mut expires = Vec::new();
+    for row in rows {
+        counters.push(row.counter);
+        keys.push(row.key);
+        values.push(row.value);
+        expires.push(row.expires_timestamp);
+    }
+    db::replace_abuse_counters(&client, &counters, &keys, &values, &expires)
+        .await
+        .make_response(MyError::InternalError("snapshotting abuse counters failed"))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    Ok(())
+}
+
+/// loads the last snapshot during warm-up, before any request is served
+pub async fn warm_up(pool: &Pool) -> Result<usize, MyError> {
+    let client = pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "restoring abuse counters failed at creating database client",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let wall_now = SystemTime::now();
+    let rows = db::get_abuse_counters(&client, &wall_now)
+        .await
+        .make_response(MyError::InternalError("restoring abuse counters failed"))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    Ok(restore_snapshot(
+        rows,
+        &mut link_attempts().lock().unwrap(),
+        negative_cache(),
+        Instant::now(),
+        wall_now,
+    ))
+}
+
+#[cfg(test)]
+fn counters() -> (LinkAttempts, NegativeCache) {
+    use crate::{link_attempts::LINK_ATTEMPT_WINDOW, negative_cache::NEGATIVE_CACHE_TTL};
+
+    (
+        LinkAttempts::new(LINK_ATTEMPT_WINDOW, 3),
+        NegativeCache::new(NEGATIVE_CACHE_TTL, 10),
+    )
+}
+
+#[test]
+fn snapshots_take_the_hottest_entries() {
+    let now = Instant::now();
+    let wall_now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let (mut attempts, negatives) = counters();
+    attempts.record_failure("email", now);
+    attempts.record_failure("email", now + Duration::from_secs(60));
+    attempts.record_failure("other-email", now + Duration::from_secs(60));
+    negatives.remember("token", now);
+
+    let snapshot = take_snapshot(
+        &attempts,
+        &negatives,
+        now + Duration::from_secs(60),
+        wall_now,
+    );
+
+    let entries = snapshot
+        .iter()
+        .map(|row| {
+            (
+                row.counter.as_str(),
+                row.key.as_str(),
+                row.value,
+                row.expires_timestamp.duration_since(wall_now).unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        entries,
+        vec![
+            // the window started at the first failure
+            (
+                "link_attempts",
+                "email",
+                2,
+                crate::link_attempts::LINK_ATTEMPT_WINDOW - Duration::from_secs(60)
+            ),
+            (
+                "link_attempts",
+                "other-email",
+                1,
+                crate::link_attempts::LINK_ATTEMPT_WINDOW
+            ),
+        ]
+    );
+    // the negative entry expired within the minute
+    assert!(!entries
+        .iter()
+        .any(|(counter, ..)| *counter == "negative_tokens"));
+}
+
+#[test]
+fn restarts_keep_the_abuse_counters() {
+    let before = Instant::now();
+    let wall_before = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let (mut attempts, negatives) = counters();
+    for _ in 0..3 {
+        attempts.record_failure("email", before);
+    }
+    negatives.remember("token", before);
+    let snapshot = take_snapshot(&attempts, &negatives, before, wall_before);
+
+    // the new process' clock has nothing to do with the old one's
+    let after = Instant::now();
+    let wall_after = wall_before + Duration::from_secs(30);
+    let (mut restarted_attempts, restarted_negatives) = counters();
+    assert_eq!(
+        restore_snapshot(
+            snapshot,
+            &mut restarted_attempts,
+            &restarted_negatives,
+            after,
+            wall_after,
+        ),
+        2
+    );
+
+    assert!(restarted_attempts.is_capped("email", after));
+    assert!(restarted_negatives.is_unknown("token", after));
+    // the 30 seconds the service was down count towards the windows
+    assert!(!restarted_negatives.is_unknown("token", after + Duration::from_secs(30)));
+    assert!(!restarted_attempts.is_capped(
+        "email",
+        after + crate::link_attempts::LINK_ATTEMPT_WINDOW - Duration::from_secs(30)
+    ));
+}
+
+#[test]
+fn expired_entries_are_not_restored() {
+    let wall_now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let row = |counter: &str, expires_in: i64| AbuseCounterRow {
+        counter: counter.to_owned(),
+        key: "key".to_owned(),
+        value: 3,
+        expires_timestamp: if expires_in >= 0 {
+            wall_now + Duration::from_secs(expires_in as u64)
+        } else {
+            wall_now - Duration::from_secs(expires_in.unsigned_abs())
+        },
+    };
+    let now = Instant::now();
+    let (mut attempts, negatives) = counters();
+
+    let restored = restore_snapshot(
+        vec![
+            row("link_attempts", -60),
+            row("link_attempts", 0),
+            row("negative_tokens", -1),
+            row("retired_counter", 60),
+        ],
+        &mut attempts,
+        &negatives,
+        now,
+        wall_now,
+    );
+
+    assert_eq!(restored, 0);
+    assert!(!attempts.is_capped("key", now));
+    assert_eq!(negatives.stats().entries, 0);
+}
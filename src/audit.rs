@@ -0,0 +1,423 @@
+use deadpool_postgres::Pool;
+use serde_json::{json, Value};
+use std::{borrow::Cow, net::IpAddr, time::SystemTime};
+use twilight_model::channel::embed::{Embed, EmbedField, EmbedFooter};
+
+use crate::{
+    config::Config,
+    constants::{ErrorLogType, LOG},
+    db,
+    errors::{ConvertResultErrorToMyError, LogMyError, MyError},
+    routes::RouteId,
+    webhook_logging::{webhook_embed_to, webhook_log},
+};
+
+const AUDIT_COLOR: u32 = 0xff_8c_00;
+
+/// the admin key a request was authorized with, only its label ever ends up in logs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AdminKey {
+    pub label: Cow<'static, str>,
+}
+
+/// the `X-Semblance-Exclusive` secret, the other admin keys are configured in `ADMIN_API_KEYS`
+pub const SEMBLANCE_KEY: AdminKey = AdminKey {
+    label: Cow::Borrowed("semblance-exclusive"),
+};
+
+/// what security events without a valid key are reported under
+const NO_KEY: AdminKey = AdminKey {
+    label: Cow::Borrowed("none"),
+};
+
+/// the webhook admin actions are reported to, apart from the general log channel
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SecurityWebhook {
+    pub webhook_id: u64,
+    pub webhook_token: String,
+}
+
+/// parses `https://discord.com/api/webhooks/{id}/{token}`, an empty url means there's none
+pub fn parse_webhook_url(url: &str) -> Option<SecurityWebhook> {
+    let mut segments = url.trim().trim_end_matches('/').rsplit('/');
+    let webhook_token = segments.next()?.to_owned();
+    let webhook_id = segments.next()?.parse::<u64>().ok().filter(|id| *id != 0)?;
+    if segments.next()? != "webhooks" || webhook_token.is_empty() {
+        return None;
+    }
+
+    Some(SecurityWebhook {
+        webhook_id,
+        webhook_token,
+    })
+}
+
+/// the actor audit rows are stored with, players' own actions aren't audited
+const ADMIN_ACTOR: &str = "admin";
+/// the actor of admin requests that were turned away
+const DENIED_ACTOR: &str = "denied";
+/// the actor of what the service reports about itself, like the break-glass switch
+const STARTUP_ACTOR: &str = "startup";
+
+/// where a log about an action goes, `admin_key` is `None` for players' own actions
+#[derive(Debug, PartialEq, Eq)]
+pub enum LogChannel {
+    Security {
+        webhook_id: u64,
+    },
+    /// the security webhook isn't configured, admin actions go to the general FAILURE logs
+    FailureFallback,
+    General,
+}
+
+pub fn log_channel(
+    admin_key: Option<&AdminKey>,
+    security_webhook: Option<&SecurityWebhook>,
+) -> LogChannel {
+    match (admin_key, security_webhook) {
+        (Some(_), Some(webhook)) => LogChannel::Security {
+            webhook_id: webhook.webhook_id,
+        },
+        (Some(_), None) => LogChannel::FailureFallback,
+        (None, _) => LogChannel::General,
+    }
+}
+
+/// something an admin did through an admin endpoint
+pub struct AuditEvent {
+    pub key: AdminKey,
+    pub route: RouteId,
+    pub target_discord_id: Option<String>,
+    pub parameters: Value,
+}
+
+impl AuditEvent {
+    pub fn new(key: AdminKey, route: RouteId, parameters: Value) -> Self {
+        AuditEvent {
+            key,
+            route,
+            target_discord_id: None,
+            parameters,
+        }
+    }
+
+    pub fn endpoint(&self) -> String {
+        route_endpoint(self.route)
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "admin key {} used {} on {} with {}",
+            self.key.label,
+            self.endpoint(),
+            self.target_discord_id.as_deref().unwrap_or("no user"),
+            self.parameters
+        )
+    }
+}
+
+/// an admin request that was turned away, by the source allowlist or for its key
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdminDenial {
+    pub source_ip: Option<IpAddr>,
+    /// only a valid key's label, a wrong key's would be whatever the caller made up
+    pub key: Option<AdminKey>,
+    pub route: RouteId,
+    /// the error code the request was answered with
+    pub reason: &'static str,
+}
+
+impl AdminDenial {
+    fn source(&self) -> String {
+        self.source_ip
+            .map_or_else(|| "unknown".to_owned(), |ip| ip.to_string())
+    }
+
+    fn key(&self) -> &AdminKey {
+        self.key.as_ref().unwrap_or(&NO_KEY)
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "admin request from {} with key {} to {} was denied ({})",
+            self.source(),
+            self.key().label,
+            route_endpoint(self.route),
+            self.reason
+        )
+    }
+}
+
+fn route_endpoint(route: RouteId) -> String {
+    match route.registration() {
+        Some((method, path)) => format!("{} {}", method, path),
+        None => route.label().to_owned(),
+    }
+}
+
+pub fn audit_embed(event: &AuditEvent) -> Embed {
+    security_embed(
+        "Admin action",
+        vec![
+            field("Key", event.key.label.to_string(), true),
+            field("Endpoint", event.endpoint(), true),
+            field(
+                "Target",
+                event
+                    .target_discord_id
+                    .clone()
+                    .unwrap_or_else(|| "none".to_owned()),
+                true,
+            ),
+            field(
+                "Parameters",
+                format!("```json\n{}```", event.parameters),
+                false,
+            ),
+        ],
+    )
+}
+
+pub fn denial_embed(denial: &AdminDenial) -> Embed {
+    security_embed(
+        "Admin request denied",
+        vec![
+            field("Source", denial.source(), true),
+            field("Key", denial.key().label.to_string(), true),
+            field("Endpoint", route_endpoint(denial.route), true),
+            field("Reason", denial.reason.to_owned(), false),
+        ],
+    )
+}
+
+fn security_embed(title: &str, fields: Vec<EmbedField>) -> Embed {
+    Embed {
+        author: None,
+        color: Some(AUDIT_COLOR),
+        description: None,
+        fields,
+        footer: Some(EmbedFooter {
+            icon_url: None,
+            proxy_icon_url: None,
+            text: "Audit".to_owned(),
+        }),
+        image: None,
+        kind: "rich".to_owned(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: Some(title.to_owned()),
+        url: None,
+        video: None,
+    }
+}
+
+fn field(name: &str, value: String, inline: bool) -> EmbedField {
+    EmbedField {
+        inline,
+        name: name.to_owned(),
+        value,
+    }
+}
+
+/// Reports an admin action to the security webhook and stores it in the audit table. Neither
+/// failing fails the action, which already happened.
+pub async fn security_log(pool: &Pool, config: &Config, event: AuditEvent) {
+    report_to_security_webhook(config, &event.key, audit_embed(&event), event.describe()).await;
+
+    let _ = record_audit_event(
+        pool,
+        AuditRow {
+            actor: ADMIN_ACTOR,
+            key_label: &event.key.label,
+            endpoint: &event.endpoint(),
+            target_discord_id: &event.target_discord_id,
+            parameters: &event.parameters,
+        },
+    )
+    .await;
+}
+
+/// Reports and stores an admin request that was turned away, the same way admin actions are.
+pub async fn security_log_denial(pool: &Pool, config: &Config, denial: &AdminDenial) {
+    report_to_security_webhook(
+        config,
+        denial.key(),
+        denial_embed(denial),
+        denial.describe(),
+    )
+    .await;
+
+    let _ = record_audit_event(
+        pool,
+        AuditRow {
+            actor: DENIED_ACTOR,
+            key_label: &denial.key().label,
+            endpoint: &route_endpoint(denial.route),
+            target_discord_id: &None,
+            parameters: &json!({
+                "source_ip": denial.source(),
+                "reason": denial.reason,
+            }),
+        },
+    )
+    .await;
+}
+
+/// Reports that `ADMIN_ALLOWLIST_BREAK_GLASS` let every source use the admin routes, at startup so
+/// it can't be switched on quietly.
+pub async fn security_log_break_glass(pool: &Pool, config: &Config) {
+    let parameters = json!({ "admin_allowed_cidrs": config.admin_allowed_cidrs.len() });
+    report_to_security_webhook(
+        config,
+        &NO_KEY,
+        security_embed(
+            "Admin allowlist disabled",
+            vec![
+                field("Switch", "ADMIN_ALLOWLIST_BREAK_GLASS".to_owned(), true),
+                field(
+                    "Ignored ranges",
+                    config.admin_allowed_cidrs.len().to_string(),
+                    true,
+                ),
+            ],
+        ),
+        "ADMIN_ALLOWLIST_BREAK_GLASS is set, the admin routes accept every source".to_owned(),
+    )
+    .await;
+
+    let _ = record_audit_event(
+        pool,
+        AuditRow {
+            actor: STARTUP_ACTOR,
+            key_label: &NO_KEY.label,
+            endpoint: "ADMIN_ALLOWLIST_BREAK_GLASS",
+            target_discord_id: &None,
+            parameters: &parameters,
+        },
+    )
+    .await;
+}
+
+/// the security webhook, or the general FAILURE logs without one
+async fn report_to_security_webhook(
+    config: &Config,
+    key: &AdminKey,
+    embed: Embed,
+    description: String,
+) {
+    match log_channel(Some(key), config.security_webhook.as_ref()) {
+        LogChannel::Security { webhook_id } => {
+            let webhook_token = config
+                .security_webhook
+                .as_ref()
+                .map(|webhook| webhook.webhook_token.clone())
+                .unwrap_or_default();
+            webhook_embed_to(webhook_id, &webhook_token, embed).await;
+        }
+        LogChannel::FailureFallback => {
+            webhook_log(
+                format!("SECURITY_WEBHOOK_URL isn't configured, {}", description),
+                LOG::FAILURE,
+            )
+            .await
+        }
+        LogChannel::General => {}
+    }
+}
+
+struct AuditRow<'a> {
+    actor: &'static str,
+    key_label: &'a str,
+    endpoint: &'a str,
+    target_discord_id: &'a Option<String>,
+    parameters: &'a Value,
+}
+
+async fn record_audit_event(pool: &Pool, row: AuditRow<'_>) -> Result<(), MyError> {
+    let client = pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "auditing failed at creating database client",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    db::create_audit_event(
+        &client,
+        row.actor,
+        row.key_label,
+        row.endpoint,
+        row.target_discord_id,
+        &row.parameters.to_string(),
+        &SystemTime::now(),
+    )
+    .await
+    .make_response(MyError::InternalError("Failed at recording an audit event"))
+    .make_log(ErrorLogType::INTERNAL)
+    .await?;
+
+    Ok(())
+}
+
+#[test]
+fn admin_actions_go_to_the_security_webhook() {
+    let webhook = parse_webhook_url("https://discord.com/api/webhooks/1234/secret-token").unwrap();
+    assert_eq!(
+        webhook,
+        SecurityWebhook {
+            webhook_id: 1234,
+            webhook_token: "secret-token".to_owned(),
+        }
+    );
+
+    // an admin clearing the recent errors
+    let event = AuditEvent::new(
+        SEMBLANCE_KEY,
+        RouteId::ClearRecentErrors,
+        serde_json::json!({}),
+    );
+    assert_eq!(
+        log_channel(Some(&event.key), Some(&webhook)),
+        LogChannel::Security { webhook_id: 1234 }
+    );
+    assert_eq!(
+        log_channel(Some(&event.key), None),
+        LogChannel::FailureFallback
+    );
+    assert_eq!(event.endpoint(), "DELETE /admin/errors");
+
+    // a player syncing their progress stays in the general channel
+    assert_eq!(log_channel(None, Some(&webhook)), LogChannel::General);
+
+    assert_eq!(parse_webhook_url(""), None);
+    assert_eq!(parse_webhook_url("https://example.com/1234/token"), None);
+}
+
+#[test]
+fn denials_name_the_source_key_and_endpoint() {
+    let denial = AdminDenial {
+        source_ip: "203.0.113.9".parse().ok(),
+        key: Some(AdminKey {
+            label: "moderators".into(),
+        }),
+        route: RouteId::DeleteUserById,
+        reason: "ADMIN_SOURCE_DENIED",
+    };
+    assert_eq!(
+        denial.describe(),
+        "admin request from 203.0.113.9 with key moderators to DELETE /admin/users/{discord_id} was denied (ADMIN_SOURCE_DENIED)"
+    );
+
+    // a wrong key's label isn't taken at its word
+    let denial = AdminDenial {
+        source_ip: None,
+        key: None,
+        ..denial
+    };
+    let embed = denial_embed(&denial);
+    assert_eq!(embed.title.as_deref(), Some("Admin request denied"));
+    assert_eq!(embed.fields[0].value, "unknown");
+    assert_eq!(embed.fields[1].value, "none");
+}
@@ -0,0 +1,91 @@
+use std::{
+    future::{ready, Ready},
+    time::{Duration, Instant},
+};
+
+use actix_web::{dev::Payload, Error, FromRequest, HttpMessage, HttpRequest};
+
+use crate::errors::MyError;
+
+/// calls that would start with less time than this left are failed right away
+pub const MIN_CALL_BUDGET: Duration = Duration::from_millis(250);
+
+/// The deadline of a request, it's created by the timeout middleware and handed to the database
+/// and Discord calls so they stop when the request itself has timed out.
+#[derive(Clone, Copy, Debug)]
+pub struct RequestBudget {
+    deadline: Option<Instant>,
+}
+
+impl RequestBudget {
+    pub fn new(timeout: Duration, now: Instant) -> Self {
+        RequestBudget {
+            deadline: Some(now + timeout),
+        }
+    }
+
+    /// for background jobs and routes that aren't behind the timeout middleware
+    pub fn unlimited() -> Self {
+        RequestBudget { deadline: None }
+    }
+
+    /// `None` means there's no deadline
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(now))
+    }
+
+    /// Fails with a 504 when less than `MIN_CALL_BUDGET` is left, `partial_state` tells the user
+    /// what already happened before the budget ran out.
+    pub fn ensure_remaining(
+        &self,
+        now: Instant,
+        partial_state: &'static str,
+    ) -> Result<Option<Duration>, MyError> {
+        match self.remaining(now) {
+            Some(remaining) if remaining < MIN_CALL_BUDGET => Err(MyError::Timeout(partial_state)),
+            remaining => Ok(remaining),
+        }
+    }
+}
+
+impl FromRequest for RequestBudget {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(req
+            .extensions()
+            .get::<RequestBudget>()
+            .copied()
+            .unwrap_or_else(RequestBudget::unlimited)))
+    }
+}
+
+#[test]
+fn nearly_exhausted_budget_short_circuits() {
+    let start = Instant::now();
+    let budget = RequestBudget::new(Duration::from_secs(1), start);
+    let mut transport_calls = 0;
+
+    if budget
+        .ensure_remaining(start + Duration::from_millis(900), "nothing was changed")
+        .is_ok()
+    {
+        transport_calls += 1;
+    }
+
+    assert_eq!(transport_calls, 0);
+    assert_eq!(
+        budget
+            .ensure_remaining(start + Duration::from_millis(500), "nothing was changed")
+            .ok(),
+        Some(Some(Duration::from_millis(500)))
+    );
+    assert_eq!(
+        RequestBudget::unlimited()
+            .ensure_remaining(start, "nothing was changed")
+            .ok(),
+        Some(None)
+    );
+}
@@ -0,0 +1,212 @@
+use serde::Serialize;
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use crate::{constants::LOG, ttl_map::TtlMap};
+
+/// how many users are read from the database at once
+pub const RESYNC_PAGE_SIZE: i64 = 100;
+/// a resync gives up after this many global rate limits in a row, the syncs need the rate limit too
+const MAX_RATE_LIMITS_IN_A_ROW: u32 = 3;
+/// how long a job can be polled for after it started
+const JOB_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+static RESYNC_JOBS: OnceLock<Mutex<ResyncJobs>> = OnceLock::new();
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ResyncState {
+    Running,
+    Completed,
+    Aborted,
+}
+
+/// how far a resync of every user's roles got, it's what `GET /admin/roles/resync/{job_id}` answers
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ResyncJob {
+    pub job_id: String,
+    pub state: ResyncState,
+    pub processed: usize,
+    pub roles_granted: usize,
+    pub failed: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub abort_reason: Option<&'static str>,
+    /// seconds since the unix epoch
+    #[serde(serialize_with = "crate::timestamps::unix_seconds::serialize")]
+    pub started_at: u64,
+    #[serde(serialize_with = "crate::timestamps::unix_seconds::serialize_option")]
+    pub finished_at: Option<u64>,
+}
+
+impl ResyncJob {
+    pub fn new(job_id: String, now: u64) -> Self {
+        ResyncJob {
+            job_id,
+            state: ResyncState::Running,
+            processed: 0,
+            roles_granted: 0,
+            failed: 0,
+            abort_reason: None,
+            started_at: now,
+            finished_at: None,
+        }
+    }
+
+    pub fn finish(&mut self, abort_reason: Option<&'static str>, now: u64) {
+        self.state = match abort_reason {
+            Some(_) => ResyncState::Aborted,
+            None => ResyncState::Completed,
+        };
+        self.abort_reason = abort_reason;
+        self.finished_at = Some(now);
+    }
+
+    /// the webhook log the job ends with, anything that didn't go through is a FAILURE
+    pub fn summary_log(&self) -> (String, LOG) {
+        let mut message = format!(
+            "the role resync {} {} after processing {} users, {} roles were granted and {} users failed",
+            self.job_id,
+            match self.state {
+                ResyncState::Aborted => "was aborted",
+                _ => "finished",
+            },
+            self.processed,
+            self.roles_granted,
+            self.failed
+        );
+        if let Some(abort_reason) = self.abort_reason {
+            message.push_str(&format!(" ({})", abort_reason));
+        }
+
+        let log = if self.state == ResyncState::Aborted || self.failed > 0 {
+            LOG::FAILURE
+        } else {
+            LOG::SUCCESSFUL
+        };
+        (message, log)
+    }
+}
+
+/// The resyncs that were started recently, only one runs at a time so two of them don't share the
+/// rate limit.
+pub struct ResyncJobs {
+    jobs: TtlMap<String, ResyncJob>,
+}
+
+impl Default for ResyncJobs {
+    fn default() -> Self {
+        ResyncJobs {
+            jobs: TtlMap::new(JOB_TTL),
+        }
+    }
+}
+
+impl ResyncJobs {
+    /// starts tracking a new job, `None` while another one is still running
+    pub fn start(&mut self, job: ResyncJob, now: Instant) -> Option<ResyncJob> {
+        self.jobs.purge_expired(now);
+        let running = self
+            .jobs
+            .live_entries(now)
+            .into_iter()
+            .any(|(_, job, _)| job.state == ResyncState::Running);
+        if running {
+            return None;
+        }
+
+        self.jobs.insert(job.job_id.clone(), job.clone(), now);
+        Some(job)
+    }
+
+    pub fn get(&self, job_id: &str, now: Instant) -> Option<ResyncJob> {
+        self.jobs.get(&job_id.to_owned(), now).cloned()
+    }
+
+    pub fn update(&mut self, job: &ResyncJob, now: Instant) {
+        if let Some(tracked) = self.jobs.get_mut(&job.job_id, now) {
+            *tracked = job.clone();
+        }
+    }
+}
+
+pub fn resync_jobs() -> &'static Mutex<ResyncJobs> {
+    RESYNC_JOBS.get_or_init(|| Mutex::new(ResyncJobs::default()))
+}
+
+/// counts the global rate limits a resync ran into without a user getting through in between
+#[derive(Default)]
+pub struct RateLimitStreak {
+    in_a_row: u32,
+}
+
+impl RateLimitStreak {
+    /// records a user that got through or failed for another reason
+    pub fn reset(&mut self) {
+        self.in_a_row = 0;
+    }
+
+    /// records a rate limited user, returns whether the resync should give up
+    pub fn record(&mut self) -> bool {
+        self.in_a_row += 1;
+        self.in_a_row >= MAX_RATE_LIMITS_IN_A_ROW
+    }
+}
+
+#[test]
+fn only_one_resync_runs_at_a_time() {
+    let start = Instant::now();
+    let mut jobs = ResyncJobs::default();
+
+    let mut first = jobs
+        .start(ResyncJob::new("1".to_owned(), 0), start)
+        .unwrap();
+    assert_eq!(jobs.start(ResyncJob::new("2".to_owned(), 0), start), None);
+
+    first.processed = 10;
+    first.finish(None, 60);
+    jobs.update(&first, start);
+    assert_eq!(jobs.get("1", start), Some(first));
+    assert!(jobs
+        .start(ResyncJob::new("2".to_owned(), 60), start)
+        .is_some());
+
+    // finished jobs can't be polled forever
+    assert_eq!(jobs.get("1", start + JOB_TTL), None);
+}
+
+#[test]
+fn resyncs_give_up_on_repeated_rate_limits() {
+    let mut streak = RateLimitStreak::default();
+
+    assert!(!streak.record());
+    assert!(!streak.record());
+    streak.reset();
+    assert!(!streak.record());
+    assert!(!streak.record());
+    assert!(streak.record());
+}
+
+#[test]
+fn resync_summaries_are_failures_unless_everyone_got_through() {
+    let mut job = ResyncJob::new("job".to_owned(), 0);
+    job.processed = 3;
+    job.roles_granted = 2;
+    job.finish(None, 60);
+    let (message, log) = job.summary_log();
+    assert_eq!(
+        message,
+        "the role resync job finished after processing 3 users, 2 roles were granted and 0 users failed"
+    );
+    assert!(matches!(log, LOG::SUCCESSFUL));
+
+    job.failed = 1;
+    assert!(matches!(job.summary_log().1, LOG::FAILURE));
+
+    job.finish(Some("Discord kept rate limiting us"), 60);
+    let (message, log) = job.summary_log();
+    assert!(message.contains("was aborted"));
+    assert!(message.ends_with("(Discord kept rate limiting us)"));
+    assert!(matches!(log, LOG::FAILURE));
+}
@@ -0,0 +1,137 @@
+use actix_web::rt::{self, time};
+use deadpool_postgres::Pool;
+use std::time::{Duration, SystemTime};
+
+use crate::{
+    constants::{ErrorLogType, LOG},
+    db,
+    digest::{unix_now, DIGEST_COUNT_RETENTION},
+    errors::{ConvertResultErrorToMyError, LogMyError, MyError},
+    import::FAILURE_RETENTION,
+    stats_history::unix_day,
+    webhook_logging::webhook_log,
+};
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const DAY: u64 = 24 * 60 * 60;
+
+/// periodically deletes data that has outlived its retention period, idempotency keys are kept for
+/// `idempotency_key_ttl`, stats snapshots for `stats_retention_days` and the snapshots of users'
+/// data for `snapshot_retention_days`
+pub fn spawn_cleanup_scheduler(
+    pool: Pool,
+    idempotency_key_ttl: Duration,
+    stats_retention_days: u64,
+    snapshot_retention_days: u64,
+) {
+    rt::spawn(async move {
+        let mut interval = time::interval(CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let _ = run_cleanup(
+                &pool,
+                idempotency_key_ttl,
+                stats_retention_days,
+                snapshot_retention_days,
+            )
+            .await;
+        }
+    });
+}
+
+async fn run_cleanup(
+    pool: &Pool,
+    idempotency_key_ttl: Duration,
+    stats_retention_days: u64,
+    snapshot_retention_days: u64,
+) -> Result<(), MyError> {
+    let client = pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "cleanup failed at creating database client",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let deleted_failures =
+        db::delete_expired_import_failures(&client, &(SystemTime::now() - FAILURE_RETENTION))
+            .await
+            .make_response(MyError::InternalError(
+                "cleanup failed at deleting expired import failures",
+            ))
+            .make_log(ErrorLogType::INTERNAL)
+            .await?;
+
+    if deleted_failures > 0 {
+        webhook_log(
+            format!(
+                "cleanup deleted {} expired import failures",
+                deleted_failures
+            ),
+            LOG::INFORMATIONAL,
+        )
+        .await;
+    }
+
+    // expired keys aren't replayed anymore either way, so they aren't worth a log
+    db::delete_expired_idempotency_keys(&client, &(SystemTime::now() - idempotency_key_ttl))
+        .await
+        .make_response(MyError::InternalError(
+            "cleanup failed at deleting expired idempotency keys",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    // expired codes can't be consumed anymore, they're only deleted to keep the table small
+    db::delete_expired_link_codes(&client, &SystemTime::now())
+        .await
+        .make_response(MyError::InternalError(
+            "cleanup failed at deleting expired link codes",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    // the digest only reads the last week, older counts aren't worth a log
+    db::delete_expired_digest_counts(&client, &(SystemTime::now() - DIGEST_COUNT_RETENTION))
+        .await
+        .make_response(MyError::InternalError(
+            "cleanup failed at deleting expired digest counts",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    // the history only goes back as far as it's kept, so pruned days aren't worth a log either
+    let first_kept_day = unix_day(unix_now()) - stats_retention_days as i64;
+    db::delete_expired_stats_snapshots(&client, first_kept_day)
+        .await
+        .make_response(MyError::InternalError(
+            "cleanup failed at deleting expired stats snapshots",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let deleted_snapshots = db::delete_expired_userdata_snapshots(
+        &client,
+        &(SystemTime::now() - Duration::from_secs(snapshot_retention_days * DAY)),
+    )
+    .await
+    .make_response(MyError::InternalError(
+        "cleanup failed at deleting expired userdata snapshots",
+    ))
+    .make_log(ErrorLogType::INTERNAL)
+    .await?;
+
+    if deleted_snapshots > 0 {
+        webhook_log(
+            format!(
+                "cleanup deleted {} expired userdata snapshots",
+                deleted_snapshots
+            ),
+            LOG::INFORMATIONAL,
+        )
+        .await;
+    }
+
+    Ok(())
+}
@@ -1,30 +1,367 @@
 use dotenv::vars;
+use std::{collections::HashMap, time::Duration};
+
+use crate::{
+    audit::{parse_webhook_url, SecurityWebhook},
+    constants::C2SGUILD,
+    db::is_valid_schema_name,
+    email_domains::{parse_domains, DomainLimits},
+    fields::ProgressMaxima,
+    headers::{parse_admin_api_keys, AdminApiKey},
+    middleware_stack::{parse_layers, Layer},
+    net::{parse_cidrs, Cidr},
+    nicknames::{parse_nickname_policies, NicknamePolicy},
+    role_rules::{parse_role_rules, RoleRule},
+    route_limits::ClassLimits,
+    session_settings::{application_name, SessionSettings},
+    slo::{parse_slo_targets, SloTarget},
+    sync_streaks::{parse_streak_rules, StreakRule},
+    token_sharing::TOKEN_SHARING_WINDOW,
+    webhook_logging::{parse_webhook_identity, WebhookStyle},
+};
 
 #[derive(Debug)]
 pub struct Config {
     pub discord_token: String,
     pub webhook_id: String,
     pub webhook_token: String,
+    /// the username, avatar and prefix the logs are sent with
+    pub webhook_style: WebhookStyle,
     pub userdata_auth: String,
+    /// the keys admin routes accept in `X-Admin-Key` next to the `X-Semblance-Exclusive` secret
+    pub admin_api_keys: Vec<AdminApiKey>,
     pub server_addr: String,
     pub game_saves_dev_api: String,
     pub game_saves_prod_api: String,
+    /// seconds during which a user gets at most one INFORMATIONAL webhook log
+    pub log_throttle_window: u64,
+    /// seconds a request to the userdata routes may take before it's answered with a 504
+    pub request_timeout: u64,
+    /// shared with the game servers for registering support codes, an empty secret disables it
+    pub beta_channel_secret: String,
+    /// signs the sync requests, an empty secret leaves them unsigned
+    pub request_signing_secret: String,
+    /// seconds a signed request's timestamp may be off from the server's clock
+    pub signature_window: u64,
+    /// signs the records `GET /admin/users/{discord_id}/portable` exports, instances that move users
+    /// between each other share it, an empty secret disables exporting and importing them
+    pub portability_secret: String,
+    /// the hours between UTC and the timezone the weekly digest's Monday is in
+    pub digest_utc_offset: i64,
+    /// the names guilds are shown with when telling users which roles they gained where
+    pub guild_names: HashMap<u64, String>,
+    /// the progress tier prefixes put in front of members' nicknames, per guild
+    pub nickname_policies: HashMap<u64, NicknamePolicy>,
+    /// how many gained roles messages and logs name, the rest are counted as "and N more"
+    pub gained_roles_listed: usize,
+    /// the game servers' egress ranges, the og endpoint rejects everyone else unless it's empty
+    pub og_allowed_cidrs: Vec<Cidr>,
+    /// where the admin routes can be used from, a valid key from anywhere else is a 403 unless
+    /// it's empty
+    pub admin_allowed_cidrs: Vec<Cidr>,
+    /// lets every source use the admin routes despite `admin_allowed_cidrs`, e.g. when the
+    /// office's address changed, it's reported to the security webhook at startup
+    pub admin_allowlist_break_glass: bool,
+    /// the proxies whose X-Forwarded-For header is believed when working out a client's address
+    pub trusted_proxies: Vec<Cidr>,
+    /// the hours between UTC and the timezone sync streak weeks start on Monday in, it's a fixed
+    /// offset so daylight saving doesn't move week boundaries
+    pub streak_utc_offset: i64,
+    /// the roles for syncing at least once a week for a number of weeks in a row
+    pub streak_rules: Vec<StreakRule>,
+    /// milestone roles added or changed without a release, see `RoleRule`
+    pub role_rules: Vec<RoleRule>,
+    /// seconds a sync's role evaluation waits for further syncs of the user, 0 evaluates right away
+    pub role_debounce_seconds: u64,
+    /// milliseconds between the users of `POST /admin/roles/resync`, so it leaves the syncs some of
+    /// Discord's rate limit
+    pub bulk_resync_delay_ms: u64,
+    /// where admin actions are reported, they fall back to the FAILURE logs without it
+    pub security_webhook: Option<SecurityWebhook>,
+    /// seconds a role can be missing from the guild before its rule is disabled
+    pub missing_role_disable_after: u64,
+    /// failed creates an email gets per day before further creates with it are refused
+    pub link_attempts_per_day: u32,
+    /// salts the email hashes failed creates are counted under, an empty salt disables the limit
+    pub link_attempt_salt: String,
+    /// how many accounts can be linked per email domain, counted under `link_attempt_salt`
+    pub domain_limits: DomainLimits,
+    /// seconds during which a repeated og payload gets the first response instead of being synced
+    pub og_dedup_window: u64,
+    /// seconds a create's Idempotency-Key is replayed for
+    pub idempotency_key_ttl: u64,
+    /// days the daily stats snapshots are kept for
+    pub stats_retention_days: u64,
+    /// days the snapshots of users' data are kept for, a user's first one is kept for good
+    pub snapshot_retention_days: u64,
+    /// whether deleting a link also takes the roles our rules granted away from the member
+    pub remove_roles_on_delete: bool,
+    /// how many distinct tokens a discord id can sync with in a week before it's flagged for review
+    pub token_sharing_threshold: usize,
+    /// the most users GET /admin/users lists per page, a higher `limit` is clamped to it
+    pub admin_page_limit: i64,
+    /// the highest values progress can be synced with, past them a sync is a 400
+    pub progress_maxima: ProgressMaxima,
+    /// the latency and success rate promised per route, reported by GET /admin/slo and the digest
+    pub slo_targets: Vec<SloTarget>,
+    /// the app middleware that's turned off, see `Layer::ORDER` for the ones there are
+    pub disabled_middleware: Vec<Layer>,
+    pub mutation_limits: ClassLimits,
+    pub read_limits: ClassLimits,
+    pub touch_limits: ClassLimits,
+    pub admin_limits: ClassLimits,
+    pub public_limits: ClassLimits,
+    /// the Postgres schema the tables live in, so several game environments can share a database
+    pub db_schema: String,
+    /// whether the migrations the schema is missing are run at startup, e.g. off when a deploy job
+    /// runs them
+    pub migrate_on_startup: bool,
+    /// starts the server even though the schema is missing migrations this binary expects
+    pub serve_with_pending_migrations: bool,
     pub pg: deadpool_postgres::Config,
+    /// what every database connection is set up with, see `SessionSettings`
+    pub session: SessionSettings,
 }
 impl Config {
     pub fn new() -> Self {
         let environment_vars: Vec<(String, String)> = vars().collect();
         let mut database_config = deadpool_postgres::Config::new();
         Config::setup_pg_config(&mut database_config, &environment_vars);
+        let db_schema = find_key_or(&environment_vars, "DB_SCHEMA", "public");
+        assert!(
+            is_valid_schema_name(&db_schema),
+            "DB_SCHEMA has to be a lowercase identifier"
+        );
+        let snapshot_retention_days: u64 =
+            find_key_or(&environment_vars, "SNAPSHOT_RETENTION_DAYS", "90")
+                .parse()
+                .unwrap();
+        assert!(
+            snapshot_retention_days >= TOKEN_SHARING_WINDOW.as_secs() / (24 * 60 * 60),
+            "SNAPSHOT_RETENTION_DAYS can't be shorter than the week token sharing is detected in"
+        );
+        let session = SessionSettings {
+            application_name: application_name(&find_key_or(
+                &environment_vars,
+                "ENVIRONMENT",
+                "production",
+            )),
+            statement_timeout: Duration::from_secs(
+                find_key_or(&environment_vars, "STATEMENT_TIMEOUT", "30")
+                    .parse()
+                    .unwrap(),
+            ),
+            idle_in_transaction_session_timeout: Duration::from_secs(
+                find_key_or(&environment_vars, "IDLE_IN_TRANSACTION_TIMEOUT", "60")
+                    .parse()
+                    .unwrap(),
+            ),
+            // without it the connections keep Postgres' own search_path
+            search_path: environment_vars
+                .iter()
+                .any(|(key, _)| key == "DB_SCHEMA")
+                .then(|| db_schema.clone()),
+        };
         Config {
             discord_token: find_key(&environment_vars, "DISCORD_TOKEN"),
             webhook_id: find_key(&environment_vars, "WEBHOOK_ID"),
             webhook_token: find_key(&environment_vars, "WEBHOOK_TOKEN"),
+            webhook_style: WebhookStyle {
+                successful: parse_webhook_identity(
+                    &find_key_or(&environment_vars, "WEBHOOK_SUCCESSFUL_USERNAME", ""),
+                    &find_key_or(&environment_vars, "WEBHOOK_SUCCESSFUL_AVATAR_URL", ""),
+                )
+                .unwrap(),
+                informational: parse_webhook_identity(
+                    &find_key_or(&environment_vars, "WEBHOOK_INFORMATIONAL_USERNAME", ""),
+                    &find_key_or(&environment_vars, "WEBHOOK_INFORMATIONAL_AVATAR_URL", ""),
+                )
+                .unwrap(),
+                failure: parse_webhook_identity(
+                    &find_key_or(&environment_vars, "WEBHOOK_FAILURE_USERNAME", ""),
+                    &find_key_or(&environment_vars, "WEBHOOK_FAILURE_AVATAR_URL", ""),
+                )
+                .unwrap(),
+                prefix: find_key_or(&environment_vars, "WEBHOOK_PREFIX", ""),
+            },
             userdata_auth: find_key(&environment_vars, "USERDATA_AUTH"),
+            admin_api_keys: parse_admin_api_keys(&find_key_or(
+                &environment_vars,
+                "ADMIN_API_KEYS",
+                "",
+            )),
             server_addr: find_key(&environment_vars, "SERVER_ADDR"),
             game_saves_dev_api: find_key(&environment_vars, "GAME_SAVES_DEV_API"),
             game_saves_prod_api: find_key(&environment_vars, "GAME_SAVES_PROD_API"),
+            log_throttle_window: find_key_or(&environment_vars, "LOG_THROTTLE_WINDOW", "3600")
+                .parse()
+                .unwrap(),
+            request_timeout: find_key_or(&environment_vars, "REQUEST_TIMEOUT", "10")
+                .parse()
+                .unwrap(),
+            beta_channel_secret: find_key_or(&environment_vars, "BETA_CHANNEL_SECRET", ""),
+            request_signing_secret: find_key_or(&environment_vars, "REQUEST_SIGNING_SECRET", ""),
+            signature_window: find_key_or(&environment_vars, "SIGNATURE_WINDOW", "300")
+                .parse()
+                .unwrap(),
+            portability_secret: find_key_or(&environment_vars, "PORTABILITY_SECRET", ""),
+            digest_utc_offset: find_key_or(&environment_vars, "DIGEST_UTC_OFFSET", "0")
+                .parse()
+                .unwrap(),
+            guild_names: parse_guild_names(&find_key_or(&environment_vars, "GUILD_NAMES", "")),
+            nickname_policies: parse_nickname_policies(
+                &find_key_or(&environment_vars, "NICKNAME_TIERS", ""),
+                &find_key_or(&environment_vars, "NICKNAME_GUILDS", ""),
+            ),
+            gained_roles_listed: find_key_or(&environment_vars, "GAINED_ROLES_LISTED", "5")
+                .parse()
+                .unwrap(),
+            og_allowed_cidrs: parse_cidrs(&find_key_or(&environment_vars, "OG_ALLOWED_CIDRS", ""))
+                .unwrap(),
+            admin_allowed_cidrs: parse_cidrs(&find_key_or(
+                &environment_vars,
+                "ADMIN_ALLOWED_CIDRS",
+                "",
+            ))
+            .unwrap(),
+            admin_allowlist_break_glass: find_key_or(
+                &environment_vars,
+                "ADMIN_ALLOWLIST_BREAK_GLASS",
+                "false",
+            )
+            .parse()
+            .unwrap(),
+            trusted_proxies: parse_cidrs(&find_key_or(&environment_vars, "TRUSTED_PROXIES", ""))
+                .unwrap(),
+            streak_utc_offset: find_key_or(&environment_vars, "STREAK_UTC_OFFSET", "0")
+                .parse()
+                .unwrap(),
+            streak_rules: parse_streak_rules(&find_key_or(&environment_vars, "STREAK_ROLES", "")),
+            role_rules: parse_role_rules(&find_key_or(&environment_vars, "ROLE_RULES", "")),
+            role_debounce_seconds: find_key_or(&environment_vars, "ROLE_DEBOUNCE_SECONDS", "0")
+                .parse()
+                .unwrap(),
+            bulk_resync_delay_ms: find_key_or(&environment_vars, "BULK_RESYNC_DELAY_MS", "1000")
+                .parse()
+                .unwrap(),
+            security_webhook: parse_webhook_url(&find_key_or(
+                &environment_vars,
+                "SECURITY_WEBHOOK_URL",
+                "",
+            )),
+            missing_role_disable_after: find_key_or(
+                &environment_vars,
+                "MISSING_ROLE_DISABLE_AFTER",
+                "86400",
+            )
+            .parse()
+            .unwrap(),
+            link_attempts_per_day: find_key_or(&environment_vars, "LINK_ATTEMPTS_PER_DAY", "20")
+                .parse()
+                .unwrap(),
+            link_attempt_salt: find_key_or(&environment_vars, "LINK_ATTEMPT_SALT", ""),
+            domain_limits: DomainLimits {
+                cap: find_key_or(&environment_vars, "DOMAIN_LINK_CAP", "0")
+                    .parse()
+                    .unwrap(),
+                allowlist: parse_domains(&find_key_or(&environment_vars, "DOMAIN_ALLOWLIST", "")),
+                denylist: parse_domains(&find_key_or(&environment_vars, "DOMAIN_DENYLIST", "")),
+            },
+            og_dedup_window: find_key_or(&environment_vars, "OG_DEDUP_WINDOW", "30")
+                .parse()
+                .unwrap(),
+            idempotency_key_ttl: find_key_or(&environment_vars, "IDEMPOTENCY_KEY_TTL", "86400")
+                .parse()
+                .unwrap(),
+            stats_retention_days: find_key_or(&environment_vars, "STATS_RETENTION_DAYS", "365")
+                .parse()
+                .unwrap(),
+            snapshot_retention_days,
+            remove_roles_on_delete: find_key_or(
+                &environment_vars,
+                "REMOVE_ROLES_ON_DELETE",
+                "false",
+            )
+            .parse()
+            .unwrap(),
+            token_sharing_threshold: find_key_or(&environment_vars, "TOKEN_SHARING_THRESHOLD", "3")
+                .parse()
+                .unwrap(),
+            admin_page_limit: find_key_or(&environment_vars, "ADMIN_PAGE_LIMIT", "100")
+                .parse::<i64>()
+                .ok()
+                .filter(|limit| *limit > 0)
+                .expect("ADMIN_PAGE_LIMIT has to be a positive number"),
+            progress_maxima: ProgressMaxima::parse(&find_key_or(
+                &environment_vars,
+                "PROGRESS_MAXIMA",
+                "",
+            )),
+            slo_targets: parse_slo_targets(&find_key_or(
+                &environment_vars,
+                "SLO_TARGETS",
+                "update_user:2000:99,og_update_user:2000:99",
+            ))
+            .unwrap(),
+            disabled_middleware: parse_layers(&find_key_or(
+                &environment_vars,
+                "DISABLED_MIDDLEWARE",
+                "",
+            ))
+            .unwrap(),
+            mutation_limits: ClassLimits {
+                requests_per_window: find_key_or(&environment_vars, "RATE_LIMIT_MUTATION", "30")
+                    .parse()
+                    .unwrap(),
+                max_concurrent: find_key_or(&environment_vars, "CONCURRENCY_MUTATION", "16")
+                    .parse()
+                    .unwrap(),
+            },
+            read_limits: ClassLimits {
+                requests_per_window: find_key_or(&environment_vars, "RATE_LIMIT_READ", "300")
+                    .parse()
+                    .unwrap(),
+                max_concurrent: find_key_or(&environment_vars, "CONCURRENCY_READ", "128")
+                    .parse()
+                    .unwrap(),
+            },
+            touch_limits: ClassLimits {
+                requests_per_window: find_key_or(&environment_vars, "RATE_LIMIT_TOUCH", "600")
+                    .parse()
+                    .unwrap(),
+                max_concurrent: find_key_or(&environment_vars, "CONCURRENCY_TOUCH", "64")
+                    .parse()
+                    .unwrap(),
+            },
+            admin_limits: ClassLimits {
+                requests_per_window: find_key_or(&environment_vars, "RATE_LIMIT_ADMIN", "60")
+                    .parse()
+                    .unwrap(),
+                max_concurrent: find_key_or(&environment_vars, "CONCURRENCY_ADMIN", "4")
+                    .parse()
+                    .unwrap(),
+            },
+            public_limits: ClassLimits {
+                requests_per_window: find_key_or(&environment_vars, "RATE_LIMIT_PUBLIC", "20")
+                    .parse()
+                    .unwrap(),
+                max_concurrent: find_key_or(&environment_vars, "CONCURRENCY_PUBLIC", "16")
+                    .parse()
+                    .unwrap(),
+            },
+            db_schema,
+            migrate_on_startup: find_key_or(&environment_vars, "MIGRATE_ON_STARTUP", "true")
+                .parse()
+                .unwrap(),
+            serve_with_pending_migrations: find_key_or(
+                &environment_vars,
+                "SERVE_WITH_PENDING_MIGRATIONS",
+                "false",
+            )
+            .parse()
+            .unwrap(),
             pg: database_config,
+            session,
         }
     }
 
@@ -39,6 +376,11 @@ impl Config {
         db_config.dbname = Some(find_key(env_vars, "DBNAME"));
         db_config
     }
+
+    /// the debounce window of syncs' role evaluations, `None` evaluates them during the sync
+    pub fn role_debounce(&self) -> Option<Duration> {
+        (self.role_debounce_seconds > 0).then(|| Duration::from_secs(self.role_debounce_seconds))
+    }
 }
 
 impl Default for Config {
@@ -47,6 +389,22 @@ impl Default for Config {
     }
 }
 
+/// parses `"{guild_id}:{name},{guild_id}:{name}"`, the C2S guild always has a name
+pub fn parse_guild_names(guild_names: &str) -> HashMap<u64, String> {
+    let mut parsed = guild_names
+        .split(',')
+        .filter_map(|entry| {
+            let (guild_id, name) = entry.split_once(':')?;
+            Some((guild_id.trim().parse::<u64>().ok()?, name.trim().to_owned()))
+        })
+        .collect::<HashMap<u64, String>>();
+    parsed
+        .entry(C2SGUILD)
+        .or_insert_with(|| "Cell to Singularity".to_owned());
+
+    parsed
+}
+
 pub fn find_key(iteration: &[(String, String)], key_search: &'static str) -> String {
     match iteration.iter().find(|(key, _)| key == key_search) {
         Some((_, value)) => value.to_string(),
@@ -56,3 +414,14 @@ pub fn find_key(iteration: &[(String, String)], key_search: &'static str) -> Str
         ),
     }
 }
+
+pub fn find_key_or(
+    iteration: &[(String, String)],
+    key_search: &'static str,
+    default: &'static str,
+) -> String {
+    match iteration.iter().find(|(key, _)| key == key_search) {
+        Some((_, value)) => value.to_string(),
+        None => default.to_owned(),
+    }
+}
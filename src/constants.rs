@@ -1,3 +1,7 @@
+use sha2::{Digest, Sha256};
+
+use crate::recent_errors::token_fingerprint;
+
 pub mod roles {
     pub const PALEONTOLOGIST_LEGEND: u64 = 892_352_619_526_377_473;
     pub const FINDER_OF_SEMBLANCE_SECRETS: u64 = 892_352_829_640_032_306;
@@ -74,11 +78,59 @@ pub enum BeyondRequirements {
     PlanetaryExplorer = 15,
 }
 
-pub enum ErrorLogType {
-    USER(String),
+pub enum ErrorLogType<'a> {
+    USER(LoggedUser<'a>),
     INTERNAL,
 }
 
+/// How a user shows up in the failure log. Anyone who can read the webhook channel could replay a
+/// full token, so it's only ever written as `token_id`. It's borrowed since it's only formatted when
+/// the error is logged.
+pub struct LoggedUser<'a> {
+    token: &'a str,
+    pub discord_id: Option<&'a str>,
+}
+
+impl<'a> LoggedUser<'a> {
+    pub fn token(token: &'a str) -> Self {
+        LoggedUser {
+            token,
+            discord_id: None,
+        }
+    }
+
+    pub fn with_discord_id(self, discord_id: &'a str) -> Self {
+        LoggedUser {
+            discord_id: Some(discord_id),
+            ..self
+        }
+    }
+
+    /// The fingerprint admins search accounts by plus the start of the token's SHA-256, enough to
+    /// tell tokens with the same fingerprint apart without being able to use either.
+    pub fn token_id(&self) -> String {
+        let hash = crate::utilities::hex_encode(&Sha256::digest(self.token.as_bytes()));
+
+        format!("{} (sha256 {})", token_fingerprint(self.token), &hash[..16])
+    }
+
+    pub fn log_message(&self, error: &impl std::fmt::Display) -> String {
+        match self.discord_id {
+            Some(discord_id) => format!(
+                "Error with a user\n\ndiscord id: {}\ntoken: {}\n\n{}",
+                discord_id,
+                self.token_id(),
+                error
+            ),
+            None => format!(
+                "Error with a user\n\ntoken: {}\n\n{}",
+                self.token_id(),
+                error
+            ),
+        }
+    }
+}
+
 pub enum LOG {
     SUCCESSFUL,
     INFORMATIONAL,
@@ -89,3 +141,32 @@ pub const BACKGROUND: &str = "\u{001b}[40m";
 pub const SUCCESSFUL: &str = "\u{001b}[0;32m";
 pub const INFORMATIONAL: &str = "\u{001b}[1;33m";
 pub const FAILURE: &str = "\u{001b}[0;31m";
+
+#[test]
+fn logged_users_never_show_their_token() {
+    let token = "80d7bf83f257ac0150c1fafc3834cd73ab3879c7";
+    let error = crate::errors::MyError::InternalError("Failed at deleting userdata");
+
+    let anonymous = LoggedUser::token(token).log_message(&error);
+    let known = LoggedUser::token(token)
+        .with_discord_id("123456789012345678")
+        .log_message(&error);
+    for message in [&anonymous, &known] {
+        assert!(!message.contains(token), "{}", message);
+        // nothing past the fingerprint is written either
+        assert!(!message.contains(&token[..9]), "{}", message);
+        assert!(message.contains("80d7bf83 (sha256 "), "{}", message);
+        assert!(
+            message.ends_with("Failed at deleting userdata"),
+            "{}",
+            message
+        );
+    }
+    assert!(known.contains("discord id: 123456789012345678"));
+
+    // tokens that share a fingerprint still get different identifiers
+    assert_ne!(
+        LoggedUser::token("80d7bf83aaaa").token_id(),
+        LoggedUser::token("80d7bf83bbbb").token_id()
+    );
+}
@@ -0,0 +1,217 @@
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+
+use crate::{errors::MyError, ttl_map::TtlMap};
+
+/// how long a create keeps its discord id claimed, both while it runs and after it went through
+const CLAIM_WINDOW: Duration = Duration::from_secs(30);
+
+/// how long a create waits for another one of the same discord id before it gives up
+const CLAIM_WAIT: Duration = Duration::from_secs(5);
+
+static CREATION_LOCKS: OnceLock<Mutex<CreationLocks>> = OnceLock::new();
+
+enum Claim {
+    /// the token's create is still running, the receiver is told once it's done
+    InFlight {
+        token: String,
+        done: watch::Receiver<bool>,
+    },
+    /// the token's create went through within the window
+    Created { token: String },
+}
+
+pub enum ClaimCheck {
+    /// nobody else is creating the discord id, the sender tells waiting creates when it's done
+    Claimed(watch::Sender<bool>),
+    Wait(watch::Receiver<bool>),
+    /// another token linked the discord id within the window
+    Conflict,
+}
+
+/// Two creates of the same discord id with different tokens both get past the existence checks,
+/// and the later one would rebind the discord id from under the first. While a create runs it
+/// claims its discord id, creates of another token wait for it and then get the conflict when it
+/// went through. Only the window is covered, a rebind after it is a deliberate one.
+pub struct CreationLocks {
+    claims: TtlMap<String, Claim>,
+}
+
+impl CreationLocks {
+    pub fn new(window: Duration) -> Self {
+        CreationLocks {
+            claims: TtlMap::new(window),
+        }
+    }
+
+    /// a create of the same token waits as well, it's answered by the checks after the claim
+    pub fn begin(&mut self, discord_id: &str, token: &str, now: Instant) -> ClaimCheck {
+        match self.claims.get(&discord_id.to_owned(), now) {
+            Some(Claim::InFlight { done, .. }) => return ClaimCheck::Wait(done.clone()),
+            Some(Claim::Created { token: created }) if created != token => {
+                return ClaimCheck::Conflict
+            }
+            _ => {}
+        }
+
+        if self.claims.len() > 10_000 {
+            self.claims.purge_expired(now);
+        }
+        let (sender, done) = watch::channel(false);
+        self.claims.insert(
+            discord_id.to_owned(),
+            Claim::InFlight {
+                token: token.to_owned(),
+                done,
+            },
+            now,
+        );
+        ClaimCheck::Claimed(sender)
+    }
+
+    /// `created` keeps the claim for the rest of the window, otherwise the discord id is free again
+    pub fn finish(&mut self, discord_id: &str, created: bool, now: Instant) {
+        let token = match self.claims.get(&discord_id.to_owned(), now) {
+            Some(Claim::InFlight { token, .. }) => token.clone(),
+            _ => return,
+        };
+        if created {
+            self.claims
+                .insert(discord_id.to_owned(), Claim::Created { token }, now);
+        } else {
+            self.claims.remove(&discord_id.to_owned());
+        }
+    }
+}
+
+pub fn creation_locks() -> &'static Mutex<CreationLocks> {
+    CREATION_LOCKS.get_or_init(|| Mutex::new(CreationLocks::new(CLAIM_WINDOW)))
+}
+
+/// the claim on a discord id, dropping it without `created` frees the discord id again
+pub struct CreationClaim<'a> {
+    locks: &'a Mutex<CreationLocks>,
+    discord_id: String,
+    done: watch::Sender<bool>,
+    created: bool,
+}
+
+impl CreationClaim<'_> {
+    /// the create went through, other tokens get the conflict for the rest of the window
+    pub fn created(mut self) {
+        self.created = true;
+    }
+}
+
+impl Drop for CreationClaim<'_> {
+    fn drop(&mut self) {
+        self.locks
+            .lock()
+            .unwrap()
+            .finish(&self.discord_id, self.created, Instant::now());
+        let _ = self.done.send(true);
+    }
+}
+
+/// Claims the discord id for the token's create, waiting for a create of it that's still running.
+/// `conflict` is what a create of another token gets, also when the running one takes too long.
+pub async fn claim_discord_id<'a>(
+    locks: &'a Mutex<CreationLocks>,
+    discord_id: &str,
+    token: &str,
+    conflict: impl Fn() -> MyError,
+) -> Result<CreationClaim<'a>, MyError> {
+    let deadline = tokio::time::Instant::now() + CLAIM_WAIT;
+    loop {
+        let check = locks
+            .lock()
+            .unwrap()
+            .begin(discord_id, token, Instant::now());
+        match check {
+            ClaimCheck::Claimed(done) => {
+                return Ok(CreationClaim {
+                    locks,
+                    discord_id: discord_id.to_owned(),
+                    done,
+                    created: false,
+                })
+            }
+            ClaimCheck::Conflict => return Err(conflict()),
+            ClaimCheck::Wait(mut done) => {
+                // a closed channel means the claim is gone already, the next round finds out why
+                let waited = tokio::time::timeout_at(deadline, done.changed()).await;
+                if waited.is_err() {
+                    return Err(conflict());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn race_claims(first_created: bool, second_token: &str) -> Result<(), MyError> {
+    let locks = Mutex::new(CreationLocks::new(CLAIM_WINDOW));
+    let conflict = || MyError::Conflict("This discord id is already bound to another account");
+
+    actix_web::rt::System::new().block_on(async {
+        let first = async {
+            let claim = claim_discord_id(&locks, "10", "token a", conflict)
+                .await
+                .unwrap();
+            // the second create arrives while the first is still writing
+            tokio::task::yield_now().await;
+            if first_created {
+                claim.created();
+            }
+        };
+        let second = async {
+            tokio::task::yield_now().await;
+            claim_discord_id(&locks, "10", second_token, conflict)
+                .await
+                .map(|_| ())
+        };
+
+        tokio::join!(first, second).1
+    })
+}
+
+#[test]
+fn the_losing_create_gets_the_conflict() {
+    let second = race_claims(true, "token b");
+
+    assert!(matches!(second, Err(MyError::Conflict(_))));
+}
+
+#[test]
+fn a_failed_create_frees_the_discord_id() {
+    assert!(race_claims(false, "token b").is_ok());
+}
+
+#[test]
+fn the_same_token_waits_and_goes_on() {
+    // the checks after the claim tell it that it's already linked
+    assert!(race_claims(true, "token a").is_ok());
+}
+
+#[test]
+fn claims_expire_with_the_window() {
+    let start = Instant::now();
+    let mut locks = CreationLocks::new(CLAIM_WINDOW);
+    assert!(matches!(
+        locks.begin("10", "token a", start),
+        ClaimCheck::Claimed(_)
+    ));
+    locks.finish("10", true, start);
+
+    assert!(matches!(
+        locks.begin("10", "token b", start + CLAIM_WINDOW / 2),
+        ClaimCheck::Conflict
+    ));
+    assert!(matches!(
+        locks.begin("10", "token b", start + CLAIM_WINDOW),
+        ClaimCheck::Claimed(_)
+    ));
+}
@@ -1,8 +1,59 @@
-use crate::models::{UpdateUserData, UserData};
+use crate::budget::RequestBudget;
+use crate::failover::is_read_only;
+use crate::models::{
+    AbuseCounterRow, ApiPartner, BetaTesterStatus, DeprecationUsageRow, DigestCountRow,
+    FingerprintMatch, ImportFailureRecord, MilestoneCount, PatchUserData, PrivacySettings,
+    PrivacySettingsPatch, PromoRoleRule, StatsResponse, SupportCodeRecord, TokenUse,
+    UpdateUserData, UserData,
+};
+use crate::recent_errors::token_fingerprint;
+use crate::role_rules::{rule_requirements, Comparison, RoleRule, RuleChannel, RuleTrace};
+use crate::session_settings::{budget_statement_timeout, session_statement_timeout};
+use crate::utilities::DerivedTokens;
 use deadpool_postgres::Client;
+use std::time::{Instant, SystemTime};
 use tokio_pg_mapper::{Error, FromTokioPostgresRow};
+use tokio_postgres::{error::SqlState, GenericClient};
+
+/// limits the statements of the connection to the time that's left in the request's budget
+async fn apply_budget(client: &Client, budget: &RequestBudget) -> Result<(), Error> {
+    let statement_timeout = budget_statement_timeout(
+        budget.remaining(Instant::now()),
+        session_statement_timeout(),
+    );
+    client
+        .batch_execute(&format!("SET statement_timeout = {}", statement_timeout))
+        .await?;
+
+    Ok(())
+}
+
+/// why looking up a user failed, a token nobody is linked with isn't a database failure
+#[derive(Debug)]
+pub enum LookupError {
+    NotFound,
+    Database(Error),
+}
+
+impl From<Error> for LookupError {
+    fn from(error: Error) -> Self {
+        LookupError::Database(error)
+    }
+}
+
+impl From<tokio_postgres::Error> for LookupError {
+    fn from(error: tokio_postgres::Error) -> Self {
+        LookupError::Database(error.into())
+    }
+}
+
+pub async fn get_userdata(
+    client: &Client,
+    budget: &RequestBudget,
+    token: &str,
+) -> Result<UserData, LookupError> {
+    apply_budget(client, budget).await?;
 
-pub async fn get_userdata(client: &Client, token: &str) -> Result<UserData, Error> {
     let _stmt = include_str!("../sql/get_userdata.sql");
     let _stmt = _stmt.replace("$token", format!("'{}'", &token).as_str());
     let stmt = client.prepare(&_stmt).await?;
@@ -11,12 +62,18 @@ pub async fn get_userdata(client: &Client, token: &str) -> Result<UserData, Erro
         .query(&stmt, &[])
         .await?
         .pop()
-        .ok_or(Error::ColumnNotFound)?;
+        .ok_or(LookupError::NotFound)?;
 
-    UserData::from_row_ref(&queried_data)
+    Ok(UserData::from_row_ref(&queried_data)?)
 }
 
-pub async fn get_userdata_by_id(client: &Client, discord_id: &str) -> Result<UserData, Error> {
+pub async fn get_userdata_by_id(
+    client: &Client,
+    budget: &RequestBudget,
+    discord_id: &str,
+) -> Result<UserData, LookupError> {
+    apply_budget(client, budget).await?;
+
     let _stmt = include_str!("../sql/get_userdata_by_id.sql");
     let stmt = client.prepare(_stmt).await?;
 
@@ -24,18 +81,101 @@ pub async fn get_userdata_by_id(client: &Client, discord_id: &str) -> Result<Use
         .query(&stmt, &[&discord_id])
         .await?
         .pop()
-        .ok_or(Error::ColumnNotFound)?;
+        .ok_or(LookupError::NotFound)?;
 
-    UserData::from_row_ref(&queried_data)
+    Ok(UserData::from_row_ref(&queried_data)?)
+}
+
+/// A page of every linked user after the discord id, ordered by discord id so paging through them
+/// doesn't skip anyone. The page starts on the discord id's unique index instead of an offset, so
+/// later pages aren't slower and users linked in between don't shift them.
+pub async fn list_userdata(
+    client: &Client,
+    limit: i64,
+    after: Option<&str>,
+) -> Result<Vec<UserData>, Error> {
+    let _stmt = include_str!("../sql/list_userdata.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .query(&stmt, &[&limit, &after])
+        .await?
+        .iter()
+        .map(UserData::from_row_ref)
+        .collect()
+}
+
+/// every account whose token has the fingerprint, different tokens can share one
+pub async fn get_userdata_by_fingerprint(
+    client: &Client,
+    fingerprint: &str,
+) -> Result<Vec<FingerprintMatch>, Error> {
+    let _stmt = include_str!("../sql/get_userdata_by_fingerprint.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .query(&stmt, &[&fingerprint])
+        .await?
+        .iter()
+        .map(FingerprintMatch::from_row_ref)
+        .collect()
+}
+
+/// what a create ran into when another request linked the token or the discord id first
+#[derive(Debug, PartialEq)]
+pub enum LinkConflict {
+    Token,
+    DiscordId,
+}
+
+impl LinkConflict {
+    /// the conflict behind a unique violation of one of the UserData constraints
+    pub fn from_constraint(constraint: &str) -> Option<LinkConflict> {
+        match constraint {
+            "UserData_pkey" => Some(LinkConflict::Token),
+            "UserData_discord_id_key" => Some(LinkConflict::DiscordId),
+            _ => None,
+        }
+    }
+}
+
+/// why creating a user failed, the constraints decide conflicts and not the checks before them
+#[derive(Debug)]
+pub enum CreateError {
+    Conflict(LinkConflict),
+    Database(Error),
+}
+
+impl From<Error> for CreateError {
+    fn from(error: Error) -> Self {
+        CreateError::Database(error)
+    }
+}
+
+impl From<tokio_postgres::Error> for CreateError {
+    fn from(error: tokio_postgres::Error) -> Self {
+        let conflict = error
+            .as_db_error()
+            .filter(|db_error| *db_error.code() == SqlState::UNIQUE_VIOLATION)
+            .and_then(|db_error| db_error.constraint())
+            .and_then(LinkConflict::from_constraint);
+        match conflict {
+            Some(conflict) => CreateError::Conflict(conflict),
+            None => CreateError::Database(error.into()),
+        }
+    }
 }
 
 pub async fn create_userdata(
     client: &Client,
+    budget: &RequestBudget,
     token: &str,
     discord_id: &str,
     beta_branch: &bool,
     user_data: UpdateUserData,
-) -> Result<UserData, Error> {
+) -> Result<UserData, CreateError> {
+    apply_budget(client, budget).await?;
+
     let _stmt = include_str!("../sql/create_userdata.sql");
     let stmt = client.prepare(_stmt).await?;
 
@@ -54,20 +194,37 @@ pub async fn create_userdata(
                 &user_data.all_sharks_obtained,
                 &user_data.all_hidden_achievements_obtained,
                 &std::time::SystemTime::now(),
+                // a rebound discord id gets a new token, its fingerprint has to follow
+                &token_fingerprint(token),
             ],
         )
         .await?
         .pop()
-        .ok_or(Error::ColumnNotFound)?;
+        // the discord id is already linked with this very token, so nothing was rebound
+        .ok_or(CreateError::Conflict(LinkConflict::Token))?;
 
-    UserData::from_row_ref(&queried_data)
+    Ok(UserData::from_row_ref(&queried_data)?)
 }
 
+/// `None` keeps the stored beta flag, a locked flag is never changed either way. Only the fields
+/// the patch has are written.
 pub async fn update_userdata(
     client: &Client,
+    budget: &RequestBudget,
     token: &str,
-    beta_branch: &bool,
-    user_data: UpdateUserData,
+    beta_branch: &Option<bool>,
+    user_data: PatchUserData,
+) -> Result<UserData, Error> {
+    apply_budget(client, budget).await?;
+
+    write_userdata(&***client, token, beta_branch, user_data).await
+}
+
+async fn write_userdata(
+    client: &impl GenericClient,
+    token: &str,
+    beta_branch: &Option<bool>,
+    user_data: PatchUserData,
 ) -> Result<UserData, Error> {
     let _stmt = include_str!("../sql/update_userdata.sql");
     let _stmt = _stmt.replace("$token", format!("'{}'", &token).as_str());
@@ -78,11 +235,12 @@ pub async fn update_userdata(
             &stmt,
             &[
                 beta_branch,
-                &(user_data.metabits as i64),
+                &user_data.metabits.map(|metabits| metabits as i64),
                 &user_data.dino_rank,
                 &user_data.prestige_rank,
                 &user_data.beyond_rank,
-                &user_data.singularity_speedrun_time,
+                &user_data.singularity_speedrun_time.flatten(),
+                &user_data.singularity_speedrun_time.is_some(),
                 &user_data.all_sharks_obtained,
                 &user_data.all_hidden_achievements_obtained,
                 &std::time::SystemTime::now(),
@@ -95,16 +253,3568 @@ pub async fn update_userdata(
     UserData::from_row_ref(&queried_data)
 }
 
-pub async fn delete_userdata(client: &Client, token: &str) -> Result<UserData, Error> {
-    let _stmt = include_str!("../sql/delete_userdata.sql");
+/// why a sync couldn't be written, a conflict with a concurrent transaction can be retried and a
+/// read-only database can be retried on a new connection
+#[derive(Debug)]
+pub enum UpdateError<E> {
+    NotFound,
+    /// the checks against the locked row turned the sync down
+    Rejected(E),
+    Conflict(tokio_postgres::Error),
+    ReadOnly(tokio_postgres::Error),
+    Database(Error),
+}
+
+impl<E> From<Error> for UpdateError<E> {
+    fn from(error: Error) -> Self {
+        // the mapper wraps the statements' errors, they're classified like unwrapped ones
+        match error {
+            Error::Conversion(source) => match source.downcast::<tokio_postgres::Error>() {
+                Ok(error) => UpdateError::from(*error),
+                Err(source) => UpdateError::Database(Error::Conversion(source)),
+            },
+            error => UpdateError::Database(error),
+        }
+    }
+}
+
+impl<E> From<tokio_postgres::Error> for UpdateError<E> {
+    fn from(error: tokio_postgres::Error) -> Self {
+        let retryable = [
+            SqlState::T_R_SERIALIZATION_FAILURE,
+            SqlState::T_R_DEADLOCK_DETECTED,
+            SqlState::LOCK_NOT_AVAILABLE,
+        ];
+        match error.code() {
+            Some(code) if retryable.contains(code) => UpdateError::Conflict(error),
+            _ if is_read_only(&error) => UpdateError::ReadOnly(error),
+            _ => UpdateError::Database(error.into()),
+        }
+    }
+}
+
+/// Locks the user's row and updates it in one transaction, so a delete or another sync can't get
+/// in between making sure the user is linked and writing their data. `prepare` gets the row as
+/// it's locked and returns the data to write, checks against the stored progress have to be made
+/// there or a concurrent sync can change it after they passed. Concurrent syncs of the same user
+/// wait for each other, the transaction is rolled back when anything fails.
+pub async fn update_linked_userdata<T, E>(
+    client: &mut Client,
+    budget: &RequestBudget,
+    token: &str,
+    beta_branch: &Option<bool>,
+    prepare: impl FnOnce(&UserData) -> Result<(PatchUserData, T), E>,
+) -> Result<(UserData, T), UpdateError<E>> {
+    apply_budget(client, budget).await?;
+
+    let transaction = client.transaction().await?;
+    let _stmt = include_str!("../sql/lock_userdata.sql");
     let _stmt = _stmt.replace("$token", format!("'{}'", &token).as_str());
+    let current = match transaction.query_opt(_stmt.as_str(), &[]).await? {
+        Some(row) => UserData::from_row_ref(&row)?,
+        None => return Err(UpdateError::NotFound),
+    };
+    let (user_data, prepared) = prepare(&current).map_err(UpdateError::Rejected)?;
+
+    let updated_data = write_userdata(&*transaction, token, beta_branch, user_data).await?;
+    transaction.commit().await?;
+
+    Ok((updated_data, prepared))
+}
+
+/// deletes the user's row and returns it, `None` when no row has the token
+pub async fn delete_userdata(
+    client: &Client,
+    budget: &RequestBudget,
+    token: &str,
+) -> Result<Option<UserData>, Error> {
+    delete_userdata_where(client, budget, &format!("\"token\" = '{}'", &token), &[]).await
+}
+
+/// deletes the row linked with the discord id the same way the user deleting it does
+pub async fn delete_userdata_by_id(
+    client: &Client,
+    budget: &RequestBudget,
+    discord_id: &str,
+) -> Result<Option<UserData>, Error> {
+    delete_userdata_where(client, budget, "\"discord_id\" = $1", &[&discord_id]).await
+}
+
+/// `delete_userdata.sql` with the row it deletes picked by `condition`, the domain count is
+/// released along with it
+async fn delete_userdata_where(
+    client: &Client,
+    budget: &RequestBudget,
+    condition: &str,
+    params: &[&(dyn tokio_postgres::types::ToSql + Sync)],
+) -> Result<Option<UserData>, Error> {
+    apply_budget(client, budget).await?;
+
+    let _stmt = include_str!("../sql/delete_userdata.sql");
+    let _stmt = _stmt.replace("$condition", condition);
     let stmt = client.prepare(&_stmt).await?;
 
+    client
+        .query_opt(&stmt, params)
+        .await?
+        .map(|row| UserData::from_row_ref(&row))
+        .transpose()
+}
+
+/// how many accounts are linked with the domain, 0 for domains nobody linked with
+pub async fn get_domain_count(client: &Client, domain_key: &str) -> Result<i64, Error> {
+    let _stmt = include_str!("../sql/get_domain_count.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .query_opt(&stmt, &[&domain_key])
+        .await?
+        .map_or(0, |row| row.get(0)))
+}
+
+/// counts the discord id's link towards the domain, deleting the link gives it back
+pub async fn count_linked_domain(
+    client: &Client,
+    discord_id: &str,
+    domain_key: &str,
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/count_linked_domain.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.execute(&stmt, &[&discord_id, &domain_key]).await?)
+}
+
+pub async fn create_userdata_snapshot(
+    client: &Client,
+    budget: &RequestBudget,
+    user_data: &UserData,
+) -> Result<(), Error> {
+    apply_budget(client, budget).await?;
+
+    let _stmt = include_str!("../sql/create_userdata_snapshot.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .execute(
+            &stmt,
+            &[
+                &user_data.token,
+                &user_data.discord_id,
+                &user_data.beta_tester,
+                &user_data.metabits,
+                &user_data.dino_rank,
+                &user_data.prestige_rank,
+                &user_data.beyond_rank,
+                &user_data.singularity_speedrun_time,
+                &user_data.all_sharks_obtained,
+                &user_data.all_hidden_achievements_obtained,
+                &user_data.edited_timestamp,
+            ],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// retrieves the most recent snapshot of a user's data that was written at or before `as_of`
+pub async fn get_userdata_snapshot(
+    client: &Client,
+    budget: &RequestBudget,
+    discord_id: &str,
+    as_of: &SystemTime,
+) -> Result<UserData, Error> {
+    apply_budget(client, budget).await?;
+
+    let _stmt = include_str!("../sql/get_userdata_snapshot.sql");
+    let stmt = client.prepare(_stmt).await?;
+
     let queried_data = client
-        .query(&stmt, &[])
+        .query(&stmt, &[&discord_id, as_of])
         .await?
         .pop()
         .ok_or(Error::ColumnNotFound)?;
 
     UserData::from_row_ref(&queried_data)
 }
+
+/// every snapshot of the user's data, oldest first
+pub async fn get_userdata_snapshots(
+    client: &Client,
+    discord_id: &str,
+) -> Result<Vec<UserData>, Error> {
+    let _stmt = include_str!("../sql/get_userdata_snapshots.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .query(&stmt, &[&discord_id])
+        .await?
+        .iter()
+        .map(UserData::from_row_ref)
+        .collect()
+}
+
+/// Writes a user exported from another instance along with their granted roles and snapshots, in
+/// one transaction. Unlike creating a user nothing is ever rebound, a token or discord id that's
+/// already linked is a conflict.
+pub async fn import_portable_user(
+    client: &mut Client,
+    user_data: &UserData,
+    granted_roles: &str,
+    history: &[UserData],
+) -> Result<(), CreateError> {
+    let transaction = client.transaction().await?;
+
+    let _stmt = include_str!("../sql/import_userdata.sql");
+    transaction
+        .execute(
+            _stmt,
+            &[
+                &user_data.token,
+                &user_data.discord_id,
+                &user_data.beta_tester,
+                &user_data.metabits,
+                &user_data.dino_rank,
+                &user_data.prestige_rank,
+                &user_data.beyond_rank,
+                &user_data.singularity_speedrun_time,
+                &user_data.all_sharks_obtained,
+                &user_data.all_hidden_achievements_obtained,
+                &user_data.edited_timestamp,
+                &token_fingerprint(&user_data.token),
+            ],
+        )
+        .await?;
+
+    let _stmt = include_str!("../sql/update_granted_roles.sql");
+    transaction
+        .execute(_stmt, &[&user_data.discord_id, &granted_roles])
+        .await?;
+
+    let _stmt = include_str!("../sql/create_userdata_snapshot.sql");
+    let stmt = transaction.prepare(_stmt).await?;
+    for snapshot in history {
+        transaction
+            .execute(
+                &stmt,
+                &[
+                    &snapshot.token,
+                    &snapshot.discord_id,
+                    &snapshot.beta_tester,
+                    &snapshot.metabits,
+                    &snapshot.dino_rank,
+                    &snapshot.prestige_rank,
+                    &snapshot.beyond_rank,
+                    &snapshot.singularity_speedrun_time,
+                    &snapshot.all_sharks_obtained,
+                    &snapshot.all_hidden_achievements_obtained,
+                    &snapshot.edited_timestamp,
+                ],
+            )
+            .await?;
+    }
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+pub async fn delete_userdata_snapshots(client: &Client, discord_id: &str) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/delete_userdata_snapshots.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.execute(&stmt, &[&discord_id]).await?)
+}
+
+/// the Idempotency-Key responses of the creates that were sent with the token
+pub async fn delete_idempotency_keys(client: &Client, token: &str) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/delete_idempotency_keys.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.execute(&stmt, &[&token]).await?)
+}
+
+/// the link codes handed out for the discord id, consumed or not
+pub async fn delete_link_codes(client: &Client, discord_id: &str) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/delete_link_codes.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.execute(&stmt, &[&discord_id]).await?)
+}
+
+pub async fn delete_user_activity(client: &Client, discord_id: &str) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/delete_user_activity.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.execute(&stmt, &[&discord_id]).await?)
+}
+
+pub async fn delete_token_transitions(client: &Client, discord_id: &str) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/delete_token_transitions.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.execute(&stmt, &[&discord_id]).await?)
+}
+
+/// Deletes the snapshots written before `before`, per discord id so a token migration doesn't
+/// leave any behind. A user's first snapshot is kept since it's when they linked.
+pub async fn delete_expired_userdata_snapshots(
+    client: &Client,
+    before: &SystemTime,
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/delete_expired_userdata_snapshots.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.execute(&stmt, &[before]).await?)
+}
+
+/// the tokens every discord id that synced with more than one token in the window used, oldest
+/// first
+pub async fn get_token_uses(client: &Client, since: &SystemTime) -> Result<Vec<TokenUse>, Error> {
+    let _stmt = include_str!("../sql/get_token_uses.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .query(&stmt, &[since])
+        .await?
+        .iter()
+        .map(TokenUse::from_row_ref)
+        .collect()
+}
+
+/// Marks the discord id moving to the token as expected, admin flows that rekey or relink an
+/// account have to record their transitions so the token sharing detector ignores them.
+pub async fn create_token_transition(
+    client: &Client,
+    discord_id: &str,
+    token: &str,
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/create_token_transition.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .execute(&stmt, &[&discord_id, &token, &SystemTime::now()])
+        .await?)
+}
+
+/// flags the discord ids for review, returns the ones that weren't flagged already
+pub async fn flag_for_review(
+    client: &Client,
+    discord_ids: &[String],
+) -> Result<Vec<String>, Error> {
+    let _stmt = include_str!("../sql/flag_for_review.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .query(&stmt, &[&discord_ids, &SystemTime::now()])
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect())
+}
+
+/// counts the users that were flagged for review within the window
+pub async fn count_flagged_for_review(
+    client: &Client,
+    start: &SystemTime,
+    end: &SystemTime,
+) -> Result<i64, Error> {
+    let _stmt = include_str!("../sql/count_flagged_for_review.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.query_one(&stmt, &[start, end]).await?.get(0))
+}
+
+/// counts the users whose first snapshot was written within the window
+pub async fn count_new_links(
+    client: &Client,
+    start: &SystemTime,
+    end: &SystemTime,
+) -> Result<i64, Error> {
+    let _stmt = include_str!("../sql/count_new_links.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.query_one(&stmt, &[start, end]).await?.get(0))
+}
+
+pub async fn count_userdata(client: &Client) -> Result<i64, Error> {
+    let _stmt = include_str!("../sql/count_userdata.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.query_one(&stmt, &[]).await?.get(0))
+}
+
+/// The SQL a rule's requirement is counted with, `$n` is its threshold. `None` for fields that
+/// aren't columns, `TRUE` for rules without a threshold.
+fn requirement_condition(requirement: &RuleTrace, param: usize) -> Option<String> {
+    let value = match requirement.field {
+        None => None,
+        Some("all_sharks_obtained") => Some("\"all_sharks_obtained\"::int".to_owned()),
+        Some("all_hidden_achievements_obtained") => {
+            Some("\"all_hidden_achievements_obtained\"::int".to_owned())
+        }
+        // the same as role_rules' dino_prestige
+        Some("dino_prestige") => Some("LEAST(GREATEST(\"dino_rank\" / 50, 0), 10)".to_owned()),
+        Some(
+            field @ ("metabits"
+            | "dino_rank"
+            | "prestige_rank"
+            | "beyond_rank"
+            | "singularity_speedrun_time"),
+        ) => Some(format!("\"{}\"", field)),
+        Some(_) => return None,
+    };
+    let condition = match (value, requirement.comparison) {
+        (Some(value), Some(comparison)) => {
+            let operator = match comparison {
+                Comparison::AtLeast => ">=",
+                Comparison::AtMost => "<=",
+                Comparison::Equals | Comparison::IsSet => "=",
+            };
+            format!("{} {} ${}::float8", value, operator, param)
+        }
+        (None, Some(_)) => return None,
+        (_, None) => "TRUE".to_owned(),
+    };
+
+    Some(match requirement.channel {
+        RuleChannel::Any => condition,
+        RuleChannel::Beta => format!("\"beta_tester\" AND ({})", condition),
+    })
+}
+
+/// the linked users, the beta testers and the users meeting each role rule, counted in one scan
+pub async fn get_stats(client: &Client, role_rules: &[RoleRule]) -> Result<StatsResponse, Error> {
+    let mut counted = Vec::new();
+    let mut thresholds = Vec::new();
+    let mut milestone_counts = String::new();
+    for requirement in rule_requirements(role_rules) {
+        let condition = match requirement_condition(&requirement, thresholds.len() + 1) {
+            Some(condition) => condition,
+            None => continue,
+        };
+        if requirement.comparison.is_some() {
+            thresholds.push(requirement.threshold.unwrap_or_default());
+        }
+        milestone_counts.push_str(&format!(",\n  COUNT(*) FILTER (WHERE {})", condition));
+        counted.push(requirement);
+    }
+
+    let _stmt = include_str!("../sql/get_stats.sql");
+    let _stmt = _stmt.replace("$milestone_counts", &milestone_counts);
+    let stmt = client.prepare(&_stmt).await?;
+    let params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = thresholds
+        .iter()
+        .map(|threshold| threshold as &(dyn tokio_postgres::types::ToSql + Sync))
+        .collect();
+    // aggregates without a GROUP BY always return a row, an empty table counts zeros
+    let row = client.query_one(&stmt, &params).await?;
+
+    Ok(StatsResponse {
+        total_linked: row.get(0),
+        beta_testers: row.get(1),
+        milestones: counted
+            .into_iter()
+            .enumerate()
+            .map(|(index, requirement)| MilestoneCount {
+                role_id: requirement.role_id,
+                role_name: requirement.role_name,
+                users: row.get(index + 2),
+            })
+            .collect(),
+    })
+}
+
+/// `None` when nobody is linked to the discord id
+pub async fn get_public_link_visible(
+    client: &Client,
+    discord_id: &str,
+) -> Result<Option<bool>, Error> {
+    let _stmt = include_str!("../sql/get_public_link_visible.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .query_opt(&stmt, &[&discord_id])
+        .await?
+        .map(|row| row.get(0)))
+}
+
+/// settings that are left out of `settings` keep their stored value
+pub async fn update_privacy_settings(
+    client: &Client,
+    token: &str,
+    settings: &PrivacySettingsPatch,
+) -> Result<PrivacySettings, Error> {
+    let _stmt = include_str!("../sql/update_privacy_settings.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    let row = client
+        .query_opt(
+            &stmt,
+            &[
+                &token,
+                &settings.public_link_visible,
+                &settings.nickname_prefix,
+            ],
+        )
+        .await?
+        .ok_or(Error::ColumnNotFound)?;
+
+    Ok(PrivacySettings {
+        public_link_visible: row.get(0),
+        nickname_prefix: row.get(1),
+    })
+}
+
+/// `None` when nobody is linked to the discord id
+pub async fn get_nickname_prefix(client: &Client, discord_id: &str) -> Result<Option<bool>, Error> {
+    let _stmt = include_str!("../sql/get_nickname_prefix.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .query_opt(&stmt, &[&discord_id])
+        .await?
+        .map(|row| row.get(0)))
+}
+
+/// `None` when nobody is linked to the discord id
+pub async fn get_beta_tester_locked(
+    client: &Client,
+    discord_id: &str,
+) -> Result<Option<bool>, Error> {
+    let _stmt = include_str!("../sql/get_beta_tester_locked.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .query_opt(&stmt, &[&discord_id])
+        .await?
+        .map(|row| row.get(0)))
+}
+
+/// sets the beta tester flag by hand, `None` when nobody is linked to the discord id
+pub async fn update_beta_tester(
+    client: &Client,
+    discord_id: &str,
+    beta_tester: &bool,
+    locked: &bool,
+) -> Result<Option<BetaTesterStatus>, Error> {
+    let _stmt = include_str!("../sql/update_beta_tester.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .query_opt(&stmt, &[&discord_id, beta_tester, locked])
+        .await?
+        .map(|row| BetaTesterStatus::from_row_ref(&row))
+        .transpose()
+}
+
+/// the user's granted roles as a JSON object of role id to grant time, `None` when nobody is
+/// linked to the discord id
+pub async fn get_granted_roles(client: &Client, discord_id: &str) -> Result<Option<String>, Error> {
+    let _stmt = include_str!("../sql/get_granted_roles.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .query_opt(&stmt, &[&discord_id])
+        .await?
+        .map(|row| row.get(0)))
+}
+
+pub async fn update_granted_roles(
+    client: &Client,
+    discord_id: &str,
+    granted_roles: &str,
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/update_granted_roles.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .execute(&stmt, &[&discord_id, &granted_roles])
+        .await?)
+}
+
+/// the week of the user's last sync and their current and longest sync streak
+pub async fn get_sync_streak(
+    client: &Client,
+    discord_id: &str,
+) -> Result<Option<(Option<i64>, i32, i32)>, Error> {
+    let _stmt = include_str!("../sql/get_sync_streak.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .query_opt(&stmt, &[&discord_id])
+        .await?
+        .map(|row| (row.get(0), row.get(1), row.get(2))))
+}
+
+/// marks the user as seen without touching their data, returns their discord id and sync streak
+/// columns, `None` when no row has the token
+pub async fn touch_userdata(
+    client: &Client,
+    token: &str,
+    now: &SystemTime,
+) -> Result<Option<(String, Option<i64>, i32, i32)>, Error> {
+    let _stmt = include_str!("../sql/touch_userdata.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .query_opt(&stmt, &[&token, now])
+        .await?
+        .map(|row| (row.get(0), row.get(1), row.get(2), row.get(3))))
+}
+
+pub async fn get_last_seen(client: &Client, discord_id: &str) -> Result<Option<SystemTime>, Error> {
+    let _stmt = include_str!("../sql/get_last_seen.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .query_opt(&stmt, &[&discord_id])
+        .await?
+        .and_then(|row| row.get(0)))
+}
+
+pub async fn update_sync_streak(
+    client: &Client,
+    discord_id: &str,
+    last_sync_week: &i64,
+    current_streak: &i32,
+    longest_streak: &i32,
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/update_sync_streak.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .execute(
+            &stmt,
+            &[&discord_id, last_sync_week, current_streak, longest_streak],
+        )
+        .await?)
+}
+
+/// adds every count onto the stored count of its role in a single statement
+pub async fn upsert_role_grant_counts(
+    client: &Client,
+    roles: &[String],
+    counts: &[i64],
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/upsert_role_grant_counts.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .execute(&stmt, &[&roles, &counts, &SystemTime::now()])
+        .await?)
+}
+
+/// adds every sync count onto the stored count of its user in a single statement, counts of users
+/// that were deleted in the meantime are dropped
+pub async fn upsert_user_activity(
+    client: &Client,
+    discord_ids: &[String],
+    syncs: &[i64],
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/upsert_user_activity.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .execute(&stmt, &[&discord_ids, &syncs, &SystemTime::now()])
+        .await?)
+}
+
+/// replaces the stored abuse counters with the given entries in a single statement
+pub async fn replace_abuse_counters(
+    client: &Client,
+    counters: &[String],
+    keys: &[String],
+    values: &[i64],
+    expires: &[SystemTime],
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/replace_abuse_counters.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .execute(&stmt, &[&counters, &keys, &values, &expires])
+        .await?)
+}
+
+/// the stored abuse counter entries that haven't expired at `now`
+pub async fn get_abuse_counters(
+    client: &Client,
+    now: &SystemTime,
+) -> Result<Vec<AbuseCounterRow>, Error> {
+    let _stmt = include_str!("../sql/get_abuse_counters.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .query(&stmt, &[now])
+        .await?
+        .iter()
+        .map(AbuseCounterRow::from_row_ref)
+        .collect()
+}
+
+/// adds every deprecated feature use onto the stored count of its feature, day and client
+pub async fn upsert_deprecation_usage(
+    client: &Client,
+    features: &[String],
+    days: &[i64],
+    fingerprints: &[String],
+    uses: &[i64],
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/upsert_deprecation_usage.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .execute(&stmt, &[&features, &days, &fingerprints, &uses])
+        .await?)
+}
+
+/// adds the counts onto the stored counts of their hour, `hours` are hours since the unix epoch
+pub async fn upsert_digest_counts(
+    client: &Client,
+    kinds: &[String],
+    hours: &[i64],
+    names: &[String],
+    counts: &[i64],
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/upsert_digest_counts.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .execute(&stmt, &[&kinds, &hours, &names, &counts])
+        .await?)
+}
+
+/// the counts of the window summed up per kind and name, the most frequent first
+pub async fn get_digest_counts(
+    client: &Client,
+    start: &SystemTime,
+    end: &SystemTime,
+) -> Result<Vec<DigestCountRow>, Error> {
+    let _stmt = include_str!("../sql/get_digest_counts.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .query(&stmt, &[start, end])
+        .await?
+        .iter()
+        .map(DigestCountRow::from_row_ref)
+        .collect()
+}
+
+pub async fn delete_expired_digest_counts(
+    client: &Client,
+    before: &SystemTime,
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/delete_expired_digest_counts.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.execute(&stmt, &[before]).await?)
+}
+
+/// the usage of every deprecated feature per day, newest day first
+pub async fn get_deprecation_usage(client: &Client) -> Result<Vec<DeprecationUsageRow>, Error> {
+    let _stmt = include_str!("../sql/get_deprecation_usage.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .query(&stmt, &[])
+        .await?
+        .iter()
+        .map(DeprecationUsageRow::from_row_ref)
+        .collect()
+}
+
+pub async fn create_audit_event(
+    client: &Client,
+    actor: &str,
+    key_label: &str,
+    endpoint: &str,
+    target_discord_id: &Option<String>,
+    parameters: &str,
+    created_timestamp: &SystemTime,
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/create_audit_event.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .execute(
+            &stmt,
+            &[
+                &actor,
+                &key_label,
+                &endpoint,
+                target_discord_id,
+                &parameters,
+                created_timestamp,
+            ],
+        )
+        .await?)
+}
+
+/// the recovery token linked to a primary token
+pub async fn get_recovery_token(
+    client: &Client,
+    primary_token: &str,
+) -> Result<Option<String>, Error> {
+    let _stmt = include_str!("../sql/get_recovery_token.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .query_opt(&stmt, &[&primary_token])
+        .await?
+        .map(|row| row.get(0)))
+}
+
+/// the primary token a recovery token belongs to
+pub async fn get_recovery_primary_token(
+    client: &Client,
+    recovery_token: &str,
+) -> Result<Option<String>, Error> {
+    let _stmt = include_str!("../sql/get_recovery_primary_token.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .query_opt(&stmt, &[&recovery_token])
+        .await?
+        .map(|row| row.get(0)))
+}
+
+/// links the recovery token to the primary token, replacing the one that was linked before
+pub async fn upsert_recovery_token(
+    client: &Client,
+    primary_token: &str,
+    recovery_token: &str,
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/upsert_recovery_token.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .execute(
+            &stmt,
+            &[&primary_token, &recovery_token, &SystemTime::now()],
+        )
+        .await?)
+}
+
+/// what a legacy token was migrated as
+#[derive(Debug, PartialEq)]
+pub enum MigratedToken {
+    Primary,
+    /// the recovery token of the user with this primary token
+    Recovery(String),
+}
+
+/// Moves a row stored under the legacy token to the v2 token, in one statement with recording the
+/// transition so the token sharing detector doesn't flag the user and with the recovery token
+/// following along. `None` when nothing was stored under the legacy token.
+pub async fn migrate_user_token(
+    client: &Client,
+    legacy_token: &str,
+    v2_token: &str,
+) -> Result<Option<MigratedToken>, Error> {
+    let _stmt = include_str!("../sql/migrate_user_token.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    let row = client
+        .query_one(
+            &stmt,
+            &[
+                &legacy_token,
+                &v2_token,
+                &token_fingerprint(v2_token),
+                &SystemTime::now(),
+            ],
+        )
+        .await?;
+    if row.get("migrated") {
+        return Ok(Some(MigratedToken::Primary));
+    }
+
+    Ok(row
+        .get::<_, Option<String>>("recovery_of")
+        .map(MigratedToken::Recovery))
+}
+
+pub async fn delete_recovery_token(client: &Client, primary_token: &str) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/delete_recovery_token.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.execute(&stmt, &[&primary_token]).await?)
+}
+
+pub async fn create_promo_role_rule(
+    client: &Client,
+    role_id: &str,
+    name: &str,
+    starts_at: &SystemTime,
+    ends_at: &SystemTime,
+) -> Result<PromoRoleRule, Error> {
+    let _stmt = include_str!("../sql/create_promo_role_rule.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    let queried_data = client
+        .query(
+            &stmt,
+            &[&role_id, &name, starts_at, ends_at, &SystemTime::now()],
+        )
+        .await?
+        .pop()
+        .ok_or(Error::ColumnNotFound)?;
+
+    PromoRoleRule::from_row_ref(&queried_data)
+}
+
+/// every promo rule whose role hasn't been swept yet
+pub async fn get_promo_role_rules(client: &Client) -> Result<Vec<PromoRoleRule>, Error> {
+    let _stmt = include_str!("../sql/get_promo_role_rules.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .query(&stmt, &[])
+        .await?
+        .iter()
+        .map(PromoRoleRule::from_row_ref)
+        .collect()
+}
+
+pub async fn mark_promo_role_rule_swept(client: &Client, rule_id: &i64) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/mark_promo_role_rule_swept.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.execute(&stmt, &[rule_id]).await?)
+}
+
+pub async fn create_api_partner(
+    client: &Client,
+    key_hash: &str,
+    label: &str,
+    multiplier: &i32,
+    expires_at: &Option<SystemTime>,
+) -> Result<ApiPartner, Error> {
+    let _stmt = include_str!("../sql/create_api_partner.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    let queried_data = client
+        .query(
+            &stmt,
+            &[
+                &key_hash,
+                &label,
+                multiplier,
+                expires_at,
+                &SystemTime::now(),
+            ],
+        )
+        .await?
+        .pop()
+        .ok_or(Error::ColumnNotFound)?;
+
+    ApiPartner::from_row_ref(&queried_data)
+}
+
+pub async fn get_api_partners(client: &Client) -> Result<Vec<ApiPartner>, Error> {
+    let _stmt = include_str!("../sql/get_api_partners.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .query(&stmt, &[])
+        .await?
+        .iter()
+        .map(ApiPartner::from_row_ref)
+        .collect()
+}
+
+/// the partner a key belongs to, `None` when it's nobody's
+pub async fn get_api_partner(client: &Client, key_hash: &str) -> Result<Option<ApiPartner>, Error> {
+    let _stmt = include_str!("../sql/get_api_partner.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .query_opt(&stmt, &[&key_hash])
+        .await?
+        .map(|row| ApiPartner::from_row_ref(&row))
+        .transpose()
+}
+
+/// the deleted partner, `None` when there was none with the id
+pub async fn delete_api_partner(client: &Client, id: &i64) -> Result<Option<ApiPartner>, Error> {
+    let _stmt = include_str!("../sql/delete_api_partner.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .query_opt(&stmt, &[id])
+        .await?
+        .map(|row| ApiPartner::from_row_ref(&row))
+        .transpose()
+}
+
+/// remembers that the user got the role from the promo rule, so only they lose it when it expires
+pub async fn create_promo_role_grant(
+    client: &Client,
+    rule_id: &i64,
+    discord_id: &str,
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/create_promo_role_grant.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .execute(&stmt, &[rule_id, &discord_id, &SystemTime::now()])
+        .await?)
+}
+
+pub async fn get_promo_role_grants(
+    client: &Client,
+    rule_id: &i64,
+    limit: &i64,
+) -> Result<Vec<String>, Error> {
+    let _stmt = include_str!("../sql/get_promo_role_grants.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .query(&stmt, &[rule_id, limit])
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect())
+}
+
+pub async fn delete_promo_role_grant(
+    client: &Client,
+    rule_id: &i64,
+    discord_id: &str,
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/delete_promo_role_grant.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.execute(&stmt, &[rule_id, &discord_id]).await?)
+}
+
+pub async fn create_import_failure(
+    client: &Client,
+    job_id: &str,
+    line: i32,
+    reason: &str,
+    row: &str,
+) -> Result<(), Error> {
+    let _stmt = include_str!("../sql/create_import_failure.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .execute(&stmt, &[&job_id, &line, &reason, &row, &SystemTime::now()])
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_import_failures(
+    client: &Client,
+    job_id: &str,
+) -> Result<Vec<ImportFailureRecord>, Error> {
+    let _stmt = include_str!("../sql/get_import_failures.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .query(&stmt, &[&job_id])
+        .await?
+        .iter()
+        .map(ImportFailureRecord::from_row_ref)
+        .collect()
+}
+
+/// deletes every import failure that was stored before `expired_before`
+pub async fn delete_expired_import_failures(
+    client: &Client,
+    expired_before: &SystemTime,
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/delete_expired_import_failures.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.execute(&stmt, &[expired_before]).await?)
+}
+
+/// the response of the create the key was first sent with, `None` when it wasn't sent for the
+/// token since `not_before`
+pub async fn get_idempotent_response(
+    client: &Client,
+    token: &str,
+    idempotency_key: &str,
+    not_before: &SystemTime,
+) -> Result<Option<String>, Error> {
+    let _stmt = include_str!("../sql/get_idempotent_response.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .query_opt(&stmt, &[&token, &idempotency_key, not_before])
+        .await?
+        .map(|row| row.get(0)))
+}
+
+/// an expired key that's sent again is taken to be a new one, its response is replaced
+pub async fn create_idempotency_key(
+    client: &Client,
+    token: &str,
+    idempotency_key: &str,
+    response: &str,
+) -> Result<(), Error> {
+    let _stmt = include_str!("../sql/create_idempotency_key.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .execute(
+            &stmt,
+            &[&token, &idempotency_key, &response, &SystemTime::now()],
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_expired_idempotency_keys(
+    client: &Client,
+    expired_before: &SystemTime,
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/delete_expired_idempotency_keys.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.execute(&stmt, &[expired_before]).await?)
+}
+
+pub async fn create_link_code(
+    client: &Client,
+    code: &str,
+    discord_id: &str,
+    expires_at: &SystemTime,
+) -> Result<(), Error> {
+    let _stmt = include_str!("../sql/create_link_code.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .execute(&stmt, &[&code, &discord_id, expires_at])
+        .await?;
+    Ok(())
+}
+
+/// Uses up a link code, returns the discord id it was handed out for. `None` when there's no such
+/// code, it expired or it was already used, a code can only be consumed once even when two creates
+/// race for it.
+pub async fn consume_link_code(
+    client: &Client,
+    code: &str,
+    now: &SystemTime,
+) -> Result<Option<String>, Error> {
+    let _stmt = include_str!("../sql/consume_link_code.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .query_opt(&stmt, &[&code, now])
+        .await?
+        .map(|row| row.get(0)))
+}
+
+pub async fn delete_expired_link_codes(
+    client: &Client,
+    expired_before: &SystemTime,
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/delete_expired_link_codes.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.execute(&stmt, &[expired_before]).await?)
+}
+
+/// writes the day's value of every metric, a day that's snapshotted again keeps the later values
+pub async fn upsert_stats_snapshot(
+    client: &Client,
+    day: i64,
+    metrics: &[&str],
+    values: &[i64],
+) -> Result<(), Error> {
+    let _stmt = include_str!("../sql/upsert_stats_snapshot.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    client
+        .execute(&stmt, &[&day, &metrics, &values, &SystemTime::now()])
+        .await?;
+
+    Ok(())
+}
+
+/// the metric's `(day, value)` snapshots from `first_day` on, oldest first
+pub async fn get_stats_snapshots(
+    client: &Client,
+    metric: &str,
+    first_day: i64,
+) -> Result<Vec<(i64, i64)>, Error> {
+    let _stmt = include_str!("../sql/get_stats_snapshots.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client
+        .query(&stmt, &[&metric, &first_day])
+        .await?
+        .iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect())
+}
+
+pub async fn delete_expired_stats_snapshots(
+    client: &Client,
+    before_day: i64,
+) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/delete_expired_stats_snapshots.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.execute(&stmt, &[&before_day]).await?)
+}
+
+pub async fn get_support_code(
+    client: &Client,
+    support_code: &str,
+) -> Result<SupportCodeRecord, Error> {
+    let _stmt = include_str!("../sql/get_support_code.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    let queried_data = client
+        .query(&stmt, &[&support_code])
+        .await?
+        .pop()
+        .ok_or(Error::ColumnNotFound)?;
+
+    SupportCodeRecord::from_row_ref(&queried_data)
+}
+
+/// maps the code to the tokens the player's credentials derive to, the credentials aren't kept
+pub async fn upsert_support_code(
+    client: &Client,
+    support_code: &str,
+    user_tokens: &DerivedTokens,
+) -> Result<SupportCodeRecord, Error> {
+    let _stmt = include_str!("../sql/upsert_support_code.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    let queried_data = client
+        .query(
+            &stmt,
+            &[
+                &support_code,
+                &user_tokens.v2,
+                &user_tokens.legacy,
+                &SystemTime::now(),
+            ],
+        )
+        .await?
+        .pop()
+        .ok_or(Error::ColumnNotFound)?;
+
+    SupportCodeRecord::from_row_ref(&queried_data)
+}
+
+/// the support codes that resolve to the token, under either of its derivations
+pub async fn delete_support_codes(client: &Client, token: &str) -> Result<u64, Error> {
+    let _stmt = include_str!("../sql/delete_support_codes.sql");
+    let stmt = client.prepare(_stmt).await?;
+
+    Ok(client.execute(&stmt, &[&token]).await?)
+}
+
+/// the migration set in the order it has to run in, the `add_*.sql` column migrations from before
+/// migrations were tracked are left out because `userdata.sql` already has those columns.
+/// Migrations are only ever appended, a migration's version is its place in the list.
+const MIGRATIONS: [&str; 26] = [
+    include_str!("../sql/userdata.sql"),
+    include_str!("../sql/userdata_snapshots.sql"),
+    include_str!("../sql/import_failures.sql"),
+    include_str!("../sql/support_codes.sql"),
+    include_str!("../sql/role_grant_counts.sql"),
+    include_str!("../sql/user_activity.sql"),
+    include_str!("../sql/recovery_tokens.sql"),
+    include_str!("../sql/promo_role_rules.sql"),
+    include_str!("../sql/deprecation_usage.sql"),
+    include_str!("../sql/audit_log.sql"),
+    include_str!("../sql/domain_counts.sql"),
+    include_str!("../sql/add_email_domain_key.sql"),
+    include_str!("../sql/add_beta_tester_lock.sql"),
+    include_str!("../sql/abuse_counters.sql"),
+    include_str!("../sql/token_transitions.sql"),
+    include_str!("../sql/add_flagged_for_review.sql"),
+    include_str!("../sql/add_last_seen.sql"),
+    include_str!("../sql/cascade_recovery_token_updates.sql"),
+    include_str!("../sql/api_partners.sql"),
+    include_str!("../sql/add_nickname_prefix.sql"),
+    include_str!("../sql/idempotency_keys.sql"),
+    include_str!("../sql/stats_snapshots.sql"),
+    include_str!("../sql/link_codes.sql"),
+    include_str!("../sql/support_code_tokens.sql"),
+    include_str!("../sql/digest_counts.sql"),
+    include_str!("../sql/add_flagged_timestamp.sql"),
+];
+
+/// the migrations that were run by hand before they were tracked
+const UNTRACKED_MIGRATIONS: i32 = 10;
+
+/// the schema version this binary expects, the version of its last migration
+pub const SCHEMA_VERSION: i32 = MIGRATIONS.len() as i32;
+
+/// the latest migration the schema had applied, 0 before anything was migrated
+pub async fn applied_schema_version(client: &Client) -> Result<i32, Error> {
+    let tracked: bool = client
+        .query_one(
+            "SELECT to_regclass('\"SchemaMigrations\"') IS NOT NULL",
+            &[],
+        )
+        .await?
+        .get(0);
+    if !tracked {
+        return Ok(0);
+    }
+
+    Ok(client
+        .query_one(
+            "SELECT coalesce(max(\"version\"), 0) FROM \"SchemaMigrations\"",
+            &[],
+        )
+        .await?
+        .get(0))
+}
+
+/// schema names are put into SQL and the connection options as they are, so only plain lowercase
+/// identifiers are allowed
+pub fn is_valid_schema_name(schema: &str) -> bool {
+    schema.len() <= 63
+        && schema.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && schema
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Creates the schema and runs the migrations it's missing, returns how many ran. A schema that
+/// has the tables from before migrations were tracked is taken to have the untracked migrations.
+pub async fn migrate(client: &mut Client, schema: &str) -> Result<usize, Error> {
+    // creating a schema needs the CREATE privilege on the database even when it already exists
+    let schema_exists: bool = client
+        .query_one(
+            "SELECT EXISTS (SELECT 1 FROM pg_namespace WHERE nspname = $1)",
+            &[&schema],
+        )
+        .await?
+        .get(0);
+    if !schema_exists {
+        client
+            .batch_execute(&format!("CREATE SCHEMA \"{}\"", schema))
+            .await?;
+    }
+
+    let existing = client
+        .query_one(
+            "SELECT to_regclass('\"SchemaMigrations\"') IS NOT NULL, to_regclass('\"UserData\"') IS NOT NULL",
+            &[],
+        )
+        .await?;
+    let (tracked, untracked_tables): (bool, bool) = (existing.get(0), existing.get(1));
+    client
+        .batch_execute(include_str!("../sql/schema_migrations.sql"))
+        .await?;
+    if !tracked && untracked_tables {
+        for version in 1..=UNTRACKED_MIGRATIONS {
+            client
+                .execute(
+                    "INSERT INTO \"SchemaMigrations\" (\"version\") VALUES ($1)",
+                    &[&version],
+                )
+                .await?;
+        }
+    }
+
+    let applied: Vec<i32> = client
+        .query("SELECT \"version\" FROM \"SchemaMigrations\"", &[])
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+    let mut ran = 0;
+    for (version, migration) in (1..).zip(MIGRATIONS) {
+        if applied.contains(&version) {
+            continue;
+        }
+        let transaction = client.transaction().await?;
+        transaction.batch_execute(migration).await?;
+        transaction
+            .execute(
+                "INSERT INTO \"SchemaMigrations\" (\"version\") VALUES ($1)",
+                &[&version],
+            )
+            .await?;
+        transaction.commit().await?;
+        ran += 1;
+    }
+
+    Ok(ran)
+}
+
+#[cfg(test)]
+static TEST_SCHEMAS: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+/// the pool config of the Postgres database at `url`, this deadpool doesn't take urls itself
+#[cfg(test)]
+pub fn test_pool_config(url: &str) -> deadpool_postgres::Config {
+    let parsed = url.parse::<tokio_postgres::Config>().unwrap();
+    deadpool_postgres::Config {
+        host: parsed.get_hosts().first().map(|host| match host {
+            tokio_postgres::config::Host::Tcp(host) => host.to_owned(),
+            tokio_postgres::config::Host::Unix(path) => path.to_string_lossy().into_owned(),
+        }),
+        port: parsed.get_ports().first().copied(),
+        user: parsed.get_user().map(str::to_owned),
+        password: parsed
+            .get_password()
+            .map(|password| String::from_utf8_lossy(password).into_owned()),
+        dbname: parsed.get_dbname().map(str::to_owned),
+        ..deadpool_postgres::Config::default()
+    }
+}
+
+/// Runs `test` against a fresh schema of the Postgres database at `TEST_DATABASE_URL`, a throwaway
+/// `docker run --rm -e POSTGRES_PASSWORD=postgres -p 5432:5432 postgres` does the job. Every test
+/// gets its own schema so they can run concurrently, it's dropped afterwards even when the test
+/// panics.
+#[cfg(test)]
+pub fn with_test_db<F, Fut>(test: F)
+where
+    F: FnOnce(deadpool_postgres::Pool) -> Fut + 'static,
+    Fut: std::future::Future<Output = ()> + 'static,
+{
+    with_test_schemas(1, |mut pools| test(pools.remove(0)));
+}
+
+/// like `with_test_db`, with a pool for each of `count` schemas of the same database
+#[cfg(test)]
+fn with_test_schemas<F, Fut>(count: usize, test: F)
+where
+    F: FnOnce(Vec<deadpool_postgres::Pool>) -> Fut + 'static,
+    Fut: std::future::Future<Output = ()> + 'static,
+{
+    use crate::session_settings::{application_name, create_pool, SessionSettings};
+    use tokio_postgres::NoTls;
+
+    actix_web::rt::System::new().block_on(async move {
+        let url = std::env::var("TEST_DATABASE_URL")
+            .expect("TEST_DATABASE_URL has to point at a Postgres database");
+        let schemas: Vec<String> = (0..count)
+            .map(|_| {
+                format!(
+                    "test_{}_{}",
+                    std::process::id(),
+                    TEST_SCHEMAS.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+                )
+            })
+            .collect();
+
+        let (admin, connection) = tokio_postgres::connect(&url, NoTls).await.unwrap();
+        actix_web::rt::spawn(connection);
+
+        let mut pools = Vec::new();
+        for schema in &schemas {
+            let pg = test_pool_config(&url);
+            let settings = SessionSettings {
+                application_name: application_name("test"),
+                statement_timeout: std::time::Duration::ZERO,
+                idle_in_transaction_session_timeout: std::time::Duration::ZERO,
+                search_path: Some(schema.clone()),
+            };
+            let pool = create_pool(&pg, settings).await.unwrap();
+            migrate(&mut pool.get().await.unwrap(), schema)
+                .await
+                .unwrap();
+            pools.push(pool);
+        }
+
+        let result = actix_web::rt::spawn(test(pools)).await;
+
+        for schema in &schemas {
+            admin
+                .batch_execute(&format!("DROP SCHEMA \"{}\" CASCADE", schema))
+                .await
+                .unwrap();
+        }
+        if let Err(error) = result {
+            std::panic::resume_unwind(error.into_panic());
+        }
+    });
+}
+
+#[cfg(test)]
+fn progress(metabits: f64, dino_rank: i32) -> UpdateUserData {
+    UpdateUserData {
+        metabits,
+        dino_rank,
+        ..UpdateUserData::default()
+    }
+}
+
+/// a sync that writes `user_data` whatever is stored
+#[cfg(test)]
+fn overwrite(
+    user_data: UpdateUserData,
+) -> impl FnOnce(&UserData) -> Result<(PatchUserData, ()), ()> {
+    move |_| Ok((user_data.into(), ()))
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn userdata_queries_round_trip() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+
+        let created = create_userdata(&client, &budget, "token", "1", &false, progress(10.0, 1))
+            .await
+            .unwrap();
+        assert_eq!((created.metabits, created.dino_rank), (10, 1));
+
+        assert_eq!(
+            get_userdata(&client, &budget, "token")
+                .await
+                .unwrap()
+                .discord_id,
+            "1"
+        );
+        assert_eq!(
+            get_userdata_by_id(&client, &budget, "1")
+                .await
+                .unwrap()
+                .token,
+            "token"
+        );
+        assert!(matches!(
+            get_userdata(&client, &budget, "unknown").await,
+            Err(LookupError::NotFound)
+        ));
+        assert!(matches!(
+            get_userdata_by_id(&client, &budget, "2").await,
+            Err(LookupError::NotFound)
+        ));
+
+        let updated = update_userdata(
+            &client,
+            &budget,
+            "token",
+            &Some(true),
+            progress(20.0, 1).into(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            (updated.metabits, updated.dino_rank, updated.beta_tester),
+            (20, 1, true)
+        );
+        assert!(updated.edited_timestamp >= created.edited_timestamp);
+
+        assert_eq!(count_userdata(&client).await.unwrap(), 1);
+        assert_eq!(list_userdata(&client, 10, None).await.unwrap().len(), 1);
+        assert!(list_userdata(&client, 10, Some("1"))
+            .await
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            get_public_link_visible(&client, "1").await.unwrap(),
+            Some(false)
+        );
+        assert_eq!(
+            update_privacy_settings(
+                &client,
+                "token",
+                &PrivacySettingsPatch {
+                    public_link_visible: Some(true),
+                    nickname_prefix: None,
+                },
+            )
+            .await
+            .unwrap(),
+            PrivacySettings {
+                public_link_visible: true,
+                nickname_prefix: true,
+            }
+        );
+        assert_eq!(
+            get_public_link_visible(&client, "1").await.unwrap(),
+            Some(true)
+        );
+        assert_eq!(get_public_link_visible(&client, "2").await.unwrap(), None);
+
+        assert_eq!(get_nickname_prefix(&client, "1").await.unwrap(), Some(true));
+        assert_eq!(
+            update_privacy_settings(
+                &client,
+                "token",
+                &PrivacySettingsPatch {
+                    public_link_visible: None,
+                    nickname_prefix: Some(false),
+                },
+            )
+            .await
+            .unwrap(),
+            PrivacySettings {
+                public_link_visible: true,
+                nickname_prefix: false,
+            }
+        );
+        assert_eq!(
+            get_nickname_prefix(&client, "1").await.unwrap(),
+            Some(false)
+        );
+        assert_eq!(get_nickname_prefix(&client, "2").await.unwrap(), None);
+
+        assert_eq!(
+            get_granted_roles(&client, "1").await.unwrap(),
+            Some("{}".to_owned())
+        );
+        assert_eq!(
+            update_granted_roles(&client, "1", r#"{"1000": 1700000000, "1001": null}"#)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            get_granted_roles(&client, "1").await.unwrap(),
+            Some(r#"{"1000": 1700000000, "1001": null}"#.to_owned())
+        );
+
+        assert_eq!(
+            get_sync_streak(&client, "1").await.unwrap(),
+            Some((None, 0, 0))
+        );
+        assert_eq!(
+            update_sync_streak(&client, "1", &2_800, &3, &5)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            get_sync_streak(&client, "1").await.unwrap(),
+            Some((Some(2_800), 3, 5))
+        );
+
+        assert_eq!(
+            delete_userdata(&client, &budget, "token")
+                .await
+                .unwrap()
+                .unwrap()
+                .discord_id,
+            "1"
+        );
+        assert_eq!(count_userdata(&client).await.unwrap(), 0);
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn fingerprint_search_returns_every_match() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+        let discord_ids = |matches: Vec<FingerprintMatch>| {
+            matches
+                .into_iter()
+                .map(|account| account.discord_id)
+                .collect::<Vec<String>>()
+        };
+
+        // two tokens that only differ past the fingerprint collide
+        for (token, discord_id) in [
+            ("80d7bf83aaaa", "1"),
+            ("80d7bf83bbbb", "2"),
+            ("087da712", "3"),
+        ] {
+            create_userdata(
+                &client,
+                &budget,
+                token,
+                discord_id,
+                &false,
+                progress(0.0, 0),
+            )
+            .await
+            .unwrap();
+        }
+        assert_eq!(
+            discord_ids(
+                get_userdata_by_fingerprint(&client, "80d7bf83")
+                    .await
+                    .unwrap()
+            ),
+            vec!["1", "2"]
+        );
+        assert!(get_userdata_by_fingerprint(&client, "ffffffff")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // binding the discord id to another account rekeys the row
+        create_userdata(
+            &client,
+            &budget,
+            "ffffffff0000",
+            "1",
+            &false,
+            progress(0.0, 0),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            discord_ids(
+                get_userdata_by_fingerprint(&client, "80d7bf83")
+                    .await
+                    .unwrap()
+            ),
+            vec!["2"]
+        );
+        let rekeyed = get_userdata_by_fingerprint(&client, "ffffffff")
+            .await
+            .unwrap();
+        assert_eq!(
+            rekeyed[0].token_fingerprint,
+            token_fingerprint("ffffffff0000")
+        );
+        assert_eq!(discord_ids(rekeyed), vec!["1"]);
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn snapshot_queries_round_trip() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+
+        let created = create_userdata(&client, &budget, "token", "1", &false, progress(10.0, 1))
+            .await
+            .unwrap();
+        create_userdata_snapshot(&client, &budget, &created)
+            .await
+            .unwrap();
+        // timestamps are stored with millisecond precision
+        actix_web::rt::time::sleep(std::time::Duration::from_millis(5)).await;
+        let updated = update_userdata(
+            &client,
+            &budget,
+            "token",
+            &Some(false),
+            progress(20.0, 2).into(),
+        )
+        .await
+        .unwrap();
+        create_userdata_snapshot(&client, &budget, &updated)
+            .await
+            .unwrap();
+
+        let snapshot = get_userdata_snapshot(&client, &budget, "1", &created.edited_timestamp)
+            .await
+            .unwrap();
+        assert_eq!(snapshot.metabits, 10);
+        let snapshot = get_userdata_snapshot(&client, &budget, "1", &SystemTime::now())
+            .await
+            .unwrap();
+        assert_eq!(snapshot.metabits, 20);
+        assert!(
+            get_userdata_snapshot(&client, &budget, "1", &SystemTime::UNIX_EPOCH)
+                .await
+                .is_err()
+        );
+
+        // only the first snapshot of a user counts as a new link
+        assert_eq!(
+            count_new_links(&client, &SystemTime::UNIX_EPOCH, &SystemTime::now())
+                .await
+                .unwrap(),
+            1
+        );
+
+        assert_eq!(delete_userdata_snapshots(&client, "1").await.unwrap(), 2);
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn expired_snapshots_keep_when_the_user_linked() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+        let sync = |token: &'static str, metabits: f64| {
+            let client = &client;
+            let budget = &budget;
+            async move {
+                // timestamps are stored with millisecond precision
+                actix_web::rt::time::sleep(std::time::Duration::from_millis(5)).await;
+                let updated = update_userdata(
+                    client,
+                    budget,
+                    token,
+                    &Some(false),
+                    progress(metabits, 1).into(),
+                )
+                .await
+                .unwrap();
+                create_userdata_snapshot(client, budget, &updated)
+                    .await
+                    .unwrap();
+            }
+        };
+
+        let created = create_userdata(&client, &budget, "legacy", "1", &false, progress(10.0, 1))
+            .await
+            .unwrap();
+        create_userdata_snapshot(&client, &budget, &created)
+            .await
+            .unwrap();
+        sync("legacy", 20.0).await;
+        // the later snapshots are under the migrated token
+        migrate_user_token(&client, "legacy", "v2").await.unwrap();
+        sync("v2", 30.0).await;
+        let cutoff = SystemTime::now();
+        sync("v2", 40.0).await;
+
+        assert_eq!(
+            delete_expired_userdata_snapshots(&client, &cutoff)
+                .await
+                .unwrap(),
+            2
+        );
+        let history = get_userdata_snapshots(&client, "1").await.unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].token, "legacy");
+        assert_eq!(history[1].token, "v2");
+        assert_eq!(
+            count_new_links(&client, &SystemTime::UNIX_EPOCH, &cutoff)
+                .await
+                .unwrap(),
+            1
+        );
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn deleting_userdata_removes_the_row_once() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+
+        create_userdata(&client, &budget, "token", "1", &false, progress(1.0, 1))
+            .await
+            .unwrap();
+        assert!(delete_userdata(&client, &budget, "nobody")
+            .await
+            .unwrap()
+            .is_none());
+
+        let deleted = delete_userdata(&client, &budget, "token")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(deleted.discord_id, "1");
+        assert!(get_userdata(&client, &budget, "token").await.is_err());
+
+        // the row is gone, so deleting it again finds nothing
+        assert!(delete_userdata(&client, &budget, "token")
+            .await
+            .unwrap()
+            .is_none());
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn deleting_by_discord_id_releases_the_domain_too() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+
+        create_userdata(&client, &budget, "token", "1", &false, progress(1.0, 1))
+            .await
+            .unwrap();
+        count_linked_domain(&client, "1", "example.com")
+            .await
+            .unwrap();
+        assert_eq!(get_domain_count(&client, "example.com").await.unwrap(), 1);
+        assert!(delete_userdata_by_id(&client, &budget, "2")
+            .await
+            .unwrap()
+            .is_none());
+
+        let deleted = delete_userdata_by_id(&client, &budget, "1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(deleted.token, "token");
+        assert!(matches!(
+            get_userdata_by_id(&client, &budget, "1").await,
+            Err(LookupError::NotFound)
+        ));
+        assert_eq!(get_domain_count(&client, "example.com").await.unwrap(), 0);
+    });
+}
+
+#[test]
+fn requirements_are_counted_with_their_column() {
+    use crate::{constants::roles, fields::ProgressField};
+
+    let role_rules = [RoleRule {
+        role_id: 1001,
+        name: "Blink".to_owned(),
+        field: ProgressField::SingularitySpeedrunTime,
+        threshold: 60.0,
+    }];
+    let conditions: Vec<(String, Option<String>)> = rule_requirements(&role_rules)
+        .iter()
+        .map(|requirement| {
+            (
+                requirement.role_id.clone(),
+                requirement_condition(requirement, 1),
+            )
+        })
+        .collect();
+    let condition_of = |role_id: u64| {
+        conditions
+            .iter()
+            .find(|(id, _)| *id == role_id.to_string())
+            .and_then(|(_, condition)| condition.as_deref())
+    };
+
+    assert_eq!(
+        condition_of(roles::REALITY_EXPLORER),
+        Some("\"metabits\" >= $1::float8")
+    );
+    assert_eq!(
+        condition_of(1001),
+        Some("\"singularity_speedrun_time\" <= $1::float8")
+    );
+    assert_eq!(
+        condition_of(roles::BETA_TESTER),
+        Some("\"beta_tester\" AND (TRUE)")
+    );
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn stats_of_an_empty_table_are_zeros() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+
+        let stats = get_stats(&client, &[]).await.unwrap();
+        assert_eq!((stats.total_linked, stats.beta_testers), (0, 0));
+        assert!(!stats.milestones.is_empty());
+        assert!(stats
+            .milestones
+            .iter()
+            .all(|milestone| milestone.users == 0));
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn stats_count_the_seeded_users() {
+    use crate::{constants::roles, fields::ProgressField};
+
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+
+        create_userdata(
+            &client,
+            &budget,
+            "token-1",
+            "1",
+            &true,
+            progress(2_000_000.0, 120),
+        )
+        .await
+        .unwrap();
+        create_userdata(&client, &budget, "token-2", "2", &false, progress(10.0, 1))
+            .await
+            .unwrap();
+        create_userdata(&client, &budget, "token-3", "3", &false, progress(5.0, 0))
+            .await
+            .unwrap();
+
+        let role_rules = [RoleRule {
+            role_id: 1001,
+            name: "Ten Metabits".to_owned(),
+            field: ProgressField::Metabits,
+            threshold: 10.0,
+        }];
+        let stats = get_stats(&client, &role_rules).await.unwrap();
+        assert_eq!((stats.total_linked, stats.beta_testers), (3, 1));
+
+        let users_of = |role_id: u64| {
+            stats
+                .milestones
+                .iter()
+                .find(|milestone| milestone.role_id == role_id.to_string())
+                .unwrap()
+                .users
+        };
+        assert_eq!(users_of(roles::REALITY_EXPLORER), 1);
+        assert_eq!(users_of(roles::REALITY_LEGEND), 0);
+        assert_eq!(users_of(roles::BETA_TESTER), 1);
+        assert_eq!(users_of(1001), 2);
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn listing_pages_through_every_user_once() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+
+        for discord_id in 1..=7 {
+            create_userdata(
+                &client,
+                &budget,
+                &format!("token-{}", discord_id),
+                &discord_id.to_string(),
+                &false,
+                progress(1.0, 1),
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut pages = Vec::new();
+        let mut after: Option<String> = None;
+        loop {
+            let page = list_userdata(&client, 3, after.as_deref()).await.unwrap();
+            if page.is_empty() {
+                break;
+            }
+            after = page.last().map(|user_data| user_data.discord_id.clone());
+            pages.push(
+                page.into_iter()
+                    .map(|user_data| user_data.discord_id)
+                    .collect::<Vec<String>>(),
+            );
+        }
+
+        assert_eq!(
+            pages,
+            vec![vec!["1", "2", "3"], vec!["4", "5", "6"], vec!["7"]]
+        );
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn touching_leaves_the_data_alone() {
+    use crate::sync_streaks::{record_touch, SyncStreak};
+
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+
+        let created = create_userdata(&client, &budget, "token", "1", &false, progress(1.0, 1))
+            .await
+            .unwrap();
+        assert_eq!(get_last_seen(&client, "1").await.unwrap(), None);
+        assert!(touch_userdata(&client, "nobody", &SystemTime::now())
+            .await
+            .unwrap()
+            .is_none());
+
+        actix_web::rt::time::sleep(std::time::Duration::from_millis(5)).await;
+        let (discord_id, last_week, current, longest) =
+            touch_userdata(&client, "token", &SystemTime::now())
+                .await
+                .unwrap()
+                .unwrap();
+        assert_eq!(discord_id, "1");
+        assert!(get_last_seen(&client, "1").await.unwrap().unwrap() > created.edited_timestamp);
+        assert_eq!(
+            get_userdata(&client, &budget, "token")
+                .await
+                .unwrap()
+                .edited_timestamp,
+            created.edited_timestamp
+        );
+
+        let streak = record_touch(
+            &client,
+            "1",
+            SyncStreak::from_columns(last_week, current, longest),
+            2_800,
+        )
+        .await
+        .unwrap();
+        assert_eq!(streak.current, 1);
+        assert_eq!(
+            get_sync_streak(&client, "1").await.unwrap(),
+            Some((Some(2_800), 1, 1))
+        );
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn token_uses_are_grouped_per_token() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+
+        let mut user_data = create_userdata(
+            &client,
+            &budget,
+            "aaaaaaaa00",
+            "1",
+            &false,
+            progress(1.0, 1),
+        )
+        .await
+        .unwrap();
+        for token in ["aaaaaaaa00", "bbbbbbbb00", "cccccccc00", "aaaaaaaa00"] {
+            user_data.token = token.to_owned();
+            user_data.edited_timestamp = SystemTime::now();
+            create_userdata_snapshot(&client, &budget, &user_data)
+                .await
+                .unwrap();
+            actix_web::rt::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        create_token_transition(&client, "1", "bbbbbbbb00")
+            .await
+            .unwrap();
+        // recording it twice doesn't fail
+        create_token_transition(&client, "1", "bbbbbbbb00")
+            .await
+            .unwrap();
+
+        let uses = get_token_uses(&client, &SystemTime::UNIX_EPOCH)
+            .await
+            .unwrap();
+        assert_eq!(
+            uses.iter()
+                .map(|token_use| (token_use.token_fingerprint.as_str(), token_use.whitelisted))
+                .collect::<Vec<_>>(),
+            vec![("aaaaaaaa", false), ("bbbbbbbb", true), ("cccccccc", false)]
+        );
+        assert!(uses[0].last_seen > uses[2].first_seen);
+        assert!(get_token_uses(&client, &SystemTime::now())
+            .await
+            .unwrap()
+            .is_empty());
+
+        let ids = vec!["1".to_owned(), "2".to_owned()];
+        let before = SystemTime::now() - std::time::Duration::from_secs(1);
+        assert_eq!(flag_for_review(&client, &ids).await.unwrap(), vec!["1"]);
+        // it's only flagged once
+        assert!(flag_for_review(&client, &ids).await.unwrap().is_empty());
+        let after = SystemTime::now() + std::time::Duration::from_secs(1);
+        assert_eq!(
+            count_flagged_for_review(&client, &before, &after)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            count_flagged_for_review(
+                &client,
+                &after,
+                &(after + std::time::Duration::from_secs(60))
+            )
+            .await
+            .unwrap(),
+            0
+        );
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn digest_counts_are_read_within_their_window() {
+    use std::time::Duration;
+
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let hour = |hours: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(hours * 60 * 60);
+        let strings = |values: &[&str]| {
+            values
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        upsert_digest_counts(
+            &client,
+            &strings(&["error", "error", "error", "deletion", "role_grant"]),
+            &[99, 100, 101, 101, 150],
+            &strings(&[
+                "BAD_REQUEST",
+                "BAD_REQUEST",
+                "INTERNAL_ERROR",
+                "",
+                "Beta Tester",
+            ]),
+            &[9, 1, 3, 2, 1],
+        )
+        .await
+        .unwrap();
+        // a later flush of the same hour adds onto it
+        upsert_digest_counts(
+            &client,
+            &strings(&["error"]),
+            &[101],
+            &strings(&["BAD_REQUEST"]),
+            &[1],
+        )
+        .await
+        .unwrap();
+
+        let counts = get_digest_counts(&client, &hour(100), &hour(150))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.kind, row.name, row.count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            counts,
+            vec![
+                ("error".to_owned(), "INTERNAL_ERROR".to_owned(), 3),
+                ("deletion".to_owned(), "".to_owned(), 2),
+                ("error".to_owned(), "BAD_REQUEST".to_owned(), 2),
+            ]
+        );
+
+        assert_eq!(
+            delete_expired_digest_counts(&client, &hour(101))
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            get_digest_counts(&client, &hour(0), &hour(101))
+                .await
+                .unwrap()
+                .len(),
+            0
+        );
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn deprecation_usage_is_summed_per_feature_and_day() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let strings = |values: &[&str]| {
+            values
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // 19723 is 2024-01-01
+        upsert_deprecation_usage(
+            &client,
+            &strings(&[
+                "legacy_message",
+                "legacy_message",
+                "legacy_message",
+                "og_endpoint",
+            ]),
+            &[19723, 19723, 19724, 19723],
+            &strings(&["aaaaaaaa", "bbbbbbbb", "aaaaaaaa", "aaaaaaaa"]),
+            &[2, 1, 4, 1],
+        )
+        .await
+        .unwrap();
+        // the same client on the same day adds onto its uses instead of counting twice
+        upsert_deprecation_usage(
+            &client,
+            &strings(&["legacy_message"]),
+            &[19723],
+            &strings(&["aaaaaaaa"]),
+            &[3],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            get_deprecation_usage(&client)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|row| (row.feature, row.day, row.uses, row.clients))
+                .collect::<Vec<_>>(),
+            vec![
+                ("legacy_message".to_owned(), "2024-01-02".to_owned(), 4, 1),
+                ("legacy_message".to_owned(), "2024-01-01".to_owned(), 6, 2),
+                ("og_endpoint".to_owned(), "2024-01-01".to_owned(), 1, 1),
+            ]
+        );
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn audit_events_are_stored_as_recorded() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let recorded = SystemTime::UNIX_EPOCH + std::time::Duration::from_millis(1_704_067_200_123);
+
+        assert_eq!(
+            create_audit_event(
+                &client,
+                "admin",
+                "moderators",
+                "DELETE /admin/users/{discord_id}",
+                &Some("1".to_owned()),
+                r#"{"reason":"requested"}"#,
+                &recorded,
+            )
+            .await
+            .unwrap(),
+            1
+        );
+        // events without a target are kept too
+        create_audit_event(
+            &client,
+            "admin",
+            "bot",
+            "POST /admin/roles/resync",
+            &None,
+            "{}",
+            &recorded,
+        )
+        .await
+        .unwrap();
+
+        let rows = client
+            .query(
+                "SELECT \"actor\", \"key_label\", \"endpoint\", \"target_discord_id\", \"parameters\"::TEXT, \"created_timestamp\" FROM \"AuditLog\" ORDER BY \"id\"",
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].get::<_, &str>(0), "admin");
+        assert_eq!(rows[0].get::<_, &str>(1), "moderators");
+        assert_eq!(
+            rows[0].get::<_, &str>(2),
+            "DELETE /admin/users/{discord_id}"
+        );
+        assert_eq!(rows[0].get::<_, Option<&str>>(3), Some("1"));
+        // the parameters are stored as JSON, not as a string of it
+        assert_eq!(rows[0].get::<_, &str>(4), r#"{"reason": "requested"}"#);
+        assert_eq!(rows[0].get::<_, SystemTime>(5), recorded);
+        assert_eq!(rows[1].get::<_, Option<&str>>(3), None);
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn metabits_past_2_53_survive_postgres_and_json() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+
+        // syncs carry metabits as doubles, snapshots store the exact integer
+        let mut user_data =
+            create_userdata(&client, &budget, "token", "1", &false, progress(1.0, 1))
+                .await
+                .unwrap();
+        user_data.metabits = (1 << 53) + 1;
+        create_userdata_snapshot(&client, &budget, &user_data)
+            .await
+            .unwrap();
+
+        let snapshot = get_userdata_snapshot(&client, &budget, "1", &SystemTime::now())
+            .await
+            .unwrap();
+        assert_eq!(snapshot.metabits, 9_007_199_254_740_993);
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let read = serde_json::from_str::<UserData>(&json).unwrap();
+        assert_eq!(read.metabits, 9_007_199_254_740_993);
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn counter_upserts_add_onto_stored_counts() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let roles = vec!["Beta Tester".to_owned(), "Shark Collector".to_owned()];
+        let discord_ids = vec!["1".to_owned(), "deleted".to_owned()];
+        create_userdata(
+            &client,
+            &RequestBudget::unlimited(),
+            "token",
+            "1",
+            &false,
+            progress(0.0, 0),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            upsert_role_grant_counts(&client, &roles, &[1, 2])
+                .await
+                .unwrap(),
+            2
+        );
+        upsert_role_grant_counts(&client, &roles[..1], &[3])
+            .await
+            .unwrap();
+        assert_eq!(
+            upsert_user_activity(&client, &discord_ids, &[2, 1])
+                .await
+                .unwrap(),
+            1
+        );
+        upsert_user_activity(&client, &discord_ids[..1], &[1])
+            .await
+            .unwrap();
+
+        let grants: i64 = client
+            .query_one(
+                r#"SELECT "count" FROM "RoleGrantCounts" WHERE "role" = 'Beta Tester'"#,
+                &[],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        let syncs: i64 = client
+            .query_one(
+                r#"SELECT "syncs" FROM "UserActivity" WHERE "discord_id" = '1'"#,
+                &[],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!((grants, syncs), (4, 3));
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn recovery_token_queries_round_trip() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+        create_userdata(&client, &budget, "primary", "1", &false, progress(0.0, 0))
+            .await
+            .unwrap();
+
+        upsert_recovery_token(&client, "primary", "recovery")
+            .await
+            .unwrap();
+        upsert_recovery_token(&client, "primary", "replacement")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            get_recovery_token(&client, "primary").await.unwrap(),
+            Some("replacement".to_owned())
+        );
+        assert_eq!(
+            get_recovery_primary_token(&client, "replacement")
+                .await
+                .unwrap(),
+            Some("primary".to_owned())
+        );
+        assert_eq!(
+            get_recovery_primary_token(&client, "recovery")
+                .await
+                .unwrap(),
+            None
+        );
+
+        assert_eq!(delete_recovery_token(&client, "primary").await.unwrap(), 1);
+        assert_eq!(get_recovery_token(&client, "primary").await.unwrap(), None);
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn legacy_tokens_move_to_v2_on_the_next_request() {
+    use crate::{
+        recovery::{resolve_user_token, Credential},
+        utilities::derive_user_tokens,
+    };
+
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+        let tokens = derive_user_tokens("player@example.com", "token", "secret");
+        let recovery = derive_user_tokens("backup@example.com", "token", "secret");
+        create_userdata(
+            &client,
+            &budget,
+            &tokens.legacy,
+            "1",
+            &false,
+            progress(0.0, 0),
+        )
+        .await
+        .unwrap();
+        upsert_recovery_token(&client, &tokens.legacy, &recovery.legacy)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            resolve_user_token(&client, &tokens).await.unwrap(),
+            (tokens.v2.clone(), Credential::Primary)
+        );
+        assert!(matches!(
+            get_userdata(&client, &budget, &tokens.legacy).await,
+            Err(LookupError::NotFound)
+        ));
+        let migrated = get_userdata(&client, &budget, &tokens.v2).await.unwrap();
+        assert_eq!(migrated.discord_id, "1");
+        // the recovery credential followed its primary and is migrated on its own next use
+        assert_eq!(
+            get_recovery_token(&client, &tokens.v2).await.unwrap(),
+            Some(recovery.legacy.clone())
+        );
+        assert_eq!(
+            resolve_user_token(&client, &recovery).await.unwrap(),
+            (tokens.v2.clone(), Credential::Recovery)
+        );
+        assert_eq!(
+            get_recovery_primary_token(&client, &recovery.v2)
+                .await
+                .unwrap(),
+            Some(tokens.v2.clone())
+        );
+
+        // later requests find the user through v2 alone
+        let v2_only = crate::utilities::DerivedTokens {
+            v2: tokens.v2.clone(),
+            legacy: "not-a-legacy-token".to_owned(),
+        };
+        assert_eq!(
+            resolve_user_token(&client, &v2_only).await.unwrap(),
+            (tokens.v2.clone(), Credential::Primary)
+        );
+        assert_eq!(
+            migrate_user_token(&client, &tokens.legacy, &tokens.v2)
+                .await
+                .unwrap(),
+            None
+        );
+        // the token change isn't mistaken for the account being shared
+        assert_eq!(
+            client
+                .query_one(
+                    "SELECT count(*) FROM \"TokenTransitions\" WHERE \"discord_id\" = '1' AND \"token\" = $1",
+                    &[&tokens.v2],
+                )
+                .await
+                .unwrap()
+                .get::<_, i64>(0),
+            1
+        );
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn promo_role_queries_round_trip() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let now = SystemTime::now();
+        let rule = create_promo_role_rule(
+            &client,
+            "1000",
+            "Anniversary",
+            &now,
+            &(now + std::time::Duration::from_secs(60)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(get_promo_role_rules(&client).await.unwrap().len(), 1);
+
+        assert_eq!(
+            create_promo_role_grant(&client, &rule.id, "1")
+                .await
+                .unwrap(),
+            1
+        );
+        // granting twice is a no-op
+        assert_eq!(
+            create_promo_role_grant(&client, &rule.id, "1")
+                .await
+                .unwrap(),
+            0
+        );
+        create_promo_role_grant(&client, &rule.id, "2")
+            .await
+            .unwrap();
+        assert_eq!(
+            get_promo_role_grants(&client, &rule.id, &1)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        assert_eq!(
+            delete_promo_role_grant(&client, &rule.id, "1")
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            get_promo_role_grants(&client, &rule.id, &10).await.unwrap(),
+            vec!["2".to_owned()]
+        );
+
+        assert_eq!(
+            mark_promo_role_rule_swept(&client, &rule.id).await.unwrap(),
+            1
+        );
+        assert!(get_promo_role_rules(&client).await.unwrap().is_empty());
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn import_failure_queries_round_trip() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+
+        create_import_failure(&client, "job", 2, "\"DuplicateToken\"", "row")
+            .await
+            .unwrap();
+        create_import_failure(&client, "other-job", 1, "\"DuplicateToken\"", "row")
+            .await
+            .unwrap();
+
+        let failures = get_import_failures(&client, "job").await.unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].line, 2);
+
+        assert_eq!(
+            delete_expired_import_failures(
+                &client,
+                &(SystemTime::now() + std::time::Duration::from_secs(1))
+            )
+            .await
+            .unwrap(),
+            2
+        );
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn support_code_queries_round_trip() {
+    use crate::utilities::derive_user_tokens;
+
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let old = derive_user_tokens("old@example.com", "token", "secret");
+        let new = derive_user_tokens("new@example.com", "token", "secret");
+
+        upsert_support_code(&client, "code", &old).await.unwrap();
+        let replaced = upsert_support_code(&client, "code", &new).await.unwrap();
+        assert_eq!(replaced.user_tokens(), new);
+
+        assert_eq!(
+            get_support_code(&client, "code")
+                .await
+                .unwrap()
+                .user_tokens(),
+            new
+        );
+        assert!(get_support_code(&client, "unknown").await.is_err());
+
+        // a user that's still under their legacy token loses their codes too
+        assert_eq!(delete_support_codes(&client, &old.legacy).await.unwrap(), 0);
+        assert_eq!(delete_support_codes(&client, &new.legacy).await.unwrap(), 1);
+        assert!(get_support_code(&client, "code").await.is_err());
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn api_partner_queries_round_trip() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+
+        let partner = create_api_partner(&client, "hash", "wiki", &5, &None)
+            .await
+            .unwrap();
+        assert_eq!(partner.multiplier, 5);
+        assert_eq!(partner.expires_at, None);
+        // a key belongs to one partner
+        assert!(create_api_partner(&client, "hash", "other", &2, &None)
+            .await
+            .is_err());
+
+        assert_eq!(
+            get_api_partner(&client, "hash")
+                .await
+                .unwrap()
+                .unwrap()
+                .label,
+            "wiki"
+        );
+        assert!(get_api_partner(&client, "unknown").await.unwrap().is_none());
+        assert_eq!(get_api_partners(&client).await.unwrap().len(), 1);
+
+        assert_eq!(
+            delete_api_partner(&client, &partner.id)
+                .await
+                .unwrap()
+                .unwrap()
+                .key_hash,
+            "hash"
+        );
+        assert!(delete_api_partner(&client, &partner.id)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(get_api_partners(&client).await.unwrap().is_empty());
+    });
+}
+
+#[test]
+fn schema_names_have_to_be_plain_identifiers() {
+    for schema in ["public", "c2s_beta", "_staging2"] {
+        assert!(is_valid_schema_name(schema), "{}", schema);
+    }
+    for schema in ["", "2fast", "Beta", "beta-env", "beta\"; DROP", "beta env"] {
+        assert!(!is_valid_schema_name(schema), "{}", schema);
+    }
+    assert!(!is_valid_schema_name(&"a".repeat(64)));
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn environments_in_separate_schemas_dont_see_each_other() {
+    with_test_schemas(2, |pools| async move {
+        let budget = RequestBudget::unlimited();
+        let production = pools[0].get().await.unwrap();
+        let beta = pools[1].get().await.unwrap();
+
+        create_userdata(
+            &production,
+            &budget,
+            "token",
+            "1",
+            &false,
+            progress(10.0, 1),
+        )
+        .await
+        .unwrap();
+        assert!(get_userdata(&beta, &budget, "token").await.is_err());
+        assert_eq!(count_userdata(&beta).await.unwrap(), 0);
+
+        // the same player can be linked in both environments
+        create_userdata(&beta, &budget, "token", "1", &true, progress(20.0, 2))
+            .await
+            .unwrap();
+        update_userdata(
+            &beta,
+            &budget,
+            "token",
+            &Some(true),
+            progress(30.0, 2).into(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            get_userdata(&production, &budget, "token")
+                .await
+                .unwrap()
+                .metabits,
+            10
+        );
+
+        delete_userdata(&production, &budget, "token")
+            .await
+            .unwrap();
+        assert_eq!(count_userdata(&production).await.unwrap(), 0);
+        assert_eq!(
+            get_userdata(&beta, &budget, "token")
+                .await
+                .unwrap()
+                .metabits,
+            30
+        );
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn migrations_only_run_once() {
+    with_test_db(|pool| async move {
+        let mut client = pool.get().await.unwrap();
+        let schema: String = client
+            .query_one("SELECT current_schema()", &[])
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(migrate(&mut client, &schema).await.unwrap(), 0);
+
+        // tables from before migrations were tracked are taken to have the untracked migrations
+        client
+            .batch_execute(
+                "DROP TABLE \"SchemaMigrations\", \"DomainCounts\", \"AbuseCounters\", \"TokenTransitions\", \"ApiPartners\", \"IdempotencyKeys\", \"StatsSnapshots\", \"LinkCodes\", \"DigestCounts\"; ALTER TABLE \"UserData\" DROP COLUMN \"email_domain_key\", DROP COLUMN \"beta_tester_locked\", DROP COLUMN \"flagged_for_review\", DROP COLUMN \"last_seen_timestamp\", DROP COLUMN \"nickname_prefix\", DROP COLUMN \"flagged_timestamp\"; ALTER TABLE \"SupportCodes\" DROP COLUMN \"v2_token\", DROP COLUMN \"legacy_token\", ADD COLUMN \"email\" TEXT NOT NULL, ADD COLUMN \"token\" TEXT NOT NULL",
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            migrate(&mut client, &schema).await.unwrap(),
+            MIGRATIONS.len() - UNTRACKED_MIGRATIONS as usize
+        );
+        assert_eq!(
+            client
+                .query_one("SELECT count(*) FROM \"SchemaMigrations\"", &[])
+                .await
+                .unwrap()
+                .get::<_, i64>(0),
+            MIGRATIONS.len() as i64
+        );
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn schema_versions_are_compared_with_the_binary() {
+    use crate::schema_compat::SchemaState;
+
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let startup = |applied| SchemaState::at_startup(applied, SCHEMA_VERSION, false);
+
+        let applied = applied_schema_version(&client).await.unwrap();
+        assert_eq!(applied, SCHEMA_VERSION);
+        assert!(!startup(applied).unwrap().compatibility_mode);
+
+        // a deploy whose migrations haven't run yet
+        client
+            .execute(
+                "DELETE FROM \"SchemaMigrations\" WHERE \"version\" = $1",
+                &[&SCHEMA_VERSION],
+            )
+            .await
+            .unwrap();
+        let applied = applied_schema_version(&client).await.unwrap();
+        assert_eq!(applied, SCHEMA_VERSION - 1);
+        assert!(startup(applied).is_err());
+
+        // an old binary against a schema a newer one migrated
+        client
+            .execute(
+                "INSERT INTO \"SchemaMigrations\" (\"version\") VALUES ($1), ($2)",
+                &[&SCHEMA_VERSION, &(SCHEMA_VERSION + 1)],
+            )
+            .await
+            .unwrap();
+        let applied = applied_schema_version(&client).await.unwrap();
+        assert_eq!(applied, SCHEMA_VERSION + 1);
+        assert!(startup(applied).unwrap().compatibility_mode);
+
+        client
+            .batch_execute("DROP TABLE \"SchemaMigrations\"")
+            .await
+            .unwrap();
+        assert_eq!(applied_schema_version(&client).await.unwrap(), 0);
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn link_codes_are_consumed_once() {
+    use crate::link_codes::{LinkCodeForm, LinkPayload};
+    use std::time::Duration;
+
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let now = SystemTime::now();
+        let expires_at = now + Duration::from_secs(600);
+        create_link_code(&client, "ABCD2345", "80351110224678912", &expires_at)
+            .await
+            .unwrap();
+        // a second back, expiring at `now` could be rounded past it by the millisecond column
+        create_link_code(
+            &client,
+            "EXPIRED0",
+            "80351110224678912",
+            &(now - Duration::from_secs(1)),
+        )
+        .await
+        .unwrap();
+
+        // the QR form redeems the same code as the typed one
+        let unix_now = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let payload = LinkPayload {
+            code: "ABCD2345".to_owned(),
+            discord_id: "80351110224678912".to_owned(),
+            expires_at: unix_now + 600,
+        }
+        .sign("secret");
+        let form = LinkCodeForm::parse(&payload, Some("secret"), unix_now).unwrap();
+        assert_eq!(
+            consume_link_code(&client, form.code(), &now).await.unwrap(),
+            Some("80351110224678912".to_owned())
+        );
+        assert_eq!(
+            consume_link_code(&client, "ABCD2345", &now).await.unwrap(),
+            None
+        );
+
+        assert_eq!(
+            consume_link_code(&client, "EXPIRED0", &now).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            consume_link_code(&client, "UNKNOWN0", &now).await.unwrap(),
+            None
+        );
+        // only the expired code goes, whether or not the other one was consumed
+        assert_eq!(delete_expired_link_codes(&client, &now).await.unwrap(), 1);
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn domain_counts_follow_links() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+        assert_eq!(get_domain_count(&client, "throwaway").await.unwrap(), 0);
+
+        for (token, discord_id) in [("token", "1"), ("other-token", "2")] {
+            create_userdata(
+                &client,
+                &budget,
+                token,
+                discord_id,
+                &false,
+                progress(0.0, 0),
+            )
+            .await
+            .unwrap();
+            count_linked_domain(&client, discord_id, "throwaway")
+                .await
+                .unwrap();
+        }
+        assert_eq!(get_domain_count(&client, "throwaway").await.unwrap(), 2);
+
+        // rebinding a discord id to an account of another domain moves the link over
+        create_userdata(&client, &budget, "rebound", "2", &false, progress(0.0, 0))
+            .await
+            .unwrap();
+        count_linked_domain(&client, "2", "provider").await.unwrap();
+        assert_eq!(get_domain_count(&client, "throwaway").await.unwrap(), 1);
+        assert_eq!(get_domain_count(&client, "provider").await.unwrap(), 1);
+
+        // deleting a link gives it back to its domain
+        delete_userdata(&client, &budget, "token").await.unwrap();
+        assert_eq!(get_domain_count(&client, "throwaway").await.unwrap(), 0);
+        delete_userdata(&client, &budget, "rebound").await.unwrap();
+        assert_eq!(get_domain_count(&client, "provider").await.unwrap(), 0);
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn locked_beta_testers_survive_syncs() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+        create_userdata(&client, &budget, "token", "1", &false, progress(0.0, 0))
+            .await
+            .unwrap();
+        assert_eq!(
+            get_beta_tester_locked(&client, "1").await.unwrap(),
+            Some(false)
+        );
+
+        let status = update_beta_tester(&client, "1", &true, &true)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(status.beta_tester && status.beta_tester_locked);
+        assert!(update_beta_tester(&client, "2", &true, &true)
+            .await
+            .unwrap()
+            .is_none());
+
+        // a sync from the stable build leaves the locked flag alone, so does relinking
+        let synced = update_userdata(
+            &client,
+            &budget,
+            "token",
+            &Some(false),
+            progress(10.0, 1).into(),
+        )
+        .await
+        .unwrap();
+        assert!(synced.beta_tester);
+        let relinked = create_userdata(&client, &budget, "rebound", "1", &false, progress(10.0, 1))
+            .await
+            .unwrap();
+        assert!(relinked.beta_tester);
+        assert_eq!(
+            get_beta_tester_locked(&client, "1").await.unwrap(),
+            Some(true)
+        );
+
+        // unlocked, the channel decides again
+        update_beta_tester(&client, "1", &true, &false)
+            .await
+            .unwrap()
+            .unwrap();
+        let synced = update_userdata(
+            &client,
+            &budget,
+            "rebound",
+            &Some(false),
+            progress(10.0, 1).into(),
+        )
+        .await
+        .unwrap();
+        assert!(!synced.beta_tester);
+        let synced = update_userdata(
+            &client,
+            &budget,
+            "rebound",
+            &Some(true),
+            progress(10.0, 1).into(),
+        )
+        .await
+        .unwrap();
+        assert!(synced.beta_tester);
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn abuse_counter_snapshots_replace_each_other() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let now = SystemTime::now();
+        let in_a_minute = now + std::time::Duration::from_secs(60);
+        // a second back, expiring at `now` could be rounded past it by the millisecond column
+        let expired = now - std::time::Duration::from_secs(1);
+        let names = |rows: Vec<AbuseCounterRow>| {
+            let mut names = rows
+                .into_iter()
+                .map(|row| (row.key, row.value))
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        replace_abuse_counters(
+            &client,
+            &["link_attempts".to_owned(), "negative_tokens".to_owned()],
+            &["email".to_owned(), "token".to_owned()],
+            &[2, 1],
+            &[in_a_minute, expired],
+        )
+        .await
+        .unwrap();
+        // the negative token has expired already
+        assert_eq!(
+            names(get_abuse_counters(&client, &now).await.unwrap()),
+            vec![("email".to_owned(), 2)]
+        );
+
+        // a later snapshot updates what's still hot and drops the rest
+        replace_abuse_counters(
+            &client,
+            &["link_attempts".to_owned(), "link_attempts".to_owned()],
+            &["email".to_owned(), "other-email".to_owned()],
+            &[3, 1],
+            &[in_a_minute, in_a_minute],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            names(get_abuse_counters(&client, &now).await.unwrap()),
+            vec![("email".to_owned(), 3), ("other-email".to_owned(), 1)]
+        );
+        assert_eq!(
+            client
+                .query_one("SELECT count(*) FROM \"AbuseCounters\"", &[])
+                .await
+                .unwrap()
+                .get::<_, i64>(0),
+            2
+        );
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn patches_only_write_the_fields_they_have() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+        let stored = UpdateUserData {
+            singularity_speedrun_time: Some(300.0),
+            all_sharks_obtained: true,
+            ..progress(10.0, 5)
+        };
+        create_userdata(&client, &budget, "token", "1", &false, stored)
+            .await
+            .unwrap();
+
+        let patched = update_userdata(
+            &client,
+            &budget,
+            "token",
+            &None,
+            PatchUserData {
+                dino_rank: Some(7),
+                ..PatchUserData::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!((patched.metabits, patched.dino_rank), (10, 7));
+        assert_eq!(patched.singularity_speedrun_time, Some(300.0));
+        assert!(patched.all_sharks_obtained);
+
+        // `null` clears the speedrun time, leaving it out doesn't
+        let cleared = update_userdata(
+            &client,
+            &budget,
+            "token",
+            &None,
+            PatchUserData {
+                singularity_speedrun_time: Some(None),
+                ..PatchUserData::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(cleared.singularity_speedrun_time, None);
+        assert_eq!(cleared.dino_rank, 7);
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn omitted_beta_flags_keep_the_stored_one() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+        for (token, discord_id, stored) in [("beta", "1", true), ("stable", "2", false)] {
+            create_userdata(
+                &client,
+                &budget,
+                token,
+                discord_id,
+                &stored,
+                progress(0.0, 0),
+            )
+            .await
+            .unwrap();
+
+            let synced = update_userdata(&client, &budget, token, &None, progress(10.0, 1).into())
+                .await
+                .unwrap();
+            assert_eq!(synced.beta_tester, stored, "{}", token);
+
+            for sent in [true, false] {
+                let synced = update_userdata(
+                    &client,
+                    &budget,
+                    token,
+                    &Some(sent),
+                    progress(10.0, 1).into(),
+                )
+                .await
+                .unwrap();
+                assert_eq!(synced.beta_tester, sent, "{}", token);
+            }
+        }
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn concurrent_syncs_leave_one_consistent_state() {
+    with_test_db(|pool| async move {
+        let budget = RequestBudget::unlimited();
+        let mut first = pool.get().await.unwrap();
+        let mut second = pool.get().await.unwrap();
+        create_userdata(&first, &budget, "token", "1", &false, progress(0.0, 0))
+            .await
+            .unwrap();
+
+        for round in 1..=10 {
+            let (a, b) = tokio::join!(
+                update_linked_userdata(
+                    &mut first,
+                    &budget,
+                    "token",
+                    &None,
+                    overwrite(progress(20.0, round))
+                ),
+                update_linked_userdata(
+                    &mut second,
+                    &budget,
+                    "token",
+                    &None,
+                    overwrite(progress(30.0, round + 100))
+                ),
+            );
+            assert!(a.is_ok() && b.is_ok());
+
+            // the writes don't get mixed, the row is one of the syncs as a whole
+            let stored = get_userdata(&first, &budget, "token").await.unwrap();
+            assert!(
+                (stored.metabits, stored.dino_rank) == (20, round)
+                    || (stored.metabits, stored.dino_rank) == (30, round + 100)
+            );
+        }
+
+        // each sync sees the row as the other one left it, so neither works from a stale rank
+        let bump = |current: &UserData| {
+            Ok::<_, ()>((
+                progress(20.0, current.dino_rank + 1).into(),
+                current.dino_rank,
+            ))
+        };
+        let mut seen = Vec::new();
+        for _ in 0..10 {
+            let (a, b) = tokio::join!(
+                update_linked_userdata(&mut first, &budget, "token", &None, bump),
+                update_linked_userdata(&mut second, &budget, "token", &None, bump),
+            );
+            seen.extend([a.unwrap().1, b.unwrap().1]);
+        }
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 20);
+
+        // a sync turned down by its checks doesn't write anything
+        let reject = |_: &UserData| Err::<(PatchUserData, ()), _>("rejected");
+        let before = get_userdata(&first, &budget, "token").await.unwrap();
+        assert!(matches!(
+            update_linked_userdata(&mut first, &budget, "token", &None, reject).await,
+            Err(UpdateError::Rejected("rejected"))
+        ));
+        let after = get_userdata(&first, &budget, "token").await.unwrap();
+        assert_eq!(before.dino_rank, after.dino_rank);
+
+        // a delete that gets in first leaves the sync nothing to write to
+        let (deleted, synced) = tokio::join!(
+            delete_userdata(&first, &budget, "token"),
+            update_linked_userdata(
+                &mut second,
+                &budget,
+                "token",
+                &None,
+                overwrite(progress(40.0, 1))
+            ),
+        );
+        assert!(deleted.unwrap().is_some());
+        assert!(matches!(synced, Ok(_) | Err(UpdateError::NotFound)));
+        assert!(matches!(
+            get_userdata(&first, &budget, "token").await,
+            Err(LookupError::NotFound)
+        ));
+        assert!(matches!(
+            update_linked_userdata(
+                &mut second,
+                &budget,
+                "token",
+                &None,
+                overwrite(progress(40.0, 1))
+            )
+            .await,
+            Err(UpdateError::NotFound)
+        ));
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn concurrent_creates_link_once() {
+    with_test_db(|pool| async move {
+        let budget = RequestBudget::unlimited();
+        let first = pool.get().await.unwrap();
+        let second = pool.get().await.unwrap();
+        let conflicts = |results: [Result<UserData, CreateError>; 2]| {
+            assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+            results
+                .into_iter()
+                .filter_map(|result| match result {
+                    Err(CreateError::Conflict(conflict)) => Some(conflict),
+                    _ => None,
+                })
+                .collect::<Vec<LinkConflict>>()
+        };
+
+        // the same account linking two discord ids at once
+        let (a, b) = tokio::join!(
+            create_userdata(&first, &budget, "token", "1", &false, progress(0.0, 0)),
+            create_userdata(&second, &budget, "token", "2", &false, progress(0.0, 0)),
+        );
+        assert_eq!(conflicts([a, b]), vec![LinkConflict::Token]);
+
+        // the same link sent twice
+        let (a, b) = tokio::join!(
+            create_userdata(&first, &budget, "other", "3", &false, progress(0.0, 0)),
+            create_userdata(&second, &budget, "other", "3", &false, progress(0.0, 0)),
+        );
+        assert_eq!(conflicts([a, b]), vec![LinkConflict::Token]);
+    });
+}
+
+#[test]
+fn unique_violations_are_told_apart_by_their_constraint() {
+    assert_eq!(
+        LinkConflict::from_constraint("UserData_pkey"),
+        Some(LinkConflict::Token)
+    );
+    assert_eq!(
+        LinkConflict::from_constraint("UserData_discord_id_key"),
+        Some(LinkConflict::DiscordId)
+    );
+    assert_eq!(LinkConflict::from_constraint("SupportCodes_pkey"), None);
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn portable_users_move_between_instances() {
+    use crate::{granted_roles::parse_granted_roles, portability};
+
+    with_test_schemas(2, |pools| async move {
+        let budget = RequestBudget::unlimited();
+        let source = pools[0].get().await.unwrap();
+        let mut target = pools[1].get().await.unwrap();
+
+        let created = create_userdata(&source, &budget, "token", "1", &true, progress(10.0, 1))
+            .await
+            .unwrap();
+        create_userdata_snapshot(&source, &budget, &created)
+            .await
+            .unwrap();
+        update_granted_roles(&source, "1", "{\"42\":1700000000,\"43\":null}")
+            .await
+            .unwrap();
+
+        let granted_roles =
+            parse_granted_roles(&get_granted_roles(&source, "1").await.unwrap().unwrap());
+        let history = get_userdata_snapshots(&source, "1").await.unwrap();
+        let blob = portability::seal(&created, &granted_roles, &history, "secret").unwrap();
+        let opened = portability::open(&blob, "secret").unwrap();
+
+        import_portable_user(
+            &mut target,
+            &opened.user,
+            &serde_json::to_string(&opened.granted_roles).unwrap(),
+            &opened.history,
+        )
+        .await
+        .unwrap();
+        let imported = get_userdata(&target, &budget, "token").await.unwrap();
+        assert_eq!(
+            serde_json::to_value(&imported).unwrap(),
+            serde_json::to_value(&created).unwrap()
+        );
+        assert_eq!(
+            parse_granted_roles(&get_granted_roles(&target, "1").await.unwrap().unwrap()),
+            granted_roles
+        );
+        assert_eq!(get_userdata_snapshots(&target, "1").await.unwrap().len(), 1);
+
+        // importing again conflicts instead of rebinding anything, and writes nothing
+        let mut remapped = portability::open(&blob, "secret").unwrap().user;
+        remapped.discord_id = "2".to_owned();
+        assert!(matches!(
+            import_portable_user(&mut target, &remapped, "{}", &opened.history).await,
+            Err(CreateError::Conflict(LinkConflict::Token))
+        ));
+        assert!(get_userdata_snapshots(&target, "2")
+            .await
+            .unwrap()
+            .is_empty());
+        let mut retokened = portability::open(&blob, "secret").unwrap().user;
+        retokened.token = "another token".to_owned();
+        assert!(matches!(
+            import_portable_user(&mut target, &retokened, "{}", &[]).await,
+            Err(CreateError::Conflict(LinkConflict::DiscordId))
+        ));
+        assert_eq!(get_userdata_snapshots(&target, "1").await.unwrap().len(), 1);
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn read_only_sessions_are_told_apart() {
+    with_test_db(|pool| async move {
+        let budget = RequestBudget::unlimited();
+        let mut client = pool.get().await.unwrap();
+        create_userdata(&client, &budget, "token", "1", &false, progress(10.0, 1))
+            .await
+            .unwrap();
+
+        // what the old primary looks like during a failover
+        client
+            .batch_execute("SET default_transaction_read_only = on")
+            .await
+            .unwrap();
+        assert!(matches!(
+            update_linked_userdata(
+                &mut client,
+                &budget,
+                "token",
+                &None,
+                overwrite(progress(20.0, 2))
+            )
+            .await,
+            Err(UpdateError::ReadOnly(_))
+        ));
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn idempotency_keys_replay_until_they_expire() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let an_hour_ago = SystemTime::now() - std::time::Duration::from_secs(60 * 60);
+
+        create_idempotency_key(&client, "token", "retry-1", r#"{"bound_discord_id":"1"}"#)
+            .await
+            .unwrap();
+        assert_eq!(
+            get_idempotent_response(&client, "token", "retry-1", &an_hour_ago)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some(r#"{"bound_discord_id":"1"}"#)
+        );
+
+        // another key for the same user, or the same key for another user, isn't a retry
+        assert_eq!(
+            get_idempotent_response(&client, "token", "retry-2", &an_hour_ago)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            get_idempotent_response(&client, "other token", "retry-1", &an_hour_ago)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let in_an_hour = SystemTime::now() + std::time::Duration::from_secs(60 * 60);
+        assert_eq!(
+            get_idempotent_response(&client, "token", "retry-1", &in_an_hour)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            delete_expired_idempotency_keys(&client, &an_hour_ago)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            delete_expired_idempotency_keys(&client, &in_an_hour)
+                .await
+                .unwrap(),
+            1
+        );
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn stats_snapshots_are_kept_per_day() {
+    with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+
+        upsert_stats_snapshot(&client, 100, &["total_linked", "new_links"], &[10, 2])
+            .await
+            .unwrap();
+        // the scheduler was down on day 101
+        upsert_stats_snapshot(&client, 102, &["total_linked", "new_links"], &[11, 1])
+            .await
+            .unwrap();
+        // a day that's snapshotted again keeps the later values
+        upsert_stats_snapshot(&client, 102, &["total_linked"], &[12])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            get_stats_snapshots(&client, "total_linked", 100)
+                .await
+                .unwrap(),
+            vec![(100, 10), (102, 12)]
+        );
+        assert_eq!(
+            get_stats_snapshots(&client, "new_links", 101)
+                .await
+                .unwrap(),
+            vec![(102, 1)]
+        );
+
+        assert_eq!(
+            delete_expired_stats_snapshots(&client, 101).await.unwrap(),
+            2
+        );
+        assert_eq!(
+            get_stats_snapshots(&client, "total_linked", 0)
+                .await
+                .unwrap(),
+            vec![(102, 12)]
+        );
+    });
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn recycled_connections_are_back_at_the_session_timeout() {
+    use crate::session_settings::{application_name, create_pool, SessionSettings};
+    use std::time::Duration;
+
+    actix_web::rt::System::new().block_on(async {
+        let mut pg = test_pool_config(
+            &std::env::var("TEST_DATABASE_URL")
+                .expect("TEST_DATABASE_URL has to point at a Postgres database"),
+        );
+        // one connection, so the second request gets the one the first returned
+        pg.pool = Some(deadpool_postgres::PoolConfig::new(1));
+        let settings = SessionSettings {
+            application_name: application_name("test"),
+            statement_timeout: Duration::from_secs(30),
+            idle_in_transaction_session_timeout: Duration::ZERO,
+            search_path: None,
+        };
+        let pool = create_pool(&pg, settings).await.unwrap();
+        async fn timeout_of(client: &Client) -> (String, i32) {
+            let row = client
+                .query_one(
+                    "SELECT current_setting('statement_timeout'), pg_backend_pid()",
+                    &[],
+                )
+                .await
+                .unwrap();
+            (row.get(0), row.get(1))
+        }
+
+        let client = pool.get().await.unwrap();
+        apply_budget(
+            &client,
+            &RequestBudget::new(Duration::from_millis(500), Instant::now()),
+        )
+        .await
+        .unwrap();
+        let (budgeted, pid) = timeout_of(&client).await;
+        assert_ne!(budgeted, "30s");
+        drop(client);
+
+        let client = pool.get().await.unwrap();
+        assert_eq!(timeout_of(&client).await, ("30s".to_owned(), pid));
+    });
+}
@@ -0,0 +1,289 @@
+use actix_web::{http::header::USER_AGENT, HttpRequest};
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use std::borrow::Cow;
+
+use crate::{
+    digest::unix_now,
+    fields::{ProgressField, DEPRECATED_FIELDS},
+    models::{DeprecationUsageRow, PatchUserData},
+    utilities::hex_encode,
+    write_behind::{write_behind, CounterTable, WriteBehindBuffer},
+};
+
+const DAY: u64 = 24 * 60 * 60;
+/// clients that read `roles` instead of the flat `message` announce it with this header
+pub const RESPONSE_SHAPE_HEADER: &str = "x-response-shape";
+
+/// everything that's on its way out of the API, usage is counted so removals can be planned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeprecatedFeature {
+    /// `POST /userdata`
+    OgEndpoint,
+    /// reading the flat `message` of a sync response instead of its `roles`
+    LegacyMessageResponse,
+    /// a sync sending a progress field the game doesn't have anymore
+    Field(ProgressField),
+}
+
+impl DeprecatedFeature {
+    pub fn name(&self) -> Cow<'static, str> {
+        match self {
+            DeprecatedFeature::OgEndpoint => Cow::Borrowed("og_endpoint"),
+            DeprecatedFeature::LegacyMessageResponse => Cow::Borrowed("legacy_message_response"),
+            DeprecatedFeature::Field(field) => Cow::Owned(format!("field:{}", field.name())),
+        }
+    }
+}
+
+/// the usage of a feature on a single day, `clients` counts the distinct fingerprints
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DayUsage {
+    pub day: String,
+    pub uses: i64,
+    pub clients: i64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct FeatureUsage {
+    pub feature: String,
+    pub total_uses: i64,
+    /// newest day first
+    pub days: Vec<DayUsage>,
+}
+
+/// A hash of the user agent and the user's token, distinct clients can be counted without storing
+/// anything that identifies them.
+pub fn client_fingerprint(req: &HttpRequest, user_token: &str) -> String {
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    fingerprint(user_agent, user_token)
+}
+
+pub fn fingerprint(user_agent: &str, user_token: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(user_agent.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(user_token.as_bytes());
+
+    hex_encode(&hasher.finalize())[..16].to_owned()
+}
+
+/// whether the request didn't opt into the grouped response shape
+pub fn wants_legacy_message(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(RESPONSE_SHAPE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map_or(true, |shape| !shape.trim().eq_ignore_ascii_case("roles"))
+}
+
+/// counts a use of the feature, the counts are written by the write-behind flusher
+pub fn note_deprecated_usage(feature: DeprecatedFeature, fingerprint: &str) {
+    note_usage(write_behind(), feature, fingerprint, unix_now());
+}
+
+/// counts every deprecated field the sync still sent
+pub fn note_deprecated_fields(payload: &PatchUserData, fingerprint: &str) {
+    for field in sent_deprecated_fields(payload, &DEPRECATED_FIELDS) {
+        note_deprecated_usage(DeprecatedFeature::Field(field), fingerprint);
+    }
+}
+
+/// the deprecated fields the payload has values for
+pub fn sent_deprecated_fields(
+    payload: &PatchUserData,
+    deprecated: &[ProgressField],
+) -> Vec<ProgressField> {
+    deprecated
+        .iter()
+        .copied()
+        .filter(|field| field.read_patch(payload).is_some())
+        .collect()
+}
+
+pub fn note_usage(
+    buffer: &WriteBehindBuffer,
+    feature: DeprecatedFeature,
+    fingerprint: &str,
+    now: u64,
+) -> bool {
+    buffer.add(
+        CounterTable::DeprecationUsage,
+        &format!("{}|{}|{}", feature.name(), now / DAY, fingerprint),
+        1,
+    )
+}
+
+/// splits the buffered keys into the columns of the upsert, malformed keys are skipped
+pub fn usage_columns(
+    keys: &[String],
+    counts: &[i64],
+) -> (Vec<String>, Vec<i64>, Vec<String>, Vec<i64>) {
+    let mut columns = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (key, count) in keys.iter().zip(counts) {
+        let mut parts = key.splitn(3, '|');
+        let (feature, day, fingerprint) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(feature), Some(day), Some(fingerprint)) => (feature, day, fingerprint),
+            _ => continue,
+        };
+        let day = match day.parse::<i64>() {
+            Ok(day) => day,
+            Err(_) => continue,
+        };
+        columns.0.push(feature.to_owned());
+        columns.1.push(day);
+        columns.2.push(fingerprint.to_owned());
+        columns.3.push(*count);
+    }
+
+    columns
+}
+
+/// groups the daily rows by feature, the rows are expected ordered by feature and newest day first
+pub fn group_usage(rows: Vec<DeprecationUsageRow>) -> Vec<FeatureUsage> {
+    let mut features: Vec<FeatureUsage> = Vec::new();
+    for row in rows {
+        let day = DayUsage {
+            day: row.day,
+            uses: row.uses,
+            clients: row.clients,
+        };
+        match features.last_mut() {
+            Some(feature) if feature.feature == row.feature => {
+                feature.total_uses += day.uses;
+                feature.days.push(day);
+            }
+            _ => features.push(FeatureUsage {
+                feature: row.feature,
+                total_uses: day.uses,
+                days: vec![day],
+            }),
+        }
+    }
+
+    features
+}
+
+#[test]
+fn usage_is_accumulated_per_feature_day_and_client() {
+    use std::time::{Duration, Instant};
+
+    let buffer = WriteBehindBuffer::new(Duration::from_secs(30), 10, Instant::now());
+    let client = fingerprint("game/1.0", "token");
+    let other_client = fingerprint("bot/2.0", "token");
+    assert_ne!(client, other_client);
+    assert_eq!(client.len(), 16);
+
+    note_usage(&buffer, DeprecatedFeature::OgEndpoint, &client, 0);
+    note_usage(&buffer, DeprecatedFeature::OgEndpoint, &client, DAY - 1);
+    note_usage(&buffer, DeprecatedFeature::OgEndpoint, &client, DAY);
+    note_usage(&buffer, DeprecatedFeature::OgEndpoint, &other_client, 0);
+    note_usage(
+        &buffer,
+        DeprecatedFeature::LegacyMessageResponse,
+        &client,
+        0,
+    );
+    note_usage(
+        &buffer,
+        DeprecatedFeature::Field(ProgressField::AllSharksObtained),
+        &client,
+        0,
+    );
+
+    let batch = buffer
+        .take_batches(Instant::now())
+        .into_iter()
+        .find(|batch| batch.table == CounterTable::DeprecationUsage)
+        .unwrap();
+    let (features, days, fingerprints, counts) = usage_columns(&batch.keys, &batch.counts);
+    let mut rows = features
+        .into_iter()
+        .zip(days)
+        .zip(fingerprints)
+        .zip(counts)
+        .map(|(((feature, day), fingerprint), count)| (feature, day, fingerprint, count))
+        .collect::<Vec<_>>();
+    rows.sort();
+
+    let mut expected = vec![
+        ("field:all_sharks_obtained".to_owned(), 0, client.clone(), 1),
+        ("legacy_message_response".to_owned(), 0, client.clone(), 1),
+        ("og_endpoint".to_owned(), 0, client.clone(), 2),
+        ("og_endpoint".to_owned(), 0, other_client, 1),
+        ("og_endpoint".to_owned(), 1, client, 1),
+    ];
+    expected.sort();
+    assert_eq!(rows, expected);
+}
+
+#[test]
+fn usage_is_listed_by_feature_and_day() {
+    let row = |feature: &str, day: &str, uses, clients| DeprecationUsageRow {
+        feature: feature.to_owned(),
+        day: day.to_owned(),
+        uses,
+        clients,
+    };
+
+    let usage = group_usage(vec![
+        row("legacy_message_response", "2024-01-02", 5, 2),
+        row("og_endpoint", "2024-01-02", 3, 1),
+        row("og_endpoint", "2024-01-01", 4, 3),
+    ]);
+
+    assert_eq!(
+        usage,
+        vec![
+            FeatureUsage {
+                feature: "legacy_message_response".to_owned(),
+                total_uses: 5,
+                days: vec![DayUsage {
+                    day: "2024-01-02".to_owned(),
+                    uses: 5,
+                    clients: 2,
+                }],
+            },
+            FeatureUsage {
+                feature: "og_endpoint".to_owned(),
+                total_uses: 7,
+                days: vec![
+                    DayUsage {
+                        day: "2024-01-02".to_owned(),
+                        uses: 3,
+                        clients: 1,
+                    },
+                    DayUsage {
+                        day: "2024-01-01".to_owned(),
+                        uses: 4,
+                        clients: 3,
+                    },
+                ],
+            },
+        ]
+    );
+    assert!(usage_columns(&["broken".to_owned()], &[1]).0.is_empty());
+}
+
+#[test]
+fn only_deprecated_fields_that_were_sent_are_counted() {
+    let deprecated = [
+        ProgressField::AllSharksObtained,
+        ProgressField::SingularitySpeedrunTime,
+    ];
+    let payload = PatchUserData {
+        metabits: Some(12.0),
+        all_sharks_obtained: Some(false),
+        ..PatchUserData::default()
+    };
+
+    assert_eq!(
+        sent_deprecated_fields(&payload, &deprecated),
+        vec![ProgressField::AllSharksObtained]
+    );
+    assert!(sent_deprecated_fields(&payload, &[]).is_empty());
+}
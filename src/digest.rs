@@ -0,0 +1,431 @@
+use actix_web::rt::{self, time};
+use deadpool_postgres::Pool;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime},
+};
+use twilight_model::channel::embed::{Embed, EmbedField, EmbedFooter};
+
+use crate::{
+    constants::{ErrorLogType, LOG},
+    db,
+    errors::{ConvertResultErrorToMyError, LogMyError, MyError},
+    role_handling::cap_role_list,
+    slo::{slo_tracker, WeeklySlo},
+    webhook_logging::{webhook_embed, webhook_log},
+    write_behind::{flush, write_behind, CounterTable},
+};
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+/// the color of the digest embed's side bar
+const DIGEST_COLOR: u32 = 0x00_99_ff;
+/// Discord refuses embeds with longer field values
+const EMBED_FIELD_LENGTH: usize = 1024;
+/// how long the counts are kept, a digest only reads the week before it
+pub const DIGEST_COUNT_RETENTION: Duration = Duration::from_secs(5 * WEEK);
+
+/// What the digest counts as it happens. The counts are kept per hour in `DigestCounts`, so the
+/// digest reads exactly its window whenever it's sent and restarts don't lose them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DigestCount {
+    /// by error code
+    Error,
+    Deletion,
+    /// by role name
+    RoleGrant,
+}
+
+impl DigestCount {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DigestCount::Error => "error",
+            DigestCount::Deletion => "deletion",
+            DigestCount::RoleGrant => "role_grant",
+        }
+    }
+
+    pub fn from_kind(kind: &str) -> Option<DigestCount> {
+        [
+            DigestCount::Error,
+            DigestCount::Deletion,
+            DigestCount::RoleGrant,
+        ]
+        .into_iter()
+        .find(|count| count.kind() == kind)
+    }
+}
+
+/// the write-behind key of a count in the hour of `now`, `{kind}|{unix hour}|{name}`
+pub fn digest_count_key(count: DigestCount, name: &str, now: u64) -> String {
+    format!("{}|{}|{}", count.kind(), now / HOUR, name)
+}
+
+/// counts `name` towards the current hour, it's written with the next flush
+pub fn record_digest_count(count: DigestCount, name: &str) {
+    write_behind().add(
+        CounterTable::DigestCounts,
+        &digest_count_key(count, name, unix_now()),
+        1,
+    );
+}
+
+/// splits the buffered keys into the columns of the upsert, malformed keys are skipped
+pub fn digest_count_columns(
+    keys: &[String],
+    counts: &[i64],
+) -> (Vec<String>, Vec<i64>, Vec<String>, Vec<i64>) {
+    let mut columns = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (key, count) in keys.iter().zip(counts) {
+        let mut parts = key.splitn(3, '|');
+        let (kind, hour, name) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(kind), Some(hour), Some(name)) => (kind, hour, name),
+            _ => continue,
+        };
+        let hour = match hour.parse::<i64>() {
+            Ok(hour) => hour,
+            Err(_) => continue,
+        };
+        columns.0.push(kind.to_owned());
+        columns.1.push(hour);
+        columns.2.push(name.to_owned());
+        columns.3.push(*count);
+    }
+
+    columns
+}
+
+pub fn record_role_grants(gained_roles: &[String]) {
+    for role in gained_roles {
+        record_digest_count(DigestCount::RoleGrant, role);
+        write_behind().add(CounterTable::RoleGrantCounts, role, 1);
+    }
+}
+
+/// everything that goes into a digest, kept separate from the composition so it can be tested
+#[derive(Serialize, Default, Debug)]
+pub struct DigestStats {
+    /// seconds since the unix epoch
+    #[serde(serialize_with = "crate::timestamps::unix_seconds::serialize")]
+    pub window_start: u64,
+    #[serde(serialize_with = "crate::timestamps::unix_seconds::serialize")]
+    pub window_end: u64,
+    pub new_links: i64,
+    pub deletions: u64,
+    pub total_linked: i64,
+    /// accounts flagged for token sharing within the window
+    pub flagged_for_review: i64,
+    pub role_grants: BTreeMap<String, u64>,
+    /// error code with how often it happened, the most frequent first
+    pub top_errors: Vec<(String, u64)>,
+    pub slo: Vec<WeeklySlo>,
+}
+
+/// The boundaries of the last full week (Monday to Monday) in a timezone that's `utc_offset_hours`
+/// away from UTC, as seconds since the unix epoch.
+pub fn last_week_window(now: u64, utc_offset_hours: i64) -> (u64, u64) {
+    let offset = utc_offset_hours * 60 * 60;
+    let local_days = (now as i64 + offset).div_euclid(DAY as i64);
+    // the unix epoch was a Thursday
+    let weekday = (local_days + 3).rem_euclid(7);
+    let this_monday = (local_days - weekday) * DAY as i64 - offset;
+
+    (this_monday as u64 - WEEK, this_monday as u64)
+}
+
+pub fn compose_digest(stats: &DigestStats) -> Embed {
+    let role_grants = if stats.role_grants.is_empty() {
+        "none".to_owned()
+    } else {
+        let lines = stats
+            .role_grants
+            .iter()
+            .map(|(role, count)| format!("{}: {}", role, count))
+            .collect::<Vec<String>>();
+        // every role is listed as long as they fit into the field
+        cap_role_list(lines.len(), lines.len(), EMBED_FIELD_LENGTH, |shown| {
+            lines[..shown].join("\n")
+        })
+    };
+    let top_errors = if stats.top_errors.is_empty() {
+        "none".to_owned()
+    } else {
+        stats
+            .top_errors
+            .iter()
+            .map(|(code, count)| format!("{}: {}", code, count))
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+    let slo = if stats.slo.is_empty() {
+        "none".to_owned()
+    } else {
+        stats
+            .slo
+            .iter()
+            .map(WeeklySlo::line)
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+
+    Embed {
+        author: None,
+        color: Some(DIGEST_COLOR),
+        description: Some(format!(
+            "<t:{}:D> to <t:{}:D>",
+            stats.window_start, stats.window_end
+        )),
+        fields: vec![
+            field("New links", stats.new_links.to_string(), true),
+            field("Deletions", stats.deletions.to_string(), true),
+            field("Total linked", stats.total_linked.to_string(), true),
+            field(
+                "Flagged for review",
+                stats.flagged_for_review.to_string(),
+                true,
+            ),
+            field("Role grants", role_grants, false),
+            field("Top errors", top_errors, false),
+            field("SLOs", slo, false),
+        ],
+        footer: Some(EmbedFooter {
+            icon_url: None,
+            proxy_icon_url: None,
+            text: "Weekly digest".to_owned(),
+        }),
+        image: None,
+        kind: "rich".to_owned(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: Some("Weekly digest".to_owned()),
+        url: None,
+        video: None,
+    }
+}
+
+fn field(name: &str, value: String, inline: bool) -> EmbedField {
+    EmbedField {
+        inline,
+        name: name.to_owned(),
+        value,
+    }
+}
+
+/// Gathers the stats of the given window from the database, the buffered counts are flushed first
+/// so the window is complete.
+pub async fn gather_stats(
+    pool: &Pool,
+    (window_start, window_end): (u64, u64),
+) -> Result<DigestStats, MyError> {
+    // a failed flush is logged and retried with the next one, the digest just misses those counts
+    let _ = flush(pool).await;
+
+    let client = pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(window_start);
+    let end = SystemTime::UNIX_EPOCH + Duration::from_secs(window_end);
+    let slo = slo_tracker()
+        .lock()
+        .unwrap()
+        .week((window_start, window_end));
+
+    let new_links = db::count_new_links(&client, &start, &end)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at counting the new links for the digest",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+    let total_linked = db::count_userdata(&client)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at counting the linked users for the digest",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+    let flagged_for_review = db::count_flagged_for_review(&client, &start, &end)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at counting the users flagged for review for the digest",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+    let counts = db::get_digest_counts(&client, &start, &end)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at reading the counts for the digest",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let mut deletions = 0;
+    let mut role_grants = BTreeMap::new();
+    // the rows are ordered by count, the most frequent first
+    let mut top_errors = Vec::new();
+    for row in counts {
+        let count = row.count as u64;
+        match DigestCount::from_kind(&row.kind) {
+            Some(DigestCount::Error) => top_errors.push((row.name, count)),
+            Some(DigestCount::Deletion) => deletions += count,
+            Some(DigestCount::RoleGrant) => {
+                role_grants.insert(row.name, count);
+            }
+            None => {}
+        }
+    }
+    top_errors.truncate(5);
+
+    Ok(DigestStats {
+        window_start,
+        window_end,
+        new_links,
+        deletions,
+        total_linked,
+        flagged_for_review,
+        role_grants,
+        top_errors,
+        slo,
+    })
+}
+
+/// sends the digest of the previous week every Monday at midnight in the configured timezone
+pub fn spawn_digest_scheduler(pool: Pool, utc_offset_hours: i64) {
+    rt::spawn(async move {
+        loop {
+            let now = unix_now();
+            let (_, this_monday) = last_week_window(now, utc_offset_hours);
+            time::sleep(Duration::from_secs(this_monday + WEEK - now)).await;
+
+            let window = last_week_window(unix_now(), utc_offset_hours);
+            match gather_stats(&pool, window).await {
+                Ok(stats) => webhook_embed(compose_digest(&stats)).await,
+                Err(error) => {
+                    webhook_log(format!("the weekly digest failed: {}", error), LOG::FAILURE).await
+                }
+            }
+        }
+    });
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[test]
+fn week_window_starts_on_local_monday() {
+    // Wednesday 2024-01-10 12:00:00 UTC
+    let now = 1_704_888_000;
+
+    // Monday 2024-01-01 00:00 UTC to Monday 2024-01-08 00:00 UTC
+    assert_eq!(last_week_window(now, 0), (1_704_067_200, 1_704_672_000));
+    // the same Mondays at midnight in UTC-5
+    assert_eq!(
+        last_week_window(now, -5),
+        (1_704_067_200 + 5 * 3600, 1_704_672_000 + 5 * 3600)
+    );
+}
+
+#[test]
+fn digest_lists_every_stat() {
+    let stats = DigestStats {
+        window_start: 1_704_067_200,
+        window_end: 1_704_672_000,
+        new_links: 12,
+        deletions: 1,
+        total_linked: 840,
+        flagged_for_review: 2,
+        role_grants: BTreeMap::from([
+            ("Beta Tester".to_owned(), 2),
+            ("Reality Legend".to_owned(), 5),
+        ]),
+        top_errors: vec![
+            ("INTERNAL_ERROR".to_owned(), 7),
+            ("BAD_REQUEST".to_owned(), 3),
+        ],
+        slo: vec![WeeklySlo {
+            route: "update_user",
+            objective: 99.0,
+            week: crate::slo::Attainment::new(995, 5, 0.99),
+        }],
+    };
+
+    let embed = compose_digest(&stats);
+
+    assert_eq!(
+        embed
+            .fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.value.as_str()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("New links", "12"),
+            ("Deletions", "1"),
+            ("Total linked", "840"),
+            ("Flagged for review", "2"),
+            ("Role grants", "Beta Tester: 2\nReality Legend: 5"),
+            ("Top errors", "INTERNAL_ERROR: 7\nBAD_REQUEST: 3"),
+            (
+                "SLOs",
+                "update_user: 99.50% of 99%, 50% of the error budget used"
+            ),
+        ]
+    );
+    assert_eq!(
+        embed.description.as_deref(),
+        Some("<t:1704067200:D> to <t:1704672000:D>")
+    );
+}
+
+#[test]
+fn digest_counts_are_keyed_by_their_hour() {
+    let keys = vec![
+        digest_count_key(DigestCount::Error, "BAD_REQUEST", 2 * HOUR + 59),
+        digest_count_key(DigestCount::RoleGrant, "A | B", 3 * HOUR),
+        "malformed".to_owned(),
+    ];
+    assert_eq!(keys[0], "error|2|BAD_REQUEST");
+
+    assert_eq!(
+        digest_count_columns(&keys, &[4, 1, 1]),
+        (
+            vec!["error".to_owned(), "role_grant".to_owned()],
+            vec![2, 3],
+            vec!["BAD_REQUEST".to_owned(), "A | B".to_owned()],
+            vec![4, 1],
+        )
+    );
+    assert_eq!(
+        DigestCount::from_kind("role_grant"),
+        Some(DigestCount::RoleGrant)
+    );
+    assert_eq!(DigestCount::from_kind("unknown"), None);
+}
+
+#[test]
+fn role_grants_are_kept_within_the_field_length() {
+    let stats = DigestStats {
+        role_grants: (0..100)
+            .map(|role| (format!("Role {} {}", role, "R".repeat(20)), 1))
+            .collect(),
+        ..DigestStats::default()
+    };
+
+    let embed = compose_digest(&stats);
+    let role_grants = &embed.fields[4].value;
+
+    assert!(role_grants.chars().count() <= EMBED_FIELD_LENGTH);
+    assert!(role_grants.ends_with(" more"));
+}
@@ -0,0 +1,152 @@
+use serde::Deserialize;
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use twilight_http::error::ErrorType;
+
+use crate::{constants::LOG, errors::MyError, webhook_logging::webhook_log};
+
+static DISCORD_PAUSE: OnceLock<Mutex<DiscordPause>> = OnceLock::new();
+
+/// the parts of a 429 body that matter to us
+#[derive(Deserialize)]
+struct RateLimitBody {
+    #[serde(default)]
+    global: bool,
+    /// seconds
+    retry_after: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PauseCheck {
+    Open,
+    Paused(Duration),
+    /// the pause just ran out, this is only handed out once per pause
+    Resumed,
+}
+
+/// When Discord hands out a global rate limit every call counts against it, so all Discord
+/// traffic is paused until it's over instead of only the call that hit it.
+#[derive(Default)]
+pub struct DiscordPause {
+    paused_until: Option<Instant>,
+}
+
+impl DiscordPause {
+    pub fn check(&mut self, now: Instant) -> PauseCheck {
+        match self.paused_until {
+            Some(paused_until) if now < paused_until => PauseCheck::Paused(paused_until - now),
+            Some(_) => {
+                self.paused_until = None;
+                PauseCheck::Resumed
+            }
+            None => PauseCheck::Open,
+        }
+    }
+
+    /// pauses everything when the response is a global 429, returns whether it did
+    pub fn record_response(&mut self, status: u16, body: &[u8], now: Instant) -> bool {
+        if status != 429 {
+            return false;
+        }
+
+        match serde_json::from_slice::<RateLimitBody>(body) {
+            Ok(rate_limit) if rate_limit.global && rate_limit.retry_after.is_finite() => {
+                let paused_until = now + Duration::from_secs_f64(rate_limit.retry_after.max(0.0));
+                self.paused_until = Some(
+                    self.paused_until
+                        .map_or(paused_until, |current| current.max(paused_until)),
+                );
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.paused_until
+            .filter(|paused_until| now < *paused_until)
+            .map(|paused_until| paused_until - now)
+    }
+}
+
+pub fn discord_pause() -> &'static Mutex<DiscordPause> {
+    DISCORD_PAUSE.get_or_init(|| Mutex::new(DiscordPause::default()))
+}
+
+/// has to be awaited before every Discord call, fails right away while Discord is paused
+pub async fn ensure_discord_available() -> Result<(), MyError> {
+    let check = discord_pause().lock().unwrap().check(Instant::now());
+    match check {
+        PauseCheck::Open => Ok(()),
+        PauseCheck::Paused(_) => Err(MyError::Unavailable(
+            "Discord is rate limiting us right now, please try again in a little while",
+        )),
+        PauseCheck::Resumed => {
+            // boxed, the webhook goes through this check as well
+            Box::pin(webhook_log(
+                "the global Discord rate limit is over, Discord calls have resumed".to_owned(),
+                LOG::INFORMATIONAL,
+            ))
+            .await;
+            Ok(())
+        }
+    }
+}
+
+/// checks a failed Discord call for a global rate limit
+pub async fn record_discord_error(error: &twilight_http::Error) {
+    if let ErrorType::Response { body, status, .. } = error.kind() {
+        let paused =
+            discord_pause()
+                .lock()
+                .unwrap()
+                .record_response(status.get(), body, Instant::now());
+        // the webhook is paused along with everything else, so this only ends up printed
+        if paused {
+            Box::pin(webhook_log(
+                "Discord responded with a global rate limit, all Discord calls are paused"
+                    .to_owned(),
+                LOG::FAILURE,
+            ))
+            .await;
+        }
+    }
+}
+
+#[test]
+fn global_rate_limit_pauses_every_call_until_it_expires() {
+    let start = Instant::now();
+    let mut pause = DiscordPause::default();
+    let mut transport_calls = 0;
+
+    assert!(pause.record_response(429, br#"{"global": true, "retry_after": 2.5}"#, start));
+
+    // concurrent callers all check the pause before touching the transport
+    for offset in [0, 500, 1000, 2499] {
+        if pause.check(start + Duration::from_millis(offset)) == PauseCheck::Open {
+            transport_calls += 1;
+        }
+    }
+    assert_eq!(transport_calls, 0);
+
+    assert_eq!(
+        pause.check(start + Duration::from_millis(2500)),
+        PauseCheck::Resumed
+    );
+    assert_eq!(
+        pause.check(start + Duration::from_millis(2501)),
+        PauseCheck::Open
+    );
+}
+
+#[test]
+fn route_rate_limits_do_not_pause_discord() {
+    let now = Instant::now();
+    let mut pause = DiscordPause::default();
+
+    assert!(!pause.record_response(429, br#"{"global": false, "retry_after": 5}"#, now));
+    assert!(!pause.record_response(404, b"", now));
+    assert_eq!(pause.check(now), PauseCheck::Open);
+}
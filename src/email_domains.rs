@@ -0,0 +1,127 @@
+use crate::{constants::LOG, link_attempts::email_key, webhook_logging::webhook_log};
+
+/// the part of the email after its last `@`, lowercased the way emails are compared everywhere else
+pub fn email_domain(email: &str) -> Option<String> {
+    let (_, domain) = email.trim().rsplit_once('@')?;
+    let domain = domain.trim_end_matches('.').to_lowercase();
+
+    (!domain.is_empty()).then_some(domain)
+}
+
+/// parses `"example.com, Example.org"` into lowercase domains
+pub fn parse_domains(domains: &str) -> Vec<String> {
+    domains
+        .split(',')
+        .map(|domain| domain.trim().trim_end_matches('.').to_lowercase())
+        .filter(|domain| !domain.is_empty())
+        .collect()
+}
+
+/// the key a domain's links are counted under, domains are only ever stored hashed
+pub fn domain_key(domain: &str, salt: &str) -> String {
+    email_key(domain, salt)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DomainCheck {
+    /// the domain is below its cap, or isn't capped at all
+    Allowed,
+    /// an allowlisted domain, it's never capped and its links aren't counted
+    Allowlisted,
+    /// a denylisted domain, nothing can be linked with it
+    Denied,
+    /// the domain already has as many links as it's allowed
+    Capped,
+}
+
+/// How many accounts can be linked per email domain. Role farming rings register lots of accounts
+/// on the same throwaway domain, regular providers go on the allowlist.
+#[derive(Clone, Debug, Default)]
+pub struct DomainLimits {
+    /// links per domain, 0 turns the cap off
+    pub cap: i64,
+    pub allowlist: Vec<String>,
+    pub denylist: Vec<String>,
+}
+
+impl DomainLimits {
+    /// `linked` is how many accounts are linked with the domain already
+    pub fn check(&self, domain: &str, linked: i64) -> DomainCheck {
+        if self.allowlist.iter().any(|allowed| allowed == domain) {
+            DomainCheck::Allowlisted
+        } else if self.denylist.iter().any(|denied| denied == domain) {
+            DomainCheck::Denied
+        } else if self.cap > 0 && linked >= self.cap {
+            DomainCheck::Capped
+        } else {
+            DomainCheck::Allowed
+        }
+    }
+}
+
+pub async fn log_refused_domain(domain_key: &str, check: &DomainCheck, cap: i64) {
+    let reason = match check {
+        DomainCheck::Denied => "its domain is denylisted".to_owned(),
+        _ => format!("its domain already has {} linked accounts", cap),
+    };
+    webhook_log(
+        format!(
+            "refused linking an email (domain key {}) because {}",
+            &domain_key[..8],
+            reason
+        ),
+        LOG::FAILURE,
+    )
+    .await;
+}
+
+#[cfg(test)]
+fn limits(cap: i64) -> DomainLimits {
+    DomainLimits {
+        cap,
+        allowlist: parse_domains("gmail.com, Outlook.com"),
+        denylist: parse_domains("mailinator.com"),
+    }
+}
+
+#[test]
+fn domains_are_taken_from_the_email() {
+    assert_eq!(
+        email_domain(" Player@Throwaway.Example "),
+        Some("throwaway.example".to_owned())
+    );
+    assert_eq!(
+        email_domain("odd\"@\"name@example.com."),
+        Some("example.com".to_owned())
+    );
+    assert_eq!(email_domain("player"), None);
+    assert_eq!(email_domain("player@"), None);
+
+    // the key doesn't depend on how the domain was spelled, and doesn't contain it
+    let key = domain_key("throwaway.example", "salt");
+    assert_eq!(domain_key("Throwaway.Example", "salt"), key);
+    assert!(!key.contains("throwaway"));
+}
+
+#[test]
+fn domains_are_capped() {
+    let capped = limits(3);
+    assert_eq!(capped.check("throwaway.example", 2), DomainCheck::Allowed);
+    assert_eq!(capped.check("throwaway.example", 3), DomainCheck::Capped);
+    assert_eq!(capped.check("mailinator.com", 0), DomainCheck::Denied);
+
+    // a cap of 0 only leaves the denylist
+    let uncapped = limits(0);
+    assert_eq!(
+        uncapped.check("throwaway.example", 1_000),
+        DomainCheck::Allowed
+    );
+    assert_eq!(uncapped.check("mailinator.com", 0), DomainCheck::Denied);
+}
+
+#[test]
+fn allowlisted_domains_bypass_the_cap() {
+    let limits = limits(1);
+    assert_eq!(limits.check("outlook.com", 1_000), DomainCheck::Allowlisted);
+    assert_eq!(limits.check("gmail.com", 0), DomainCheck::Allowlisted);
+}
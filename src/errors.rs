@@ -10,13 +10,18 @@ use tokio_postgres::error::Error as PGError;
 
 use crate::{
     constants::{ErrorLogType, LOG},
-    models::MessageResponse,
+    evaluation::ValidationIssue,
+    models::{ErrorBody, ErrorResponse},
     webhook_logging::webhook_log,
 };
 
+/// the admin routes' answer when they're asked about a discord id nobody linked
+pub const DISCORD_ID_NOT_LINKED: &str = "Nobody is linked with this discord id";
+
 #[derive(Display, Debug)]
 pub enum MyError {
-    NotFound,
+    #[display(fmt = "Not Found: {}", _0)]
+    NotFound(&'static str),
     PGError(PGError),
     PGMError(PGMError),
     PoolError(PoolError),
@@ -24,24 +29,124 @@ pub enum MyError {
     InternalError(&'static str),
     #[display(fmt = "Bad Request: {}", _0)]
     BadRequest(&'static str),
+    #[display(fmt = "Unauthorized: {}", _0)]
+    Unauthorized(&'static str),
+    #[display(fmt = "Forbidden: {}", _0)]
+    Forbidden(&'static str),
+    #[display(fmt = "Conflict: {}", _0)]
+    Conflict(&'static str),
     #[display(fmt = "Gateway Timeout: {}", _0)]
     Timeout(&'static str),
+    #[display(fmt = "Service Unavailable: {}", _0)]
+    Unavailable(&'static str),
+    #[display(fmt = "Too Many Requests: {}", _0)]
+    TooManyRequests(&'static str),
+    /// another error with a code clients can tell it apart by, see `MyError::with_code`
+    #[display(fmt = "{}", _1)]
+    Coded(&'static str, Box<MyError>),
+    /// another error clients should retry after some seconds, see `MyError::with_retry_after`
+    #[display(fmt = "{}", _1)]
+    RetryAfter(u64, Box<MyError>),
+    /// another error that lists what's wrong with the payload, see `MyError::with_issues`
+    #[display(fmt = "{}", _1)]
+    Invalid(Vec<ValidationIssue>, Box<MyError>),
 }
 impl std::error::Error for MyError {}
 
+impl MyError {
+    /// gives the error a code for the response body, e.g. `ALREADY_LINKED`, so clients don't have
+    /// to match its message
+    pub fn with_code(self, code: &'static str) -> MyError {
+        MyError::Coded(code, Box::new(self))
+    }
+
+    /// answers the error with a `Retry-After` header of `seconds`
+    pub fn with_retry_after(self, seconds: u64) -> MyError {
+        MyError::RetryAfter(seconds, Box::new(self))
+    }
+
+    /// lists the payload's problems in the response body's `issues`
+    pub fn with_issues(self, issues: Vec<ValidationIssue>) -> MyError {
+        MyError::Invalid(issues, Box::new(self))
+    }
+
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            MyError::RetryAfter(seconds, _) => Some(*seconds),
+            MyError::Coded(_, error) | MyError::Invalid(_, error) => error.retry_after(),
+            _ => None,
+        }
+    }
+
+    pub fn issues(&self) -> &[ValidationIssue] {
+        match self {
+            MyError::Invalid(issues, _) => issues,
+            MyError::Coded(_, error) | MyError::RetryAfter(_, error) => error.issues(),
+            _ => &[],
+        }
+    }
+
+    /// the error without the code it was given
+    pub fn kind(&self) -> &MyError {
+        match self {
+            MyError::Coded(_, error)
+            | MyError::RetryAfter(_, error)
+            | MyError::Invalid(_, error) => error.kind(),
+            error => error,
+        }
+    }
+
+    /// The code clients get in the response body, errors without one get their kind's. It's the
+    /// only code an error has, the recent errors and the digest show the same one.
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            MyError::Coded(code, _) => code,
+            MyError::RetryAfter(_, error) | MyError::Invalid(_, error) => error.error_code(),
+            MyError::NotFound(_) => "NOT_FOUND",
+            MyError::PGError(_) | MyError::PGMError(_) | MyError::PoolError(_) => "DATABASE_ERROR",
+            MyError::InternalError(_) => "INTERNAL_ERROR",
+            MyError::BadRequest(_) => "BAD_REQUEST",
+            MyError::Unauthorized(_) => "UNAUTHORIZED",
+            MyError::Forbidden(_) => "FORBIDDEN",
+            MyError::Conflict(_) => "CONFLICT",
+            MyError::Timeout(_) => "TIMEOUT",
+            MyError::Unavailable(_) => "UNAVAILABLE",
+            MyError::TooManyRequests(_) => "TOO_MANY_REQUESTS",
+        }
+    }
+}
+
 impl ResponseError for MyError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponseBuilder::new(self.status_code())
+        let mut response = HttpResponseBuilder::new(self.status_code());
+        if let Some(seconds) = self.retry_after() {
+            response.insert_header((header::RETRY_AFTER, seconds));
+        }
+        response
             .insert_header(header::ContentType::json())
-            .json(MessageResponse {
+            .json(ErrorResponse {
                 message: self.to_string(),
+                error: ErrorBody {
+                    code: self.error_code(),
+                    message: self.to_string(),
+                    issues: self.issues().to_vec(),
+                },
             })
     }
 
     fn status_code(&self) -> StatusCode {
         match *self {
+            MyError::Coded(_, ref error)
+            | MyError::RetryAfter(_, ref error)
+            | MyError::Invalid(_, ref error) => error.status_code(),
+            MyError::NotFound(_) => StatusCode::NOT_FOUND,
             MyError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            MyError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            MyError::Forbidden(_) => StatusCode::FORBIDDEN,
+            MyError::Conflict(_) => StatusCode::CONFLICT,
             MyError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            MyError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            MyError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -53,7 +158,7 @@ pub trait ConvertResultErrorToMyError<T> {
 
 #[async_trait]
 pub trait LogMyError<T> {
-    async fn make_log(self, error_type: ErrorLogType) -> Result<T, MyError>;
+    async fn make_log(self, error_type: ErrorLogType<'_>) -> Result<T, MyError>;
 }
 
 pub trait InternalErrorConverter<T> {
@@ -74,14 +179,12 @@ impl<T, E: std::fmt::Debug> ConvertResultErrorToMyError<T> for Result<T, E> {
 
 #[async_trait]
 impl<T: std::marker::Send> LogMyError<T> for Result<T, MyError> {
-    async fn make_log(self, error_type: ErrorLogType) -> Result<T, MyError> {
+    async fn make_log(self, error_type: ErrorLogType<'_>) -> Result<T, MyError> {
         match self {
             Ok(value) => Ok(value),
             Err(error) => {
                 let error_content = match error_type {
-                    ErrorLogType::USER(token) => {
-                        format!("Error with a user\n\ntoken: {}\n\n{}", token, error)
-                    }
+                    ErrorLogType::USER(user) => user.log_message(&error),
                     ErrorLogType::INTERNAL => error.to_string(),
                 };
                 webhook_log(error_content, LOG::FAILURE).await;
@@ -99,3 +202,136 @@ impl<T, E: std::fmt::Debug> InternalErrorConverter<T> for Result<T, E> {
         }
     }
 }
+
+#[test]
+fn error_bodies_carry_a_stable_code() {
+    let body = |error: MyError| {
+        let response = error.error_response();
+        let status = response.status();
+        let bytes = actix_web::rt::System::new()
+            .block_on(actix_web::body::to_bytes(response.into_body()))
+            .unwrap();
+        (
+            status,
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap(),
+        )
+    };
+
+    for (error, status, code) in [
+        (
+            MyError::NotFound("gone"),
+            StatusCode::NOT_FOUND,
+            "NOT_FOUND",
+        ),
+        (
+            MyError::PGMError(PGMError::ColumnNotFound),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "DATABASE_ERROR",
+        ),
+        (
+            MyError::InternalError("broke"),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "INTERNAL_ERROR",
+        ),
+        (
+            MyError::BadRequest("bad"),
+            StatusCode::BAD_REQUEST,
+            "BAD_REQUEST",
+        ),
+        (
+            MyError::Unauthorized("who"),
+            StatusCode::UNAUTHORIZED,
+            "UNAUTHORIZED",
+        ),
+        (MyError::Forbidden("no"), StatusCode::FORBIDDEN, "FORBIDDEN"),
+        (MyError::Conflict("taken"), StatusCode::CONFLICT, "CONFLICT"),
+        (
+            MyError::Timeout("slow"),
+            StatusCode::GATEWAY_TIMEOUT,
+            "TIMEOUT",
+        ),
+        (
+            MyError::Unavailable("down"),
+            StatusCode::SERVICE_UNAVAILABLE,
+            "UNAVAILABLE",
+        ),
+        (
+            MyError::TooManyRequests("slow down"),
+            StatusCode::TOO_MANY_REQUESTS,
+            "TOO_MANY_REQUESTS",
+        ),
+    ] {
+        let message = error.to_string();
+        let (response_status, json) = body(error);
+        assert_eq!(response_status, status, "{}", code);
+        assert_eq!(json["error"]["code"], code);
+        assert_eq!(json["error"]["message"], message);
+        assert_eq!(json["message"], message);
+    }
+
+    // a code replaces the kind's code but keeps its status and message
+    let coded = MyError::NotFound("There's no account linked with these credentials")
+        .with_code("ACCOUNT_NOT_LINKED");
+    assert!(matches!(coded.kind(), MyError::NotFound(_)));
+    assert_eq!(coded.error_code(), "ACCOUNT_NOT_LINKED");
+    let (status, json) = body(coded);
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(json["error"]["code"], "ACCOUNT_NOT_LINKED");
+    assert_eq!(
+        json["error"]["message"],
+        "Not Found: There's no account linked with these credentials"
+    );
+}
+
+#[test]
+fn retry_after_is_answered_as_a_header() {
+    let error = MyError::Unavailable("failing over")
+        .with_code("DATABASE_READ_ONLY")
+        .with_retry_after(30);
+    assert_eq!(error.retry_after(), Some(30));
+    assert_eq!(error.error_code(), "DATABASE_READ_ONLY");
+    assert!(matches!(error.kind(), MyError::Unavailable(_)));
+
+    let response = error.error_response();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "30");
+    assert!(MyError::Unavailable("down")
+        .error_response()
+        .headers()
+        .get(header::RETRY_AFTER)
+        .is_none());
+}
+
+#[test]
+fn issues_are_listed_in_the_error_body() {
+    use crate::evaluation::IssueCode;
+
+    let error = MyError::BadRequest("The progress values aren't valid")
+        .with_code("INVALID_PROGRESS")
+        .with_issues(vec![ValidationIssue::new(
+            "dino_rank",
+            IssueCode::OutOfRange,
+            "must not be negative",
+        )
+        .with_limit(0.0)]);
+    assert_eq!(error.error_code(), "INVALID_PROGRESS");
+    assert_eq!(error.issues().len(), 1);
+
+    let response = error.error_response();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let bytes = actix_web::rt::System::new()
+        .block_on(actix_web::body::to_bytes(response.into_body()))
+        .unwrap();
+    let body = serde_json::from_slice::<serde_json::Value>(&bytes).unwrap();
+    assert_eq!(body["error"]["issues"][0]["code"], "out_of_range");
+    assert_eq!(body["error"]["issues"][0]["field_path"], "dino_rank");
+
+    // errors without issues keep their old body
+    let bytes = actix_web::rt::System::new()
+        .block_on(actix_web::body::to_bytes(
+            MyError::BadRequest("bad").error_response().into_body(),
+        ))
+        .unwrap();
+    let body = serde_json::from_slice::<serde_json::Value>(&bytes).unwrap();
+    assert!(body["error"].get("issues").is_none());
+}
@@ -0,0 +1,807 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::SystemTime;
+
+use crate::{
+    constants::MetabitRequirements,
+    fields::{progress_maxima, Monotonicity, ProgressField, ProgressMaxima, DEPRECATED_FIELDS},
+    models::{UpdateUserData, UserData},
+    role_handling::{compute_earned_roles, EarnedRole},
+    role_rules::RoleRule,
+};
+
+/// the suspicion score at which a payload gets flagged for a moderator to review
+pub const FLAG_THRESHOLD: u32 = 3;
+
+/// the suspicion weight of a progress value going backwards
+const MONOTONIC_WEIGHT: u32 = 2;
+/// the suspicion weight of metabits growing more than `METABIT_JUMP_FACTOR` times in a single sync
+const METABIT_JUMP_WEIGHT: u32 = 2;
+const METABIT_JUMP_FACTOR: f64 = 1000.0;
+/// the suspicion weight of a singularity speedrun that's faster than anyone has managed legitimately
+const SPEEDRUN_WEIGHT: u32 = 3;
+const FASTEST_PLAUSIBLE_SPEEDRUN: f64 = 30.0;
+
+/// What's wrong with a field. The codes are part of the public API, tools highlight fields by
+/// them, so they're never renamed and new ones are only ever added.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueCode {
+    /// the payload has a field the endpoint doesn't know, it's ignored by syncs
+    UnknownField,
+    TypeMismatch,
+    /// the value is past the field's bounds
+    OutOfRange,
+    /// the value went the wrong way compared to the stored one
+    MonotonicDecrease,
+    /// the value can't be stored the way it was sent
+    InvariantViolation,
+    MissingRequired,
+}
+
+impl IssueCode {
+    pub const ALL: [IssueCode; 6] = [
+        IssueCode::UnknownField,
+        IssueCode::TypeMismatch,
+        IssueCode::OutOfRange,
+        IssueCode::MonotonicDecrease,
+        IssueCode::InvariantViolation,
+        IssueCode::MissingRequired,
+    ];
+}
+
+/// a single problem with a payload, as /user/validate and the 400s of the real endpoints list them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ValidationIssue {
+    /// the field's name in the payload, empty when the problem couldn't be tied to a field
+    pub field_path: String,
+    pub code: IssueCode,
+    pub detail: String,
+    /// the bound that was crossed, e.g. the lowest value a field takes or the stored value it can't
+    /// go back from
+    pub limit: Option<f64>,
+}
+
+impl ValidationIssue {
+    pub fn new(field_path: &str, code: IssueCode, detail: &str) -> Self {
+        ValidationIssue {
+            field_path: field_path.to_owned(),
+            code,
+            detail: detail.to_owned(),
+            limit: None,
+        }
+    }
+
+    pub fn with_limit(self, limit: f64) -> Self {
+        ValidationIssue {
+            limit: Some(limit),
+            ..self
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct MonotonicViolation {
+    pub field: &'static str,
+    pub current: f64,
+    pub received: f64,
+}
+
+impl MonotonicViolation {
+    pub fn issue(&self) -> ValidationIssue {
+        let detail = match ProgressField::from_name(self.field).map(|field| field.monotonicity()) {
+            Some(Monotonicity::NonIncreasing) => "must not get slower",
+            _ => "must not go backwards",
+        };
+
+        ValidationIssue::new(self.field, IssueCode::MonotonicDecrease, detail)
+            .with_limit(self.current)
+    }
+}
+
+/// the full result of running a payload through validation, the monotonic checks,
+/// the suspicion heuristics, and the role computation against some "current" state
+#[derive(Serialize, Debug)]
+pub struct EvaluationReport {
+    pub validation_issues: Vec<ValidationIssue>,
+    pub monotonic_violations: Vec<MonotonicViolation>,
+    pub suspicion_score: u32,
+    pub suspicion_reasons: Vec<String>,
+    pub flagged: bool,
+    pub earned_roles: Vec<EarnedRole>,
+}
+
+/// Evaluates `payload` as if it was synced on top of `current`, which can be the live row or any
+/// historical snapshot of it. Nothing here reads from or writes to the database.
+pub fn evaluate_payload(
+    current: &UserData,
+    payload: &UpdateUserData,
+    beta_tester: bool,
+    role_rules: &[RoleRule],
+) -> EvaluationReport {
+    let validation_issues = validate_payload(payload);
+    let monotonic_violations = check_monotonic_fields(current, payload);
+
+    let mut suspicion_score = 0;
+    let mut suspicion_reasons = Vec::new();
+
+    for violation in &monotonic_violations {
+        suspicion_score += MONOTONIC_WEIGHT;
+        suspicion_reasons.push(format!(
+            "{} went backwards from {} to {}",
+            violation.field, violation.current, violation.received
+        ));
+    }
+
+    if !ProgressField::Metabits.is_deprecated()
+        && current.metabits > 0
+        && payload.metabits >= MetabitRequirements::RealityExpert as i64 as f64
+        && payload.metabits / current.metabits as f64 > METABIT_JUMP_FACTOR
+    {
+        suspicion_score += METABIT_JUMP_WEIGHT;
+        suspicion_reasons.push(format!(
+            "metabits grew from {} to {} in a single sync",
+            current.metabits, payload.metabits
+        ));
+    }
+
+    if let Some(speedrun_time) = payload
+        .singularity_speedrun_time
+        .filter(|_| !ProgressField::SingularitySpeedrunTime.is_deprecated())
+    {
+        if speedrun_time > 0.0 && speedrun_time < FASTEST_PLAUSIBLE_SPEEDRUN {
+            suspicion_score += SPEEDRUN_WEIGHT;
+            suspicion_reasons.push(format!(
+                "singularity speedrun time of {} seconds is implausibly fast",
+                speedrun_time
+            ));
+        }
+    }
+
+    let earned_roles = if validation_issues.is_empty() {
+        // promo roles depend on when the sync happens rather than on the progress, so they're left out
+        compute_earned_roles(
+            &apply_payload(current, payload, beta_tester),
+            role_rules,
+            &[],
+            SystemTime::now(),
+        )
+    } else {
+        Vec::new()
+    };
+
+    EvaluationReport {
+        flagged: suspicion_score >= FLAG_THRESHOLD,
+        validation_issues,
+        monotonic_violations,
+        suspicion_score,
+        suspicion_reasons,
+        earned_roles,
+    }
+}
+
+/// checks the values of a payload on their own, without any knowledge of the stored state
+pub fn validate_payload(payload: &UpdateUserData) -> Vec<ValidationIssue> {
+    validate_payload_within(payload, progress_maxima())
+}
+
+/// `validate_payload` with the given maxima instead of the configured ones
+pub fn validate_payload_within(
+    payload: &UpdateUserData,
+    maxima: &ProgressMaxima,
+) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+
+    for field in ProgressField::ALL {
+        let (value, bounds) = match (field.read_update(payload), field.bounds()) {
+            (Some(value), Some(bounds)) => (value, bounds),
+            _ => continue,
+        };
+
+        let issue = if !value.is_finite() {
+            // an exclusive bound's reason already asks for a real, positive number
+            if bounds.min_exclusive {
+                ValidationIssue::new(field.name(), IssueCode::OutOfRange, bounds.reason)
+                    .with_limit(bounds.min)
+            } else {
+                ValidationIssue::new(
+                    field.name(),
+                    IssueCode::OutOfRange,
+                    "must be a finite number",
+                )
+            }
+        } else if value < bounds.min || (bounds.min_exclusive && value == bounds.min) {
+            ValidationIssue::new(field.name(), IssueCode::OutOfRange, bounds.reason)
+                .with_limit(bounds.min)
+        } else if let Some(max) = maxima.get(field).filter(|max| value > *max) {
+            ValidationIssue::new(
+                field.name(),
+                IssueCode::OutOfRange,
+                "must not be more than the configured maximum",
+            )
+            .with_limit(max)
+        } else {
+            continue;
+        };
+
+        issues.push(issue);
+    }
+    issues.extend(check_invariants(payload));
+
+    issues
+}
+
+/// values that are in bounds but would change when they're written to the row
+fn check_invariants(payload: &UpdateUserData) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+
+    // `i64::MAX as f64` rounds up to 2^63, which is already past the column
+    if payload.metabits.is_finite() && payload.metabits >= i64::MAX as f64 {
+        issues.push(
+            ValidationIssue::new(
+                ProgressField::Metabits.name(),
+                IssueCode::InvariantViolation,
+                "must fit the 64-bit integer it's stored as",
+            )
+            .with_limit(i64::MAX as f64),
+        );
+    }
+
+    issues
+}
+
+/// Checks the payload's JSON before it's read, for /user/validate. Unknown fields are reported even
+/// though syncs ignore them, a misspelled field would otherwise go unnoticed.
+pub fn check_payload_json(payload: &Value) -> Vec<ValidationIssue> {
+    let fields = match payload.as_object() {
+        Some(fields) => fields,
+        None => {
+            return vec![ValidationIssue::new(
+                "",
+                IssueCode::TypeMismatch,
+                "the payload must be an object",
+            )]
+        }
+    };
+
+    let mut issues = Vec::new();
+    for name in fields.keys() {
+        if ProgressField::from_name(name).is_none() {
+            issues.push(ValidationIssue::new(
+                name,
+                IssueCode::UnknownField,
+                "isn't a progress field",
+            ));
+        }
+    }
+    for field in ProgressField::ALL {
+        match fields.get(field.name()) {
+            None if field.is_optional() => {}
+            None => issues.push(ValidationIssue::new(
+                field.name(),
+                IssueCode::MissingRequired,
+                "is required",
+            )),
+            Some(value) => {
+                if let Err(error) = field.check_json_type(value) {
+                    issues.push(ValidationIssue::new(
+                        field.name(),
+                        IssueCode::TypeMismatch,
+                        &error.to_string(),
+                    ));
+                }
+            }
+        }
+    }
+
+    issues
+}
+
+/// The issue a body that couldn't be read is answered with. serde only names the field for missing
+/// and unknown fields, a type mismatch is reported with the position it gives instead.
+pub fn json_error_issue(error: &serde_json::Error) -> ValidationIssue {
+    let detail = error.to_string();
+    let named_field = detail.split('`').nth(1).unwrap_or_default();
+
+    if detail.starts_with("missing field") {
+        ValidationIssue::new(named_field, IssueCode::MissingRequired, &detail)
+    } else if detail.starts_with("unknown field") {
+        ValidationIssue::new(named_field, IssueCode::UnknownField, &detail)
+    } else {
+        ValidationIssue::new("", IssueCode::TypeMismatch, &detail)
+    }
+}
+
+/// lists every progress value in the payload that moved the wrong way compared to `current`,
+/// deprecated fields can move either way
+pub fn check_monotonic_fields(
+    current: &UserData,
+    payload: &UpdateUserData,
+) -> Vec<MonotonicViolation> {
+    monotonic_violations(current, payload, &DEPRECATED_FIELDS)
+}
+
+fn monotonic_violations(
+    current: &UserData,
+    payload: &UpdateUserData,
+    deprecated: &[ProgressField],
+) -> Vec<MonotonicViolation> {
+    let mut violations = Vec::new();
+
+    for field in ProgressField::ALL {
+        if deprecated.contains(&field) {
+            continue;
+        }
+        let (current_value, received_value) =
+            match (field.read_userdata(current), field.read_update(payload)) {
+                (Some(current_value), Some(received_value)) => (current_value, received_value),
+                _ => continue,
+            };
+
+        let violated = match field.monotonicity() {
+            Monotonicity::NonDecreasing => received_value < current_value,
+            Monotonicity::NonIncreasing => received_value > current_value,
+        };
+        let reset = field.reset_by().map_or(false, |reset_by| {
+            match (
+                reset_by.read_userdata(current),
+                reset_by.read_update(payload),
+            ) {
+                (Some(current_value), Some(received_value)) => received_value > current_value,
+                _ => false,
+            }
+        });
+        if violated && !reset {
+            violations.push(MonotonicViolation {
+                field: field.name(),
+                current: current_value,
+                received: received_value,
+            });
+        }
+    }
+
+    violations
+}
+
+/// a payload with the fields that couldn't be written taken out
+pub struct PartialPayload {
+    pub payload: UpdateUserData,
+    pub skipped: Vec<ValidationIssue>,
+}
+
+/// Takes the fields that fail validation or move the wrong way out of the payload, they keep the
+/// value stored in `current`. Skipping a field can take away the reason another field was allowed
+/// to go backwards, so the check is repeated until nothing else needs to be skipped. `None` when
+/// the payload isn't consistent even with every offending field skipped.
+pub fn skip_invalid_fields(current: &UserData, payload: &UpdateUserData) -> Option<PartialPayload> {
+    let mut skipped = validate_payload(payload);
+
+    // every pass skips at least one more field
+    for _ in 0..=ProgressField::ALL.len() {
+        let filtered = keep_current_values(current, payload, &skipped);
+        let violations = check_monotonic_fields(current, &filtered);
+        if violations.is_empty() {
+            return validate_payload(&filtered)
+                .is_empty()
+                .then_some(PartialPayload {
+                    payload: filtered,
+                    skipped,
+                });
+        }
+
+        skipped.extend(violations.iter().map(MonotonicViolation::issue));
+    }
+
+    None
+}
+
+/// the payload with the skipped fields set to their values in `current`
+fn keep_current_values(
+    current: &UserData,
+    payload: &UpdateUserData,
+    skipped: &[ValidationIssue],
+) -> UpdateUserData {
+    let mut filtered = UpdateUserData::default();
+    for field in ProgressField::ALL {
+        let value = if skipped.iter().any(|issue| issue.field_path == field.name()) {
+            field.read_userdata(current)
+        } else {
+            field.read_update(payload)
+        };
+        if let Some(value) = value {
+            field.write_update(&mut filtered, value);
+        }
+    }
+
+    filtered
+}
+
+/// the state the row would be in after `payload` is written on top of `current`
+pub fn apply_payload(current: &UserData, payload: &UpdateUserData, beta_tester: bool) -> UserData {
+    UserData {
+        discord_id: current.discord_id.clone(),
+        token: current.token.clone(),
+        beta_tester,
+        metabits: payload.metabits as i64,
+        dino_rank: payload.dino_rank,
+        prestige_rank: payload.prestige_rank,
+        beyond_rank: payload.beyond_rank,
+        singularity_speedrun_time: payload.singularity_speedrun_time,
+        all_sharks_obtained: payload.all_sharks_obtained,
+        all_hidden_achievements_obtained: payload.all_hidden_achievements_obtained,
+        edited_timestamp: SystemTime::now(),
+    }
+}
+
+#[cfg(test)]
+fn snapshot_fixture() -> UserData {
+    UserData {
+        discord_id: "123456789012345678".to_owned(),
+        token: "token".to_owned(),
+        beta_tester: false,
+        metabits: 5_000_000,
+        dino_rank: 120,
+        prestige_rank: 4,
+        beyond_rank: 10,
+        singularity_speedrun_time: Some(400.0),
+        all_sharks_obtained: false,
+        all_hidden_achievements_obtained: false,
+        edited_timestamp: SystemTime::UNIX_EPOCH,
+    }
+}
+
+#[test]
+fn replayed_flagged_payload_matches_original_decision() {
+    // the payload that was flagged when it was originally synced on top of the snapshot
+    let flagged_payload = UpdateUserData {
+        metabits: 100_000_000_000_000.0,
+        dino_rank: 60,
+        prestige_rank: 4,
+        beyond_rank: 15,
+        singularity_speedrun_time: Some(12.0),
+        all_sharks_obtained: true,
+        all_hidden_achievements_obtained: false,
+    };
+
+    let report = evaluate_payload(&snapshot_fixture(), &flagged_payload, false, &[]);
+
+    assert!(report.flagged);
+    assert!(report.validation_issues.is_empty());
+    assert_eq!(
+        report.monotonic_violations,
+        vec![MonotonicViolation {
+            field: "dino_rank",
+            current: 120.0,
+            received: 60.0,
+        }]
+    );
+    assert_eq!(
+        report.suspicion_score,
+        MONOTONIC_WEIGHT + METABIT_JUMP_WEIGHT + SPEEDRUN_WEIGHT
+    );
+    assert_eq!(
+        report
+            .earned_roles
+            .iter()
+            .map(|role| role.name.as_ref())
+            .collect::<Vec<_>>(),
+        vec![
+            "Reality Legend",
+            "Progressive Paleontologist",
+            "Planetary Explorer",
+            "Sonic Speedster of Simulations",
+            "Shark Collector",
+        ]
+    );
+}
+
+#[test]
+fn ordinary_progress_is_not_flagged() {
+    let payload = UpdateUserData {
+        metabits: 7_500_000.0,
+        dino_rank: 130,
+        prestige_rank: 4,
+        beyond_rank: 11,
+        singularity_speedrun_time: Some(290.0),
+        all_sharks_obtained: false,
+        all_hidden_achievements_obtained: false,
+    };
+
+    let report = evaluate_payload(&snapshot_fixture(), &payload, false, &[]);
+
+    assert!(!report.flagged);
+    assert_eq!(report.suspicion_score, 0);
+    assert!(report.monotonic_violations.is_empty());
+}
+
+#[test]
+fn invalid_payload_reports_issues_and_earns_nothing() {
+    let payload = UpdateUserData {
+        metabits: f64::NAN,
+        dino_rank: -1,
+        ..UpdateUserData::default()
+    };
+
+    let report = evaluate_payload(&snapshot_fixture(), &payload, false, &[]);
+
+    assert_eq!(
+        report.validation_issues,
+        vec![
+            ValidationIssue::new("metabits", IssueCode::OutOfRange, "must be a finite number"),
+            ValidationIssue::new("dino_rank", IssueCode::OutOfRange, "must not be negative")
+                .with_limit(0.0),
+        ]
+    );
+    assert!(report.earned_roles.is_empty());
+}
+
+#[test]
+fn partial_sync_skips_only_the_broken_fields() {
+    let payload = UpdateUserData {
+        metabits: 7_500_000.0,
+        dino_rank: -3,
+        prestige_rank: 5,
+        beyond_rank: 9,
+        singularity_speedrun_time: Some(290.0),
+        all_sharks_obtained: true,
+        all_hidden_achievements_obtained: false,
+    };
+
+    // without partial the whole payload is rejected
+    assert!(!validate_payload(&payload).is_empty());
+
+    let partial = skip_invalid_fields(&snapshot_fixture(), &payload).unwrap();
+
+    assert_eq!(
+        partial.skipped,
+        vec![
+            ValidationIssue::new("dino_rank", IssueCode::OutOfRange, "must not be negative")
+                .with_limit(0.0),
+            ValidationIssue::new(
+                "beyond_rank",
+                IssueCode::MonotonicDecrease,
+                "must not go backwards"
+            )
+            .with_limit(10.0),
+        ]
+    );
+    // the skipped fields keep their stored values, everything else is applied
+    assert_eq!(partial.payload.dino_rank, 120);
+    assert_eq!(partial.payload.beyond_rank, 10);
+    assert_eq!(partial.payload.metabits, 7_500_000.0);
+    assert_eq!(partial.payload.prestige_rank, 5);
+    assert_eq!(partial.payload.singularity_speedrun_time, Some(290.0));
+    assert!(partial.payload.all_sharks_obtained);
+}
+
+#[test]
+fn partial_sync_keeps_resets_consistent() {
+    // prestiging resets the dino rank, that isn't going backwards
+    let prestiged = UpdateUserData {
+        metabits: 5_000_000.0,
+        dino_rank: 3,
+        prestige_rank: 5,
+        beyond_rank: 10,
+        singularity_speedrun_time: Some(400.0),
+        ..UpdateUserData::default()
+    };
+    assert!(skip_invalid_fields(&snapshot_fixture(), &prestiged)
+        .unwrap()
+        .skipped
+        .is_empty());
+
+    // but once the prestige has to be skipped the reset isn't explained anymore
+    let corrupted_prestige = UpdateUserData {
+        prestige_rank: -1,
+        ..prestiged
+    };
+    let partial = skip_invalid_fields(&snapshot_fixture(), &corrupted_prestige).unwrap();
+
+    assert_eq!(
+        partial
+            .skipped
+            .iter()
+            .map(|issue| issue.field_path.as_str())
+            .collect::<Vec<_>>(),
+        vec!["prestige_rank", "dino_rank"]
+    );
+    assert_eq!(partial.payload.prestige_rank, 4);
+    assert_eq!(partial.payload.dino_rank, 120);
+}
+
+#[test]
+fn issue_codes_are_serialized_by_their_public_names() {
+    for code in IssueCode::ALL {
+        // a new code has to be added here, and the names here must never change
+        let name = match code {
+            IssueCode::UnknownField => "unknown_field",
+            IssueCode::TypeMismatch => "type_mismatch",
+            IssueCode::OutOfRange => "out_of_range",
+            IssueCode::MonotonicDecrease => "monotonic_decrease",
+            IssueCode::InvariantViolation => "invariant_violation",
+            IssueCode::MissingRequired => "missing_required",
+        };
+        assert_eq!(serde_json::to_value(code).unwrap(), name);
+    }
+
+    assert_eq!(
+        serde_json::to_value(
+            ValidationIssue::new("dino_rank", IssueCode::OutOfRange, "must not be negative")
+                .with_limit(0.0)
+        )
+        .unwrap(),
+        serde_json::json!({
+            "field_path": "dino_rank",
+            "code": "out_of_range",
+            "detail": "must not be negative",
+            "limit": 0.0,
+        })
+    );
+    // issues without a limit still have the key, so every issue has the same shape
+    assert_eq!(
+        serde_json::to_value(ValidationIssue::new(
+            "",
+            IssueCode::TypeMismatch,
+            "the payload must be an object"
+        ))
+        .unwrap()["limit"],
+        Value::Null
+    );
+}
+
+#[test]
+fn payload_json_is_checked_field_by_field() {
+    let payload = serde_json::json!({
+        "metabits": "12",
+        "dino_rank": "high",
+        "prestige_rank": 1,
+        "beyond_rank": 1,
+        "all_sharks_obtained": false,
+        "metabit": 12,
+    });
+
+    assert_eq!(
+        check_payload_json(&payload)
+            .iter()
+            .map(|issue| (issue.field_path.as_str(), issue.code))
+            .collect::<Vec<_>>(),
+        vec![
+            ("metabit", IssueCode::UnknownField),
+            ("dino_rank", IssueCode::TypeMismatch),
+            (
+                "all_hidden_achievements_obtained",
+                IssueCode::MissingRequired
+            ),
+        ]
+    );
+    assert_eq!(
+        check_payload_json(&serde_json::json!([]))[0].code,
+        IssueCode::TypeMismatch
+    );
+}
+
+#[test]
+fn unreadable_bodies_name_the_field_serde_names() {
+    let issue =
+        |json: &str| json_error_issue(&serde_json::from_str::<UpdateUserData>(json).err().unwrap());
+
+    let missing = issue("{\"metabits\": 1}");
+    assert_eq!(missing.code, IssueCode::MissingRequired);
+    assert_eq!(missing.field_path, "dino_rank");
+
+    let mismatched = issue("{\"metabits\": 1, \"dino_rank\": \"high\"}");
+    assert_eq!(mismatched.code, IssueCode::TypeMismatch);
+    assert_eq!(mismatched.field_path, "");
+    assert!(
+        mismatched.detail.contains("line 1"),
+        "{}",
+        mismatched.detail
+    );
+}
+
+#[test]
+fn unstorable_metabits_break_the_invariant() {
+    let payload = UpdateUserData {
+        metabits: 1e19,
+        ..UpdateUserData::default()
+    };
+
+    assert_eq!(
+        validate_payload(&payload),
+        vec![ValidationIssue::new(
+            "metabits",
+            IssueCode::InvariantViolation,
+            "must fit the 64-bit integer it's stored as"
+        )
+        .with_limit(i64::MAX as f64)]
+    );
+    assert!(validate_payload(&UpdateUserData {
+        metabits: 9.2e18,
+        ..UpdateUserData::default()
+    })
+    .is_empty());
+}
+
+#[test]
+fn values_past_the_maxima_are_out_of_range() {
+    let maxima = ProgressMaxima::parse("metabits:1000,dino_rank:50,singularity_speedrun_time:3600");
+    let at_the_maxima = UpdateUserData {
+        metabits: 1000.0,
+        dino_rank: 50,
+        singularity_speedrun_time: Some(3600.0),
+        ..UpdateUserData::default()
+    };
+    assert!(validate_payload_within(&at_the_maxima, &maxima).is_empty());
+
+    let past_the_maxima = UpdateUserData {
+        metabits: 1001.0,
+        dino_rank: 51,
+        prestige_rank: 1_000_000,
+        ..at_the_maxima
+    };
+    assert_eq!(
+        validate_payload_within(&past_the_maxima, &maxima),
+        vec![
+            ValidationIssue::new(
+                "metabits",
+                IssueCode::OutOfRange,
+                "must not be more than the configured maximum"
+            )
+            .with_limit(1000.0),
+            ValidationIssue::new(
+                "dino_rank",
+                IssueCode::OutOfRange,
+                "must not be more than the configured maximum"
+            )
+            .with_limit(50.0),
+        ]
+    );
+}
+
+#[test]
+fn negative_values_are_out_of_range_whatever_the_maxima() {
+    let maxima = ProgressMaxima::parse("beyond_rank:10");
+    let payload = UpdateUserData {
+        beyond_rank: -1,
+        ..UpdateUserData::default()
+    };
+
+    assert_eq!(
+        validate_payload_within(&payload, &maxima),
+        vec![
+            ValidationIssue::new("beyond_rank", IssueCode::OutOfRange, "must not be negative")
+                .with_limit(0.0)
+        ]
+    );
+    assert!(validate_payload_within(
+        &UpdateUserData {
+            beyond_rank: 0,
+            ..UpdateUserData::default()
+        },
+        &maxima
+    )
+    .is_empty());
+}
+
+#[test]
+fn deprecated_fields_can_go_backwards() {
+    let payload = UpdateUserData {
+        metabits: 5_000_000.0,
+        dino_rank: 60,
+        prestige_rank: 4,
+        beyond_rank: 10,
+        singularity_speedrun_time: Some(400.0),
+        ..UpdateUserData::default()
+    };
+
+    assert_eq!(
+        monotonic_violations(&snapshot_fixture(), &payload, &[])
+            .iter()
+            .map(|violation| violation.field)
+            .collect::<Vec<_>>(),
+        vec!["dino_rank"]
+    );
+    assert!(
+        monotonic_violations(&snapshot_fixture(), &payload, &[ProgressField::DinoRank]).is_empty()
+    );
+}
@@ -0,0 +1,284 @@
+use async_trait::async_trait;
+use deadpool_postgres::{Client, Object, Pool};
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio_postgres::error::SqlState;
+
+use crate::errors::{ConvertResultErrorToMyError, MyError};
+
+/// how long the database is reported as degraded after a retried write was still refused, clients
+/// are told to come back after the rest of it
+pub const READ_ONLY_DEGRADED_FOR: Duration = Duration::from_secs(30);
+
+static READ_ONLY_STATE: OnceLock<Mutex<ReadOnlyState>> = OnceLock::new();
+
+/// Postgres' `read_only_sql_transaction` (25006), what the old primary answers writes with while a
+/// managed failover is going on
+pub fn is_read_only(error: &tokio_postgres::Error) -> bool {
+    error.code() == Some(&SqlState::READ_ONLY_SQL_TRANSACTION)
+}
+
+/// How often writes ran into a read-only database, and whether it's reported as degraded because a
+/// retry on a fresh connection didn't help either.
+#[derive(Default)]
+pub struct ReadOnlyState {
+    encounters: u64,
+    degraded_until: Option<Instant>,
+}
+
+impl ReadOnlyState {
+    pub fn record_encounter(&mut self) {
+        self.encounters += 1;
+    }
+
+    pub fn degrade(&mut self, now: Instant) {
+        self.degraded_until = Some(now + READ_ONLY_DEGRADED_FOR);
+    }
+
+    pub fn degraded_for(&self, now: Instant) -> Option<Duration> {
+        self.degraded_until
+            .filter(|degraded_until| now < *degraded_until)
+            .map(|degraded_until| degraded_until - now)
+    }
+
+    /// read-only answers since the start, retries included
+    pub fn encounters(&self) -> u64 {
+        self.encounters
+    }
+}
+
+pub fn read_only_state() -> &'static Mutex<ReadOnlyState> {
+    READ_ONLY_STATE.get_or_init(|| Mutex::new(ReadOnlyState::default()))
+}
+
+/// the 503 for a write the database still refused after the retry
+pub fn read_only_unavailable(state: &ReadOnlyState, now: Instant) -> MyError {
+    let retry_after = state
+        .degraded_for(now)
+        .map_or(1, |remaining| remaining.as_secs_f64().ceil() as u64);
+
+    MyError::Unavailable("The database is failing over, please try again shortly")
+        .with_code("DATABASE_READ_ONLY")
+        .with_retry_after(retry_after.max(1))
+}
+
+/// where a write's connection comes from, the pool outside of tests
+#[async_trait]
+pub trait ConnectionSource {
+    type Connection: Send;
+
+    /// Swaps the connection for a fresh one. The old one is dropped instead of going back to the
+    /// pool, it's still attached to the server that turned read only.
+    async fn replace(&self, connection: &mut Self::Connection) -> Result<(), MyError>;
+}
+
+#[async_trait]
+impl ConnectionSource for Pool {
+    type Connection = Client;
+
+    async fn replace(&self, connection: &mut Client) -> Result<(), MyError> {
+        let fresh = self.get().await.make_response(MyError::Unavailable(
+            "Failed at getting a new database connection",
+        ))?;
+        drop(Object::take(std::mem::replace(connection, fresh)));
+
+        Ok(())
+    }
+}
+
+/// a write that can be run again on another connection
+#[async_trait]
+pub trait PrimaryWrite<C: Send>: Sync {
+    type Output: Send;
+    type Error: Send;
+
+    async fn run(&self, connection: &mut C) -> Result<Self::Output, Self::Error>;
+
+    fn is_read_only(error: &Self::Error) -> bool;
+}
+
+/// Runs the write, when the database answers that it's read only the connection is replaced and
+/// the write runs once more. A write that's refused again, or that can't get a fresh connection,
+/// degrades the database and returns the read-only error for the caller to answer with
+/// `read_only_unavailable`.
+pub async fn write_on_primary<S, W>(
+    source: &S,
+    connection: &mut S::Connection,
+    write: &W,
+    state: &Mutex<ReadOnlyState>,
+) -> Result<W::Output, W::Error>
+where
+    S: ConnectionSource + Sync,
+    W: PrimaryWrite<S::Connection>,
+{
+    let mut error = match write.run(connection).await {
+        Err(error) if W::is_read_only(&error) => error,
+        result => return result,
+    };
+    state.lock().unwrap().record_encounter();
+
+    if source.replace(connection).await.is_ok() {
+        error = match write.run(connection).await {
+            Err(error) if W::is_read_only(&error) => error,
+            result => return result,
+        };
+        state.lock().unwrap().record_encounter();
+    }
+    state.lock().unwrap().degrade(Instant::now());
+
+    Err(error)
+}
+
+/// Connections are numbers, the ones in `read_only` refuse writes and 0 is broken. Everything
+/// that happens is written to `events`.
+#[cfg(test)]
+#[derive(Default)]
+struct FakeConnections {
+    read_only: Vec<u32>,
+    pool_down: bool,
+    events: Mutex<Vec<String>>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl ConnectionSource for FakeConnections {
+    type Connection = u32;
+
+    async fn replace(&self, connection: &mut u32) -> Result<(), MyError> {
+        let mut events = self.events.lock().unwrap();
+        if self.pool_down {
+            events.push("no connection".to_owned());
+            return Err(MyError::Unavailable("down"));
+        }
+        events.push(format!("recycle {}", connection));
+        *connection += 1;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl PrimaryWrite<u32> for FakeConnections {
+    type Output = u32;
+    type Error = &'static str;
+
+    async fn run(&self, connection: &mut u32) -> Result<u32, &'static str> {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("write on {}", connection));
+        if self.read_only.contains(connection) {
+            Err("read only")
+        } else if *connection == 0 {
+            Err("broken")
+        } else {
+            Ok(*connection)
+        }
+    }
+
+    fn is_read_only(error: &&'static str) -> bool {
+        *error == "read only"
+    }
+}
+
+#[cfg(test)]
+fn run_fake(connections: &FakeConnections, first: u32) -> (Result<u32, &'static str>, u64) {
+    let state = Mutex::new(ReadOnlyState::default());
+    let mut connection = first;
+    let result = actix_web::rt::System::new().block_on(write_on_primary(
+        connections,
+        &mut connection,
+        connections,
+        &state,
+    ));
+
+    (result, state.into_inner().unwrap().encounters())
+}
+
+#[test]
+fn read_only_writes_are_retried_on_a_fresh_connection() {
+    let connections = FakeConnections {
+        read_only: vec![1],
+        ..FakeConnections::default()
+    };
+    let state = Mutex::new(ReadOnlyState::default());
+    let mut connection = 1;
+    let result = actix_web::rt::System::new().block_on(write_on_primary(
+        &connections,
+        &mut connection,
+        &connections,
+        &state,
+    ));
+
+    assert_eq!(result, Ok(2));
+    // the caller keeps using the fresh connection
+    assert_eq!(connection, 2);
+    assert_eq!(
+        connections.events.into_inner().unwrap(),
+        vec!["write on 1", "recycle 1", "write on 2"]
+    );
+    let state = state.into_inner().unwrap();
+    assert_eq!(state.encounters(), 1);
+    assert_eq!(state.degraded_for(Instant::now()), None);
+}
+
+#[test]
+fn a_second_read_only_answer_degrades_the_database() {
+    let connections = FakeConnections {
+        read_only: vec![1, 2],
+        ..FakeConnections::default()
+    };
+    let state = Mutex::new(ReadOnlyState::default());
+    let mut connection = 1;
+    let result = actix_web::rt::System::new().block_on(write_on_primary(
+        &connections,
+        &mut connection,
+        &connections,
+        &state,
+    ));
+
+    assert_eq!(result, Err("read only"));
+    assert_eq!(
+        connections.events.into_inner().unwrap(),
+        vec!["write on 1", "recycle 1", "write on 2"]
+    );
+    let state = state.into_inner().unwrap();
+    assert_eq!(state.encounters(), 2);
+    let now = Instant::now();
+    assert!(state.degraded_for(now).is_some());
+    assert_eq!(
+        read_only_unavailable(&state, now).retry_after(),
+        Some(READ_ONLY_DEGRADED_FOR.as_secs())
+    );
+    assert_eq!(
+        read_only_unavailable(&state, now).error_code(),
+        "DATABASE_READ_ONLY"
+    );
+    assert_eq!(state.degraded_for(now + READ_ONLY_DEGRADED_FOR), None);
+}
+
+#[test]
+fn only_read_only_answers_are_retried() {
+    let connections = FakeConnections::default();
+    assert_eq!(run_fake(&connections, 0), (Err("broken"), 0));
+    assert_eq!(run_fake(&connections, 3), (Ok(3), 0));
+    assert_eq!(
+        connections.events.into_inner().unwrap(),
+        vec!["write on 0", "write on 3"]
+    );
+
+    // without a fresh connection there's nothing to retry on
+    let connections = FakeConnections {
+        read_only: vec![1],
+        pool_down: true,
+        ..FakeConnections::default()
+    };
+    assert_eq!(run_fake(&connections, 1), (Err("read only"), 1));
+    assert_eq!(
+        connections.events.into_inner().unwrap(),
+        vec!["write on 1", "no connection"]
+    );
+}
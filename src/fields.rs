@@ -0,0 +1,357 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::{collections::HashMap, sync::OnceLock};
+
+use crate::{
+    large_numbers::number_or_string_f64,
+    models::{PatchUserData, UpdateUserData, UserData},
+};
+
+/// One variant per progress column, this is the only place that should know the names, bounds,
+/// and monotonicity of the progress fields. Adding a game field means adding a variant here plus
+/// the model and migration changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProgressField {
+    Metabits,
+    DinoRank,
+    PrestigeRank,
+    BeyondRank,
+    SingularitySpeedrunTime,
+    AllSharksObtained,
+    AllHiddenAchievementsObtained,
+}
+
+/// Fields a game update removed. Old clients still send them, so they're accepted and stored for
+/// history, but the monotonic checks skip them and role rules reading them are frozen: their roles
+/// are neither granted nor taken away. New clients won't send the field, so deprecating one also
+/// means giving it a `#[serde(default)]` in the models.
+pub const DEPRECATED_FIELDS: [ProgressField; 0] = [];
+
+/// which way a field is allowed to move between syncs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Monotonicity {
+    NonDecreasing,
+    /// e.g. a personal best time, which can only ever get faster
+    NonIncreasing,
+}
+
+/// How a field is written to JSON. Fields that can go past 2^53 are sent as strings, the dashboard
+/// and the bot's JS parse numbers into doubles and would quietly round them. Strings and numbers
+/// are both accepted when reading either way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NumberFormat {
+    Number,
+    String,
+}
+
+/// the lowest value a field accepts and the reason given when it's lower
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FieldBounds {
+    pub min: f64,
+    pub min_exclusive: bool,
+    pub reason: &'static str,
+}
+
+/// The highest values the fields can be synced with. They're configured with `PROGRESS_MAXIMA`, so
+/// the game's progression growing past them only takes a config change. Fields without a maximum
+/// aren't limited.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProgressMaxima(HashMap<ProgressField, f64>);
+
+static PROGRESS_MAXIMA: OnceLock<ProgressMaxima> = OnceLock::new();
+
+impl ProgressMaxima {
+    /// parses `{field}:{max}` entries separated by commas, malformed entries and the flags, which
+    /// have no bounds, are skipped
+    pub fn parse(maxima: &str) -> Self {
+        ProgressMaxima(
+            maxima
+                .split(',')
+                .filter_map(|entry| {
+                    let (name, max) = entry.split_once(':')?;
+                    let field = ProgressField::from_name(name.trim())?;
+                    field.bounds()?;
+                    let max = max
+                        .trim()
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|max| max.is_finite())?;
+                    Some((field, max))
+                })
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, field: ProgressField) -> Option<f64> {
+        self.0.get(&field).copied()
+    }
+}
+
+/// the maxima every payload is validated against, they're set once from the config at startup
+pub fn progress_maxima() -> &'static ProgressMaxima {
+    PROGRESS_MAXIMA.get_or_init(ProgressMaxima::default)
+}
+
+pub fn set_progress_maxima(maxima: ProgressMaxima) {
+    let _ = PROGRESS_MAXIMA.set(maxima);
+}
+
+impl ProgressField {
+    pub const ALL: [ProgressField; 7] = [
+        ProgressField::Metabits,
+        ProgressField::DinoRank,
+        ProgressField::PrestigeRank,
+        ProgressField::BeyondRank,
+        ProgressField::SingularitySpeedrunTime,
+        ProgressField::AllSharksObtained,
+        ProgressField::AllHiddenAchievementsObtained,
+    ];
+
+    /// the name of the field in payloads and columns
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProgressField::Metabits => "metabits",
+            ProgressField::DinoRank => "dino_rank",
+            ProgressField::PrestigeRank => "prestige_rank",
+            ProgressField::BeyondRank => "beyond_rank",
+            ProgressField::SingularitySpeedrunTime => "singularity_speedrun_time",
+            ProgressField::AllSharksObtained => "all_sharks_obtained",
+            ProgressField::AllHiddenAchievementsObtained => "all_hidden_achievements_obtained",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ProgressField> {
+        ProgressField::ALL
+            .into_iter()
+            .find(|field| field.name() == name)
+    }
+
+    /// `None` for the flags, which can't be out of bounds
+    pub fn bounds(&self) -> Option<FieldBounds> {
+        match self {
+            ProgressField::Metabits
+            | ProgressField::DinoRank
+            | ProgressField::PrestigeRank
+            | ProgressField::BeyondRank => Some(FieldBounds {
+                min: 0.0,
+                min_exclusive: false,
+                reason: "must not be negative",
+            }),
+            ProgressField::SingularitySpeedrunTime => Some(FieldBounds {
+                min: 0.0,
+                min_exclusive: true,
+                reason: "must be a positive number of seconds",
+            }),
+            ProgressField::AllSharksObtained | ProgressField::AllHiddenAchievementsObtained => None,
+        }
+    }
+
+    pub fn monotonicity(&self) -> Monotonicity {
+        match self {
+            ProgressField::SingularitySpeedrunTime => Monotonicity::NonIncreasing,
+            _ => Monotonicity::NonDecreasing,
+        }
+    }
+
+    /// the models' serde attributes have to agree, `serialized_formats_follow_the_registry` checks it
+    pub fn number_format(&self) -> NumberFormat {
+        match self {
+            ProgressField::Metabits => NumberFormat::String,
+            _ => NumberFormat::Number,
+        }
+    }
+
+    pub fn is_deprecated(&self) -> bool {
+        DEPRECATED_FIELDS.contains(self)
+    }
+
+    /// whether payloads can leave the field out
+    pub fn is_optional(&self) -> bool {
+        *self == ProgressField::SingularitySpeedrunTime
+    }
+
+    /// reads the field's JSON value the way payloads are read, without keeping it
+    pub fn check_json_type(&self, value: &Value) -> Result<(), serde_json::Error> {
+        match self {
+            ProgressField::Metabits => number_or_string_f64(value).map(|_| ()),
+            ProgressField::DinoRank | ProgressField::PrestigeRank | ProgressField::BeyondRank => {
+                i32::deserialize(value).map(|_| ())
+            }
+            ProgressField::SingularitySpeedrunTime => Option::<f64>::deserialize(value).map(|_| ()),
+            ProgressField::AllSharksObtained | ProgressField::AllHiddenAchievementsObtained => {
+                bool::deserialize(value).map(|_| ())
+            }
+        }
+    }
+
+    /// the field that going up lets this one start over, prestiging resets the dino rank
+    pub fn reset_by(&self) -> Option<ProgressField> {
+        match self {
+            ProgressField::DinoRank => Some(ProgressField::PrestigeRank),
+            _ => None,
+        }
+    }
+
+    /// flags are read as 0 or 1, `None` means the field isn't set
+    pub fn read_userdata(&self, user_data: &UserData) -> Option<f64> {
+        match self {
+            ProgressField::Metabits => Some(user_data.metabits as f64),
+            ProgressField::DinoRank => Some(user_data.dino_rank as f64),
+            ProgressField::PrestigeRank => Some(user_data.prestige_rank as f64),
+            ProgressField::BeyondRank => Some(user_data.beyond_rank as f64),
+            ProgressField::SingularitySpeedrunTime => user_data.singularity_speedrun_time,
+            ProgressField::AllSharksObtained => Some(user_data.all_sharks_obtained as u8 as f64),
+            ProgressField::AllHiddenAchievementsObtained => {
+                Some(user_data.all_hidden_achievements_obtained as u8 as f64)
+            }
+        }
+    }
+
+    pub fn read_update(&self, user_data: &UpdateUserData) -> Option<f64> {
+        match self {
+            ProgressField::Metabits => Some(user_data.metabits),
+            ProgressField::DinoRank => Some(user_data.dino_rank as f64),
+            ProgressField::PrestigeRank => Some(user_data.prestige_rank as f64),
+            ProgressField::BeyondRank => Some(user_data.beyond_rank as f64),
+            ProgressField::SingularitySpeedrunTime => user_data.singularity_speedrun_time,
+            ProgressField::AllSharksObtained => Some(user_data.all_sharks_obtained as u8 as f64),
+            ProgressField::AllHiddenAchievementsObtained => {
+                Some(user_data.all_hidden_achievements_obtained as u8 as f64)
+            }
+        }
+    }
+
+    /// `None` when the patch leaves the field out or clears it
+    pub fn read_patch(&self, patch: &PatchUserData) -> Option<f64> {
+        match self {
+            ProgressField::Metabits => patch.metabits,
+            ProgressField::DinoRank => patch.dino_rank.map(f64::from),
+            ProgressField::PrestigeRank => patch.prestige_rank.map(f64::from),
+            ProgressField::BeyondRank => patch.beyond_rank.map(f64::from),
+            ProgressField::SingularitySpeedrunTime => patch.singularity_speedrun_time.flatten(),
+            ProgressField::AllSharksObtained => patch
+                .all_sharks_obtained
+                .map(|obtained| obtained as u8 as f64),
+            ProgressField::AllHiddenAchievementsObtained => patch
+                .all_hidden_achievements_obtained
+                .map(|obtained| obtained as u8 as f64),
+        }
+    }
+
+    pub fn write_userdata(&self, user_data: &mut UserData, value: f64) {
+        match self {
+            ProgressField::Metabits => user_data.metabits = value as i64,
+            ProgressField::DinoRank => user_data.dino_rank = value as i32,
+            ProgressField::PrestigeRank => user_data.prestige_rank = value as i32,
+            ProgressField::BeyondRank => user_data.beyond_rank = value as i32,
+            ProgressField::SingularitySpeedrunTime => {
+                user_data.singularity_speedrun_time = Some(value)
+            }
+            ProgressField::AllSharksObtained => user_data.all_sharks_obtained = value != 0.0,
+            ProgressField::AllHiddenAchievementsObtained => {
+                user_data.all_hidden_achievements_obtained = value != 0.0
+            }
+        }
+    }
+
+    pub fn write_update(&self, user_data: &mut UpdateUserData, value: f64) {
+        match self {
+            ProgressField::Metabits => user_data.metabits = value,
+            ProgressField::DinoRank => user_data.dino_rank = value as i32,
+            ProgressField::PrestigeRank => user_data.prestige_rank = value as i32,
+            ProgressField::BeyondRank => user_data.beyond_rank = value as i32,
+            ProgressField::SingularitySpeedrunTime => {
+                user_data.singularity_speedrun_time = Some(value)
+            }
+            ProgressField::AllSharksObtained => user_data.all_sharks_obtained = value != 0.0,
+            ProgressField::AllHiddenAchievementsObtained => {
+                user_data.all_hidden_achievements_obtained = value != 0.0
+            }
+        }
+    }
+}
+
+#[test]
+fn every_field_round_trips_through_the_accessors() {
+    for field in ProgressField::ALL {
+        let value = if field.bounds().is_some() { 42.0 } else { 1.0 };
+
+        let mut user_data = UserData::default();
+        field.write_userdata(&mut user_data, value);
+        assert_eq!(field.read_userdata(&user_data), Some(value), "{:?}", field);
+
+        let mut update = UpdateUserData::default();
+        field.write_update(&mut update, value);
+        assert_eq!(field.read_update(&update), Some(value), "{:?}", field);
+        assert_eq!(
+            field.read_patch(&PatchUserData::from(update)),
+            Some(value),
+            "{:?}",
+            field
+        );
+
+        assert_eq!(ProgressField::from_name(field.name()), Some(field));
+    }
+}
+
+#[test]
+fn serialized_formats_follow_the_registry() {
+    let user_data = UserData {
+        metabits: (1 << 53) + 1,
+        singularity_speedrun_time: Some(12.5),
+        ..UserData::default()
+    };
+    let json = serde_json::to_value(&user_data).unwrap();
+
+    for field in ProgressField::ALL {
+        let value = &json[field.name()];
+        let expected_string = field.number_format() == NumberFormat::String;
+        assert_eq!(value.is_string(), expected_string, "{:?}", field);
+    }
+    assert_eq!(json["metabits"], "9007199254740993");
+
+    let read = serde_json::from_value::<UserData>(json).unwrap();
+    assert_eq!(read.metabits, user_data.metabits);
+}
+
+#[test]
+fn json_types_are_checked_like_payloads_read_them() {
+    for field in ProgressField::ALL {
+        let value = match field.bounds() {
+            Some(_) => serde_json::json!(42),
+            None => serde_json::json!(true),
+        };
+        assert!(field.check_json_type(&value).is_ok(), "{:?}", field);
+        assert!(
+            field
+                .check_json_type(&serde_json::json!({ "nested": 1 }))
+                .is_err(),
+            "{:?}",
+            field
+        );
+    }
+
+    // large numbers are sent as strings, the ranks aren't
+    assert!(ProgressField::Metabits
+        .check_json_type(&serde_json::json!("9007199254740993"))
+        .is_ok());
+    assert!(ProgressField::DinoRank
+        .check_json_type(&serde_json::json!("42"))
+        .is_err());
+    assert!(ProgressField::SingularitySpeedrunTime
+        .check_json_type(&serde_json::Value::Null)
+        .is_ok());
+}
+
+#[test]
+fn maxima_are_only_parsed_for_bounded_fields() {
+    let maxima = ProgressMaxima::parse(
+        "metabits:1e15, dino_rank : 500,all_sharks_obtained:1,bad,beyond_rank:x",
+    );
+
+    assert_eq!(maxima.get(ProgressField::Metabits), Some(1e15));
+    assert_eq!(maxima.get(ProgressField::DinoRank), Some(500.0));
+    assert_eq!(maxima.get(ProgressField::AllSharksObtained), None);
+    assert_eq!(maxima.get(ProgressField::BeyondRank), None);
+    assert_eq!(ProgressMaxima::parse(""), ProgressMaxima::default());
+}
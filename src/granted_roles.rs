@@ -0,0 +1,119 @@
+use deadpool_postgres::Client;
+use std::collections::BTreeMap;
+
+use crate::{
+    constants::ErrorLogType,
+    db,
+    digest::unix_now,
+    errors::{ConvertResultErrorToMyError, LogMyError, MyError, DISCORD_ID_NOT_LINKED},
+    role_handling::RoleSync,
+};
+
+/// role id to the unix seconds the role was granted at, `None` for roles the member already had
+/// before grant times were tracked
+pub type GrantedRoles = BTreeMap<String, Option<u64>>;
+
+/// Carries the grant times over to the roles the member holds after a sync. Newly gained roles are
+/// stamped with `now`, re-granting a role keeps its original time and roles that were taken away
+/// are dropped.
+pub fn stamp_granted_roles(
+    previous: &GrantedRoles,
+    held: &[u64],
+    gained: &[u64],
+    now: u64,
+) -> GrantedRoles {
+    held.iter()
+        .map(|role_id| {
+            let role_id_key = role_id.to_string();
+            let granted_at = match previous.get(&role_id_key) {
+                Some(granted_at) => *granted_at,
+                None if gained.contains(role_id) => Some(now),
+                None => None,
+            };
+            (role_id_key, granted_at)
+        })
+        .collect()
+}
+
+/// a column that can't be parsed is treated as empty, the next sync writes it again
+pub fn parse_granted_roles(granted_roles: &str) -> GrantedRoles {
+    serde_json::from_str(granted_roles).unwrap_or_default()
+}
+
+pub async fn get_granted_roles(client: &Client, discord_id: &str) -> Result<GrantedRoles, MyError> {
+    let granted_roles = db::get_granted_roles(client, discord_id)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at retrieving the granted roles",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?
+        .ok_or(MyError::NotFound(DISCORD_ID_NOT_LINKED))?;
+
+    Ok(parse_granted_roles(&granted_roles))
+}
+
+/// stores when each of the roles the member holds after a sync was granted
+pub async fn record_granted_roles(client: &Client, discord_id: &str, role_sync: &RoleSync) {
+    let previous = match get_granted_roles(client, discord_id).await {
+        Ok(previous) => previous,
+        Err(_) => return,
+    };
+    let gained = role_sync
+        .gained
+        .iter()
+        .map(|role| role.id)
+        .collect::<Vec<u64>>();
+    let granted_roles = stamp_granted_roles(&previous, &role_sync.held, &gained, unix_now());
+    if granted_roles == previous {
+        return;
+    }
+
+    let _ = db::update_granted_roles(
+        client,
+        discord_id,
+        &serde_json::to_string(&granted_roles).unwrap_or_default(),
+    )
+    .await
+    .make_response(MyError::InternalError(
+        "Failed at recording the granted roles",
+    ))
+    .make_log(ErrorLogType::INTERNAL)
+    .await;
+}
+
+#[test]
+fn first_grants_are_stamped() {
+    let granted_roles = stamp_granted_roles(&GrantedRoles::new(), &[1, 2], &[2], 1_000);
+
+    // the member had role 1 before grant times were tracked
+    assert_eq!(
+        granted_roles,
+        GrantedRoles::from([("1".to_owned(), None), ("2".to_owned(), Some(1_000))])
+    );
+}
+
+#[test]
+fn regranting_keeps_the_original_time() {
+    let previous = GrantedRoles::from([("2".to_owned(), Some(1_000))]);
+
+    // the member lost the role outside of a sync and gained it back
+    let granted_roles = stamp_granted_roles(&previous, &[2], &[2], 2_000);
+
+    assert_eq!(granted_roles, previous);
+}
+
+#[test]
+fn removed_roles_lose_their_time() {
+    let previous = GrantedRoles::from([("1".to_owned(), None), ("2".to_owned(), Some(1_000))]);
+
+    let granted_roles = stamp_granted_roles(&previous, &[1], &[], 2_000);
+
+    assert_eq!(granted_roles, GrantedRoles::from([("1".to_owned(), None)]));
+    // and gaining it again starts over
+    assert_eq!(
+        stamp_granted_roles(&granted_roles, &[1, 2], &[2], 3_000)["2"],
+        Some(3_000)
+    );
+    assert_eq!(parse_granted_roles("not json"), GrantedRoles::new());
+}
@@ -0,0 +1,343 @@
+use actix_web::rt::{self, time};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use twilight_http::Client;
+use twilight_model::id::{marker::GuildMarker, Id};
+
+use crate::{
+    constants::{C2SGUILD, LOG},
+    digest::unix_now,
+    discord_pause::ensure_discord_available,
+    errors::{InternalErrorConverter, MyError},
+    role_handling::discord_call,
+    webhook_logging::webhook_log,
+};
+
+/// how long the guild's roles are used before they're fetched again
+pub const GUILD_ROLES_TTL: Duration = Duration::from_secs(10 * 60);
+/// a refresh that's asked for because the cache looks wrong is skipped if the last one is this recent
+pub const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+static GUILD_ROLES_CACHE: OnceLock<Mutex<GuildRolesCache>> = OnceLock::new();
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GuildRole {
+    pub id: u64,
+    pub name: String,
+    /// the role's place in the hierarchy, members can only be given roles below the bot's highest
+    pub position: i64,
+    /// roles of integrations and boosts, these can't be given to anyone
+    pub managed: bool,
+}
+
+/// where the guild's roles come from, so the cache can be tested without Discord
+#[async_trait]
+pub trait GuildRoleSource {
+    async fn fetch_roles(&self) -> Result<Vec<GuildRole>, MyError>;
+}
+
+pub struct DiscordGuild<'a> {
+    client: &'a Client,
+    guild_id: Id<GuildMarker>,
+}
+
+impl<'a> DiscordGuild<'a> {
+    pub fn new(client: &'a Client) -> Self {
+        DiscordGuild {
+            client,
+            guild_id: Id::new(C2SGUILD),
+        }
+    }
+}
+
+#[async_trait]
+impl GuildRoleSource for DiscordGuild<'_> {
+    async fn fetch_roles(&self) -> Result<Vec<GuildRole>, MyError> {
+        ensure_discord_available().await?;
+        let roles = discord_call(
+            self.client.roles(self.guild_id).exec().await,
+            "failed at retrieving the guild's roles",
+        )
+        .await?
+        .models()
+        .await
+        .make_internal_error("failed at parsing the guild's roles")?;
+
+        Ok(roles
+            .into_iter()
+            .map(|role| GuildRole {
+                id: role.id.get(),
+                name: role.name,
+                position: role.position,
+                managed: role.managed,
+            })
+            .collect())
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ListedGuildRole {
+    pub id: String,
+    pub name: String,
+    pub position: i64,
+    pub managed: bool,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct GuildRolesStatus {
+    pub roles: Vec<ListedGuildRole>,
+    /// seconds since the unix epoch, `None` until the roles were fetched once
+    #[serde(serialize_with = "crate::timestamps::unix_seconds::serialize_option")]
+    pub refreshed_at: Option<u64>,
+    pub stale: bool,
+}
+
+/// The guild's roles as of the last successful fetch. Everything that needs to know about the
+/// guild's roles reads them from here instead of asking Discord itself.
+pub struct GuildRolesCache {
+    ttl: Duration,
+    roles: Vec<GuildRole>,
+    refreshed: Option<(Instant, u64)>,
+}
+
+impl GuildRolesCache {
+    pub fn new(ttl: Duration) -> Self {
+        GuildRolesCache {
+            ttl,
+            roles: Vec::new(),
+            refreshed: None,
+        }
+    }
+
+    /// whether the roles are older than the TTL, or were never fetched
+    pub fn is_stale(&self, now: Instant) -> bool {
+        self.is_older_than(self.ttl, now)
+    }
+
+    pub fn is_older_than(&self, age: Duration, now: Instant) -> bool {
+        self.refreshed.map_or(true, |(refreshed_at, _)| {
+            now.duration_since(refreshed_at) >= age
+        })
+    }
+
+    pub fn replace(&mut self, roles: Vec<GuildRole>, now: Instant, unix_now: u64) {
+        self.roles = roles;
+        self.refreshed = Some((now, unix_now));
+    }
+
+    pub fn by_id(&self, role_id: u64) -> Option<&GuildRole> {
+        self.roles.iter().find(|role| role.id == role_id)
+    }
+
+    /// whether the role exists, `None` while the roles were never fetched
+    pub fn knows(&self, role_id: u64) -> Option<bool> {
+        self.refreshed.map(|_| self.by_id(role_id).is_some())
+    }
+
+    /// seconds since the unix epoch, `None` until the roles were fetched once
+    pub fn refreshed_at(&self) -> Option<u64> {
+        self.refreshed.map(|(_, unix_now)| unix_now)
+    }
+
+    pub fn role_ids(&self) -> Vec<u64> {
+        self.roles.iter().map(|role| role.id).collect()
+    }
+
+    pub fn status(&self, now: Instant) -> GuildRolesStatus {
+        GuildRolesStatus {
+            roles: self
+                .roles
+                .iter()
+                .map(|role| ListedGuildRole {
+                    id: role.id.to_string(),
+                    name: role.name.clone(),
+                    position: role.position,
+                    managed: role.managed,
+                })
+                .collect(),
+            refreshed_at: self.refreshed_at(),
+            stale: self.is_stale(now),
+        }
+    }
+}
+
+pub fn guild_roles_cache() -> &'static Mutex<GuildRolesCache> {
+    GUILD_ROLES_CACHE.get_or_init(|| Mutex::new(GuildRolesCache::new(GUILD_ROLES_TTL)))
+}
+
+/// Fetches the roles into the cache unless they were fetched within `min_age`. A failed fetch
+/// leaves the roles that were there, they're still better than nothing.
+pub async fn refresh_guild_roles(
+    cache: &Mutex<GuildRolesCache>,
+    source: &impl GuildRoleSource,
+    min_age: Duration,
+) -> Result<(), MyError> {
+    if !cache.lock().unwrap().is_older_than(min_age, Instant::now()) {
+        return Ok(());
+    }
+
+    let roles = source.fetch_roles().await?;
+    cache
+        .lock()
+        .unwrap()
+        .replace(roles, Instant::now(), unix_now());
+
+    Ok(())
+}
+
+/// refreshes the shared cache, failures are logged and the stale roles stay in use
+pub async fn refresh_shared_guild_roles(
+    source: &impl GuildRoleSource,
+    min_age: Duration,
+) -> Result<(), MyError> {
+    let result = refresh_guild_roles(guild_roles_cache(), source, min_age).await;
+    if let Err(error) = &result {
+        webhook_log(
+            format!(
+                "failed at refreshing the guild's roles, the roles from the last refresh stay in use: {}",
+                error
+            ),
+            LOG::INFORMATIONAL,
+        )
+        .await;
+    }
+
+    result
+}
+
+/// fills the cache at startup and refreshes it whenever it's past its TTL
+pub fn spawn_guild_roles_scheduler(discord_token: String) {
+    rt::spawn(async move {
+        let client = Client::new(discord_token);
+        let source = DiscordGuild::new(&client);
+        let mut interval = time::interval(MIN_REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let _ = refresh_shared_guild_roles(&source, GUILD_ROLES_TTL).await;
+        }
+    });
+}
+
+#[cfg(test)]
+struct FakeGuild {
+    roles: Mutex<Result<Vec<GuildRole>, &'static str>>,
+    fetches: std::sync::atomic::AtomicU32,
+}
+
+#[cfg(test)]
+impl FakeGuild {
+    fn new(roles: Vec<GuildRole>) -> Self {
+        FakeGuild {
+            roles: Mutex::new(Ok(roles)),
+            fetches: std::sync::atomic::AtomicU32::new(0),
+        }
+    }
+
+    fn fetches(&self) -> u32 {
+        self.fetches.load(std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl GuildRoleSource for FakeGuild {
+    async fn fetch_roles(&self) -> Result<Vec<GuildRole>, MyError> {
+        self.fetches
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.roles
+            .lock()
+            .unwrap()
+            .clone()
+            .map_err(MyError::InternalError)
+    }
+}
+
+#[cfg(test)]
+fn guild_role(id: u64, name: &str, position: i64) -> GuildRole {
+    GuildRole {
+        id,
+        name: name.to_owned(),
+        position,
+        managed: false,
+    }
+}
+
+#[test]
+fn roles_are_refetched_once_the_ttl_expires() {
+    let guild = FakeGuild::new(vec![guild_role(1, "Reality Explorer", 3)]);
+    let cache = Mutex::new(GuildRolesCache::new(GUILD_ROLES_TTL));
+    let now = Instant::now();
+    assert!(cache.lock().unwrap().is_stale(now));
+    assert_eq!(cache.lock().unwrap().knows(1), None);
+
+    let runtime = actix_web::rt::System::new();
+    runtime
+        .block_on(refresh_guild_roles(&cache, &guild, GUILD_ROLES_TTL))
+        .unwrap();
+    // fresh roles aren't fetched again
+    runtime
+        .block_on(refresh_guild_roles(&cache, &guild, GUILD_ROLES_TTL))
+        .unwrap();
+    assert_eq!(guild.fetches(), 1);
+
+    {
+        let cache = cache.lock().unwrap();
+        assert_eq!(cache.by_id(1).map(|role| role.position), Some(3));
+        assert_eq!(cache.knows(2), Some(false));
+        let refreshed_at = cache.refreshed.unwrap().0;
+        assert!(!cache.is_stale(refreshed_at + GUILD_ROLES_TTL - Duration::from_secs(1)));
+        assert!(cache.is_stale(refreshed_at + GUILD_ROLES_TTL));
+    }
+}
+
+#[test]
+fn a_manual_refresh_picks_up_new_roles() {
+    let guild = FakeGuild::new(vec![guild_role(1, "Reality Explorer", 3)]);
+    let cache = Mutex::new(GuildRolesCache::new(GUILD_ROLES_TTL));
+    let runtime = actix_web::rt::System::new();
+    runtime
+        .block_on(refresh_guild_roles(&cache, &guild, Duration::ZERO))
+        .unwrap();
+
+    *guild.roles.lock().unwrap() = Ok(vec![
+        guild_role(1, "Reality Explorer", 3),
+        guild_role(2, "Reality Expert", 4),
+    ]);
+    runtime
+        .block_on(refresh_guild_roles(&cache, &guild, Duration::ZERO))
+        .unwrap();
+
+    assert_eq!(guild.fetches(), 2);
+    assert_eq!(cache.lock().unwrap().role_ids(), vec![1, 2]);
+}
+
+#[test]
+fn stale_roles_are_served_when_a_refresh_fails() {
+    let guild = FakeGuild::new(vec![guild_role(1, "Reality Explorer", 3)]);
+    let cache = Mutex::new(GuildRolesCache::new(Duration::ZERO));
+    let runtime = actix_web::rt::System::new();
+    runtime
+        .block_on(refresh_guild_roles(&cache, &guild, Duration::ZERO))
+        .unwrap();
+
+    *guild.roles.lock().unwrap() = Err("Discord is down");
+    assert!(runtime
+        .block_on(refresh_guild_roles(&cache, &guild, Duration::ZERO))
+        .is_err());
+
+    let status = cache.lock().unwrap().status(Instant::now());
+    assert!(status.stale);
+    assert_eq!(
+        status.roles,
+        vec![ListedGuildRole {
+            id: "1".to_owned(),
+            name: "Reality Explorer".to_owned(),
+            position: 3,
+            managed: false,
+        }]
+    );
+}
@@ -1,200 +1,476 @@
 use crate::{
-    constants::{ErrorLogType, LOG},
+    audit::{security_log, AdminKey, AuditEvent},
+    budget::RequestBudget,
+    bulk_resync::{resync_jobs, RateLimitStreak, ResyncJob, RESYNC_PAGE_SIZE},
+    constants::persistent_roles::PERSISTENT_ROLES,
+    constants::{ErrorLogType, LoggedUser, C2SGUILD, LOG},
+    creation_locks::{claim_discord_id, creation_locks},
     db,
-    errors::{ConvertResultErrorToMyError, LogMyError, MyError},
-    headers::{Authorization, DistributionChannel},
-    models::{CreateUserData, MessageResponse, OGUpdateUserData, UpdateUserData},
-    role_handling::handle_roles,
-    utilities::encode_user_token,
-    webhook_logging::webhook_log,
+    deprecations::{
+        client_fingerprint, group_usage, note_deprecated_fields, note_deprecated_usage,
+        wants_legacy_message, DeprecatedFeature,
+    },
+    digest::{
+        compose_digest, gather_stats, last_week_window, record_digest_count, record_role_grants,
+        unix_now, DigestCount,
+    },
+    discord_pause::discord_pause,
+    email_domains::{domain_key, email_domain, log_refused_domain, DomainCheck},
+    errors::{ConvertResultErrorToMyError, LogMyError, MyError, DISCORD_ID_NOT_LINKED},
+    evaluation::{
+        apply_payload, check_monotonic_fields, check_payload_json, evaluate_payload,
+        json_error_issue, skip_invalid_fields, validate_payload, IssueCode, MonotonicViolation,
+        ValidationIssue,
+    },
+    extractors::{
+        account_not_linked, admin_access, linked_userdata, AuthenticatedUser, OgPayload,
+        UserCredentials,
+    },
+    failover::{read_only_state, read_only_unavailable, write_on_primary, PrimaryWrite},
+    fields::DEPRECATED_FIELDS,
+    granted_roles::{get_granted_roles, record_granted_roles, GrantedRoles},
+    guild_role_cache::{guild_roles_cache, refresh_shared_guild_roles, DiscordGuild},
+    headers::{
+        sanitize_header_value, Authorization, DistributionChannel, ExpectedDiscordId,
+        IdempotencyKey, IDEMPOTENT_REPLAY_HEADER,
+    },
+    import::{
+        new_job_id, render_failures_csv, ImportBatch, ImportFailure, ImportFailureReason, ImportRow,
+    },
+    link_attempts::{email_key, link_attempts, record_link_attempt},
+    link_codes::{new_link_code, LinkCodeForm, LinkPayload, LINK_CODE_TTL},
+    models::{
+        discord_mention, ApiPartnerRequest, ApiPartnerUsage, BetaTesterUpdate, BoundUserResponse,
+        CreateUserData, CreatedUserResponse, FingerprintMatch, GuildRoles, LinkCodeRequest,
+        LinkCodeResponse, MessageResponse, OGBinaryResponse, OGMessageResponse, PatchUserData,
+        PortableImportRequest, PrivacySettingsPatch, PromoRoleRuleRequest, PublicLinkStatus,
+        RecentErrorsQuery, RecoveryCredentialRequest, ReportFormat, ResyncOptions, RoleRuleStatus,
+        RoleRuleUpdate, RolesPreviewRequest, RuleStatus, SimulationRequest, StatsHistoryQuery,
+        SupportCodeRegistration, SyncOptions, TouchOptions, UpdateUserData, UserData,
+        UserListQuery, UserResponse, ValidateProgressRequest, ValidationReport, ViewOptions,
+        WebhookReloadRequest, WithheldRole,
+    },
+    negative_cache::negative_cache,
+    net::request_client_ip,
+    nicknames::MemberNickname,
+    og_allowlist::{og_rejects, og_request_allowed, record_og_reject},
+    og_binary::{encode_frame, OG_BINARY_CONTENT_TYPE},
+    og_conversion::{parse_og_payload, record_conversion_report},
+    og_dedup::{
+        og_dedup, payload_hash, start_og_request, OgDedupStart, DUPLICATE_SUPPRESSED_HEADER,
+    },
+    partners::{hash_partner_key, partner_keys, MIN_PARTNER_KEY_LENGTH},
+    portability,
+    promo_roles::{
+        promo_rules, promo_window, record_promo_grants, reload_promo_rules, PromoWindow,
+    },
+    purge::purge_user_artifacts,
+    recent_errors::{group_errors, is_token_fingerprint, recent_errors, ErrorGroup, RecordedError},
+    recovery::{plan_recovery_link, resolve_user_token, Credential, RecoveryLink},
+    request_signing::{skew_histogram, ClockSkewStats},
+    role_debounce::{pending_evaluations, PendingEvaluation},
+    role_handling::{
+        compute_earned_roles, gained_roles_log, gained_roles_log_type, gained_roles_message,
+        group_by_guild, handle_roles, removed_roles_log, role_names, EarnedRole, RoleSync,
+        RoleSyncMode, SyncRoles, MILESTONE_ROLES,
+    },
+    role_pauses::role_pauses,
+    role_removal::{
+        managed_role_ids, managed_roles, queue_granted_role_removals, role_removals,
+        RoleRemovalSummary,
+    },
+    role_rules::{
+        closest_miss, compute_earned_roles_with_trace, deprecated_field_roles, explain_rule,
+        trace_streak_rules, ClosestMiss, ExplainedRule, RoleRule, RuleTrace,
+    },
+    routes::RouteId,
+    schema_compat::SchemaState,
+    slo::slo_tracker,
+    stats_history::{daily_series, unix_day, StatsMetric, StatsPoint},
+    status::{current_status, observe, render_status_html},
+    support_codes::{invalidate_support_code, support_code_cache},
+    sync_streaks::{
+        get_sync_streak, record_sync, record_touch, streak_roles, sync_week, SyncStreak,
+    },
+    sync_timings::{finish_sync_timings, sync_timing_histograms, SyncStage, SyncTimings},
+    user_view::{compute_fields, ComputedFields, UserDataView},
+    utilities::{derive_og_user_tokens, derive_user_tokens, is_same_player, DerivedTokens},
+    webhook_logging::{webhook_health, webhook_log, webhook_log_for_user},
+    write_behind::{write_behind, CounterTable},
 };
-use actix_web::{delete, patch, post, web, HttpRequest, HttpResponse};
-use crypto::{hmac::Hmac, mac::Mac, sha1::Sha1};
+use actix_web::{
+    delete,
+    error::JsonPayloadError,
+    get,
+    http::header::{self, ContentType},
+    patch, post,
+    rt::{self, time},
+    web, HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+use async_trait::async_trait;
 use deadpool_postgres::{Client, Pool};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Deserialize)]
 pub struct PlayerData {
     #[serde(rename = "playerId")]
     player_id: String,
+    /// skip the fields that can't be written instead of rejecting the whole payload
+    #[serde(default)]
+    partial: bool,
+    /// take away the managed roles the progress doesn't earn anymore
+    #[serde(default)]
+    reconcile: bool,
 }
 
 #[post("")]
 pub async fn og_update_user(
+    req: HttpRequest,
     query: web::Query<PlayerData>,
-    received_user: web::Json<OGUpdateUserData>,
+    received_user: OgPayload,
     db_pool: web::Data<Pool>,
     config: web::Data<crate::config::Config>,
+    budget: RequestBudget,
 ) -> Result<HttpResponse, MyError> {
-    let user_data = received_user.into_inner();
+    let client_ip = request_client_ip(&req, &config.trusted_proxies);
+    if !og_request_allowed(&config.og_allowed_cidrs, client_ip) {
+        record_og_reject(og_rejects(), client_ip);
+        return Err(MyError::Forbidden(
+            "This endpoint only accepts requests from the game's servers",
+        )
+        .with_code("NOT_A_GAME_SERVER"));
+    }
+
+    let payload_hash = payload_hash(&received_user.0);
+    let (user_data, converted_data, conversion_report) = parse_og_payload(received_user.0)
+        .make_response(
+            MyError::BadRequest("the payload doesn't match the userdata definition")
+                .with_code("INVALID_PAYLOAD"),
+        )?;
+    record_conversion_report(&conversion_report);
     let config = config.get_ref();
 
     println!("og update user function");
 
-    let client: Client = db_pool
-        .get()
-        .await
-        .make_response(MyError::InternalError(
-            "request failed at creating database client, please try again",
-        ))
-        .make_log(ErrorLogType::INTERNAL)
-        .await?;
+    let user_tokens = derive_og_user_tokens(
+        &query.player_id,
+        &user_data.player_token,
+        &config.userdata_auth,
+    );
+    // the launcher's retries get the first response, a failing request forgets the payload again
+    let dedup_guard =
+        match start_og_request(og_dedup(), (user_tokens.v2.clone(), payload_hash)).await {
+            OgDedupStart::First(guard) => guard,
+            OgDedupStart::Duplicate(body) => {
+                return og_response(
+                    &req,
+                    HttpResponse::Ok().insert_header((DUPLICATE_SUPPRESSED_HEADER, "true")),
+                    body,
+                )
+            }
+        };
 
-    let mut user_token = Hmac::new(Sha1::new(), config.userdata_auth.as_bytes());
-    user_token.input(query.player_id.as_bytes());
-    user_token.input(user_data.player_token.as_bytes());
+    let AuthenticatedUser {
+        mut client,
+        user_token,
+        credential,
+        ..
+    } = AuthenticatedUser::authenticate(&db_pool, &budget, &user_tokens).await?;
+    let fingerprint = client_fingerprint(&req, &user_token);
+    note_deprecated_usage(DeprecatedFeature::OgEndpoint, &fingerprint);
+    // the OG payload has every field, so every field is written
+    let converted_data = PatchUserData::from(converted_data);
+    note_deprecated_fields(&converted_data, &fingerprint);
 
-    let user_token = user_token
-        .result()
-        .code()
-        .iter()
-        .map(|byte| format!("{:02x?}", byte))
-        .collect::<Vec<String>>()
-        .join("");
+    let (updated_data, skipped) = write_sync(
+        &db_pool,
+        &mut client,
+        &budget,
+        &user_token,
+        &user_data.beta_tester,
+        converted_data,
+        query.partial,
+    )
+    .await?;
 
-    db::get_userdata(&client, &user_token)
-        .await
-        .make_response(MyError::InternalError(
-            "Failed at retrieving existing data, you may not have your account linked yet",
-        ))
-        .make_log(ErrorLogType::USER(user_token.to_owned()))
-        .await?;
+    snapshot_userdata(&client, &budget, &updated_data, &user_token).await;
 
-    let updated_data = db::update_userdata(
+    let streak = record_sync(
         &client,
-        &user_token,
-        &user_data.beta_tester.clone(),
-        UpdateUserData::from(user_data),
+        &updated_data.discord_id,
+        sync_week(unix_now(), config.streak_utc_offset),
+    )
+    .await?;
+    let role_sync = handle_roles(
+        &updated_data,
+        sync_roles(config, &streak, RoleSyncMode::reconciling(query.reconcile)),
+        member_nickname(&client, config, &updated_data.discord_id).await,
+        config.discord_token.clone(),
+        &budget,
+        &mut SyncTimings::start(Instant::now()),
     )
     .await
-    .make_response(MyError::InternalError(
-        "The request has unfortunately failed the update",
+    .make_response(
+        MyError::InternalError("The role-handling process has failed")
+            .with_code("ROLE_HANDLING_FAILED"),
+    )
+    .make_log(ErrorLogType::USER(
+        LoggedUser::token(&user_token).with_discord_id(&updated_data.discord_id),
     ))
-    .make_log(ErrorLogType::USER(user_token.to_owned()))
     .await?;
+    record_promo_grants(&client, &updated_data.discord_id, &role_sync.gained).await;
+    record_granted_roles(&client, &updated_data.discord_id, &role_sync).await;
+    let synced = synced_roles(&config, &updated_data, &streak, &role_sync.gained);
+    record_role_grants(&synced.gained_roles);
 
-    let gained_roles = handle_roles(&updated_data, config.discord_token.clone())
-        .await
+    write_behind().add(CounterTable::UserActivity, &updated_data.discord_id, 1);
+    webhook_log_for_user(
+        &updated_data.discord_id,
+        gained_roles_log_type(&synced.roles),
+        || {
+            gained_roles_log(
+                &updated_data.discord_id,
+                &synced.roles,
+                credential.log_suffix(),
+                config.gained_roles_listed,
+            )
+        },
+    )
+    .await;
+    log_removed_roles(
+        &updated_data.discord_id,
+        &role_sync.removed,
+        credential.log_suffix(),
+        config.gained_roles_listed,
+    )
+    .await;
+    let body = serde_json::to_string(&OGMessageResponse {
+        message: synced.message,
+        roles: synced.roles,
+        gained_roles: synced.gained_roles,
+        discord_id: updated_data.discord_id,
+        withheld: role_sync.withheld,
+        skipped,
+        warnings: conversion_report.warnings(),
+        closest_miss: synced.closest_miss,
+        removed_roles: role_names(&role_sync.removed),
+    })
+    .make_response(MyError::InternalError(
+        "The request was successful, but its response couldn't be created",
+    ))?;
+    dedup_guard.finish(&body);
+
+    og_response(&req, &mut HttpResponse::Ok(), body)
+}
+
+/// Answers the OG endpoint in the binary format when the client accepts it and with the JSON
+/// `body` otherwise. Duplicates are answered from the JSON the first request got, so the binary
+/// response is always made from it.
+fn og_response(
+    req: &HttpRequest,
+    response: &mut HttpResponseBuilder,
+    body: String,
+) -> Result<HttpResponse, MyError> {
+    let accepts_binary = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map_or(false, |accept| accept.contains(OG_BINARY_CONTENT_TYPE));
+    if !accepts_binary {
+        return Ok(response.content_type(ContentType::json()).body(body));
+    }
+
+    let frame = serde_json::from_str::<OGBinaryResponse>(&body)
         .make_response(MyError::InternalError(
-            "The role-handling process has failed",
+            "The request was successful, but its response couldn't be created",
         ))
-        .make_log(ErrorLogType::USER(user_token))
-        .await?;
-    let roles = if gained_roles.join(", ").is_empty() {
-        "The request was successful, but you've already gained all of the possible roles with your current progress".to_owned()
-    } else {
-        format!(
-            "The request was successful, you've gained the following roles: {}",
-            gained_roles.join(", ")
-        )
-    };
+        .and_then(|binary_response| {
+            encode_frame(&binary_response).make_response(MyError::InternalError(
+                "The request was successful, but its response couldn't be created",
+            ))
+        })?;
+    Ok(response.content_type(OG_BINARY_CONTENT_TYPE).body(frame))
+}
 
-    let logged_roles = if gained_roles.join(", ").is_empty() {
-        format!(
-            "user with ID {} had a successful request but gained no roles",
-            updated_data.discord_id
-        )
+/// the data that's stored for the user, with the same credentials they sync with
+#[get("")]
+pub async fn get_user(
+    user: AuthenticatedUser,
+    options: web::Query<ViewOptions>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    let computed = if options.computed() {
+        Some(computed_fields(&user.client, &config, &user.existing_data).await?)
     } else {
-        format!(
-            "user with ID {} gained the following roles: {}",
-            updated_data.discord_id,
-            gained_roles.join(", ")
-        )
+        None
     };
 
-    webhook_log(logged_roles, LOG::INFORMATIONAL).await;
-    Ok(HttpResponse::Ok().json(MessageResponse { message: roles }))
+    Ok(HttpResponse::Ok().json(UserDataView {
+        data: user.existing_data,
+        computed,
+    }))
+}
+
+/// the computed fields of a user whose streak still has to be looked up
+async fn computed_fields(
+    client: &Client,
+    config: &crate::config::Config,
+    user_data: &UserData,
+) -> Result<ComputedFields, MyError> {
+    let streak = get_sync_streak(client, &user_data.discord_id)
+        .await?
+        .as_of(sync_week(unix_now(), config.streak_utc_offset));
+
+    Ok(compute_fields(
+        user_data,
+        &config.role_rules,
+        &config.streak_rules,
+        &streak,
+    ))
 }
 
 #[patch("")]
 pub async fn update_user(
-    auth_header: web::Header<Authorization>,
-    distribution_channel: web::Header<DistributionChannel>,
-    received_user: web::Json<UpdateUserData>,
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    options: web::Query<SyncOptions>,
+    received_user: web::Json<PatchUserData>,
     db_pool: web::Data<Pool>,
     config: web::Data<crate::config::Config>,
+    budget: RequestBudget,
 ) -> Result<HttpResponse, MyError> {
-    let user_data = received_user.into_inner();
-    let distribution_channel = distribution_channel.into_inner();
-    let auth_header = auth_header.into_inner();
+    let AuthenticatedUser {
+        mut client,
+        user_token,
+        credential,
+        mut timings,
+        ..
+    } = user;
+    let beta_branch = DistributionChannel::beta_branch(req.headers())?;
+    let patch = received_user.into_inner();
+    if patch.is_empty() {
+        return Err(empty_patch());
+    }
 
-    let client: Client = db_pool
-        .get()
-        .await
-        .make_response(MyError::InternalError(
-            "request failed at creating database client, please try again",
-        ))
-        .make_log(ErrorLogType::INTERNAL)
-        .await?;
+    let fingerprint = client_fingerprint(&req, &user_token);
+    note_deprecated_fields(&patch, &fingerprint);
 
-    let user_token = encode_user_token(
-        &auth_header.email,
-        &auth_header.token,
-        &config.userdata_auth,
-    );
+    let write_started = Instant::now();
+    let (updated_data, skipped) = write_sync(
+        &db_pool,
+        &mut client,
+        &budget,
+        &user_token,
+        &beta_branch,
+        patch,
+        options.partial,
+    )
+    .await?;
 
-    db::get_userdata(&client, &user_token)
-        .await
-        .make_response(MyError::InternalError(
-            "Failed at retrieving existing data, you may not have your account linked yet",
-        ))
-        .make_log(ErrorLogType::USER(user_token.to_owned()))
-        .await?;
+    snapshot_userdata(&client, &budget, &updated_data, &user_token).await;
 
-    let updated_data = db::update_userdata(
+    let streak = record_sync(
         &client,
-        &user_token,
-        &(distribution_channel.0 == "Beta"),
-        user_data,
+        &updated_data.discord_id,
+        sync_week(unix_now(), config.streak_utc_offset),
+    )
+    .await?;
+    timings.record(SyncStage::DbWrite, write_started.elapsed());
+    if let Some(window) = config.role_debounce() {
+        write_behind().add(CounterTable::UserActivity, &updated_data.discord_id, 1);
+        let timings = finish_sync_timings(
+            &timings,
+            options.debug_timing,
+            &updated_data.discord_id,
+            Instant::now(),
+        );
+        return Ok(HttpResponse::Ok().json(UserResponse {
+            timings,
+            ..defer_roles(updated_data, options.reconcile, skipped, window)
+        }));
+    }
+    let role_sync = handle_roles(
+        &updated_data,
+        sync_roles(
+            &config,
+            &streak,
+            RoleSyncMode::reconciling(options.reconcile),
+        ),
+        member_nickname(&client, &config, &updated_data.discord_id).await,
+        config.discord_token.clone(),
+        &budget,
+        &mut timings,
     )
     .await
-    .make_response(MyError::InternalError(
-        "The request has unfortunately failed the update",
+    .make_response(
+        MyError::InternalError("The role-handling process has failed")
+            .with_code("ROLE_HANDLING_FAILED"),
+    )
+    .make_log(ErrorLogType::USER(
+        LoggedUser::token(&user_token).with_discord_id(&updated_data.discord_id),
     ))
-    .make_log(ErrorLogType::USER(user_token.to_owned()))
     .await?;
+    record_promo_grants(&client, &updated_data.discord_id, &role_sync.gained).await;
+    record_granted_roles(&client, &updated_data.discord_id, &role_sync).await;
+    let synced = synced_roles(&config, &updated_data, &streak, &role_sync.gained);
+    record_role_grants(&synced.gained_roles);
 
-    let gained_roles = handle_roles(&updated_data, config.discord_token.clone())
-        .await
-        .make_response(MyError::InternalError(
-            "The role-handling process has failed",
-        ))
-        .make_log(ErrorLogType::USER(user_token))
-        .await?;
-    let roles = if gained_roles.join(", ").is_empty() {
-        "The request was successful, but you've already gained all of the possible roles with your current progress".to_owned()
-    } else {
-        format!(
-            "The request was successful, you've gained the following roles: {}",
-            gained_roles.join(", ")
-        )
-    };
-
-    let logged_roles = if gained_roles.join(", ").is_empty() {
-        format!(
-            "user with ID {} had a successful request but gained no roles",
-            updated_data.discord_id
-        )
-    } else {
-        format!(
-            "user with ID {} gained the following roles: {}",
-            updated_data.discord_id,
-            gained_roles.join(", ")
-        )
-    };
-
-    webhook_log(logged_roles, LOG::INFORMATIONAL).await;
-    Ok(HttpResponse::Ok().json(MessageResponse { message: roles }))
+    if wants_legacy_message(&req) {
+        note_deprecated_usage(DeprecatedFeature::LegacyMessageResponse, &fingerprint);
+    }
+    write_behind().add(CounterTable::UserActivity, &updated_data.discord_id, 1);
+    webhook_log_for_user(
+        &updated_data.discord_id,
+        gained_roles_log_type(&synced.roles),
+        || {
+            gained_roles_log(
+                &updated_data.discord_id,
+                &synced.roles,
+                credential.log_suffix(),
+                config.gained_roles_listed,
+            )
+        },
+    )
+    .await;
+    log_removed_roles(
+        &updated_data.discord_id,
+        &role_sync.removed,
+        credential.log_suffix(),
+        config.gained_roles_listed,
+    )
+    .await;
+    let timings = finish_sync_timings(
+        &timings,
+        options.debug_timing,
+        &updated_data.discord_id,
+        Instant::now(),
+    );
+    Ok(HttpResponse::Ok().json(UserResponse {
+        message: synced.message,
+        roles: synced.roles,
+        gained_roles: synced.gained_roles,
+        discord_id: updated_data.discord_id,
+        withheld: role_sync.withheld,
+        skipped,
+        closest_miss: synced.closest_miss,
+        removed_roles: role_names(&role_sync.removed),
+        roles_pending: false,
+        timings,
+    }))
 }
 
 #[post("")]
 pub async fn create_user(
     req: HttpRequest,
-    auth_header: web::Header<Authorization>,
-    distribution_channel: Option<web::Header<DistributionChannel>>,
+    credentials: UserCredentials,
+    expected_discord_id: Option<web::Header<ExpectedDiscordId>>,
     received_user: web::Json<CreateUserData>,
     db_pool: web::Data<Pool>,
     config: web::Data<crate::config::Config>,
+    budget: RequestBudget,
 ) -> Result<HttpResponse, MyError> {
     // note: may later replace this snippet with some other way of allowing users to create linked data
     let semblance_access = req.headers().get("X-Semblance-Exclusive");
@@ -220,17 +496,133 @@ pub async fn create_user(
     // end of code that may later be replaced with some other way of allowing users to create linked data
 
     let user_data = received_user.into_inner();
-    let is_default_userdata = user_data.data.is_none();
-    let inner_data = match user_data.data {
-        Some(user) => user,
-        None => UpdateUserData::default(),
+    // a link code's discord id is only known once it's redeemed
+    if user_data.link_code.is_none() {
+        if user_data.discord_id.is_empty() {
+            return Err(
+                MyError::BadRequest("Either a discord_id or a link_code is required")
+                    .with_code("MISSING_DISCORD_ID"),
+            );
+        }
+        if let Some(expected_discord_id) = &expected_discord_id {
+            expected_discord_id.verify(&user_data.discord_id)?;
+        }
+    }
+    if let Some(data) = &user_data.data {
+        data.validate().map_err(|issues| {
+            MyError::BadRequest("The progress values aren't valid")
+                .with_code("INVALID_PROGRESS")
+                .with_issues(issues)
+        })?;
+    }
+
+    // a retry of a create that went through gets its response, also when it's over the attempts
+    let idempotency_key = IdempotencyKey::from_headers(req.headers())?;
+    if let Some(idempotency_key) = &idempotency_key {
+        if let Some(body) =
+            idempotent_response(&db_pool, &credentials, idempotency_key, config.get_ref()).await?
+        {
+            return Ok(replayed_create(body));
+        }
+    }
+
+    // without the header the user isn't taken to be a beta tester
+    let distribution_channel = DistributionChannel::from_headers(req.headers())?;
+
+    // a support code doesn't come with the email, the game already vouched for it
+    let attempts_key = credentials
+        .login
+        .as_ref()
+        .filter(|_| !config.link_attempt_salt.is_empty())
+        .map(|login| email_key(&login.email, &config.link_attempt_salt));
+    if let Some(attempts_key) = &attempts_key {
+        if link_attempts()
+            .lock()
+            .unwrap()
+            .is_capped(attempts_key, Instant::now())
+        {
+            return Err(MyError::TooManyRequests(
+                "Too many failed attempts at linking this email today, please try again tomorrow",
+            )
+            .with_code("LINK_ATTEMPTS_EXCEEDED"));
+        }
+    }
+
+    // failed redemptions count as failed link attempts, so codes can't be guessed either
+    let linked = match redeem_link_code(
+        &db_pool,
+        &config,
+        expected_discord_id.as_deref(),
+        user_data,
+    )
+    .await
+    {
+        Ok(user_data) => {
+            link_user(
+                &req,
+                &credentials,
+                user_data,
+                distribution_channel.map_or(false, |channel| channel.is_beta()),
+                &db_pool,
+                &config,
+                &budget,
+            )
+            .await
+        }
+        Err(error) => Err(error.into()),
     };
+    let result = match (linked, &idempotency_key) {
+        // the retry raced the create it repeats, once that one is done it's answered the same way
+        (Err(LinkFailure::AlreadyLinked), Some(idempotency_key)) => {
+            match idempotent_response(&db_pool, &credentials, idempotency_key, config.get_ref())
+                .await?
+            {
+                Some(body) => return Ok(replayed_create(body)),
+                None => Err(LinkFailure::AlreadyLinked.into()),
+            }
+        }
+        (Ok(body), Some(idempotency_key)) => {
+            store_idempotent_response(&db_pool, &credentials, idempotency_key, &body).await;
+            Ok(body)
+        }
+        (result, _) => result.map_err(MyError::from),
+    }
+    .map(|body| {
+        HttpResponse::Created()
+            .content_type(ContentType::json())
+            .body(body)
+    });
+    if let Some(attempts_key) = &attempts_key {
+        record_link_attempt(attempts_key, &result).await;
+    }
+
+    result
+}
 
-    let distribution_channel = match distribution_channel {
-        Some(channel) => channel.into_inner(),
-        None => DistributionChannel("".to_owned()),
+/// the QR payloads are signed with the portability secret, without it only typed codes work
+fn link_payload_secret(config: &crate::config::Config) -> Option<&str> {
+    (!config.portability_secret.is_empty()).then_some(config.portability_secret.as_str())
+}
+
+fn invalid_link_code() -> MyError {
+    MyError::BadRequest("The link code is invalid, expired or was already used")
+        .with_code("INVALID_LINK_CODE")
+}
+
+/// Binds a create with a link code to the discord id the code was handed out for and uses the code
+/// up, creates without one are left as they are.
+async fn redeem_link_code(
+    db_pool: &Pool,
+    config: &crate::config::Config,
+    expected_discord_id: Option<&ExpectedDiscordId>,
+    mut user_data: CreateUserData,
+) -> Result<CreateUserData, MyError> {
+    let link_code = match user_data.link_code.take() {
+        Some(link_code) => link_code,
+        None => return Ok(user_data),
     };
-    let auth_header = auth_header.into_inner();
+    let form = LinkCodeForm::parse(&link_code, link_payload_secret(config), unix_now())
+        .map_err(|_| invalid_link_code())?;
 
     let client: Client = db_pool
         .get()
@@ -240,106 +632,150 @@ pub async fn create_user(
         ))
         .make_log(ErrorLogType::INTERNAL)
         .await?;
-
-    let user_token = encode_user_token(
-        &auth_header.email,
-        &auth_header.token,
-        &config.userdata_auth,
-    );
-
-    let user_exists = db::get_userdata(&client, &user_token)
+    let discord_id = db::consume_link_code(&client, form.code(), &SystemTime::now())
         .await
-        .make_response(MyError::NotFound)
-        .make_log(ErrorLogType::USER(user_token.to_owned()))
-        .await;
-    if user_exists.is_ok() {
-        if user_data.discord_id != user_exists?.discord_id {
-            return Err(MyError::BadRequest(
-                "This account is already bound to another discord id",
-            ));
+        .make_response(MyError::InternalError(
+            "Failed at redeeming the link code, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?
+        .ok_or_else(invalid_link_code)?;
+    if let LinkCodeForm::Payload(payload) = &form {
+        // only a payload signed with a leaked secret gets here
+        if payload.discord_id != discord_id {
+            return Err(invalid_link_code());
         }
-        return Err(MyError::InternalError(
-            "You're already linked, please use the update endpoint",
+    }
+    if !user_data.discord_id.is_empty() && user_data.discord_id != discord_id {
+        return Err(MyError::Conflict(
+            "The discord id doesn't match the one the link code is for",
         ));
     }
+    if let Some(expected_discord_id) = expected_discord_id {
+        expected_discord_id.verify(&discord_id)?;
+    }
+
+    user_data.discord_id = discord_id;
+    Ok(user_data)
+}
+
+/// A single-use code for the bot to show the player, as typed or scanned it stands in for the
+/// discord id of a create. The QR payload is signed so the game can check it offline first.
+#[post("/link-codes/qr")]
+pub async fn create_link_code_qr(
+    admin_key: AdminKey,
+    received_request: web::Json<LinkCodeRequest>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    let secret = link_payload_secret(&config).ok_or(
+        MyError::Unavailable("QR link codes are disabled, PORTABILITY_SECRET isn't set")
+            .with_code("LINK_CODES_DISABLED"),
+    )?;
+    let discord_id = received_request.into_inner().discord_id;
+    if !is_plausible_snowflake(&discord_id) {
+        return Err(
+            MyError::BadRequest("The discord id must be a valid snowflake")
+                .with_code("INVALID_DISCORD_ID"),
+        );
+    }
 
-    // forgot why I had this here, but might remove it if it's completely unnecessary
-    // let account_exists_with_id = db::get_userdata_by_id(&client, &user_data.discord_id)
-    //     .await
-    //     .make_response(MyError::NotFound)
-    //     .make_log(ErrorLogType::USER(user_token.to_owned()))
-    //     .await;
-    // if account_exists_with_id.is_ok() {
-    //     return Err(MyError::BadRequest(
-    //         "This discord id is already bound to another account",
-    //     ));
-    // }
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
 
-    let created_data = db::create_userdata(
+    let expires_at = unix_now() + LINK_CODE_TTL.as_secs();
+    let payload = LinkPayload {
+        code: new_link_code(secret, &discord_id),
+        discord_id,
+        expires_at,
+    };
+    db::create_link_code(
         &client,
-        &user_token,
-        &user_data.discord_id,
-        &(distribution_channel.0 == "Beta"),
-        inner_data,
+        &payload.code,
+        &payload.discord_id,
+        &(SystemTime::UNIX_EPOCH + Duration::from_secs(expires_at)),
     )
     .await
     .make_response(MyError::InternalError(
-        "The request has unfortunately failed at creating your account",
+        "Failed at creating the link code, please try again",
     ))
-    .make_log(ErrorLogType::USER(user_token.to_owned()))
+    .make_log(ErrorLogType::INTERNAL)
     .await?;
 
-    if is_default_userdata {
-        webhook_log(
-            format!(
-                "created userdata for user of id '{}'",
-                created_data.discord_id
-            ),
-            LOG::SUCCESSFUL,
-        )
-        .await;
-        return Ok(HttpResponse::Ok().json(created_data));
+    security_log(
+        &db_pool,
+        &config,
+        AuditEvent::new(
+            admin_key,
+            RouteId::CreateLinkCodeQr,
+            json!({ "discord_id": payload.discord_id }),
+        ),
+    )
+    .await;
+
+    Ok(HttpResponse::Created().json(LinkCodeResponse {
+        qr_payload: payload.sign(secret),
+        code: payload.code,
+        discord_id: payload.discord_id,
+        expires_at,
+    }))
+}
+
+/// Refuses emails whose domain is denylisted or has as many links as it's allowed. Returns the key
+/// the link is counted under, `None` when domains aren't counted for it.
+async fn check_email_domain(
+    client: &Client,
+    config: &crate::config::Config,
+    email: &str,
+) -> Result<Option<String>, MyError> {
+    let limits = &config.domain_limits;
+    if config.link_attempt_salt.is_empty() {
+        return Ok(None);
     }
+    let domain = match email_domain(email) {
+        Some(domain) => domain,
+        None => return Ok(None),
+    };
+    let domain_key = domain_key(&domain, &config.link_attempt_salt);
 
-    let gained_roles = handle_roles(&created_data, config.discord_token.clone())
+    let linked = db::get_domain_count(client, &domain_key)
         .await
         .make_response(MyError::InternalError(
-            "The role-handling process has failed",
+            "request failed at checking your email's domain, please try again",
         ))
-        .make_log(ErrorLogType::USER(user_token))
+        .make_log(ErrorLogType::INTERNAL)
         .await?;
-    let roles = if gained_roles.join(", ").is_empty() {
-        "The request was successful, but you've already gained all of the possible roles with your current progress".to_owned()
-    } else {
-        format!(
-            "The request was successful, you've gained the following roles: {}",
-            gained_roles.join(", ")
-        )
-    };
-
-    let logged_roles = if gained_roles.join(", ").is_empty() {
-        format!(
-            "user with ID {} had a successful request but gained no roles",
-            created_data.discord_id
-        )
-    } else {
-        format!(
-            "user with ID {} gained the following roles: {}",
-            created_data.discord_id,
-            gained_roles.join(", ")
-        )
-    };
-
-    webhook_log(logged_roles, LOG::INFORMATIONAL).await;
-    Ok(HttpResponse::Ok().json(MessageResponse { message: roles }))
+    match limits.check(&domain, linked) {
+        DomainCheck::Allowed => Ok(Some(domain_key)),
+        DomainCheck::Allowlisted => Ok(None),
+        DomainCheck::Denied => {
+            log_refused_domain(&domain_key, &DomainCheck::Denied, limits.cap).await;
+            Err(MyError::Forbidden(
+                "Emails of this domain can't be linked, please link with an email of another provider",
+            ).with_code("EMAIL_DOMAIN_DENIED"))
+        }
+        DomainCheck::Capped => {
+            log_refused_domain(&domain_key, &DomainCheck::Capped, limits.cap).await;
+            Err(MyError::TooManyRequests(
+                "Too many accounts are linked with emails of this domain, please link with an email of another provider",
+            ).with_code("EMAIL_DOMAIN_CAPPED"))
+        }
+    }
 }
 
-#[delete("")]
-pub async fn delete_user(
-    auth_header: web::Header<Authorization>,
-    db_pool: web::Data<Pool>,
-    config: web::Data<crate::config::Config>,
-) -> Result<HttpResponse, MyError> {
+/// the response of the create that was sent with the key, as long as the key hasn't expired
+async fn idempotent_response(
+    db_pool: &Pool,
+    credentials: &UserCredentials,
+    idempotency_key: &IdempotencyKey,
+    config: &crate::config::Config,
+) -> Result<Option<String>, MyError> {
     let client: Client = db_pool
         .get()
         .await
@@ -349,19 +785,3984 @@ pub async fn delete_user(
         .make_log(ErrorLogType::INTERNAL)
         .await?;
 
-    let user_token = encode_user_token(
-        &auth_header.email,
-        &auth_header.token,
-        &config.userdata_auth,
-    );
+    db::get_idempotent_response(
+        &client,
+        &credentials.user_tokens.v2,
+        &idempotency_key.0,
+        &(SystemTime::now() - Duration::from_secs(config.idempotency_key_ttl)),
+    )
+    .await
+    .make_response(MyError::InternalError(
+        "failed at looking up the create this Idempotency-Key was sent with",
+    ))
+    .make_log(ErrorLogType::INTERNAL)
+    .await
+}
+
+/// the link stands either way, a retry is only told it's already linked without the key
+async fn store_idempotent_response(
+    db_pool: &Pool,
+    credentials: &UserCredentials,
+    idempotency_key: &IdempotencyKey,
+    body: &str,
+) {
+    let client = match db_pool.get().await {
+        Ok(client) => client,
+        Err(_) => return,
+    };
+    let _ = db::create_idempotency_key(
+        &client,
+        &credentials.user_tokens.v2,
+        &idempotency_key.0,
+        body,
+    )
+    .await
+    .make_response(MyError::InternalError(
+        "failed at storing the response of a create for its Idempotency-Key",
+    ))
+    .make_log(ErrorLogType::INTERNAL)
+    .await;
+}
+
+/// a replay is answered like the create it repeats, with a 201
+fn replayed_create(body: String) -> HttpResponse {
+    HttpResponse::Created()
+        .content_type(ContentType::json())
+        .insert_header((IDEMPOTENT_REPLAY_HEADER, "true"))
+        .body(body)
+}
+
+/// Why `link_user` failed. A token that's already linked is told apart, a retried create that
+/// raced the one it repeats is answered like that one.
+enum LinkFailure {
+    AlreadyLinked,
+    Failed(MyError),
+}
+
+impl From<MyError> for LinkFailure {
+    fn from(error: MyError) -> Self {
+        LinkFailure::Failed(error)
+    }
+}
+
+impl From<db::LinkConflict> for LinkFailure {
+    fn from(conflict: db::LinkConflict) -> Self {
+        match conflict {
+            db::LinkConflict::Token => LinkFailure::AlreadyLinked,
+            conflict => LinkFailure::Failed(link_conflict(conflict)),
+        }
+    }
+}
+
+impl From<LinkFailure> for MyError {
+    fn from(failure: LinkFailure) -> Self {
+        match failure {
+            LinkFailure::AlreadyLinked => link_conflict(db::LinkConflict::Token),
+            LinkFailure::Failed(error) => error,
+        }
+    }
+}
+
+/// the part of `create_user` that counts towards the email's link attempts, it answers with the
+/// response's body
+async fn link_user(
+    req: &HttpRequest,
+    credentials: &UserCredentials,
+    user_data: CreateUserData,
+    beta_tester: bool,
+    db_pool: &Pool,
+    config: &crate::config::Config,
+    budget: &RequestBudget,
+) -> Result<String, LinkFailure> {
+    let is_default_userdata = user_data.data.is_none();
+    let inner_data = match user_data.data {
+        Some(user) => user,
+        None => UpdateUserData::default(),
+    };
 
-    db::get_userdata(&client, &user_token) // TODO: replace with delete_userdata once it's implemented
+    let client: Client = db_pool
+        .get()
         .await
         .make_response(MyError::InternalError(
-            "Failed at deleting userdata, this token may not be valid",
+            "request failed at creating database client, please try again",
         ))
-        .make_log(ErrorLogType::USER(user_token.to_owned()))
+        .make_log(ErrorLogType::INTERNAL)
         .await?;
 
-    Ok(HttpResponse::NoContent().finish())
+    let user_tokens = &credentials.user_tokens;
+    // an account that's still under its legacy token is moved over first, so it's found below
+    // and the new link can't end up next to it under the v2 token
+    let (user_token, _) = resolve_user_token(&client, user_tokens)
+        .await
+        .make_log(ErrorLogType::USER(LoggedUser::token(&user_tokens.v2)))
+        .await?;
+    // a create of the discord id that's still running goes first, see `CreationLocks`
+    let claim = claim_discord_id(creation_locks(), &user_data.discord_id, &user_token, || {
+        link_conflict(db::LinkConflict::DiscordId)
+    })
+    .await?;
+
+    let user_exists = match linked_userdata(&client, budget, &user_token).await {
+        Err(error) if matches!(error.kind(), MyError::NotFound(_)) => None,
+        user_exists => Some(user_exists?),
+    };
+    if let Some(user_exists) = user_exists {
+        if user_data.discord_id != user_exists.discord_id {
+            return Err(
+                MyError::BadRequest("This account is already bound to another discord id")
+                    .with_code("ACCOUNT_BOUND_ELSEWHERE")
+                    .into(),
+            );
+        }
+        return Err(LinkFailure::AlreadyLinked);
+    }
+
+    // a discord id that's linked to another account gets rebound by the upsert below, unless it's
+    // this player under a differently spelled email, rebinding would just split their account
+    if let (Some(login), Ok(bound_account)) = (
+        &credentials.login,
+        db::get_userdata_by_id(&client, budget, &user_data.discord_id).await,
+    ) {
+        if is_same_player(
+            &bound_account.token,
+            &login.email,
+            &login.token,
+            &config.userdata_auth,
+        ) {
+            return Err(MyError::Conflict(
+                "This discord id is already linked to your account under a differently spelled email, sync through PATCH /v2/userdata with the email you linked with or link this one through POST /user/recovery-credential",
+            ).with_code("DISCORD_ID_TAKEN").into());
+        }
+    }
+
+    let domain_key = match &credentials.login {
+        Some(login) => check_email_domain(&client, config, &login.email).await?,
+        None => None,
+    };
+
+    // the checks above are a fast path, a concurrent link is only caught by the constraints
+    let created_data = match db::create_userdata(
+        &client,
+        budget,
+        &user_token,
+        &user_data.discord_id,
+        &beta_tester,
+        inner_data,
+    )
+    .await
+    {
+        Err(db::CreateError::Conflict(conflict)) => return Err(conflict.into()),
+        created_data => {
+            created_data
+                .make_response(MyError::InternalError(
+                    "The request has unfortunately failed at creating your account",
+                ))
+                .make_log(ErrorLogType::USER(
+                    LoggedUser::token(&user_token).with_discord_id(&user_data.discord_id),
+                ))
+                .await?
+        }
+    };
+    claim.created();
+    negative_cache().forget(&user_token);
+    if let Some(domain_key) = &domain_key {
+        // the link stands either way, a missed count only makes the cap a bit more lenient
+        let _ = db::count_linked_domain(&client, &created_data.discord_id, domain_key)
+            .await
+            .make_response(MyError::InternalError(
+                "failed at counting the link towards its email domain",
+            ))
+            .make_log(ErrorLogType::INTERNAL)
+            .await;
+    }
+
+    snapshot_userdata(&client, budget, &created_data, &user_token).await;
+
+    let bound_to = format!(
+        " (bound to {}, id '{}')",
+        discord_mention(&created_data.discord_id),
+        created_data.discord_id
+    );
+    let fingerprint = client_fingerprint(req, &user_token);
+    // an account without progress hasn't synced anything yet
+    let streak = if is_default_userdata {
+        SyncStreak::default()
+    } else {
+        record_sync(
+            &client,
+            &created_data.discord_id,
+            sync_week(unix_now(), config.streak_utc_offset),
+        )
+        .await?
+    };
+    let role_sync = handle_roles(
+        &created_data,
+        sync_roles(config, &streak, RoleSyncMode::Additive),
+        member_nickname(&client, config, &created_data.discord_id).await,
+        config.discord_token.clone(),
+        budget,
+        &mut SyncTimings::start(Instant::now()),
+    )
+    .await
+    .make_response(
+        MyError::InternalError("The role-handling process has failed")
+            .with_code("ROLE_HANDLING_FAILED"),
+    )
+    .make_log(ErrorLogType::USER(
+        LoggedUser::token(&user_token).with_discord_id(&created_data.discord_id),
+    ))
+    .await?;
+    record_promo_grants(&client, &created_data.discord_id, &role_sync.gained).await;
+    record_granted_roles(&client, &created_data.discord_id, &role_sync).await;
+    let synced = synced_roles(config, &created_data, &streak, &role_sync.gained);
+    record_role_grants(&synced.gained_roles);
+    if wants_legacy_message(req) {
+        note_deprecated_usage(DeprecatedFeature::LegacyMessageResponse, &fingerprint);
+    }
+
+    // a create is logged as a success either way, unlike a sync that gained nothing
+    if synced.roles.is_empty() {
+        webhook_log(
+            format!("created userdata for a user{}", bound_to),
+            LOG::SUCCESSFUL,
+        )
+        .await;
+    } else {
+        webhook_log_for_user(&created_data.discord_id, LOG::SUCCESSFUL, || {
+            gained_roles_log(
+                &created_data.discord_id,
+                &synced.roles,
+                &bound_to,
+                config.gained_roles_listed,
+            )
+        })
+        .await;
+    }
+    let discord_id = created_data.discord_id.clone();
+    serde_json::to_string(&BoundUserResponse::new(
+        CreatedUserResponse {
+            user: created_data,
+            message: synced.message,
+            roles: synced.roles,
+            gained_roles: synced.gained_roles,
+            withheld: role_sync.withheld,
+            closest_miss: synced.closest_miss,
+        },
+        &discord_id,
+    ))
+    .make_response(MyError::InternalError(
+        "The request was successful, but its response couldn't be created",
+    ))
+    .map_err(LinkFailure::from)
+}
+
+fn link_conflict(conflict: db::LinkConflict) -> MyError {
+    match conflict {
+        db::LinkConflict::Token => {
+            MyError::BadRequest("You're already linked, please use the update endpoint")
+                .with_code("ALREADY_LINKED")
+        }
+        db::LinkConflict::DiscordId => {
+            MyError::Conflict("This discord id is already bound to another account")
+                .with_code("DISCORD_ID_BOUND")
+        }
+    }
+}
+
+#[delete("")]
+pub async fn delete_user(
+    user: AuthenticatedUser,
+    config: web::Data<crate::config::Config>,
+    budget: RequestBudget,
+) -> Result<HttpResponse, MyError> {
+    let (deleted_data, roles) = delete_link(
+        &user.client,
+        &config,
+        &budget,
+        DeletedLink::Token(&user.user_token),
+    )
+    .await?;
+    webhook_log(
+        format!(
+            "deleted userdata for a user (was bound to {}, id '{}')",
+            discord_mention(&deleted_data.discord_id),
+            deleted_data.discord_id
+        ),
+        LOG::SUCCESSFUL,
+    )
+    .await;
+
+    Ok(match roles {
+        Some(roles) => HttpResponse::Ok().json(RoleRemovalResponse { roles }),
+        None => HttpResponse::NoContent().finish(),
+    })
+}
+
+#[derive(Serialize)]
+pub struct RoleRemovalResponse {
+    roles: RoleRemovalSummary,
+}
+
+/// how the link that's deleted is found, users delete their own by token and admins by discord id
+#[derive(Clone, Copy)]
+enum DeletedLink<'a> {
+    Token(&'a str),
+    DiscordId(&'a str),
+}
+
+/// Deletes the user's row and everything derived from it. With `REMOVE_ROLES_ON_DELETE` the roles
+/// our rules granted are queued to be taken away once the row is gone, returned as the summary.
+async fn delete_link(
+    client: &Client,
+    config: &crate::config::Config,
+    budget: &RequestBudget,
+    link: DeletedLink<'_>,
+) -> Result<(UserData, Option<RoleRemovalSummary>), MyError> {
+    // the granted roles go with the row, so they're read first
+    let granted = if config.remove_roles_on_delete {
+        let discord_id = match link {
+            DeletedLink::Token(user_token) => {
+                linked_userdata(client, budget, user_token)
+                    .await?
+                    .discord_id
+            }
+            DeletedLink::DiscordId(discord_id) => discord_id.to_owned(),
+        };
+        Some(get_granted_roles(client, &discord_id).await?)
+    } else {
+        None
+    };
+
+    let deleted_data = match link {
+        DeletedLink::Token(user_token) => db::delete_userdata(client, budget, user_token)
+            .await
+            .make_response(MyError::InternalError("Failed at deleting userdata"))
+            .make_log(ErrorLogType::USER(LoggedUser::token(user_token)))
+            .await?
+            .ok_or_else(account_not_linked)?,
+        DeletedLink::DiscordId(discord_id) => db::delete_userdata_by_id(client, budget, discord_id)
+            .await
+            .make_response(MyError::InternalError("Failed at deleting userdata"))
+            .make_log(ErrorLogType::INTERNAL)
+            .await?
+            .ok_or(MyError::NotFound(DISCORD_ID_NOT_LINKED))?,
+    };
+
+    purge_user_artifacts(client, &deleted_data).await;
+    record_digest_count(DigestCount::Deletion, "");
+
+    let managed = managed_role_ids(
+        &config.role_rules,
+        &promo_rules().lock().unwrap(),
+        &config.streak_rules,
+    );
+    let roles = queue_granted_role_removals(
+        role_removals(),
+        &deleted_data.discord_id,
+        granted.as_ref(),
+        &managed,
+    );
+
+    Ok((deleted_data, roles))
+}
+
+#[patch("/privacy")]
+pub async fn update_privacy(
+    credentials: UserCredentials,
+    received_settings: web::Json<PrivacySettingsPatch>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, MyError> {
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let user_tokens = &credentials.user_tokens;
+    let (user_token, _) = resolve_user_token(&client, user_tokens)
+        .await
+        .make_log(ErrorLogType::USER(LoggedUser::token(&user_tokens.v2)))
+        .await?;
+
+    let privacy_settings = db::update_privacy_settings(&client, &user_token, &received_settings)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at updating your privacy settings, you may not have your account linked yet",
+        ))
+        .make_log(ErrorLogType::USER(LoggedUser::token(&user_token)))
+        .await?;
+
+    Ok(HttpResponse::Ok().json(privacy_settings))
+}
+
+#[post("/recovery-credential")]
+pub async fn link_recovery_credential(
+    auth_header: web::Header<Authorization>,
+    received_credential: web::Json<RecoveryCredentialRequest>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+    budget: RequestBudget,
+) -> Result<HttpResponse, MyError> {
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let user_tokens = derive_user_tokens(
+        &auth_header.email,
+        &auth_header.token,
+        &config.userdata_auth,
+    );
+    let user_data = primary_userdata(&client, &budget, &user_tokens).await?;
+    let user_token = user_tokens.v2;
+
+    let recovery_tokens = derive_user_tokens(
+        &received_credential.email,
+        &received_credential.token,
+        &config.userdata_auth,
+    );
+    // a credential that's still under its legacy token is moved over before it's checked
+    resolve_user_token(&client, &recovery_tokens)
+        .await
+        .make_log(ErrorLogType::USER(
+            LoggedUser::token(&user_token).with_discord_id(&user_data.discord_id),
+        ))
+        .await?;
+    let recovery_token = recovery_tokens.v2;
+    let existing = db::get_recovery_token(&client, &user_token)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at retrieving your recovery credential",
+        ))
+        .make_log(ErrorLogType::USER(
+            LoggedUser::token(&user_token).with_discord_id(&user_data.discord_id),
+        ))
+        .await?;
+    let taken = db::get_userdata(&client, &budget, &recovery_token)
+        .await
+        .is_ok()
+        || db::get_recovery_primary_token(&client, &recovery_token)
+            .await
+            .make_response(MyError::InternalError(
+                "Failed at checking the recovery credential",
+            ))
+            .make_log(ErrorLogType::USER(
+                LoggedUser::token(&user_token).with_discord_id(&user_data.discord_id),
+            ))
+            .await?
+            .is_some();
+
+    let link = plan_recovery_link(&user_token, &recovery_token, existing.as_deref(), taken)?;
+
+    db::upsert_recovery_token(&client, &user_token, &recovery_token)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at linking your recovery credential",
+        ))
+        .make_log(ErrorLogType::USER(
+            LoggedUser::token(&user_token).with_discord_id(&user_data.discord_id),
+        ))
+        .await?;
+    negative_cache().forget(&recovery_token);
+
+    let action = match link {
+        RecoveryLink::Created => "linked",
+        RecoveryLink::Replaced => "replaced",
+    };
+    webhook_log(
+        format!(
+            "user with ID {} {} their recovery credential",
+            user_data.discord_id, action
+        ),
+        LOG::INFORMATIONAL,
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(MessageResponse {
+        message: format!("Your recovery credential was {}", action),
+    }))
+}
+
+#[delete("/recovery-credential")]
+pub async fn remove_recovery_credential(
+    auth_header: web::Header<Authorization>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+    budget: RequestBudget,
+) -> Result<HttpResponse, MyError> {
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let user_tokens = derive_user_tokens(
+        &auth_header.email,
+        &auth_header.token,
+        &config.userdata_auth,
+    );
+    let user_data = primary_userdata(&client, &budget, &user_tokens).await?;
+    let user_token = user_tokens.v2;
+
+    let removed = db::delete_recovery_token(&client, &user_token)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at removing your recovery credential",
+        ))
+        .make_log(ErrorLogType::USER(
+            LoggedUser::token(&user_token).with_discord_id(&user_data.discord_id),
+        ))
+        .await?;
+    if removed == 0 {
+        return Err(MyError::NotFound("You don't have a recovery credential")
+            .with_code("NO_RECOVERY_CREDENTIAL"));
+    }
+
+    webhook_log(
+        format!(
+            "user with ID {} removed their recovery credential",
+            user_data.discord_id
+        ),
+        LOG::INFORMATIONAL,
+    )
+    .await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Serialize)]
+pub struct GrantedRolesResponse {
+    discord_id: String,
+    #[serde(serialize_with = "crate::timestamps::unix_seconds::serialize_map")]
+    granted_roles: GrantedRoles,
+}
+
+/// when each of the user's roles was granted, for the bot's role anniversaries
+#[get("/granted-roles")]
+pub async fn get_own_granted_roles(
+    auth_header: web::Header<Authorization>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+    budget: RequestBudget,
+) -> Result<HttpResponse, MyError> {
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let user_tokens = derive_user_tokens(
+        &auth_header.email,
+        &auth_header.token,
+        &config.userdata_auth,
+    );
+    let (user_token, _) = resolve_user_token(&client, &user_tokens)
+        .await
+        .make_log(ErrorLogType::USER(LoggedUser::token(&user_tokens.v2)))
+        .await?;
+    let user_data = linked_userdata(&client, &budget, &user_token).await?;
+
+    Ok(HttpResponse::Ok().json(GrantedRolesResponse {
+        granted_roles: get_granted_roles(&client, &user_data.discord_id).await?,
+        discord_id: user_data.discord_id,
+    }))
+}
+
+/// a user's row the way it's stored, without the token
+#[derive(Serialize)]
+pub struct StoredProgress {
+    discord_id: String,
+    beta_tester: bool,
+    #[serde(serialize_with = "crate::large_numbers::string_i64::serialize")]
+    metabits: i64,
+    dino_rank: i32,
+    prestige_rank: i32,
+    beyond_rank: i32,
+    singularity_speedrun_time: Option<f64>,
+    all_sharks_obtained: bool,
+    all_hidden_achievements_obtained: bool,
+    /// when the progress was last synced
+    #[serde(with = "crate::timestamps::system_time")]
+    edited_timestamp: SystemTime,
+}
+
+impl From<UserData> for StoredProgress {
+    fn from(user_data: UserData) -> Self {
+        StoredProgress {
+            discord_id: user_data.discord_id,
+            beta_tester: user_data.beta_tester,
+            metabits: user_data.metabits,
+            dino_rank: user_data.dino_rank,
+            prestige_rank: user_data.prestige_rank,
+            beyond_rank: user_data.beyond_rank,
+            singularity_speedrun_time: user_data.singularity_speedrun_time,
+            all_sharks_obtained: user_data.all_sharks_obtained,
+            all_hidden_achievements_obtained: user_data.all_hidden_achievements_obtained,
+            edited_timestamp: user_data.edited_timestamp,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ProgressResponse {
+    #[serde(flatten)]
+    progress: StoredProgress,
+    /// the weeks in a row the user synced at least once
+    streak: SyncStreak,
+}
+
+impl ProgressResponse {
+    /// the stored progress without the token
+    fn new(user_data: UserData, streak: SyncStreak) -> Self {
+        ProgressResponse {
+            progress: user_data.into(),
+            streak,
+        }
+    }
+}
+
+/// Marks the user as still active without sending progress, it never touches roles or their data
+/// and isn't logged to the webhook.
+#[post("/touch")]
+pub async fn touch_user(
+    auth_header: web::Header<Authorization>,
+    options: web::Query<TouchOptions>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    let client: Client = db_pool.get().await.make_response(MyError::InternalError(
+        "request failed at creating database client, please try again",
+    ))?;
+
+    let user_tokens = derive_user_tokens(
+        &auth_header.email,
+        &auth_header.token,
+        &config.userdata_auth,
+    );
+    let (user_token, _) = resolve_user_token(&client, &user_tokens).await?;
+    let (discord_id, last_week, current, longest) =
+        db::touch_userdata(&client, &user_token, &SystemTime::now())
+            .await
+            .make_response(MyError::InternalError("Failed at marking you as active"))?
+            .ok_or_else(account_not_linked)?;
+
+    if options.streak {
+        record_touch(
+            &client,
+            &discord_id,
+            SyncStreak::from_columns(last_week, current, longest),
+            sync_week(unix_now(), config.streak_utc_offset),
+        )
+        .await?;
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// the user's stored progress and sync streak
+#[get("/progress")]
+pub async fn get_own_progress(
+    auth_header: web::Header<Authorization>,
+    options: web::Query<ViewOptions>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+    budget: RequestBudget,
+) -> Result<HttpResponse, MyError> {
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let user_tokens = derive_user_tokens(
+        &auth_header.email,
+        &auth_header.token,
+        &config.userdata_auth,
+    );
+    let (user_token, _) = resolve_user_token(&client, &user_tokens)
+        .await
+        .make_log(ErrorLogType::USER(LoggedUser::token(&user_tokens.v2)))
+        .await?;
+    let user_data = linked_userdata(&client, &budget, &user_token).await?;
+    let streak = get_sync_streak(&client, &user_data.discord_id)
+        .await?
+        .as_of(sync_week(unix_now(), config.streak_utc_offset));
+
+    let computed = options.computed().then(|| {
+        compute_fields(
+            &user_data,
+            &config.role_rules,
+            &config.streak_rules,
+            &streak,
+        )
+    });
+
+    Ok(HttpResponse::Ok().json(UserDataView {
+        data: ProgressResponse::new(user_data, streak),
+        computed,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct RolesExplanation {
+    rules: Vec<ExplainedRule>,
+}
+
+/// whether the user meets each rule and how far along they are, without the exact thresholds
+#[get("/roles/explain")]
+pub async fn explain_own_roles(
+    auth_header: web::Header<Authorization>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+    budget: RequestBudget,
+) -> Result<HttpResponse, MyError> {
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let user_tokens = derive_user_tokens(
+        &auth_header.email,
+        &auth_header.token,
+        &config.userdata_auth,
+    );
+    let (user_token, _) = resolve_user_token(&client, &user_tokens)
+        .await
+        .make_log(ErrorLogType::USER(LoggedUser::token(&user_tokens.v2)))
+        .await?;
+    let user_data = linked_userdata(&client, &budget, &user_token).await?;
+
+    let rules = trace_user_roles(&client, &config, &user_data).await?;
+    Ok(HttpResponse::Ok().json(RolesExplanation {
+        rules: rules.iter().map(explain_rule).collect(),
+    }))
+}
+
+/// every rule the user's roles are decided by, milestone and promo rules as well as streak rules
+async fn trace_user_roles(
+    client: &Client,
+    config: &crate::config::Config,
+    user_data: &UserData,
+) -> Result<Vec<RuleTrace>, MyError> {
+    let streak = get_sync_streak(client, &user_data.discord_id)
+        .await?
+        .as_of(sync_week(unix_now(), config.streak_utc_offset));
+
+    Ok(trace_rules(config, user_data, &streak))
+}
+
+/// `trace_user_roles` with the streak at hand
+fn trace_rules(
+    config: &crate::config::Config,
+    user_data: &UserData,
+    streak: &SyncStreak,
+) -> Vec<RuleTrace> {
+    let now = SystemTime::now();
+    let pauses = role_pauses().lock().unwrap();
+    let mut rules = compute_earned_roles_with_trace(
+        user_data,
+        &config.role_rules,
+        &promo_rules().lock().unwrap(),
+        &pauses,
+        &DEPRECATED_FIELDS,
+        now,
+    )
+    .rules;
+    rules.append(&mut trace_streak_rules(
+        &config.streak_rules,
+        streak,
+        &pauses,
+        now,
+    ));
+
+    rules
+}
+
+/// what a sync's response says about the roles, every endpoint that syncs answers with the same
+struct SyncedRoles {
+    message: String,
+    /// the gained roles per guild
+    roles: Vec<GuildRoles>,
+    gained_roles: Vec<String>,
+    closest_miss: Option<ClosestMiss>,
+}
+
+/// the roles `handle_roles` gained, described for the response
+fn synced_roles(
+    config: &crate::config::Config,
+    user_data: &UserData,
+    streak: &SyncStreak,
+    gained: &[EarnedRole],
+) -> SyncedRoles {
+    // only a sync that gained nothing is told what it came closest to, from the rules
+    // `/roles/explain` shows
+    let closest_miss = gained
+        .is_empty()
+        .then(|| closest_miss(&trace_rules(config, user_data, streak), user_data))
+        .flatten();
+
+    describe_synced_roles(
+        gained,
+        &config.guild_names,
+        config.gained_roles_listed,
+        closest_miss,
+    )
+}
+
+fn describe_synced_roles(
+    gained: &[EarnedRole],
+    guild_names: &HashMap<u64, String>,
+    listed: usize,
+    closest_miss: Option<ClosestMiss>,
+) -> SyncedRoles {
+    let roles = group_by_guild(&[(C2SGUILD, gained)], guild_names);
+
+    SyncedRoles {
+        message: gained_roles_message(&roles, listed, closest_miss.as_ref()),
+        roles,
+        gained_roles: role_names(gained),
+        closest_miss,
+    }
+}
+
+/// Leaves the roles of a sync to the debounce worker, it evaluates them once the user hasn't synced
+/// for `window` so several syncs in a row only talk to Discord once.
+fn defer_roles(
+    updated_data: UserData,
+    reconcile: bool,
+    skipped: Vec<ValidationIssue>,
+    window: Duration,
+) -> UserResponse {
+    let discord_id = updated_data.discord_id;
+    pending_evaluations()
+        .lock()
+        .unwrap()
+        .request(&discord_id, reconcile, window, Instant::now());
+
+    UserResponse {
+        message: "Your progress was synced, your roles will be updated in a moment".to_owned(),
+        roles: Vec::new(),
+        gained_roles: Vec::new(),
+        discord_id,
+        withheld: Vec::new(),
+        skipped,
+        closest_miss: None,
+        removed_roles: Vec::new(),
+        roles_pending: true,
+        timings: None,
+    }
+}
+
+const DEBOUNCED_LOG_SUFFIX: &str = " (after a debounced sync)";
+
+/// what the roles of a sync of the user are decided by
+fn sync_roles<'a>(
+    config: &'a crate::config::Config,
+    streak: &SyncStreak,
+    mode: RoleSyncMode,
+) -> SyncRoles<'a> {
+    SyncRoles {
+        role_rules: &config.role_rules,
+        streak_roles: streak_roles(&config.streak_rules, streak),
+        managed: managed_roles(
+            &config.role_rules,
+            &promo_rules().lock().unwrap(),
+            &config.streak_rules,
+        ),
+        mode,
+    }
+}
+
+/// only reconciling syncs take roles away, the others have nothing to log
+async fn log_removed_roles(
+    discord_id: &str,
+    removed: &[EarnedRole],
+    log_suffix: &str,
+    listed: usize,
+) {
+    if removed.is_empty() {
+        return;
+    }
+
+    webhook_log_for_user(discord_id, LOG::SUCCESSFUL, || {
+        removed_roles_log(discord_id, removed, log_suffix, listed)
+    })
+    .await;
+}
+
+/// Without `partial` a payload with an invalid field is rejected as a whole, with it the fields
+/// that can't be written are skipped and keep their stored values.
+fn sync_payload(
+    current: &UserData,
+    payload: UpdateUserData,
+    partial: bool,
+) -> Result<(UpdateUserData, Vec<ValidationIssue>), MyError> {
+    if !partial {
+        payload.validate().map_err(|issues| {
+            MyError::BadRequest(
+                "The progress values aren't valid, send partial=true to skip the invalid fields",
+            )
+            .with_code("INVALID_PROGRESS")
+            .with_issues(issues)
+        })?;
+        return Ok((payload, Vec::new()));
+    }
+
+    let partial = skip_invalid_fields(current, &payload).ok_or(
+        MyError::BadRequest(
+            "The progress values aren't consistent even with the invalid fields skipped",
+        )
+        .with_code("INVALID_PROGRESS"),
+    )?;
+
+    Ok((partial.payload, partial.skipped))
+}
+
+/// a sync's write, it's run again on a new connection when the database turned read only
+struct SyncWrite<'a> {
+    budget: &'a RequestBudget,
+    user_token: &'a str,
+    beta_branch: &'a Option<bool>,
+    payload: PatchUserData,
+    partial: bool,
+}
+
+#[async_trait]
+impl PrimaryWrite<Client> for SyncWrite<'_> {
+    type Output = (UserData, Vec<ValidationIssue>);
+    type Error = db::UpdateError<MyError>;
+
+    async fn run(
+        &self,
+        client: &mut Client,
+    ) -> Result<(UserData, Vec<ValidationIssue>), db::UpdateError<MyError>> {
+        db::update_linked_userdata(
+            client,
+            self.budget,
+            self.user_token,
+            self.beta_branch,
+            |current| {
+                // the checks see the whole payload, only the fields that were sent are written
+                let (user_data, skipped) =
+                    sync_payload(current, self.payload.apply_to(current), self.partial)?;
+                Ok((self.payload.sent_values(&user_data), skipped))
+            },
+        )
+        .await
+    }
+
+    fn is_read_only(error: &db::UpdateError<MyError>) -> bool {
+        matches!(error, db::UpdateError::ReadOnly(_))
+    }
+}
+
+/// Writes the sync in one transaction with making sure the user is still linked, the payload is
+/// checked against the row as it's locked. A conflict with a concurrent request is answered with
+/// a code the client can retry on and isn't logged, so is a database that's still read only after
+/// retrying on a new connection. `client` is the new connection afterwards.
+async fn write_sync(
+    db_pool: &Pool,
+    client: &mut Client,
+    budget: &RequestBudget,
+    user_token: &str,
+    beta_branch: &Option<bool>,
+    payload: PatchUserData,
+    partial: bool,
+) -> Result<(UserData, Vec<ValidationIssue>), MyError> {
+    let write = SyncWrite {
+        budget,
+        user_token,
+        beta_branch,
+        payload,
+        partial,
+    };
+    match write_on_primary(db_pool, client, &write, read_only_state()).await {
+        Ok(synced) => Ok(synced),
+        Err(db::UpdateError::NotFound) => Err(account_not_linked()),
+        Err(db::UpdateError::Rejected(error)) => Err(error),
+        Err(db::UpdateError::Conflict(error)) => {
+            println!("{:?}", error);
+            Err(sync_conflict())
+        }
+        Err(db::UpdateError::ReadOnly(error)) => {
+            println!("{:?}", error);
+            Err(read_only_unavailable(
+                &read_only_state().lock().unwrap(),
+                Instant::now(),
+            ))
+        }
+        Err(db::UpdateError::Database(error)) => {
+            Err(error)
+                .make_response(MyError::InternalError(
+                    "The request has unfortunately failed the update",
+                ))
+                .make_log(ErrorLogType::USER(LoggedUser::token(user_token)))
+                .await
+        }
+    }
+}
+
+/// How the C2S guild's nickname policy applies to the user, `None` leaves their nickname alone. It's
+/// also left alone when their opt out can't be read, rather than taking a wanted prefix away.
+async fn member_nickname<'a>(
+    client: &Client,
+    config: &'a crate::config::Config,
+    discord_id: &str,
+) -> Option<MemberNickname<'a>> {
+    let policy = config
+        .nickname_policies
+        .get(&C2SGUILD)
+        .filter(|policy| policy.enabled)?;
+    let prefixed = db::get_nickname_prefix(client, discord_id)
+        .await
+        .ok()
+        .flatten()?;
+
+    Some(MemberNickname { policy, prefixed })
+}
+
+fn empty_patch() -> MyError {
+    MyError::BadRequest("The body has to have at least one progress field to update")
+        .with_code("EMPTY_PATCH")
+}
+
+fn sync_conflict() -> MyError {
+    MyError::Conflict("Your data was being changed by another request, please try again")
+        .with_code("SYNC_CONFLICT")
+}
+
+/// the user's data, as long as the credentials are their primary and not their recovery ones, the
+/// row is under the v2 token afterwards
+async fn primary_userdata(
+    client: &Client,
+    budget: &RequestBudget,
+    derived_tokens: &DerivedTokens,
+) -> Result<UserData, MyError> {
+    let (user_token, credential) = resolve_user_token(client, derived_tokens)
+        .await
+        .make_log(ErrorLogType::USER(LoggedUser::token(&derived_tokens.v2)))
+        .await?;
+    if credential == Credential::Recovery {
+        return Err(MyError::Forbidden(
+            "The recovery credential can only be managed with your primary credential",
+        )
+        .with_code("PRIMARY_CREDENTIAL_REQUIRED"));
+    }
+
+    linked_userdata(client, budget, &user_token).await
+}
+
+/// lets community tools show a "linked" badge, users that haven't opted in are reported as unlinked
+#[get("/linked/{discord_id}")]
+pub async fn public_linked(
+    discord_id: web::Path<String>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, MyError> {
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let public_link_visible = db::get_public_link_visible(&client, &discord_id)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at checking whether the user is linked",
+        ))?;
+
+    Ok(HttpResponse::Ok().json(PublicLinkStatus::from_visibility(public_link_visible)))
+}
+
+#[derive(Serialize)]
+pub struct ReadinessResponse {
+    database: bool,
+    /// milliseconds until Discord calls resume after a global rate limit
+    discord_paused_for: Option<u128>,
+    /// milliseconds the database stays degraded for after a write was still refused as read only
+    database_read_only_for: Option<u128>,
+    /// how often writes were refused as read only since the start, e.g. during failovers
+    read_only_encounters: u64,
+    /// the schema is newer than this binary, mutations are refused until it's upgraded
+    compatibility_mode: bool,
+}
+
+#[get("/ready")]
+pub async fn ready(db_pool: web::Data<Pool>, schema: web::Data<SchemaState>) -> HttpResponse {
+    let database = observe(&db_pool).await.database;
+    let discord_paused_for = discord_pause()
+        .lock()
+        .unwrap()
+        .remaining(std::time::Instant::now())
+        .map(|remaining| remaining.as_millis());
+    let (database_read_only_for, read_only_encounters) = {
+        let read_only = read_only_state().lock().unwrap();
+        (
+            read_only
+                .degraded_for(std::time::Instant::now())
+                .map(|remaining| remaining.as_millis()),
+            read_only.encounters(),
+        )
+    };
+
+    let readiness = ReadinessResponse {
+        database,
+        discord_paused_for,
+        database_read_only_for,
+        read_only_encounters,
+        compatibility_mode: schema.compatibility_mode,
+    };
+    if database {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+/// the build and the schema versions it runs against, e.g. to check a deploy went through
+#[derive(Serialize)]
+pub struct VersionResponse {
+    version: &'static str,
+    #[serde(flatten)]
+    schema: SchemaState,
+}
+
+#[get("/version")]
+pub async fn version(schema: web::Data<SchemaState>) -> HttpResponse {
+    HttpResponse::Ok().json(VersionResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        schema: **schema,
+    })
+}
+
+/// a summary of the API's health for players wondering whether it's down
+#[get("/status")]
+pub async fn get_status(db_pool: web::Data<Pool>) -> HttpResponse {
+    HttpResponse::Ok().json(current_status(&db_pool).await)
+}
+
+#[get("/status.html")]
+pub async fn status_page(db_pool: web::Data<Pool>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(render_status_html(&current_status(&db_pool).await))
+}
+
+/// lets the game register the support code it shows players that can't see their email and token
+#[post("")]
+pub async fn register_support_code(
+    req: HttpRequest,
+    received_registration: web::Json<SupportCodeRegistration>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    let secret = req
+        .headers()
+        .get("X-Beta-Channel-Secret")
+        .and_then(|value| value.to_str().ok());
+    if config.beta_channel_secret.is_empty() || secret != Some(config.beta_channel_secret.as_str())
+    {
+        return Err(MyError::Forbidden(
+            "You are not allowed to register support codes",
+        ));
+    }
+
+    let registration = received_registration.into_inner();
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let user_tokens = derive_user_tokens(
+        &registration.email,
+        &registration.token,
+        &config.userdata_auth,
+    );
+    db::upsert_support_code(&client, &registration.support_code, &user_tokens)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at registering the support code",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    invalidate_support_code(support_code_cache(), &registration.support_code);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// A dry-run of the role handling, nothing is written and Discord isn't contacted. Without a config
+/// only the built-in rules are previewed.
+#[post("/roles/preview")]
+pub async fn preview_roles(
+    received_request: web::Json<RolesPreviewRequest>,
+    config: Option<web::Data<crate::config::Config>>,
+) -> Result<HttpResponse, MyError> {
+    let preview = received_request.into_inner();
+
+    let issues = validate_payload(&preview.data);
+    if !issues.is_empty() {
+        return Err(MyError::BadRequest("The progress values aren't valid")
+            .with_code("INVALID_PROGRESS")
+            .with_issues(issues));
+    }
+
+    let earned_roles = compute_earned_roles(
+        &apply_payload(&UserData::default(), &preview.data, preview.beta_tester),
+        config
+            .as_ref()
+            .map_or(&[][..], |config| &config.role_rules[..]),
+        &promo_rules().lock().unwrap(),
+        SystemTime::now(),
+    );
+
+    Ok(HttpResponse::Ok().json(earned_roles))
+}
+
+/// Checks a payload without syncing it, every problem is listed with a code tools can highlight
+/// the field by. With `previous` the payload is also checked against the progress it'd be synced on
+/// top of.
+#[post("/validate")]
+pub async fn validate_progress(
+    received_request: web::Json<ValidateProgressRequest>,
+) -> Result<HttpResponse, MyError> {
+    let request = received_request.into_inner();
+
+    let mut issues = check_payload_json(&request.data);
+    // syncs ignore unknown fields, so the values can still be checked next to them
+    if issues
+        .iter()
+        .all(|issue| issue.code == IssueCode::UnknownField)
+    {
+        match serde_json::from_value::<UpdateUserData>(request.data) {
+            Ok(payload) => {
+                issues.extend(validate_payload(&payload));
+                if let Some(previous) = &request.previous {
+                    let previous = apply_payload(&UserData::default(), previous, false);
+                    issues.extend(
+                        check_monotonic_fields(&previous, &payload)
+                            .iter()
+                            .map(MonotonicViolation::issue),
+                    );
+                }
+            }
+            Err(error) => issues.push(json_error_issue(&error)),
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(ValidationReport {
+        valid: issues.is_empty(),
+        issues,
+    }))
+}
+
+/// The JSON bodies of every scope are read with this, a body that doesn't match what the endpoint
+/// expects is answered with the issue serde ran into instead of actix' plain text.
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(json_error)
+}
+
+fn json_error(error: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    match error {
+        JsonPayloadError::Deserialize(error) if error.is_data() => {
+            MyError::BadRequest("The body doesn't match what the endpoint expects")
+                .with_code("INVALID_BODY")
+                .with_issues(vec![json_error_issue(&error)])
+                .into()
+        }
+        JsonPayloadError::Deserialize(error) => {
+            println!("{:?}", error);
+            MyError::BadRequest("The body isn't valid JSON")
+                .with_code("INVALID_JSON")
+                .into()
+        }
+        error => error.into(),
+    }
+}
+
+#[get("/users/{discord_id}/granted-roles")]
+pub async fn get_user_granted_roles(
+    req: HttpRequest,
+    discord_id: web::Path<String>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    verify_admin_access(&req, &config).await?;
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(GrantedRolesResponse {
+        granted_roles: get_granted_roles(&client, &discord_id).await?,
+        discord_id: discord_id.into_inner(),
+    }))
+}
+
+#[derive(Serialize)]
+pub struct RoleTraceResponse {
+    discord_id: String,
+    /// whether syncs are kept from changing the beta tester status
+    beta_tester_locked: bool,
+    rules: Vec<RuleTrace>,
+    /// only there with `?include=computed`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    computed: Option<ComputedFields>,
+}
+
+/// how every role rule was evaluated for the user, for "why didn't I get this role" tickets
+#[get("/users/{discord_id}/role-trace")]
+pub async fn get_user_role_trace(
+    req: HttpRequest,
+    discord_id: web::Path<String>,
+    options: web::Query<ViewOptions>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+    budget: RequestBudget,
+) -> Result<HttpResponse, MyError> {
+    verify_admin_access(&req, &config).await?;
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let user_data = db::get_userdata_by_id(&client, &budget, &discord_id)
+        .await
+        .make_response(MyError::NotFound(DISCORD_ID_NOT_LINKED))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let beta_tester_locked = db::get_beta_tester_locked(&client, &user_data.discord_id)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at getting the beta tester lock",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?
+        .unwrap_or(false);
+
+    let computed = if options.computed() {
+        Some(computed_fields(&client, &config, &user_data).await?)
+    } else {
+        None
+    };
+
+    Ok(HttpResponse::Ok().json(RoleTraceResponse {
+        rules: trace_user_roles(&client, &config, &user_data).await?,
+        discord_id: user_data.discord_id,
+        beta_tester_locked,
+        computed,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct DeletedUserResponse {
+    discord_id: String,
+    /// the row that was deleted
+    user: StoredProgress,
+    /// only there with `REMOVE_ROLES_ON_DELETE`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    roles: Option<RoleRemovalSummary>,
+}
+
+/// whether the id could be a Discord snowflake, anything else can't be linked with anyone
+fn is_plausible_snowflake(discord_id: &str) -> bool {
+    (17..=20).contains(&discord_id.len()) && discord_id.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+/// a page of GET admin/users, `next_cursor` is null on the last page
+#[derive(Serialize)]
+pub struct UserPage {
+    users: Vec<StoredProgress>,
+    next_cursor: Option<String>,
+}
+
+/// the cursor is the last discord id of the page, encoded so clients don't build their own
+fn encode_user_cursor(discord_id: &str) -> String {
+    base64::encode_config(discord_id, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode_user_cursor(cursor: &str) -> Result<String, MyError> {
+    base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .and_then(|discord_id| String::from_utf8(discord_id).ok())
+        .filter(|discord_id| is_plausible_snowflake(discord_id))
+        .ok_or_else(|| {
+            MyError::BadRequest("The cursor has to be the next_cursor of a previous page")
+                .with_code("INVALID_CURSOR")
+        })
+}
+
+/// `rows` is fetched with one row more than the page has, a page without it is the last one
+fn user_page(mut rows: Vec<UserData>, limit: i64) -> UserPage {
+    let next_cursor = if rows.len() as i64 > limit {
+        rows.truncate(limit as usize);
+        rows.last()
+            .map(|user_data| encode_user_cursor(&user_data.discord_id))
+    } else {
+        None
+    };
+
+    UserPage {
+        users: rows.into_iter().map(StoredProgress::from).collect(),
+        next_cursor,
+    }
+}
+
+/// every linked user a page at a time, without their tokens
+#[get("/users")]
+pub async fn list_users(
+    _admin_key: AdminKey,
+    query: web::Query<UserListQuery>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    let limit = query
+        .limit
+        .unwrap_or(config.admin_page_limit)
+        .clamp(1, config.admin_page_limit);
+    let after = query
+        .cursor
+        .as_deref()
+        .map(decode_user_cursor)
+        .transpose()?;
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+    let rows = db::list_userdata(&client, limit + 1, after.as_deref())
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at listing the users, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(user_page(rows, limit)))
+}
+
+/// a user's stored progress and sync streak the way `GET user/progress` shows it to them
+#[get("/users/{discord_id}")]
+pub async fn get_user_by_id(
+    _admin_key: AdminKey,
+    discord_id: web::Path<String>,
+    options: web::Query<ViewOptions>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+    budget: RequestBudget,
+) -> Result<HttpResponse, MyError> {
+    if !is_plausible_snowflake(&discord_id) {
+        return Err(
+            MyError::BadRequest("The discord id must be a valid snowflake")
+                .with_code("INVALID_DISCORD_ID"),
+        );
+    }
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let user_data = match db::get_userdata_by_id(&client, &budget, &discord_id).await {
+        Ok(user_data) => user_data,
+        // nobody being linked with the id isn't worth a log
+        Err(db::LookupError::NotFound) => return Err(MyError::NotFound(DISCORD_ID_NOT_LINKED)),
+        Err(error) => {
+            Err(error)
+                .make_response(MyError::InternalError(
+                    "Failed at retrieving the user, please try again",
+                ))
+                .make_log(ErrorLogType::INTERNAL)
+                .await?
+        }
+    };
+    let streak = get_sync_streak(&client, &user_data.discord_id)
+        .await?
+        .as_of(sync_week(unix_now(), config.streak_utc_offset));
+    let computed = options.computed().then(|| {
+        compute_fields(
+            &user_data,
+            &config.role_rules,
+            &config.streak_rules,
+            &streak,
+        )
+    });
+
+    Ok(HttpResponse::Ok().json(UserDataView {
+        data: ProgressResponse::new(user_data, streak),
+        computed,
+    }))
+}
+
+/// unlinks a user for them, e.g. when they lost their game credentials or asked for their data to
+/// be purged
+#[delete("/users/{discord_id}")]
+pub async fn delete_user_by_id(
+    admin_key: AdminKey,
+    discord_id: web::Path<String>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+    budget: RequestBudget,
+) -> Result<HttpResponse, MyError> {
+    if !is_plausible_snowflake(&discord_id) {
+        return Err(
+            MyError::BadRequest("The discord id must be a valid snowflake")
+                .with_code("INVALID_DISCORD_ID"),
+        );
+    }
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let (deleted_data, roles) = delete_link(
+        &client,
+        &config,
+        &budget,
+        DeletedLink::DiscordId(&discord_id),
+    )
+    .await?;
+
+    webhook_log(
+        format!(
+            "admin {} force-deleted the userdata bound to {} (id '{}')",
+            admin_key.label,
+            discord_mention(&deleted_data.discord_id),
+            deleted_data.discord_id
+        ),
+        LOG::SUCCESSFUL,
+    )
+    .await;
+    let mut event = AuditEvent::new(
+        admin_key,
+        RouteId::DeleteUserById,
+        json!({ "roles": &roles }),
+    );
+    event.target_discord_id = Some(deleted_data.discord_id.clone());
+    security_log(&db_pool, &config, event).await;
+
+    Ok(HttpResponse::Ok().json(DeletedUserResponse {
+        discord_id: deleted_data.discord_id.clone(),
+        user: deleted_data.into(),
+        roles,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct ResyncResponse {
+    discord_id: String,
+    /// the names of the roles the member was given
+    added_roles: Vec<String>,
+    /// the names of the managed roles the member lost
+    removed_roles: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    withheld: Vec<WithheldRole>,
+}
+
+/// Brings a user's roles in line with their stored progress, e.g. after their inflated progress was
+/// corrected or they moved to a fresh account. It reconciles unless `reconcile=false` is given.
+#[post("/users/{discord_id}/resync")]
+pub async fn resync_user_roles(
+    admin_key: AdminKey,
+    discord_id: web::Path<String>,
+    options: web::Query<ResyncOptions>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+    budget: RequestBudget,
+) -> Result<HttpResponse, MyError> {
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let user_data = db::get_userdata_by_id(&client, &budget, &discord_id)
+        .await
+        .make_response(MyError::NotFound(DISCORD_ID_NOT_LINKED))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+    let streak = get_sync_streak(&client, &user_data.discord_id)
+        .await?
+        .as_of(sync_week(unix_now(), config.streak_utc_offset));
+    let role_sync = handle_roles(
+        &user_data,
+        sync_roles(
+            &config,
+            &streak,
+            RoleSyncMode::reconciling(options.reconcile),
+        ),
+        member_nickname(&client, &config, &user_data.discord_id).await,
+        config.discord_token.clone(),
+        &budget,
+        &mut SyncTimings::start(Instant::now()),
+    )
+    .await
+    .make_response(
+        MyError::InternalError("The role-handling process has failed")
+            .with_code("ROLE_HANDLING_FAILED"),
+    )
+    .make_log(ErrorLogType::INTERNAL)
+    .await?;
+    record_promo_grants(&client, &user_data.discord_id, &role_sync.gained).await;
+    record_granted_roles(&client, &user_data.discord_id, &role_sync).await;
+
+    let added_roles = role_names(&role_sync.gained);
+    let removed_roles = role_names(&role_sync.removed);
+    record_role_grants(&added_roles);
+    if !added_roles.is_empty() {
+        let groups = group_by_guild(&[(C2SGUILD, &role_sync.gained)], &config.guild_names);
+        webhook_log_for_user(&user_data.discord_id, LOG::SUCCESSFUL, || {
+            gained_roles_log(
+                &user_data.discord_id,
+                &groups,
+                " (resynced by an admin)",
+                config.gained_roles_listed,
+            )
+        })
+        .await;
+    }
+    log_removed_roles(
+        &user_data.discord_id,
+        &role_sync.removed,
+        " (resynced by an admin)",
+        config.gained_roles_listed,
+    )
+    .await;
+
+    let mut event = AuditEvent::new(
+        admin_key,
+        RouteId::ResyncUserRoles,
+        json!({
+            "reconcile": options.reconcile,
+            "added_roles": &added_roles,
+            "removed_roles": &removed_roles,
+        }),
+    );
+    event.target_discord_id = Some(user_data.discord_id.clone());
+    security_log(&db_pool, &config, event).await;
+
+    Ok(HttpResponse::Ok().json(ResyncResponse {
+        discord_id: user_data.discord_id,
+        added_roles,
+        removed_roles,
+        withheld: role_sync.withheld,
+    }))
+}
+
+/// Grants every linked user the roles their stored progress earns, e.g. after a milestone role was
+/// added. It only adds roles and runs in the background, the response has the job to poll.
+#[post("/roles/resync")]
+pub async fn resync_all_roles(
+    admin_key: AdminKey,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    let job = resync_jobs()
+        .lock()
+        .unwrap()
+        .start(ResyncJob::new(new_job_id(), unix_now()), Instant::now())
+        .ok_or(
+            MyError::Conflict("A role resync is already running, poll it until it's done")
+                .with_code("RESYNC_RUNNING"),
+        )?;
+    rt::spawn(run_role_resync(
+        db_pool.clone(),
+        config.clone(),
+        job.clone(),
+    ));
+
+    security_log(
+        &db_pool,
+        &config,
+        AuditEvent::new(
+            admin_key,
+            RouteId::ResyncAllRoles,
+            json!({ "job_id": &job.job_id }),
+        ),
+    )
+    .await;
+
+    Ok(HttpResponse::Accepted().json(job))
+}
+
+#[get("/roles/resync/{job_id}")]
+pub async fn get_role_resync(
+    _admin_key: AdminKey,
+    job_id: web::Path<String>,
+) -> Result<HttpResponse, MyError> {
+    let job = resync_jobs()
+        .lock()
+        .unwrap()
+        .get(&job_id, Instant::now())
+        .ok_or(MyError::NotFound("There's no role resync with this job id"))?;
+
+    Ok(HttpResponse::Ok().json(job))
+}
+
+async fn run_role_resync(
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+    mut job: ResyncJob,
+) {
+    let abort_reason = resync_every_user(&db_pool, &config, &mut job).await.err();
+    job.finish(abort_reason, unix_now());
+    resync_jobs().lock().unwrap().update(&job, Instant::now());
+
+    let (message, log) = job.summary_log();
+    webhook_log(message, log).await;
+}
+
+/// Pages through every linked user with `bulk_resync_delay_ms` between them. A user Discord rate
+/// limited us on is retried once the limit is over, the error is why it gave up.
+async fn resync_every_user(
+    db_pool: &Pool,
+    config: &crate::config::Config,
+    job: &mut ResyncJob,
+) -> Result<(), &'static str> {
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "role resync failed at creating database client",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await
+        .map_err(|_| "the database couldn't be reached")?;
+    let budget = RequestBudget::unlimited();
+    let mut rate_limits = RateLimitStreak::default();
+    let mut after: Option<String> = None;
+
+    loop {
+        let page = db::list_userdata(&client, RESYNC_PAGE_SIZE, after.as_deref())
+            .await
+            .make_response(MyError::InternalError(
+                "role resync failed at listing the users",
+            ))
+            .make_log(ErrorLogType::INTERNAL)
+            .await
+            .map_err(|_| "the users couldn't be listed")?;
+
+        for user_data in &page {
+            loop {
+                let resynced = resync_user(&client, config, &budget, user_data).await;
+                let rate_limited = discord_pause().lock().unwrap().remaining(Instant::now());
+                match (resynced, rate_limited) {
+                    (Err(_), Some(remaining)) => {
+                        if rate_limits.record() {
+                            return Err("Discord kept rate limiting us");
+                        }
+                        time::sleep(remaining).await;
+                    }
+                    (resynced, _) => {
+                        rate_limits.reset();
+                        job.processed += 1;
+                        match resynced {
+                            Ok(gained) => job.roles_granted += gained,
+                            Err(_) => job.failed += 1,
+                        }
+                        break;
+                    }
+                }
+            }
+            resync_jobs().lock().unwrap().update(job, Instant::now());
+            time::sleep(Duration::from_millis(config.bulk_resync_delay_ms)).await;
+        }
+
+        if (page.len() as i64) < RESYNC_PAGE_SIZE {
+            return Ok(());
+        }
+        after = page.last().map(|user_data| user_data.discord_id.clone());
+    }
+}
+
+/// a single user of a resync, returns how many roles they gained
+async fn resync_user(
+    client: &Client,
+    config: &crate::config::Config,
+    budget: &RequestBudget,
+    user_data: &UserData,
+) -> Result<usize, MyError> {
+    let role_sync =
+        evaluate_roles(client, config, budget, user_data, RoleSyncMode::Additive).await?;
+
+    Ok(role_sync.gained.len())
+}
+
+/// syncs the roles of stored progress outside of a sync, the streak is the one the last sync left
+async fn evaluate_roles(
+    client: &Client,
+    config: &crate::config::Config,
+    budget: &RequestBudget,
+    user_data: &UserData,
+    mode: RoleSyncMode,
+) -> Result<RoleSync, MyError> {
+    let streak = get_sync_streak(client, &user_data.discord_id)
+        .await?
+        .as_of(sync_week(unix_now(), config.streak_utc_offset));
+    let role_sync = handle_roles(
+        user_data,
+        sync_roles(config, &streak, mode),
+        member_nickname(client, config, &user_data.discord_id).await,
+        config.discord_token.clone(),
+        budget,
+        &mut SyncTimings::start(Instant::now()),
+    )
+    .await?;
+    record_promo_grants(client, &user_data.discord_id, &role_sync.gained).await;
+    record_granted_roles(client, &user_data.discord_id, &role_sync).await;
+    record_role_grants(&role_names(&role_sync.gained));
+
+    Ok(role_sync)
+}
+
+/// Runs the role evaluation of debounced syncs once the user stopped syncing, against their stored
+/// data. A user who was deleted since has nothing to evaluate. It's logged like the
+/// sync would have been.
+pub async fn evaluate_deferred_roles(
+    db_pool: &Pool,
+    config: &crate::config::Config,
+    evaluation: PendingEvaluation,
+) -> Result<(), MyError> {
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "debounced role evaluation failed at creating database client",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+    let budget = RequestBudget::unlimited();
+    let user_data = match db::get_userdata_by_id(&client, &budget, &evaluation.discord_id).await {
+        Ok(user_data) => user_data,
+        Err(db::LookupError::NotFound) => return Ok(()),
+        Err(db::LookupError::Database(error)) => {
+            return Err(error)
+                .make_response(MyError::InternalError(
+                    "The debounced role evaluation failed at reading the user",
+                ))
+                .make_log(ErrorLogType::INTERNAL)
+                .await
+        }
+    };
+
+    let role_sync = evaluate_roles(
+        &client,
+        config,
+        &budget,
+        &user_data,
+        RoleSyncMode::reconciling(evaluation.reconcile),
+    )
+    .await
+    .make_response(
+        MyError::InternalError("The debounced role-handling process has failed")
+            .with_code("ROLE_HANDLING_FAILED"),
+    )
+    .make_log(ErrorLogType::USER(
+        LoggedUser::token(&user_data.token).with_discord_id(&user_data.discord_id),
+    ))
+    .await?;
+
+    let groups = group_by_guild(&[(C2SGUILD, &role_sync.gained)], &config.guild_names);
+    webhook_log_for_user(
+        &user_data.discord_id,
+        gained_roles_log_type(&groups),
+        || {
+            gained_roles_log(
+                &user_data.discord_id,
+                &groups,
+                DEBOUNCED_LOG_SUFFIX,
+                config.gained_roles_listed,
+            )
+        },
+    )
+    .await;
+    log_removed_roles(
+        &user_data.discord_id,
+        &role_sync.removed,
+        DEBOUNCED_LOG_SUFFIX,
+        config.gained_roles_listed,
+    )
+    .await;
+
+    Ok(())
+}
+
+/// sets the user's beta tester status by hand, e.g. for trusted members who sync from stable.
+/// Locking it keeps syncs from either channel from changing it until it's unlocked.
+#[patch("/users/{discord_id}/beta")]
+pub async fn update_beta_tester(
+    req: HttpRequest,
+    discord_id: web::Path<String>,
+    received_request: web::Json<BetaTesterUpdate>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    let admin_key = verify_admin_access(&req, &config).await?;
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let update = received_request.into_inner();
+    let status = db::update_beta_tester(&client, &discord_id, &update.beta_tester, &update.locked)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at updating the beta tester status",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?
+        .ok_or(MyError::NotFound(DISCORD_ID_NOT_LINKED))?;
+
+    let mut event = AuditEvent::new(
+        admin_key,
+        RouteId::UpdateBetaTester,
+        json!({ "beta_tester": status.beta_tester, "locked": status.beta_tester_locked }),
+    );
+    event.target_discord_id = Some(status.discord_id.clone());
+    security_log(&db_pool, &config, event).await;
+
+    Ok(HttpResponse::Ok().json(status))
+}
+
+#[derive(Serialize)]
+pub struct FingerprintSearchResponse {
+    fingerprint: String,
+    /// every account with the fingerprint, there can be more than one
+    accounts: Vec<FingerprintMatch>,
+}
+
+/// finds the accounts a token fingerprint from the logs or a report could belong to
+#[get("/users/by-fingerprint/{fingerprint}")]
+pub async fn find_users_by_fingerprint(
+    req: HttpRequest,
+    fingerprint: web::Path<String>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    verify_admin_access(&req, &config).await?;
+
+    let fingerprint = fingerprint.into_inner().to_ascii_lowercase();
+    if !is_token_fingerprint(&fingerprint) {
+        return Err(MyError::BadRequest(
+            "A token fingerprint is the first 8 hex characters of a token",
+        ));
+    }
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let accounts = db::get_userdata_by_fingerprint(&client, &fingerprint)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at searching accounts by fingerprint",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(FingerprintSearchResponse {
+        fingerprint,
+        accounts,
+    }))
+}
+
+#[post("/users/{discord_id}/simulate")]
+pub async fn simulate_user(
+    req: HttpRequest,
+    discord_id: web::Path<String>,
+    received_request: web::Json<SimulationRequest>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+    budget: RequestBudget,
+) -> Result<HttpResponse, MyError> {
+    verify_admin_access(&req, &config).await?;
+
+    let discord_id = discord_id.into_inner();
+    let simulation = received_request.into_inner();
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let current_state = match simulation.as_of {
+        Some(as_of) => {
+            let as_of = SystemTime::UNIX_EPOCH + Duration::from_millis(as_of);
+            db::get_userdata_snapshot(&client, &budget, &discord_id, &as_of)
+                .await
+                .make_response(MyError::BadRequest(
+                    "There is no snapshot of this user from before the given time",
+                ))?
+        }
+        None => db::get_userdata_by_id(&client, &budget, &discord_id)
+            .await
+            .make_response(MyError::BadRequest("There is no user linked to this id"))?,
+    };
+
+    let beta_tester = simulation.beta_tester.unwrap_or(current_state.beta_tester);
+    let report = evaluate_payload(
+        &current_state,
+        &simulation.data,
+        beta_tester,
+        &config.role_rules,
+    );
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// the portable records are off until the instances moving users between them share a secret
+fn portability_secret(config: &crate::config::Config) -> Result<&str, MyError> {
+    match config.portability_secret.as_str() {
+        "" => Err(MyError::Unavailable(
+            "Portable records are disabled, PORTABILITY_SECRET isn't set",
+        )
+        .with_code("PORTABILITY_DISABLED")),
+        secret => Ok(secret),
+    }
+}
+
+/// an import never rebinds anything, unlike creating a user both conflicts are answered with a 409
+fn portable_conflict(conflict: db::LinkConflict) -> MyError {
+    match conflict {
+        db::LinkConflict::Token => {
+            MyError::Conflict("The record's token is already linked on this instance")
+                .with_code("ALREADY_LINKED")
+        }
+        db::LinkConflict::DiscordId => {
+            MyError::Conflict("The discord id is already linked on this instance")
+                .with_code("DISCORD_ID_TAKEN")
+        }
+    }
+}
+
+/// the user with their granted roles and snapshots, signed so another instance can import them
+#[get("/users/{discord_id}/portable")]
+pub async fn export_portable_user(
+    req: HttpRequest,
+    discord_id: web::Path<String>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+    budget: RequestBudget,
+) -> Result<HttpResponse, MyError> {
+    let admin_key = verify_admin_access(&req, &config).await?;
+    let secret = portability_secret(&config)?;
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let user_data = db::get_userdata_by_id(&client, &budget, &discord_id)
+        .await
+        .make_response(MyError::NotFound(DISCORD_ID_NOT_LINKED))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+    let granted_roles = get_granted_roles(&client, &user_data.discord_id).await?;
+    let history = db::get_userdata_snapshots(&client, &user_data.discord_id)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at retrieving the user's snapshots",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let blob = portability::seal(&user_data, &granted_roles, &history, secret)
+        .make_response(MyError::InternalError(
+            "Failed at sealing the portable record",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    // the blob carries the user's token, so every export is reported
+    let mut event = AuditEvent::new(
+        admin_key,
+        RouteId::ExportPortableUser,
+        json!({ "snapshots": history.len() }),
+    );
+    event.target_discord_id = Some(user_data.discord_id);
+    security_log(&db_pool, &config, event).await;
+
+    Ok(HttpResponse::Ok().json(blob))
+}
+
+/// Imports a record another instance exported after checking every section's signature. Nothing
+/// already linked is overwritten, a taken token or discord id is a conflict.
+#[post("/users/portable")]
+pub async fn import_portable_user(
+    req: HttpRequest,
+    received_request: web::Json<PortableImportRequest>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    let admin_key = verify_admin_access(&req, &config).await?;
+    let secret = portability_secret(&config)?;
+
+    let request = received_request.into_inner();
+    let mut opened = portability::open(&request.blob, secret)
+        .map_err(|error| MyError::BadRequest(error.message()).with_code(error.code()))?;
+    let remapped_from = match request.discord_id {
+        Some(discord_id) if discord_id != opened.user.discord_id => {
+            for snapshot in &mut opened.history {
+                snapshot.discord_id = discord_id.clone();
+            }
+            Some(std::mem::replace(&mut opened.user.discord_id, discord_id))
+        }
+        _ => None,
+    };
+
+    let mut client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let granted_roles = serde_json::to_string(&opened.granted_roles).unwrap_or_default();
+    match db::import_portable_user(&mut client, &opened.user, &granted_roles, &opened.history).await
+    {
+        Err(db::CreateError::Conflict(conflict)) => return Err(portable_conflict(conflict)),
+        imported => {
+            imported
+                .make_response(MyError::InternalError(
+                    "Failed at importing the portable record",
+                ))
+                .make_log(ErrorLogType::INTERNAL)
+                .await?
+        }
+    };
+    negative_cache().forget(&opened.user.token);
+
+    let mut event = AuditEvent::new(
+        admin_key,
+        RouteId::ImportPortableUser,
+        json!({ "snapshots": opened.history.len(), "remapped_from": remapped_from }),
+    );
+    event.target_discord_id = Some(opened.user.discord_id.clone());
+    security_log(&db_pool, &config, event).await;
+
+    Ok(HttpResponse::Created().json(opened.user))
+}
+
+#[derive(Serialize)]
+pub struct ImportResponse {
+    job_id: String,
+    imported: usize,
+    failures: Vec<ImportFailure>,
+}
+
+#[post("/import")]
+pub async fn import_users(
+    req: HttpRequest,
+    received_rows: web::Json<Vec<Value>>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+    budget: RequestBudget,
+) -> Result<HttpResponse, MyError> {
+    let admin_key = verify_admin_access(&req, &config).await?;
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let job_id = new_job_id();
+    let mut batch = ImportBatch::default();
+    let mut imported = 0;
+    let mut failures: Vec<ImportFailure> = Vec::new();
+
+    for (index, raw_row) in received_rows.into_inner().into_iter().enumerate() {
+        let line = index + 1;
+        let classified = match batch.classify_row(line, &raw_row) {
+            Ok(row) => match linked_duplicate(&client, &budget, &row).await {
+                Some(reason) => Err(reason),
+                None => Ok(row),
+            },
+            Err(reason) => Err(reason),
+        };
+
+        match classified {
+            Ok(row) => {
+                let created = db::create_userdata(
+                    &client,
+                    &budget,
+                    &row.token,
+                    &row.discord_id,
+                    &row.beta_tester,
+                    row.data,
+                )
+                .await;
+                // a user that was linked while the import ran is a failed row like any duplicate
+                let reason = match created {
+                    Ok(_) => None,
+                    Err(db::CreateError::Conflict(db::LinkConflict::Token)) => {
+                        Some(ImportFailureReason::DuplicateToken)
+                    }
+                    Err(db::CreateError::Conflict(db::LinkConflict::DiscordId)) => {
+                        Some(ImportFailureReason::DuplicateDiscordId)
+                    }
+                    // the rows before it are already imported, so the import carries on without it
+                    Err(error) => {
+                        let _ = Err::<(), _>(error)
+                            .make_response(MyError::InternalError(
+                                "The import has unfortunately failed at creating an account",
+                            ))
+                            .make_log(ErrorLogType::INTERNAL)
+                            .await;
+                        Some(ImportFailureReason::DatabaseError)
+                    }
+                };
+                if let Some(reason) = reason {
+                    failures.push(ImportFailure {
+                        line,
+                        reason,
+                        row: raw_row.to_string(),
+                    });
+                    continue;
+                }
+                // imports move accounts to new tokens on purpose, they aren't token sharing. The
+                // account is created either way, without its transition it can at worst be flagged
+                let _ = db::create_token_transition(&client, &row.discord_id, &row.token)
+                    .await
+                    .make_response(MyError::InternalError(
+                        "The import has unfortunately failed at recording a token transition",
+                    ))
+                    .make_log(ErrorLogType::INTERNAL)
+                    .await;
+                negative_cache().forget(&row.token);
+                imported += 1;
+            }
+            Err(reason) => failures.push(ImportFailure {
+                line,
+                reason,
+                row: raw_row.to_string(),
+            }),
+        }
+    }
+
+    for failure in &failures {
+        db::create_import_failure(
+            &client,
+            &job_id,
+            failure.line as i32,
+            &serde_json::to_string(&failure.reason).unwrap_or_default(),
+            &failure.row,
+        )
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at storing the import's failed rows",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+    }
+
+    webhook_log(
+        format!(
+            "import {} created {} users with {} failed rows",
+            job_id,
+            imported,
+            failures.len()
+        ),
+        LOG::SUCCESSFUL,
+    )
+    .await;
+    security_log(
+        &db_pool,
+        &config,
+        AuditEvent::new(
+            admin_key,
+            RouteId::ImportUsers,
+            json!({ "job_id": job_id, "imported": imported, "failed": failures.len() }),
+        ),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(ImportResponse {
+        job_id,
+        imported,
+        failures,
+    }))
+}
+
+/// Why the row can't be imported because of the users that are already linked. A lookup that
+/// fails is a failed row too, it can't be told apart from a duplicate.
+async fn linked_duplicate(
+    client: &Client,
+    budget: &RequestBudget,
+    row: &ImportRow,
+) -> Option<ImportFailureReason> {
+    let linked = match db::get_userdata(client, budget, &row.token).await {
+        Err(db::LookupError::NotFound) => db::get_userdata_by_id(client, budget, &row.discord_id)
+            .await
+            .map(|_| ImportFailureReason::DuplicateDiscordId),
+        lookup => lookup.map(|_| ImportFailureReason::DuplicateToken),
+    };
+
+    match linked {
+        Ok(duplicate) => Some(duplicate),
+        Err(db::LookupError::NotFound) => None,
+        Err(db::LookupError::Database(error)) => {
+            let _ = Err::<(), _>(error)
+                .make_response(MyError::InternalError(
+                    "The import has unfortunately failed at checking for linked users",
+                ))
+                .make_log(ErrorLogType::INTERNAL)
+                .await;
+            Some(ImportFailureReason::DatabaseError)
+        }
+    }
+}
+
+#[get("/import/{job_id}/failures")]
+pub async fn get_import_failures(
+    req: HttpRequest,
+    job_id: web::Path<String>,
+    query: web::Query<ReportFormat>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    verify_admin_access(&req, &config).await?;
+    // the job id ends up in the csv's Content-Disposition
+    let filename = import_failures_filename(&job_id)?;
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let failures = db::get_import_failures(&client, &job_id)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at retrieving the import's failed rows",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?
+        .into_iter()
+        .map(ImportFailure::from)
+        .collect::<Vec<ImportFailure>>();
+
+    match query.format.as_deref() {
+        Some("csv") => Ok(HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header(header::ContentDisposition {
+                disposition: header::DispositionType::Attachment,
+                parameters: vec![header::DispositionParam::Filename(filename)],
+            })
+            .body(render_failures_csv(&failures))),
+        Some("json") | None => Ok(HttpResponse::Ok().json(failures)),
+        Some(_) => Err(MyError::BadRequest("The format must be either csv or json")),
+    }
+}
+
+fn import_failures_filename(job_id: &str) -> Result<String, MyError> {
+    Ok(format!(
+        "import-{}-failures.csv",
+        sanitize_header_value(job_id)?
+    ))
+}
+
+/// renders what the weekly digest would currently contain without sending it
+#[post("/digest/preview")]
+pub async fn preview_digest(
+    req: HttpRequest,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    verify_admin_access(&req, &config).await?;
+
+    let now = unix_now();
+    let (_, this_monday) = last_week_window(now, config.digest_utc_offset);
+    let stats = gather_stats(&db_pool, (this_monday, now)).await?;
+
+    Ok(HttpResponse::Ok().json(compose_digest(&stats)))
+}
+
+#[derive(Serialize)]
+pub struct RecentErrorsResponse {
+    errors: Vec<RecordedError>,
+    summary: Vec<ErrorGroup>,
+}
+
+#[get("/errors")]
+pub async fn get_recent_errors(
+    req: HttpRequest,
+    query: web::Query<RecentErrorsQuery>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    verify_admin_access(&req, &config).await?;
+
+    let errors = recent_errors()
+        .lock()
+        .unwrap()
+        .query(query.code.as_deref(), query.since);
+    let summary = group_errors(&errors);
+
+    Ok(HttpResponse::Ok().json(RecentErrorsResponse { errors, summary }))
+}
+
+#[derive(Serialize)]
+pub struct StatsHistory {
+    metric: &'static str,
+    points: Vec<StatsPoint>,
+}
+
+/// a metric's daily snapshots up to yesterday, days nothing was snapshotted on are nulls
+#[get("/history")]
+pub async fn get_stats_history(
+    req: HttpRequest,
+    query: web::Query<StatsHistoryQuery>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    verify_admin_access(&req, &config).await?;
+
+    let metric = StatsMetric::from_name(&query.metric).ok_or_else(|| {
+        MyError::BadRequest("metric has to be total_linked or new_links")
+            .with_code("UNKNOWN_METRIC")
+    })?;
+    let days = i64::from(query.days.unwrap_or(30));
+    if days < 1 || days > config.stats_retention_days as i64 {
+        return Err(MyError::BadRequest(
+            "days has to be at least 1 and at most the days snapshots are kept for",
+        )
+        .with_code("INVALID_DAYS"));
+    }
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+    let last_day = unix_day(unix_now()) - 1;
+    let first_day = last_day - days + 1;
+    let snapshots = db::get_stats_snapshots(&client, metric.name(), first_day)
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at reading the stats snapshots, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(StatsHistory {
+        metric: metric.name(),
+        points: daily_series(&snapshots, first_day, last_day),
+    }))
+}
+
+/// the linked users, the beta testers and how many users meet each role rule, for the status page
+#[get("/stats")]
+pub async fn get_stats(
+    _admin_key: AdminKey,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+    let stats = db::get_stats(&client, &config.role_rules)
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at counting the users, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+#[delete("/errors")]
+pub async fn clear_recent_errors(
+    req: HttpRequest,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    let admin_key = verify_admin_access(&req, &config).await?;
+
+    recent_errors().lock().unwrap().clear();
+    security_log(
+        &db_pool,
+        &config,
+        AuditEvent::new(admin_key, RouteId::ClearRecentErrors, json!({})),
+    )
+    .await;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/webhook-status")]
+pub async fn webhook_status(
+    req: HttpRequest,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    verify_admin_access(&req, &config).await?;
+
+    let status = webhook_health().lock().unwrap().status();
+    Ok(HttpResponse::Ok().json(status))
+}
+
+#[get("/write-behind-status")]
+pub async fn write_behind_status(
+    req: HttpRequest,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    verify_admin_access(&req, &config).await?;
+
+    Ok(HttpResponse::Ok().json(write_behind().stats()))
+}
+
+#[get("/sync-timings")]
+pub async fn sync_timings_status(
+    req: HttpRequest,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    verify_admin_access(&req, &config).await?;
+
+    Ok(HttpResponse::Ok().json(*sync_timing_histograms().lock().unwrap()))
+}
+
+#[get("/negative-cache-status")]
+pub async fn negative_cache_status(
+    req: HttpRequest,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    verify_admin_access(&req, &config).await?;
+
+    Ok(HttpResponse::Ok().json(negative_cache().stats()))
+}
+
+#[get("/clock-skew")]
+pub async fn get_clock_skew(
+    req: HttpRequest,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    verify_admin_access(&req, &config).await?;
+
+    Ok(HttpResponse::Ok().json(ClockSkewStats {
+        window_seconds: config.signature_window,
+        buckets: skew_histogram().lock().unwrap().buckets(),
+    }))
+}
+
+/// every route's objective with how it did over the last hour, day and week
+#[get("/slo")]
+pub async fn get_slo(
+    req: HttpRequest,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    verify_admin_access(&req, &config).await?;
+
+    Ok(HttpResponse::Ok().json(slo_tracker().lock().unwrap().summary(unix_now())))
+}
+
+#[get("/deprecations")]
+pub async fn get_deprecations(
+    req: HttpRequest,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    verify_admin_access(&req, &config).await?;
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let rows = db::get_deprecation_usage(&client)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at retrieving the deprecation usage",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(group_usage(rows)))
+}
+
+#[post("/webhook-reload")]
+pub async fn reload_webhook(
+    req: HttpRequest,
+    received_webhook: web::Json<WebhookReloadRequest>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    let admin_key = verify_admin_access(&req, &config).await?;
+
+    let webhook = received_webhook.into_inner();
+    let webhook_id = webhook
+        .webhook_id
+        .parse::<u64>()
+        .ok()
+        .filter(|id| *id != 0)
+        .ok_or(MyError::BadRequest(
+            "The webhook id must be a valid snowflake",
+        ))?;
+
+    webhook_health()
+        .lock()
+        .unwrap()
+        .replace(webhook_id, webhook.webhook_token);
+
+    webhook_log(
+        format!("logging webhook was replaced with {}", webhook_id),
+        LOG::SUCCESSFUL,
+    )
+    .await;
+    // the token stays out of the audit trail
+    security_log(
+        &db_pool,
+        &config,
+        AuditEvent::new(
+            admin_key,
+            RouteId::ReloadWebhook,
+            json!({ "webhook_id": webhook_id.to_string() }),
+        ),
+    )
+    .await;
+
+    let status = webhook_health().lock().unwrap().status();
+    Ok(HttpResponse::Ok().json(status))
+}
+
+/// fetches the guild's roles right away instead of waiting for the cache to expire
+#[post("/guild-roles/refresh")]
+pub async fn refresh_guild_role_cache(
+    req: HttpRequest,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    let admin_key = verify_admin_access(&req, &config).await?;
+
+    let client = twilight_http::Client::new(config.discord_token.clone());
+    refresh_shared_guild_roles(&DiscordGuild::new(&client), Duration::ZERO).await?;
+    security_log(
+        &db_pool,
+        &config,
+        AuditEvent::new(admin_key, RouteId::RefreshGuildRoleCache, json!({})),
+    )
+    .await;
+
+    let status = guild_roles_cache().lock().unwrap().status(Instant::now());
+    Ok(HttpResponse::Ok().json(status))
+}
+
+#[patch("/role-rules/{role_id}")]
+pub async fn update_role_rule(
+    req: HttpRequest,
+    role_id: web::Path<u64>,
+    received_update: web::Json<RoleRuleUpdate>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    let admin_key = verify_admin_access(&req, &config).await?;
+
+    let role = MILESTONE_ROLES
+        .into_iter()
+        .find(|role| role.id == *role_id)
+        .ok_or(MyError::NotFound("There's no milestone role with this id"))?;
+    let update = received_update.into_inner();
+
+    if update.paused {
+        if update
+            .resume_at
+            .map_or(false, |resume_at| resume_at <= unix_now())
+        {
+            return Err(MyError::BadRequest("resume_at must be in the future"));
+        }
+
+        role_pauses()
+            .lock()
+            .unwrap()
+            .pause(role.id, update.resume_at);
+        let until = match update.resume_at {
+            Some(resume_at) => format!("until <t:{}:f>", resume_at),
+            None => "until it's resumed".to_owned(),
+        };
+        webhook_log(
+            format!("granting of the role {} was paused {}", role.name, until),
+            LOG::INFORMATIONAL,
+        )
+        .await;
+    } else if role_pauses().lock().unwrap().resume(role.id) {
+        webhook_log(
+            format!("granting of the role {} was resumed", role.name),
+            LOG::INFORMATIONAL,
+        )
+        .await;
+    }
+    security_log(
+        &db_pool,
+        &config,
+        AuditEvent::new(
+            admin_key,
+            RouteId::UpdateRoleRule,
+            json!({
+                "role_id": role.id.to_string(),
+                "paused": update.paused,
+                "resume_at": update.resume_at,
+            }),
+        ),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(role_rule_status(role.id, &role.name, &config.role_rules)))
+}
+
+#[get("")]
+pub async fn get_role_rules(
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    let now = SystemTime::now();
+    let mut rules = MILESTONE_ROLES
+        .into_iter()
+        .map(|role| role_rule_status(role.id, &role.name, &config.role_rules))
+        .chain(
+            config
+                .role_rules
+                .iter()
+                .filter(|rule| MILESTONE_ROLES.iter().all(|role| role.id != rule.role_id))
+                .map(|rule| role_rule_status(rule.role_id, &rule.name, &config.role_rules)),
+        )
+        .collect::<Vec<RoleRuleStatus>>();
+
+    for rule in promo_rules().lock().unwrap().iter() {
+        let status = match promo_window(rule, now) {
+            PromoWindow::Upcoming => RuleStatus::UpcomingPromo,
+            PromoWindow::Active => RuleStatus::ActivePromo,
+            PromoWindow::Ended => continue,
+        };
+        rules.push(RoleRuleStatus {
+            id: rule.role_id.clone(),
+            name: rule.name.clone(),
+            status,
+            resume_at: None,
+            starts_at: Some(unix_seconds(rule.starts_at)),
+            ends_at: Some(unix_seconds(rule.ends_at)),
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(rules))
+}
+
+fn role_rule_status(role_id: u64, name: &str, role_rules: &[RoleRule]) -> RoleRuleStatus {
+    let pause = role_pauses().lock().unwrap().get(role_id, unix_now());
+    RoleRuleStatus {
+        id: role_id.to_string(),
+        name: name.to_owned(),
+        status: if deprecated_field_roles(&DEPRECATED_FIELDS, role_rules).contains(&role_id) {
+            RuleStatus::DeprecatedField
+        } else if pause.is_some() {
+            RuleStatus::TemporarilyPaused
+        } else {
+            RuleStatus::Active
+        },
+        resume_at: pause.and_then(|pause| pause.resume_at),
+        starts_at: None,
+        ends_at: None,
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[post("/promo-rules")]
+pub async fn create_promo_rule(
+    req: HttpRequest,
+    received_rule: web::Json<PromoRoleRuleRequest>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    let admin_key = verify_admin_access(&req, &config).await?;
+
+    let rule = received_rule.into_inner();
+    let role_id = rule
+        .role_id
+        .parse::<u64>()
+        .ok()
+        .filter(|id| *id != 0)
+        .ok_or(MyError::BadRequest("The role id must be a valid snowflake"))?;
+    // nothing is known about the role until the guild's roles were fetched once
+    let (role_exists, role_managed) = {
+        let cache = guild_roles_cache().lock().unwrap();
+        (
+            cache.knows(role_id),
+            cache.by_id(role_id).map_or(false, |role| role.managed),
+        )
+    };
+    if role_exists == Some(false) {
+        return Err(MyError::BadRequest("The role doesn't exist in the guild"));
+    }
+    if role_managed {
+        return Err(MyError::BadRequest(
+            "The role belongs to an integration and can't be given to anyone",
+        ));
+    }
+    if MILESTONE_ROLES.iter().any(|role| role.id == role_id) || PERSISTENT_ROLES.contains(&role_id)
+    {
+        return Err(MyError::BadRequest(
+            "Promo rules can't use a permanent role, it would be removed when the promo ends",
+        ));
+    }
+    if rule.ends_at <= rule.starts_at || rule.ends_at <= unix_now() {
+        return Err(MyError::BadRequest(
+            "ends_at must be in the future and after starts_at",
+        ));
+    }
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let created_rule = db::create_promo_role_rule(
+        &client,
+        &rule.role_id,
+        &rule.name,
+        &(SystemTime::UNIX_EPOCH + Duration::from_secs(rule.starts_at)),
+        &(SystemTime::UNIX_EPOCH + Duration::from_secs(rule.ends_at)),
+    )
+    .await
+    .make_response(MyError::InternalError("Failed at creating the promo rule"))
+    .make_log(ErrorLogType::INTERNAL)
+    .await?;
+    reload_promo_rules(&client).await?;
+
+    webhook_log(
+        format!(
+            "promo role {} will be granted from <t:{}:f> to <t:{}:f>",
+            created_rule.name, rule.starts_at, rule.ends_at
+        ),
+        LOG::INFORMATIONAL,
+    )
+    .await;
+    security_log(
+        &db_pool,
+        &config,
+        AuditEvent::new(
+            admin_key,
+            RouteId::CreatePromoRule,
+            json!({
+                "role_id": rule.role_id,
+                "name": rule.name,
+                "starts_at": rule.starts_at,
+                "ends_at": rule.ends_at,
+            }),
+        ),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(created_rule))
+}
+
+/// adds a partner whose requests get `multiplier` times the rate limits, the key is chosen by
+/// the admin and only its hash is stored
+#[post("/partners")]
+pub async fn create_api_partner(
+    req: HttpRequest,
+    received_partner: web::Json<ApiPartnerRequest>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    let admin_key = verify_admin_access(&req, &config).await?;
+
+    let partner = received_partner.into_inner();
+    if partner.key.len() < MIN_PARTNER_KEY_LENGTH {
+        return Err(MyError::BadRequest(
+            "The key must be at least 32 characters long",
+        ));
+    }
+    let multiplier = i32::try_from(partner.multiplier)
+        .ok()
+        .filter(|multiplier| *multiplier >= 1)
+        .ok_or(MyError::BadRequest(
+            "The multiplier must be a positive whole number",
+        ))?;
+    if partner
+        .expires_at
+        .map_or(false, |expires_at| expires_at <= unix_now())
+    {
+        return Err(MyError::BadRequest("expires_at must be in the future"));
+    }
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let key_hash = hash_partner_key(&partner.key);
+    let existing = db::get_api_partner(&client, &key_hash)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at looking up the partner key",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+    if existing.is_some() {
+        return Err(MyError::Conflict("Another partner already has this key"));
+    }
+
+    let created_partner = db::create_api_partner(
+        &client,
+        &key_hash,
+        &partner.label,
+        &multiplier,
+        &partner
+            .expires_at
+            .map(|expires_at| SystemTime::UNIX_EPOCH + Duration::from_secs(expires_at)),
+    )
+    .await
+    .make_response(MyError::InternalError("Failed at creating the partner"))
+    .make_log(ErrorLogType::INTERNAL)
+    .await?;
+    // an unknown key is cached as well, it's this partner's from now on
+    partner_keys().lock().unwrap().forget(&key_hash);
+
+    security_log(
+        &db_pool,
+        &config,
+        AuditEvent::new(
+            admin_key,
+            RouteId::CreateApiPartner,
+            json!({
+                "label": partner.label,
+                "multiplier": partner.multiplier,
+                "expires_at": partner.expires_at,
+            }),
+        ),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(created_partner))
+}
+
+/// every partner with the requests it made since the service started
+#[get("/partners")]
+pub async fn get_api_partners(
+    req: HttpRequest,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    verify_admin_access(&req, &config).await?;
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let partners = db::get_api_partners(&client)
+        .await
+        .make_response(MyError::InternalError("Failed at retrieving the partners"))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+    let keys = partner_keys().lock().unwrap();
+    let partners: Vec<ApiPartnerUsage> = partners
+        .into_iter()
+        .map(|partner| ApiPartnerUsage {
+            requests: keys.usage(partner.id),
+            partner,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(partners))
+}
+
+/// takes the partner's higher limits away right away, its key gets the normal limits afterwards
+#[delete("/partners/{partner_id}")]
+pub async fn delete_api_partner(
+    req: HttpRequest,
+    partner_id: web::Path<i64>,
+    db_pool: web::Data<Pool>,
+    config: web::Data<crate::config::Config>,
+) -> Result<HttpResponse, MyError> {
+    let admin_key = verify_admin_access(&req, &config).await?;
+
+    let client: Client = db_pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "request failed at creating database client, please try again",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let deleted_partner = db::delete_api_partner(&client, &partner_id)
+        .await
+        .make_response(MyError::InternalError("Failed at deleting the partner"))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?
+        .ok_or(MyError::NotFound("There's no partner with this id"))?;
+    partner_keys()
+        .lock()
+        .unwrap()
+        .forget(&deleted_partner.key_hash);
+
+    security_log(
+        &db_pool,
+        &config,
+        AuditEvent::new(
+            admin_key,
+            RouteId::DeleteApiPartner,
+            json!({ "partner_id": deleted_partner.id, "label": deleted_partner.label }),
+        ),
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().json(deleted_partner))
+}
+
+/// keeps a copy of the written state around so admins can later replay payloads against it,
+/// a failed snapshot gets logged but never fails the request that caused it
+async fn snapshot_userdata(
+    client: &Client,
+    budget: &RequestBudget,
+    user_data: &UserData,
+    token: &str,
+) {
+    let _ = db::create_userdata_snapshot(client, budget, user_data)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at writing a snapshot of the user's data",
+        ))
+        .make_log(ErrorLogType::USER(
+            LoggedUser::token(token).with_discord_id(&user_data.discord_id),
+        ))
+        .await;
+}
+
+/// the key the request was authorized with, admin actions are audited under its label
+/// for the admin routes that don't take the `AdminKey` extractor
+async fn verify_admin_access(
+    req: &HttpRequest,
+    config: &crate::config::Config,
+) -> Result<AdminKey, MyError> {
+    admin_access(req, config).await
+}
+
+#[test]
+fn every_response_follows_the_serialization_policy() {
+    use crate::{
+        digest::DigestStats,
+        guild_role_cache::{GuildRole, GuildRolesCache, GUILD_ROLES_TTL},
+        import::{ImportFailure, ImportFailureReason},
+        models::{
+            BetaTesterStatus, FingerprintMatch, GuildRoles, ImportFailureRecord, MilestoneCount,
+            PromoRoleRule, StatsResponse, SupportCodeRecord, WithheldReason, WithheldRole,
+        },
+        role_pauses::{RolePause, RolePauses},
+        status::StatusHistory,
+        sync_timings::TimingsReport,
+        timestamps::timestamp_violations,
+    };
+
+    let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let promo_rule = PromoRoleRule {
+        id: 1,
+        role_id: "42".to_owned(),
+        name: "Launch Week".to_owned(),
+        starts_at: now - Duration::from_secs(60),
+        ends_at: now + Duration::from_secs(60),
+        swept: false,
+        created_timestamp: now,
+    };
+    let user_data = UserData {
+        discord_id: "1".to_owned(),
+        edited_timestamp: now,
+        ..UserData::default()
+    };
+    let trace = compute_earned_roles_with_trace(
+        &user_data,
+        &[],
+        std::slice::from_ref(&promo_rule),
+        &RolePauses::default(),
+        &[],
+        now,
+    );
+    let mut guild_roles = GuildRolesCache::new(GUILD_ROLES_TTL);
+    guild_roles.replace(
+        vec![GuildRole {
+            id: 42,
+            name: "Launch Week".to_owned(),
+            position: 1,
+            managed: false,
+        }],
+        Instant::now(),
+        1_700_000_000,
+    );
+
+    let responses = [
+        ("UserData", serde_json::to_value(&user_data)),
+        ("PromoRoleRule", serde_json::to_value(&promo_rule)),
+        (
+            "SupportCodeRecord",
+            serde_json::to_value(SupportCodeRecord {
+                support_code: "code".to_owned(),
+                v2_token: "v2".to_owned(),
+                legacy_token: "legacy".to_owned(),
+                edited_timestamp: now,
+            }),
+        ),
+        (
+            "ImportFailureRecord",
+            serde_json::to_value(ImportFailureRecord {
+                job_id: "job".to_owned(),
+                line: 1,
+                reason: "{}".to_owned(),
+                row: "{}".to_owned(),
+                created_timestamp: now,
+            }),
+        ),
+        (
+            "FingerprintSearchResponse",
+            serde_json::to_value(FingerprintSearchResponse {
+                fingerprint: "abcd".to_owned(),
+                accounts: vec![FingerprintMatch {
+                    discord_id: "1".to_owned(),
+                    token_fingerprint: "abcd".to_owned(),
+                    edited_timestamp: now,
+                    beta_tester_locked: false,
+                }],
+            }),
+        ),
+        (
+            "BetaTesterStatus",
+            serde_json::to_value(BetaTesterStatus {
+                discord_id: "1".to_owned(),
+                beta_tester: true,
+                beta_tester_locked: true,
+            }),
+        ),
+        (
+            "RoleRuleStatus",
+            serde_json::to_value(vec![
+                RoleRuleStatus {
+                    id: "42".to_owned(),
+                    name: "Launch Week".to_owned(),
+                    status: RuleStatus::ActivePromo,
+                    resume_at: None,
+                    starts_at: Some(1_699_999_940),
+                    ends_at: Some(1_700_000_060),
+                },
+                RoleRuleStatus {
+                    id: "1".to_owned(),
+                    name: "Reality Explorer".to_owned(),
+                    status: RuleStatus::TemporarilyPaused,
+                    resume_at: Some(1_700_000_060),
+                    starts_at: None,
+                    ends_at: None,
+                },
+            ]),
+        ),
+        (
+            "RolePause",
+            serde_json::to_value(RolePause {
+                resume_at: Some(1_700_000_060),
+            }),
+        ),
+        (
+            "GrantedRolesResponse",
+            serde_json::to_value(GrantedRolesResponse {
+                discord_id: "1".to_owned(),
+                granted_roles: [
+                    ("1".to_owned(), Some(1_700_000_000)),
+                    ("2".to_owned(), None),
+                ]
+                .into_iter()
+                .collect(),
+            }),
+        ),
+        (
+            "GuildRolesStatus",
+            serde_json::to_value(guild_roles.status(Instant::now())),
+        ),
+        (
+            "StatusSummary",
+            serde_json::to_value(StatusHistory::new(1_700_000_000).summary(1_700_000_060)),
+        ),
+        (
+            "DigestStats",
+            serde_json::to_value(DigestStats {
+                window_start: 1_699_395_200,
+                window_end: 1_700_000_000,
+                ..DigestStats::default()
+            }),
+        ),
+        (
+            "RecentErrorsResponse",
+            serde_json::to_value(RecentErrorsResponse {
+                errors: vec![RecordedError::new(
+                    "ACCOUNT_NOT_LINKED".to_owned(),
+                    "Not Found".to_owned(),
+                    "/v2/userdata".to_owned(),
+                    None,
+                    None,
+                )],
+                summary: Vec::new(),
+            }),
+        ),
+        (
+            "RoleTraceResponse",
+            serde_json::to_value(RoleTraceResponse {
+                discord_id: "1".to_owned(),
+                beta_tester_locked: true,
+                rules: trace.rules.clone(),
+                computed: None,
+            }),
+        ),
+        (
+            "RolesExplanation",
+            serde_json::to_value(RolesExplanation {
+                rules: trace.rules.iter().map(explain_rule).collect(),
+            }),
+        ),
+        (
+            "ProgressResponse",
+            serde_json::to_value(ProgressResponse::new(
+                UserData::default(),
+                SyncStreak::default(),
+            )),
+        ),
+        (
+            "UserResponse",
+            serde_json::to_value(UserResponse {
+                message: "Synced".to_owned(),
+                roles: vec![GuildRoles {
+                    guild_id: C2SGUILD.to_string(),
+                    guild_name: "C2S".to_owned(),
+                    roles: vec!["Reality Explorer".to_owned()],
+                }],
+                gained_roles: vec!["Reality Explorer".to_owned()],
+                discord_id: "1".to_owned(),
+                withheld: vec![WithheldRole {
+                    id: "42".to_owned(),
+                    name: "Launch Week".to_owned(),
+                    reason: WithheldReason::RoleMissing,
+                }],
+                skipped: Vec::new(),
+                closest_miss: None,
+                removed_roles: Vec::new(),
+                roles_pending: false,
+                timings: Some(TimingsReport::default()),
+            }),
+        ),
+        (
+            "ImportResponse",
+            serde_json::to_value(ImportResponse {
+                job_id: "job".to_owned(),
+                imported: 0,
+                failures: vec![ImportFailure {
+                    line: 1,
+                    reason: ImportFailureReason::DuplicateDiscordId,
+                    row: "{}".to_owned(),
+                }],
+            }),
+        ),
+        (
+            "DeletedUserResponse",
+            serde_json::to_value(DeletedUserResponse {
+                discord_id: "1".to_owned(),
+                user: UserData::default().into(),
+                roles: Some(RoleRemovalSummary::default()),
+            }),
+        ),
+        (
+            "ReadinessResponse",
+            serde_json::to_value(ReadinessResponse {
+                database: true,
+                discord_paused_for: None,
+                database_read_only_for: None,
+                read_only_encounters: 0,
+                compatibility_mode: false,
+            }),
+        ),
+        (
+            "VersionResponse",
+            serde_json::to_value(VersionResponse {
+                version: "1.0.0",
+                schema: SchemaState {
+                    expected_version: 22,
+                    applied_version: 23,
+                    compatibility_mode: true,
+                },
+            }),
+        ),
+        (
+            "UserPage",
+            serde_json::to_value(user_page(
+                vec![UserData {
+                    discord_id: "1".to_owned(),
+                    edited_timestamp: now,
+                    ..UserData::default()
+                }],
+                1,
+            )),
+        ),
+        (
+            "StatsResponse",
+            serde_json::to_value(StatsResponse {
+                total_linked: 2,
+                beta_testers: 1,
+                milestones: vec![MilestoneCount {
+                    role_id: "42".to_owned(),
+                    role_name: "Launch Week".into(),
+                    users: 1,
+                }],
+            }),
+        ),
+    ];
+
+    for (name, json) in responses {
+        let json = json.unwrap();
+        assert_eq!(
+            timestamp_violations(&json, name),
+            Vec::<String>::new(),
+            "{}",
+            json
+        );
+    }
+}
+
+#[test]
+fn every_response_enum_is_camel_case() {
+    use crate::{
+        bulk_resync::ResyncState,
+        import::ImportFailureReason,
+        models::WithheldReason,
+        role_rules::{Comparison, Requirement, RuleChannel, Verdict},
+        status::{Component, ComponentState},
+        timestamps::is_camel_case,
+    };
+
+    let values = [
+        serde_json::to_value([
+            RuleStatus::Active,
+            RuleStatus::TemporarilyPaused,
+            RuleStatus::UpcomingPromo,
+            RuleStatus::ActivePromo,
+            RuleStatus::DeprecatedField,
+        ]),
+        serde_json::to_value([WithheldReason::RoleMissing]),
+        serde_json::to_value([
+            PromoWindow::Upcoming,
+            PromoWindow::Active,
+            PromoWindow::Ended,
+        ]),
+        serde_json::to_value([
+            Comparison::AtLeast,
+            Comparison::AtMost,
+            Comparison::Equals,
+            Comparison::IsSet,
+        ]),
+        serde_json::to_value([RuleChannel::Any, RuleChannel::Beta]),
+        serde_json::to_value([
+            ResyncState::Running,
+            ResyncState::Completed,
+            ResyncState::Aborted,
+        ]),
+        serde_json::to_value([
+            Verdict::Granted,
+            Verdict::NotMet,
+            Verdict::WrongChannel,
+            Verdict::Excluded,
+            Verdict::Paused,
+            Verdict::OutsidePromoWindow,
+            Verdict::DeprecatedField,
+        ]),
+        serde_json::to_value([Requirement::Met, Requirement::NotMet]),
+        serde_json::to_value(Component::ALL),
+        serde_json::to_value([
+            ComponentState::Operational,
+            ComponentState::Degraded,
+            ComponentState::Down,
+        ]),
+    ];
+    for values in values {
+        for value in values.unwrap().as_array().unwrap() {
+            let value = value.as_str().unwrap();
+            assert!(is_camel_case(value), "{}", value);
+        }
+    }
+
+    // the import failures are tagged with their type
+    for reason in [
+        ImportFailureReason::DuplicateToken,
+        ImportFailureReason::DuplicateDiscordId,
+        ImportFailureReason::Validation {
+            field: "metabits".to_owned(),
+            reason: "is negative".to_owned(),
+        },
+        ImportFailureReason::MalformedRow { line: 1 },
+        ImportFailureReason::DatabaseError,
+    ] {
+        let json = serde_json::to_value(&reason).unwrap();
+        assert!(is_camel_case(json["type"].as_str().unwrap()), "{}", json);
+    }
+    // failures stored before the tags were camelCase are still read
+    assert_eq!(
+        serde_json::from_str::<ImportFailureReason>("{\"type\":\"duplicate_discord_id\"}").unwrap(),
+        ImportFailureReason::DuplicateDiscordId
+    );
+}
+
+#[test]
+fn import_failure_reports_refuse_header_injection() {
+    use actix_web::{http::StatusCode, ResponseError};
+
+    assert_eq!(
+        import_failures_filename("42").unwrap(),
+        "import-42-failures.csv"
+    );
+    let injected = import_failures_filename("42\r\nSet-Cookie: session=stolen").unwrap_err();
+    assert_eq!(injected.status_code(), StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn empty_patches_are_a_bad_request() {
+    use actix_web::{http::StatusCode, ResponseError};
+
+    let error = empty_patch();
+    assert_eq!(error.status_code(), StatusCode::BAD_REQUEST);
+    assert_eq!(error.error_code(), "EMPTY_PATCH");
+}
+
+#[test]
+fn og_responses_are_binary_when_the_client_accepts_it() {
+    use crate::og_binary::decode_frame;
+
+    let body = serde_json::to_string(&OGMessageResponse {
+        message: "You gained Reality Explorer".to_owned(),
+        roles: vec![crate::models::GuildRoles {
+            guild_id: C2SGUILD.to_string(),
+            guild_name: "Cell to Singularity".to_owned(),
+            roles: vec!["Reality Explorer".to_owned()],
+        }],
+        gained_roles: vec!["Reality Explorer".to_owned()],
+        discord_id: "1".to_owned(),
+        withheld: Vec::new(),
+        skipped: Vec::new(),
+        warnings: vec!["dino_rank was missing and was stored as empty".to_owned()],
+        closest_miss: Some(ClosestMiss {
+            role_id: "42".to_owned(),
+            role_name: "Reality Expert".to_owned(),
+            field: "metabits".to_owned(),
+            remaining: 12_400.0,
+        }),
+        removed_roles: vec!["Reality Legend".to_owned()],
+    })
+    .unwrap();
+    let respond = |accept: &str| {
+        let req = actix_web::test::TestRequest::default()
+            .insert_header((header::ACCEPT, accept))
+            .to_http_request();
+        let response = og_response(&req, &mut HttpResponse::Ok(), body.clone()).unwrap();
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .unwrap()
+            .clone();
+        let bytes = actix_web::rt::System::new()
+            .block_on(actix_web::body::to_bytes(response.into_body()))
+            .unwrap();
+        (content_type, bytes)
+    };
+
+    let (content_type, bytes) = respond(OG_BINARY_CONTENT_TYPE);
+    assert_eq!(content_type, OG_BINARY_CONTENT_TYPE);
+    assert_eq!(
+        decode_frame::<OGBinaryResponse>(&bytes).unwrap(),
+        serde_json::from_str::<OGBinaryResponse>(&body).unwrap()
+    );
+
+    let (content_type, bytes) = respond("application/json");
+    assert_eq!(content_type, "application/json");
+    assert_eq!(bytes, body);
+}
+
+#[test]
+fn sync_conflicts_can_be_told_apart_by_their_code() {
+    use actix_web::{http::StatusCode, ResponseError};
+
+    let conflict = sync_conflict();
+    assert_eq!(conflict.status_code(), StatusCode::CONFLICT);
+    assert_eq!(conflict.error_code(), "SYNC_CONFLICT");
+}
+
+#[test]
+fn link_conflicts_name_what_was_linked_first() {
+    use actix_web::{http::StatusCode, ResponseError};
+
+    for (conflict, status, code, message) in [
+        (
+            db::LinkConflict::Token,
+            StatusCode::BAD_REQUEST,
+            "ALREADY_LINKED",
+            "You're already linked, please use the update endpoint",
+        ),
+        (
+            db::LinkConflict::DiscordId,
+            StatusCode::CONFLICT,
+            "DISCORD_ID_BOUND",
+            "This discord id is already bound to another account",
+        ),
+    ] {
+        let error = link_conflict(conflict);
+        assert_eq!(error.status_code(), status);
+        assert_eq!(error.error_code(), code);
+        assert!(error.to_string().ends_with(message));
+    }
+
+    // only the token conflict lets a retried create be answered like the one it repeats
+    assert!(matches!(
+        LinkFailure::from(db::LinkConflict::Token),
+        LinkFailure::AlreadyLinked
+    ));
+    assert_eq!(
+        MyError::from(LinkFailure::AlreadyLinked).error_code(),
+        "ALREADY_LINKED"
+    );
+    assert!(matches!(
+        LinkFailure::from(db::LinkConflict::DiscordId),
+        LinkFailure::Failed(_)
+    ));
+}
+
+#[cfg(test)]
+fn progress_json() -> Value {
+    json!({
+        "metabits": "5000000",
+        "dino_rank": 120,
+        "prestige_rank": 4,
+        "beyond_rank": 10,
+        "singularity_speedrun_time": 400.0,
+        "all_sharks_obtained": false,
+        "all_hidden_achievements_obtained": false
+    })
+}
+
+/// posts the body to the /user scope the way main.rs sets it up, returns the status and the body
+#[cfg(test)]
+fn post_to_user_scope(path: &str, body: String) -> (actix_web::http::StatusCode, Value) {
+    use actix_web::{test, App};
+
+    actix_web::rt::System::new().block_on(async {
+        let app = test::init_service(
+            App::new().service(
+                web::scope("/user")
+                    .app_data(json_config())
+                    .service(preview_roles)
+                    .service(validate_progress),
+            ),
+        )
+        .await;
+        let request = test::TestRequest::post()
+            .uri(path)
+            .insert_header(ContentType::json())
+            .set_payload(body)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+
+        (response.status(), test::read_body_json(response).await)
+    })
+}
+
+#[cfg(test)]
+fn preview_issues(data: Value) -> (actix_web::http::StatusCode, Value) {
+    let (status, body) =
+        post_to_user_scope("/user/roles/preview", json!({ "data": data }).to_string());
+
+    (status, body["error"].clone())
+}
+
+#[cfg(test)]
+fn validation_issues(request: Value) -> Vec<Value> {
+    let (status, body) = post_to_user_scope("/user/validate", request.to_string());
+    assert_eq!(status, actix_web::http::StatusCode::OK);
+    assert_eq!(body["valid"], body["issues"].as_array().unwrap().is_empty());
+
+    body["issues"].as_array().unwrap().clone()
+}
+
+#[test]
+fn valid_payloads_validate_without_issues() {
+    assert!(validation_issues(json!({ "data": progress_json() })).is_empty());
+}
+
+#[test]
+fn unknown_fields_are_reported_by_validate() {
+    let mut data = progress_json();
+    data["metabit"] = json!(12);
+
+    assert_eq!(
+        validation_issues(json!({ "data": data })),
+        vec![json!({
+            "field_path": "metabit",
+            "code": "unknown_field",
+            "detail": "isn't a progress field",
+            "limit": null
+        })]
+    );
+}
+
+#[test]
+fn mismatched_types_are_reported_by_real_endpoints() {
+    let mut data = progress_json();
+    data["dino_rank"] = json!("high");
+
+    let (status, error) = preview_issues(data);
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+    assert_eq!(error["code"], "INVALID_BODY");
+    assert_eq!(error["issues"][0]["code"], "type_mismatch");
+}
+
+#[test]
+fn missing_fields_are_reported_by_real_endpoints() {
+    let mut data = progress_json();
+    data.as_object_mut().unwrap().remove("dino_rank");
+
+    let (status, error) = preview_issues(data);
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+    assert_eq!(error["issues"][0]["code"], "missing_required");
+    assert_eq!(error["issues"][0]["field_path"], "dino_rank");
+}
+
+#[test]
+fn out_of_range_values_are_reported_by_real_endpoints() {
+    let mut data = progress_json();
+    data["dino_rank"] = json!(-1);
+
+    let (status, error) = preview_issues(data);
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+    assert_eq!(error["code"], "INVALID_PROGRESS");
+    assert_eq!(
+        error["issues"],
+        json!([{
+            "field_path": "dino_rank",
+            "code": "out_of_range",
+            "detail": "must not be negative",
+            "limit": 0.0
+        }])
+    );
+}
+
+#[test]
+fn unstorable_values_are_reported_by_real_endpoints() {
+    let mut data = progress_json();
+    data["metabits"] = json!("10000000000000000000");
+
+    let (status, error) = preview_issues(data);
+    assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST);
+    assert_eq!(error["issues"][0]["code"], "invariant_violation");
+    assert_eq!(error["issues"][0]["field_path"], "metabits");
+}
+
+#[test]
+fn going_backwards_is_reported_by_validate() {
+    let mut previous = progress_json();
+    previous["beyond_rank"] = json!(11);
+
+    assert_eq!(
+        validation_issues(json!({ "data": progress_json(), "previous": previous })),
+        vec![json!({
+            "field_path": "beyond_rank",
+            "code": "monotonic_decrease",
+            "detail": "must not go backwards",
+            "limit": 11.0
+        })]
+    );
+}
+
+#[test]
+fn replayed_creates_are_answered_like_the_create() {
+    use actix_web::http::StatusCode;
+
+    let response = replayed_create("{}".to_owned());
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+        response.headers().get(IDEMPOTENT_REPLAY_HEADER).unwrap(),
+        "true"
+    );
+}
+
+#[test]
+fn synced_roles_are_listed_by_name() {
+    let guild_names = HashMap::from([(C2SGUILD, "C2S".to_owned())]);
+    let nothing = describe_synced_roles(&[], &guild_names, 5, None);
+    assert!(nothing.gained_roles.is_empty());
+    assert!(nothing.roles.is_empty());
+
+    let synced = describe_synced_roles(&MILESTONE_ROLES[..2], &guild_names, 5, None);
+    assert_eq!(
+        synced.gained_roles,
+        vec!["Reality Explorer".to_owned(), "Reality Expert".to_owned()]
+    );
+    assert_eq!(synced.roles[0].roles, synced.gained_roles);
+    assert_eq!(
+        synced.message,
+        "The request was successful, you've gained the following roles: C2S: Reality Explorer, Reality Expert"
+    );
+}
+
+#[test]
+fn computed_fields_are_only_included_when_asked_for() {
+    use actix_web::{test, App};
+
+    let included = |query: &str| {
+        actix_web::rt::System::new().block_on(async {
+            let app = test::init_service(App::new().route(
+                "/user/progress",
+                web::get().to(|options: web::Query<ViewOptions>| async move {
+                    HttpResponse::Ok().json(UserDataView {
+                        data: UserData::default(),
+                        computed: options.computed().then(|| {
+                            compute_fields(&UserData::default(), &[], &[], &SyncStreak::default())
+                        }),
+                    })
+                }),
+            ))
+            .await;
+            let request = test::TestRequest::get()
+                .uri(&format!("/user/progress{}", query))
+                .to_request();
+            let body: Value = test::call_and_read_body_json(&app, request).await;
+
+            body.get("computed").is_some()
+        })
+    };
+
+    assert!(!included(""));
+    assert!(!included("?include="));
+    assert!(!included("?include=streak"));
+    assert!(included("?include=computed"));
+    assert!(included("?include=roles,computed"));
+}
+
+#[test]
+fn admin_keys_dont_change_user_routes() {
+    use crate::headers::ADMIN_KEY_HEADER;
+    use actix_web::{http::StatusCode, test, App};
+
+    let preview = |admin_key: Option<&str>| {
+        actix_web::rt::System::new().block_on(async {
+            let app =
+                test::init_service(App::new().service(web::scope("/user").service(preview_roles)))
+                    .await;
+            let mut request = test::TestRequest::post()
+                .uri("/user/roles/preview")
+                .insert_header(ContentType::json())
+                .set_payload(json!({ "data": progress_json() }).to_string());
+            if let Some(admin_key) = admin_key {
+                request = request.insert_header((ADMIN_KEY_HEADER, admin_key));
+            }
+
+            test::call_service(&app, request.to_request())
+                .await
+                .status()
+        })
+    };
+
+    assert_eq!(preview(None), StatusCode::OK);
+    // a wrong admin key is only a 401 on admin routes
+    assert_eq!(preview(Some("guessed-key")), StatusCode::OK);
+}
+
+#[test]
+fn only_plausible_snowflakes_are_looked_up() {
+    assert!(is_plausible_snowflake("80351110224678912"));
+    assert!(is_plausible_snowflake("1234567890123456789"));
+    assert!(is_plausible_snowflake("12345678901234567890"));
+
+    assert!(!is_plausible_snowflake(""));
+    assert!(!is_plausible_snowflake("1234"));
+    assert!(!is_plausible_snowflake("123456789012345678901"));
+    assert!(!is_plausible_snowflake("8035111022467891a"));
+    assert!(!is_plausible_snowflake("-80351110224678912"));
+    assert!(!is_plausible_snowflake(" 80351110224678912"));
+}
+
+#[test]
+fn looked_up_users_show_their_beta_status_and_last_sync() {
+    let user_data = UserData {
+        discord_id: "80351110224678912".to_owned(),
+        token: "token".to_owned(),
+        beta_tester: true,
+        edited_timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(60),
+        ..UserData::default()
+    };
+
+    let response =
+        serde_json::to_value(ProgressResponse::new(user_data, SyncStreak::default())).unwrap();
+    assert_eq!(response["discord_id"], "80351110224678912");
+    assert_eq!(response["beta_tester"], true);
+    assert_eq!(response["edited_timestamp"], "1970-01-01T00:01:00.000Z");
+    // only the user themselves gets to see it
+    assert!(response.get("token").is_none());
+}
+
+#[test]
+fn force_deleted_users_are_returned_without_their_token() {
+    let user_data = UserData {
+        discord_id: "80351110224678912".to_owned(),
+        token: "token".to_owned(),
+        beta_tester: true,
+        ..UserData::default()
+    };
+
+    let response = serde_json::to_value(DeletedUserResponse {
+        discord_id: user_data.discord_id.clone(),
+        user: user_data.into(),
+        roles: None,
+    })
+    .unwrap();
+    assert_eq!(response["discord_id"], "80351110224678912");
+    assert_eq!(response["user"]["discord_id"], "80351110224678912");
+    assert_eq!(response["user"]["beta_tester"], true);
+    assert!(response["user"].get("token").is_none());
+    // the roles are only there with REMOVE_ROLES_ON_DELETE
+    assert!(response.get("roles").is_none());
+}
+
+#[test]
+fn user_pages_carry_a_cursor_until_the_last_one() {
+    let rows = |discord_ids: &[&str]| {
+        discord_ids
+            .iter()
+            .map(|discord_id| UserData {
+                discord_id: discord_id.to_string(),
+                token: "token".to_owned(),
+                ..UserData::default()
+            })
+            .collect::<Vec<UserData>>()
+    };
+
+    let page = user_page(
+        rows(&[
+            "80351110224678912",
+            "80351110224678913",
+            "80351110224678914",
+        ]),
+        2,
+    );
+    assert_eq!(page.users.len(), 2);
+    let cursor = page.next_cursor.unwrap();
+    assert_eq!(decode_user_cursor(&cursor).unwrap(), "80351110224678913");
+
+    let json = serde_json::to_value(user_page(rows(&["80351110224678914"]), 2)).unwrap();
+    assert_eq!(json["next_cursor"], serde_json::Value::Null);
+    assert!(json["users"][0].get("token").is_none());
+}
+
+#[test]
+fn invalid_cursors_are_rejected() {
+    let injected = encode_user_cursor("1' OR '1'='1");
+    for cursor in ["", "not base64!", injected.as_str()] {
+        assert_eq!(
+            decode_user_cursor(cursor).unwrap_err().error_code(),
+            "INVALID_CURSOR"
+        );
+    }
 }
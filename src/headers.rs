@@ -1,12 +1,239 @@
-use actix_web::http::header::{
-    Header, HeaderName, HeaderValue, InvalidHeaderValue, TryIntoHeaderValue,
+use std::future::{ready, Ready};
+
+use actix_web::{
+    dev::Payload,
+    http::header::{
+        Header, HeaderMap, HeaderName, HeaderValue, InvalidHeaderValue, TryIntoHeaderValue,
+    },
+    FromRequest, HttpRequest,
 };
 
-use crate::utilities::AuthData;
+use crate::{errors::MyError, utilities::safe_basic_auth_decoder};
+
+/// Makes a request-derived value safe to put in a response header. Control characters, CR and LF
+/// among them, only end up in a value when someone's trying to split the response, so those are
+/// refused with a 400 instead of being stripped. Anything else that isn't visible ASCII is dropped.
+pub fn sanitize_header_value(value: &str) -> Result<String, MyError> {
+    if value.chars().any(char::is_control) {
+        return Err(MyError::BadRequest(
+            "The request contains control characters where they aren't allowed",
+        ));
+    }
+
+    Ok(value
+        .chars()
+        .filter(|character| matches!(character, ' '..='~'))
+        .collect())
+}
+
+pub const DISTRIBUTION_CHANNEL_HEADER: &str = "x-distribution-channel";
+
+const UNKNOWN_DISTRIBUTION_CHANNEL: &str =
+    "X-Distribution-Channel has to be one of Beta, Steam or Mobile";
+
+/// where the game was installed from, only the beta channel marks users as beta testers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DistributionChannel {
+    Beta,
+    Steam,
+    Mobile,
+}
+
+impl DistributionChannel {
+    pub const ALL: [DistributionChannel; 3] = [
+        DistributionChannel::Beta,
+        DistributionChannel::Steam,
+        DistributionChannel::Mobile,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DistributionChannel::Beta => "Beta",
+            DistributionChannel::Steam => "Steam",
+            DistributionChannel::Mobile => "Mobile",
+        }
+    }
+
+    /// the channel's name in any casing, anything else is refused with a 400
+    pub fn parse(value: &str) -> Result<Self, MyError> {
+        let value = value.trim();
+        DistributionChannel::ALL
+            .into_iter()
+            .find(|channel| channel.name().eq_ignore_ascii_case(value))
+            .ok_or(MyError::BadRequest(UNKNOWN_DISTRIBUTION_CHANNEL).with_code("UNKNOWN_CHANNEL"))
+    }
+
+    /// the request's channel, `None` when it didn't send one
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, MyError> {
+        headers
+            .get(DISTRIBUTION_CHANNEL_HEADER)
+            .map(|value| {
+                value
+                    .to_str()
+                    .map_err(|_| {
+                        MyError::BadRequest(UNKNOWN_DISTRIBUTION_CHANNEL)
+                            .with_code("UNKNOWN_CHANNEL")
+                    })
+                    .and_then(DistributionChannel::parse)
+            })
+            .transpose()
+    }
+
+    pub fn is_beta(&self) -> bool {
+        *self == DistributionChannel::Beta
+    }
+
+    /// the beta flag a sync writes, without the header it's `None` and the stored flag is kept
+    pub fn beta_branch(headers: &HeaderMap) -> Result<Option<bool>, MyError> {
+        Ok(DistributionChannel::from_headers(headers)?.map(|channel| channel.is_beta()))
+    }
+}
+
+/// for routes that need the channel, use `DistributionChannel::from_headers` where it's optional
+impl FromRequest for DistributionChannel {
+    type Error = MyError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            DistributionChannel::from_headers(req.headers()).and_then(|channel| {
+                channel.ok_or(
+                    MyError::BadRequest("The X-Distribution-Channel header is missing")
+                        .with_code("MISSING_CHANNEL"),
+                )
+            }),
+        )
+    }
+}
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// marks the response of a create that was answered like the first create with its key
+pub const IDEMPOTENT_REPLAY_HEADER: &str = "Idempotent-Replayed";
+
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// a key the game sends with a create, a retry with the same key gets the first create's response
+/// instead of being told the account is already linked
+#[derive(Debug, PartialEq, Eq)]
+pub struct IdempotencyKey(pub String);
+
+impl IdempotencyKey {
+    /// `None` when the request didn't send one, a key that's empty, too long or not visible ASCII
+    /// is refused
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, MyError> {
+        let value = match headers.get(IDEMPOTENCY_KEY_HEADER) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        value
+            .to_str()
+            .ok()
+            .filter(|key| {
+                !key.is_empty()
+                    && key.len() <= MAX_IDEMPOTENCY_KEY_LENGTH
+                    && key.chars().all(|character| matches!(character, '!'..='~'))
+            })
+            .map(|key| Some(IdempotencyKey(key.to_owned())))
+            .ok_or(
+                MyError::BadRequest(
+                    "Idempotency-Key has to be 1 to 255 visible ASCII characters without spaces",
+                )
+                .with_code("INVALID_IDEMPOTENCY_KEY"),
+            )
+    }
+}
+
+/// the discord id the bot expects an account to be bound to, it's compared against the body's
+/// discord id so an id the player typed somewhere else can't get bound by accident
+pub struct ExpectedDiscordId(pub String);
+
+impl ExpectedDiscordId {
+    pub fn verify(&self, discord_id: &str) -> Result<(), MyError> {
+        if self.0 != discord_id {
+            return Err(MyError::Conflict(
+                "The discord id doesn't match the one in X-Expected-Discord-Id",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl TryIntoHeaderValue for ExpectedDiscordId {
+    type Error = InvalidHeaderValue;
+
+    fn try_into_value(self) -> Result<HeaderValue, Self::Error> {
+        HeaderValue::from_str(&self.0)
+    }
+}
+
+impl Header for ExpectedDiscordId {
+    fn name() -> HeaderName {
+        HeaderName::from_static("x-expected-discord-id")
+    }
+
+    fn parse<M: actix_web::HttpMessage>(msg: &M) -> Result<Self, actix_web::error::ParseError> {
+        let value = msg
+            .headers()
+            .get(Self::name())
+            .ok_or(actix_web::error::ParseError::Header)?;
+        let value = value
+            .to_str()
+            .map_err(|_| actix_web::error::ParseError::Header)?;
+        Ok(ExpectedDiscordId(value.trim().to_owned()))
+    }
+}
 
-pub struct DistributionChannel(pub String);
+pub const ADMIN_KEY_HEADER: &str = "x-admin-key";
 
-impl TryIntoHeaderValue for DistributionChannel {
+/// an admin API key of `ADMIN_API_KEYS`, logs only ever show its label
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AdminApiKey {
+    pub label: String,
+    pub key: String,
+}
+
+/// Parses `"{label}:{key},{label}:{key}"`, several keys are valid at once so they can be rotated
+/// without downtime. Entries without a label or a key are skipped.
+pub fn parse_admin_api_keys(value: &str) -> Vec<AdminApiKey> {
+    value
+        .split(',')
+        .filter_map(|entry| {
+            let (label, key) = entry.split_once(':')?;
+            let (label, key) = (label.trim(), key.trim());
+            (!label.is_empty() && !key.is_empty()).then(|| AdminApiKey {
+                label: label.to_owned(),
+                key: key.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// compares every byte wherever the first difference is, so only the length can be timed
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// the admin API key a request sent in `X-Admin-Key`
+pub struct AdminAuthorization(pub String);
+
+impl AdminAuthorization {
+    /// the configured key it is, every key is compared so the time doesn't give away which matched
+    pub fn verify<'a>(&self, keys: &'a [AdminApiKey]) -> Option<&'a AdminApiKey> {
+        keys.iter().fold(None, |matched, key| {
+            let matches = constant_time_eq(self.0.as_bytes(), key.key.as_bytes());
+            matched.or(matches.then_some(key))
+        })
+    }
+}
+
+impl TryIntoHeaderValue for AdminAuthorization {
     type Error = InvalidHeaderValue;
 
     fn try_into_value(self) -> Result<HeaderValue, Self::Error> {
@@ -14,9 +241,9 @@ impl TryIntoHeaderValue for DistributionChannel {
     }
 }
 
-impl Header for DistributionChannel {
+impl Header for AdminAuthorization {
     fn name() -> HeaderName {
-        HeaderName::from_static("x-distribution-channel")
+        HeaderName::from_static(ADMIN_KEY_HEADER)
     }
 
     fn parse<M: actix_web::HttpMessage>(msg: &M) -> Result<Self, actix_web::error::ParseError> {
@@ -24,7 +251,10 @@ impl Header for DistributionChannel {
             .headers()
             .get(Self::name())
             .ok_or(actix_web::error::ParseError::Header)?;
-        Ok(DistributionChannel(value.to_str().unwrap().to_string()))
+        let value = value
+            .to_str()
+            .map_err(|_| actix_web::error::ParseError::Header)?;
+        Ok(AdminAuthorization(value.trim().to_owned()))
     }
 }
 
@@ -57,7 +287,12 @@ impl Header for Authorization {
             .get(Self::name())
             .ok_or(actix_web::error::ParseError::Header)?;
 
-        let auth_data = AuthData::from(value.to_str().unwrap());
+        // a header that isn't `Basic {base64(email:token)}` is refused like a missing one
+        let auth_data = value
+            .to_str()
+            .ok()
+            .and_then(|value| safe_basic_auth_decoder(value).ok())
+            .ok_or(actix_web::error::ParseError::Header)?;
 
         Ok(Authorization {
             email: auth_data.email,
@@ -68,3 +303,153 @@ impl Header for Authorization {
 
 // TODO: I guess implement a header for parsing "x-secret-key" header just for create route?
 // note: may be better to receive a temporary discord token from a user via OAuth2 to confirm it's their account they're linking
+
+#[test]
+fn header_injection_is_refused() {
+    for value in [
+        "1\r\nSet-Cookie: a=b",
+        "1\nX-Injected: 1",
+        "1\r",
+        "1\u{7f}",
+        "1\0",
+    ] {
+        assert!(
+            matches!(sanitize_header_value(value), Err(MyError::BadRequest(_))),
+            "{:?}",
+            value
+        );
+    }
+
+    assert_eq!(sanitize_header_value("import-42").unwrap(), "import-42");
+    // values that can't be written in a header but aren't an attack are cleaned up
+    assert_eq!(sanitize_header_value("jöb 1").unwrap(), "jb 1");
+}
+
+#[test]
+fn mismatched_expected_discord_ids_are_a_conflict() {
+    let expected = ExpectedDiscordId("1".to_owned());
+
+    assert!(expected.verify("1").is_ok());
+    assert!(matches!(expected.verify("2"), Err(MyError::Conflict(_))));
+}
+
+#[test]
+fn distribution_channels_are_matched_in_any_casing() {
+    for (value, channel) in [
+        ("Beta", DistributionChannel::Beta),
+        ("beta", DistributionChannel::Beta),
+        ("BETA", DistributionChannel::Beta),
+        (" Beta ", DistributionChannel::Beta),
+        ("Steam", DistributionChannel::Steam),
+        ("steam", DistributionChannel::Steam),
+        ("MOBILE", DistributionChannel::Mobile),
+    ] {
+        assert_eq!(
+            DistributionChannel::parse(value).unwrap(),
+            channel,
+            "{}",
+            value
+        );
+    }
+
+    assert!(DistributionChannel::parse("Beta").unwrap().is_beta());
+    assert!(!DistributionChannel::parse("steam").unwrap().is_beta());
+}
+
+#[test]
+fn unknown_distribution_channels_are_refused() {
+    for value in ["", "Betaa", "nightly", "Beta; DROP"] {
+        let error = DistributionChannel::parse(value).unwrap_err();
+        assert!(
+            matches!(error.kind(), MyError::BadRequest(message) if message.contains("Beta, Steam or Mobile")),
+            "{}",
+            value
+        );
+    }
+
+    let request = actix_web::test::TestRequest::default()
+        .insert_header((DISTRIBUTION_CHANNEL_HEADER, "garbage"))
+        .to_http_request();
+    assert!(DistributionChannel::from_headers(request.headers()).is_err());
+    let request = actix_web::test::TestRequest::default().to_http_request();
+    assert_eq!(
+        DistributionChannel::from_headers(request.headers()).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn syncs_without_a_distribution_channel_keep_the_beta_flag() {
+    let beta_branch = |value: Option<&str>| {
+        let mut request = actix_web::test::TestRequest::default();
+        if let Some(value) = value {
+            request = request.insert_header((DISTRIBUTION_CHANNEL_HEADER, value));
+        }
+        DistributionChannel::beta_branch(request.to_http_request().headers())
+    };
+
+    assert_eq!(beta_branch(Some("Beta")).unwrap(), Some(true));
+    assert_eq!(beta_branch(Some("Steam")).unwrap(), Some(false));
+    assert_eq!(beta_branch(None).unwrap(), None);
+    assert!(beta_branch(Some("nightly")).is_err());
+}
+
+#[test]
+fn idempotency_keys_are_optional_but_checked() {
+    let idempotency_key = |value: Option<&str>| {
+        let mut request = actix_web::test::TestRequest::default();
+        if let Some(value) = value {
+            request = request.insert_header((IDEMPOTENCY_KEY_HEADER, value));
+        }
+        IdempotencyKey::from_headers(request.to_http_request().headers())
+    };
+
+    assert_eq!(
+        idempotency_key(Some("3f2c-retry")).unwrap(),
+        Some(IdempotencyKey("3f2c-retry".to_owned()))
+    );
+    assert_eq!(idempotency_key(None).unwrap(), None);
+    assert!(idempotency_key(Some("")).is_err());
+    assert!(idempotency_key(Some("two words")).is_err());
+    assert!(idempotency_key(Some(&"k".repeat(256))).is_err());
+    assert!(idempotency_key(Some(&"k".repeat(255))).is_ok());
+}
+
+#[test]
+fn admin_api_keys_can_be_rotated() {
+    let keys =
+        parse_admin_api_keys("bot:first-key, moderators : second-key,broken,:no-label,empty:");
+    assert_eq!(
+        keys,
+        vec![
+            AdminApiKey {
+                label: "bot".to_owned(),
+                key: "first-key".to_owned(),
+            },
+            AdminApiKey {
+                label: "moderators".to_owned(),
+                key: "second-key".to_owned(),
+            },
+        ]
+    );
+
+    let verify = |key: &str| {
+        AdminAuthorization(key.to_owned())
+            .verify(&keys)
+            .map(|key| key.label.as_str())
+    };
+    assert_eq!(verify("first-key"), Some("bot"));
+    assert_eq!(verify("second-key"), Some("moderators"));
+    assert_eq!(verify("second-kez"), None);
+    assert_eq!(verify("second-key-"), None);
+    assert_eq!(verify(""), None);
+    assert_eq!(AdminAuthorization("first-key".to_owned()).verify(&[]), None);
+}
+
+#[test]
+fn constant_time_comparisons_still_compare() {
+    assert!(constant_time_eq(b"secret", b"secret"));
+    assert!(!constant_time_eq(b"secret", b"secreT"));
+    assert!(!constant_time_eq(b"secret", b"secret2"));
+    assert!(constant_time_eq(b"", b""));
+}
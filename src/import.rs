@@ -0,0 +1,227 @@
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    evaluation::validate_payload,
+    models::{ImportFailureRecord, UpdateUserData},
+};
+
+/// how long the failed rows of an import are kept around for
+pub const FAILURE_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+static JOB_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// a single user that was exported from somewhere else, the token is already encoded
+#[derive(Deserialize)]
+pub struct ImportRow {
+    pub token: String,
+    pub discord_id: String,
+    #[serde(default)]
+    pub beta_tester: bool,
+    #[serde(default)]
+    pub data: UpdateUserData,
+}
+
+#[derive(Serialize, Deserialize, Display, Debug, PartialEq, Eq, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ImportFailureReason {
+    // the aliases read the failures stored before the tags were camelCase
+    #[display(fmt = "the token is already linked")]
+    #[serde(alias = "duplicate_token")]
+    DuplicateToken,
+    #[display(fmt = "the discord id is already linked")]
+    #[serde(alias = "duplicate_discord_id")]
+    DuplicateDiscordId,
+    #[display(fmt = "{} {}", field, reason)]
+    Validation { field: String, reason: String },
+    #[display(fmt = "line {} isn't a valid row", line)]
+    #[serde(alias = "malformed_row")]
+    MalformedRow { line: usize },
+    /// the database failed at checking or writing the row, nothing about the row itself is wrong
+    #[display(fmt = "the database failed at importing the row, it can be re-submitted")]
+    DatabaseError,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ImportFailure {
+    pub line: usize,
+    pub reason: ImportFailureReason,
+    /// the row exactly as it was submitted so it can be fixed and re-submitted
+    pub row: String,
+}
+
+impl From<ImportFailureRecord> for ImportFailure {
+    fn from(record: ImportFailureRecord) -> Self {
+        ImportFailure {
+            line: record.line as usize,
+            reason: serde_json::from_str(&record.reason).unwrap_or(
+                ImportFailureReason::MalformedRow {
+                    line: record.line as usize,
+                },
+            ),
+            row: record.row,
+        }
+    }
+}
+
+/// keeps track of what an import has already seen so duplicates within the same batch are caught
+#[derive(Default)]
+pub struct ImportBatch {
+    seen_tokens: HashSet<String>,
+    seen_discord_ids: HashSet<String>,
+}
+
+impl ImportBatch {
+    /// Checks everything about a row that doesn't need the database, `line` starts at 1.
+    pub fn classify_row(
+        &mut self,
+        line: usize,
+        row: &Value,
+    ) -> Result<ImportRow, ImportFailureReason> {
+        let row = serde_json::from_value::<ImportRow>(row.clone())
+            .map_err(|_| ImportFailureReason::MalformedRow { line })?;
+
+        if let Some(issue) = validate_payload(&row.data).into_iter().next() {
+            return Err(ImportFailureReason::Validation {
+                field: issue.field_path,
+                reason: issue.detail,
+            });
+        }
+
+        if !self.seen_tokens.insert(row.token.clone()) {
+            return Err(ImportFailureReason::DuplicateToken);
+        }
+        if !self.seen_discord_ids.insert(row.discord_id.clone()) {
+            return Err(ImportFailureReason::DuplicateDiscordId);
+        }
+
+        Ok(row)
+    }
+}
+
+pub fn new_job_id() -> String {
+    let millis = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    format!(
+        "{:x}-{:x}",
+        millis,
+        JOB_COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// renders the failures as CSV with a `line,reason,detail,row` header
+pub fn render_failures_csv(failures: &[ImportFailure]) -> String {
+    let mut csv = String::from("line,reason,detail,row\n");
+
+    for failure in failures {
+        let reason = match &failure.reason {
+            ImportFailureReason::DuplicateToken => "duplicate_token",
+            ImportFailureReason::DuplicateDiscordId => "duplicate_discord_id",
+            ImportFailureReason::Validation { .. } => "validation",
+            ImportFailureReason::MalformedRow { .. } => "malformed_row",
+            ImportFailureReason::DatabaseError => "database_error",
+        };
+
+        csv.push_str(&format!(
+            "{},{},{},{}\n",
+            failure.line,
+            reason,
+            escape_csv(&failure.reason.to_string()),
+            escape_csv(&failure.row)
+        ));
+    }
+
+    csv
+}
+
+fn escape_csv(value: &str) -> String {
+    if value.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+#[cfg(test)]
+fn row(token: &str, discord_id: &str) -> Value {
+    serde_json::json!({ "token": token, "discord_id": discord_id })
+}
+
+#[test]
+fn classifies_each_failure_reason() {
+    let mut batch = ImportBatch::default();
+
+    assert!(batch.classify_row(1, &row("a", "1")).is_ok());
+    assert_eq!(
+        batch.classify_row(2, &row("a", "2")).err(),
+        Some(ImportFailureReason::DuplicateToken)
+    );
+    assert_eq!(
+        batch.classify_row(3, &row("b", "1")).err(),
+        Some(ImportFailureReason::DuplicateDiscordId)
+    );
+    assert_eq!(
+        batch
+            .classify_row(
+                4,
+                &serde_json::json!({
+                    "token": "c",
+                    "discord_id": "3",
+                    "data": {
+                        "metabits": -1.0,
+                        "dino_rank": 0,
+                        "prestige_rank": 0,
+                        "beyond_rank": 0,
+                        "singularity_speedrun_time": null,
+                        "all_sharks_obtained": false,
+                        "all_hidden_achievements_obtained": false
+                    }
+                })
+            )
+            .err(),
+        Some(ImportFailureReason::Validation {
+            field: "metabits".to_owned(),
+            reason: "must not be negative".to_owned(),
+        })
+    );
+    assert_eq!(
+        batch
+            .classify_row(5, &serde_json::json!({ "token": 5 }))
+            .err(),
+        Some(ImportFailureReason::MalformedRow { line: 5 })
+    );
+}
+
+#[test]
+fn renders_failures_as_csv() {
+    let failures = vec![
+        ImportFailure {
+            line: 2,
+            reason: ImportFailureReason::DuplicateToken,
+            row: r#"{"token":"a","discord_id":"2"}"#.to_owned(),
+        },
+        ImportFailure {
+            line: 4,
+            reason: ImportFailureReason::Validation {
+                field: "metabits".to_owned(),
+                reason: "must not be negative".to_owned(),
+            },
+            row: "{}".to_owned(),
+        },
+    ];
+
+    assert_eq!(
+        render_failures_csv(&failures),
+        "line,reason,detail,row\n\
+         2,duplicate_token,the token is already linked,\"{\"\"token\"\":\"\"a\"\",\"\"discord_id\"\":\"\"2\"\"}\"\n\
+         4,validation,metabits must not be negative,{}\n"
+    );
+}
@@ -0,0 +1,134 @@
+use serde::{
+    de::{self, Visitor},
+    Deserializer, Serializer,
+};
+use std::fmt;
+
+/// for `#[serde(with)]` on i64 fields that are sent as strings, numbers are still accepted
+pub mod string_i64 {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+        deserializer.deserialize_any(I64Visitor)
+    }
+}
+
+/// for `#[serde(deserialize_with)]` on f64 fields that can come in as strings or numbers
+pub fn number_or_string_f64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    deserializer.deserialize_any(F64Visitor)
+}
+
+/// `number_or_string_f64` for optional fields, which need `#[serde(default)]` as well
+pub fn option_number_or_string_f64<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f64>, D::Error> {
+    number_or_string_f64(deserializer).map(Some)
+}
+
+struct I64Visitor;
+
+impl<'de> Visitor<'de> for I64Visitor {
+    type Value = i64;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an integer or a string holding one")
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<i64, E> {
+        Ok(value)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<i64, E> {
+        i64::try_from(value).map_err(|_| E::custom("the integer doesn't fit into 64 bits"))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<i64, E> {
+        // `i64::MAX as f64` rounds up to 2^63, which is already out of range
+        if value.fract() == 0.0 && value >= i64::MIN as f64 && value < i64::MAX as f64 {
+            Ok(value as i64)
+        } else {
+            Err(E::custom("expected a whole number"))
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<i64, E> {
+        value
+            .parse()
+            .map_err(|_| E::custom("the string doesn't hold an integer"))
+    }
+}
+
+struct F64Visitor;
+
+impl<'de> Visitor<'de> for F64Visitor {
+    type Value = f64;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a number or a string holding one")
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<f64, E> {
+        Ok(value as f64)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<f64, E> {
+        Ok(value as f64)
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<f64, E> {
+        Ok(value)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<f64, E> {
+        value
+            .parse()
+            .map_err(|_| E::custom("the string doesn't hold a number"))
+    }
+}
+
+/// the first integer JavaScript's numbers can't hold exactly
+#[cfg(test)]
+const PAST_JS_PRECISION: i64 = (1 << 53) + 1;
+
+#[cfg(test)]
+#[derive(serde::Deserialize, serde::Serialize, Debug, PartialEq)]
+struct Progress {
+    #[serde(with = "string_i64")]
+    metabits: i64,
+}
+
+#[test]
+fn integers_past_2_53_survive_a_round_trip() {
+    for metabits in [PAST_JS_PRECISION, i64::MAX, i64::MIN, -PAST_JS_PRECISION] {
+        let json = serde_json::to_string(&Progress { metabits }).unwrap();
+        assert_eq!(json, format!("{{\"metabits\":\"{}\"}}", metabits));
+        assert_eq!(
+            serde_json::from_str::<Progress>(&json).unwrap(),
+            Progress { metabits }
+        );
+    }
+}
+
+#[test]
+fn numbers_are_still_accepted() {
+    let read = |json: &str| serde_json::from_str::<Progress>(json).map(|read| read.metabits);
+
+    assert_eq!(
+        read("{\"metabits\":9007199254740993}").unwrap(),
+        9_007_199_254_740_993
+    );
+    assert_eq!(read("{\"metabits\":1e15}").unwrap(), 1_000_000_000_000_000);
+    assert!(read("{\"metabits\":1.5}").is_err());
+    assert!(read("{\"metabits\":18446744073709551615}").is_err());
+    assert!(read("{\"metabits\":\"lots\"}").is_err());
+
+    let read_f64 =
+        |json: &str| number_or_string_f64(&mut serde_json::Deserializer::from_str(json)).unwrap();
+    assert_eq!(read_f64("\"9007199254740992\""), 9_007_199_254_740_992.0);
+    assert_eq!(read_f64("7.5"), 7.5);
+    assert_eq!(read_f64("12"), 12.0);
+}
@@ -0,0 +1,179 @@
+use actix_web::{HttpResponse, ResponseError};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::Config, constants::LOG, errors::MyError, ttl_map::TtlMap, utilities::hex_encode,
+    webhook_logging::webhook_log,
+};
+
+/// failed link attempts are counted for a day from the first one
+pub const LINK_ATTEMPT_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+static LINK_ATTEMPTS: OnceLock<Mutex<LinkAttempts>> = OnceLock::new();
+
+/// Failed creates per email. Credential stuffing tries one email with lots of tokens, so they're
+/// counted by email rather than by the derived token, which changes with every guess.
+pub struct LinkAttempts {
+    failures: TtlMap<String, u32>,
+    max_failures: u32,
+}
+
+impl LinkAttempts {
+    pub fn new(window: Duration, max_failures: u32) -> Self {
+        LinkAttempts {
+            failures: TtlMap::new(window),
+            max_failures,
+        }
+    }
+
+    /// whether creates with the email are refused until its window passes
+    pub fn is_capped(&self, email_key: &str, now: Instant) -> bool {
+        self.failures
+            .get(&email_key.to_owned(), now)
+            .map_or(false, |failures| *failures >= self.max_failures)
+    }
+
+    /// counts a failed create, returns whether it's the one that reached the cap
+    pub fn record_failure(&mut self, email_key: &str, now: Instant) -> bool {
+        let key = email_key.to_owned();
+        let failures = match self.failures.get_mut(&key, now) {
+            Some(failures) => {
+                *failures += 1;
+                *failures
+            }
+            None => {
+                if self.failures.len() > 10_000 {
+                    self.failures.purge_expired(now);
+                }
+                self.failures.insert(key, 1, now);
+                1
+            }
+        };
+
+        failures == self.max_failures
+    }
+
+    /// the emails with the most failures and how much of their window is left, for keeping them
+    /// over a restart
+    pub fn hot_entries(&self, now: Instant, limit: usize) -> Vec<(String, u32, Duration)> {
+        let mut entries = self
+            .failures
+            .live_entries(now)
+            .into_iter()
+            .map(|(key, failures, remaining)| (key.clone(), *failures, remaining))
+            .collect::<Vec<_>>();
+        entries.sort_by(|(_, a, _), (_, b, _)| b.cmp(a));
+        entries.truncate(limit);
+
+        entries
+    }
+
+    /// puts back an email's failures from before a restart
+    pub fn restore(&mut self, email_key: &str, failures: u32, remaining: Duration, now: Instant) {
+        self.failures
+            .insert_remaining(email_key.to_owned(), failures, remaining, now);
+    }
+
+    /// a successful create starts the email over
+    pub fn reset(&mut self, email_key: &str) {
+        self.failures.remove(&email_key.to_owned());
+    }
+}
+
+pub fn link_attempts() -> &'static Mutex<LinkAttempts> {
+    LINK_ATTEMPTS.get_or_init(|| {
+        Mutex::new(LinkAttempts::new(
+            LINK_ATTEMPT_WINDOW,
+            Config::new().link_attempts_per_day,
+        ))
+    })
+}
+
+/// The key attempts are counted under. It's salted with a secret of its own, so the emails can't be
+/// recovered from the keys by hashing guessed emails, and case doesn't make a different email.
+pub fn email_key(email: &str, salt: &str) -> String {
+    let mut key =
+        Hmac::<Sha1>::new_from_slice(salt.as_bytes()).expect("HMAC takes keys of any length");
+    key.update(email.trim().to_lowercase().as_bytes());
+
+    hex_encode(&key.finalize().into_bytes())
+}
+
+/// records how a create went, only failures that are the client's fault count
+pub async fn record_link_attempt(email_key: &str, result: &Result<HttpResponse, MyError>) {
+    let reached_cap = match result {
+        Ok(_) => {
+            link_attempts().lock().unwrap().reset(email_key);
+            false
+        }
+        Err(error) if error.status_code().is_client_error() => link_attempts()
+            .lock()
+            .unwrap()
+            .record_failure(email_key, Instant::now()),
+        Err(_) => false,
+    };
+
+    if reached_cap {
+        webhook_log(
+            format!(
+                "an email (key {}) reached {} failed link attempts, further creates with it are refused for the rest of the day",
+                &email_key[..8],
+                Config::new().link_attempts_per_day
+            ),
+            LOG::FAILURE,
+        )
+        .await;
+    }
+}
+
+#[test]
+fn creates_are_refused_once_an_email_hits_the_cap() {
+    let now = Instant::now();
+    let mut attempts = LinkAttempts::new(LINK_ATTEMPT_WINDOW, 3);
+    let email = email_key("player@example.com", "salt");
+
+    assert!(!attempts.record_failure(&email, now));
+    assert!(!attempts.record_failure(&email, now));
+    assert!(!attempts.is_capped(&email, now));
+    // only the failure that reaches the cap is reported
+    assert!(attempts.record_failure(&email, now));
+    assert!(!attempts.record_failure(&email, now));
+    assert!(attempts.is_capped(&email, now));
+
+    // the window starts at the first failure and isn't extended by later ones
+    assert!(attempts.is_capped(&email, now + LINK_ATTEMPT_WINDOW - Duration::from_secs(1)));
+    assert!(!attempts.is_capped(&email, now + LINK_ATTEMPT_WINDOW));
+}
+
+#[test]
+fn a_successful_create_resets_the_email() {
+    let now = Instant::now();
+    let mut attempts = LinkAttempts::new(LINK_ATTEMPT_WINDOW, 2);
+    let email = email_key("player@example.com", "salt");
+
+    attempts.record_failure(&email, now);
+    attempts.reset(&email);
+    assert!(!attempts.record_failure(&email, now));
+    assert!(!attempts.is_capped(&email, now));
+}
+
+#[test]
+fn emails_are_counted_separately() {
+    let now = Instant::now();
+    let mut attempts = LinkAttempts::new(LINK_ATTEMPT_WINDOW, 1);
+    let email = email_key("player@example.com", "salt");
+    let other_email = email_key("other@example.com", "salt");
+
+    assert!(attempts.record_failure(&email, now));
+    assert!(attempts.is_capped(&email, now));
+    assert!(!attempts.is_capped(&other_email, now));
+
+    // the same email with different casing is the same email, a different salt is a different key
+    assert_eq!(email_key(" Player@Example.com", "salt"), email);
+    assert_ne!(email_key("player@example.com", "other-salt"), email);
+}
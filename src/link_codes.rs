@@ -0,0 +1,249 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+/// how long a link code can be redeemed for after the bot handed it out
+pub const LINK_CODE_TTL: Duration = Duration::from_secs(10 * 60);
+/// Crockford's base32, it leaves out the letters that are easy to mistake for others when typed
+const CODE_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const CODE_LENGTH: usize = 8;
+/// keeps the link payloads' signatures apart from the portable records', they share the secret
+const PAYLOAD_CONTEXT: &[u8] = b"link-code\n";
+
+static CODE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// a code the player can't guess without the secret, two codes for the same discord id differ
+pub fn new_link_code(secret: &str, discord_id: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let nonce = format!("{}-{}", nanos, CODE_COUNTER.fetch_add(1, Ordering::Relaxed));
+
+    derive_link_code(secret, discord_id, &nonce)
+}
+
+fn derive_link_code(secret: &str, discord_id: &str, nonce: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(b"link-code-id\n");
+    mac.update(discord_id.as_bytes());
+    mac.update(b"\n");
+    mac.update(nonce.as_bytes());
+
+    mac.finalize().into_bytes()[..CODE_LENGTH]
+        .iter()
+        .map(|byte| CODE_ALPHABET[(byte % 32) as usize] as char)
+        .collect()
+}
+
+/// codes are read case insensitively, players type them too
+pub fn normalize_link_code(code: &str) -> Option<String> {
+    let code = code.trim().to_ascii_uppercase();
+    (code.len() == CODE_LENGTH && code.bytes().all(|byte| CODE_ALPHABET.contains(&byte)))
+        .then_some(code)
+}
+
+/// what the QR code the bot shows carries, the game can check it without asking the API
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinkPayload {
+    pub code: String,
+    pub discord_id: String,
+    /// seconds since the unix epoch
+    pub expires_at: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LinkPayloadError {
+    /// it isn't a link code or a payload at all
+    Malformed,
+    /// it doesn't match its signature, it was changed or signed with another secret
+    Tampered,
+    Expired,
+}
+
+impl LinkPayload {
+    fn signed_text(&self) -> String {
+        format!("{}.{}.{}", self.code, self.discord_id, self.expires_at)
+    }
+
+    fn mac(secret: &str, text: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(PAYLOAD_CONTEXT);
+        mac.update(text.as_bytes());
+        mac
+    }
+
+    /// `{code}.{discord_id}.{expires_at}.{signature}`, every part is URL-safe so the payload can be
+    /// put into a QR code as it is or at the end of a link
+    pub fn sign(&self, secret: &str) -> String {
+        let text = self.signed_text();
+        let signature = Self::mac(secret, &text).finalize().into_bytes();
+
+        format!(
+            "{}.{}",
+            text,
+            base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+        )
+    }
+}
+
+/// Checks a payload the way the game does before it asks the player for their credentials, `now` is
+/// in seconds since the unix epoch. Only the API can tell whether the code was already used.
+pub fn verify_link_payload(
+    payload: &str,
+    secret: &str,
+    now: u64,
+) -> Result<LinkPayload, LinkPayloadError> {
+    let (text, signature) = payload
+        .trim()
+        .rsplit_once('.')
+        .ok_or(LinkPayloadError::Malformed)?;
+    let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| LinkPayloadError::Tampered)?;
+    // verify_slice compares in constant time
+    LinkPayload::mac(secret, text)
+        .verify_slice(&signature)
+        .map_err(|_| LinkPayloadError::Tampered)?;
+
+    let link_payload = match text.split('.').collect::<Vec<&str>>()[..] {
+        [code, discord_id, expires_at] => LinkPayload {
+            code: normalize_link_code(code).ok_or(LinkPayloadError::Malformed)?,
+            discord_id: discord_id.to_owned(),
+            expires_at: expires_at
+                .parse()
+                .map_err(|_| LinkPayloadError::Malformed)?,
+        },
+        _ => return Err(LinkPayloadError::Malformed),
+    };
+    if link_payload.expires_at <= now {
+        return Err(LinkPayloadError::Expired);
+    }
+
+    Ok(link_payload)
+}
+
+/// a create's `link_code`, the code as the player typed it or the payload of the QR code
+#[derive(Debug, PartialEq)]
+pub enum LinkCodeForm {
+    Code(String),
+    Payload(LinkPayload),
+}
+
+impl LinkCodeForm {
+    /// the payload is only verified with a secret, without one the form can't be used
+    pub fn parse(
+        value: &str,
+        secret: Option<&str>,
+        now: u64,
+    ) -> Result<LinkCodeForm, LinkPayloadError> {
+        if !value.contains('.') {
+            return normalize_link_code(value)
+                .map(LinkCodeForm::Code)
+                .ok_or(LinkPayloadError::Malformed);
+        }
+
+        let secret = secret.ok_or(LinkPayloadError::Tampered)?;
+        verify_link_payload(value, secret, now).map(LinkCodeForm::Payload)
+    }
+
+    pub fn code(&self) -> &str {
+        match self {
+            LinkCodeForm::Code(code) => code,
+            LinkCodeForm::Payload(payload) => &payload.code,
+        }
+    }
+}
+
+#[cfg(test)]
+fn payload(expires_at: u64) -> LinkPayload {
+    LinkPayload {
+        code: derive_link_code("secret", "80351110224678912", "nonce"),
+        discord_id: "80351110224678912".to_owned(),
+        expires_at,
+    }
+}
+
+#[test]
+fn link_codes_are_typeable_and_unique() {
+    let code = derive_link_code("secret", "80351110224678912", "1");
+    assert_eq!(code.len(), CODE_LENGTH);
+    assert_eq!(
+        normalize_link_code(&code.to_lowercase()),
+        Some(code.clone())
+    );
+    assert_ne!(derive_link_code("secret", "80351110224678912", "2"), code);
+    assert_ne!(derive_link_code("other", "80351110224678912", "1"), code);
+    assert_ne!(
+        new_link_code("secret", "80351110224678912"),
+        new_link_code("secret", "80351110224678912")
+    );
+
+    assert_eq!(normalize_link_code("ABC"), None);
+    // O and I aren't part of the alphabet, they'd be mistaken for 0 and 1
+    assert_eq!(normalize_link_code("OOOOIIII"), None);
+}
+
+#[test]
+fn link_payloads_are_verified_offline() {
+    let payload = payload(1_000);
+    let signed = payload.sign("secret");
+    assert!(signed
+        .bytes()
+        .all(|byte| byte.is_ascii_alphanumeric() || b".-_".contains(&byte)));
+
+    assert_eq!(verify_link_payload(&signed, "secret", 999), Ok(payload));
+    assert_eq!(
+        verify_link_payload(&signed, "other-secret", 999),
+        Err(LinkPayloadError::Tampered)
+    );
+
+    // another discord id can't be swapped in
+    let swapped = signed.replace("80351110224678912", "80351110224678913");
+    assert_eq!(
+        verify_link_payload(&swapped, "secret", 999),
+        Err(LinkPayloadError::Tampered)
+    );
+    assert_eq!(
+        verify_link_payload("no-signature", "secret", 999),
+        Err(LinkPayloadError::Malformed)
+    );
+}
+
+#[test]
+fn link_payloads_expire() {
+    let signed = payload(1_000).sign("secret");
+
+    assert_eq!(
+        verify_link_payload(&signed, "secret", 1_000),
+        Err(LinkPayloadError::Expired)
+    );
+    // a later expiry would change the signature
+    let extended = signed.replace(".1000.", ".9000.");
+    assert_eq!(
+        verify_link_payload(&extended, "secret", 1_000),
+        Err(LinkPayloadError::Tampered)
+    );
+}
+
+#[test]
+fn creates_take_either_form_of_a_link_code() {
+    let payload = payload(1_000);
+    let code = payload.code.clone();
+
+    assert_eq!(
+        LinkCodeForm::parse(&code.to_lowercase(), None, 999),
+        Ok(LinkCodeForm::Code(code.clone()))
+    );
+    let form = LinkCodeForm::parse(&payload.sign("secret"), Some("secret"), 999).unwrap();
+    assert_eq!(form.code(), code);
+    assert_eq!(form, LinkCodeForm::Payload(payload.clone()));
+
+    // without the secret a payload can't be trusted
+    assert_eq!(
+        LinkCodeForm::parse(&payload.sign("secret"), None, 999),
+        Err(LinkPayloadError::Tampered)
+    );
+}
@@ -1,59 +1,291 @@
 #![feature(result_option_inspect)]
 
+pub mod abuse_snapshot;
+pub mod audit;
+pub mod budget;
+pub mod bulk_resync;
+pub mod cleanup;
 pub mod config;
 pub mod constants;
+pub mod creation_locks;
 pub mod db;
+pub mod deprecations;
+pub mod digest;
+pub mod discord_pause;
+pub mod email_domains;
 pub mod errors;
+pub mod evaluation;
+pub mod extractors;
+pub mod failover;
+pub mod fields;
+pub mod granted_roles;
+pub mod guild_role_cache;
 mod handlers;
 pub mod headers;
+pub mod import;
+pub mod large_numbers;
+pub mod link_attempts;
+pub mod link_codes;
 pub mod middleware;
+pub mod middleware_stack;
+pub mod missing_roles;
 pub mod models;
+pub mod negative_cache;
+pub mod net;
+pub mod nicknames;
+pub mod og_allowlist;
+pub mod og_binary;
+pub mod og_conversion;
+pub mod og_dedup;
+pub mod partners;
+pub mod portability;
+pub mod promo_roles;
+pub mod purge;
+pub mod recent_errors;
+pub mod recovery;
+pub mod request_signing;
+pub mod role_debounce;
 pub mod role_handling;
+pub mod role_pauses;
+pub mod role_removal;
+pub mod role_rules;
+pub mod route_limits;
+pub mod routes;
+pub mod schema_compat;
+pub mod session_settings;
+pub mod slo;
+pub mod smoke;
+pub mod socket_activation;
+pub mod stats_history;
+pub mod status;
+pub mod support_codes;
+pub mod sync_streaks;
+pub mod sync_timings;
+pub mod timestamps;
+pub mod token_sharing;
+pub mod ttl_map;
+pub mod user_view;
 pub mod utilities;
 pub mod webhook_logging;
+pub mod write_behind;
 
 use actix_web::{
     guard, main,
     web::{self, Data},
     App, HttpServer,
 };
-use deadpool_postgres::Runtime;
 use dotenv::dotenv;
 use handlers::og_update_user;
-use tokio_postgres::NoTls;
+use std::time::Duration;
 use webhook_logging::webhook_log;
 
-use crate::handlers::{create_user, delete_user, update_user};
+use crate::handlers::{
+    clear_recent_errors, create_api_partner, create_link_code_qr, create_promo_rule, create_user,
+    delete_api_partner, delete_user, delete_user_by_id, explain_own_roles, export_portable_user,
+    find_users_by_fingerprint, get_api_partners, get_clock_skew, get_deprecations,
+    get_import_failures, get_own_granted_roles, get_own_progress, get_recent_errors,
+    get_role_resync, get_role_rules, get_slo, get_stats, get_stats_history, get_status, get_user,
+    get_user_by_id, get_user_granted_roles, get_user_role_trace, import_portable_user,
+    import_users, json_config, link_recovery_credential, list_users, negative_cache_status,
+    preview_digest, preview_roles, public_linked, ready, refresh_guild_role_cache,
+    register_support_code, reload_webhook, remove_recovery_credential, resync_all_roles,
+    resync_user_roles, simulate_user, status_page, sync_timings_status, touch_user,
+    update_beta_tester, update_privacy, update_role_rule, update_user, validate_progress, version,
+    webhook_status, write_behind_status,
+};
+use crate::middleware_stack::MiddlewareStack;
+use crate::schema_compat::SchemaState;
+use crate::socket_activation::ServerListener;
+
+const IMPORT_PAYLOAD_LIMIT: usize = 16 * 1024 * 1024;
+const PREVIEW_PAYLOAD_LIMIT: usize = 4 * 1024;
 
 #[main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
 
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("smoke") {
+        let base_url = args.next().expect("usage: discord-link smoke <base url>");
+        std::process::exit(smoke::run_smoke_command(&base_url).await);
+    }
+
     let config = crate::config::Config::new();
-    let pool = config.pg.create_pool(Some(Runtime::Tokio1), NoTls).unwrap();
+    // taken before anything else runs, so the socket's variables aren't passed on
+    let listener = ServerListener::from_env(&config.server_addr)?;
+    fields::set_progress_maxima(config.progress_maxima.clone());
+    let pool = session_settings::create_pool(&config.pg, config.session.clone())
+        .await
+        .expect("failed at setting up the database connections");
+    if config.migrate_on_startup {
+        db::migrate(&mut pool.get().await.unwrap(), &config.db_schema)
+            .await
+            .expect("failed at migrating the database schema");
+    }
+    let applied_schema_version = db::applied_schema_version(&pool.get().await.unwrap())
+        .await
+        .expect("failed at reading the database schema version");
+    let schema_state = SchemaState::at_startup(
+        applied_schema_version,
+        db::SCHEMA_VERSION,
+        config.serve_with_pending_migrations,
+    )
+    .map_err(|message| std::io::Error::new(std::io::ErrorKind::Other, message))?;
+    if schema_state.compatibility_mode {
+        println!(
+            "the database schema is at version {} but this binary expects {}, mutations are refused until it's upgraded",
+            schema_state.applied_version, schema_state.expected_version
+        );
+    }
+    if config.admin_allowlist_break_glass {
+        audit::security_log_break_glass(&pool, &config).await;
+    }
+    // warm-up, the abuse counters have to be back before the first request is served
+    let _ = abuse_snapshot::warm_up(&pool).await;
+    cleanup::spawn_cleanup_scheduler(
+        pool.clone(),
+        Duration::from_secs(config.idempotency_key_ttl),
+        config.stats_retention_days,
+        config.snapshot_retention_days,
+    );
+    og_conversion::spawn_drop_report_scheduler();
+    og_allowlist::spawn_reject_report_scheduler();
+    role_pauses::spawn_auto_resume_scheduler();
+    missing_roles::spawn_missing_role_scheduler(config.missing_role_disable_after);
+    promo_roles::spawn_promo_scheduler(pool.clone(), config.discord_token.clone());
+    guild_role_cache::spawn_guild_roles_scheduler(config.discord_token.clone());
+    role_removal::spawn_role_removal_worker(config.discord_token.clone());
+    role_debounce::spawn_debounce_worker(pool.clone(), Data::new(crate::config::Config::new()));
+    write_behind::spawn_flusher(pool.clone());
+    let shutdown_pool = pool.clone();
+    digest::spawn_digest_scheduler(pool.clone(), config.digest_utc_offset);
+    stats_history::spawn_stats_snapshot_scheduler(pool.clone());
+    token_sharing::spawn_token_sharing_scheduler(pool.clone(), config.token_sharing_threshold);
+    let middleware_stack = MiddlewareStack::new(&config);
+    println!(
+        "Middleware: {}",
+        middleware_stack
+            .layers()
+            .iter()
+            .map(|layer| layer.name())
+            .collect::<Vec<&str>>()
+            .join(", ")
+    );
+    let request_timeout = Duration::from_secs(config.request_timeout);
+    let request_signing_secret = config.request_signing_secret.clone();
+    let signature_window = config.signature_window;
 
     let server = HttpServer::new(move || {
-        App::new()
+        middleware_stack
+            .wrap(App::new())
             .app_data(Data::new(pool.clone()))
             .app_data(Data::new(crate::config::Config::new()))
-            .service(web::scope("/userdata").service(og_update_user))
+            .app_data(Data::new(schema_state))
+            .app_data(json_config())
+            .service(
+                web::scope("/userdata")
+                    .wrap(middleware::SignedRequests {
+                        secret: request_signing_secret.clone(),
+                        window: signature_window,
+                    })
+                    .wrap(middleware::RequestTimeout {
+                        timeout: request_timeout,
+                    })
+                    .service(og_update_user),
+            )
             .service(
                 web::scope("/v2/userdata")
                     .wrap(middleware::UserDataAuthorization {})
+                    .wrap(middleware::SignedRequests {
+                        secret: request_signing_secret.clone(),
+                        window: signature_window,
+                    })
+                    .wrap(middleware::RequestTimeout {
+                        timeout: request_timeout,
+                    })
                     .guard(guard::Header("content-type", "application/json"))
                     .service(create_user)
+                    .service(get_user)
                     .service(update_user)
+                    .service(update_privacy)
                     .service(delete_user),
             )
-    })
-    .bind(config.server_addr.clone())?
+            .service(web::scope("/health").service(ready))
+            .service(version)
+            .service(get_status)
+            .service(status_page)
+            .service(web::scope("/support-codes").service(register_support_code))
+            .service(
+                web::scope("/user")
+                    .app_data(json_config().limit(PREVIEW_PAYLOAD_LIMIT))
+                    .service(preview_roles)
+                    .service(validate_progress)
+                    .service(link_recovery_credential)
+                    .service(remove_recovery_credential)
+                    .service(get_own_granted_roles)
+                    .service(get_own_progress)
+                    .service(explain_own_roles)
+                    .service(touch_user),
+            )
+            .service(web::scope("/roles").service(get_role_rules))
+            .service(web::scope("/stats").service(get_stats_history))
+            .service(web::scope("/public").service(public_linked))
+            .service(web::scope("/bot").service(create_link_code_qr))
+            .service(
+                web::scope("/admin")
+                    // imports can contain thousands of rows
+                    .app_data(json_config().limit(IMPORT_PAYLOAD_LIMIT))
+                    .service(simulate_user)
+                    .service(export_portable_user)
+                    .service(import_portable_user)
+                    .service(get_user_granted_roles)
+                    .service(list_users)
+                    .service(get_user_by_id)
+                    .service(delete_user_by_id)
+                    .service(update_beta_tester)
+                    .service(resync_user_roles)
+                    .service(resync_all_roles)
+                    .service(get_role_resync)
+                    .service(get_user_role_trace)
+                    .service(find_users_by_fingerprint)
+                    .service(import_users)
+                    .service(get_import_failures)
+                    .service(webhook_status)
+                    .service(reload_webhook)
+                    .service(refresh_guild_role_cache)
+                    .service(get_recent_errors)
+                    .service(clear_recent_errors)
+                    .service(preview_digest)
+                    .service(get_stats)
+                    .service(update_role_rule)
+                    .service(create_promo_rule)
+                    .service(create_api_partner)
+                    .service(get_api_partners)
+                    .service(delete_api_partner)
+                    .service(write_behind_status)
+                    .service(negative_cache_status)
+                    .service(get_clock_skew)
+                    .service(sync_timings_status)
+                    .service(get_deprecations)
+                    .service(get_slo),
+            )
+    });
+    let running_at = listener.describe();
+    let server = match listener {
+        ServerListener::Inherited(listener) => server.listen(listener)?,
+        ServerListener::Bound(server_addr) => server.bind(server_addr)?,
+    }
     .run();
     webhook_log(
-        format!("Server running at http://{}/", config.server_addr),
+        format!("Server running at {}", running_at),
         constants::LOG::SUCCESSFUL,
     )
     .await;
-    println!("Server running at http://{}/", config.server_addr);
+    println!("Server running at {}", running_at);
 
-    server.await
+    let result = server.await;
+    // whatever is still buffered would be lost otherwise
+    let _ = write_behind::flush(&shutdown_pool).await;
+    let _ = abuse_snapshot::write_snapshot(&shutdown_pool).await;
+    result
 }
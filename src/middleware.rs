@@ -1,16 +1,44 @@
 use std::{
     future::{ready, Future, Ready},
     pin::Pin,
+    rc::Rc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use actix_web::{
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error,
+    error::InternalError,
+    http::header::{ContentType, HeaderName, HeaderValue},
+    rt::time,
+    web::{Bytes, Data},
+    Error, HttpMessage, HttpResponse,
 };
+use deadpool_postgres::Pool;
 
 use crate::{
+    budget::RequestBudget,
+    constants::LOG,
+    digest::{record_digest_count, unix_now, DigestCount},
+    errors::MyError,
+    headers::DistributionChannel,
     models::{GameSavesMetadataPostRequest, GameSavesMetadataResponse},
-    utilities::{safe_basic_auth_decoder, InvalidItems},
+    net::request_client_ip,
+    partners::{partner_keys, partner_limit, request_partner, PARTNER_KEY_HEADER},
+    recent_errors::{recent_errors, token_fingerprint, RecordedError},
+    request_signing::{
+        body_payload, check_window, remember_signature, seen_signatures, skew_histogram,
+        SignedRequest, SIGNATURE_HEADER, TIMESTAMP_HEADER,
+    },
+    route_limits::RouteLimits,
+    routes::RouteId,
+    schema_compat::{mid_upgrade_unavailable, SchemaState},
+    slo::slo_tracker,
+    support_codes::{
+        parse_auth_scheme, resolve_support_code, support_code_cache, AuthScheme,
+        ResolvedSupportCode,
+    },
+    utilities::{encode_user_token_v2, safe_basic_auth_decoder, InvalidItems},
+    webhook_logging::webhook_log,
 };
 
 type LocalBoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
@@ -26,7 +54,7 @@ pub struct UserDataAuthorization;
 // `B` - type of response's body
 impl<S, B> Transform<S, ServiceRequest> for UserDataAuthorization
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -38,20 +66,20 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(UserDataAuthorizationMiddleware {
-            service,
+            service: Rc::new(service),
             http_client: reqwest::Client::new(),
         }))
     }
 }
 
 pub struct UserDataAuthorizationMiddleware<S> {
-    service: S,
+    service: Rc<S>,
     http_client: reqwest::Client,
 }
 
 impl<S, B> Service<ServiceRequest> for UserDataAuthorizationMiddleware<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -62,32 +90,51 @@ where
     forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        let headers = &mut req.headers().clone();
-
-        let mut auth_header = headers.remove("authorization");
-        let mut distribution_header = headers.remove("X-Distribution-Channel");
-
-        let auth_header = auth_header.next();
-        let distribution_header = distribution_header.next();
-
+        let service = self.service.clone();
         let http_client = self.http_client.clone();
 
-        let fut = self.service.call(req);
         Box::pin(async move {
             // obtain the auth header and convert to string
-            let auth_header = auth_header.invalid_auth()?;
-            let auth_header = auth_header.to_str().invalid_auth()?;
+            let auth_header = req.headers().get("authorization").invalid_auth()?;
+            let auth_header = auth_header.to_str().invalid_auth()?.to_owned();
 
-            let auth_header_data = safe_basic_auth_decoder(auth_header)?;
+            let auth_header_data = match parse_auth_scheme(&auth_header)? {
+                AuthScheme::Basic(auth_data) => auth_data,
+                AuthScheme::SupportCode(code) => {
+                    let client = req
+                        .app_data::<Data<Pool>>()
+                        .invalid_header()?
+                        .get()
+                        .await
+                        .map_err(|_| {
+                            actix_web::error::ErrorServiceUnavailable(
+                                "request failed at creating database client, please try again",
+                            )
+                        })?;
+                    let user_tokens = resolve_support_code(&client, support_code_cache(), &code)
+                        .await?
+                        .ok_or_else(|| {
+                            actix_web::error::ErrorUnauthorized(
+                                "Unknown support code, check the code in the game's settings or use your email and token instead",
+                            )
+                        })?;
+
+                    // the game checked the credentials when it registered the code, and they
+                    // aren't kept to check them again
+                    req.extensions_mut()
+                        .insert(ResolvedSupportCode(user_tokens));
+                    return service.call(req).await;
+                }
+            };
 
             let config = crate::config::Config::new();
 
             // retrieve the distibution channel from the header
-            let distribution_header = distribution_header.invalid_header()?;
-            let distribution_header = distribution_header.to_str().invalid_header()?;
+            let distribution_channel =
+                DistributionChannel::from_headers(req.headers())?.invalid_header()?;
 
             // check which game save API to use based on the distribution channel
-            let url = if distribution_header == "Beta" {
+            let url = if distribution_channel.is_beta() {
                 config.game_saves_dev_api
             } else {
                 config.game_saves_prod_api
@@ -120,9 +167,498 @@ where
                 return Err(actix_web::error::ErrorUnauthorized("Invalid credentials"));
             }
 
-            let res = fut.await?;
+            let res = service.call(req).await?;
 
             Ok(res)
         })
     }
 }
+
+/// Resolves the `RouteId` of a request and stores it in the request extensions, every other
+/// middleware reads it from there. This has to be the outermost middleware.
+pub struct TagRoute;
+
+impl<S, B> Transform<S, ServiceRequest> for TagRoute
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = TagRouteMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TagRouteMiddleware { service }))
+    }
+}
+
+pub struct TagRouteMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for TagRouteMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let route = RouteId::resolve(req.method(), req.match_pattern().as_deref());
+        req.extensions_mut().insert(route);
+
+        // a schema newer than this binary could be written wrong, reads are still fine
+        let mid_upgrade = req
+            .app_data::<Data<SchemaState>>()
+            .map_or(false, |schema| schema.compatibility_mode);
+        if mid_upgrade && route.is_mutation() {
+            return Box::pin(ready(Err(mid_upgrade_unavailable().into())));
+        }
+
+        Box::pin(self.service.call(req))
+    }
+}
+
+/// the route a request was tagged with by `TagRoute`
+fn route_of(req: &ServiceRequest) -> RouteId {
+    req.extensions()
+        .get::<RouteId>()
+        .copied()
+        .unwrap_or(RouteId::Unknown)
+}
+
+/// Applies the rate limit bucket and concurrency semaphore of the class of the request's route.
+/// Requests with a partner's `X-Partner-Key` get the partner's multiple of the rate limit.
+pub struct ClassifiedRoute {
+    pub limits: RouteLimits,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ClassifiedRoute
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ClassifiedRouteMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ClassifiedRouteMiddleware {
+            service: Rc::new(service),
+            limits: self.limits.clone(),
+        }))
+    }
+}
+
+pub struct ClassifiedRouteMiddleware<S> {
+    service: Rc<S>,
+    limits: RouteLimits,
+}
+
+impl<S, B> Service<ServiceRequest> for ClassifiedRouteMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let class = route_of(&req).class();
+
+        let (class_limits, semaphore) = match self.limits.class(class) {
+            Some(class) => class,
+            None => return Box::pin(self.service.call(req)),
+        };
+
+        let client = request_client_ip(req.parts_mut().0, &self.limits.trusted_proxies)
+            .map_or_else(|| "unknown".to_owned(), |ip| ip.to_string());
+        let partner_key = req
+            .headers()
+            .get(PARTNER_KEY_HEADER)
+            .and_then(|key| key.to_str().ok())
+            .map(str::to_owned);
+        let db_pool = req.app_data::<Data<Pool>>().cloned();
+        let service = self.service.clone();
+        let limiter = self.limits.limiter.clone();
+
+        Box::pin(async move {
+            let partner = match &partner_key {
+                Some(key) => {
+                    request_partner(
+                        partner_keys(),
+                        db_pool.as_ref().map(Data::get_ref),
+                        key,
+                        Instant::now(),
+                    )
+                    .await
+                }
+                None => None,
+            };
+            let limit = partner_limit(
+                class_limits.requests_per_window,
+                partner.as_ref(),
+                SystemTime::now(),
+            );
+
+            let quota = limiter.acquire(class, &client, limit, Instant::now());
+            if !quota.allowed {
+                let message = format!("Too many {} requests, please slow down", class.label());
+                let mut response = HttpResponse::TooManyRequests();
+                response.content_type(ContentType::plaintext());
+                for header in quota.headers() {
+                    response.insert_header(header);
+                }
+                return Err(
+                    InternalError::from_response(message.clone(), response.body(message)).into(),
+                );
+            }
+
+            let permit = semaphore.try_acquire_owned().map_err(|_| {
+                actix_web::error::ErrorServiceUnavailable(format!(
+                    "The server is handling too many {} requests, please try again",
+                    class.label()
+                ))
+            })?;
+
+            let mut res = service.call(req).await;
+            drop(permit);
+            if let Ok(res) = &mut res {
+                for (name, value) in quota.headers() {
+                    res.headers_mut()
+                        .insert(HeaderName::from_static(name), HeaderValue::from(value));
+                }
+            }
+            res
+        })
+    }
+}
+
+/// Records every error response in the recent errors buffer that's served at GET /admin/errors.
+pub struct RecordErrors;
+
+impl<S, B> Transform<S, ServiceRequest> for RecordErrors
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RecordErrorsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RecordErrorsMiddleware { service }))
+    }
+}
+
+pub struct RecordErrorsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RecordErrorsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let endpoint = route_of(&req).label().to_owned();
+        let request_id = req
+            .headers()
+            .get("x-request-id")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let auth_header = req
+            .headers()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await;
+
+            let failure = match &res {
+                Ok(response) => response.response().error(),
+                Err(error) => Some(error),
+            };
+
+            if let Some(error) = failure {
+                let code = match error.as_error::<MyError>() {
+                    Some(my_error) => my_error.error_code().to_owned(),
+                    None => format!("HTTP_{}", error.as_response_error().status_code().as_u16()),
+                };
+                let fingerprint = auth_header
+                    .and_then(|header| safe_basic_auth_decoder(&header).ok())
+                    .map(|auth| {
+                        token_fingerprint(&encode_user_token_v2(
+                            &auth.email,
+                            &auth.token,
+                            &crate::config::Config::new().userdata_auth,
+                        ))
+                    });
+
+                record_digest_count(DigestCount::Error, &code);
+                recent_errors().lock().unwrap().push(RecordedError::new(
+                    code,
+                    error.to_string(),
+                    endpoint,
+                    request_id,
+                    fingerprint,
+                ));
+            }
+
+            res
+        })
+    }
+}
+
+/// Times every response of a route with an objective and counts it towards the route's SLO, a
+/// route whose error budget for the week runs out is reported once.
+pub struct TrackSlo;
+
+impl<S, B> Transform<S, ServiceRequest> for TrackSlo
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = TrackSloMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TrackSloMiddleware { service }))
+    }
+}
+
+pub struct TrackSloMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for TrackSloMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let route = route_of(&req);
+        let started = Instant::now();
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await;
+
+            // client errors are the client's, only server errors and slow responses are ours
+            let status = match &res {
+                Ok(response) => response.status(),
+                Err(error) => error.as_response_error().status_code(),
+            };
+            let alert = slo_tracker().lock().unwrap().record(
+                route,
+                started.elapsed(),
+                !status.is_server_error(),
+                unix_now(),
+            );
+            if let Some(alert) = alert {
+                actix_web::rt::spawn(webhook_log(alert.message(), LOG::FAILURE));
+            }
+
+            res
+        })
+    }
+}
+
+/// Times out requests that take longer than `timeout`, the deadline is stored in the request
+/// extensions as a `RequestBudget` so the database and Discord calls can stop in time too.
+pub struct RequestTimeout {
+    pub timeout: Duration,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestTimeout
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestTimeoutMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestTimeoutMiddleware {
+            service,
+            timeout: self.timeout,
+        }))
+    }
+}
+
+pub struct RequestTimeoutMiddleware<S> {
+    service: S,
+    timeout: Duration,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestTimeoutMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        req.extensions_mut()
+            .insert(RequestBudget::new(self.timeout, Instant::now()));
+
+        let timeout = self.timeout;
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            match time::timeout(timeout, fut).await {
+                Ok(res) => res,
+                Err(_) => {
+                    Err(MyError::Timeout("The request took too long, please try again").into())
+                }
+            }
+        })
+    }
+}
+
+/// Rejects requests that aren't signed with `secret`, whose timestamp is more than `window`
+/// seconds off or that were already received. An empty secret lets every request through.
+pub struct SignedRequests {
+    pub secret: String,
+    pub window: u64,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for SignedRequests
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SignedRequestsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SignedRequestsMiddleware {
+            service: Rc::new(service),
+            secret: self.secret.clone(),
+            window: self.window,
+        }))
+    }
+}
+
+pub struct SignedRequestsMiddleware<S> {
+    service: Rc<S>,
+    secret: String,
+    window: u64,
+}
+
+impl<S, B> Service<ServiceRequest> for SignedRequestsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        if self.secret.is_empty() {
+            return Box::pin(self.service.call(req));
+        }
+
+        let service = self.service.clone();
+        let secret = self.secret.clone();
+        let window = self.window;
+
+        Box::pin(async move {
+            let header = |name: &str| {
+                req.headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_owned)
+            };
+            let timestamp = header(TIMESTAMP_HEADER);
+            let signature = header(SIGNATURE_HEADER);
+            let method = req.method().to_string();
+            let path = req
+                .uri()
+                .path_and_query()
+                .map_or_else(|| req.path().to_owned(), |path| path.to_string());
+            let body = req.extract::<Bytes>().await?;
+
+            let server_time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64;
+            let skew_seconds = SignedRequest {
+                timestamp: timestamp.as_deref(),
+                signature: signature.as_deref(),
+                method: &method,
+                path: &path,
+                body: &body,
+            }
+            .verify(&secret, server_time)
+            .map_err(|error| error.into_error())?;
+
+            skew_histogram().lock().unwrap().record(skew_seconds);
+            check_window(skew_seconds, window, server_time).map_err(|error| error.into_error())?;
+            // verify only succeeds with a signature
+            remember_signature(
+                seen_signatures(window),
+                signature.as_deref().unwrap_or_default(),
+                Instant::now(),
+            )
+            .map_err(|error| error.into_error())?;
+
+            req.set_payload(body_payload(body));
+            service.call(req).await
+        })
+    }
+}
@@ -0,0 +1,171 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceFactory, ServiceRequest, ServiceResponse},
+    middleware::Condition,
+    App, Error,
+};
+
+use crate::{
+    config::Config,
+    middleware::{ClassifiedRoute, RecordErrors, TagRoute, TrackSlo},
+    route_limits::RouteLimits,
+};
+
+/// the middleware every request goes through, the per-scope ones like the request timeout aren't
+/// part of the stack
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    /// resolves the route of the request, the layers after it read it
+    TagRoute,
+    /// counts responses towards their route's SLO, it's outside the others so the time they take
+    /// is part of the latency
+    Slo,
+    /// keeps error responses for GET /admin/errors, it's outside the limits so their 429s and 503s
+    /// are recorded too
+    RecordErrors,
+    /// the per class rate limits and concurrency limits, they come before any handler parses a
+    /// body
+    RouteLimits,
+}
+
+impl Layer {
+    /// the order requests go through the layers in, outermost first
+    pub const ORDER: [Layer; 4] = [
+        Layer::TagRoute,
+        Layer::Slo,
+        Layer::RecordErrors,
+        Layer::RouteLimits,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layer::TagRoute => "tag_route",
+            Layer::Slo => "slo",
+            Layer::RecordErrors => "record_errors",
+            Layer::RouteLimits => "route_limits",
+        }
+    }
+
+    /// every other layer depends on the route being tagged, so it can't be turned off
+    pub fn is_required(&self) -> bool {
+        matches!(self, Layer::TagRoute)
+    }
+}
+
+/// parses `"record_errors,route_limits"`, unknown and required layers are refused
+pub fn parse_layers(layers: &str) -> Result<Vec<Layer>, String> {
+    layers
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(
+            |name| match Layer::ORDER.into_iter().find(|layer| layer.name() == name) {
+                Some(layer) if layer.is_required() => {
+                    Err(format!("the {} middleware can't be disabled", name))
+                }
+                Some(layer) => Ok(layer),
+                None => Err(format!("there's no middleware called {}", name)),
+            },
+        )
+        .collect()
+}
+
+/// the layers that are applied when `disabled` are turned off, outermost first
+pub fn effective_layers(disabled: &[Layer]) -> Vec<Layer> {
+    Layer::ORDER
+        .into_iter()
+        .filter(|layer| !disabled.contains(layer))
+        .collect()
+}
+
+/// Assembles the app's middleware in the order of `Layer::ORDER`, so adding a layer doesn't mean
+/// working out where its `wrap` call has to go.
+#[derive(Clone)]
+pub struct MiddlewareStack {
+    route_limits: RouteLimits,
+    layers: Vec<Layer>,
+}
+
+impl MiddlewareStack {
+    pub fn new(config: &Config) -> Self {
+        MiddlewareStack {
+            route_limits: RouteLimits::new(config),
+            layers: effective_layers(&config.disabled_middleware),
+        }
+    }
+
+    /// the layers that are applied, outermost first
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    fn is_enabled(&self, layer: Layer) -> bool {
+        self.layers.contains(&layer)
+    }
+
+    pub fn wrap<T, B>(
+        &self,
+        app: App<T>,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<impl MessageBody>,
+            Error = Error,
+            InitError = (),
+        >,
+    >
+    where
+        T: ServiceFactory<
+                ServiceRequest,
+                Config = (),
+                Response = ServiceResponse<B>,
+                Error = Error,
+                InitError = (),
+            > + 'static,
+        B: MessageBody + 'static,
+    {
+        // the layer that's wrapped last is the outermost, so this is `Layer::ORDER` backwards
+        app.wrap(Condition::new(
+            self.is_enabled(Layer::RouteLimits),
+            ClassifiedRoute {
+                limits: self.route_limits.clone(),
+            },
+        ))
+        .wrap(Condition::new(
+            self.is_enabled(Layer::RecordErrors),
+            RecordErrors,
+        ))
+        .wrap(Condition::new(self.is_enabled(Layer::Slo), TrackSlo))
+        .wrap(TagRoute)
+    }
+}
+
+#[test]
+fn layers_are_applied_in_the_documented_order() {
+    assert_eq!(
+        effective_layers(&[]),
+        vec![
+            Layer::TagRoute,
+            Layer::Slo,
+            Layer::RecordErrors,
+            Layer::RouteLimits
+        ]
+    );
+}
+
+#[test]
+fn disabling_a_layer_only_removes_that_layer() {
+    assert_eq!(
+        effective_layers(&parse_layers("route_limits").unwrap()),
+        vec![Layer::TagRoute, Layer::Slo, Layer::RecordErrors]
+    );
+    assert_eq!(
+        effective_layers(&parse_layers(" record_errors ,").unwrap()),
+        vec![Layer::TagRoute, Layer::Slo, Layer::RouteLimits]
+    );
+    assert_eq!(effective_layers(&parse_layers("").unwrap()), Layer::ORDER);
+
+    assert!(parse_layers("tag_route").is_err());
+    assert!(parse_layers("compression").is_err());
+}
@@ -0,0 +1,150 @@
+use actix_web::rt::{self, time};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use crate::{
+    constants::LOG, digest::unix_now, role_pauses::role_pauses, webhook_logging::webhook_log,
+};
+
+/// a missing role is logged at most this often, no matter how many syncs run into it
+pub const MISSING_ROLE_LOG_INTERVAL: u64 = 60 * 60;
+const DISABLE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+static MISSING_ROLES: OnceLock<Mutex<MissingRoles>> = OnceLock::new();
+
+struct MissingRole {
+    /// seconds since the unix epoch
+    first_seen: u64,
+    last_logged: u64,
+}
+
+/// Roles the rules grant that were deleted from the guild, they're skipped by syncs until an admin
+/// fixes the rule or the role shows up again.
+#[derive(Default)]
+pub struct MissingRoles {
+    missing: HashMap<u64, MissingRole>,
+}
+
+impl MissingRoles {
+    /// records that a sync ran into the missing role, returns whether it should be logged
+    pub fn record(&mut self, role_id: u64, now: u64) -> bool {
+        match self.missing.get_mut(&role_id) {
+            Some(role) if now.saturating_sub(role.last_logged) < MISSING_ROLE_LOG_INTERVAL => false,
+            Some(role) => {
+                role.last_logged = now;
+                true
+            }
+            None => {
+                self.missing.insert(
+                    role_id,
+                    MissingRole {
+                        first_seen: now,
+                        last_logged: now,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    /// roles that were applied successfully exist again
+    pub fn clear(&mut self, role_ids: &[u64]) {
+        for role_id in role_ids {
+            self.missing.remove(role_id);
+        }
+    }
+
+    /// takes the roles that have been missing for at least `disable_after` seconds
+    pub fn take_expired(&mut self, disable_after: u64, now: u64) -> Vec<u64> {
+        let expired = self
+            .missing
+            .iter()
+            .filter(|(_, role)| now.saturating_sub(role.first_seen) >= disable_after)
+            .map(|(role_id, _)| *role_id)
+            .collect::<Vec<u64>>();
+        self.clear(&expired);
+
+        expired
+    }
+}
+
+pub fn missing_roles() -> &'static Mutex<MissingRoles> {
+    MISSING_ROLES.get_or_init(|| Mutex::new(MissingRoles::default()))
+}
+
+/// logs the roles a sync found missing, each role at most once per `MISSING_ROLE_LOG_INTERVAL`
+pub async fn report_missing_roles(role_ids: &[u64]) {
+    let now = unix_now();
+    let due = role_ids
+        .iter()
+        .filter(|role_id| missing_roles().lock().unwrap().record(**role_id, now))
+        .copied()
+        .collect::<Vec<u64>>();
+
+    for role_id in due {
+        webhook_log(
+            format!(
+                "the role {} no longer exists in the guild and is skipped by every sync, please fix its rule",
+                role_id
+            ),
+            LOG::FAILURE,
+        )
+        .await;
+    }
+}
+
+/// pauses the rules of roles that have been missing for longer than `disable_after` seconds
+pub fn spawn_missing_role_scheduler(disable_after: u64) {
+    rt::spawn(async move {
+        let mut interval = time::interval(DISABLE_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let expired = missing_roles()
+                .lock()
+                .unwrap()
+                .take_expired(disable_after, unix_now());
+            for role_id in expired {
+                role_pauses().lock().unwrap().pause(role_id, None);
+                webhook_log(
+                    format!(
+                        "the role {} has been missing from the guild for {} hours, its rule was disabled until it's resumed through PATCH /admin/role-rules/{}",
+                        role_id,
+                        disable_after / (60 * 60),
+                        role_id
+                    ),
+                    LOG::FAILURE,
+                )
+                .await;
+            }
+        }
+    });
+}
+
+#[test]
+fn missing_roles_are_logged_once_per_interval() {
+    let mut missing = MissingRoles::default();
+
+    assert!(missing.record(1, 1_000));
+    assert!(!missing.record(1, 1_000 + MISSING_ROLE_LOG_INTERVAL - 1));
+    assert!(missing.record(2, 1_000));
+    assert!(missing.record(1, 1_000 + MISSING_ROLE_LOG_INTERVAL));
+
+    // a role that's back starts over
+    missing.clear(&[1]);
+    assert!(missing.record(1, 1_001));
+}
+
+#[test]
+fn roles_missing_for_too_long_are_disabled_once() {
+    let mut missing = MissingRoles::default();
+    missing.record(1, 0);
+    missing.record(2, 500);
+
+    assert_eq!(missing.take_expired(1_000, 999), Vec::<u64>::new());
+    assert_eq!(missing.take_expired(1_000, 1_000), vec![1]);
+    assert_eq!(missing.take_expired(1_000, 1_000), Vec::<u64>::new());
+    assert_eq!(missing.take_expired(1_000, 1_500), vec![2]);
+}
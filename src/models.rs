@@ -1,13 +1,21 @@
-use serde::{Deserialize, Serialize};
-use std::time::SystemTime;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use std::{borrow::Cow, time::SystemTime};
 use tokio_pg_mapper_derive::PostgresMapper;
 
+use crate::evaluation::{validate_payload, ValidationIssue};
+use crate::portability::PortableUser;
+use crate::role_rules::ClosestMiss;
+use crate::sync_timings::TimingsReport;
+use crate::utilities::DerivedTokens;
+
 #[derive(Deserialize, PostgresMapper, Serialize)]
 #[pg_mapper(table = "UserData")]
 pub struct UserData {
     pub discord_id: String,
     pub token: String,
     pub beta_tester: bool,
+    #[serde(with = "crate::large_numbers::string_i64")]
     pub metabits: i64,
     pub dino_rank: i32,
     pub prestige_rank: i32,
@@ -15,15 +23,17 @@ pub struct UserData {
     pub singularity_speedrun_time: Option<f64>,
     pub all_sharks_obtained: bool,
     pub all_hidden_achievements_obtained: bool,
+    #[serde(with = "crate::timestamps::system_time")]
     pub edited_timestamp: SystemTime,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct OGUpdateUserData {
     #[serde(rename = "playerToken")]
     pub player_token: String,
+    /// the stored flag is kept when this is left out
     #[serde(rename = "betaTester")]
-    pub beta_tester: bool,
+    pub beta_tester: Option<bool>,
     pub metabits: f64,
     pub dino_rank: i32,
     pub prestige_rank: i32,
@@ -33,8 +43,9 @@ pub struct OGUpdateUserData {
     pub all_hidden_achievements_obtained: bool,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct UpdateUserData {
+    #[serde(deserialize_with = "crate::large_numbers::number_or_string_f64")]
     pub metabits: f64,
     pub dino_rank: i32,
     pub prestige_rank: i32,
@@ -44,12 +55,118 @@ pub struct UpdateUserData {
     pub all_hidden_achievements_obtained: bool,
 }
 
+/// The body of `PATCH /userdata`, fields that are left out keep their stored value. Sending
+/// `null` as the speedrun time clears it, like leaving it out of a full payload does.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct PatchUserData {
+    #[serde(
+        default,
+        deserialize_with = "crate::large_numbers::option_number_or_string_f64"
+    )]
+    pub metabits: Option<f64>,
+    pub dino_rank: Option<i32>,
+    pub prestige_rank: Option<i32>,
+    pub beyond_rank: Option<i32>,
+    #[serde(default, deserialize_with = "present")]
+    pub singularity_speedrun_time: Option<Option<f64>>,
+    pub all_sharks_obtained: Option<bool>,
+    pub all_hidden_achievements_obtained: Option<bool>,
+}
+
+/// tells a field that's `null` (`Some(None)`) apart from one that's left out (`None`)
+fn present<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+impl PatchUserData {
+    pub fn is_empty(&self) -> bool {
+        *self == PatchUserData::default()
+    }
+
+    /// the full payload the patch makes out of the stored data
+    pub fn apply_to(&self, current: &UserData) -> UpdateUserData {
+        UpdateUserData {
+            metabits: self.metabits.unwrap_or(current.metabits as f64),
+            dino_rank: self.dino_rank.unwrap_or(current.dino_rank),
+            prestige_rank: self.prestige_rank.unwrap_or(current.prestige_rank),
+            beyond_rank: self.beyond_rank.unwrap_or(current.beyond_rank),
+            singularity_speedrun_time: self
+                .singularity_speedrun_time
+                .unwrap_or(current.singularity_speedrun_time),
+            all_sharks_obtained: self
+                .all_sharks_obtained
+                .unwrap_or(current.all_sharks_obtained),
+            all_hidden_achievements_obtained: self
+                .all_hidden_achievements_obtained
+                .unwrap_or(current.all_hidden_achievements_obtained),
+        }
+    }
+
+    /// the values of `payload` for the fields this patch sent, the others are still left out
+    pub fn sent_values(&self, payload: &UpdateUserData) -> PatchUserData {
+        PatchUserData {
+            metabits: self.metabits.map(|_| payload.metabits),
+            dino_rank: self.dino_rank.map(|_| payload.dino_rank),
+            prestige_rank: self.prestige_rank.map(|_| payload.prestige_rank),
+            beyond_rank: self.beyond_rank.map(|_| payload.beyond_rank),
+            singularity_speedrun_time: self
+                .singularity_speedrun_time
+                .map(|_| payload.singularity_speedrun_time),
+            all_sharks_obtained: self
+                .all_sharks_obtained
+                .map(|_| payload.all_sharks_obtained),
+            all_hidden_achievements_obtained: self
+                .all_hidden_achievements_obtained
+                .map(|_| payload.all_hidden_achievements_obtained),
+        }
+    }
+}
+
+/// a full payload sends every field
+impl From<UpdateUserData> for PatchUserData {
+    fn from(data: UpdateUserData) -> Self {
+        PatchUserData {
+            metabits: Some(data.metabits),
+            dino_rank: Some(data.dino_rank),
+            prestige_rank: Some(data.prestige_rank),
+            beyond_rank: Some(data.beyond_rank),
+            singularity_speedrun_time: Some(data.singularity_speedrun_time),
+            all_sharks_obtained: Some(data.all_sharks_obtained),
+            all_hidden_achievements_obtained: Some(data.all_hidden_achievements_obtained),
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct CreateUserData {
+    /// left out when the discord id comes from `link_code`
+    #[serde(default)]
     pub discord_id: String,
+    /// a code the bot handed out with POST bot/link-codes/qr, as typed or as its QR payload
+    pub link_code: Option<String>,
     pub data: Option<UpdateUserData>,
 }
 
+impl Default for UserData {
+    fn default() -> Self {
+        UserData {
+            discord_id: String::new(),
+            token: String::new(),
+            beta_tester: false,
+            metabits: 0,
+            dino_rank: 0,
+            prestige_rank: 0,
+            beyond_rank: 0,
+            singularity_speedrun_time: None,
+            all_sharks_obtained: false,
+            all_hidden_achievements_obtained: false,
+            edited_timestamp: SystemTime::UNIX_EPOCH,
+        }
+    }
+}
+
 impl Default for UpdateUserData {
     fn default() -> Self {
         UpdateUserData {
@@ -64,6 +181,19 @@ impl Default for UpdateUserData {
     }
 }
 
+impl UpdateUserData {
+    /// checks the values against the field bounds and the configured maxima before they're
+    /// written, the issues name the fields that are off
+    pub fn validate(&self) -> Result<(), Vec<ValidationIssue>> {
+        let issues = validate_payload(self);
+        if !issues.is_empty() {
+            return Err(issues);
+        }
+
+        Ok(())
+    }
+}
+
 impl From<OGUpdateUserData> for UpdateUserData {
     fn from(data: OGUpdateUserData) -> Self {
         UpdateUserData {
@@ -78,11 +208,585 @@ impl From<OGUpdateUserData> for UpdateUserData {
     }
 }
 
+/// the tokens the credentials a support code was registered with derive to
+#[derive(Deserialize, PostgresMapper, Serialize)]
+#[pg_mapper(table = "SupportCodes")]
+pub struct SupportCodeRecord {
+    pub support_code: String,
+    pub v2_token: String,
+    pub legacy_token: String,
+    #[serde(with = "crate::timestamps::system_time")]
+    pub edited_timestamp: SystemTime,
+}
+
+impl SupportCodeRecord {
+    pub fn user_tokens(&self) -> DerivedTokens {
+        DerivedTokens {
+            v2: self.v2_token.clone(),
+            legacy: self.legacy_token.clone(),
+        }
+    }
+}
+
+/// request structure for registering the support code the game shows a player
+#[derive(Deserialize)]
+pub struct SupportCodeRegistration {
+    pub support_code: String,
+    pub email: String,
+    pub token: String,
+}
+
+/// a row of an admin import that failed, kept around so it can be fixed and re-submitted
+#[derive(Deserialize, PostgresMapper, Serialize)]
+#[pg_mapper(table = "ImportFailures")]
+pub struct ImportFailureRecord {
+    pub job_id: String,
+    pub line: i32,
+    /// the serialized `ImportFailureReason`
+    pub reason: String,
+    pub row: String,
+    #[serde(with = "crate::timestamps::system_time")]
+    pub created_timestamp: SystemTime,
+}
+
+/// an account whose token starts with a searched fingerprint
+#[derive(Deserialize, PostgresMapper, Serialize)]
+#[pg_mapper(table = "UserData")]
+pub struct FingerprintMatch {
+    pub discord_id: String,
+    pub token_fingerprint: String,
+    #[serde(with = "crate::timestamps::system_time")]
+    pub edited_timestamp: SystemTime,
+    pub beta_tester_locked: bool,
+}
+
+/// a token a discord id synced with, from its snapshots
+#[derive(Clone, Debug, PartialEq, PostgresMapper)]
+#[pg_mapper(table = "UserDataSnapshots")]
+pub struct TokenUse {
+    pub discord_id: String,
+    pub token_fingerprint: String,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+    /// an admin flow moved the discord id to this token, so it doesn't count as sharing
+    pub whitelisted: bool,
+}
+
+/// an abuse counter entry that's kept over restarts
+#[derive(PostgresMapper)]
+#[pg_mapper(table = "AbuseCounters")]
+pub struct AbuseCounterRow {
+    /// which counter the entry belongs to, see `AbuseCounter`
+    pub counter: String,
+    pub key: String,
+    pub value: i64,
+    pub expires_timestamp: SystemTime,
+}
+
+/// how often something the weekly digest counts happened within its window, see `DigestCount`
+#[derive(Deserialize, PostgresMapper, Serialize)]
+#[pg_mapper(table = "DigestCounts")]
+pub struct DigestCountRow {
+    pub kind: String,
+    pub name: String,
+    pub count: i64,
+}
+
+/// the usage of a deprecated feature on a single day
+#[derive(Deserialize, PostgresMapper, Serialize)]
+#[pg_mapper(table = "DeprecationUsage")]
+pub struct DeprecationUsageRow {
+    pub feature: String,
+    /// `YYYY-MM-DD`
+    pub day: String,
+    pub uses: i64,
+    /// how many distinct client fingerprints used the feature that day
+    pub clients: i64,
+}
+
+/// query structure for choosing the format of a downloadable report
+#[derive(Deserialize)]
+pub struct ReportFormat {
+    /// either "csv" or "json", defaults to json
+    pub format: Option<String>,
+}
+
+/// request structure for previewing which roles some progress would earn
+#[derive(Deserialize)]
+pub struct RolesPreviewRequest {
+    pub data: UpdateUserData,
+    #[serde(default)]
+    pub beta_tester: bool,
+}
+
+/// request structure for checking a payload without syncing it, `data` is read as JSON so every
+/// field's problem can be reported instead of the first one serde runs into
+#[derive(Deserialize)]
+pub struct ValidateProgressRequest {
+    pub data: Value,
+    /// the progress the payload would be synced on top of, to check that nothing goes backwards
+    #[serde(default)]
+    pub previous: Option<UpdateUserData>,
+}
+
+/// response structure for /user/validate
+#[derive(Serialize)]
+pub struct ValidationReport {
+    pub valid: bool,
+    pub issues: Vec<ValidationIssue>,
+}
+
+/// query structure for the update endpoints
+#[derive(Deserialize)]
+pub struct SyncOptions {
+    /// skip the fields that can't be written instead of rejecting the whole payload
+    #[serde(default)]
+    pub partial: bool,
+    /// take away the managed roles the progress doesn't earn anymore, a sync only adds roles
+    /// without it
+    #[serde(default)]
+    pub reconcile: bool,
+    /// answer with the time each stage of the sync took, a handful of times an hour per user
+    #[serde(default)]
+    pub debug_timing: bool,
+}
+
+/// query structure for POST admin/users/{discord_id}/resync, it reconciles unless told otherwise
+#[derive(Deserialize)]
+pub struct ResyncOptions {
+    #[serde(default = "reconcile_by_default")]
+    pub reconcile: bool,
+}
+
+fn reconcile_by_default() -> bool {
+    true
+}
+
+/// query structure for POST user/touch
+#[derive(Deserialize)]
+pub struct TouchOptions {
+    /// count the touch towards the sync streak like a sync would
+    #[serde(default)]
+    pub streak: bool,
+}
+
+/// query structure for the routes that can add the computed fields, e.g. `?include=computed`
+#[derive(Deserialize, Default)]
+pub struct ViewOptions {
+    #[serde(default)]
+    pub include: String,
+}
+
+impl ViewOptions {
+    pub fn computed(&self) -> bool {
+        self.include
+            .split(',')
+            .any(|part| part.trim() == "computed")
+    }
+}
+
+/// query structure for filtering the recent errors
+#[derive(Deserialize)]
+pub struct RecentErrorsQuery {
+    pub code: Option<String>,
+    /// milliseconds since the unix epoch
+    pub since: Option<u64>,
+}
+
+/// query structure for a metric's daily history, `days` counts back from yesterday
+#[derive(Deserialize)]
+pub struct StatsHistoryQuery {
+    pub metric: String,
+    pub days: Option<u32>,
+}
+
+/// query structure for GET admin/users, `cursor` is the `next_cursor` of the previous page
+#[derive(Deserialize)]
+pub struct UserListQuery {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+/// request structure for swapping the logging webhook without a restart
+#[derive(Deserialize)]
+pub struct WebhookReloadRequest {
+    pub webhook_id: String,
+    pub webhook_token: String,
+}
+
+/// a role that's granted to everyone who syncs between `starts_at` and `ends_at`
+#[derive(Clone, Debug, Deserialize, PostgresMapper, Serialize)]
+#[pg_mapper(table = "PromoRoleRules")]
+pub struct PromoRoleRule {
+    pub id: i64,
+    pub role_id: String,
+    pub name: String,
+    #[serde(with = "crate::timestamps::system_time")]
+    pub starts_at: SystemTime,
+    #[serde(with = "crate::timestamps::system_time")]
+    pub ends_at: SystemTime,
+    /// whether the role was already taken away from everyone after the rule ended
+    pub swept: bool,
+    #[serde(with = "crate::timestamps::system_time")]
+    pub created_timestamp: SystemTime,
+}
+
+/// request structure for creating a promo role rule, the times are seconds since the unix epoch
+#[derive(Deserialize)]
+pub struct PromoRoleRuleRequest {
+    pub role_id: String,
+    pub name: String,
+    pub starts_at: u64,
+    pub ends_at: u64,
+}
+
+/// an integration that's trusted with higher rate limits, it sends its key as `X-Partner-Key`
+#[derive(Clone, Debug, PostgresMapper, Serialize)]
+#[pg_mapper(table = "ApiPartners")]
+pub struct ApiPartner {
+    pub id: i64,
+    /// the SHA-256 of the key, the key itself is never stored
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub label: String,
+    /// how many times the normal limits the partner gets
+    pub multiplier: i32,
+    /// the key is ignored afterwards, `None` keeps it working until it's deleted
+    #[serde(serialize_with = "crate::timestamps::system_time::serialize_option")]
+    pub expires_at: Option<SystemTime>,
+    #[serde(with = "crate::timestamps::system_time")]
+    pub created_timestamp: SystemTime,
+}
+
+/// request structure for POST bot/link-codes/qr
+#[derive(Deserialize)]
+pub struct LinkCodeRequest {
+    pub discord_id: String,
+}
+
+/// a link code with the payload the bot puts into a QR code for it
+#[derive(Serialize)]
+pub struct LinkCodeResponse {
+    pub code: String,
+    pub discord_id: String,
+    pub qr_payload: String,
+    #[serde(serialize_with = "crate::timestamps::unix_seconds::serialize")]
+    pub expires_at: u64,
+}
+
+/// counts for the bot's status page, the users are counted by the database
+#[derive(Serialize, Debug, PartialEq)]
+pub struct StatsResponse {
+    pub total_linked: i64,
+    pub beta_testers: i64,
+    /// every milestone and configured role rule, in the order the roles are granted in
+    pub milestones: Vec<MilestoneCount>,
+}
+
+/// how many users meet a role rule's requirement, whether or not a higher tier replaced the role
+#[derive(Serialize, Debug, PartialEq)]
+pub struct MilestoneCount {
+    /// a string since role ids don't fit into JavaScript's numbers
+    pub role_id: String,
+    pub role_name: Cow<'static, str>,
+    pub users: i64,
+}
+
+/// request structure for adding a partner, `expires_at` is seconds since the unix epoch
+#[derive(Deserialize)]
+pub struct ApiPartnerRequest {
+    pub key: String,
+    pub label: String,
+    pub multiplier: u32,
+    pub expires_at: Option<u64>,
+}
+
+/// a partner with the requests it made since the service started
+#[derive(Serialize)]
+pub struct ApiPartnerUsage {
+    #[serde(flatten)]
+    pub partner: ApiPartner,
+    pub requests: u64,
+}
+
+/// request structure for setting a user's beta tester status by hand
+#[derive(Deserialize)]
+pub struct BetaTesterUpdate {
+    pub beta_tester: bool,
+    /// syncs leave a locked status alone, whichever channel they come from
+    pub locked: bool,
+}
+
+/// a user's beta tester status after an admin set it
+#[derive(Deserialize, PostgresMapper, Serialize)]
+#[pg_mapper(table = "UserData")]
+pub struct BetaTesterStatus {
+    pub discord_id: String,
+    pub beta_tester: bool,
+    pub beta_tester_locked: bool,
+}
+
+/// request structure for pausing or resuming the granting of a milestone role
+#[derive(Deserialize)]
+pub struct RoleRuleUpdate {
+    pub paused: bool,
+    /// seconds since the unix epoch, the role is granted again afterwards
+    pub resume_at: Option<u64>,
+}
+
+/// whether a role rule is currently granted
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum RuleStatus {
+    Active,
+    TemporarilyPaused,
+    UpcomingPromo,
+    ActivePromo,
+    /// the rule reads a field the game doesn't have anymore, members keep the role if they have it
+    DeprecatedField,
+}
+
+/// response structure for a milestone role and whether it's currently granted
+#[derive(Serialize)]
+pub struct RoleRuleStatus {
+    pub id: String,
+    pub name: String,
+    pub status: RuleStatus,
+    #[serde(serialize_with = "crate::timestamps::unix_seconds::serialize_option")]
+    pub resume_at: Option<u64>,
+    /// only set for promo roles
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "crate::timestamps::unix_seconds::serialize_option"
+    )]
+    pub starts_at: Option<u64>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "crate::timestamps::unix_seconds::serialize_option"
+    )]
+    pub ends_at: Option<u64>,
+}
+
+/// request structure for replaying a payload against a user's historical state
+#[derive(Deserialize)]
+pub struct SimulationRequest {
+    pub data: UpdateUserData,
+    /// milliseconds since the unix epoch, the live row is used when this is omitted
+    pub as_of: Option<u64>,
+    /// defaults to the beta tester status of the state being simulated against
+    pub beta_tester: Option<bool>,
+}
+
+/// request structure for importing a user another instance exported
+#[derive(Deserialize)]
+pub struct PortableImportRequest {
+    pub blob: PortableUser,
+    /// imports the user under another discord id, e.g. when they moved Discord accounts
+    pub discord_id: Option<String>,
+}
+
+/// request structure for linking a secondary email and token to a user
+#[derive(Deserialize)]
+pub struct RecoveryCredentialRequest {
+    pub email: String,
+    pub token: String,
+}
+
+/// a user's privacy settings
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PrivacySettings {
+    pub public_link_visible: bool,
+    /// whether the progress tier prefix is put in front of the user's nickname
+    pub nickname_prefix: bool,
+}
+
+/// request structure for changing a user's privacy settings, left out settings aren't changed
+#[derive(Deserialize)]
+pub struct PrivacySettingsPatch {
+    pub public_link_visible: Option<bool>,
+    pub nickname_prefix: Option<bool>,
+}
+
+/// response structure for the public linked check, this must never contain anything else
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PublicLinkStatus {
+    pub linked: bool,
+}
+
+impl PublicLinkStatus {
+    /// opted-out users look exactly like users that aren't linked at all
+    pub fn from_visibility(public_link_visible: Option<bool>) -> Self {
+        PublicLinkStatus {
+            linked: public_link_visible == Some(true),
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct MessageResponse {
     pub message: String,
 }
 
+/// the body of a signed request that was only rejected for its timestamp, so clients can correct
+/// their clock
+#[derive(Serialize)]
+pub struct ClockSkewResponse {
+    pub message: String,
+    /// how far the client's clock is ahead of the server's, negative when it's behind
+    pub skew_seconds: i64,
+    /// the server's unix time in seconds
+    pub server_time: i64,
+}
+
+/// what every `MyError` is answered with, `message` is still there for clients that read it from
+/// before errors had codes
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    pub message: String,
+    pub error: ErrorBody,
+}
+
+#[derive(Serialize)]
+pub struct ErrorBody {
+    /// stable across releases, unlike the message
+    pub code: &'static str,
+    pub message: String,
+    /// what's wrong with the payload, field by field
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub issues: Vec<ValidationIssue>,
+}
+
+/// the roles a user gained in a single guild
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GuildRoles {
+    pub guild_id: String,
+    pub guild_name: String,
+    pub roles: Vec<String>,
+}
+
+/// why an earned role wasn't granted
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum WithheldReason {
+    /// the role was deleted from the guild
+    RoleMissing,
+}
+
+/// an earned role that wasn't granted
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WithheldRole {
+    pub id: String,
+    pub name: String,
+    pub reason: WithheldReason,
+}
+
+/// response structure for syncing progress, `roles` breaks the gained roles down per guild
+#[derive(Serialize)]
+pub struct UserResponse {
+    pub message: String,
+    pub roles: Vec<GuildRoles>,
+    /// the names of the gained roles, empty when the sync didn't gain any
+    pub gained_roles: Vec<String>,
+    pub discord_id: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub withheld: Vec<WithheldRole>,
+    /// the fields a partial sync didn't write
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<ValidationIssue>,
+    /// the role the user is closest to when the sync didn't gain any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closest_miss: Option<ClosestMiss>,
+    /// the names of the roles a `reconcile=true` sync took away
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub removed_roles: Vec<String>,
+    /// the roles are evaluated once the user stops syncing, see `ROLE_DEBOUNCE_SECONDS`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub roles_pending: bool,
+    /// where the sync's time went, only with `debug_timing=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<TimingsReport>,
+}
+
+/// Response structure for creating a user, with or without progress. The roles the new account
+/// was granted are listed like a sync lists them, `gained_roles` is empty when it didn't get any.
+#[derive(Serialize)]
+pub struct CreatedUserResponse {
+    pub user: UserData,
+    pub message: String,
+    pub roles: Vec<GuildRoles>,
+    pub gained_roles: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub withheld: Vec<WithheldRole>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closest_miss: Option<ClosestMiss>,
+}
+
+/// response structure for creating a user, echoes the discord id the account was actually bound to
+#[derive(Serialize)]
+pub struct BoundUserResponse<T> {
+    #[serde(flatten)]
+    pub response: T,
+    pub bound_discord_id: String,
+    /// `<@{discord_id}>`, how Discord renders the bound user
+    pub discord_mention: String,
+}
+
+impl<T> BoundUserResponse<T> {
+    pub fn new(response: T, discord_id: &str) -> Self {
+        BoundUserResponse {
+            response,
+            bound_discord_id: discord_id.to_owned(),
+            discord_mention: discord_mention(discord_id),
+        }
+    }
+}
+
+pub fn discord_mention(discord_id: &str) -> String {
+    format!("<@{}>", discord_id)
+}
+
+/// response structure for the og endpoint, `warnings` lists the fields that were lost in conversion
+#[derive(Serialize)]
+pub struct OGMessageResponse {
+    pub message: String,
+    pub roles: Vec<GuildRoles>,
+    pub gained_roles: Vec<String>,
+    pub discord_id: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub withheld: Vec<WithheldRole>,
+    /// the fields a partial sync didn't write
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<ValidationIssue>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closest_miss: Option<ClosestMiss>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub removed_roles: Vec<String>,
+}
+
+/// `OGMessageResponse` for clients that accept the binary format, bincode has no field names to
+/// tell a left out field by, so every field is always there
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct OGBinaryResponse {
+    pub message: String,
+    pub roles: Vec<GuildRoles>,
+    #[serde(default)]
+    pub withheld: Vec<WithheldRole>,
+    #[serde(default)]
+    pub skipped: Vec<ValidationIssue>,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    #[serde(default)]
+    pub closest_miss: Option<ClosestMiss>,
+    #[serde(default)]
+    pub gained_roles: Vec<String>,
+    #[serde(default)]
+    pub discord_id: String,
+    #[serde(default)]
+    pub removed_roles: Vec<String>,
+}
+
 /// response structure for game saves metadata
 #[derive(Deserialize, Debug)]
 pub struct GameSavesMetadataResponse {
@@ -108,3 +812,182 @@ pub struct GameSavesMetadataPostRequest {
     /// user access token
     pub token: String,
 }
+
+#[test]
+fn opted_out_users_look_unlinked() {
+    assert_eq!(
+        PublicLinkStatus::from_visibility(Some(true)),
+        PublicLinkStatus { linked: true }
+    );
+    assert_eq!(
+        PublicLinkStatus::from_visibility(Some(false)),
+        PublicLinkStatus { linked: false }
+    );
+    assert_eq!(
+        PublicLinkStatus::from_visibility(None),
+        PublicLinkStatus { linked: false }
+    );
+}
+
+#[test]
+fn created_users_echo_the_bound_discord_id() {
+    let response = BoundUserResponse::new(
+        CreatedUserResponse {
+            user: UserData {
+                discord_id: "10".to_owned(),
+                ..UserData::default()
+            },
+            message: "The request was successful".to_owned(),
+            roles: Vec::new(),
+            gained_roles: Vec::new(),
+            withheld: Vec::new(),
+            closest_miss: None,
+        },
+        "10",
+    );
+
+    let response = serde_json::to_value(response).unwrap();
+    assert_eq!(response["bound_discord_id"], "10");
+    assert_eq!(response["discord_mention"], "<@10>");
+}
+
+#[cfg(test)]
+fn created_response_keys(response: CreatedUserResponse) -> (Vec<String>, Value) {
+    let value = serde_json::to_value(BoundUserResponse::new(response, "10")).unwrap();
+    let mut keys = value
+        .as_object()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect::<Vec<String>>();
+    keys.sort();
+
+    (keys, value)
+}
+
+#[test]
+fn creates_without_progress_have_the_same_shape() {
+    let (keys, value) = created_response_keys(CreatedUserResponse {
+        user: UserData {
+            discord_id: "10".to_owned(),
+            ..UserData::default()
+        },
+        message: "The request was successful, but you've already gained all of the possible roles with your current progress".to_owned(),
+        roles: Vec::new(),
+        gained_roles: Vec::new(),
+        withheld: Vec::new(),
+        closest_miss: None,
+    });
+
+    assert_eq!(
+        keys,
+        [
+            "bound_discord_id",
+            "discord_mention",
+            "gained_roles",
+            "message",
+            "roles",
+            "user"
+        ]
+    );
+    assert_eq!(value["gained_roles"], serde_json::json!([]));
+    assert_eq!(value["user"]["discord_id"], "10");
+    assert_eq!(value["user"]["metabits"], "0");
+}
+
+#[test]
+fn creates_with_progress_list_the_gained_roles() {
+    let (keys, value) = created_response_keys(CreatedUserResponse {
+        user: UserData {
+            discord_id: "10".to_owned(),
+            metabits: 2_000_000,
+            ..UserData::default()
+        },
+        message:
+            "The request was successful, you've gained the following roles: C2S: Reality Explorer"
+                .to_owned(),
+        roles: vec![GuildRoles {
+            guild_id: "1".to_owned(),
+            guild_name: "C2S".to_owned(),
+            roles: vec!["Reality Explorer".to_owned()],
+        }],
+        gained_roles: vec!["Reality Explorer".to_owned()],
+        withheld: Vec::new(),
+        closest_miss: None,
+    });
+
+    assert_eq!(
+        keys,
+        [
+            "bound_discord_id",
+            "discord_mention",
+            "gained_roles",
+            "message",
+            "roles",
+            "user"
+        ]
+    );
+    assert_eq!(
+        value["gained_roles"],
+        serde_json::json!(["Reality Explorer"])
+    );
+    assert_eq!(
+        value["roles"],
+        serde_json::json!([{ "guild_id": "1", "guild_name": "C2S", "roles": ["Reality Explorer"] }])
+    );
+    assert_eq!(value["user"]["metabits"], "2000000");
+}
+
+#[test]
+fn single_field_patches_keep_the_other_values() {
+    let current = UserData {
+        metabits: 10,
+        dino_rank: 5,
+        singularity_speedrun_time: Some(300.0),
+        all_sharks_obtained: true,
+        ..UserData::default()
+    };
+    let patch = serde_json::from_str::<PatchUserData>("{\"dino_rank\": 7}").unwrap();
+
+    let payload = patch.apply_to(&current);
+    assert_eq!((payload.metabits, payload.dino_rank), (10.0, 7));
+    assert_eq!(payload.singularity_speedrun_time, Some(300.0));
+    assert!(payload.all_sharks_obtained);
+    // only the sent field is written
+    assert_eq!(
+        patch.sent_values(&payload),
+        PatchUserData {
+            dino_rank: Some(7),
+            ..PatchUserData::default()
+        }
+    );
+}
+
+#[test]
+fn patches_tell_null_apart_from_left_out_fields() {
+    let read = |json: &str| serde_json::from_str::<PatchUserData>(json).unwrap();
+
+    assert_eq!(
+        read("{\"singularity_speedrun_time\": null}").singularity_speedrun_time,
+        Some(None)
+    );
+    assert_eq!(read("{\"metabits\": \"12\"}").metabits, Some(12.0));
+    assert!(read("{}").is_empty());
+    // unknown fields are ignored like in full payloads, so they don't make a patch
+    assert!(read("{\"metabit\": 12}").is_empty());
+    assert!(!read("{\"all_sharks_obtained\": false}").is_empty());
+}
+
+#[test]
+fn invalid_progress_names_the_field() {
+    let issues = UpdateUserData {
+        prestige_rank: -5,
+        ..UpdateUserData::default()
+    }
+    .validate()
+    .unwrap_err();
+
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].field_path, "prestige_rank");
+    assert!(UpdateUserData::default().validate().is_ok());
+}
@@ -0,0 +1,136 @@
+use serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::ttl_map::TtlMap;
+
+/// how long a token that isn't linked is answered without asking the database
+pub const NEGATIVE_CACHE_TTL: Duration = Duration::from_secs(60);
+/// guessing traffic can't grow the cache past this, tokens that don't fit are simply looked up
+pub const MAX_NEGATIVE_ENTRIES: usize = 100_000;
+
+static NEGATIVE_CACHE: OnceLock<NegativeCache> = OnceLock::new();
+
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct NegativeCacheStats {
+    /// requests that were answered as not linked without a database query
+    pub hits: u64,
+    pub entries: usize,
+}
+
+/// Tokens that recently turned out not to be linked to anyone, so that guessing tokens on the
+/// update endpoints doesn't cost a database query per guess.
+pub struct NegativeCache {
+    tokens: Mutex<TtlMap<String, ()>>,
+    max_entries: usize,
+    hits: AtomicU64,
+}
+
+impl NegativeCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        NegativeCache {
+            tokens: Mutex::new(TtlMap::new(ttl)),
+            max_entries,
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// whether the token is known not to be linked, every hit is counted
+    pub fn is_unknown(&self, token: &str, now: Instant) -> bool {
+        let unknown = self
+            .tokens
+            .lock()
+            .unwrap()
+            .contains_key(&token.to_owned(), now);
+        if unknown {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+
+        unknown
+    }
+
+    pub fn remember(&self, token: &str, now: Instant) {
+        let mut tokens = self.tokens.lock().unwrap();
+        if tokens.len() >= self.max_entries {
+            tokens.purge_expired(now);
+            if tokens.len() >= self.max_entries {
+                return;
+            }
+        }
+        tokens.insert(token.to_owned(), (), now);
+    }
+
+    /// this has to happen whenever a token gets linked, otherwise the new link is hidden until
+    /// the entry expires
+    pub fn forget(&self, token: &str) {
+        self.tokens.lock().unwrap().remove(&token.to_owned());
+    }
+
+    /// the most recently remembered tokens and how much of their lifetime is left, for keeping them
+    /// over a restart
+    pub fn hot_entries(&self, now: Instant, limit: usize) -> Vec<(String, Duration)> {
+        let tokens = self.tokens.lock().unwrap();
+        let mut entries = tokens
+            .live_entries(now)
+            .into_iter()
+            .map(|(token, _, remaining)| (token.clone(), remaining))
+            .collect::<Vec<_>>();
+        entries.sort_by(|(_, a), (_, b)| b.cmp(a));
+        entries.truncate(limit);
+
+        entries
+    }
+
+    /// puts back a token from before a restart, a full cache leaves it out
+    pub fn restore(&self, token: &str, remaining: Duration, now: Instant) {
+        let mut tokens = self.tokens.lock().unwrap();
+        if tokens.len() < self.max_entries {
+            tokens.insert_remaining(token.to_owned(), (), remaining, now);
+        }
+    }
+
+    pub fn stats(&self) -> NegativeCacheStats {
+        NegativeCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            entries: self.tokens.lock().unwrap().len(),
+        }
+    }
+}
+
+pub fn negative_cache() -> &'static NegativeCache {
+    NEGATIVE_CACHE.get_or_init(|| NegativeCache::new(NEGATIVE_CACHE_TTL, MAX_NEGATIVE_ENTRIES))
+}
+
+#[test]
+fn linking_a_token_clears_its_negative_entry() {
+    let cache = NegativeCache::new(NEGATIVE_CACHE_TTL, 10);
+    let now = Instant::now();
+
+    cache.remember("token", now);
+    assert!(cache.is_unknown("token", now));
+
+    cache.forget("token");
+
+    assert!(!cache.is_unknown("token", now));
+    assert_eq!(cache.stats().hits, 1);
+}
+
+#[test]
+fn negative_entries_expire() {
+    let cache = NegativeCache::new(NEGATIVE_CACHE_TTL, 1);
+    let now = Instant::now();
+
+    cache.remember("token", now);
+    assert!(cache.is_unknown("token", now + NEGATIVE_CACHE_TTL - Duration::from_secs(1)));
+    assert!(!cache.is_unknown("token", now + NEGATIVE_CACHE_TTL));
+
+    // a full cache makes room by dropping the expired entries
+    cache.remember("other-token", now + NEGATIVE_CACHE_TTL);
+    assert!(cache.is_unknown("other-token", now + NEGATIVE_CACHE_TTL));
+    assert_eq!(cache.stats().entries, 1);
+}
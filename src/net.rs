@@ -0,0 +1,184 @@
+use actix_web::HttpRequest;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+/// an IPv4 or IPv6 address range like `10.0.0.0/8` or `2001:db8::/32`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, normalize(ip)) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                mask(u32::from(ip) as u128, self.prefix_len, 32)
+                    == mask(u32::from(network) as u128, self.prefix_len, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                mask(u128::from(ip), self.prefix_len, 128)
+                    == mask(u128::from(network), self.prefix_len, 128)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    /// a bare address is a range of just that address
+    fn from_str(cidr: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match cidr.trim().split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (cidr.trim(), None),
+        };
+        let network = normalize(
+            address
+                .parse::<IpAddr>()
+                .map_err(|_| format!("'{}' isn't a valid address", address))?,
+        );
+        let max_len = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix_len| *prefix_len <= max_len)
+                .ok_or_else(|| format!("'{}' isn't a valid prefix length", prefix_len))?,
+            None => max_len,
+        };
+
+        Ok(Cidr {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+/// parses a comma separated list of ranges, an empty list is valid
+pub fn parse_cidrs(cidrs: &str) -> Result<Vec<Cidr>, String> {
+    cidrs
+        .split(',')
+        .filter(|cidr| !cidr.trim().is_empty())
+        .map(Cidr::from_str)
+        .collect()
+}
+
+pub fn in_any(cidrs: &[Cidr], ip: IpAddr) -> bool {
+    cidrs.iter().any(|cidr| cidr.contains(ip))
+}
+
+/// IPv4-mapped IPv6 addresses (`::ffff:10.0.0.1`) are treated as the IPv4 address they carry
+fn normalize(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(IpAddr::V6(v6), IpAddr::V4),
+        ip => ip,
+    }
+}
+
+fn mask(bits: u128, prefix_len: u8, len: u8) -> u128 {
+    if prefix_len == 0 {
+        return 0;
+    }
+    bits >> (len - prefix_len)
+}
+
+/// Finds the address of the client behind a request. `X-Forwarded-For` is only believed when the
+/// peer is one of the trusted proxies, and then the rightmost address that isn't a trusted proxy
+/// is the client, everything left of it could have been made up by the client.
+pub fn client_ip(
+    peer: Option<IpAddr>,
+    forwarded_for: Option<&str>,
+    trusted_proxies: &[Cidr],
+) -> Option<IpAddr> {
+    let peer = normalize(peer?);
+    if !in_any(trusted_proxies, peer) {
+        return Some(peer);
+    }
+
+    let mut client = peer;
+    for hop in forwarded_for.unwrap_or_default().rsplit(',') {
+        match hop.trim().parse::<IpAddr>() {
+            Ok(hop) => {
+                client = normalize(hop);
+                if !in_any(trusted_proxies, client) {
+                    break;
+                }
+            }
+            // a malformed entry ends the chain that can be trusted
+            Err(_) => break,
+        }
+    }
+
+    Some(client)
+}
+
+pub fn request_client_ip(req: &HttpRequest, trusted_proxies: &[Cidr]) -> Option<IpAddr> {
+    client_ip(
+        req.peer_addr().map(|addr| addr.ip()),
+        req.headers()
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok()),
+        trusted_proxies,
+    )
+}
+
+/// the /24 an IPv4 address is in, IPv6 addresses are grouped by their /48
+pub fn subnet_of(ip: IpAddr) -> String {
+    match normalize(ip) {
+        IpAddr::V4(ip) => format!("{}/24", Ipv4Addr::from(u32::from(ip) & 0xffff_ff00)),
+        IpAddr::V6(ip) => format!("{}/48", Ipv6Addr::from(u128::from(ip) & (!0u128 << 80))),
+    }
+}
+
+#[test]
+fn cidrs_match_ipv4_and_ipv6() {
+    let ranges = parse_cidrs("10.0.0.0/8, 192.168.1.5, 2001:db8::/32").unwrap();
+    let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+
+    assert!(in_any(&ranges, ip("10.255.0.1")));
+    assert!(in_any(&ranges, ip("::ffff:10.0.0.1")));
+    assert!(in_any(&ranges, ip("192.168.1.5")));
+    assert!(!in_any(&ranges, ip("192.168.1.6")));
+    assert!(in_any(&ranges, ip("2001:db8:1::1")));
+    assert!(!in_any(&ranges, ip("2001:db9::1")));
+    assert!(!in_any(&ranges, ip("11.0.0.1")));
+
+    assert!(Cidr::from_str("0.0.0.0/0").unwrap().contains(ip("8.8.8.8")));
+    assert!(!Cidr::from_str("0.0.0.0/0").unwrap().contains(ip("::1")));
+    assert!(parse_cidrs("").unwrap().is_empty());
+    assert!(parse_cidrs("10.0.0.0/33").is_err());
+    assert!(parse_cidrs("not-an-ip/8").is_err());
+}
+
+#[test]
+fn forwarded_for_is_only_trusted_from_proxies() {
+    let proxies = parse_cidrs("10.0.0.0/8").unwrap();
+    let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+
+    // a client can't pick its own address by sending the header directly
+    assert_eq!(
+        client_ip(ip("203.0.113.7"), Some("198.51.100.1"), &proxies),
+        ip("203.0.113.7")
+    );
+    // the rightmost address that isn't a proxy is the client
+    assert_eq!(
+        client_ip(
+            ip("10.0.0.2"),
+            Some("198.51.100.1, 203.0.113.7, 10.0.0.3"),
+            &proxies
+        ),
+        ip("203.0.113.7")
+    );
+    assert_eq!(client_ip(ip("10.0.0.2"), None, &proxies), ip("10.0.0.2"));
+    assert_eq!(client_ip(None, Some("203.0.113.7"), &proxies), None);
+
+    assert_eq!(subnet_of(ip("203.0.113.7").unwrap()), "203.0.113.0/24");
+    assert_eq!(subnet_of(ip("2001:db8:1:2::1").unwrap()), "2001:db8:1::/48");
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+/// the longest nickname Discord accepts, in characters
+pub const NICKNAME_LENGTH: usize = 32;
+
+/// a prefix members get while they hold the tier's role
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NicknameTier {
+    pub role_id: u64,
+    pub prefix: String,
+}
+
+/// a guild's progress tier nicknames, the tiers are listed from the lowest to the highest
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NicknamePolicy {
+    pub enabled: bool,
+    pub tiers: Vec<NicknameTier>,
+}
+
+impl NicknamePolicy {
+    /// the highest tier whose role the member holds
+    pub fn member_tier(&self, held_roles: &[u64]) -> Option<&NicknameTier> {
+        self.tiers
+            .iter()
+            .rev()
+            .find(|tier| held_roles.contains(&tier.role_id))
+    }
+}
+
+/// how a member's nickname is handled on a sync
+pub struct MemberNickname<'a> {
+    pub policy: &'a NicknamePolicy,
+    /// false once the member opted out, a prefix they still have is taken away again
+    pub prefixed: bool,
+}
+
+/// Parses `{guild_id}:{role_id}:{prefix}` entries separated by commas, a guild's tiers keep the
+/// order they're given in. Only the guilds in `enabled_guilds` (`{guild_id},{guild_id}`) get
+/// prefixes, malformed entries are skipped.
+pub fn parse_nickname_policies(tiers: &str, enabled_guilds: &str) -> HashMap<u64, NicknamePolicy> {
+    let mut policies: HashMap<u64, NicknamePolicy> = HashMap::new();
+    let parsed_tiers = tiers.split(',').filter_map(|entry| {
+        let mut parts = entry.splitn(3, ':');
+        let guild_id = parts.next()?.trim().parse::<u64>().ok()?;
+        let role_id = parts.next()?.trim().parse::<u64>().ok()?;
+        let prefix = parts.next()?.trim().to_owned();
+        if prefix.is_empty() {
+            return None;
+        }
+        Some((guild_id, NicknameTier { role_id, prefix }))
+    });
+    for (guild_id, tier) in parsed_tiers {
+        policies.entry(guild_id).or_default().tiers.push(tier);
+    }
+
+    for guild_id in enabled_guilds
+        .split(',')
+        .filter_map(|guild_id| guild_id.trim().parse::<u64>().ok())
+    {
+        if let Some(policy) = policies.get_mut(&guild_id) {
+            policy.enabled = true;
+        }
+    }
+
+    policies
+}
+
+/// the name the member picked themselves, without any of the tier prefixes in front of it
+pub fn base_name<'a>(nick: Option<&'a str>, username: &'a str, tiers: &[NicknameTier]) -> &'a str {
+    let name = nick.unwrap_or(username);
+    tiers
+        .iter()
+        .find_map(|tier| name.strip_prefix(&format!("{} ", tier.prefix)))
+        .filter(|base| !base.trim().is_empty())
+        .unwrap_or(name)
+}
+
+/// Cuts `name` down to `max_len` characters. A joiner or space left dangling at the end is dropped
+/// too, so emoji sequences aren't cut into a joiner followed by nothing.
+fn truncate_name(name: &str, max_len: usize) -> String {
+    let mut truncated = name.chars().take(max_len).collect::<String>();
+    while truncated.ends_with(|c| c == '\u{200d}' || c == ' ') {
+        truncated.pop();
+    }
+
+    truncated
+}
+
+/// The nickname the member should have in `tier`, their base name is kept behind the prefix and
+/// cut short when both don't fit. `None` clears the nickname, which shows the username again.
+pub fn tiered_nickname(
+    nick: Option<&str>,
+    username: &str,
+    tiers: &[NicknameTier],
+    tier: Option<&NicknameTier>,
+) -> Option<String> {
+    let base = base_name(nick, username, tiers);
+    match tier {
+        Some(tier) => {
+            let room = NICKNAME_LENGTH.saturating_sub(tier.prefix.chars().count() + 1);
+            Some(format!("{} {}", tier.prefix, truncate_name(base, room)))
+        }
+        None if base == username => None,
+        None => Some(base.to_owned()),
+    }
+}
+
+#[cfg(test)]
+fn test_tiers() -> Vec<NicknameTier> {
+    vec![
+        NicknameTier {
+            role_id: 1,
+            prefix: "[Explorer]".to_owned(),
+        },
+        NicknameTier {
+            role_id: 2,
+            prefix: "🦖".to_owned(),
+        },
+    ]
+}
+
+#[test]
+fn policies_are_parsed_per_guild() {
+    let policies = parse_nickname_policies("10:1:[Explorer], 10:2:🦖,20:3:A:B,bad,30:4:", "10");
+
+    assert_eq!(
+        policies.get(&10),
+        Some(&NicknamePolicy {
+            enabled: true,
+            tiers: test_tiers(),
+        })
+    );
+    assert_eq!(
+        policies.get(&20),
+        Some(&NicknamePolicy {
+            enabled: false,
+            tiers: vec![NicknameTier {
+                role_id: 3,
+                prefix: "A:B".to_owned(),
+            }],
+        })
+    );
+    assert_eq!(policies.get(&30), None);
+}
+
+#[test]
+fn the_highest_held_tier_is_used() {
+    let policy = NicknamePolicy {
+        enabled: true,
+        tiers: test_tiers(),
+    };
+
+    assert_eq!(policy.member_tier(&[2, 1]).unwrap().role_id, 2);
+    assert_eq!(policy.member_tier(&[1, 5]).unwrap().role_id, 1);
+    assert_eq!(policy.member_tier(&[5]), None);
+}
+
+#[test]
+fn tier_changes_keep_the_base_name() {
+    let tiers = test_tiers();
+
+    let explorer = tiered_nickname(None, "sirh", &tiers, Some(&tiers[0]));
+    assert_eq!(explorer.as_deref(), Some("[Explorer] sirh"));
+
+    let promoted = tiered_nickname(explorer.as_deref(), "sirh", &tiers, Some(&tiers[1]));
+    assert_eq!(promoted.as_deref(), Some("🦖 sirh"));
+
+    // the member's own nickname comes back once they're out of every tier
+    let custom = tiered_nickname(Some("🦖 Hunter"), "sirh", &tiers, None);
+    assert_eq!(custom.as_deref(), Some("Hunter"));
+    assert_eq!(
+        tiered_nickname(promoted.as_deref(), "sirh", &tiers, None),
+        None
+    );
+}
+
+#[test]
+fn long_names_are_cut_to_fit_behind_the_prefix() {
+    let tiers = test_tiers();
+    let nickname = tiered_nickname(Some(&"a".repeat(40)), "sirh", &tiers, Some(&tiers[0])).unwrap();
+
+    assert_eq!(nickname.chars().count(), NICKNAME_LENGTH);
+    assert_eq!(nickname, format!("[Explorer] {}", "a".repeat(21)));
+}
+
+#[test]
+fn emoji_are_counted_as_characters_and_not_left_dangling() {
+    let tiers = test_tiers();
+    let nickname = tiered_nickname(Some(&"🦈".repeat(40)), "sirh", &tiers, Some(&tiers[1]));
+    assert_eq!(nickname, Some(format!("🦖 {}", "🦈".repeat(30))));
+
+    // a cut right after a zero width joiner drops the joiner instead of keeping half a sequence
+    let family = format!("{}👨\u{200d}👩", "a".repeat(28));
+    let nickname = tiered_nickname(Some(&family), "sirh", &tiers, Some(&tiers[1])).unwrap();
+    assert_eq!(nickname, format!("🦖 {}👨", "a".repeat(28)));
+}
@@ -0,0 +1,85 @@
+use actix_web::rt::{self, time};
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use crate::{
+    constants::LOG,
+    net::{in_any, subnet_of, Cidr},
+    webhook_logging::webhook_log,
+};
+
+const REJECT_REPORT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+static OG_REJECTS: OnceLock<Mutex<BTreeMap<String, u64>>> = OnceLock::new();
+
+/// whether the og endpoint accepts a request from `client_ip`, an empty allowlist accepts everyone
+pub fn og_request_allowed(allowed_cidrs: &[Cidr], client_ip: Option<IpAddr>) -> bool {
+    allowed_cidrs.is_empty() || client_ip.map_or(false, |ip| in_any(allowed_cidrs, ip))
+}
+
+/// the rejected og requests of the current day, counted per subnet
+pub fn og_rejects() -> &'static Mutex<BTreeMap<String, u64>> {
+    OG_REJECTS.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+pub fn record_og_reject(rejects: &Mutex<BTreeMap<String, u64>>, client_ip: Option<IpAddr>) {
+    let subnet = client_ip.map_or_else(|| "unknown".to_owned(), subnet_of);
+    *rejects.lock().unwrap().entry(subnet).or_insert(0) += 1;
+}
+
+/// `None` when nothing was rejected
+pub fn summarize_og_rejects(rejects: &BTreeMap<String, u64>) -> Option<String> {
+    if rejects.is_empty() {
+        return None;
+    }
+
+    Some(format!(
+        "og requests rejected by the allowlist in the last day:\n{}",
+        rejects
+            .iter()
+            .map(|(subnet, count)| format!("{}: {}", subnet, count))
+            .collect::<Vec<String>>()
+            .join("\n")
+    ))
+}
+
+/// sends the daily count of rejected og requests to the webhook
+pub fn spawn_reject_report_scheduler() {
+    rt::spawn(async move {
+        let mut interval = time::interval(REJECT_REPORT_INTERVAL);
+        // the first tick completes right away
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let rejects = std::mem::take(&mut *og_rejects().lock().unwrap());
+            if let Some(summary) = summarize_og_rejects(&rejects) {
+                webhook_log(summary, LOG::INFORMATIONAL).await;
+            }
+        }
+    });
+}
+
+#[test]
+fn only_allowlisted_ranges_reach_the_og_endpoint() {
+    let allowed = crate::net::parse_cidrs("203.0.113.0/24").unwrap();
+    let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+
+    assert!(og_request_allowed(&[], ip("198.51.100.1")));
+    assert!(og_request_allowed(&[], None));
+    assert!(og_request_allowed(&allowed, ip("203.0.113.9")));
+    assert!(!og_request_allowed(&allowed, ip("198.51.100.1")));
+    assert!(!og_request_allowed(&allowed, None));
+
+    let rejects = Mutex::new(BTreeMap::new());
+    record_og_reject(&rejects, ip("198.51.100.1"));
+    record_og_reject(&rejects, ip("198.51.100.2"));
+    record_og_reject(&rejects, None);
+    assert_eq!(
+        summarize_og_rejects(&rejects.lock().unwrap()).unwrap(),
+        "og requests rejected by the allowlist in the last day:\n198.51.100.0/24: 2\nunknown: 1"
+    );
+}
@@ -0,0 +1,61 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+/// the content type of the compact OG bodies, requests send it and responses are sent in it when
+/// the request's Accept has it
+pub const OG_BINARY_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// the bytes of the little-endian length in front of a frame's bincode
+const LENGTH_PREFIX: usize = 4;
+
+/// Why a binary body couldn't be read. This file only depends on serde and bincode, so clients can
+/// take it as it is, `examples/og_binary_payload.rs` does.
+#[derive(Debug)]
+pub enum FrameError {
+    /// the body is too short to have the length in front
+    MissingLength,
+    /// the length in front doesn't match the bytes that follow it
+    LengthMismatch {
+        declared: usize,
+        actual: usize,
+    },
+    Decode(bincode::Error),
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::MissingLength => write!(f, "the body is missing its length"),
+            FrameError::LengthMismatch { declared, actual } => {
+                write!(f, "the body declares {} bytes but has {}", declared, actual)
+            }
+            FrameError::Decode(error) => write!(f, "the body couldn't be decoded: {}", error),
+        }
+    }
+}
+
+/// `value` as bincode, with its length as a little-endian u32 in front
+pub fn encode_frame<T: Serialize>(value: &T) -> Result<Vec<u8>, bincode::Error> {
+    let encoded = bincode::serialize(value)?;
+    let mut frame = Vec::with_capacity(LENGTH_PREFIX + encoded.len());
+    frame.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&encoded);
+
+    Ok(frame)
+}
+
+/// reads a frame `encode_frame` wrote, bytes past the declared length are refused like missing ones
+pub fn decode_frame<T: DeserializeOwned>(frame: &[u8]) -> Result<T, FrameError> {
+    if frame.len() < LENGTH_PREFIX {
+        return Err(FrameError::MissingLength);
+    }
+    let (length, encoded) = frame.split_at(LENGTH_PREFIX);
+    let declared = u32::from_le_bytes([length[0], length[1], length[2], length[3]]) as usize;
+    if declared != encoded.len() {
+        return Err(FrameError::LengthMismatch {
+            declared,
+            actual: encoded.len(),
+        });
+    }
+
+    bincode::deserialize(encoded).map_err(FrameError::Decode)
+}
@@ -0,0 +1,319 @@
+use actix_web::rt::{self, time};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use crate::{
+    constants::LOG,
+    errors::{ConvertResultErrorToMyError, MyError},
+    models::{OGUpdateUserData, UpdateUserData},
+    og_binary::decode_frame,
+    webhook_logging::webhook_log,
+};
+
+const DROP_REPORT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+static DROPPED_FIELDS: OnceLock<Mutex<BTreeMap<String, u64>>> = OnceLock::new();
+
+/// what the conversion to `UpdateUserData` does with a field of the OG payload
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldMapping {
+    /// copied into the progress field of the same name
+    Mapped,
+    /// read by the og endpoint itself instead of being stored as progress
+    Consumed,
+}
+
+/// Every field of `OGUpdateUserData` with the decision of what happens to it, the tests fail when
+/// a field is added to the struct without being listed here.
+pub const OG_FIELD_MAPPINGS: [(&str, FieldMapping); 9] = [
+    ("playerToken", FieldMapping::Consumed),
+    ("betaTester", FieldMapping::Consumed),
+    ("metabits", FieldMapping::Mapped),
+    ("dino_rank", FieldMapping::Mapped),
+    ("prestige_rank", FieldMapping::Mapped),
+    ("beyond_rank", FieldMapping::Mapped),
+    ("singularity_speedrun_time", FieldMapping::Mapped),
+    ("all_sharks_obtained", FieldMapping::Mapped),
+    ("all_hidden_achievements_obtained", FieldMapping::Mapped),
+];
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ConversionReport {
+    /// fields the client sent that nothing reads
+    pub dropped: Vec<String>,
+    /// optional fields the client left out, these are stored as their default
+    pub defaulted: Vec<String>,
+}
+
+impl ConversionReport {
+    pub fn is_empty(&self) -> bool {
+        self.dropped.is_empty() && self.defaulted.is_empty()
+    }
+
+    /// the warnings handed back to the launcher
+    pub fn warnings(&self) -> Vec<String> {
+        self.dropped
+            .iter()
+            .map(|field| format!("{} is not a known field and was discarded", field))
+            .chain(
+                self.defaulted
+                    .iter()
+                    .map(|field| format!("{} was missing and was stored as empty", field)),
+            )
+            .collect()
+    }
+}
+
+/// Deserializes an OG payload while keeping track of which of the fields the client sent didn't
+/// make it into the conversion.
+pub fn parse_og_payload(
+    payload: Value,
+) -> Result<(OGUpdateUserData, UpdateUserData, ConversionReport), serde_json::Error> {
+    let mut report = ConversionReport::default();
+    if let Value::Object(fields) = &payload {
+        report.dropped = fields
+            .keys()
+            .filter(|key| !OG_FIELD_MAPPINGS.iter().any(|(name, _)| name == key))
+            .cloned()
+            .collect();
+        report.defaulted = OG_FIELD_MAPPINGS
+            .iter()
+            .filter(|(name, mapping)| {
+                *mapping == FieldMapping::Mapped && !fields.contains_key(*name)
+            })
+            .map(|(name, _)| name.to_string())
+            .collect();
+    }
+
+    let og_data: OGUpdateUserData = serde_json::from_value(payload)?;
+    let update_data = UpdateUserData::from(og_data.clone());
+
+    Ok((og_data, update_data, report))
+}
+
+/// Reads a binary OG body into the JSON payload it stands for, so it's converted like a JSON body.
+/// Binary bodies always have every field, so none of them are reported as missing.
+pub fn og_binary_payload(frame: &[u8]) -> Result<Value, MyError> {
+    let og_data = decode_frame::<OGUpdateUserData>(frame).make_response(
+        MyError::BadRequest("the binary payload doesn't match the userdata definition")
+            .with_code("INVALID_BINARY_PAYLOAD"),
+    )?;
+
+    serde_json::to_value(og_data).make_response(
+        MyError::BadRequest("the binary payload doesn't match the userdata definition")
+            .with_code("INVALID_BINARY_PAYLOAD"),
+    )
+}
+
+pub fn dropped_fields() -> &'static Mutex<BTreeMap<String, u64>> {
+    DROPPED_FIELDS.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+pub fn record_conversion_report(report: &ConversionReport) {
+    let mut dropped_fields = dropped_fields().lock().unwrap();
+    for field in &report.dropped {
+        *dropped_fields
+            .entry(format!("{} (discarded)", field))
+            .or_insert(0) += 1;
+    }
+    for field in &report.defaulted {
+        *dropped_fields
+            .entry(format!("{} (defaulted)", field))
+            .or_insert(0) += 1;
+    }
+}
+
+/// `None` when nothing was dropped
+pub fn summarize_dropped_fields(dropped_fields: &BTreeMap<String, u64>) -> Option<String> {
+    if dropped_fields.is_empty() {
+        return None;
+    }
+
+    Some(format!(
+        "og payload fields lost during conversion in the last day:\n{}",
+        dropped_fields
+            .iter()
+            .map(|(field, count)| format!("{}: {}", field, count))
+            .collect::<Vec<String>>()
+            .join("\n")
+    ))
+}
+
+/// sends the daily count of dropped og fields to the webhook
+pub fn spawn_drop_report_scheduler() {
+    rt::spawn(async move {
+        let mut interval = time::interval(DROP_REPORT_INTERVAL);
+        // the first tick completes right away
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let dropped = std::mem::take(&mut *dropped_fields().lock().unwrap());
+            if let Some(summary) = summarize_dropped_fields(&dropped) {
+                webhook_log(summary, LOG::INFORMATIONAL).await;
+            }
+        }
+    });
+}
+
+#[test]
+fn every_og_field_has_a_mapping_decision() {
+    // constructing the struct literal stops compiling when a field is added
+    let sentinel = OGUpdateUserData {
+        player_token: "sentinel".to_owned(),
+        beta_tester: Some(true),
+        metabits: 101.0,
+        dino_rank: 102,
+        prestige_rank: 103,
+        beyond_rank: 104,
+        singularity_speedrun_time: Some(105.0),
+        all_sharks_obtained: true,
+        all_hidden_achievements_obtained: true,
+    };
+    let serialized = serde_json::to_value(&sentinel).unwrap();
+    let sent_fields = serialized.as_object().unwrap();
+
+    for key in sent_fields.keys() {
+        assert!(
+            OG_FIELD_MAPPINGS.iter().any(|(name, _)| name == key),
+            "{} has no mapping decision",
+            key
+        );
+    }
+    assert_eq!(sent_fields.len(), OG_FIELD_MAPPINGS.len());
+
+    let (_, converted, report) = parse_og_payload(serialized.clone()).unwrap();
+    assert!(report.is_empty());
+
+    for (name, mapping) in OG_FIELD_MAPPINGS {
+        let progress_field = crate::fields::ProgressField::from_name(name);
+        match mapping {
+            FieldMapping::Mapped => {
+                let progress_field = progress_field.unwrap();
+                let sent = match &serialized[name] {
+                    Value::Bool(flag) => *flag as u8 as f64,
+                    value => value.as_f64().unwrap(),
+                };
+                assert_eq!(
+                    progress_field.read_update(&converted),
+                    Some(sent),
+                    "{}",
+                    name
+                );
+            }
+            FieldMapping::Consumed => assert_eq!(progress_field, None, "{}", name),
+        }
+    }
+}
+
+#[test]
+fn unknown_and_missing_fields_are_reported() {
+    let payload = serde_json::json!({
+        "playerToken": "token",
+        "betaTester": false,
+        "metabits": 10.0,
+        "dino_rank": 1,
+        "prestige_rank": 2,
+        "beyond_rank": 0,
+        "all_sharks_obtained": false,
+        "all_hidden_achievements_obtained": false,
+        "reality_rank": 7,
+    });
+
+    let (_, converted, report) = parse_og_payload(payload).unwrap();
+
+    assert_eq!(converted.singularity_speedrun_time, None);
+    assert_eq!(
+        report,
+        ConversionReport {
+            dropped: vec!["reality_rank".to_owned()],
+            defaulted: vec!["singularity_speedrun_time".to_owned()],
+        }
+    );
+    assert_eq!(report.warnings().len(), 2);
+}
+
+#[test]
+fn a_missing_beta_flag_is_left_for_the_stored_one() {
+    let payload = serde_json::json!({
+        "playerToken": "token",
+        "metabits": 10.0,
+        "dino_rank": 1,
+        "prestige_rank": 2,
+        "beyond_rank": 0,
+        "singularity_speedrun_time": null,
+        "all_sharks_obtained": false,
+        "all_hidden_achievements_obtained": false,
+    });
+
+    let (og_data, _, report) = parse_og_payload(payload).unwrap();
+
+    assert_eq!(og_data.beta_tester, None);
+    assert!(report.is_empty());
+}
+
+#[test]
+fn binary_and_json_payloads_write_the_same() {
+    use crate::models::PatchUserData;
+
+    let og_data = OGUpdateUserData {
+        player_token: "token".to_owned(),
+        beta_tester: Some(false),
+        metabits: 1_500_000.0,
+        dino_rank: 40,
+        prestige_rank: 2,
+        beyond_rank: 0,
+        singularity_speedrun_time: None,
+        all_sharks_obtained: true,
+        all_hidden_achievements_obtained: false,
+    };
+    let json = serde_json::json!({
+        "playerToken": "token",
+        "betaTester": false,
+        "metabits": 1_500_000.0,
+        "dino_rank": 40,
+        "prestige_rank": 2,
+        "beyond_rank": 0,
+        "singularity_speedrun_time": null,
+        "all_sharks_obtained": true,
+        "all_hidden_achievements_obtained": false,
+    });
+    let frame = crate::og_binary::encode_frame(&og_data).unwrap();
+
+    let binary = og_binary_payload(&frame).unwrap();
+    assert_eq!(binary, json);
+    let (_, from_binary, binary_report) = parse_og_payload(binary).unwrap();
+    let (_, from_json, json_report) = parse_og_payload(json).unwrap();
+    assert_eq!(
+        PatchUserData::from(from_binary),
+        PatchUserData::from(from_json)
+    );
+    assert!(binary_report.is_empty() && json_report.is_empty());
+    // the binary body is what saves the mobile clients' data
+    assert!(frame.len() < serde_json::to_vec(&og_data).unwrap().len());
+}
+
+#[test]
+fn malformed_binary_payloads_are_bad_requests() {
+    let frame = crate::og_binary::encode_frame(&1u8).unwrap();
+    let truncated = &crate::og_binary::encode_frame(&OGUpdateUserData {
+        player_token: "token".to_owned(),
+        beta_tester: None,
+        metabits: 0.0,
+        dino_rank: 0,
+        prestige_rank: 0,
+        beyond_rank: 0,
+        singularity_speedrun_time: None,
+        all_sharks_obtained: false,
+        all_hidden_achievements_obtained: false,
+    })
+    .unwrap()[..20];
+
+    for body in [&frame[..], truncated, &[1, 0][..], &[]] {
+        let error = og_binary_payload(body).unwrap_err();
+        assert_eq!(error.error_code(), "INVALID_BINARY_PAYLOAD", "{:?}", body);
+    }
+}
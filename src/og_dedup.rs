@@ -0,0 +1,229 @@
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+
+use crate::{config::Config, ttl_map::TtlMap, utilities::hex_encode};
+
+/// the header duplicates are answered with, next to the first request's response
+pub const DUPLICATE_SUPPRESSED_HEADER: &str = "X-Duplicate-Suppressed";
+
+static OG_DEDUP: OnceLock<Mutex<OgDedup>> = OnceLock::new();
+
+/// the derived token and the hash of the payload, the same payload for another player isn't a duplicate
+pub type OgDedupKey = (String, String);
+
+enum Entry {
+    /// the first request is still running, duplicates wait for its response
+    InFlight(watch::Receiver<Option<String>>),
+    /// the JSON body the first request was answered with
+    Done(String),
+}
+
+pub enum DedupCheck {
+    /// nobody sent the payload within the window, the request has to be handled
+    First(watch::Sender<Option<String>>),
+    /// the payload is being handled right now, the receiver gets the response once it's done
+    InFlight(watch::Receiver<Option<String>>),
+    Duplicate(String),
+}
+
+/// The legacy launcher fires the same og payload several times in a burst. Repeats of a payload
+/// within the window get the first request's response instead of being synced again. Only
+/// successful responses are kept, a failed first request lets the next one through.
+pub struct OgDedup {
+    entries: TtlMap<OgDedupKey, Entry>,
+}
+
+impl OgDedup {
+    pub fn new(window: Duration) -> Self {
+        OgDedup {
+            entries: TtlMap::new(window),
+        }
+    }
+
+    pub fn begin(&mut self, key: &OgDedupKey, now: Instant) -> DedupCheck {
+        match self.entries.get(key, now) {
+            Some(Entry::Done(body)) => DedupCheck::Duplicate(body.clone()),
+            Some(Entry::InFlight(receiver)) => DedupCheck::InFlight(receiver.clone()),
+            None => {
+                if self.entries.len() > 10_000 {
+                    self.entries.purge_expired(now);
+                }
+                let (sender, receiver) = watch::channel(None);
+                self.entries
+                    .insert(key.clone(), Entry::InFlight(receiver), now);
+                DedupCheck::First(sender)
+            }
+        }
+    }
+
+    /// keeps the response for the rest of the window, which still counts from the first request
+    pub fn finish(&mut self, key: &OgDedupKey, body: &str, now: Instant) {
+        if let Some(entry) = self.entries.get_mut(key, now) {
+            *entry = Entry::Done(body.to_owned());
+        }
+    }
+
+    pub fn fail(&mut self, key: &OgDedupKey) {
+        self.entries.remove(key);
+    }
+}
+
+pub fn og_dedup() -> &'static Mutex<OgDedup> {
+    OG_DEDUP.get_or_init(|| {
+        Mutex::new(OgDedup::new(Duration::from_secs(
+            Config::new().og_dedup_window,
+        )))
+    })
+}
+
+pub fn payload_hash(payload: &Value) -> String {
+    hex_encode(&Sha1::digest(payload.to_string().as_bytes()))
+}
+
+/// The first request of a payload. Dropping it without `finish`, e.g. because the request failed
+/// or timed out, forgets the payload and lets the waiting duplicates through.
+pub struct OgDedupGuard<'a> {
+    dedup: &'a Mutex<OgDedup>,
+    key: OgDedupKey,
+    sender: watch::Sender<Option<String>>,
+    finished: bool,
+}
+
+impl OgDedupGuard<'_> {
+    pub fn finish(mut self, body: &str) {
+        self.dedup
+            .lock()
+            .unwrap()
+            .finish(&self.key, body, Instant::now());
+        let _ = self.sender.send(Some(body.to_owned()));
+        self.finished = true;
+    }
+}
+
+impl Drop for OgDedupGuard<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.dedup.lock().unwrap().fail(&self.key);
+        }
+    }
+}
+
+pub enum OgDedupStart<'a> {
+    First(OgDedupGuard<'a>),
+    /// the body of the first request's response
+    Duplicate(String),
+}
+
+/// waits for a running first request of the payload, if the first request fails this one takes over
+pub async fn start_og_request(dedup: &Mutex<OgDedup>, key: OgDedupKey) -> OgDedupStart<'_> {
+    loop {
+        let check = dedup.lock().unwrap().begin(&key, Instant::now());
+        match check {
+            DedupCheck::Duplicate(body) => return OgDedupStart::Duplicate(body),
+            DedupCheck::First(sender) => {
+                return OgDedupStart::First(OgDedupGuard {
+                    dedup,
+                    key,
+                    sender,
+                    finished: false,
+                })
+            }
+            DedupCheck::InFlight(mut receiver) => {
+                // the sender is dropped without a response when the first request fails
+                if receiver.changed().await.is_ok() {
+                    if let Some(body) = receiver.borrow().clone() {
+                        return OgDedupStart::Duplicate(body);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn key(payload: &str) -> OgDedupKey {
+    ("token".to_owned(), payload.to_owned())
+}
+
+#[test]
+fn bursts_get_the_first_response() {
+    let now = Instant::now();
+    let mut dedup = OgDedup::new(Duration::from_secs(30));
+    let sender = match dedup.begin(&key("a"), now) {
+        DedupCheck::First(sender) => sender,
+        _ => panic!("the first request has to be handled"),
+    };
+
+    // a duplicate sent while the first one is running waits for its response
+    let receiver = match dedup.begin(&key("a"), now + Duration::from_millis(50)) {
+        DedupCheck::InFlight(receiver) => receiver,
+        _ => panic!("the first request is still running"),
+    };
+    dedup.finish(&key("a"), "{\"message\":\"ok\"}", now);
+    sender
+        .send(Some("{\"message\":\"ok\"}".to_owned()))
+        .unwrap();
+    assert_eq!(receiver.borrow().as_deref(), Some("{\"message\":\"ok\"}"));
+
+    // and so does one sent after it finished
+    assert!(matches!(
+        dedup.begin(&key("a"), now + Duration::from_secs(29)),
+        DedupCheck::Duplicate(body) if body == "{\"message\":\"ok\"}"
+    ));
+    // another payload is synced as usual
+    assert!(matches!(dedup.begin(&key("b"), now), DedupCheck::First(_)));
+}
+
+#[test]
+fn payloads_are_synced_again_after_the_window() {
+    let now = Instant::now();
+    let mut dedup = OgDedup::new(Duration::from_secs(30));
+    assert!(matches!(dedup.begin(&key("a"), now), DedupCheck::First(_)));
+    // finishing late doesn't extend the window
+    dedup.finish(&key("a"), "{}", now + Duration::from_secs(20));
+
+    assert!(matches!(
+        dedup.begin(&key("a"), now + Duration::from_secs(30)),
+        DedupCheck::First(_)
+    ));
+}
+
+#[test]
+fn failed_requests_are_not_cached() {
+    let dedup = Mutex::new(OgDedup::new(Duration::from_secs(30)));
+    let runtime = actix_web::rt::System::new();
+
+    let first = match runtime.block_on(start_og_request(&dedup, key("a"))) {
+        OgDedupStart::First(guard) => guard,
+        OgDedupStart::Duplicate(_) => panic!("nothing was sent before"),
+    };
+    let receiver = match dedup.lock().unwrap().begin(&key("a"), Instant::now()) {
+        DedupCheck::InFlight(receiver) => receiver,
+        _ => panic!("the first request is still running"),
+    };
+
+    // the first request failed, the waiting duplicate is let through and so is a later re-send
+    drop(first);
+    assert!(runtime
+        .block_on(async move {
+            let mut receiver = receiver;
+            receiver.changed().await
+        })
+        .is_err());
+    let retry = runtime.block_on(start_og_request(&dedup, key("a")));
+    assert!(matches!(retry, OgDedupStart::First(_)));
+
+    // a successful retry is what duplicates get from then on
+    if let OgDedupStart::First(guard) = retry {
+        guard.finish("{}");
+    }
+    assert!(matches!(
+        runtime.block_on(start_og_request(&dedup, key("a"))),
+        OgDedupStart::Duplicate(body) if body == "{}"
+    ));
+}
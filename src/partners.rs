@@ -0,0 +1,283 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use deadpool_postgres::Pool;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    constants::LOG, db, models::ApiPartner, ttl_map::TtlMap, utilities::hex_encode,
+    webhook_logging::webhook_log,
+};
+
+pub const PARTNER_KEY_HEADER: &str = "x-partner-key";
+
+/// shorter keys could be guessed
+pub const MIN_PARTNER_KEY_LENGTH: usize = 32;
+
+/// how long a looked up key is trusted before it's looked up again, deleting a partner drops it
+/// right away
+const PARTNER_KEY_TTL: Duration = Duration::from_secs(60);
+
+/// an unknown or expired key is logged at most once per this
+const REJECTED_KEY_LOG_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+static PARTNER_KEYS: OnceLock<Mutex<PartnerKeys>> = OnceLock::new();
+
+/// what a valid key gets, `expires_at` is checked whenever it's used
+#[derive(Clone, Debug, PartialEq)]
+pub struct PartnerGrant {
+    pub id: i64,
+    pub multiplier: u32,
+    pub expires_at: Option<SystemTime>,
+}
+
+impl PartnerGrant {
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| expires_at <= now)
+    }
+}
+
+impl From<&ApiPartner> for PartnerGrant {
+    fn from(partner: &ApiPartner) -> Self {
+        PartnerGrant {
+            id: partner.id,
+            multiplier: u32::try_from(partner.multiplier).unwrap_or(1).max(1),
+            expires_at: partner.expires_at,
+        }
+    }
+}
+
+/// the keys are stored as their SHA-256 like the tokens, so a leaked table can't be replayed
+pub fn hash_partner_key(key: &str) -> String {
+    hex_encode(&Sha256::digest(key.as_bytes()))
+}
+
+/// the limit a request gets, unknown and expired keys get the normal one
+pub fn partner_limit(limit: u32, grant: Option<&PartnerGrant>, now: SystemTime) -> u32 {
+    match grant {
+        Some(grant) if !grant.is_expired(now) => limit.saturating_mul(grant.multiplier),
+        _ => limit,
+    }
+}
+
+/// The looked up keys, unknown ones included so sending one over and over doesn't query the
+/// database every time, and the requests each partner made since the service started.
+pub struct PartnerKeys {
+    keys: TtlMap<String, Option<PartnerGrant>>,
+    usage: HashMap<i64, u64>,
+    logged_rejections: TtlMap<String, ()>,
+}
+
+impl PartnerKeys {
+    pub fn new() -> Self {
+        PartnerKeys {
+            keys: TtlMap::new(PARTNER_KEY_TTL),
+            usage: HashMap::new(),
+            logged_rejections: TtlMap::new(REJECTED_KEY_LOG_WINDOW),
+        }
+    }
+
+    /// `None` when the key has to be looked up, `Some(None)` when it's known to be nobody's
+    pub fn cached(&self, key_hash: &str, now: Instant) -> Option<Option<PartnerGrant>> {
+        self.keys.get(&key_hash.to_owned(), now).cloned()
+    }
+
+    pub fn remember(&mut self, key_hash: &str, grant: Option<PartnerGrant>, now: Instant) {
+        if self.keys.len() > 10_000 {
+            self.keys.purge_expired(now);
+        }
+        self.keys.insert(key_hash.to_owned(), grant, now);
+    }
+
+    pub fn forget(&mut self, key_hash: &str) {
+        self.keys.remove(&key_hash.to_owned());
+    }
+
+    pub fn record_use(&mut self, partner_id: i64) {
+        *self.usage.entry(partner_id).or_insert(0) += 1;
+    }
+
+    pub fn usage(&self, partner_id: i64) -> u64 {
+        self.usage.get(&partner_id).copied().unwrap_or(0)
+    }
+
+    /// Counts the request for the partner or, for an unknown or expired key, returns the log
+    /// about it. A rejected key is logged once per `REJECTED_KEY_LOG_WINDOW`.
+    pub fn use_key(
+        &mut self,
+        key_hash: &str,
+        grant: Option<&PartnerGrant>,
+        now: Instant,
+        system_now: SystemTime,
+    ) -> Option<String> {
+        let state = match grant {
+            Some(grant) if !grant.is_expired(system_now) => {
+                self.record_use(grant.id);
+                return None;
+            }
+            Some(_) => "an expired",
+            None => "an unknown",
+        };
+
+        if self
+            .logged_rejections
+            .contains_key(&key_hash.to_owned(), now)
+        {
+            return None;
+        }
+        if self.logged_rejections.len() > 10_000 {
+            self.logged_rejections.purge_expired(now);
+        }
+        self.logged_rejections.insert(key_hash.to_owned(), (), now);
+        Some(format!(
+            "a request sent {} partner key ({}), it got the normal rate limits",
+            state,
+            &key_hash[..8]
+        ))
+    }
+}
+
+impl Default for PartnerKeys {
+    fn default() -> Self {
+        PartnerKeys::new()
+    }
+}
+
+pub fn partner_keys() -> &'static Mutex<PartnerKeys> {
+    PARTNER_KEYS.get_or_init(|| Mutex::new(PartnerKeys::new()))
+}
+
+/// Resolves an `X-Partner-Key` into its partner's grant, see `partner_limit` for what it gets.
+/// Unknown and expired keys are logged, a key that couldn't be looked up gets the normal limits.
+pub async fn request_partner(
+    keys: &Mutex<PartnerKeys>,
+    db_pool: Option<&Pool>,
+    key: &str,
+    now: Instant,
+) -> Option<PartnerGrant> {
+    let key_hash = hash_partner_key(key);
+    let grant = partner_grant(keys, db_pool, &key_hash, now).await;
+    let rejection_log =
+        keys.lock()
+            .unwrap()
+            .use_key(&key_hash, grant.as_ref(), now, SystemTime::now());
+    if let Some(log) = rejection_log {
+        webhook_log(log, LOG::INFORMATIONAL).await;
+    }
+
+    grant
+}
+
+/// the cached grant of a key or the one looked up, it's only cached when the lookup worked
+async fn partner_grant(
+    keys: &Mutex<PartnerKeys>,
+    db_pool: Option<&Pool>,
+    key_hash: &str,
+    now: Instant,
+) -> Option<PartnerGrant> {
+    let cached = keys.lock().unwrap().cached(key_hash, now);
+    if let Some(grant) = cached {
+        return grant;
+    }
+
+    let client = db_pool?.get().await.ok()?;
+    match db::get_api_partner(&client, key_hash).await {
+        Ok(partner) => {
+            let grant = partner.as_ref().map(PartnerGrant::from);
+            keys.lock().unwrap().remember(key_hash, grant.clone(), now);
+            grant
+        }
+        Err(error) => {
+            println!("{:?}", error);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+fn grant(multiplier: u32, expires_at: Option<SystemTime>) -> PartnerGrant {
+    PartnerGrant {
+        id: 1,
+        multiplier,
+        expires_at,
+    }
+}
+
+#[test]
+fn partner_limits_are_multiplied() {
+    let now = SystemTime::now();
+
+    assert_eq!(partner_limit(20, Some(&grant(5, None)), now), 100);
+    assert_eq!(
+        partner_limit(u32::MAX, Some(&grant(5, None)), now),
+        u32::MAX
+    );
+    assert_eq!(partner_limit(20, None, now), 20);
+}
+
+#[test]
+fn expired_partners_get_the_normal_limits() {
+    let now = SystemTime::now();
+    let hour = Duration::from_secs(60 * 60);
+
+    assert_eq!(
+        partner_limit(20, Some(&grant(5, Some(now + hour))), now),
+        100
+    );
+    assert_eq!(partner_limit(20, Some(&grant(5, Some(now))), now), 20);
+    assert_eq!(
+        partner_limit(20, Some(&grant(5, Some(now - hour))), now),
+        20
+    );
+}
+
+#[test]
+fn unknown_keys_fall_back_to_the_normal_limits() {
+    let mut keys = PartnerKeys::new();
+    let now = Instant::now();
+    let system_now = SystemTime::now();
+    let unknown = hash_partner_key("unknown");
+
+    let log = keys.use_key(&unknown, None, now, system_now).unwrap();
+    assert!(log.contains("an unknown"), "{}", log);
+    // the log is throttled, the fallback isn't
+    assert_eq!(keys.use_key(&unknown, None, now, system_now), None);
+    let expired = grant(5, Some(system_now));
+    let log = keys
+        .use_key(
+            &hash_partner_key("expired"),
+            Some(&expired),
+            now,
+            system_now,
+        )
+        .unwrap();
+    assert!(log.contains("an expired"), "{}", log);
+    assert_eq!(keys.usage(1), 0);
+
+    let wiki = hash_partner_key("wiki");
+    assert_eq!(
+        keys.use_key(&wiki, Some(&grant(5, None)), now, system_now),
+        None
+    );
+    assert_eq!(keys.usage(1), 1);
+
+    // a key that isn't cached and can't be looked up isn't trusted
+    actix_web::rt::System::new().block_on(async {
+        let keys = Mutex::new(PartnerKeys::new());
+        keys.lock()
+            .unwrap()
+            .remember(&wiki, Some(grant(5, None)), now);
+
+        assert_eq!(
+            partner_grant(&keys, None, &wiki, now).await,
+            Some(grant(5, None))
+        );
+        assert_eq!(partner_grant(&keys, None, &unknown, now).await, None);
+        keys.lock().unwrap().forget(&wiki);
+        assert_eq!(partner_grant(&keys, None, &wiki, now).await, None);
+    });
+}
@@ -0,0 +1,343 @@
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{
+    granted_roles::GrantedRoles,
+    models::UserData,
+    utilities::{hex_decode, hex_encode},
+};
+
+/// bumped whenever a section changes shape, blobs of another version are refused
+pub const PORTABLE_VERSION: u32 = 1;
+
+/// the parts of a blob that are signed on their own, so a rejected blob can say which one changed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PortableSection {
+    User,
+    GrantedRoles,
+    History,
+}
+
+impl PortableSection {
+    pub fn name(self) -> &'static str {
+        match self {
+            PortableSection::User => "user",
+            PortableSection::GrantedRoles => "granted_roles",
+            PortableSection::History => "history",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PortableError {
+    /// the blob was exported by another version of the format
+    Version(u32),
+    /// the section doesn't match its signature, it was changed or signed with another secret
+    Tampered(PortableSection),
+    /// the section is signed but isn't what this version expects
+    Malformed(PortableSection),
+}
+
+impl PortableError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            PortableError::Version(_) => "The portable record was exported by another version",
+            PortableError::Tampered(PortableSection::User) => {
+                "The portable record's user section doesn't match its signature"
+            }
+            PortableError::Tampered(PortableSection::GrantedRoles) => {
+                "The portable record's granted_roles section doesn't match its signature"
+            }
+            PortableError::Tampered(PortableSection::History) => {
+                "The portable record's history section doesn't match its signature"
+            }
+            PortableError::Malformed(PortableSection::User) => {
+                "The portable record's user section couldn't be read"
+            }
+            PortableError::Malformed(PortableSection::GrantedRoles) => {
+                "The portable record's granted_roles section couldn't be read"
+            }
+            PortableError::Malformed(PortableSection::History) => {
+                "The portable record's history section couldn't be read"
+            }
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            PortableError::Version(_) => "PORTABLE_VERSION_UNSUPPORTED",
+            PortableError::Tampered(_) => "PORTABLE_RECORD_TAMPERED",
+            PortableError::Malformed(_) => "PORTABLE_RECORD_MALFORMED",
+        }
+    }
+}
+
+/// Everything about one user that moves between instances. Each section is carried as the JSON
+/// text it was signed as, re-serializing parsed JSON could change a float's last digit and break
+/// the signature without anyone touching it.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PortableUser {
+    pub version: u32,
+    pub user: String,
+    pub granted_roles: String,
+    pub history: String,
+    pub signatures: PortableSignatures,
+}
+
+/// hex HMAC-SHA256s of the sections, see `sign_section`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PortableSignatures {
+    pub user: String,
+    pub granted_roles: String,
+    pub history: String,
+}
+
+/// the sections of a blob whose signatures all matched
+pub struct OpenedUser {
+    pub user: UserData,
+    pub granted_roles: GrantedRoles,
+    pub history: Vec<UserData>,
+}
+
+/// the granted roles are written the way every response writes them, with RFC 3339 grant times
+#[derive(Deserialize, Serialize)]
+struct PortableGrantedRoles(
+    #[serde(
+        serialize_with = "crate::timestamps::unix_seconds::serialize_map",
+        deserialize_with = "crate::timestamps::unix_seconds::deserialize_map"
+    )]
+    GrantedRoles,
+);
+
+/// The user section is signed on its own, the other two also take in the user's signature so
+/// they can't be swapped in from another user's blob.
+fn section_mac(
+    secret: &str,
+    section: PortableSection,
+    user_signature: Option<&str>,
+    text: &str,
+) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(format!("{}\n{}\n", PORTABLE_VERSION, section.name()).as_bytes());
+    if let Some(user_signature) = user_signature {
+        mac.update(user_signature.as_bytes());
+        mac.update(b"\n");
+    }
+    mac.update(text.as_bytes());
+
+    mac
+}
+
+fn sign_section(
+    secret: &str,
+    section: PortableSection,
+    user_signature: Option<&str>,
+    text: &str,
+) -> String {
+    hex_encode(
+        &section_mac(secret, section, user_signature, text)
+            .finalize()
+            .into_bytes(),
+    )
+}
+
+fn verify_section(
+    secret: &str,
+    section: PortableSection,
+    user_signature: Option<&str>,
+    text: &str,
+    signature: &str,
+) -> Result<(), PortableError> {
+    // verify_slice compares in constant time
+    let signature = hex_decode(signature).ok_or(PortableError::Tampered(section))?;
+    section_mac(secret, section, user_signature, text)
+        .verify_slice(&signature)
+        .map_err(|_| PortableError::Tampered(section))
+}
+
+pub fn seal(
+    user: &UserData,
+    granted_roles: &GrantedRoles,
+    history: &[UserData],
+    secret: &str,
+) -> Result<PortableUser, serde_json::Error> {
+    let user = serde_json::to_string(user)?;
+    let granted_roles = serde_json::to_string(&PortableGrantedRoles(granted_roles.clone()))?;
+    let history = serde_json::to_string(history)?;
+
+    let user_signature = sign_section(secret, PortableSection::User, None, &user);
+    let signatures = PortableSignatures {
+        granted_roles: sign_section(
+            secret,
+            PortableSection::GrantedRoles,
+            Some(&user_signature),
+            &granted_roles,
+        ),
+        history: sign_section(
+            secret,
+            PortableSection::History,
+            Some(&user_signature),
+            &history,
+        ),
+        user: user_signature,
+    };
+
+    Ok(PortableUser {
+        version: PORTABLE_VERSION,
+        user,
+        granted_roles,
+        history,
+        signatures,
+    })
+}
+
+/// checks every section's signature before reading any of them, the first one that fails is named
+pub fn open(blob: &PortableUser, secret: &str) -> Result<OpenedUser, PortableError> {
+    if blob.version != PORTABLE_VERSION {
+        return Err(PortableError::Version(blob.version));
+    }
+    let signatures = &blob.signatures;
+    verify_section(
+        secret,
+        PortableSection::User,
+        None,
+        &blob.user,
+        &signatures.user,
+    )?;
+    verify_section(
+        secret,
+        PortableSection::GrantedRoles,
+        Some(&signatures.user),
+        &blob.granted_roles,
+        &signatures.granted_roles,
+    )?;
+    verify_section(
+        secret,
+        PortableSection::History,
+        Some(&signatures.user),
+        &blob.history,
+        &signatures.history,
+    )?;
+
+    Ok(OpenedUser {
+        user: serde_json::from_str(&blob.user)
+            .map_err(|_| PortableError::Malformed(PortableSection::User))?,
+        granted_roles: serde_json::from_str::<PortableGrantedRoles>(&blob.granted_roles)
+            .map_err(|_| PortableError::Malformed(PortableSection::GrantedRoles))?
+            .0,
+        history: serde_json::from_str(&blob.history)
+            .map_err(|_| PortableError::Malformed(PortableSection::History))?,
+    })
+}
+
+#[cfg(test)]
+fn user_fixture(metabits: i64) -> UserData {
+    UserData {
+        discord_id: "123456789012345678".to_owned(),
+        token: "token".to_owned(),
+        beta_tester: true,
+        metabits,
+        dino_rank: 120,
+        prestige_rank: 4,
+        beyond_rank: 10,
+        singularity_speedrun_time: Some(0.1 + 0.2),
+        all_sharks_obtained: false,
+        all_hidden_achievements_obtained: true,
+        edited_timestamp: std::time::UNIX_EPOCH
+            + std::time::Duration::from_millis(1_700_000_000_123),
+    }
+}
+
+#[cfg(test)]
+fn sealed_fixture(secret: &str) -> PortableUser {
+    let granted_roles = GrantedRoles::from([
+        ("1".to_owned(), Some(1_700_000_000)),
+        ("2".to_owned(), None),
+    ]);
+    let history = [user_fixture(10), user_fixture(9_007_199_254_740_993)];
+
+    seal(
+        &user_fixture(9_007_199_254_740_993),
+        &granted_roles,
+        &history,
+        secret,
+    )
+    .unwrap()
+}
+
+#[test]
+fn sealed_users_open_to_the_same_record() {
+    let blob = sealed_fixture("secret");
+    // a blob survives being sent around as JSON
+    let blob: PortableUser = serde_json::from_str(&serde_json::to_string(&blob).unwrap()).unwrap();
+
+    let opened = open(&blob, "secret").unwrap();
+    assert_eq!(opened.user.metabits, 9_007_199_254_740_993);
+    assert_eq!(opened.user.singularity_speedrun_time, Some(0.1 + 0.2));
+    assert_eq!(
+        opened.user.edited_timestamp,
+        user_fixture(0).edited_timestamp
+    );
+    assert_eq!(
+        opened.granted_roles,
+        GrantedRoles::from([
+            ("1".to_owned(), Some(1_700_000_000)),
+            ("2".to_owned(), None),
+        ])
+    );
+    assert_eq!(
+        opened
+            .history
+            .iter()
+            .map(|snapshot| snapshot.metabits)
+            .collect::<Vec<_>>(),
+        vec![10, 9_007_199_254_740_993]
+    );
+}
+
+#[test]
+fn changing_one_byte_names_the_section() {
+    let sections = [
+        ("\"beta_tester\":true", PortableSection::User),
+        ("\"1\":\"2023", PortableSection::GrantedRoles),
+        ("\"metabits\":\"10", PortableSection::History),
+    ];
+    for (needle, section) in sections {
+        let json = serde_json::to_string(&sealed_fixture("secret")).unwrap();
+        // the sections are strings in the blob, so their quotes are escaped
+        let needle = needle.replace('"', "\\\"");
+        let at = json.find(&needle).unwrap() + needle.len() - 1;
+        let mut bytes = json.into_bytes();
+        bytes[at] = if bytes[at] == b'1' { b'2' } else { b'1' };
+        let tampered: PortableUser = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(
+            open(&tampered, "secret").err(),
+            Some(PortableError::Tampered(section))
+        );
+    }
+
+    let mut swapped = sealed_fixture("secret");
+    swapped.history = "[]".to_owned();
+    assert_eq!(
+        open(&swapped, "secret").err(),
+        Some(PortableError::Tampered(PortableSection::History))
+    );
+}
+
+#[test]
+fn blobs_only_open_with_their_secret_and_version() {
+    assert_eq!(
+        open(&sealed_fixture("secret"), "another secret").err(),
+        Some(PortableError::Tampered(PortableSection::User))
+    );
+
+    let mut blob = sealed_fixture("secret");
+    blob.version = PORTABLE_VERSION + 1;
+    assert_eq!(
+        open(&blob, "secret").err(),
+        Some(PortableError::Version(PORTABLE_VERSION + 1))
+    );
+}
@@ -0,0 +1,265 @@
+use actix_web::rt::{self, time};
+use deadpool_postgres::Pool;
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    sync::{Mutex, OnceLock},
+    time::{Duration, SystemTime},
+};
+use twilight_http::Client;
+use twilight_model::id::{
+    marker::{GuildMarker, RoleMarker, UserMarker},
+    Id,
+};
+
+use crate::{
+    constants::{ErrorLogType, C2SGUILD, LOG},
+    db,
+    discord_pause::ensure_discord_available,
+    errors::{ConvertResultErrorToMyError, LogMyError, MyError},
+    models::PromoRoleRule,
+    role_handling::{discord_call, EarnedRole},
+    webhook_logging::webhook_log,
+};
+
+const PROMO_INTERVAL: Duration = Duration::from_secs(60);
+/// how many members lose an expired promo role at once
+const SWEEP_BATCH_SIZE: i64 = 10;
+/// the pause between batches, so a sweep doesn't eat the rate limit the syncs need
+const SWEEP_BATCH_DELAY: Duration = Duration::from_secs(2);
+
+static PROMO_RULES: OnceLock<Mutex<Vec<PromoRoleRule>>> = OnceLock::new();
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum PromoWindow {
+    Upcoming,
+    Active,
+    Ended,
+}
+
+pub fn promo_window(rule: &PromoRoleRule, now: SystemTime) -> PromoWindow {
+    if now < rule.starts_at {
+        PromoWindow::Upcoming
+    } else if now < rule.ends_at {
+        PromoWindow::Active
+    } else {
+        PromoWindow::Ended
+    }
+}
+
+/// the roles of the promo rules whose window `now` is inside of
+pub fn active_promo_roles(rules: &[PromoRoleRule], now: SystemTime) -> Vec<EarnedRole> {
+    rules
+        .iter()
+        .filter(|rule| promo_window(rule, now) == PromoWindow::Active)
+        .filter_map(|rule| {
+            Some(EarnedRole {
+                id: rule.role_id.parse::<u64>().ok().filter(|id| *id != 0)?,
+                name: Cow::Owned(rule.name.clone()),
+                promo_rule: Some(rule.id),
+            })
+        })
+        .collect()
+}
+
+/// the rules that ended and still need their role taken away from everyone that got it
+pub fn rules_due_for_sweep(rules: &[PromoRoleRule], now: SystemTime) -> Vec<&PromoRoleRule> {
+    rules
+        .iter()
+        .filter(|rule| !rule.swept && promo_window(rule, now) == PromoWindow::Ended)
+        .collect()
+}
+
+/// the promo rules that haven't been swept yet, they're reloaded from the database every minute
+pub fn promo_rules() -> &'static Mutex<Vec<PromoRoleRule>> {
+    PROMO_RULES.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+pub async fn reload_promo_rules(client: &deadpool_postgres::Client) -> Result<(), MyError> {
+    let rules = db::get_promo_role_rules(client)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at loading the promo role rules",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+    *promo_rules().lock().unwrap() = rules;
+
+    Ok(())
+}
+
+/// records which of the gained roles came from promo rules
+pub async fn record_promo_grants(
+    client: &deadpool_postgres::Client,
+    discord_id: &str,
+    gained_roles: &[EarnedRole],
+) {
+    for rule_id in gained_roles.iter().filter_map(|role| role.promo_rule) {
+        let _ = db::create_promo_role_grant(client, &rule_id, discord_id)
+            .await
+            .make_response(MyError::InternalError(
+                "Failed at recording a promo role grant",
+            ))
+            .make_log(ErrorLogType::INTERNAL)
+            .await;
+    }
+}
+
+/// keeps the promo rules fresh and takes the roles of ended rules away from everyone that got them
+pub fn spawn_promo_scheduler(pool: Pool, discord_token: String) {
+    rt::spawn(async move {
+        let mut interval = time::interval(PROMO_INTERVAL);
+        loop {
+            interval.tick().await;
+            let _ = run_promo_sweep(&pool, &discord_token).await;
+        }
+    });
+}
+
+async fn run_promo_sweep(pool: &Pool, discord_token: &str) -> Result<(), MyError> {
+    let client = pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "promo sweep failed at creating database client",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+    reload_promo_rules(&client).await?;
+
+    let rules = promo_rules().lock().unwrap().clone();
+    for rule in rules_due_for_sweep(&rules, SystemTime::now()) {
+        sweep_rule(&client, discord_token, rule).await?;
+    }
+
+    Ok(())
+}
+
+/// Removes the rule's role from the members that got it from the rule, members that have the
+/// role for any other reason keep it. A sweep that's interrupted picks up where it stopped.
+async fn sweep_rule(
+    client: &deadpool_postgres::Client,
+    discord_token: &str,
+    rule: &PromoRoleRule,
+) -> Result<(), MyError> {
+    let discord = Client::new(discord_token.to_owned());
+    let guild_id = Id::<GuildMarker>::new(C2SGUILD);
+    let role_id = Id::<RoleMarker>::new(
+        rule.role_id
+            .parse::<u64>()
+            .ok()
+            .filter(|id| *id != 0)
+            .ok_or(MyError::InternalError(
+                "a promo rule has an invalid role id",
+            ))?,
+    );
+
+    loop {
+        let discord_ids = db::get_promo_role_grants(client, &rule.id, &SWEEP_BATCH_SIZE)
+            .await
+            .make_response(MyError::InternalError(
+                "promo sweep failed at retrieving the grants",
+            ))
+            .make_log(ErrorLogType::INTERNAL)
+            .await?;
+        if discord_ids.is_empty() {
+            break;
+        }
+
+        for discord_id in discord_ids {
+            // a paused Discord stops the sweep until the next run
+            ensure_discord_available().await?;
+            if let Some(user_id) = discord_id.parse::<u64>().ok().filter(|id| *id != 0) {
+                let removed = discord_call(
+                    discord
+                        .remove_guild_member_role(guild_id, Id::<UserMarker>::new(user_id), role_id)
+                        .exec()
+                        .await,
+                    "promo sweep failed at removing a role",
+                )
+                .await;
+                // members that left the server can't lose the role anymore, only a paused Discord stops the sweep
+                if let Err(error) = removed {
+                    if ensure_discord_available().await.is_err() {
+                        return Err(error);
+                    }
+                }
+            }
+
+            db::delete_promo_role_grant(client, &rule.id, &discord_id)
+                .await
+                .make_response(MyError::InternalError(
+                    "promo sweep failed at deleting a grant",
+                ))
+                .make_log(ErrorLogType::INTERNAL)
+                .await?;
+        }
+
+        time::sleep(SWEEP_BATCH_DELAY).await;
+    }
+
+    db::mark_promo_role_rule_swept(client, &rule.id)
+        .await
+        .make_response(MyError::InternalError(
+            "promo sweep failed at marking the rule as swept",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    webhook_log(
+        format!(
+            "the promo role {} ended and was removed from everyone that got it",
+            rule.name
+        ),
+        LOG::INFORMATIONAL,
+    )
+    .await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn promo_rule(starts_at: u64, ends_at: u64) -> PromoRoleRule {
+    PromoRoleRule {
+        id: 1,
+        role_id: "1000".to_owned(),
+        name: "Anniversary".to_owned(),
+        starts_at: SystemTime::UNIX_EPOCH + Duration::from_secs(starts_at),
+        ends_at: SystemTime::UNIX_EPOCH + Duration::from_secs(ends_at),
+        swept: false,
+        created_timestamp: SystemTime::UNIX_EPOCH,
+    }
+}
+
+#[test]
+fn promo_roles_are_only_earned_inside_their_window() {
+    let rules = [promo_rule(100, 200)];
+    let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+
+    assert!(active_promo_roles(&rules, at(99)).is_empty());
+    assert_eq!(
+        active_promo_roles(&rules, at(100)),
+        vec![EarnedRole {
+            id: 1000,
+            name: Cow::Borrowed("Anniversary"),
+            promo_rule: Some(1),
+        }]
+    );
+    assert_eq!(active_promo_roles(&rules, at(199)).len(), 1);
+    assert!(active_promo_roles(&rules, at(200)).is_empty());
+}
+
+#[test]
+fn only_ended_unswept_rules_are_swept() {
+    let mut swept = promo_rule(0, 50);
+    swept.swept = true;
+    let rules = [promo_rule(0, 100), promo_rule(100, 200), swept];
+    let now = SystemTime::UNIX_EPOCH + Duration::from_secs(150);
+
+    let due = rules_due_for_sweep(&rules, now);
+
+    assert_eq!(due.len(), 1);
+    assert_eq!(promo_window(due[0], now), PromoWindow::Ended);
+    assert_eq!(promo_window(&rules[1], now), PromoWindow::Active);
+}
@@ -0,0 +1,224 @@
+use deadpool_postgres::Client;
+use std::sync::Mutex;
+
+use crate::{
+    constants::{ErrorLogType, LoggedUser, LOG},
+    db,
+    errors::{ConvertResultErrorToMyError, LogMyError, MyError},
+    models::UserData,
+    role_debounce::{pending_evaluations, PendingEvaluations},
+    webhook_logging::{log_throttle, webhook_log, LogThrottle},
+    write_behind::{write_behind, CounterTable, WriteBehindBuffer},
+};
+
+/// the in-memory state that's kept per user, see `UserStores::global`
+pub struct UserStores<'a> {
+    pub log_throttle: &'a Mutex<LogThrottle>,
+    pub write_behind: &'a WriteBehindBuffer,
+    pub pending_evaluations: &'a Mutex<PendingEvaluations>,
+}
+
+impl UserStores<'static> {
+    /// the stores the service runs with
+    pub fn global() -> Self {
+        UserStores {
+            log_throttle: log_throttle(),
+            write_behind: write_behind(),
+            pending_evaluations: pending_evaluations(),
+        }
+    }
+}
+
+/// Removes everything that was derived from a user's data so nothing acts on the user after
+/// they've been deleted, every way of deleting a user goes through here. It's best-effort, a
+/// failing step is logged and the rest still run.
+///
+/// Returns the names of the steps that failed.
+pub async fn purge_user_artifacts(client: &Client, user_data: &UserData) -> Vec<&'static str> {
+    purge_user_artifacts_from(client, &UserStores::global(), user_data).await
+}
+
+/// `purge_user_artifacts` with the in-memory state in `stores`
+pub async fn purge_user_artifacts_from(
+    client: &Client,
+    stores: &UserStores<'_>,
+    user_data: &UserData,
+) -> Vec<&'static str> {
+    let discord_id = user_data.discord_id.as_str();
+
+    stores.log_throttle.lock().unwrap().forget(discord_id);
+    // a flush that already took the count is dropped by the upsert, the user isn't linked anymore
+    stores
+        .write_behind
+        .forget(CounterTable::UserActivity, discord_id);
+    stores
+        .pending_evaluations
+        .lock()
+        .unwrap()
+        .cancel(discord_id);
+
+    let steps = [
+        (
+            "snapshots",
+            db::delete_userdata_snapshots(client, discord_id).await,
+        ),
+        (
+            "idempotency keys",
+            db::delete_idempotency_keys(client, &user_data.token).await,
+        ),
+        (
+            "link codes",
+            db::delete_link_codes(client, discord_id).await,
+        ),
+        (
+            "activity",
+            db::delete_user_activity(client, discord_id).await,
+        ),
+        (
+            "token transitions",
+            db::delete_token_transitions(client, discord_id).await,
+        ),
+        (
+            "support codes",
+            db::delete_support_codes(client, &user_data.token).await,
+        ),
+    ];
+
+    let mut failed_steps = Vec::new();
+    for (step, result) in steps {
+        let result = result
+            .make_response(MyError::InternalError(
+                "Failed at purging the artifacts of a deleted user",
+            ))
+            .make_log(ErrorLogType::USER(
+                LoggedUser::token(&user_data.token).with_discord_id(discord_id),
+            ))
+            .await;
+        if result.is_err() {
+            failed_steps.push(step);
+        }
+    }
+
+    if !failed_steps.is_empty() {
+        webhook_log(
+            format!(
+                "purging the artifacts of user with ID {} partially failed at: {}",
+                discord_id,
+                failed_steps.join(", ")
+            ),
+            LOG::FAILURE,
+        )
+        .await;
+    }
+
+    failed_steps
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn nothing_acts_on_a_purged_user() {
+    use crate::{budget::RequestBudget, models::UpdateUserData, write_behind::FLUSH_INTERVAL};
+    use std::time::{Duration, Instant, SystemTime};
+
+    db::with_test_db(|pool| async move {
+        let client = pool.get().await.unwrap();
+        let budget = RequestBudget::unlimited();
+        let now = SystemTime::now();
+        let log_throttle = Mutex::new(LogThrottle::new(Duration::from_secs(60), Instant::now()));
+        let write_behind = WriteBehindBuffer::new(FLUSH_INTERVAL, 10, Instant::now());
+        let pending_evaluations = Mutex::new(PendingEvaluations::default());
+        let stores = UserStores {
+            log_throttle: &log_throttle,
+            write_behind: &write_behind,
+            pending_evaluations: &pending_evaluations,
+        };
+
+        let created = db::create_userdata(
+            &client,
+            &budget,
+            "token",
+            "1",
+            &false,
+            UpdateUserData::default(),
+        )
+        .await
+        .unwrap();
+        db::create_userdata_snapshot(&client, &budget, &created)
+            .await
+            .unwrap();
+        db::create_idempotency_key(&client, "token", "key", "{}")
+            .await
+            .unwrap();
+        db::create_link_code(&client, "ABCD2345", "1", &(now + Duration::from_secs(600)))
+            .await
+            .unwrap();
+        db::upsert_user_activity(&client, &["1".to_owned()], &[1])
+            .await
+            .unwrap();
+        db::create_token_transition(&client, "1", "token")
+            .await
+            .unwrap();
+        let user_tokens = crate::utilities::DerivedTokens {
+            v2: "token".to_owned(),
+            legacy: "legacy".to_owned(),
+        };
+        db::upsert_support_code(&client, "code", &user_tokens)
+            .await
+            .unwrap();
+        write_behind.add(CounterTable::UserActivity, "1", 1);
+        pending_evaluations.lock().unwrap().request(
+            "1",
+            true,
+            Duration::from_secs(1),
+            Instant::now(),
+        );
+        assert!(log_throttle.lock().unwrap().allow("1", Instant::now()));
+
+        let deleted = db::delete_userdata(&client, &budget, "token")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(purge_user_artifacts_from(&client, &stores, &deleted)
+            .await
+            .is_empty());
+
+        assert!(db::get_userdata_snapshots(&client, "1")
+            .await
+            .unwrap()
+            .is_empty());
+        // a retried create isn't answered with the deleted link
+        assert_eq!(
+            db::get_idempotent_response(&client, "token", "key", &SystemTime::UNIX_EPOCH)
+                .await
+                .unwrap(),
+            None
+        );
+        // and a code handed out before can't link the discord id again
+        assert_eq!(
+            db::consume_link_code(&client, "ABCD2345", &now)
+                .await
+                .unwrap(),
+            None
+        );
+        // the game's support code doesn't sign them in anymore
+        assert!(matches!(
+            db::get_support_code(&client, "code").await,
+            Err(tokio_pg_mapper::Error::ColumnNotFound)
+        ));
+        for table in ["UserActivity", "TokenTransitions"] {
+            let rows: i64 = client
+                .query_one(&format!("SELECT count(*) FROM \"{}\"", table), &[])
+                .await
+                .unwrap()
+                .get(0);
+            assert_eq!(rows, 0, "{}", table);
+        }
+        assert!(write_behind.take_batches(Instant::now()).is_empty());
+        assert!(pending_evaluations
+            .lock()
+            .unwrap()
+            .take_due(Instant::now() + Duration::from_secs(60))
+            .is_empty());
+        assert!(log_throttle.lock().unwrap().allow("1", Instant::now()));
+    });
+}
@@ -0,0 +1,201 @@
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Mutex, OnceLock},
+    time::SystemTime,
+};
+
+/// how many errors are kept around for GET /admin/errors
+pub const RECENT_ERRORS_CAPACITY: usize = 1000;
+
+static RECENT_ERRORS: OnceLock<Mutex<RecentErrors>> = OnceLock::new();
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct RecordedError {
+    pub code: String,
+    pub message: String,
+    pub endpoint: String,
+    pub request_id: Option<String>,
+    /// milliseconds since the unix epoch
+    #[serde(serialize_with = "crate::timestamps::unix_millis::serialize")]
+    pub timestamp: u64,
+    /// the first few characters of the user's token, enough to tell users apart without leaking it
+    pub token_fingerprint: Option<String>,
+}
+
+impl RecordedError {
+    pub fn new(
+        code: String,
+        message: String,
+        endpoint: String,
+        request_id: Option<String>,
+        token_fingerprint: Option<String>,
+    ) -> Self {
+        RecordedError {
+            code,
+            message,
+            endpoint,
+            request_id,
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            token_fingerprint,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ErrorGroup {
+    pub code: String,
+    pub endpoint: String,
+    pub count: usize,
+}
+
+/// a ring buffer of the most recent errors, the oldest one is evicted once it's full
+pub struct RecentErrors {
+    capacity: usize,
+    entries: VecDeque<RecordedError>,
+}
+
+impl RecentErrors {
+    pub fn new(capacity: usize) -> Self {
+        RecentErrors {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, error: RecordedError) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(error);
+    }
+
+    /// the errors matching the filters, oldest first
+    pub fn query(&self, code: Option<&str>, since: Option<u64>) -> Vec<RecordedError> {
+        self.entries
+            .iter()
+            .filter(|error| code.map_or(true, |code| error.code == code))
+            .filter(|error| since.map_or(true, |since| error.timestamp >= since))
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// counts the errors per code per endpoint
+pub fn group_errors(errors: &[RecordedError]) -> Vec<ErrorGroup> {
+    let mut groups: BTreeMap<(&str, &str), usize> = BTreeMap::new();
+    for error in errors {
+        *groups
+            .entry((error.code.as_str(), error.endpoint.as_str()))
+            .or_insert(0) += 1;
+    }
+
+    groups
+        .into_iter()
+        .map(|((code, endpoint), count)| ErrorGroup {
+            code: code.to_owned(),
+            endpoint: endpoint.to_owned(),
+            count,
+        })
+        .collect()
+}
+
+const TOKEN_FINGERPRINT_LEN: usize = 8;
+
+/// The part of a user token that's safe to show to responders. It's also stored with every account
+/// so leaked fingerprints can be searched for, both have to come from here to stay comparable.
+pub fn token_fingerprint(token: &str) -> String {
+    token.chars().take(TOKEN_FINGERPRINT_LEN).collect()
+}
+
+/// whether `fingerprint` could have come from `token_fingerprint`, tokens are lowercase hex
+pub fn is_token_fingerprint(fingerprint: &str) -> bool {
+    fingerprint.len() == TOKEN_FINGERPRINT_LEN
+        && fingerprint
+            .bytes()
+            .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+pub fn recent_errors() -> &'static Mutex<RecentErrors> {
+    RECENT_ERRORS.get_or_init(|| Mutex::new(RecentErrors::new(RECENT_ERRORS_CAPACITY)))
+}
+
+#[cfg(test)]
+fn recorded_error(code: &str, endpoint: &str, timestamp: u64) -> RecordedError {
+    RecordedError {
+        code: code.to_owned(),
+        message: String::new(),
+        endpoint: endpoint.to_owned(),
+        request_id: None,
+        timestamp,
+        token_fingerprint: None,
+    }
+}
+
+#[test]
+fn evicts_the_oldest_errors_past_capacity() {
+    let mut errors = RecentErrors::new(3);
+    for timestamp in 0..5 {
+        errors.push(recorded_error("INTERNAL_ERROR", "/v2/userdata", timestamp));
+    }
+
+    assert_eq!(
+        errors
+            .query(None, None)
+            .iter()
+            .map(|error| error.timestamp)
+            .collect::<Vec<u64>>(),
+        vec![2, 3, 4]
+    );
+    assert_eq!(errors.query(None, Some(4)).len(), 1);
+}
+
+#[test]
+fn groups_errors_by_code_and_endpoint() {
+    let errors = vec![
+        recorded_error("INTERNAL_ERROR", "/v2/userdata", 0),
+        recorded_error("BAD_REQUEST", "/v2/userdata", 1),
+        recorded_error("INTERNAL_ERROR", "/v2/userdata", 2),
+        recorded_error("INTERNAL_ERROR", "/userdata", 3),
+    ];
+
+    assert_eq!(
+        group_errors(&errors),
+        vec![
+            ErrorGroup {
+                code: "BAD_REQUEST".to_owned(),
+                endpoint: "/v2/userdata".to_owned(),
+                count: 1,
+            },
+            ErrorGroup {
+                code: "INTERNAL_ERROR".to_owned(),
+                endpoint: "/userdata".to_owned(),
+                count: 1,
+            },
+            ErrorGroup {
+                code: "INTERNAL_ERROR".to_owned(),
+                endpoint: "/v2/userdata".to_owned(),
+                count: 2,
+            },
+        ]
+    );
+}
+
+#[test]
+fn fingerprints_are_the_start_of_the_token() {
+    let token = "80d7bf83f257ac0150c1fafc3834cd73ab3879c7";
+    assert_eq!(token_fingerprint(token), "80d7bf83");
+    assert!(is_token_fingerprint(&token_fingerprint(token)));
+
+    assert!(!is_token_fingerprint("80D7BF83"));
+    assert!(!is_token_fingerprint("80d7bf8"));
+    assert!(!is_token_fingerprint("80d7bf83f"));
+    assert!(!is_token_fingerprint("80d7bf8z"));
+}
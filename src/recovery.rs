@@ -0,0 +1,132 @@
+use deadpool_postgres::Client;
+
+use crate::{
+    db::{self, MigratedToken},
+    errors::{ConvertResultErrorToMyError, MyError},
+    utilities::DerivedTokens,
+};
+
+/// which of the user's credentials a request was made with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Credential {
+    Primary,
+    /// the secondary email and token linked through POST /user/recovery-credential
+    Recovery,
+}
+
+impl Credential {
+    /// appended to the log messages of a request so moderators can tell how the user signed in
+    pub fn log_suffix(&self) -> &'static str {
+        match self {
+            Credential::Primary => "",
+            Credential::Recovery => " (using their recovery credential)",
+        }
+    }
+}
+
+/// what linking a recovery credential will do
+#[derive(Debug, PartialEq)]
+pub enum RecoveryLink {
+    Created,
+    /// a user only has a single recovery credential, so a new one takes the old one's place
+    Replaced,
+}
+
+/// `recovery_of` is the primary token the derived token is the recovery token of, if any
+pub fn resolve_credential(
+    derived_token: &str,
+    recovery_of: Option<String>,
+) -> (String, Credential) {
+    match recovery_of {
+        Some(primary_token) => (primary_token, Credential::Recovery),
+        None => (derived_token.to_owned(), Credential::Primary),
+    }
+}
+
+/// Turns the tokens derived from a request's credentials into the token of the user's row, so
+/// every user endpoint accepts the recovery credential in place of the primary one. The v2 token
+/// is tried first, a row or recovery credential still under the legacy token is moved to the v2
+/// token on the way.
+pub async fn resolve_user_token(
+    client: &Client,
+    derived_tokens: &DerivedTokens,
+) -> Result<(String, Credential), MyError> {
+    let recovery_of = db::get_recovery_primary_token(client, &derived_tokens.v2)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at checking your credentials, please try again",
+        ))?;
+    if recovery_of.is_some() {
+        return Ok(resolve_credential(&derived_tokens.v2, recovery_of));
+    }
+
+    let migrated = db::migrate_user_token(client, &derived_tokens.legacy, &derived_tokens.v2)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at checking your credentials, please try again",
+        ))?;
+    let recovery_of = match migrated {
+        Some(MigratedToken::Recovery(primary_token)) => Some(primary_token),
+        Some(MigratedToken::Primary) | None => None,
+    };
+
+    Ok(resolve_credential(&derived_tokens.v2, recovery_of))
+}
+
+/// Checks whether `recovery_token` can be linked to the user of `primary_token`.
+/// `existing` is the user's current recovery token and `taken` is whether the recovery token
+/// already belongs to another user, either as their primary or their recovery token.
+pub fn plan_recovery_link(
+    primary_token: &str,
+    recovery_token: &str,
+    existing: Option<&str>,
+    taken: bool,
+) -> Result<RecoveryLink, MyError> {
+    if recovery_token == primary_token {
+        return Err(MyError::BadRequest(
+            "The recovery credential has to be different from the credential you're using",
+        ));
+    }
+    if taken && existing != Some(recovery_token) {
+        return Err(MyError::BadRequest(
+            "These credentials are already in use by another account",
+        ));
+    }
+
+    Ok(match existing {
+        Some(_) => RecoveryLink::Replaced,
+        None => RecoveryLink::Created,
+    })
+}
+
+#[test]
+fn recovery_credentials_resolve_to_the_primary_row() {
+    assert_eq!(
+        resolve_credential("primary", None),
+        ("primary".to_owned(), Credential::Primary)
+    );
+    assert_eq!(
+        resolve_credential("recovery", Some("primary".to_owned())),
+        ("primary".to_owned(), Credential::Recovery)
+    );
+}
+
+#[test]
+fn linking_replaces_the_single_recovery_credential() {
+    assert_eq!(
+        plan_recovery_link("primary", "recovery", None, false).ok(),
+        Some(RecoveryLink::Created)
+    );
+    // linking again keeps the user at one recovery credential
+    assert_eq!(
+        plan_recovery_link("primary", "new-recovery", Some("recovery"), false).ok(),
+        Some(RecoveryLink::Replaced)
+    );
+    assert_eq!(
+        plan_recovery_link("primary", "recovery", Some("recovery"), true).ok(),
+        Some(RecoveryLink::Replaced)
+    );
+
+    assert!(plan_recovery_link("primary", "primary", None, false).is_err());
+    assert!(plan_recovery_link("primary", "someone-else", Some("recovery"), true).is_err());
+}
@@ -0,0 +1,363 @@
+use actix_web::{web::Bytes, HttpResponse};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    models::ClockSkewResponse,
+    ttl_map::TtlMap,
+    utilities::{hex_decode, hex_encode},
+};
+
+pub const TIMESTAMP_HEADER: &str = "X-Signature-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+/// a clock can be wrong by a lot, but not by more than a year, those timestamps are broken
+pub const MAX_PLAUSIBLE_SKEW: i64 = 365 * 24 * 60 * 60;
+/// the upper bounds in seconds of the skew histogram's buckets, larger skews go in a last bucket
+pub const SKEW_BUCKETS: [u64; 8] = [1, 5, 30, 60, 300, 900, 3600, 86400];
+/// replays are remembered by their signature, signatures that don't fit are only checked by time
+const MAX_SEEN_SIGNATURES: usize = 100_000;
+
+static SKEW_HISTOGRAM: OnceLock<Mutex<SkewHistogram>> = OnceLock::new();
+static SEEN_SIGNATURES: OnceLock<Mutex<TtlMap<String, ()>>> = OnceLock::new();
+
+#[derive(Debug, PartialEq)]
+pub enum SignatureError {
+    Missing,
+    /// the timestamp isn't a number or it's further off than any clock could be
+    Malformed,
+    Invalid,
+    /// the signature is right, only the timestamp is outside the window
+    Skewed {
+        skew_seconds: i64,
+        server_time: i64,
+    },
+    Replayed,
+}
+
+impl SignatureError {
+    pub fn into_error(self) -> actix_web::Error {
+        match self {
+            SignatureError::Missing => actix_web::error::ErrorUnauthorized(
+                "This request has to be signed with X-Signature-Timestamp and X-Signature",
+            ),
+            SignatureError::Malformed => {
+                actix_web::error::ErrorBadRequest("Invalid X-Signature-Timestamp header")
+            }
+            SignatureError::Invalid => actix_web::error::ErrorUnauthorized("Invalid signature"),
+            SignatureError::Skewed {
+                skew_seconds,
+                server_time,
+            } => {
+                let message = "The request's timestamp is too far from the server's time, check your device's clock";
+                actix_web::error::InternalError::from_response(
+                    message,
+                    HttpResponse::Unauthorized().json(ClockSkewResponse {
+                        message: message.to_owned(),
+                        skew_seconds,
+                        server_time,
+                    }),
+                )
+                .into()
+            }
+            SignatureError::Replayed => {
+                actix_web::error::ErrorUnauthorized("This request was already received")
+            }
+        }
+    }
+}
+
+/// The parts of a request its signature covers, taken from the request before it's handled.
+pub struct SignedRequest<'a> {
+    pub timestamp: Option<&'a str>,
+    pub signature: Option<&'a str>,
+    pub method: &'a str,
+    pub path: &'a str,
+    pub body: &'a [u8],
+}
+
+impl SignedRequest<'_> {
+    /// Checks the signature and returns how far the client's clock is ahead of `server_time`, the
+    /// caller decides whether that's within the window. The timestamp's sanity bound is checked
+    /// first so a broken timestamp isn't reported as a skew.
+    pub fn verify(&self, secret: &str, server_time: i64) -> Result<i64, SignatureError> {
+        let (timestamp, signature) = match (self.timestamp, self.signature) {
+            (Some(timestamp), Some(signature)) => (timestamp, signature),
+            _ => return Err(SignatureError::Missing),
+        };
+        let skew_seconds = timestamp
+            .parse::<i64>()
+            .map_err(|_| SignatureError::Malformed)?
+            .checked_sub(server_time)
+            .filter(|skew| skew.abs() <= MAX_PLAUSIBLE_SKEW)
+            .ok_or(SignatureError::Malformed)?;
+
+        // verify_slice compares in constant time
+        let signature = hex_decode(signature).ok_or(SignatureError::Invalid)?;
+        request_mac(secret, timestamp, self.method, self.path, self.body)
+            .verify_slice(&signature)
+            .map_err(|_| SignatureError::Invalid)?;
+
+        Ok(skew_seconds)
+    }
+}
+
+/// the HMAC-SHA256 of `"{timestamp}\n{method}\n{path}\n{body}"`, the path includes the query
+fn request_mac(
+    secret: &str,
+    timestamp: &str,
+    method: &str,
+    path: &str,
+    body: &[u8],
+) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b"\n");
+    mac.update(method.as_bytes());
+    mac.update(b"\n");
+    mac.update(path.as_bytes());
+    mac.update(b"\n");
+    mac.update(body);
+
+    mac
+}
+
+pub fn sign_request(
+    secret: &str,
+    timestamp: &str,
+    method: &str,
+    path: &str,
+    body: &[u8],
+) -> String {
+    hex_encode(
+        &request_mac(secret, timestamp, method, path, body)
+            .finalize()
+            .into_bytes(),
+    )
+}
+
+/// rejects a correctly signed request whose timestamp is more than `window` seconds off
+pub fn check_window(
+    skew_seconds: i64,
+    window: u64,
+    server_time: i64,
+) -> Result<(), SignatureError> {
+    if skew_seconds.unsigned_abs() > window {
+        return Err(SignatureError::Skewed {
+            skew_seconds,
+            server_time,
+        });
+    }
+
+    Ok(())
+}
+
+/// A signature is accepted once, it's remembered for twice the window since a timestamp can be
+/// that far off in either direction.
+pub fn seen_signatures(window: u64) -> &'static Mutex<TtlMap<String, ()>> {
+    SEEN_SIGNATURES.get_or_init(|| Mutex::new(TtlMap::new(Duration::from_secs(window * 2))))
+}
+
+pub fn remember_signature(
+    seen: &Mutex<TtlMap<String, ()>>,
+    signature: &str,
+    now: Instant,
+) -> Result<(), SignatureError> {
+    let mut seen = seen.lock().unwrap();
+    let signature = signature.to_lowercase();
+    if seen.contains_key(&signature, now) {
+        return Err(SignatureError::Replayed);
+    }
+    if seen.len() >= MAX_SEEN_SIGNATURES {
+        seen.purge_expired(now);
+        if seen.len() >= MAX_SEEN_SIGNATURES {
+            return Ok(());
+        }
+    }
+    seen.insert(signature, (), now);
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct ClockSkewStats {
+    pub window_seconds: u64,
+    pub buckets: Vec<SkewBucket>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SkewBucket {
+    /// the bucket's upper bound, `None` for the last bucket
+    pub le_seconds: Option<u64>,
+    /// requests whose clock was ahead of the server's
+    pub ahead: u64,
+    /// requests whose clock was behind the server's or matched it
+    pub behind: u64,
+}
+
+/// How far the clocks of correctly signed requests were off, for tuning the window.
+#[derive(Default)]
+pub struct SkewHistogram {
+    ahead: [u64; SKEW_BUCKETS.len() + 1],
+    behind: [u64; SKEW_BUCKETS.len() + 1],
+}
+
+impl SkewHistogram {
+    pub fn record(&mut self, skew_seconds: i64) {
+        let bucket = SKEW_BUCKETS
+            .iter()
+            .position(|bound| skew_seconds.unsigned_abs() <= *bound)
+            .unwrap_or(SKEW_BUCKETS.len());
+        if skew_seconds > 0 {
+            self.ahead[bucket] += 1;
+        } else {
+            self.behind[bucket] += 1;
+        }
+    }
+
+    pub fn buckets(&self) -> Vec<SkewBucket> {
+        (0..=SKEW_BUCKETS.len())
+            .map(|bucket| SkewBucket {
+                le_seconds: SKEW_BUCKETS.get(bucket).copied(),
+                ahead: self.ahead[bucket],
+                behind: self.behind[bucket],
+            })
+            .collect()
+    }
+}
+
+pub fn skew_histogram() -> &'static Mutex<SkewHistogram> {
+    SKEW_HISTOGRAM.get_or_init(|| Mutex::new(SkewHistogram::default()))
+}
+
+/// the body has to be read for the signature, the handler gets it back through this
+pub fn body_payload(body: Bytes) -> actix_web::dev::Payload {
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(body);
+
+    payload.into()
+}
+
+#[cfg(test)]
+fn signed<'a>(timestamp: &'a str, signature: &'a str) -> SignedRequest<'a> {
+    SignedRequest {
+        timestamp: Some(timestamp),
+        signature: Some(signature),
+        method: "POST",
+        path: "/userdata?player_id=1",
+        body: b"{}",
+    }
+}
+
+#[test]
+fn in_window_skews_are_accepted() {
+    let signature = sign_request("secret", "1000030", "POST", "/userdata?player_id=1", b"{}");
+    let skew = signed("1000030", &signature).verify("secret", 1_000_000);
+
+    assert_eq!(skew, Ok(30));
+    assert_eq!(check_window(30, 300, 1_000_000), Ok(()));
+    assert_eq!(check_window(-300, 300, 1_000_000), Ok(()));
+}
+
+#[test]
+fn skews_past_the_window_are_rejected_with_the_skew() {
+    let signature = sign_request("secret", "999400", "POST", "/userdata?player_id=1", b"{}");
+    let skew = signed("999400", &signature)
+        .verify("secret", 1_000_000)
+        .unwrap();
+
+    assert_eq!(skew, -600);
+    assert_eq!(
+        check_window(skew, 300, 1_000_000),
+        Err(SignatureError::Skewed {
+            skew_seconds: -600,
+            server_time: 1_000_000
+        })
+    );
+}
+
+#[test]
+fn implausible_skews_are_malformed() {
+    let timestamp = (1_000_000 + MAX_PLAUSIBLE_SKEW + 1).to_string();
+    let signature = sign_request("secret", &timestamp, "POST", "/userdata?player_id=1", b"{}");
+
+    assert_eq!(
+        signed(&timestamp, &signature).verify("secret", 1_000_000),
+        Err(SignatureError::Malformed)
+    );
+    assert_eq!(
+        signed("yesterday", &signature).verify("secret", 1_000_000),
+        Err(SignatureError::Malformed)
+    );
+    assert_eq!(
+        signed("-9223372036854775808", &signature).verify("secret", 1_000_000),
+        Err(SignatureError::Malformed)
+    );
+}
+
+#[test]
+fn wrong_signatures_are_not_reported_as_skew() {
+    let signature = sign_request("other", "999400", "POST", "/userdata?player_id=1", b"{}");
+
+    assert_eq!(
+        signed("999400", &signature).verify("secret", 1_000_000),
+        Err(SignatureError::Invalid)
+    );
+    let signature = sign_request("secret", "1000000", "POST", "/userdata?player_id=1", b"{}");
+    let tampered = SignedRequest {
+        body: b"{\"metabits\":1}",
+        ..signed("1000000", &signature)
+    };
+    assert_eq!(
+        tampered.verify("secret", 1_000_000),
+        Err(SignatureError::Invalid)
+    );
+    assert_eq!(
+        SignedRequest {
+            signature: None,
+            ..signed("1000000", "")
+        }
+        .verify("secret", 1_000_000),
+        Err(SignatureError::Missing)
+    );
+}
+
+#[test]
+fn signatures_are_accepted_once() {
+    let seen = Mutex::new(TtlMap::new(Duration::from_secs(600)));
+    let now = Instant::now();
+
+    assert_eq!(remember_signature(&seen, "abc", now), Ok(()));
+    assert_eq!(
+        remember_signature(&seen, "ABC", now + Duration::from_secs(599)),
+        Err(SignatureError::Replayed)
+    );
+    assert_eq!(
+        remember_signature(&seen, "abc", now + Duration::from_secs(600)),
+        Ok(())
+    );
+}
+
+#[test]
+fn skews_are_bucketed_by_size_and_direction() {
+    let mut histogram = SkewHistogram::default();
+    histogram.record(0);
+    histogram.record(3);
+    histogram.record(-3);
+    histogram.record(-600);
+    histogram.record(MAX_PLAUSIBLE_SKEW);
+
+    let buckets = histogram.buckets();
+    assert_eq!(buckets.len(), SKEW_BUCKETS.len() + 1);
+    assert_eq!((buckets[0].ahead, buckets[0].behind), (0, 1));
+    assert_eq!((buckets[1].ahead, buckets[1].behind), (1, 1));
+    assert_eq!(buckets[5].le_seconds, Some(900));
+    assert_eq!((buckets[5].ahead, buckets[5].behind), (0, 1));
+    assert_eq!(buckets[8].le_seconds, None);
+    assert_eq!(buckets[8].ahead, 1);
+}
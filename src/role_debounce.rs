@@ -0,0 +1,189 @@
+use actix_web::{
+    rt::{self, time},
+    web,
+};
+use deadpool_postgres::Pool;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use crate::{config::Config, handlers::evaluate_deferred_roles};
+
+/// how often the pending evaluations are checked for ones that are due
+const DEBOUNCE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// a user who never stops syncing is still evaluated after this many windows
+const MAX_DEBOUNCE_WINDOWS: u32 = 5;
+
+static PENDING_EVALUATIONS: OnceLock<Mutex<PendingEvaluations>> = OnceLock::new();
+
+/// The roles of a user that still have to be worked out. They're evaluated against the user's
+/// stored data once due, it may have changed since the syncs that asked for them.
+pub struct PendingEvaluation {
+    pub discord_id: String,
+    /// whether any of the merged syncs asked to reconcile
+    pub reconcile: bool,
+    first_requested: Instant,
+    due: Instant,
+}
+
+/// Role evaluations of syncs that are waiting for the user to stop syncing, so a player that
+/// crosses several thresholds in a row gets one Discord evaluation instead of one per sync.
+#[derive(Default)]
+pub struct PendingEvaluations {
+    pending: HashMap<String, PendingEvaluation>,
+}
+
+impl PendingEvaluations {
+    /// Records a sync's evaluation `window` from now. A sync within the window of a pending one
+    /// pushes it back, returns whether it was merged like that.
+    pub fn request(
+        &mut self,
+        discord_id: &str,
+        reconcile: bool,
+        window: Duration,
+        now: Instant,
+    ) -> bool {
+        match self.pending.get_mut(discord_id) {
+            Some(pending) => {
+                let latest = pending.first_requested + window * MAX_DEBOUNCE_WINDOWS;
+                pending.due = (now + window).min(latest);
+                pending.reconcile |= reconcile;
+                true
+            }
+            None => {
+                self.pending.insert(
+                    discord_id.to_owned(),
+                    PendingEvaluation {
+                        discord_id: discord_id.to_owned(),
+                        reconcile,
+                        first_requested: now,
+                        due: now + window,
+                    },
+                );
+                false
+            }
+        }
+    }
+
+    /// takes the evaluations whose window ran out
+    pub fn take_due(&mut self, now: Instant) -> Vec<PendingEvaluation> {
+        let due = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.due <= now)
+            .map(|(discord_id, _)| discord_id.clone())
+            .collect::<Vec<String>>();
+
+        due.iter()
+            .filter_map(|discord_id| self.pending.remove(discord_id))
+            .collect()
+    }
+
+    /// drops the user's pending evaluation, e.g. when they're deleted
+    pub fn cancel(&mut self, discord_id: &str) {
+        self.pending.remove(discord_id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+pub fn pending_evaluations() -> &'static Mutex<PendingEvaluations> {
+    PENDING_EVALUATIONS.get_or_init(|| Mutex::new(PendingEvaluations::default()))
+}
+
+/// runs the debounced role evaluations once they're due, the queue is only filled when
+/// `ROLE_DEBOUNCE_SECONDS` is set
+pub fn spawn_debounce_worker(pool: Pool, config: web::Data<Config>) {
+    rt::spawn(async move {
+        let mut interval = time::interval(DEBOUNCE_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let due = pending_evaluations()
+                .lock()
+                .unwrap()
+                .take_due(Instant::now());
+            for evaluation in due {
+                let _ = evaluate_deferred_roles(&pool, &config, evaluation).await;
+            }
+        }
+    });
+}
+
+#[test]
+fn rapid_syncs_are_evaluated_once() {
+    let start = Instant::now();
+    let window = Duration::from_secs(60);
+    let mut pending = PendingEvaluations::default();
+
+    assert!(!pending.request("1", false, window, start));
+    assert!(pending.request("1", false, window, start + Duration::from_secs(20)));
+    assert!(pending.request("1", false, window, start + Duration::from_secs(40)));
+    assert_eq!(pending.len(), 1);
+
+    // the last sync pushed the evaluation back
+    assert!(pending.take_due(start + window).is_empty());
+    let due = pending.take_due(start + Duration::from_secs(100));
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].discord_id, "1");
+    assert!(pending.is_empty());
+}
+
+#[test]
+fn users_are_debounced_separately() {
+    let start = Instant::now();
+    let window = Duration::from_secs(60);
+    let mut pending = PendingEvaluations::default();
+
+    pending.request("1", false, window, start);
+    pending.request("2", true, window, start + Duration::from_secs(30));
+
+    let due = pending.take_due(start + window);
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].discord_id, "1");
+    let due = pending.take_due(start + Duration::from_secs(90));
+    assert_eq!(due.len(), 1);
+    assert!(due[0].reconcile);
+}
+
+#[test]
+fn users_that_keep_syncing_are_still_evaluated() {
+    let start = Instant::now();
+    let window = Duration::from_secs(60);
+    let mut pending = PendingEvaluations::default();
+
+    pending.request("1", true, window, start);
+    // a sync every 30 seconds never lets the window run out
+    let evaluated_at = (1..=20)
+        .map(|sync| start + Duration::from_secs(30 * sync))
+        .find(|now| {
+            pending.request("1", false, window, *now);
+            let due = pending.take_due(*now);
+            // a reconcile that was asked for along the way isn't lost
+            due.iter().all(|evaluation| evaluation.reconcile) && !due.is_empty()
+        });
+
+    assert_eq!(evaluated_at, Some(start + window * MAX_DEBOUNCE_WINDOWS));
+}
+
+#[test]
+fn cancelled_evaluations_never_come_due() {
+    let start = Instant::now();
+    let window = Duration::from_secs(60);
+    let mut pending = PendingEvaluations::default();
+
+    pending.request("1", true, window, start);
+    pending.request("2", false, window, start);
+    pending.cancel("1");
+
+    let due = pending.take_due(start + window);
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].discord_id, "2");
+}
@@ -1,225 +1,1158 @@
-use crate::constants::{
-    persistent_roles, roles, BeyondRequirements, MetabitRequirements, PaleoRequirements,
-    SimulationRequirements, C2SGUILD,
-};
+use crate::budget::RequestBudget;
+use crate::constants::LOG;
+use crate::constants::{roles, C2SGUILD};
+use crate::digest::unix_now;
+use crate::discord_pause::{ensure_discord_available, record_discord_error};
 use crate::errors::{InternalErrorConverter, MyError};
-use crate::models::UserData;
-use twilight_http::Client;
+use crate::fields::DEPRECATED_FIELDS;
+use crate::guild_role_cache::{
+    guild_roles_cache, refresh_shared_guild_roles, DiscordGuild, MIN_REFRESH_INTERVAL,
+};
+use crate::missing_roles::{missing_roles, report_missing_roles};
+use crate::models::PromoRoleRule;
+use crate::models::{GuildRoles, UserData, WithheldReason, WithheldRole};
+use crate::nicknames::{tiered_nickname, MemberNickname};
+use crate::promo_roles::promo_rules;
+use crate::role_pauses::{role_pauses, RolePauses};
+use crate::role_rules::{
+    compute_earned_roles_with_trace, deprecated_field_roles, ClosestMiss, RoleRule,
+};
+use crate::sync_timings::{SyncStage, SyncTimings};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::time::{Instant, SystemTime};
+use twilight_http::{error::ErrorType, Client};
 use twilight_model::id::marker::{GuildMarker, RoleMarker, UserMarker};
-use twilight_model::{guild::Member, id::Id};
+use twilight_model::id::Id;
+
+/// the partial state reported when the budget runs out before the roles were updated
+const ROLES_NOT_UPDATED: &str =
+    "Your progress was saved, but there wasn't enough time left to update your roles, please sync again";
+
+/// Discord's JSON error code for a role that doesn't exist
+const UNKNOWN_ROLE_CODE: u64 = 10011;
+
+/// Discord's JSON error code for changing a member that ranks above the bot, like the guild owner
+const MISSING_PERMISSIONS_CODE: u64 = 50013;
+
+/// a milestone role that the user's progress qualifies for
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct EarnedRole {
+    pub id: u64,
+    pub name: Cow<'static, str>,
+    /// the promo rule the role comes from, these roles are taken away again once the rule ends
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub promo_rule: Option<i64>,
+}
+
+/// every milestone role that can be earned through progress
+pub const MILESTONE_ROLES: [EarnedRole; 12] = [
+    apply_a_role(roles::REALITY_EXPLORER, "Reality Explorer"),
+    apply_a_role(roles::REALITY_EXPERT, "Reality Expert"),
+    apply_a_role(roles::REALITY_LEGEND, "Reality Legend"),
+    apply_a_role(roles::PALEONTOLOGIST, "Paleontologist"),
+    apply_a_role(
+        roles::PROGRESSIVE_PALEONTOLOGIST,
+        "Progressive Paleontologist",
+    ),
+    apply_a_role(roles::PALEONTOLOGIST_LEGEND, "Paleontologist Legend"),
+    apply_a_role(roles::PLANETARY_EXPLORER, "Planetary Explorer"),
+    apply_a_role(roles::SIMULATION_SPEEDSTER, "Simulation Speedster"),
+    apply_a_role(
+        roles::SONIC_SPEEDSTER_OF_SIMULATIONS,
+        "Sonic Speedster of Simulations",
+    ),
+    apply_a_role(roles::SHARK_COLLECTOR, "Shark Collector"),
+    apply_a_role(
+        roles::FINDER_OF_SEMBLANCE_SECRETS,
+        "Finder of Semblance's Secrets",
+    ),
+    apply_a_role(roles::BETA_TESTER, "Beta Tester"),
+];
+
+/// the roles a member ends up with after a sync
+#[derive(Debug, PartialEq)]
+pub struct ReconciledRoles {
+    /// every role the member should have, this replaces their current roles
+    pub applied: Vec<u64>,
+    /// the earned roles the member didn't have yet
+    pub gained: Vec<EarnedRole>,
+    /// the managed roles the member doesn't qualify for anymore, only taken away when reconciling
+    pub removed: Vec<EarnedRole>,
+    /// the managed roles the member has after the update
+    pub held: Vec<u64>,
+}
+
+/// what a sync does with the managed roles a member has but doesn't qualify for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoleSyncMode {
+    /// roles are only ever added
+    Additive,
+    /// they're taken away as well, e.g. after inflated progress was corrected
+    Reconcile,
+}
+
+impl RoleSyncMode {
+    pub fn reconciling(reconcile: bool) -> RoleSyncMode {
+        if reconcile {
+            RoleSyncMode::Reconcile
+        } else {
+            RoleSyncMode::Additive
+        }
+    }
+}
+
+/// Works out the member's new roles, roles outside `managed` (like the persistent ones) are never
+/// touched. Paused roles are neither granted nor taken away from members that already have them,
+/// neither are `deprecated_roles`, whose rules read a field the game doesn't have anymore.
+pub fn reconcile_roles(
+    earned_roles: &[EarnedRole],
+    member_roles: &[u64],
+    managed: &[EarnedRole],
+    mode: RoleSyncMode,
+    pauses: &RolePauses,
+    deprecated_roles: &[u64],
+    now: u64,
+) -> ReconciledRoles {
+    let grantable_roles = earned_roles
+        .iter()
+        .filter(|role| !pauses.is_paused(role.id, now))
+        .collect::<Vec<&EarnedRole>>();
+
+    let gained = grantable_roles
+        .iter()
+        .filter(|role| !member_roles.contains(&role.id))
+        .map(|role| (*role).clone())
+        .collect::<Vec<EarnedRole>>();
+
+    let mut removed: Vec<EarnedRole> = Vec::new();
+    if mode == RoleSyncMode::Reconcile {
+        for role in managed {
+            let lost = member_roles.contains(&role.id)
+                && grantable_roles.iter().all(|earned| earned.id != role.id)
+                && !pauses.is_paused(role.id, now)
+                && !deprecated_roles.contains(&role.id)
+                && removed.iter().all(|taken| taken.id != role.id);
+            if lost {
+                removed.push(role.clone());
+            }
+        }
+    }
+
+    let mut applied = member_roles
+        .iter()
+        .copied()
+        .filter(|role_id| removed.iter().all(|role| role.id != *role_id))
+        .collect::<Vec<u64>>();
+    applied.extend(gained.iter().map(|role| role.id));
+    let held = applied
+        .iter()
+        .copied()
+        .filter(|role_id| managed.iter().any(|role| role.id == *role_id))
+        .collect();
+
+    ReconciledRoles {
+        applied,
+        gained,
+        removed,
+        held,
+    }
+}
+
+/// the outcome of updating a member's roles
+#[derive(Debug, Default, PartialEq)]
+pub struct RoleSync {
+    pub gained: Vec<EarnedRole>,
+    pub withheld: Vec<WithheldRole>,
+    /// roles that don't exist in the guild anymore, including ones the member wasn't going to gain
+    pub missing: Vec<u64>,
+    /// the managed roles the member has after the update, persistent roles aren't included
+    pub held: Vec<u64>,
+    /// the managed roles that were taken away when reconciling
+    pub removed: Vec<EarnedRole>,
+}
+
+pub enum RoleUpdateError {
+    /// Discord doesn't know one of the roles
+    UnknownRole,
+    Failed(MyError),
+}
+
+pub enum NicknameUpdateError {
+    /// the member ranks above the bot, Discord never lets it change the guild owner's nickname
+    MissingPermissions,
+    Failed(MyError),
+}
+
+/// the Discord calls of a role update, so the handling of missing roles can be tested without Discord
+#[async_trait]
+pub trait MemberRoleUpdater {
+    /// replaces the member's roles with `role_ids`
+    async fn set_roles(&self, role_ids: &[u64]) -> Result<(), RoleUpdateError>;
+    /// every role that exists in the guild
+    async fn guild_roles(&self) -> Result<Vec<u64>, MyError>;
+    /// changes the member's nickname, `None` clears it
+    async fn set_nickname(&self, nickname: Option<&str>) -> Result<(), NicknameUpdateError>;
+}
+
+struct DiscordMember<'a> {
+    client: &'a Client,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+}
+
+#[async_trait]
+impl MemberRoleUpdater for DiscordMember<'_> {
+    async fn set_roles(&self, role_ids: &[u64]) -> Result<(), RoleUpdateError> {
+        let role_ids = role_ids
+            .iter()
+            .map(|role_id| Id::<RoleMarker>::new(*role_id))
+            .collect::<Vec<Id<RoleMarker>>>();
+
+        ensure_discord_available()
+            .await
+            .map_err(RoleUpdateError::Failed)?;
+        let result = self
+            .client
+            .update_guild_member(self.guild_id, self.user_id)
+            .roles(role_ids.as_slice())
+            .exec()
+            .await;
+        if matches!(&result, Err(error) if has_error_code(error, UNKNOWN_ROLE_CODE)) {
+            return Err(RoleUpdateError::UnknownRole);
+        }
+        let updated_member_data = discord_call(result, "failed at updating member roles")
+            .await
+            .map_err(RoleUpdateError::Failed)?;
+        updated_member_data
+            .model()
+            .await
+            .make_internal_error("failed at parsing member data model")
+            .map_err(RoleUpdateError::Failed)?;
+
+        Ok(())
+    }
+
+    async fn guild_roles(&self) -> Result<Vec<u64>, MyError> {
+        // Discord just refused a role the cache may still have, so it's refreshed unless that
+        // happened moments ago
+        let refreshed =
+            refresh_shared_guild_roles(&DiscordGuild::new(self.client), MIN_REFRESH_INTERVAL).await;
+        let cache = guild_roles_cache().lock().unwrap();
+        match (refreshed, cache.refreshed_at()) {
+            (Err(error), None) => Err(error),
+            _ => Ok(cache.role_ids()),
+        }
+    }
+
+    async fn set_nickname(&self, nickname: Option<&str>) -> Result<(), NicknameUpdateError> {
+        ensure_discord_available()
+            .await
+            .map_err(NicknameUpdateError::Failed)?;
+        let result = self
+            .client
+            .update_guild_member(self.guild_id, self.user_id)
+            .nick(nickname)
+            .make_internal_error("failed at validating the nickname")
+            .map_err(NicknameUpdateError::Failed)?
+            .exec()
+            .await;
+        if matches!(&result, Err(error) if has_error_code(error, MISSING_PERMISSIONS_CODE)) {
+            return Err(NicknameUpdateError::MissingPermissions);
+        }
+        discord_call(result, "failed at updating the member's nickname")
+            .await
+            .map_err(NicknameUpdateError::Failed)?;
+
+        Ok(())
+    }
+}
+
+/// whether Discord answered with the JSON error `code`, e.g. "Unknown Role" for a role that's being
+/// applied but was deleted from the guild
+fn has_error_code(error: &twilight_http::Error, code: u64) -> bool {
+    match error.kind() {
+        ErrorType::Response { body, .. } => serde_json::from_slice::<serde_json::Value>(body)
+            .ok()
+            .and_then(|body| body.get("code")?.as_u64())
+            .map_or(false, |body_code| body_code == code),
+        _ => false,
+    }
+}
+
+/// Applies the reconciled roles. When Discord doesn't know one of them, the roles that were deleted
+/// from the guild are left out and the rest is applied, instead of failing the whole sync.
+pub async fn apply_member_roles(
+    updater: &impl MemberRoleUpdater,
+    reconciled: ReconciledRoles,
+) -> Result<RoleSync, MyError> {
+    let ReconciledRoles {
+        mut applied,
+        mut gained,
+        removed,
+        mut held,
+    } = reconciled;
+
+    let missing = match updater.set_roles(&applied).await {
+        Ok(()) => Vec::new(),
+        Err(RoleUpdateError::Failed(error)) => return Err(error),
+        Err(RoleUpdateError::UnknownRole) => {
+            let guild_roles = updater.guild_roles().await?;
+            let missing = applied
+                .iter()
+                .filter(|role_id| !guild_roles.contains(role_id))
+                .copied()
+                .collect::<Vec<u64>>();
+            if missing.is_empty() {
+                return Err(MyError::InternalError("failed at updating member roles"));
+            }
+
+            applied.retain(|role_id| !missing.contains(role_id));
+            match updater.set_roles(&applied).await {
+                Ok(()) => missing,
+                Err(RoleUpdateError::Failed(error)) => return Err(error),
+                Err(RoleUpdateError::UnknownRole) => {
+                    return Err(MyError::InternalError("failed at updating member roles"))
+                }
+            }
+        }
+    };
 
+    let withheld = gained
+        .iter()
+        .filter(|role| missing.contains(&role.id))
+        .map(|role| WithheldRole {
+            id: role.id.to_string(),
+            name: role.name.to_string(),
+            reason: WithheldReason::RoleMissing,
+        })
+        .collect();
+    gained.retain(|role| !missing.contains(&role.id));
+    held.retain(|role_id| !missing.contains(role_id));
+
+    Ok(RoleSync {
+        gained,
+        withheld,
+        missing,
+        held,
+        removed,
+    })
+}
+
+/// Changes the member's nickname to the one of the tier their held roles put them in, nothing is
+/// sent when it's already the right one. Members Discord doesn't let the bot rename, like the guild
+/// owner, keep their nickname without failing the sync.
+pub async fn apply_member_nickname(
+    updater: &impl MemberRoleUpdater,
+    nick: Option<&str>,
+    username: &str,
+    nickname: &MemberNickname<'_>,
+    held_roles: &[u64],
+) -> Result<(), MyError> {
+    let tier = if nickname.prefixed {
+        nickname.policy.member_tier(held_roles)
+    } else {
+        None
+    };
+    let tiered = tiered_nickname(nick, username, &nickname.policy.tiers, tier);
+    if tiered.as_deref() == nick {
+        return Ok(());
+    }
+
+    match updater.set_nickname(tiered.as_deref()).await {
+        Ok(()) | Err(NicknameUpdateError::MissingPermissions) => Ok(()),
+        Err(NicknameUpdateError::Failed(error)) => Err(error),
+    }
+}
+
+/// what the roles of a sync are decided by besides the progress
+pub struct SyncRoles<'a> {
+    pub role_rules: &'a [RoleRule],
+    /// earned on top of the roles the progress earns, see `sync_streaks`
+    pub streak_roles: Vec<EarnedRole>,
+    /// every role our rules grant, see `role_removal::managed_roles`
+    pub managed: Vec<EarnedRole>,
+    pub mode: RoleSyncMode,
+}
+
+/// The nickname is only touched when the guild's nickname policy is given and enabled.
 pub async fn handle_roles(
     user_data: &UserData,
+    sync_roles: SyncRoles<'_>,
+    nickname: Option<MemberNickname<'_>>,
     discord_token: String,
-) -> Result<Vec<&'static str>, MyError> {
-    let mut gained_roles: Vec<&'static str> = Vec::new();
-    let client = Client::new(discord_token);
+    budget: &RequestBudget,
+    timings: &mut SyncTimings,
+) -> Result<RoleSync, MyError> {
+    let mut client = Client::builder().token(discord_token);
+    if let Some(remaining) = budget.ensure_remaining(Instant::now(), ROLES_NOT_UPDATED)? {
+        client = client.timeout(remaining);
+    }
+    let client = client.build();
     let guild_id = Id::<GuildMarker>::new(C2SGUILD);
     let user_id = Id::<UserMarker>::new(
         str::parse::<u64>(&user_data.discord_id)
             .make_internal_error("parsing discord id failed")?,
     );
 
-    let member_data = client
-        .guild_member(guild_id, user_id)
-        .exec()
-        .await
-        .make_internal_error(
+    let discord_started = Instant::now();
+    ensure_discord_available().await?;
+    let member_data = discord_call(
+        client.guild_member(guild_id, user_id).exec().await,
         "failed retrieving member data (this usually occurs when you're not in the Discord server)",
-    )?;
+    )
+    .await?;
     let member_data = member_data
         .model()
         .await
         .make_internal_error("failed at parsing the member data to a Member struct")?;
+    timings.record(SyncStage::Discord, discord_started.elapsed());
 
-    let mut gained_metabit_roles = handle_metabit_roles(&mut gained_roles, &member_data, user_data);
-    let mut gained_paleo_roles = handle_paleo_roles(&mut gained_roles, &member_data, user_data);
-    let mut gained_beyond_roles = handle_beyond_roles(&mut gained_roles, &member_data, user_data);
-    let mut gained_simulation_roles =
-        handle_simulation_roles(&mut gained_roles, &member_data, user_data);
-
-    let mut applyable_roles = persistent_roles::PERSISTENT_ROLES
-        .into_iter()
-        .map(Id::<RoleMarker>::new)
-        .filter(|role| member_data.roles.contains(role))
-        .collect::<Vec<Id<RoleMarker>>>();
-
-    applyable_roles.append(&mut gained_metabit_roles);
-    applyable_roles.append(&mut gained_paleo_roles);
-    applyable_roles.append(&mut gained_beyond_roles);
-    applyable_roles.append(&mut gained_simulation_roles);
-
-    let updated_member_data = client
-        .update_guild_member(guild_id, user_id)
-        .roles(applyable_roles.as_slice())
-        .exec()
-        .await
-        .make_internal_error("failed at updating member roles")?;
-    updated_member_data
-        .model()
-        .await
-        .make_internal_error("failed at parsing member data model")?;
+    let member_roles = member_data
+        .roles
+        .iter()
+        .map(|role| role.get())
+        .collect::<Vec<u64>>();
+    let reconciled = timings.measure(SyncStage::RoleCompute, || {
+        let promo_rules = promo_rules().lock().unwrap().clone();
+        let mut earned_roles = compute_earned_roles(
+            user_data,
+            sync_roles.role_rules,
+            &promo_rules,
+            SystemTime::now(),
+        );
+        earned_roles.extend(sync_roles.streak_roles);
+        reconcile_roles(
+            &earned_roles,
+            &member_roles,
+            &sync_roles.managed,
+            sync_roles.mode,
+            &role_pauses().lock().unwrap(),
+            &deprecated_field_roles(&DEPRECATED_FIELDS, sync_roles.role_rules),
+            unix_now(),
+        )
+    });
+    let applied = reconciled.applied.clone();
+
+    budget.ensure_remaining(Instant::now(), ROLES_NOT_UPDATED)?;
+    let member = DiscordMember {
+        client: &client,
+        guild_id,
+        user_id,
+    };
+    let role_sync = timings
+        .time(SyncStage::Discord, apply_member_roles(&member, reconciled))
+        .await?;
+
+    missing_roles().lock().unwrap().clear(
+        &applied
+            .into_iter()
+            .filter(|role_id| !role_sync.missing.contains(role_id))
+            .collect::<Vec<u64>>(),
+    );
+    report_missing_roles(&role_sync.missing).await;
+
+    if let Some(nickname) = nickname.filter(|nickname| nickname.policy.enabled) {
+        timings
+            .time(
+                SyncStage::Discord,
+                apply_member_nickname(
+                    &member,
+                    member_data.nick.as_deref(),
+                    &member_data.user.name,
+                    &nickname,
+                    &role_sync.held,
+                ),
+            )
+            .await?;
+    }
 
-    Ok(gained_roles)
+    Ok(role_sync)
 }
 
-fn handle_metabit_roles(
-    gained_roles: &mut Vec<&'static str>,
-    member: &Member,
-    user_data: &UserData,
-) -> Vec<Id<RoleMarker>> {
-    // the length of the vector will always be 1 to simplify the process of combining the roles array
-    let mut applyable_roles: Vec<Id<RoleMarker>> = Vec::new();
-
-    if user_data.metabits >= MetabitRequirements::RealityLegend as i64 {
-        applyable_roles.push(apply_a_role(
-            gained_roles,
-            member,
-            roles::REALITY_LEGEND,
-            "Reality Legend",
-        ));
-    } else if user_data.metabits >= MetabitRequirements::RealityExpert as i64 {
-        applyable_roles.push(apply_a_role(
-            gained_roles,
-            member,
-            roles::REALITY_EXPERT,
-            "Reality Expert",
-        ));
-    } else if user_data.metabits >= MetabitRequirements::RealityExplorer as i64 {
-        applyable_roles.push(apply_a_role(
-            gained_roles,
-            member,
-            roles::REALITY_EXPLORER,
-            "Reality Explorer",
-        ));
-    }
-
-    applyable_roles
-}
-
-fn handle_paleo_roles(
-    gained_roles: &mut Vec<&'static str>,
-    member: &Member,
-    user_data: &UserData,
-) -> Vec<Id<RoleMarker>> {
-    // the length of the vector will always be 1 to simplify the process of combining the roles array
-    let mut applyable_roles: Vec<Id<RoleMarker>> = Vec::new();
-    let dino_prestige = (user_data.dino_rank / 50).clamp(0, 10);
-
-    if dino_prestige == PaleoRequirements::PaleontologistLegend as i32 {
-        applyable_roles.push(apply_a_role(
-            gained_roles,
-            member,
-            roles::PALEONTOLOGIST_LEGEND,
-            "Paleontologist Legend",
-        ));
-    } else if dino_prestige == PaleoRequirements::ProgressivePaleontologist as i32 {
-        applyable_roles.push(apply_a_role(
-            gained_roles,
-            member,
-            roles::PROGRESSIVE_PALEONTOLOGIST,
-            "Progressive Paleontologist",
-        ));
-    } else if user_data.dino_rank >= PaleoRequirements::Paleontologist as i32 {
-        applyable_roles.push(apply_a_role(
-            gained_roles,
-            member,
-            roles::PALEONTOLOGIST,
-            "Paleontologist",
-        ));
-    }
-
-    applyable_roles
-}
-
-fn handle_beyond_roles(
-    gained_roles: &mut Vec<&'static str>,
-    member: &Member,
-    user_data: &UserData,
-) -> Vec<Id<RoleMarker>> {
-    // the length of the vector will always be 1 to simplify the process of combining the roles array
-    let mut applyable_roles: Vec<Id<RoleMarker>> = Vec::new();
+/// the names of the roles, for responses and logs
+pub fn role_names(roles: &[EarnedRole]) -> Vec<String> {
+    roles.iter().map(|role| role.name.to_string()).collect()
+}
 
-    if user_data.beyond_rank == BeyondRequirements::PlanetaryExplorer as i32 {
-        applyable_roles.push(apply_a_role(
-            gained_roles,
-            member,
-            roles::PLANETARY_EXPLORER,
-            "Planetary Explorer",
-        ));
+/// Groups the gained roles by the guild they were granted in, guilds keep the order they're given
+/// in and guilds without gained roles are left out. Guilds without a configured name are shown
+/// with their id.
+pub fn group_by_guild(
+    gained_roles: &[(u64, &[EarnedRole])],
+    guild_names: &HashMap<u64, String>,
+) -> Vec<GuildRoles> {
+    let mut groups: Vec<GuildRoles> = Vec::new();
+    for (guild_id, roles) in gained_roles {
+        if roles.is_empty() {
+            continue;
+        }
+        let group = match groups
+            .iter()
+            .position(|group| group.guild_id == guild_id.to_string())
+        {
+            Some(index) => &mut groups[index],
+            None => {
+                groups.push(GuildRoles {
+                    guild_id: guild_id.to_string(),
+                    guild_name: guild_names
+                        .get(guild_id)
+                        .cloned()
+                        .unwrap_or_else(|| guild_id.to_string()),
+                    roles: Vec::new(),
+                });
+                groups.last_mut().unwrap()
+            }
+        };
+        group.roles.extend(role_names(roles));
     }
 
-    applyable_roles
+    groups
 }
 
-fn handle_simulation_roles(
-    gained_roles: &mut Vec<&'static str>,
-    member: &Member,
-    user_data: &UserData,
-) -> Vec<Id<RoleMarker>> {
-    // the length of the vector will always be 1 to simplify the process of combining the roles array
-    let mut applyable_roles: Vec<Id<RoleMarker>> = Vec::new();
-
-    if user_data.all_hidden_achievements_obtained {
-        applyable_roles.push(apply_a_role(
-            gained_roles,
-            member,
-            roles::FINDER_OF_SEMBLANCE_SECRETS,
-            "Finder of Semblance's Secrets",
-        ));
+/// the longest role list the in-game message shows, in characters
+pub const GAINED_ROLES_MESSAGE_LENGTH: usize = 500;
+/// the longest role list a webhook log shows, the log's coloring roughly doubles its length and
+/// Discord cuts messages off at 2000 characters
+pub const GAINED_ROLES_LOG_LENGTH: usize = 800;
+
+/// Spells out at most `listed` of `total` roles and counts the rest as "and N more", fewer are
+/// spelled out while the text is longer than `max_len` characters. `render` spells out the first
+/// `shown` roles. A single role that's too long on its own is cut off.
+pub fn cap_role_list(
+    total: usize,
+    listed: usize,
+    max_len: usize,
+    render: impl Fn(usize) -> String,
+) -> String {
+    let text = |shown: usize| match total - shown {
+        0 => render(shown),
+        hidden if shown == 0 => format!("{} roles", hidden),
+        hidden => format!("{} and {} more", render(shown), hidden),
+    };
+
+    let mut shown = listed.min(total);
+    let mut capped = text(shown);
+    while capped.chars().count() > max_len && shown > 1 {
+        shown -= 1;
+        capped = text(shown);
+    }
+
+    if capped.chars().count() > max_len {
+        capped = capped.chars().take(max_len.saturating_sub(1)).collect();
+        capped.push('…');
+    }
+    capped
+}
+
+/// `"{guild}: {role}, {role}; {guild}: {role}"` with only the first `shown` roles
+fn describe_first_guild_roles(groups: &[GuildRoles], shown: usize) -> String {
+    let mut remaining = shown;
+    let mut described = Vec::new();
+    for group in groups {
+        if remaining == 0 {
+            break;
+        }
+        let roles = &group.roles[..remaining.min(group.roles.len())];
+        remaining -= roles.len();
+        described.push(format!("{}: {}", group.guild_name, roles.join(", ")));
+    }
+
+    described.join("; ")
+}
+
+/// `"{guild}: {role}, {role}; {guild}: {role}"`
+pub fn describe_guild_roles(groups: &[GuildRoles]) -> String {
+    describe_first_guild_roles(groups, usize::MAX)
+}
+
+/// like `describe_guild_roles`, with at most `listed` roles spelled out
+pub fn describe_capped_guild_roles(groups: &[GuildRoles], listed: usize, max_len: usize) -> String {
+    let total = groups.iter().map(|group| group.roles.len()).sum();
+
+    cap_role_list(total, listed, max_len, |shown| {
+        describe_first_guild_roles(groups, shown)
+    })
+}
+
+/// the unit a field's remaining amount is named in, the field itself when it has none
+fn progress_unit(field: &str) -> &str {
+    match field {
+        "metabits" => "metabits",
+        "dino_rank" => "dino ranks",
+        "dino_prestige" => "dino prestiges",
+        "beyond_rank" => "beyond ranks",
+        "singularity_speedrun_time" => "seconds",
+        "longest_streak_weeks" => "weeks of syncing",
+        field => field,
+    }
+}
+
+/// `1234567.0` as `"1,234,567"`, partial amounts are rounded up since they still have to be made up
+fn format_amount(amount: f64) -> String {
+    let digits = (amount.ceil() as u64).to_string();
+    let mut formatted = String::with_capacity(digits.len() + digits.len() / 3);
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index) % 3 == 0 {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+
+    formatted
+}
+
+/// The message a user gets after syncing their progress, `listed` is how many roles are named.
+/// A sync without new roles names the closest role that's left, if there's any.
+pub fn gained_roles_message(
+    groups: &[GuildRoles],
+    listed: usize,
+    closest_miss: Option<&ClosestMiss>,
+) -> String {
+    if let (true, Some(miss)) = (groups.is_empty(), closest_miss) {
+        format!(
+            "The request was successful, but you haven't gained any new roles, you're {} {} away from {}",
+            format_amount(miss.remaining),
+            progress_unit(&miss.field),
+            miss.role_name
+        )
+    } else if groups.is_empty() {
+        "The request was successful, but you've already gained all of the possible roles with your current progress".to_owned()
     } else {
-        let speedrun_time = if user_data.singularity_speedrun_time.is_some() {
-            user_data.singularity_speedrun_time.unwrap()
-        } else {
-            1000.0
-        };
-        if speedrun_time <= SimulationRequirements::SonicSpeedsterOfSimulations as i32 as f64 {
-            applyable_roles.push(apply_a_role(
-                gained_roles,
-                member,
-                roles::SONIC_SPEEDSTER_OF_SIMULATIONS,
-                "Sonic Speedster of Simulations",
-            ));
-        } else if speedrun_time <= SimulationRequirements::SimulationSpeedster as i32 as f64 {
-            applyable_roles.push(apply_a_role(
-                gained_roles,
-                member,
-                roles::SIMULATION_SPEEDSTER,
-                "Simulation Speedster",
-            ));
-        }
-
-        if user_data.all_sharks_obtained {
-            applyable_roles.push(apply_a_role(
-                gained_roles,
-                member,
-                roles::SHARK_COLLECTOR,
-                "Shark Collector",
-            ));
-        }
-    }
-
-    if user_data.beta_tester {
-        applyable_roles.push(apply_a_role(
-            gained_roles,
-            member,
-            roles::BETA_TESTER,
-            "Beta Tester",
-        ));
-    }
-
-    applyable_roles
-}
-
-fn apply_a_role(
-    gained_roles: &mut Vec<&'static str>,
-    member: &Member,
-    role_id: u64,
-    role_name: &'static str,
-) -> Id<RoleMarker> {
-    let role = Id::<RoleMarker>::new(role_id);
-    if !member.roles.contains(&role) {
-        gained_roles.push(role_name);
-    }
-    role
+        format!(
+            "The request was successful, you've gained the following roles: {}",
+            describe_capped_guild_roles(groups, listed, GAINED_ROLES_MESSAGE_LENGTH)
+        )
+    }
+}
+
+/// the webhook log of a sync, `log_suffix` is appended as is
+pub fn gained_roles_log(
+    discord_id: &str,
+    groups: &[GuildRoles],
+    log_suffix: &str,
+    listed: usize,
+) -> String {
+    if groups.is_empty() {
+        format!(
+            "user with ID {} had a successful request but gained no roles{}",
+            discord_id, log_suffix
+        )
+    } else {
+        format!(
+            "user with ID {} gained the following roles: {}{}",
+            discord_id,
+            describe_capped_guild_roles(groups, listed, GAINED_ROLES_LOG_LENGTH),
+            log_suffix
+        )
+    }
+}
+
+/// the webhook log of the roles a reconciling sync took away, `log_suffix` is appended as is
+pub fn removed_roles_log(
+    discord_id: &str,
+    removed: &[EarnedRole],
+    log_suffix: &str,
+    listed: usize,
+) -> String {
+    let names = role_names(removed);
+    format!(
+        "user with ID {} lost the following roles: {}{}",
+        discord_id,
+        cap_role_list(names.len(), listed, GAINED_ROLES_LOG_LENGTH, |shown| {
+            names[..shown].join(", ")
+        }),
+        log_suffix
+    )
+}
+
+/// syncs without new roles are only informational, so they go through the log throttle
+pub fn gained_roles_log_type(groups: &[GuildRoles]) -> LOG {
+    if groups.is_empty() {
+        LOG::INFORMATIONAL
+    } else {
+        LOG::SUCCESSFUL
+    }
+}
+
+/// converts the result of a Discord call while watching out for global rate limits
+pub async fn discord_call<T>(
+    result: Result<T, twilight_http::Error>,
+    message: &'static str,
+) -> Result<T, MyError> {
+    match result {
+        Ok(value) => Ok(value),
+        Err(error) => {
+            record_discord_error(&error).await;
+            Err(MyError::InternalError(message))
+        }
+    }
+}
+
+/// Computes every milestone role the given progress qualifies for without talking to Discord,
+/// so it can be reused for dry-runs against arbitrary (e.g. historical) user states. Promo roles
+/// are only earned when `now` is inside the promo rule's window.
+pub fn compute_earned_roles(
+    user_data: &UserData,
+    role_rules: &[RoleRule],
+    promo_rules: &[PromoRoleRule],
+    now: SystemTime,
+) -> Vec<EarnedRole> {
+    // pauses don't change the earned roles, reconciling takes care of them
+    compute_earned_roles_with_trace(
+        user_data,
+        role_rules,
+        promo_rules,
+        &RolePauses::default(),
+        &DEPRECATED_FIELDS,
+        now,
+    )
+    .earned
+}
+
+const fn apply_a_role(role_id: u64, role_name: &'static str) -> EarnedRole {
+    EarnedRole {
+        id: role_id,
+        name: Cow::Borrowed(role_name),
+        promo_rule: None,
+    }
+}
+
+#[test]
+fn paused_roles_are_not_granted() {
+    let mut pauses = RolePauses::default();
+    pauses.pause(roles::REALITY_LEGEND, None);
+    let earned_roles = [
+        apply_a_role(roles::REALITY_LEGEND, "Reality Legend"),
+        apply_a_role(roles::SHARK_COLLECTOR, "Shark Collector"),
+    ];
+
+    let reconciled = reconcile_roles(
+        &earned_roles,
+        &[],
+        &MILESTONE_ROLES,
+        RoleSyncMode::Reconcile,
+        &pauses,
+        &[],
+        0,
+    );
+
+    assert_eq!(
+        reconciled,
+        ReconciledRoles {
+            applied: vec![roles::SHARK_COLLECTOR],
+            gained: vec![apply_a_role(roles::SHARK_COLLECTOR, "Shark Collector")],
+            removed: Vec::new(),
+            held: vec![roles::SHARK_COLLECTOR],
+        }
+    );
+}
+
+#[test]
+fn paused_roles_are_not_removed() {
+    use crate::constants::persistent_roles;
+
+    let mut pauses = RolePauses::default();
+    pauses.pause(roles::REALITY_LEGEND, None);
+    let member_roles = [roles::REALITY_LEGEND, persistent_roles::PERSISTENT_ROLES[0]];
+
+    // the member no longer qualifies after their metabits were corrected
+    let reconciled = reconcile_roles(
+        &[apply_a_role(roles::REALITY_EXPERT, "Reality Expert")],
+        &member_roles,
+        &MILESTONE_ROLES,
+        RoleSyncMode::Reconcile,
+        &pauses,
+        &[],
+        0,
+    );
+
+    assert_eq!(
+        reconciled,
+        ReconciledRoles {
+            applied: vec![
+                roles::REALITY_LEGEND,
+                persistent_roles::PERSISTENT_ROLES[0],
+                roles::REALITY_EXPERT
+            ],
+            gained: vec![apply_a_role(roles::REALITY_EXPERT, "Reality Expert")],
+            removed: Vec::new(),
+            held: vec![roles::REALITY_LEGEND, roles::REALITY_EXPERT],
+        }
+    );
+
+    // once resumed, the role is reconciled like any other
+    pauses.resume(roles::REALITY_LEGEND);
+    let reconciled = reconcile_roles(
+        &[],
+        &member_roles,
+        &MILESTONE_ROLES,
+        RoleSyncMode::Reconcile,
+        &pauses,
+        &[],
+        0,
+    );
+    assert_eq!(
+        reconciled.applied,
+        vec![persistent_roles::PERSISTENT_ROLES[0]]
+    );
+}
+
+#[test]
+fn additive_syncs_keep_roles_that_are_no_longer_earned() {
+    // the member's metabits were corrected from legend down to expert
+    let reconciled = reconcile_roles(
+        &[apply_a_role(roles::REALITY_EXPERT, "Reality Expert")],
+        &[roles::REALITY_LEGEND],
+        &MILESTONE_ROLES,
+        RoleSyncMode::Additive,
+        &RolePauses::default(),
+        &[],
+        0,
+    );
+
+    assert_eq!(
+        reconciled.applied,
+        vec![roles::REALITY_LEGEND, roles::REALITY_EXPERT]
+    );
+    assert!(reconciled.removed.is_empty());
+}
+
+#[test]
+fn reconciling_only_takes_away_managed_roles() {
+    // a moderator role nobody's rules grant
+    let moderator = 1_234;
+    let reconciled = reconcile_roles(
+        &[apply_a_role(roles::REALITY_EXPERT, "Reality Expert")],
+        &[moderator, roles::REALITY_LEGEND, roles::SHARK_COLLECTOR],
+        &MILESTONE_ROLES,
+        RoleSyncMode::Reconcile,
+        &RolePauses::default(),
+        &[roles::SHARK_COLLECTOR],
+        0,
+    );
+
+    assert_eq!(
+        reconciled,
+        ReconciledRoles {
+            applied: vec![moderator, roles::SHARK_COLLECTOR, roles::REALITY_EXPERT],
+            gained: vec![apply_a_role(roles::REALITY_EXPERT, "Reality Expert")],
+            removed: vec![apply_a_role(roles::REALITY_LEGEND, "Reality Legend")],
+            held: vec![roles::SHARK_COLLECTOR, roles::REALITY_EXPERT],
+        }
+    );
+}
+
+#[test]
+fn gained_roles_are_grouped_by_guild() {
+    let guild_names = HashMap::from([(1, "main server".to_owned()), (2, "beta server".to_owned())]);
+    let singularity = apply_a_role(roles::SIMULATION_SPEEDSTER, "Singularity");
+    let main_roles = [
+        apply_a_role(roles::REALITY_EXPLORER, "Reality Explorer"),
+        singularity.clone(),
+    ];
+    let beta_roles = [singularity];
+
+    let groups = group_by_guild(
+        &[(1, &main_roles), (2, &beta_roles), (3, &[])],
+        &guild_names,
+    );
+
+    assert_eq!(
+        gained_roles_message(&groups, 5, None),
+        "The request was successful, you've gained the following roles: main server: Reality Explorer, Singularity; beta server: Singularity"
+    );
+    assert_eq!(
+        gained_roles_log("10", &groups, "", 5),
+        "user with ID 10 gained the following roles: main server: Reality Explorer, Singularity; beta server: Singularity"
+    );
+    assert_eq!(groups[1].guild_id, "2");
+    assert_eq!(groups[1].roles, vec!["Singularity".to_owned()]);
+    assert_eq!(
+        describe_guild_roles(&group_by_guild(&[(3, &beta_roles)], &guild_names)),
+        "3: Singularity"
+    );
+}
+
+#[cfg(test)]
+fn guild_with_roles(names: &[&str]) -> Vec<GuildRoles> {
+    vec![GuildRoles {
+        guild_id: "1".to_owned(),
+        guild_name: "main server".to_owned(),
+        roles: names.iter().map(|name| name.to_string()).collect(),
+    }]
+}
+
+#[test]
+fn gained_role_lists_are_capped_at_the_listed_count() {
+    let names = ["A", "B", "C", "D", "E", "F", "G"];
+    let describe =
+        |count: usize| describe_capped_guild_roles(&guild_with_roles(&names[..count]), 5, 100);
+
+    assert_eq!(describe(4), "main server: A, B, C, D");
+    assert_eq!(describe(5), "main server: A, B, C, D, E");
+    assert_eq!(describe(6), "main server: A, B, C, D, E and 1 more");
+    assert_eq!(describe(7), "main server: A, B, C, D, E and 2 more");
+
+    // the cap counts roles across guilds
+    let mut groups = guild_with_roles(&names[..3]);
+    groups.push(GuildRoles {
+        guild_id: "2".to_owned(),
+        guild_name: "beta server".to_owned(),
+        roles: vec!["X".to_owned(), "Y".to_owned(), "Z".to_owned()],
+    });
+    assert_eq!(
+        describe_capped_guild_roles(&groups, 4, 100),
+        "main server: A, B, C; beta server: X and 2 more"
+    );
+    assert_eq!(describe_capped_guild_roles(&groups, 0, 100), "6 roles");
+}
+
+#[test]
+fn long_role_names_are_kept_within_the_length() {
+    let long_name = "R".repeat(300);
+    let groups = guild_with_roles(&["Short", &long_name, "Other"]);
+
+    // the long name doesn't fit, so it's counted instead
+    assert_eq!(
+        describe_capped_guild_roles(&groups, 5, 100),
+        "main server: Short and 2 more"
+    );
+
+    // a single name that doesn't fit is cut off
+    let single = describe_capped_guild_roles(&guild_with_roles(&[&long_name]), 5, 100);
+    assert_eq!(single.chars().count(), 100);
+    assert!(single.starts_with("main server: RRR") && single.ends_with('…'));
+
+    // two of the names fit into a log
+    let log = gained_roles_log("10", &guild_with_roles(&[long_name.as_str(); 20]), "", 5);
+    assert!(log.ends_with(&format!("{}, {} and 18 more", long_name, long_name)));
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct FakeDiscord {
+    guild_roles: Vec<u64>,
+    applied: std::sync::Mutex<Vec<Vec<u64>>>,
+    /// the member ranks above the bot
+    owner: bool,
+    nicknames: std::sync::Mutex<Vec<Option<String>>>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl MemberRoleUpdater for FakeDiscord {
+    async fn set_roles(&self, role_ids: &[u64]) -> Result<(), RoleUpdateError> {
+        self.applied.lock().unwrap().push(role_ids.to_vec());
+        if role_ids
+            .iter()
+            .any(|role_id| !self.guild_roles.contains(role_id))
+        {
+            return Err(RoleUpdateError::UnknownRole);
+        }
+
+        Ok(())
+    }
+
+    async fn guild_roles(&self) -> Result<Vec<u64>, MyError> {
+        Ok(self.guild_roles.clone())
+    }
+
+    async fn set_nickname(&self, nickname: Option<&str>) -> Result<(), NicknameUpdateError> {
+        if self.owner {
+            return Err(NicknameUpdateError::MissingPermissions);
+        }
+        self.nicknames
+            .lock()
+            .unwrap()
+            .push(nickname.map(str::to_owned));
+
+        Ok(())
+    }
+}
+
+#[test]
+fn deleted_roles_are_withheld_instead_of_failing_the_sync() {
+    let discord = FakeDiscord {
+        guild_roles: vec![roles::REALITY_LEGEND, roles::BETA_TESTER],
+        ..FakeDiscord::default()
+    };
+    let reconciled = reconcile_roles(
+        &[
+            apply_a_role(roles::REALITY_LEGEND, "Reality Legend"),
+            apply_a_role(roles::SHARK_COLLECTOR, "Shark Collector"),
+            apply_a_role(roles::BETA_TESTER, "Beta Tester"),
+        ],
+        &[],
+        &MILESTONE_ROLES,
+        RoleSyncMode::Reconcile,
+        &RolePauses::default(),
+        &[],
+        0,
+    );
+
+    let role_sync = actix_web::rt::System::new()
+        .block_on(apply_member_roles(&discord, reconciled))
+        .unwrap();
+
+    assert_eq!(
+        role_sync,
+        RoleSync {
+            gained: vec![
+                apply_a_role(roles::REALITY_LEGEND, "Reality Legend"),
+                apply_a_role(roles::BETA_TESTER, "Beta Tester"),
+            ],
+            withheld: vec![WithheldRole {
+                id: roles::SHARK_COLLECTOR.to_string(),
+                name: "Shark Collector".to_owned(),
+                reason: WithheldReason::RoleMissing,
+            }],
+            missing: vec![roles::SHARK_COLLECTOR],
+            held: vec![roles::REALITY_LEGEND, roles::BETA_TESTER],
+            removed: Vec::new(),
+        }
+    );
+    assert_eq!(
+        discord.applied.lock().unwrap().last().unwrap(),
+        &vec![roles::REALITY_LEGEND, roles::BETA_TESTER]
+    );
+}
+
+#[cfg(test)]
+fn nickname_policy() -> crate::nicknames::NicknamePolicy {
+    crate::nicknames::NicknamePolicy {
+        enabled: true,
+        tiers: vec![
+            crate::nicknames::NicknameTier {
+                role_id: roles::REALITY_EXPLORER,
+                prefix: "[Explorer]".to_owned(),
+            },
+            crate::nicknames::NicknameTier {
+                role_id: roles::REALITY_LEGEND,
+                prefix: "[Legend]".to_owned(),
+            },
+        ],
+    }
+}
+
+#[test]
+fn nicknames_follow_tier_changes() {
+    let policy = nickname_policy();
+    let nickname = MemberNickname {
+        policy: &policy,
+        prefixed: true,
+    };
+    let discord = FakeDiscord::default();
+    let runtime = actix_web::rt::System::new();
+
+    runtime
+        .block_on(apply_member_nickname(
+            &discord,
+            Some("[Explorer] sirh"),
+            "sirh",
+            &nickname,
+            &[roles::REALITY_EXPLORER, roles::REALITY_LEGEND],
+        ))
+        .unwrap();
+    // nothing is sent while the tier stays the same
+    runtime
+        .block_on(apply_member_nickname(
+            &discord,
+            Some("[Legend] sirh"),
+            "sirh",
+            &nickname,
+            &[roles::REALITY_LEGEND],
+        ))
+        .unwrap();
+
+    assert_eq!(
+        *discord.nicknames.lock().unwrap(),
+        vec![Some("[Legend] sirh".to_owned())]
+    );
+}
+
+#[test]
+fn opted_out_members_lose_their_prefix() {
+    let policy = nickname_policy();
+    let discord = FakeDiscord::default();
+
+    actix_web::rt::System::new()
+        .block_on(apply_member_nickname(
+            &discord,
+            Some("[Legend] sirh"),
+            "sirh",
+            &MemberNickname {
+                policy: &policy,
+                prefixed: false,
+            },
+            &[roles::REALITY_LEGEND],
+        ))
+        .unwrap();
+
+    assert_eq!(*discord.nicknames.lock().unwrap(), vec![None]);
+}
+
+#[test]
+fn the_guild_owner_keeps_their_nickname_without_failing_the_sync() {
+    let policy = nickname_policy();
+    let discord = FakeDiscord {
+        owner: true,
+        ..FakeDiscord::default()
+    };
+
+    assert!(actix_web::rt::System::new()
+        .block_on(apply_member_nickname(
+            &discord,
+            None,
+            "owner",
+            &MemberNickname {
+                policy: &policy,
+                prefixed: true,
+            },
+            &[roles::REALITY_EXPLORER],
+        ))
+        .is_ok());
+    assert!(discord.nicknames.lock().unwrap().is_empty());
+}
+
+#[test]
+fn syncs_without_new_roles_name_the_closest_role() {
+    use crate::role_rules::closest_miss;
+
+    // a mid-game player past the explorer, paleontologist and speedster roles
+    let user_data = UserData {
+        metabits: 999_987_600,
+        dino_rank: 30,
+        singularity_speedrun_time: Some(150.0),
+        ..UserData::default()
+    };
+    let rules = compute_earned_roles_with_trace(
+        &user_data,
+        &[],
+        &[],
+        &RolePauses::default(),
+        &DEPRECATED_FIELDS,
+        SystemTime::now(),
+    )
+    .rules;
+    let miss = closest_miss(&rules, &user_data).unwrap();
+
+    assert_eq!(
+        gained_roles_message(&[], 5, Some(&miss)),
+        "The request was successful, but you haven't gained any new roles, you're 12,400 metabits away from Reality Expert"
+    );
+    assert_eq!(
+        gained_roles_message(&[], 5, None),
+        "The request was successful, but you've already gained all of the possible roles with your current progress"
+    );
+    // gained roles are named instead
+    assert!(
+        gained_roles_message(&guild_with_roles(&["Singularity"]), 5, Some(&miss))
+            .ends_with("the following roles: main server: Singularity")
+    );
+    assert_eq!(format_amount(1_234_567.0), "1,234,567");
+    assert_eq!(format_amount(29.5), "30");
+    assert_eq!(format_amount(100.0), "100");
 }
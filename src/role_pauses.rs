@@ -0,0 +1,104 @@
+use actix_web::rt::{self, time};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use crate::{constants::LOG, digest::unix_now, webhook_logging::webhook_log};
+
+const AUTO_RESUME_INTERVAL: Duration = Duration::from_secs(60);
+
+static ROLE_PAUSES: OnceLock<Mutex<RolePauses>> = OnceLock::new();
+
+/// the pause of a single role rule
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct RolePause {
+    /// seconds since the unix epoch, `None` means it's paused until someone resumes it
+    #[serde(serialize_with = "crate::timestamps::unix_seconds::serialize_option")]
+    pub resume_at: Option<u64>,
+}
+
+/// Milestone roles that temporarily aren't granted, e.g. while a stat the game inflated is being
+/// corrected. Users that already have a paused role keep it.
+#[derive(Default)]
+pub struct RolePauses {
+    paused: HashMap<u64, RolePause>,
+}
+
+impl RolePauses {
+    pub fn pause(&mut self, role_id: u64, resume_at: Option<u64>) {
+        self.paused.insert(role_id, RolePause { resume_at });
+    }
+
+    /// returns whether the role was paused
+    pub fn resume(&mut self, role_id: u64) -> bool {
+        self.paused.remove(&role_id).is_some()
+    }
+
+    pub fn get(&self, role_id: u64, now: u64) -> Option<RolePause> {
+        self.paused
+            .get(&role_id)
+            .filter(|pause| pause.resume_at.map_or(true, |resume_at| now < resume_at))
+            .copied()
+    }
+
+    pub fn is_paused(&self, role_id: u64, now: u64) -> bool {
+        self.get(role_id, now).is_some()
+    }
+
+    /// removes the pauses whose auto-resume time has passed and returns their roles
+    pub fn resume_due(&mut self, now: u64) -> Vec<u64> {
+        let due = self
+            .paused
+            .iter()
+            .filter(|(_, pause)| pause.resume_at.map_or(false, |resume_at| resume_at <= now))
+            .map(|(role_id, _)| *role_id)
+            .collect::<Vec<u64>>();
+        for role_id in &due {
+            self.paused.remove(role_id);
+        }
+
+        due
+    }
+}
+
+pub fn role_pauses() -> &'static Mutex<RolePauses> {
+    ROLE_PAUSES.get_or_init(|| Mutex::new(RolePauses::default()))
+}
+
+/// resumes paused roles once their auto-resume time has passed
+pub fn spawn_auto_resume_scheduler() {
+    rt::spawn(async move {
+        let mut interval = time::interval(AUTO_RESUME_INTERVAL);
+        loop {
+            interval.tick().await;
+            let resumed = role_pauses().lock().unwrap().resume_due(unix_now());
+            for role_id in resumed {
+                webhook_log(
+                    format!("granting of the role {} was automatically resumed", role_id),
+                    LOG::INFORMATIONAL,
+                )
+                .await;
+            }
+        }
+    });
+}
+
+#[test]
+fn pauses_resume_automatically() {
+    let mut pauses = RolePauses::default();
+    pauses.pause(1, Some(1_000));
+    pauses.pause(2, None);
+
+    assert!(pauses.is_paused(1, 999));
+    assert_eq!(pauses.resume_due(999), Vec::<u64>::new());
+
+    assert!(!pauses.is_paused(1, 1_000));
+    assert_eq!(pauses.resume_due(1_000), vec![1]);
+    assert!(pauses.is_paused(2, u64::MAX));
+
+    assert!(pauses.resume(2));
+    assert!(!pauses.resume(2));
+}
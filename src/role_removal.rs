@@ -0,0 +1,355 @@
+use actix_web::rt::{self, time};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+use twilight_http::Client;
+use twilight_model::id::{
+    marker::{GuildMarker, RoleMarker, UserMarker},
+    Id,
+};
+
+use crate::{
+    constants::{persistent_roles::PERSISTENT_ROLES, C2SGUILD},
+    discord_pause::ensure_discord_available,
+    errors::MyError,
+    granted_roles::GrantedRoles,
+    models::PromoRoleRule,
+    role_handling::{discord_call, EarnedRole, MILESTONE_ROLES},
+    role_rules::RoleRule,
+    sync_streaks::StreakRule,
+};
+
+/// how often queued removals are sent to Discord
+pub const REMOVAL_INTERVAL: Duration = Duration::from_secs(5);
+/// removals sent per run, so a burst of deletes doesn't hog the rate limit
+const REMOVAL_BATCH_SIZE: usize = 50;
+
+static ROLE_REMOVALS: OnceLock<Mutex<VecDeque<RoleRemoval>>> = OnceLock::new();
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoleRemoval {
+    pub discord_id: String,
+    pub role_id: u64,
+}
+
+/// what happens to the roles a deleted user was granted
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RoleRemovalSummary {
+    /// roles one of our rules granted, these are queued to be taken away
+    pub removing: Vec<String>,
+    /// roles none of our rules manage, these are never touched
+    pub kept: Vec<String>,
+}
+
+/// every role one of our rules grants, persistent roles are given out by hand and aren't ours
+pub fn managed_roles(
+    role_rules: &[RoleRule],
+    promo_rules: &[PromoRoleRule],
+    streak_rules: &[StreakRule],
+) -> Vec<EarnedRole> {
+    let configured = |role_id: u64, name: &str, promo_rule: Option<i64>| EarnedRole {
+        id: role_id,
+        name: Cow::Owned(name.to_owned()),
+        promo_rule,
+    };
+
+    MILESTONE_ROLES
+        .into_iter()
+        .chain(
+            role_rules
+                .iter()
+                .map(|rule| configured(rule.role_id, &rule.name, None)),
+        )
+        .chain(promo_rules.iter().filter_map(|rule| {
+            let role_id = rule.role_id.parse::<u64>().ok()?;
+            Some(configured(role_id, &rule.name, Some(rule.id)))
+        }))
+        .chain(
+            streak_rules
+                .iter()
+                .map(|rule| configured(rule.role_id, &rule.name, None)),
+        )
+        .filter(|role| !PERSISTENT_ROLES.contains(&role.id))
+        .collect()
+}
+
+pub fn managed_role_ids(
+    role_rules: &[RoleRule],
+    promo_rules: &[PromoRoleRule],
+    streak_rules: &[StreakRule],
+) -> Vec<u64> {
+    managed_roles(role_rules, promo_rules, streak_rules)
+        .into_iter()
+        .map(|role| role.id)
+        .collect()
+}
+
+/// splits the granted roles into the ones to take away and the ones to leave alone
+pub fn plan_role_removal(granted: &GrantedRoles, managed: &[u64]) -> RoleRemovalSummary {
+    let (removing, kept) = granted.keys().cloned().partition(|role_id| {
+        role_id
+            .parse::<u64>()
+            .map_or(false, |role_id| managed.contains(&role_id))
+    });
+
+    RoleRemovalSummary { removing, kept }
+}
+
+pub fn role_removals() -> &'static Mutex<VecDeque<RoleRemoval>> {
+    ROLE_REMOVALS.get_or_init(|| Mutex::new(VecDeque::new()))
+}
+
+fn enqueue_role_removals(
+    queue: &Mutex<VecDeque<RoleRemoval>>,
+    discord_id: &str,
+    summary: &RoleRemovalSummary,
+) {
+    let mut queue = queue.lock().unwrap();
+    for role_id in summary
+        .removing
+        .iter()
+        .filter_map(|role_id| role_id.parse::<u64>().ok())
+    {
+        queue.push_back(RoleRemoval {
+            discord_id: discord_id.to_owned(),
+            role_id,
+        });
+    }
+}
+
+/// Queues the removal of a deleted user's rule roles. `granted` is `None` when removing roles on
+/// delete is turned off, nothing is queued then.
+pub fn queue_granted_role_removals(
+    queue: &Mutex<VecDeque<RoleRemoval>>,
+    discord_id: &str,
+    granted: Option<&GrantedRoles>,
+    managed: &[u64],
+) -> Option<RoleRemovalSummary> {
+    let summary = plan_role_removal(granted?, managed);
+    enqueue_role_removals(queue, discord_id, &summary);
+
+    Some(summary)
+}
+
+/// takes a single role away from a member, so tests can run the queue without Discord
+#[async_trait]
+pub trait MemberRoleRemover {
+    /// fails with `MyError::Unavailable` while Discord calls are paused
+    async fn remove_role(&self, discord_id: &str, role_id: u64) -> Result<(), MyError>;
+}
+
+pub struct DiscordRoleRemover {
+    client: Client,
+    guild_id: Id<GuildMarker>,
+}
+
+impl DiscordRoleRemover {
+    pub fn new(discord_token: String) -> Self {
+        DiscordRoleRemover {
+            client: Client::new(discord_token),
+            guild_id: Id::new(C2SGUILD),
+        }
+    }
+}
+
+#[async_trait]
+impl MemberRoleRemover for DiscordRoleRemover {
+    async fn remove_role(&self, discord_id: &str, role_id: u64) -> Result<(), MyError> {
+        ensure_discord_available().await?;
+        let (user_id, role_id) = match (
+            discord_id.parse::<u64>().ok().filter(|id| *id != 0),
+            Some(role_id).filter(|id| *id != 0),
+        ) {
+            (Some(user_id), Some(role_id)) => (user_id, role_id),
+            _ => {
+                return Err(MyError::InternalError(
+                    "a queued role removal has an invalid id",
+                ))
+            }
+        };
+
+        let removed = discord_call(
+            self.client
+                .remove_guild_member_role(
+                    self.guild_id,
+                    Id::<UserMarker>::new(user_id),
+                    Id::<RoleMarker>::new(role_id),
+                )
+                .exec()
+                .await,
+            "failed at removing a deleted user's role",
+        )
+        .await;
+        // the call may have been what started the pause
+        if removed.is_err() {
+            ensure_discord_available().await?;
+        }
+
+        removed.map(|_| ())
+    }
+}
+
+/// Sends up to `batch_size` queued removals, returns how many went through. Removals that fail
+/// for other reasons (mostly members that left the server) are dropped, a paused Discord puts the
+/// removal back for the next run.
+pub async fn drain_role_removals(
+    queue: &Mutex<VecDeque<RoleRemoval>>,
+    remover: &impl MemberRoleRemover,
+    batch_size: usize,
+) -> usize {
+    let mut removed = 0;
+    for _ in 0..batch_size {
+        let removal = match queue.lock().unwrap().pop_front() {
+            Some(removal) => removal,
+            None => break,
+        };
+
+        match remover
+            .remove_role(&removal.discord_id, removal.role_id)
+            .await
+        {
+            Ok(()) => removed += 1,
+            Err(MyError::Unavailable(_)) => {
+                queue.lock().unwrap().push_front(removal);
+                break;
+            }
+            Err(_) => {}
+        }
+    }
+
+    removed
+}
+
+pub fn spawn_role_removal_worker(discord_token: String) {
+    rt::spawn(async move {
+        let remover = DiscordRoleRemover::new(discord_token);
+        let mut interval = time::interval(REMOVAL_INTERVAL);
+        loop {
+            interval.tick().await;
+            drain_role_removals(role_removals(), &remover, REMOVAL_BATCH_SIZE).await;
+        }
+    });
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct FakeRemover {
+    removed: Mutex<Vec<(String, u64)>>,
+    paused: bool,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl MemberRoleRemover for FakeRemover {
+    async fn remove_role(&self, discord_id: &str, role_id: u64) -> Result<(), MyError> {
+        if self.paused {
+            return Err(MyError::Unavailable("paused"));
+        }
+        self.removed
+            .lock()
+            .unwrap()
+            .push((discord_id.to_owned(), role_id));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn granted(role_ids: &[u64]) -> GrantedRoles {
+    role_ids
+        .iter()
+        .map(|role_id| (role_id.to_string(), Some(1_700_000_000)))
+        .collect()
+}
+
+#[test]
+fn nothing_is_removed_when_the_flag_is_off() {
+    let queue = Mutex::new(VecDeque::new());
+    let managed = managed_role_ids(&[], &[], &[]);
+    assert_eq!(
+        queue_granted_role_removals(&queue, "1234", None, &managed),
+        None
+    );
+
+    let discord = FakeRemover::default();
+    let runtime = actix_web::rt::System::new();
+    assert_eq!(
+        runtime.block_on(drain_role_removals(&queue, &discord, 10)),
+        0
+    );
+    assert!(discord.removed.lock().unwrap().is_empty());
+}
+
+#[test]
+fn granted_rule_roles_are_queued_for_removal() {
+    let explorer = MILESTONE_ROLES[0].id;
+    let streak = StreakRule {
+        weeks: 4,
+        role_id: 42,
+        name: "Regular".to_owned(),
+    };
+    let managed = managed_role_ids(&[], &[], &[streak]);
+    let queue = Mutex::new(VecDeque::new());
+    let summary =
+        queue_granted_role_removals(&queue, "1234", Some(&granted(&[explorer, 42])), &managed)
+            .unwrap();
+    assert_eq!(summary.removing.len(), 2);
+    assert!(summary.kept.is_empty());
+    assert_eq!(queue.lock().unwrap().len(), 2);
+
+    // a paused Discord leaves the removals for the next run
+    let runtime = actix_web::rt::System::new();
+    let paused = FakeRemover {
+        paused: true,
+        ..FakeRemover::default()
+    };
+    assert_eq!(
+        runtime.block_on(drain_role_removals(&queue, &paused, 10)),
+        0
+    );
+    assert_eq!(queue.lock().unwrap().len(), 2);
+
+    let discord = FakeRemover::default();
+    assert_eq!(
+        runtime.block_on(drain_role_removals(&queue, &discord, 10)),
+        2
+    );
+    assert!(queue.lock().unwrap().is_empty());
+    let mut removed = discord.removed.into_inner().unwrap();
+    removed.sort();
+    assert_eq!(
+        removed,
+        vec![("1234".to_owned(), 42), ("1234".to_owned(), explorer)]
+    );
+}
+
+#[test]
+fn roles_we_dont_manage_are_never_removed() {
+    let explorer = MILESTONE_ROLES[0].id;
+    // the server booster role
+    let booster = PERSISTENT_ROLES[4];
+    let managed = managed_role_ids(&[], &[], &[]);
+    let queue = Mutex::new(VecDeque::new());
+    let summary = queue_granted_role_removals(
+        &queue,
+        "1234",
+        Some(&granted(&[explorer, booster, 99])),
+        &managed,
+    )
+    .unwrap();
+
+    assert_eq!(summary.removing, vec![explorer.to_string()]);
+    assert_eq!(summary.kept, vec![booster.to_string(), "99".to_owned()]);
+    assert_eq!(
+        queue.into_inner().unwrap(),
+        VecDeque::from([RoleRemoval {
+            discord_id: "1234".to_owned(),
+            role_id: explorer,
+        }])
+    );
+}
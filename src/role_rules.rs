@@ -0,0 +1,1175 @@
+use serde::{Deserialize, Serialize};
+use std::{borrow::Cow, time::SystemTime};
+
+use crate::{
+    constants::{
+        roles, BeyondRequirements, MetabitRequirements, PaleoRequirements, SimulationRequirements,
+    },
+    fields::{Monotonicity, ProgressField},
+    models::{PromoRoleRule, UserData},
+    promo_roles::{active_promo_roles, promo_window, PromoWindow},
+    role_handling::{EarnedRole, MILESTONE_ROLES},
+    role_pauses::RolePauses,
+    sync_streaks::{StreakRule, SyncStreak},
+};
+
+/// how a rule compares the user's value with its threshold
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Comparison {
+    AtLeast,
+    /// e.g. a speedrun time, which has to be fast enough
+    AtMost,
+    Equals,
+    /// flags, the threshold is always 1
+    IsSet,
+}
+
+/// the distribution channels of the game a rule applies to
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum RuleChannel {
+    Any,
+    Beta,
+}
+
+/// why a role was or wasn't granted, the first condition that applies wins
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Verdict {
+    Granted,
+    /// the user's value doesn't meet the threshold
+    NotMet,
+    /// the rule is only for another distribution channel
+    WrongChannel,
+    /// a higher tier, or a role that replaces this one, was granted instead
+    Excluded,
+    /// the user qualifies, but the role isn't being granted right now
+    Paused,
+    /// the promo rule's window isn't open
+    OutsidePromoWindow,
+    /// the rule reads a field the game doesn't have anymore, members keep the role if they have it
+    DeprecatedField,
+}
+
+/// how a single rule was evaluated for a user
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct RuleTrace {
+    /// a string since role ids don't fit into JavaScript's numbers
+    pub role_id: String,
+    pub role_name: Cow<'static, str>,
+    /// `None` for rules that don't look at progress, like promo rules
+    pub field: Option<&'static str>,
+    pub value: Option<f64>,
+    pub comparison: Option<Comparison>,
+    pub threshold: Option<f64>,
+    pub channel: RuleChannel,
+    /// the role granted in this one's place
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub excluded_by: Option<Cow<'static, str>>,
+    pub paused: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub promo_window: Option<PromoWindow>,
+    pub verdict: Verdict,
+}
+
+impl RuleTrace {
+    /// whether the value meets the threshold, rules without a threshold are always met
+    pub fn requirement_met(&self) -> bool {
+        match (self.comparison, self.value, self.threshold) {
+            (None, _, _) => true,
+            (Some(_), None, _) | (Some(_), _, None) => false,
+            (Some(comparison), Some(value), Some(threshold)) => match comparison {
+                Comparison::AtLeast => value >= threshold,
+                Comparison::AtMost => value <= threshold,
+                Comparison::Equals | Comparison::IsSet => value == threshold,
+            },
+        }
+    }
+
+    /// how close the value is to the threshold, `None` when there's nothing to measure
+    pub fn progress_percent(&self) -> Option<u8> {
+        let (comparison, value, threshold) = (self.comparison?, self.value?, self.threshold?);
+        if self.requirement_met() {
+            return Some(100);
+        }
+
+        let ratio = match comparison {
+            Comparison::AtMost if value > 0.0 => threshold / value,
+            Comparison::AtMost | Comparison::IsSet => 0.0,
+            Comparison::AtLeast | Comparison::Equals if threshold > 0.0 => value / threshold,
+            Comparison::AtLeast | Comparison::Equals => 0.0,
+        };
+
+        // 100 is kept for rules that are met
+        Some((ratio * 100.0).clamp(0.0, 99.0) as u8)
+    }
+}
+
+/// the earned roles and how every rule got there
+#[derive(Debug, PartialEq)]
+pub struct RolesTrace {
+    /// the same roles `compute_earned_roles` returns, paused roles are in here too since
+    /// reconciling is what leaves them out
+    pub earned: Vec<EarnedRole>,
+    pub rules: Vec<RuleTrace>,
+}
+
+/// A role granted once `field` reaches `threshold`, configured with `ROLE_RULES` so a new milestone
+/// role doesn't take a release. A rule for one of the built-in milestone roles replaces its
+/// requirement, the role keeps its place among the tiers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoleRule {
+    pub role_id: u64,
+    pub name: String,
+    pub field: ProgressField,
+    pub threshold: f64,
+}
+
+impl RoleRule {
+    /// fields that only go down, like a speedrun time, have to be at most the threshold
+    fn comparison(&self) -> Comparison {
+        match self.field.monotonicity() {
+            Monotonicity::NonDecreasing => Comparison::AtLeast,
+            Monotonicity::NonIncreasing => Comparison::AtMost,
+        }
+    }
+
+    fn trace(&self, user_data: &UserData) -> RuleTrace {
+        RuleTrace {
+            role_id: self.role_id.to_string(),
+            role_name: Cow::Owned(self.name.clone()),
+            field: Some(self.field.name()),
+            value: self.field.read_userdata(user_data),
+            comparison: Some(self.comparison()),
+            threshold: Some(self.threshold),
+            channel: RuleChannel::Any,
+            excluded_by: None,
+            paused: false,
+            promo_window: None,
+            verdict: Verdict::Granted,
+        }
+    }
+}
+
+/// parses `{role_id}:{field}:{threshold}:{name}` entries separated by commas, malformed entries and
+/// unknown fields are skipped
+pub fn parse_role_rules(role_rules: &str) -> Vec<RoleRule> {
+    role_rules
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.splitn(4, ':');
+            let role_id = parts
+                .next()?
+                .trim()
+                .parse::<u64>()
+                .ok()
+                .filter(|id| *id != 0)?;
+            let field = ProgressField::from_name(parts.next()?.trim())?;
+            let threshold = parts
+                .next()?
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|threshold| threshold.is_finite())?;
+            let name = parts.next()?.trim().to_owned();
+            Some(RoleRule {
+                role_id,
+                name,
+                field,
+                threshold,
+            })
+        })
+        .collect()
+}
+
+/// a milestone role and the progress it takes
+struct MilestoneRule {
+    role_id: u64,
+    field: Option<&'static str>,
+    /// the progress field the value is read from
+    source: Option<ProgressField>,
+    value: fn(&UserData) -> Option<f64>,
+    comparison: Option<Comparison>,
+    threshold: f64,
+    channel: RuleChannel,
+    /// roles that replace this one when they're earned as well, highest first
+    superseded_by: &'static [u64],
+}
+
+fn dino_prestige(user_data: &UserData) -> Option<f64> {
+    Some((user_data.dino_rank / 50).clamp(0, 10) as f64)
+}
+
+fn flag(set: bool) -> Option<f64> {
+    Some(set as u8 as f64)
+}
+
+/// every milestone rule in the order of `MILESTONE_ROLES`, which is also the order they're granted in
+static MILESTONE_RULES: [MilestoneRule; 12] = [
+    MilestoneRule {
+        role_id: roles::REALITY_EXPLORER,
+        field: Some("metabits"),
+        source: Some(ProgressField::Metabits),
+        value: |user_data| Some(user_data.metabits as f64),
+        comparison: Some(Comparison::AtLeast),
+        threshold: MetabitRequirements::RealityExplorer as i64 as f64,
+        channel: RuleChannel::Any,
+        superseded_by: &[roles::REALITY_LEGEND, roles::REALITY_EXPERT],
+    },
+    MilestoneRule {
+        role_id: roles::REALITY_EXPERT,
+        field: Some("metabits"),
+        source: Some(ProgressField::Metabits),
+        value: |user_data| Some(user_data.metabits as f64),
+        comparison: Some(Comparison::AtLeast),
+        threshold: MetabitRequirements::RealityExpert as i64 as f64,
+        channel: RuleChannel::Any,
+        superseded_by: &[roles::REALITY_LEGEND],
+    },
+    MilestoneRule {
+        role_id: roles::REALITY_LEGEND,
+        field: Some("metabits"),
+        source: Some(ProgressField::Metabits),
+        value: |user_data| Some(user_data.metabits as f64),
+        comparison: Some(Comparison::AtLeast),
+        threshold: MetabitRequirements::RealityLegend as i64 as f64,
+        channel: RuleChannel::Any,
+        superseded_by: &[],
+    },
+    MilestoneRule {
+        role_id: roles::PALEONTOLOGIST,
+        field: Some("dino_rank"),
+        source: Some(ProgressField::DinoRank),
+        value: |user_data| Some(user_data.dino_rank as f64),
+        comparison: Some(Comparison::AtLeast),
+        threshold: PaleoRequirements::Paleontologist as i32 as f64,
+        channel: RuleChannel::Any,
+        superseded_by: &[
+            roles::PALEONTOLOGIST_LEGEND,
+            roles::PROGRESSIVE_PALEONTOLOGIST,
+        ],
+    },
+    MilestoneRule {
+        role_id: roles::PROGRESSIVE_PALEONTOLOGIST,
+        field: Some("dino_prestige"),
+        source: Some(ProgressField::DinoRank),
+        value: dino_prestige,
+        comparison: Some(Comparison::Equals),
+        threshold: PaleoRequirements::ProgressivePaleontologist as i32 as f64,
+        channel: RuleChannel::Any,
+        superseded_by: &[roles::PALEONTOLOGIST_LEGEND],
+    },
+    MilestoneRule {
+        role_id: roles::PALEONTOLOGIST_LEGEND,
+        field: Some("dino_prestige"),
+        source: Some(ProgressField::DinoRank),
+        value: dino_prestige,
+        comparison: Some(Comparison::Equals),
+        threshold: PaleoRequirements::PaleontologistLegend as i32 as f64,
+        channel: RuleChannel::Any,
+        superseded_by: &[],
+    },
+    MilestoneRule {
+        role_id: roles::PLANETARY_EXPLORER,
+        field: Some("beyond_rank"),
+        source: Some(ProgressField::BeyondRank),
+        value: |user_data| Some(user_data.beyond_rank as f64),
+        comparison: Some(Comparison::Equals),
+        threshold: BeyondRequirements::PlanetaryExplorer as i32 as f64,
+        channel: RuleChannel::Any,
+        superseded_by: &[],
+    },
+    MilestoneRule {
+        role_id: roles::SIMULATION_SPEEDSTER,
+        field: Some("singularity_speedrun_time"),
+        source: Some(ProgressField::SingularitySpeedrunTime),
+        value: |user_data| user_data.singularity_speedrun_time,
+        comparison: Some(Comparison::AtMost),
+        threshold: SimulationRequirements::SimulationSpeedster as i32 as f64,
+        channel: RuleChannel::Any,
+        superseded_by: &[
+            roles::FINDER_OF_SEMBLANCE_SECRETS,
+            roles::SONIC_SPEEDSTER_OF_SIMULATIONS,
+        ],
+    },
+    MilestoneRule {
+        role_id: roles::SONIC_SPEEDSTER_OF_SIMULATIONS,
+        field: Some("singularity_speedrun_time"),
+        source: Some(ProgressField::SingularitySpeedrunTime),
+        value: |user_data| user_data.singularity_speedrun_time,
+        comparison: Some(Comparison::AtMost),
+        threshold: SimulationRequirements::SonicSpeedsterOfSimulations as i32 as f64,
+        channel: RuleChannel::Any,
+        superseded_by: &[roles::FINDER_OF_SEMBLANCE_SECRETS],
+    },
+    MilestoneRule {
+        role_id: roles::SHARK_COLLECTOR,
+        field: Some("all_sharks_obtained"),
+        source: Some(ProgressField::AllSharksObtained),
+        value: |user_data| flag(user_data.all_sharks_obtained),
+        comparison: Some(Comparison::IsSet),
+        threshold: 1.0,
+        channel: RuleChannel::Any,
+        superseded_by: &[roles::FINDER_OF_SEMBLANCE_SECRETS],
+    },
+    MilestoneRule {
+        role_id: roles::FINDER_OF_SEMBLANCE_SECRETS,
+        field: Some("all_hidden_achievements_obtained"),
+        source: Some(ProgressField::AllHiddenAchievementsObtained),
+        value: |user_data| flag(user_data.all_hidden_achievements_obtained),
+        comparison: Some(Comparison::IsSet),
+        threshold: 1.0,
+        channel: RuleChannel::Any,
+        superseded_by: &[],
+    },
+    MilestoneRule {
+        role_id: roles::BETA_TESTER,
+        field: None,
+        source: None,
+        value: |_| None,
+        comparison: None,
+        threshold: 0.0,
+        channel: RuleChannel::Beta,
+        superseded_by: &[],
+    },
+];
+
+fn milestone_role(role_id: u64) -> EarnedRole {
+    MILESTONE_ROLES
+        .into_iter()
+        .find(|role| role.id == role_id)
+        .expect("every milestone rule has a milestone role")
+}
+
+fn unix_seconds(now: SystemTime) -> u64 {
+    now.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs())
+}
+
+/// the configured rule that replaces a built-in milestone rule's requirement
+fn configured_rule(role_id: u64, role_rules: &[RoleRule]) -> Option<&RoleRule> {
+    role_rules.iter().find(|rule| rule.role_id == role_id)
+}
+
+/// the configured rules for roles that aren't built in
+fn added_rules(role_rules: &[RoleRule]) -> impl Iterator<Item = &RoleRule> {
+    role_rules.iter().filter(|rule| {
+        !MILESTONE_RULES
+            .iter()
+            .any(|milestone| milestone.role_id == rule.role_id)
+    })
+}
+
+impl MilestoneRule {
+    fn trace(&self, user_data: &UserData, role_rules: &[RoleRule]) -> RuleTrace {
+        if let Some(configured) = configured_rule(self.role_id, role_rules) {
+            return RuleTrace {
+                role_name: milestone_role(self.role_id).name,
+                channel: self.channel,
+                ..configured.trace(user_data)
+            };
+        }
+
+        let comparison = self.comparison;
+        RuleTrace {
+            role_id: self.role_id.to_string(),
+            role_name: milestone_role(self.role_id).name,
+            field: self.field,
+            value: (self.value)(user_data),
+            comparison,
+            threshold: comparison.map(|_| self.threshold),
+            channel: self.channel,
+            excluded_by: None,
+            paused: false,
+            promo_window: None,
+            verdict: Verdict::Granted,
+        }
+    }
+}
+
+impl MilestoneRule {
+    fn reads_deprecated(&self, deprecated: &[ProgressField], role_rules: &[RoleRule]) -> bool {
+        configured_rule(self.role_id, role_rules)
+            .map_or(self.source, |configured| Some(configured.field))
+            .map_or(false, |field| deprecated.contains(&field))
+    }
+}
+
+/// the roles whose rules read a deprecated field, reconciling leaves them as they are
+pub fn deprecated_field_roles(deprecated: &[ProgressField], role_rules: &[RoleRule]) -> Vec<u64> {
+    MILESTONE_RULES
+        .iter()
+        .filter(|rule| rule.reads_deprecated(deprecated, role_rules))
+        .map(|rule| rule.role_id)
+        .chain(
+            added_rules(role_rules)
+                .filter(|rule| deprecated.contains(&rule.field))
+                .map(|rule| rule.role_id),
+        )
+        .collect()
+}
+
+/// every milestone and configured rule with its requirement but no user's value, e.g. to count the
+/// users meeting each of them
+pub fn rule_requirements(role_rules: &[RoleRule]) -> Vec<RuleTrace> {
+    let nobody = UserData::default();
+    MILESTONE_RULES
+        .iter()
+        .map(|rule| rule.trace(&nobody, role_rules))
+        .chain(added_rules(role_rules).map(|rule| rule.trace(&nobody)))
+        .map(|trace| RuleTrace {
+            value: None,
+            ..trace
+        })
+        .collect()
+}
+
+fn channel_applies(channel: RuleChannel, user_data: &UserData) -> bool {
+    match channel {
+        RuleChannel::Any => true,
+        RuleChannel::Beta => user_data.beta_tester,
+    }
+}
+
+/// `compute_earned_roles` with the reasoning behind every rule, for answering "why didn't I get
+/// this role" without re-running the rules by hand. Rules reading one of the `deprecated` fields
+/// don't earn anything and don't exclude anything either.
+pub fn compute_earned_roles_with_trace(
+    user_data: &UserData,
+    role_rules: &[RoleRule],
+    promo_rules: &[PromoRoleRule],
+    pauses: &RolePauses,
+    deprecated: &[ProgressField],
+    now: SystemTime,
+) -> RolesTrace {
+    let unix_now = unix_seconds(now);
+    let qualifying = MILESTONE_RULES
+        .iter()
+        .map(|rule| {
+            !rule.reads_deprecated(deprecated, role_rules)
+                && rule.trace(user_data, role_rules).requirement_met()
+                && channel_applies(rule.channel, user_data)
+        })
+        .collect::<Vec<bool>>();
+
+    let mut earned = Vec::new();
+    let mut rules = Vec::new();
+    for rule in MILESTONE_RULES.iter() {
+        let mut trace = rule.trace(user_data, role_rules);
+        let superseding_rule = rule.superseded_by.iter().find(|superseding| {
+            MILESTONE_RULES
+                .iter()
+                .zip(&qualifying)
+                .any(|(other, qualifies)| other.role_id == **superseding && *qualifies)
+        });
+        trace.paused = pauses.is_paused(rule.role_id, unix_now);
+
+        trace.verdict = if rule.reads_deprecated(deprecated, role_rules) {
+            Verdict::DeprecatedField
+        } else if !trace.requirement_met() {
+            Verdict::NotMet
+        } else if !channel_applies(rule.channel, user_data) {
+            Verdict::WrongChannel
+        } else if let Some(superseding) = superseding_rule {
+            trace.excluded_by = Some(milestone_role(*superseding).name);
+            Verdict::Excluded
+        } else if trace.paused {
+            Verdict::Paused
+        } else {
+            Verdict::Granted
+        };
+        if matches!(trace.verdict, Verdict::Granted | Verdict::Paused) {
+            earned.push(milestone_role(rule.role_id));
+        }
+        rules.push(trace);
+    }
+
+    for rule in added_rules(role_rules) {
+        let mut trace = rule.trace(user_data);
+        trace.paused = pauses.is_paused(rule.role_id, unix_now);
+        trace.verdict = if deprecated.contains(&rule.field) {
+            Verdict::DeprecatedField
+        } else if !trace.requirement_met() {
+            Verdict::NotMet
+        } else if trace.paused {
+            Verdict::Paused
+        } else {
+            Verdict::Granted
+        };
+        if matches!(trace.verdict, Verdict::Granted | Verdict::Paused) {
+            earned.push(EarnedRole {
+                id: rule.role_id,
+                name: Cow::Owned(rule.name.clone()),
+                promo_rule: None,
+            });
+        }
+        rules.push(trace);
+    }
+
+    for rule in promo_rules {
+        let role_id = match rule.role_id.parse::<u64>().ok().filter(|id| *id != 0) {
+            Some(role_id) => role_id,
+            None => continue,
+        };
+        let window = promo_window(rule, now);
+        let paused = pauses.is_paused(role_id, unix_now);
+        let verdict = match (window, paused) {
+            (PromoWindow::Active, false) => Verdict::Granted,
+            (PromoWindow::Active, true) => Verdict::Paused,
+            _ => Verdict::OutsidePromoWindow,
+        };
+
+        rules.push(RuleTrace {
+            role_id: role_id.to_string(),
+            role_name: Cow::Owned(rule.name.clone()),
+            field: None,
+            value: None,
+            comparison: None,
+            threshold: None,
+            channel: RuleChannel::Any,
+            excluded_by: None,
+            paused,
+            promo_window: Some(window),
+            verdict,
+        });
+    }
+
+    earned.append(&mut active_promo_roles(promo_rules, now));
+
+    RolesTrace { earned, rules }
+}
+
+/// the streak rules' side of the trace, streak roles are earned separately from the milestone roles
+pub fn trace_streak_rules(
+    streak_rules: &[StreakRule],
+    streak: &SyncStreak,
+    pauses: &RolePauses,
+    now: SystemTime,
+) -> Vec<RuleTrace> {
+    let unix_now = unix_seconds(now);
+    streak_rules
+        .iter()
+        .map(|rule| {
+            let paused = pauses.is_paused(rule.role_id, unix_now);
+            let met = streak.longest >= rule.weeks;
+            RuleTrace {
+                role_id: rule.role_id.to_string(),
+                role_name: Cow::Owned(rule.name.clone()),
+                field: Some("longest_streak_weeks"),
+                value: Some(streak.longest as f64),
+                comparison: Some(Comparison::AtLeast),
+                threshold: Some(rule.weeks as f64),
+                channel: RuleChannel::Any,
+                excluded_by: None,
+                paused,
+                promo_window: None,
+                verdict: match (met, paused) {
+                    (false, _) => Verdict::NotMet,
+                    (true, true) => Verdict::Paused,
+                    (true, false) => Verdict::Granted,
+                },
+            }
+        })
+        .collect()
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Requirement {
+    Met,
+    NotMet,
+}
+
+/// what a user is shown about a rule, their values and the thresholds stay out of it
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ExplainedRule {
+    pub role_id: String,
+    pub role_name: Cow<'static, str>,
+    pub requirement: Requirement,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress_percent: Option<u8>,
+    pub verdict: Verdict,
+}
+
+pub fn explain_rule(trace: &RuleTrace) -> ExplainedRule {
+    ExplainedRule {
+        role_id: trace.role_id.clone(),
+        role_name: trace.role_name.clone(),
+        requirement: if trace.requirement_met() {
+            Requirement::Met
+        } else {
+            Requirement::NotMet
+        },
+        progress_percent: trace.progress_percent(),
+        verdict: trace.verdict,
+    }
+}
+
+/// the unearned role a user is closest to and how much of its field is still missing
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ClosestMiss {
+    pub role_id: String,
+    pub role_name: String,
+    pub field: String,
+    /// in the field's own unit, seconds for a speedrun time that still has to come down
+    pub remaining: f64,
+}
+
+/// whether a rule that replaces this one is met, getting this one wouldn't change anything then
+fn superseded(trace: &RuleTrace, rules: &[RuleTrace]) -> bool {
+    let superseded_by = MILESTONE_RULES
+        .iter()
+        .find(|rule| rule.role_id.to_string() == trace.role_id)
+        .map_or(&[][..], |rule| rule.superseded_by);
+
+    rules.iter().any(|other| {
+        superseded_by
+            .iter()
+            .any(|superseding| other.role_id == superseding.to_string())
+            && matches!(
+                other.verdict,
+                Verdict::Granted | Verdict::Excluded | Verdict::Paused
+            )
+    })
+}
+
+/// How much is missing for a rule that isn't met, flags and values past an exact threshold
+/// can't be counted down to.
+fn remaining(trace: &RuleTrace) -> Option<f64> {
+    let (comparison, value, threshold) = (trace.comparison?, trace.value?, trace.threshold?);
+    let remaining = match comparison {
+        Comparison::AtLeast | Comparison::Equals => threshold - value,
+        Comparison::AtMost => value - threshold,
+        Comparison::IsSet => return None,
+    };
+
+    Some(remaining).filter(|remaining| *remaining > 0.0)
+}
+
+/// The rule with the highest `progress_percent` the user can still work towards, the first one
+/// wins a tie. Paused rules, rules for another channel and rules a met rule replaces are left out.
+pub fn closest_miss(rules: &[RuleTrace], user_data: &UserData) -> Option<ClosestMiss> {
+    rules
+        .iter()
+        .filter(|trace| {
+            trace.verdict == Verdict::NotMet
+                && !trace.paused
+                && channel_applies(trace.channel, user_data)
+                && !superseded(trace, rules)
+        })
+        .filter_map(|trace| Some((trace, trace.progress_percent()?, remaining(trace)?)))
+        .fold(
+            None,
+            |closest: Option<(&RuleTrace, u8, f64)>, candidate| match closest {
+                Some(closest) if closest.1 >= candidate.1 => Some(closest),
+                _ => Some(candidate),
+            },
+        )
+        .map(|(trace, _, remaining)| ClosestMiss {
+            role_id: trace.role_id.clone(),
+            role_name: trace.role_name.to_string(),
+            field: trace.field.unwrap_or_default().to_owned(),
+            remaining,
+        })
+}
+
+/// How many of the progress milestones are done and how many there are. A milestone is done once
+/// its role or a role replacing it is earned, rules without a requirement and rules reading a
+/// deprecated field don't count.
+pub fn milestones_completed(rules: &[RuleTrace]) -> (usize, usize) {
+    let milestones = rules
+        .iter()
+        .filter(|trace| trace.comparison.is_some() && trace.verdict != Verdict::DeprecatedField)
+        .collect::<Vec<&RuleTrace>>();
+    let completed = milestones
+        .iter()
+        .filter(|trace| {
+            matches!(
+                trace.verdict,
+                Verdict::Granted | Verdict::Excluded | Verdict::Paused
+            ) || superseded(trace, rules)
+        })
+        .count();
+
+    (completed, milestones.len())
+}
+
+#[cfg(test)]
+fn trace_of(user_data: &UserData, pauses: &RolePauses, role_id: u64) -> RuleTrace {
+    compute_earned_roles_with_trace(user_data, &[], &[], pauses, &[], SystemTime::now())
+        .rules
+        .into_iter()
+        .find(|trace| trace.role_id == role_id.to_string())
+        .unwrap()
+}
+
+#[test]
+fn a_met_rule_is_granted() {
+    let user_data = UserData {
+        metabits: 2_000_000,
+        ..UserData::default()
+    };
+    let trace = trace_of(&user_data, &RolePauses::default(), roles::REALITY_EXPLORER);
+
+    assert_eq!(trace.verdict, Verdict::Granted);
+    assert_eq!(trace.value, Some(2_000_000.0));
+    assert_eq!(trace.threshold, Some(1_000_000.0));
+    assert_eq!(trace.progress_percent(), Some(100));
+}
+
+#[test]
+fn an_unmet_threshold_is_not_met() {
+    let user_data = UserData {
+        metabits: 250_000,
+        ..UserData::default()
+    };
+    let trace = trace_of(&user_data, &RolePauses::default(), roles::REALITY_EXPLORER);
+
+    assert_eq!(trace.verdict, Verdict::NotMet);
+    assert_eq!(trace.progress_percent(), Some(25));
+
+    // a speedrun that isn't fast enough yet
+    let user_data = UserData {
+        singularity_speedrun_time: Some(600.0),
+        ..UserData::default()
+    };
+    let trace = trace_of(
+        &user_data,
+        &RolePauses::default(),
+        roles::SIMULATION_SPEEDSTER,
+    );
+    assert_eq!(trace.verdict, Verdict::NotMet);
+    assert_eq!(trace.progress_percent(), Some(50));
+}
+
+#[test]
+fn channel_rules_need_the_channel() {
+    let trace = trace_of(
+        &UserData::default(),
+        &RolePauses::default(),
+        roles::BETA_TESTER,
+    );
+    assert_eq!(trace.verdict, Verdict::WrongChannel);
+    assert_eq!(trace.channel, RuleChannel::Beta);
+
+    let beta_tester = UserData {
+        beta_tester: true,
+        ..UserData::default()
+    };
+    let trace = trace_of(&beta_tester, &RolePauses::default(), roles::BETA_TESTER);
+    assert_eq!(trace.verdict, Verdict::Granted);
+}
+
+#[test]
+fn lower_tiers_are_excluded_by_higher_ones() {
+    let user_data = UserData {
+        metabits: MetabitRequirements::RealityLegend as i64,
+        all_sharks_obtained: true,
+        all_hidden_achievements_obtained: true,
+        ..UserData::default()
+    };
+    let pauses = RolePauses::default();
+
+    let explorer = trace_of(&user_data, &pauses, roles::REALITY_EXPLORER);
+    assert_eq!(explorer.verdict, Verdict::Excluded);
+    assert_eq!(explorer.excluded_by.as_deref(), Some("Reality Legend"));
+    // finding every secret replaces the shark collector role
+    let sharks = trace_of(&user_data, &pauses, roles::SHARK_COLLECTOR);
+    assert_eq!(sharks.verdict, Verdict::Excluded);
+    assert_eq!(
+        sharks.excluded_by.as_deref(),
+        Some("Finder of Semblance's Secrets")
+    );
+}
+
+#[test]
+fn paused_rules_are_reported_as_paused() {
+    let mut pauses = RolePauses::default();
+    pauses.pause(roles::SHARK_COLLECTOR, None);
+    let user_data = UserData {
+        all_sharks_obtained: true,
+        ..UserData::default()
+    };
+
+    let trace =
+        compute_earned_roles_with_trace(&user_data, &[], &[], &pauses, &[], SystemTime::now());
+    let sharks = trace
+        .rules
+        .iter()
+        .find(|trace| trace.role_id == roles::SHARK_COLLECTOR.to_string())
+        .unwrap();
+    assert_eq!(sharks.verdict, Verdict::Paused);
+    assert!(sharks.paused);
+    // reconciling is what keeps paused roles from being granted
+    assert_eq!(
+        trace
+            .earned
+            .iter()
+            .map(|role| role.id)
+            .collect::<Vec<u64>>(),
+        vec![roles::SHARK_COLLECTOR]
+    );
+}
+
+#[test]
+fn promo_rules_need_their_window() {
+    let now = SystemTime::now();
+    let hour = std::time::Duration::from_secs(60 * 60);
+    let promo = |id: i64, starts_at: SystemTime, ends_at: SystemTime| PromoRoleRule {
+        id,
+        role_id: (100 + id).to_string(),
+        name: format!("Promo {}", id),
+        starts_at,
+        ends_at,
+        swept: false,
+        created_timestamp: now,
+    };
+    let rules = [
+        promo(1, now - hour, now + hour),
+        promo(2, now + hour, now + hour * 2),
+        promo(3, now - hour * 2, now - hour),
+    ];
+
+    let trace = compute_earned_roles_with_trace(
+        &UserData::default(),
+        &[],
+        &rules,
+        &RolePauses::default(),
+        &[],
+        now,
+    );
+    let verdicts = trace
+        .rules
+        .iter()
+        .filter(|trace| trace.promo_window.is_some())
+        .map(|trace| trace.verdict)
+        .collect::<Vec<Verdict>>();
+    assert_eq!(
+        verdicts,
+        vec![
+            Verdict::Granted,
+            Verdict::OutsidePromoWindow,
+            Verdict::OutsidePromoWindow
+        ]
+    );
+    assert_eq!(trace.earned.len(), 1);
+    assert_eq!(trace.earned[0].promo_rule, Some(1));
+}
+
+#[test]
+fn traces_serialize_with_string_ids() {
+    let user_data = UserData {
+        metabits: 250_000,
+        ..UserData::default()
+    };
+    let trace = trace_of(&user_data, &RolePauses::default(), roles::REALITY_EXPLORER);
+
+    assert_eq!(
+        serde_json::to_value(&trace).unwrap(),
+        serde_json::json!({
+            "role_id": "499316778426433538",
+            "role_name": "Reality Explorer",
+            "field": "metabits",
+            "value": 250_000.0,
+            "comparison": "atLeast",
+            "threshold": 1_000_000.0,
+            "channel": "any",
+            "paused": false,
+            "verdict": "notMet",
+        })
+    );
+
+    // users only see whether they're there yet
+    assert_eq!(
+        serde_json::to_value(explain_rule(&trace)).unwrap(),
+        serde_json::json!({
+            "role_id": "499316778426433538",
+            "role_name": "Reality Explorer",
+            "requirement": "notMet",
+            "progress_percent": 25,
+            "verdict": "notMet",
+        })
+    );
+}
+
+#[test]
+fn syncing_only_deprecated_fields_leaves_the_roles_alone() {
+    use crate::{evaluation::apply_payload, models::UpdateUserData};
+
+    let deprecated = [ProgressField::AllSharksObtained];
+    let current = UserData {
+        metabits: 2_000_000,
+        ..UserData::default()
+    };
+    let earned = |user_data: &UserData| {
+        compute_earned_roles_with_trace(
+            user_data,
+            &[],
+            &[],
+            &RolePauses::default(),
+            &deprecated,
+            SystemTime::now(),
+        )
+        .earned
+    };
+
+    // an old client still sends the field, the value is stored all the same
+    let synced = apply_payload(
+        &current,
+        &UpdateUserData {
+            metabits: 2_000_000.0,
+            all_sharks_obtained: true,
+            ..UpdateUserData::default()
+        },
+        false,
+    );
+    assert!(synced.all_sharks_obtained);
+    assert_eq!(earned(&synced), earned(&current));
+
+    let sharks = compute_earned_roles_with_trace(
+        &synced,
+        &[],
+        &[],
+        &RolePauses::default(),
+        &deprecated,
+        SystemTime::now(),
+    )
+    .rules
+    .into_iter()
+    .find(|trace| trace.role_id == roles::SHARK_COLLECTOR.to_string())
+    .unwrap();
+    assert_eq!(sharks.verdict, Verdict::DeprecatedField);
+
+    // members that already had the role keep it
+    let member_roles = [roles::SHARK_COLLECTOR];
+    let reconciled = crate::role_handling::reconcile_roles(
+        &earned(&synced),
+        &member_roles,
+        &MILESTONE_ROLES,
+        crate::role_handling::RoleSyncMode::Reconcile,
+        &RolePauses::default(),
+        &deprecated_field_roles(&deprecated, &[]),
+        0,
+    );
+    assert!(reconciled.applied.contains(&roles::SHARK_COLLECTOR));
+    assert!(reconciled
+        .gained
+        .iter()
+        .all(|role| role.id != roles::SHARK_COLLECTOR));
+}
+
+#[cfg(test)]
+fn mid_game_user() -> UserData {
+    UserData {
+        metabits: 999_987_600,
+        dino_rank: 30,
+        singularity_speedrun_time: Some(150.0),
+        ..UserData::default()
+    }
+}
+
+#[test]
+fn the_closest_miss_is_the_rule_furthest_along() {
+    let rules = compute_earned_roles_with_trace(
+        &mid_game_user(),
+        &[],
+        &[],
+        &RolePauses::default(),
+        &[],
+        SystemTime::now(),
+    )
+    .rules;
+
+    assert_eq!(
+        closest_miss(&rules, &mid_game_user()),
+        Some(ClosestMiss {
+            role_id: roles::REALITY_EXPERT.to_string(),
+            role_name: "Reality Expert".to_owned(),
+            field: "metabits".to_owned(),
+            remaining: 12_400.0,
+        })
+    );
+}
+
+#[test]
+fn paused_and_replaced_rules_are_no_closest_miss() {
+    let mut pauses = RolePauses::default();
+    pauses.pause(roles::REALITY_EXPERT, None);
+    let rules = compute_earned_roles_with_trace(
+        &mid_game_user(),
+        &[],
+        &[],
+        &pauses,
+        &[],
+        SystemTime::now(),
+    )
+    .rules;
+
+    // the speedrun is the next closest, 150 seconds where 120 are needed
+    let miss = closest_miss(&rules, &mid_game_user()).unwrap();
+    assert_eq!(miss.role_name, "Sonic Speedster of Simulations");
+    assert_eq!(miss.remaining, 30.0);
+
+    // the legend role replaces everything below it
+    let legend = UserData {
+        metabits: MetabitRequirements::RealityLegend as i64,
+        dino_rank: 10 * 50,
+        beyond_rank: BeyondRequirements::PlanetaryExplorer as i32,
+        singularity_speedrun_time: Some(100.0),
+        all_sharks_obtained: true,
+        all_hidden_achievements_obtained: true,
+        ..UserData::default()
+    };
+    let rules = compute_earned_roles_with_trace(
+        &legend,
+        &[],
+        &[],
+        &RolePauses::default(),
+        &[],
+        SystemTime::now(),
+    )
+    .rules;
+    assert_eq!(closest_miss(&rules, &legend), None);
+}
+
+#[cfg(test)]
+fn configured_roles(user_data: &UserData, role_rules: &[RoleRule]) -> Vec<u64> {
+    compute_earned_roles_with_trace(
+        user_data,
+        role_rules,
+        &[],
+        &RolePauses::default(),
+        &[],
+        SystemTime::now(),
+    )
+    .earned
+    .into_iter()
+    .map(|role| role.id)
+    .filter(|role_id| role_rules.iter().any(|rule| rule.role_id == *role_id))
+    .collect()
+}
+
+#[test]
+fn role_rules_are_parsed_from_the_config() {
+    let rules = parse_role_rules(
+        "1001:metabits:5e9:Multiverse Tourist, 1002:singularity_speedrun_time:60:Blink,junk,0:dino_rank:1:Zero,1003:no_such_field:1:Nope,1004:dino_rank:NaN:Nope",
+    );
+
+    assert_eq!(
+        rules,
+        vec![
+            RoleRule {
+                role_id: 1001,
+                name: "Multiverse Tourist".to_owned(),
+                field: ProgressField::Metabits,
+                threshold: 5e9,
+            },
+            RoleRule {
+                role_id: 1002,
+                name: "Blink".to_owned(),
+                field: ProgressField::SingularitySpeedrunTime,
+                threshold: 60.0,
+            },
+        ]
+    );
+    assert!(parse_role_rules("").is_empty());
+}
+
+#[cfg(test)]
+fn role_rule(role_id: u64, field: ProgressField, threshold: f64) -> RoleRule {
+    RoleRule {
+        role_id,
+        name: format!("Role {}", role_id),
+        field,
+        threshold,
+    }
+}
+
+#[test]
+fn every_satisfied_rule_on_a_field_is_granted() {
+    let rules = [
+        role_rule(1001, ProgressField::DinoRank, 10.0),
+        role_rule(1002, ProgressField::DinoRank, 30.0),
+        role_rule(1003, ProgressField::DinoRank, 31.0),
+    ];
+
+    assert_eq!(configured_roles(&mid_game_user(), &rules), vec![1001, 1002]);
+}
+
+#[test]
+fn rules_on_different_fields_are_granted_independently() {
+    let rules = [
+        role_rule(1001, ProgressField::Metabits, 1e9),
+        role_rule(1002, ProgressField::SingularitySpeedrunTime, 200.0),
+        role_rule(1003, ProgressField::SingularitySpeedrunTime, 100.0),
+        role_rule(1004, ProgressField::AllSharksObtained, 1.0),
+    ];
+
+    assert_eq!(configured_roles(&mid_game_user(), &rules), vec![1002]);
+
+    let sharks = UserData {
+        all_sharks_obtained: true,
+        metabits: 1_000_000_000,
+        ..UserData::default()
+    };
+    assert_eq!(configured_roles(&sharks, &rules), vec![1001, 1004]);
+}
+
+#[test]
+fn users_satisfying_no_rule_gain_no_configured_role() {
+    let rules = [
+        role_rule(1001, ProgressField::Metabits, 1e12),
+        role_rule(1002, ProgressField::SingularitySpeedrunTime, 60.0),
+        role_rule(1003, ProgressField::BeyondRank, 1.0),
+    ];
+
+    assert!(configured_roles(&UserData::default(), &rules).is_empty());
+    assert!(configured_roles(&mid_game_user(), &rules).is_empty());
+}
+
+#[test]
+fn a_rule_for_a_built_in_role_replaces_its_requirement() {
+    let rules = [role_rule(
+        roles::REALITY_EXPERT,
+        ProgressField::Metabits,
+        5e8,
+    )];
+
+    assert_eq!(
+        configured_roles(&mid_game_user(), &rules),
+        vec![roles::REALITY_EXPERT]
+    );
+    let trace = compute_earned_roles_with_trace(
+        &mid_game_user(),
+        &rules,
+        &[],
+        &RolePauses::default(),
+        &[],
+        SystemTime::now(),
+    )
+    .rules
+    .into_iter()
+    .find(|trace| trace.role_id == roles::REALITY_EXPERT.to_string())
+    .unwrap();
+    assert_eq!(trace.role_name, "Reality Expert");
+    assert_eq!(trace.threshold, Some(5e8));
+}
+
+#[test]
+fn requirements_cover_every_rule_without_a_value() {
+    let rules = [
+        role_rule(roles::REALITY_EXPERT, ProgressField::Metabits, 5e8),
+        role_rule(1001, ProgressField::BeyondRank, 3.0),
+    ];
+    let requirements = rule_requirements(&rules);
+
+    assert_eq!(requirements.len(), MILESTONE_RULES.len() + 1);
+    assert!(requirements.iter().all(|trace| trace.value.is_none()));
+    let expert = requirements
+        .iter()
+        .find(|trace| trace.role_id == roles::REALITY_EXPERT.to_string())
+        .unwrap();
+    assert_eq!(expert.threshold, Some(5e8));
+    assert_eq!(requirements.last().unwrap().field, Some("beyond_rank"));
+}
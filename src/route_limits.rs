@@ -0,0 +1,231 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Semaphore;
+
+use crate::{config::Config, net::Cidr, ttl_map::TtlMap};
+
+/// the length of a rate limit window
+pub const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// What kind of work a route does, each class gets its own rate limit bucket and concurrency
+/// semaphore so that heavy traffic on one class can't starve the others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RouteClass {
+    /// routes that write user data and talk to Discord
+    Mutation,
+    /// side-effect free routes like the roles preview
+    Read,
+    /// POST user/touch, it's a single cheap update the bot sends a lot of
+    Touch,
+    Admin,
+    /// unauthenticated routes anyone can call, these get the strictest limits
+    Public,
+    /// health checks and the like, these are never limited
+    Infra,
+}
+
+impl RouteClass {
+    /// the label used for this class in logs and metrics
+    pub fn label(&self) -> &'static str {
+        match self {
+            RouteClass::Mutation => "mutation",
+            RouteClass::Read => "read",
+            RouteClass::Touch => "touch",
+            RouteClass::Admin => "admin",
+            RouteClass::Public => "public",
+            RouteClass::Infra => "infra",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ClassLimits {
+    /// how many requests a single client can make within `RATE_LIMIT_WINDOW`
+    pub requests_per_window: u32,
+    /// how many requests of this class can be handled at the same time
+    pub max_concurrent: usize,
+}
+
+pub const RATE_LIMIT_LIMIT_HEADER: &str = "x-ratelimit-limit";
+pub const RATE_LIMIT_REMAINING_HEADER: &str = "x-ratelimit-remaining";
+pub const RATE_LIMIT_RESET_HEADER: &str = "x-ratelimit-reset";
+
+/// where a client's bucket stands once a request was counted against it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quota {
+    pub allowed: bool,
+    pub limit: u32,
+    /// the requests that are left in the window
+    pub remaining: u32,
+    /// until the window is over and the bucket is full again
+    pub reset_after: Duration,
+}
+
+impl Quota {
+    /// the `X-RateLimit-*` headers, the reset is in seconds and rounded up so clients that wait it
+    /// out never come back too early
+    pub fn headers(&self) -> [(&'static str, u64); 3] {
+        let reset_after =
+            self.reset_after.as_secs() + u64::from(self.reset_after.subsec_nanos() > 0);
+        [
+            (RATE_LIMIT_LIMIT_HEADER, u64::from(self.limit)),
+            (RATE_LIMIT_REMAINING_HEADER, u64::from(self.remaining)),
+            (RATE_LIMIT_RESET_HEADER, reset_after),
+        ]
+    }
+}
+
+/// fixed window rate limiter that keeps a separate bucket per route class and client
+pub struct RateLimiter {
+    buckets: Mutex<TtlMap<(RouteClass, String), u32>>,
+}
+
+impl RateLimiter {
+    pub fn new(window: Duration) -> Self {
+        RateLimiter {
+            buckets: Mutex::new(TtlMap::new(window)),
+        }
+    }
+
+    /// counts the request against the client's bucket for `class`, returns false once it's exhausted
+    pub fn try_acquire(&self, class: RouteClass, client: &str, limit: u32, now: Instant) -> bool {
+        self.acquire(class, client, limit, now).allowed
+    }
+
+    /// counts the request like `try_acquire` and returns what's left of the bucket
+    pub fn acquire(&self, class: RouteClass, client: &str, limit: u32, now: Instant) -> Quota {
+        let mut buckets = self.buckets.lock().unwrap();
+        let key = (class, client.to_owned());
+
+        let allowed = match buckets.get_mut(&key, now) {
+            Some(used) if *used >= limit => false,
+            Some(used) => {
+                *used += 1;
+                true
+            }
+            None => {
+                if buckets.len() > 10_000 {
+                    buckets.purge_expired(now);
+                }
+                buckets.insert(key.clone(), 1, now);
+                limit > 0
+            }
+        };
+
+        let used = buckets.get(&key, now).copied().unwrap_or(0);
+        Quota {
+            allowed,
+            limit,
+            remaining: limit.saturating_sub(used),
+            reset_after: buckets.remaining(&key, now).unwrap_or(buckets.ttl()),
+        }
+    }
+}
+
+/// the shared limiter state, it's created once and cloned into every worker
+#[derive(Clone)]
+pub struct RouteLimits {
+    pub limiter: Arc<RateLimiter>,
+    /// clients are identified the same way the og allowlist identifies them
+    pub trusted_proxies: Arc<Vec<Cidr>>,
+    classes: Arc<HashMap<RouteClass, (ClassLimits, Arc<Semaphore>)>>,
+}
+
+impl RouteLimits {
+    pub fn new(config: &Config) -> Self {
+        let classes = [
+            (RouteClass::Mutation, config.mutation_limits),
+            (RouteClass::Read, config.read_limits),
+            (RouteClass::Touch, config.touch_limits),
+            (RouteClass::Admin, config.admin_limits),
+            (RouteClass::Public, config.public_limits),
+        ]
+        .into_iter()
+        .map(|(class, limits)| {
+            (
+                class,
+                (limits, Arc::new(Semaphore::new(limits.max_concurrent))),
+            )
+        })
+        .collect();
+
+        RouteLimits {
+            limiter: Arc::new(RateLimiter::new(RATE_LIMIT_WINDOW)),
+            trusted_proxies: Arc::new(config.trusted_proxies.clone()),
+            classes: Arc::new(classes),
+        }
+    }
+
+    /// the limits and semaphore of a class, `None` means the class isn't limited
+    pub fn class(&self, class: RouteClass) -> Option<(ClassLimits, Arc<Semaphore>)> {
+        self.classes
+            .get(&class)
+            .map(|(limits, semaphore)| (*limits, semaphore.clone()))
+    }
+}
+
+#[test]
+fn read_flood_does_not_consume_mutation_bucket() {
+    let now = Instant::now();
+    let limiter = RateLimiter::new(RATE_LIMIT_WINDOW);
+
+    for _ in 0..300 {
+        assert!(limiter.try_acquire(RouteClass::Read, "127.0.0.1", 300, now));
+    }
+    assert!(!limiter.try_acquire(RouteClass::Read, "127.0.0.1", 300, now));
+
+    assert!(limiter.try_acquire(RouteClass::Mutation, "127.0.0.1", 30, now));
+    // the read bucket is refilled once the window passes
+    assert!(limiter.try_acquire(RouteClass::Read, "127.0.0.1", 300, now + RATE_LIMIT_WINDOW));
+}
+
+#[test]
+fn public_bucket_is_limited_per_client() {
+    let now = Instant::now();
+    let limiter = RateLimiter::new(RATE_LIMIT_WINDOW);
+
+    for _ in 0..20 {
+        assert!(limiter.try_acquire(RouteClass::Public, "10.0.0.1", 20, now));
+    }
+    assert!(!limiter.try_acquire(RouteClass::Public, "10.0.0.1", 20, now));
+    assert!(limiter.try_acquire(RouteClass::Public, "10.0.0.2", 20, now));
+}
+
+#[test]
+fn quota_headers_count_down_to_the_limit() {
+    let now = Instant::now();
+    let limiter = RateLimiter::new(RATE_LIMIT_WINDOW);
+    let headers = |quota: Quota| {
+        quota
+            .headers()
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect::<Vec<u64>>()
+    };
+
+    let first = limiter.acquire(RouteClass::Mutation, "10.0.0.1", 3, now);
+    assert!(first.allowed);
+    assert_eq!(headers(first), vec![3, 2, 60]);
+
+    let later = now + Duration::from_millis(20_500);
+    assert_eq!(
+        headers(limiter.acquire(RouteClass::Mutation, "10.0.0.1", 3, later)),
+        vec![3, 1, 40]
+    );
+    let last = limiter.acquire(RouteClass::Mutation, "10.0.0.1", 3, later);
+    assert!(last.allowed);
+    assert_eq!(headers(last), vec![3, 0, 40]);
+
+    // the rejected request reports the same window it's waiting on
+    let rejected = limiter.acquire(RouteClass::Mutation, "10.0.0.1", 3, later);
+    assert!(!rejected.allowed);
+    assert_eq!(headers(rejected), vec![3, 0, 40]);
+
+    let refilled = limiter.acquire(RouteClass::Mutation, "10.0.0.1", 3, now + RATE_LIMIT_WINDOW);
+    assert!(refilled.allowed);
+    assert_eq!(headers(refilled), vec![3, 2, 60]);
+}
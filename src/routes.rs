@@ -0,0 +1,398 @@
+use actix_web::http::Method;
+
+use crate::route_limits::RouteClass;
+
+/// Every endpoint of the API, middleware identifies requests by this instead of by their path so
+/// that renaming a path can't silently break rate limits or error grouping.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RouteId {
+    OgUpdateUser,
+    CreateUser,
+    GetUser,
+    UpdateUser,
+    DeleteUser,
+    UpdatePrivacy,
+    LinkRecoveryCredential,
+    RemoveRecoveryCredential,
+    GetOwnGrantedRoles,
+    GetOwnProgress,
+    ExplainOwnRoles,
+    TouchUser,
+    Ready,
+    Version,
+    Status,
+    StatusPage,
+    RegisterSupportCode,
+    PreviewRoles,
+    ValidateProgress,
+    GetRoleRules,
+    PublicLinked,
+    GetUserGrantedRoles,
+    GetUserRoleTrace,
+    GetUserById,
+    DeleteUserById,
+    UpdateBetaTester,
+    ResyncUserRoles,
+    ResyncAllRoles,
+    GetRoleResync,
+    FindUsersByFingerprint,
+    SimulateUser,
+    ExportPortableUser,
+    ImportPortableUser,
+    ImportUsers,
+    GetImportFailures,
+    PreviewDigest,
+    GetRecentErrors,
+    ClearRecentErrors,
+    WebhookStatus,
+    WriteBehindStatus,
+    NegativeCacheStatus,
+    GetClockSkew,
+    SyncTimingsStatus,
+    GetDeprecations,
+    GetSlo,
+    ReloadWebhook,
+    RefreshGuildRoleCache,
+    UpdateRoleRule,
+    CreatePromoRule,
+    CreateApiPartner,
+    GetApiPartners,
+    DeleteApiPartner,
+    GetStatsHistory,
+    GetStats,
+    ListUsers,
+    CreateLinkCodeQr,
+    /// paths that don't belong to any endpoint
+    Unknown,
+}
+
+impl RouteId {
+    /// every registered route, `Unknown` isn't one of them
+    pub const ALL: [RouteId; 56] = [
+        RouteId::OgUpdateUser,
+        RouteId::CreateUser,
+        RouteId::GetUser,
+        RouteId::UpdateUser,
+        RouteId::DeleteUser,
+        RouteId::UpdatePrivacy,
+        RouteId::LinkRecoveryCredential,
+        RouteId::RemoveRecoveryCredential,
+        RouteId::GetOwnGrantedRoles,
+        RouteId::GetOwnProgress,
+        RouteId::ExplainOwnRoles,
+        RouteId::TouchUser,
+        RouteId::Ready,
+        RouteId::Version,
+        RouteId::Status,
+        RouteId::StatusPage,
+        RouteId::RegisterSupportCode,
+        RouteId::PreviewRoles,
+        RouteId::ValidateProgress,
+        RouteId::GetRoleRules,
+        RouteId::PublicLinked,
+        RouteId::GetUserGrantedRoles,
+        RouteId::GetUserRoleTrace,
+        RouteId::GetUserById,
+        RouteId::DeleteUserById,
+        RouteId::UpdateBetaTester,
+        RouteId::ResyncUserRoles,
+        RouteId::ResyncAllRoles,
+        RouteId::GetRoleResync,
+        RouteId::FindUsersByFingerprint,
+        RouteId::SimulateUser,
+        RouteId::ExportPortableUser,
+        RouteId::ImportPortableUser,
+        RouteId::ImportUsers,
+        RouteId::GetImportFailures,
+        RouteId::PreviewDigest,
+        RouteId::GetRecentErrors,
+        RouteId::ClearRecentErrors,
+        RouteId::WebhookStatus,
+        RouteId::WriteBehindStatus,
+        RouteId::NegativeCacheStatus,
+        RouteId::GetClockSkew,
+        RouteId::SyncTimingsStatus,
+        RouteId::GetDeprecations,
+        RouteId::GetSlo,
+        RouteId::ReloadWebhook,
+        RouteId::RefreshGuildRoleCache,
+        RouteId::UpdateRoleRule,
+        RouteId::CreatePromoRule,
+        RouteId::CreateApiPartner,
+        RouteId::GetApiPartners,
+        RouteId::DeleteApiPartner,
+        RouteId::GetStatsHistory,
+        RouteId::GetStats,
+        RouteId::ListUsers,
+        RouteId::CreateLinkCodeQr,
+    ];
+
+    /// the method and full path pattern the route is registered with
+    pub fn registration(&self) -> Option<(Method, &'static str)> {
+        let registration = match self {
+            RouteId::OgUpdateUser => (Method::POST, "/userdata"),
+            RouteId::CreateUser => (Method::POST, "/v2/userdata"),
+            RouteId::GetUser => (Method::GET, "/v2/userdata"),
+            RouteId::UpdateUser => (Method::PATCH, "/v2/userdata"),
+            RouteId::DeleteUser => (Method::DELETE, "/v2/userdata"),
+            RouteId::UpdatePrivacy => (Method::PATCH, "/v2/userdata/privacy"),
+            RouteId::LinkRecoveryCredential => (Method::POST, "/user/recovery-credential"),
+            RouteId::RemoveRecoveryCredential => (Method::DELETE, "/user/recovery-credential"),
+            RouteId::GetOwnGrantedRoles => (Method::GET, "/user/granted-roles"),
+            RouteId::GetOwnProgress => (Method::GET, "/user/progress"),
+            RouteId::ExplainOwnRoles => (Method::GET, "/user/roles/explain"),
+            RouteId::TouchUser => (Method::POST, "/user/touch"),
+            RouteId::Ready => (Method::GET, "/health/ready"),
+            RouteId::Version => (Method::GET, "/version"),
+            RouteId::Status => (Method::GET, "/status"),
+            RouteId::StatusPage => (Method::GET, "/status.html"),
+            RouteId::RegisterSupportCode => (Method::POST, "/support-codes"),
+            RouteId::PreviewRoles => (Method::POST, "/user/roles/preview"),
+            RouteId::ValidateProgress => (Method::POST, "/user/validate"),
+            RouteId::GetRoleRules => (Method::GET, "/roles"),
+            RouteId::PublicLinked => (Method::GET, "/public/linked/{discord_id}"),
+            RouteId::GetUserGrantedRoles => {
+                (Method::GET, "/admin/users/{discord_id}/granted-roles")
+            }
+            RouteId::GetUserRoleTrace => (Method::GET, "/admin/users/{discord_id}/role-trace"),
+            RouteId::GetUserById => (Method::GET, "/admin/users/{discord_id}"),
+            RouteId::DeleteUserById => (Method::DELETE, "/admin/users/{discord_id}"),
+            RouteId::UpdateBetaTester => (Method::PATCH, "/admin/users/{discord_id}/beta"),
+            RouteId::ResyncUserRoles => (Method::POST, "/admin/users/{discord_id}/resync"),
+            RouteId::ResyncAllRoles => (Method::POST, "/admin/roles/resync"),
+            RouteId::GetRoleResync => (Method::GET, "/admin/roles/resync/{job_id}"),
+            RouteId::FindUsersByFingerprint => {
+                (Method::GET, "/admin/users/by-fingerprint/{fingerprint}")
+            }
+            RouteId::SimulateUser => (Method::POST, "/admin/users/{discord_id}/simulate"),
+            RouteId::ExportPortableUser => (Method::GET, "/admin/users/{discord_id}/portable"),
+            RouteId::ImportPortableUser => (Method::POST, "/admin/users/portable"),
+            RouteId::ImportUsers => (Method::POST, "/admin/import"),
+            RouteId::GetImportFailures => (Method::GET, "/admin/import/{job_id}/failures"),
+            RouteId::PreviewDigest => (Method::POST, "/admin/digest/preview"),
+            RouteId::GetRecentErrors => (Method::GET, "/admin/errors"),
+            RouteId::ClearRecentErrors => (Method::DELETE, "/admin/errors"),
+            RouteId::WebhookStatus => (Method::GET, "/admin/webhook-status"),
+            RouteId::WriteBehindStatus => (Method::GET, "/admin/write-behind-status"),
+            RouteId::NegativeCacheStatus => (Method::GET, "/admin/negative-cache-status"),
+            RouteId::GetClockSkew => (Method::GET, "/admin/clock-skew"),
+            RouteId::SyncTimingsStatus => (Method::GET, "/admin/sync-timings"),
+            RouteId::GetDeprecations => (Method::GET, "/admin/deprecations"),
+            RouteId::GetSlo => (Method::GET, "/admin/slo"),
+            RouteId::ReloadWebhook => (Method::POST, "/admin/webhook-reload"),
+            RouteId::RefreshGuildRoleCache => (Method::POST, "/admin/guild-roles/refresh"),
+            RouteId::UpdateRoleRule => (Method::PATCH, "/admin/role-rules/{role_id}"),
+            RouteId::CreatePromoRule => (Method::POST, "/admin/promo-rules"),
+            RouteId::CreateApiPartner => (Method::POST, "/admin/partners"),
+            RouteId::GetApiPartners => (Method::GET, "/admin/partners"),
+            RouteId::DeleteApiPartner => (Method::DELETE, "/admin/partners/{partner_id}"),
+            RouteId::GetStatsHistory => (Method::GET, "/stats/history"),
+            RouteId::GetStats => (Method::GET, "/admin/stats"),
+            RouteId::ListUsers => (Method::GET, "/admin/users"),
+            RouteId::CreateLinkCodeQr => (Method::POST, "/bot/link-codes/qr"),
+            RouteId::Unknown => return None,
+        };
+
+        Some(registration)
+    }
+
+    /// the label used for this route in logs and metrics, it's the name of the route's handler
+    pub fn label(&self) -> &'static str {
+        match self {
+            RouteId::OgUpdateUser => "og_update_user",
+            RouteId::CreateUser => "create_user",
+            RouteId::GetUser => "get_user",
+            RouteId::UpdateUser => "update_user",
+            RouteId::DeleteUser => "delete_user",
+            RouteId::UpdatePrivacy => "update_privacy",
+            RouteId::LinkRecoveryCredential => "link_recovery_credential",
+            RouteId::RemoveRecoveryCredential => "remove_recovery_credential",
+            RouteId::GetOwnGrantedRoles => "get_own_granted_roles",
+            RouteId::GetOwnProgress => "get_own_progress",
+            RouteId::ExplainOwnRoles => "explain_own_roles",
+            RouteId::TouchUser => "touch_user",
+            RouteId::Ready => "ready",
+            RouteId::Version => "version",
+            RouteId::Status => "get_status",
+            RouteId::StatusPage => "status_page",
+            RouteId::RegisterSupportCode => "register_support_code",
+            RouteId::PreviewRoles => "preview_roles",
+            RouteId::ValidateProgress => "validate_progress",
+            RouteId::GetRoleRules => "get_role_rules",
+            RouteId::PublicLinked => "public_linked",
+            RouteId::GetUserGrantedRoles => "get_user_granted_roles",
+            RouteId::GetUserRoleTrace => "get_user_role_trace",
+            RouteId::GetUserById => "get_user_by_id",
+            RouteId::DeleteUserById => "delete_user_by_id",
+            RouteId::UpdateBetaTester => "update_beta_tester",
+            RouteId::ResyncUserRoles => "resync_user_roles",
+            RouteId::ResyncAllRoles => "resync_all_roles",
+            RouteId::GetRoleResync => "get_role_resync",
+            RouteId::FindUsersByFingerprint => "find_users_by_fingerprint",
+            RouteId::SimulateUser => "simulate_user",
+            RouteId::ExportPortableUser => "export_portable_user",
+            RouteId::ImportPortableUser => "import_portable_user",
+            RouteId::ImportUsers => "import_users",
+            RouteId::GetImportFailures => "get_import_failures",
+            RouteId::PreviewDigest => "preview_digest",
+            RouteId::GetRecentErrors => "get_recent_errors",
+            RouteId::ClearRecentErrors => "clear_recent_errors",
+            RouteId::WebhookStatus => "webhook_status",
+            RouteId::WriteBehindStatus => "write_behind_status",
+            RouteId::NegativeCacheStatus => "negative_cache_status",
+            RouteId::GetClockSkew => "get_clock_skew",
+            RouteId::SyncTimingsStatus => "sync_timings_status",
+            RouteId::GetDeprecations => "get_deprecations",
+            RouteId::GetSlo => "get_slo",
+            RouteId::ReloadWebhook => "reload_webhook",
+            RouteId::RefreshGuildRoleCache => "refresh_guild_role_cache",
+            RouteId::UpdateRoleRule => "update_role_rule",
+            RouteId::CreatePromoRule => "create_promo_rule",
+            RouteId::CreateApiPartner => "create_api_partner",
+            RouteId::GetApiPartners => "get_api_partners",
+            RouteId::DeleteApiPartner => "delete_api_partner",
+            RouteId::GetStatsHistory => "get_stats_history",
+            RouteId::GetStats => "get_stats",
+            RouteId::ListUsers => "list_users",
+            RouteId::CreateLinkCodeQr => "create_link_code_qr",
+            RouteId::Unknown => "unknown",
+        }
+    }
+
+    pub fn class(&self) -> RouteClass {
+        match self {
+            RouteId::OgUpdateUser
+            | RouteId::CreateUser
+            | RouteId::UpdateUser
+            | RouteId::DeleteUser
+            | RouteId::UpdatePrivacy
+            | RouteId::LinkRecoveryCredential
+            | RouteId::RemoveRecoveryCredential
+            | RouteId::RegisterSupportCode => RouteClass::Mutation,
+            RouteId::GetUser
+            | RouteId::PreviewRoles
+            | RouteId::ValidateProgress
+            | RouteId::GetRoleRules
+            | RouteId::GetOwnGrantedRoles
+            | RouteId::GetOwnProgress
+            | RouteId::ExplainOwnRoles => RouteClass::Read,
+            RouteId::TouchUser => RouteClass::Touch,
+            RouteId::PublicLinked | RouteId::Status | RouteId::StatusPage => RouteClass::Public,
+            RouteId::SimulateUser
+            | RouteId::ExportPortableUser
+            | RouteId::ImportPortableUser
+            | RouteId::GetUserGrantedRoles
+            | RouteId::GetUserRoleTrace
+            | RouteId::GetUserById
+            | RouteId::DeleteUserById
+            | RouteId::UpdateBetaTester
+            | RouteId::ResyncUserRoles
+            | RouteId::ResyncAllRoles
+            | RouteId::GetRoleResync
+            | RouteId::FindUsersByFingerprint
+            | RouteId::ImportUsers
+            | RouteId::GetImportFailures
+            | RouteId::PreviewDigest
+            | RouteId::GetRecentErrors
+            | RouteId::ClearRecentErrors
+            | RouteId::WebhookStatus
+            | RouteId::WriteBehindStatus
+            | RouteId::NegativeCacheStatus
+            | RouteId::GetClockSkew
+            | RouteId::SyncTimingsStatus
+            | RouteId::GetDeprecations
+            | RouteId::GetSlo
+            | RouteId::ReloadWebhook
+            | RouteId::RefreshGuildRoleCache
+            | RouteId::UpdateRoleRule
+            | RouteId::CreatePromoRule
+            | RouteId::CreateApiPartner
+            | RouteId::GetApiPartners
+            | RouteId::DeleteApiPartner
+            | RouteId::GetStatsHistory
+            | RouteId::GetStats
+            | RouteId::ListUsers
+            | RouteId::CreateLinkCodeQr => RouteClass::Admin,
+            RouteId::Ready | RouteId::Version | RouteId::Unknown => RouteClass::Infra,
+        }
+    }
+
+    /// whether the route can write, everything but GET can
+    pub fn is_mutation(&self) -> bool {
+        self.registration()
+            .map_or(false, |(method, _)| method != Method::GET)
+    }
+
+    /// finds the route a request was matched to, `pattern` is the request's match pattern
+    pub fn resolve(method: &Method, pattern: Option<&str>) -> RouteId {
+        let pattern = match pattern {
+            Some(pattern) => pattern,
+            None => return RouteId::Unknown,
+        };
+
+        RouteId::ALL
+            .into_iter()
+            .find(|route| {
+                route
+                    .registration()
+                    .map_or(false, |(route_method, route_pattern)| {
+                        route_method == method && route_pattern == pattern
+                    })
+            })
+            .unwrap_or(RouteId::Unknown)
+    }
+}
+
+#[test]
+fn every_handler_has_a_route_id() {
+    // the handlers are registered through their attribute macros, so those are the service tree
+    let handlers = include_str!("handlers.rs");
+    let mut registered = 0;
+
+    for (line, next_line) in handlers.lines().zip(handlers.lines().skip(1)) {
+        let attribute = ["get", "post", "patch", "delete"]
+            .into_iter()
+            .find_map(|method| {
+                line.strip_prefix(&format!("#[{}(\"", method))
+                    .and_then(|rest| rest.strip_suffix("\")]"))
+                    .map(|path| (method, path))
+            });
+        let (method, path) = match attribute {
+            Some(attribute) => attribute,
+            None => continue,
+        };
+        let handler = next_line
+            .trim_start_matches("pub async fn ")
+            .split('(')
+            .next()
+            .unwrap();
+        registered += 1;
+
+        let route = RouteId::ALL
+            .into_iter()
+            .find(|route| route.label() == handler)
+            .unwrap_or_else(|| panic!("{} has no RouteId", handler));
+        let (route_method, route_pattern) = route.registration().unwrap();
+        assert_eq!(route_method.as_str(), method.to_uppercase(), "{}", handler);
+        assert!(route_pattern.ends_with(path), "{}", handler);
+    }
+
+    assert_eq!(registered, RouteId::ALL.len());
+}
+
+#[test]
+fn unregistered_paths_are_unknown() {
+    assert_eq!(
+        RouteId::resolve(&Method::GET, Some("/admin/errors")),
+        RouteId::GetRecentErrors
+    );
+    assert_eq!(
+        RouteId::resolve(&Method::DELETE, Some("/admin/errors")),
+        RouteId::ClearRecentErrors
+    );
+    assert_eq!(
+        RouteId::resolve(&Method::POST, Some("/admin/errors")),
+        RouteId::Unknown
+    );
+    assert_eq!(RouteId::resolve(&Method::GET, None), RouteId::Unknown);
+}
@@ -0,0 +1,142 @@
+use serde::Serialize;
+
+use crate::errors::MyError;
+
+/// how the schema's applied migrations compare to the ones this binary expects
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SchemaCheck {
+    Matched,
+    /// the schema is missing migrations, queries would fail on the columns they expect
+    Behind,
+    /// a newer binary migrated the schema, e.g. while an old one is still being rolled back to
+    Ahead,
+}
+
+pub fn check_schema(applied: i32, expected: i32) -> SchemaCheck {
+    match applied.cmp(&expected) {
+        std::cmp::Ordering::Equal => SchemaCheck::Matched,
+        std::cmp::Ordering::Less => SchemaCheck::Behind,
+        std::cmp::Ordering::Greater => SchemaCheck::Ahead,
+    }
+}
+
+/// The schema version the server started against, it's app data for the handlers. In
+/// compatibility mode the schema is newer than this binary, so reads are served but mutations
+/// are refused until the new binary is deployed.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct SchemaState {
+    pub expected_version: i32,
+    pub applied_version: i32,
+    pub compatibility_mode: bool,
+}
+
+impl SchemaState {
+    /// What the server starts in, a schema that's behind refuses to start unless
+    /// `serve_with_pending_migrations` is set.
+    pub fn at_startup(
+        applied: i32,
+        expected: i32,
+        serve_with_pending_migrations: bool,
+    ) -> Result<Self, String> {
+        let state = SchemaState {
+            expected_version: expected,
+            applied_version: applied,
+            compatibility_mode: false,
+        };
+        match check_schema(applied, expected) {
+            SchemaCheck::Matched => Ok(state),
+            SchemaCheck::Behind if serve_with_pending_migrations => Ok(state),
+            SchemaCheck::Behind => Err(format!(
+                "the database schema is at version {} but this binary expects {}, run the migrations or set SERVE_WITH_PENDING_MIGRATIONS",
+                applied, expected
+            )),
+            SchemaCheck::Ahead => Ok(SchemaState {
+                compatibility_mode: true,
+                ..state
+            }),
+        }
+    }
+}
+
+/// the 503 mutations are answered with in compatibility mode
+pub fn mid_upgrade_unavailable() -> MyError {
+    MyError::Unavailable("The service is mid-upgrade, please try again shortly")
+        .with_code("MID_UPGRADE")
+        .with_retry_after(30)
+}
+
+#[test]
+fn schemas_that_are_behind_refuse_to_start() {
+    let error = SchemaState::at_startup(20, 22, false).unwrap_err();
+    assert!(error.contains("at version 20 but this binary expects 22"));
+
+    // unless they're served anyway
+    let state = SchemaState::at_startup(20, 22, true).unwrap();
+    assert!(!state.compatibility_mode);
+    assert_eq!(state.applied_version, 20);
+}
+
+#[test]
+fn schemas_that_are_ahead_start_in_compatibility_mode() {
+    let state = SchemaState::at_startup(23, 22, false).unwrap();
+    assert!(state.compatibility_mode);
+    assert_eq!(
+        serde_json::to_value(state).unwrap(),
+        serde_json::json!({
+            "expected_version": 22,
+            "applied_version": 23,
+            "compatibility_mode": true,
+        })
+    );
+}
+
+#[test]
+fn matched_schemas_start_normally() {
+    assert_eq!(check_schema(22, 22), SchemaCheck::Matched);
+    assert_eq!(
+        SchemaState::at_startup(22, 22, false),
+        Ok(SchemaState {
+            expected_version: 22,
+            applied_version: 22,
+            compatibility_mode: false,
+        })
+    );
+}
+
+#[test]
+fn only_mutations_are_refused_mid_upgrade() {
+    use crate::middleware::TagRoute;
+    use actix_web::{dev::Service, http::StatusCode, test, web, App, HttpResponse};
+
+    let call = |compatibility_mode: bool, request: test::TestRequest| {
+        actix_web::rt::System::new().block_on(async move {
+            let app = test::init_service(
+                App::new()
+                    .wrap(TagRoute)
+                    .app_data(web::Data::new(SchemaState {
+                        expected_version: 22,
+                        applied_version: 23,
+                        compatibility_mode,
+                    }))
+                    .service(
+                        web::resource("/v2/userdata")
+                            .route(web::get().to(HttpResponse::Ok))
+                            .route(web::patch().to(HttpResponse::Ok)),
+                    ),
+            )
+            .await;
+            // the refusal is an error of the middleware, it's turned into a response by the server
+            match app.call(request.uri("/v2/userdata").to_request()).await {
+                Ok(response) => response.status(),
+                Err(error) => error.as_response_error().status_code(),
+            }
+        })
+    };
+
+    assert_eq!(
+        call(true, test::TestRequest::patch()),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_eq!(call(true, test::TestRequest::get()), StatusCode::OK);
+    assert_eq!(call(false, test::TestRequest::patch()), StatusCode::OK);
+}
@@ -0,0 +1,194 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use deadpool_postgres::{Hook, HookError, HookErrorCause, Pool, Runtime};
+use tokio_postgres::NoTls;
+
+/// the statement_timeout every pooled connection starts with in milliseconds, 0 is no timeout
+static STATEMENT_TIMEOUT_MS: AtomicU64 = AtomicU64::new(0);
+
+/// What every connection of the pool is set up with before it's handed out. The application name
+/// is what the connections show up as in pg_stat_activity, a timeout of 0 turns it off.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionSettings {
+    pub application_name: String,
+    pub statement_timeout: Duration,
+    pub idle_in_transaction_session_timeout: Duration,
+    pub search_path: Option<String>,
+}
+
+impl SessionSettings {
+    /// the SET commands that apply the settings, run as one batch on new connections
+    pub fn statements(&self) -> String {
+        let mut statements = format!(
+            "SET application_name = '{}'; SET statement_timeout = {}; SET idle_in_transaction_session_timeout = {};",
+            self.application_name.replace('\'', "''"),
+            self.statement_timeout.as_millis(),
+            self.idle_in_transaction_session_timeout.as_millis(),
+        );
+        if let Some(schema) = &self.search_path {
+            statements.push_str(&format!(" SET search_path = \"{}\";", schema));
+        }
+        statements
+    }
+}
+
+/// `userdata-api/{version}/{environment}`, Postgres cuts it off after 63 bytes
+pub fn application_name(environment: &str) -> String {
+    format!("userdata-api/{}/{}", env!("CARGO_PKG_VERSION"), environment)
+}
+
+/// the statement_timeout connections go back to once a request's budget no longer limits them
+pub fn session_statement_timeout() -> u64 {
+    STATEMENT_TIMEOUT_MS.load(Ordering::Relaxed)
+}
+
+/// the statement_timeout for a statement with `remaining` of its budget left, it's never longer
+/// than the session's own timeout
+pub fn budget_statement_timeout(remaining: Option<Duration>, session_timeout: u64) -> u64 {
+    match remaining {
+        Some(remaining) => {
+            let remaining = (remaining.as_millis() as u64).max(1);
+            if session_timeout == 0 {
+                remaining
+            } else {
+                remaining.min(session_timeout)
+            }
+        }
+        None => session_timeout,
+    }
+}
+
+/// Creates the pool with the settings applied to every new connection. One connection is made
+/// right away, a setting Postgres refuses fails here instead of with the first request.
+pub async fn create_pool(
+    pg: &deadpool_postgres::Config,
+    settings: SessionSettings,
+) -> Result<Pool, String> {
+    let statements = settings.statements();
+    let restore_timeout = format!(
+        "SET statement_timeout = {}",
+        settings.statement_timeout.as_millis()
+    );
+    let pool = pg
+        .builder(NoTls)
+        .map_err(|error| format!("invalid database config: {}", error))?
+        .runtime(Runtime::Tokio1)
+        .post_create(Hook::async_fn(move |client, _| {
+            let statements = statements.clone();
+            Box::pin(async move {
+                client
+                    .batch_execute(&statements)
+                    .await
+                    .map_err(|error| HookError::Abort(HookErrorCause::Backend(error)))
+            })
+        }))
+        // a request's budget lowers the timeout of its connection, the next one gets it back
+        .post_recycle(Hook::async_fn(move |client, _| {
+            let restore_timeout = restore_timeout.clone();
+            Box::pin(async move {
+                client
+                    .batch_execute(&restore_timeout)
+                    .await
+                    .map_err(|error| HookError::Continue(Some(HookErrorCause::Backend(error))))
+            })
+        }))
+        .build()
+        .map_err(|error| format!("failed at creating the database pool: {}", error))?;
+
+    drop(
+        pool.get()
+            .await
+            .map_err(|error| format!("failed at applying the session settings: {}", error))?,
+    );
+    STATEMENT_TIMEOUT_MS.store(
+        settings.statement_timeout.as_millis() as u64,
+        Ordering::Relaxed,
+    );
+    Ok(pool)
+}
+
+#[cfg(test)]
+fn test_settings(search_path: Option<&str>) -> SessionSettings {
+    SessionSettings {
+        application_name: application_name("test"),
+        statement_timeout: Duration::from_secs(30),
+        idle_in_transaction_session_timeout: Duration::from_secs(60),
+        search_path: search_path.map(str::to_owned),
+    }
+}
+
+#[test]
+fn settings_are_quoted() {
+    let settings = SessionSettings {
+        application_name: "it's".to_owned(),
+        ..test_settings(Some("c2s_beta"))
+    };
+
+    assert_eq!(
+        settings.statements(),
+        "SET application_name = 'it''s'; SET statement_timeout = 30000; SET idle_in_transaction_session_timeout = 60000; SET search_path = \"c2s_beta\";"
+    );
+    assert!(!test_settings(None).statements().contains("search_path"));
+}
+
+#[test]
+fn budgets_never_outlast_the_session_timeout() {
+    let seconds = Duration::from_secs;
+
+    assert_eq!(budget_statement_timeout(Some(seconds(5)), 30_000), 5_000);
+    assert_eq!(budget_statement_timeout(Some(seconds(60)), 30_000), 30_000);
+    assert_eq!(budget_statement_timeout(Some(seconds(60)), 0), 60_000);
+    assert_eq!(budget_statement_timeout(Some(Duration::ZERO), 0), 1);
+    assert_eq!(budget_statement_timeout(None, 30_000), 30_000);
+    assert_eq!(budget_statement_timeout(None, 0), 0);
+}
+
+#[test]
+#[ignore = "needs a Postgres database at TEST_DATABASE_URL"]
+fn pooled_connections_start_with_the_settings() {
+    actix_web::rt::System::new().block_on(async {
+        let pg = crate::db::test_pool_config(
+            &std::env::var("TEST_DATABASE_URL")
+                .expect("TEST_DATABASE_URL has to point at a Postgres database"),
+        );
+        let pool = create_pool(&pg, test_settings(Some("pg_catalog")))
+            .await
+            .unwrap();
+        let client = pool.get().await.unwrap();
+
+        let show = |setting: &'static str| {
+            let client = &client;
+            async move {
+                client
+                    .query_one(&format!("SHOW {}", setting), &[])
+                    .await
+                    .unwrap()
+                    .get::<_, String>(0)
+            }
+        };
+        assert_eq!(show("application_name").await, application_name("test"));
+        assert_eq!(show("statement_timeout").await, "30s");
+        assert_eq!(show("idle_in_transaction_session_timeout").await, "1min");
+        assert_eq!(show("search_path").await, "pg_catalog");
+
+        let listed: String = client
+            .query_one(
+                "SELECT application_name FROM pg_stat_activity WHERE pid = pg_backend_pid()",
+                &[],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(listed, application_name("test"));
+
+        // a value Postgres doesn't take fails the pool instead of the first request
+        let refused = SessionSettings {
+            statement_timeout: Duration::from_secs(u64::from(u32::MAX)),
+            ..test_settings(None)
+        };
+        assert!(create_pool(&pg, refused).await.is_err());
+    });
+}
@@ -0,0 +1,402 @@
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use crate::{config::Config, digest::last_week_window, routes::RouteId};
+
+/// responses are counted in buckets of this many seconds
+const BUCKET_SECONDS: u64 = 5 * 60;
+/// a bit more than a week, so the digest's week is still complete when it's sent
+const KEPT_FOR: u64 = 8 * 24 * 60 * 60;
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+static SLO_TRACKER: OnceLock<Mutex<SloTracker>> = OnceLock::new();
+
+/// what a route promises, e.g. syncs succeed within 2 seconds 99% of the time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SloTarget {
+    pub route: RouteId,
+    /// slower responses count against the objective even when they succeed
+    pub latency: Duration,
+    /// the share of responses that have to be good, between 0 and 1
+    pub objective: f64,
+}
+
+/// parses `"{route}:{milliseconds}:{percent},{route}:{milliseconds}:{percent}"`, the routes are
+/// named by their handler
+pub fn parse_slo_targets(targets: &str) -> Result<Vec<SloTarget>, String> {
+    targets
+        .split(',')
+        .map(str::trim)
+        .filter(|target| !target.is_empty())
+        .map(|target| {
+            let parts = target.split(':').collect::<Vec<&str>>();
+            let (route, latency, objective) = match parts[..] {
+                [route, latency, objective] => (route, latency, objective),
+                _ => return Err(format!("{} isn't route:milliseconds:percent", target)),
+            };
+            let route = RouteId::ALL
+                .into_iter()
+                .find(|known| known.label() == route)
+                .ok_or_else(|| format!("there's no route called {}", route))?;
+            let latency = latency
+                .parse::<u64>()
+                .map_err(|_| format!("{} isn't a number of milliseconds", latency))?;
+            let objective = objective
+                .parse::<f64>()
+                .ok()
+                .filter(|percent| *percent > 0.0 && *percent < 100.0)
+                .ok_or_else(|| format!("{} isn't a percentage below 100", objective))?;
+
+            Ok(SloTarget {
+                route,
+                latency: Duration::from_millis(latency),
+                objective: objective / 100.0,
+            })
+        })
+        .collect()
+}
+
+struct Bucket {
+    start: u64,
+    good: u64,
+    bad: u64,
+}
+
+/// how a route did against its objective within a window
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Attainment {
+    pub good: u64,
+    pub bad: u64,
+    /// the share of good responses, 1 when there weren't any
+    pub attainment: f64,
+    /// how much of the error budget the bad responses used, above 1 it's exhausted
+    pub budget_burn: f64,
+}
+
+impl Attainment {
+    pub fn new(good: u64, bad: u64, objective: f64) -> Self {
+        let total = good + bad;
+        if total == 0 {
+            return Attainment {
+                good,
+                bad,
+                attainment: 1.0,
+                budget_burn: 0.0,
+            };
+        }
+
+        Attainment {
+            good,
+            bad,
+            attainment: good as f64 / total as f64,
+            budget_burn: bad as f64 / ((1.0 - objective) * total as f64),
+        }
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.budget_burn > 1.0
+    }
+}
+
+/// a route's objective with how it did, what GET /admin/slo and the digest report
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct RouteSlo {
+    pub route: &'static str,
+    pub latency_ms: u64,
+    /// in percent
+    pub objective: f64,
+    pub last_hour: Attainment,
+    pub last_day: Attainment,
+    pub last_week: Attainment,
+}
+
+/// a route's week in the digest
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct WeeklySlo {
+    pub route: &'static str,
+    /// in percent
+    pub objective: f64,
+    pub week: Attainment,
+}
+
+impl WeeklySlo {
+    pub fn line(&self) -> String {
+        format!(
+            "{}: {:.2}% of {}%, {:.0}% of the error budget used",
+            self.route,
+            self.week.attainment * 100.0,
+            self.objective,
+            self.week.budget_burn * 100.0
+        )
+    }
+}
+
+/// A route whose error budget for the week was used up, it's reported once per week.
+#[derive(Debug, PartialEq)]
+pub struct SloAlert {
+    pub target: SloTarget,
+    pub week: Attainment,
+}
+
+impl SloAlert {
+    pub fn message(&self) -> String {
+        format!(
+            "{} used up its error budget for the week, {:.2}% of its {} responses this week were successful within {}ms while the objective is {}%",
+            self.target.route.label(),
+            self.week.attainment * 100.0,
+            self.week.good + self.week.bad,
+            self.target.latency.as_millis(),
+            self.target.objective * 100.0
+        )
+    }
+}
+
+/// Counts the good and bad responses of every route with an objective, in buckets that are kept
+/// for a little over a week.
+pub struct SloTracker {
+    targets: Vec<SloTarget>,
+    /// weeks start on Monday in this timezone, the same as the digest's
+    utc_offset_hours: i64,
+    buckets: HashMap<RouteId, VecDeque<Bucket>>,
+    /// the start of the week each route's exhausted budget was last reported in
+    alerted: HashMap<RouteId, u64>,
+}
+
+impl SloTracker {
+    pub fn new(targets: Vec<SloTarget>, utc_offset_hours: i64) -> Self {
+        SloTracker {
+            targets,
+            utc_offset_hours,
+            buckets: HashMap::new(),
+            alerted: HashMap::new(),
+        }
+    }
+
+    fn target(&self, route: RouteId) -> Option<SloTarget> {
+        self.targets
+            .iter()
+            .find(|target| target.route == route)
+            .copied()
+    }
+
+    /// Counts a response of the route, `now` is in seconds since the unix epoch. Returns an alert
+    /// when this response used up the rest of the route's error budget for the week.
+    pub fn record(
+        &mut self,
+        route: RouteId,
+        latency: Duration,
+        succeeded: bool,
+        now: u64,
+    ) -> Option<SloAlert> {
+        let target = self.target(route)?;
+        let good = succeeded && latency <= target.latency;
+
+        let buckets = self.buckets.entry(route).or_default();
+        let start = now - now % BUCKET_SECONDS;
+        if buckets.back().map_or(true, |bucket| bucket.start != start) {
+            buckets.push_back(Bucket {
+                start,
+                good: 0,
+                bad: 0,
+            });
+        }
+        let bucket = buckets.back_mut().unwrap();
+        if good {
+            bucket.good += 1;
+        } else {
+            bucket.bad += 1;
+        }
+        while buckets
+            .front()
+            .map_or(false, |bucket| now.saturating_sub(bucket.start) >= KEPT_FOR)
+        {
+            buckets.pop_front();
+        }
+
+        if good {
+            return None;
+        }
+        let (_, week_start) = last_week_window(now, self.utc_offset_hours);
+        if self.alerted.get(&route) == Some(&week_start) {
+            return None;
+        }
+        let week = self.attainment(route, (week_start, now + 1))?;
+        if !week.is_exhausted() {
+            return None;
+        }
+
+        self.alerted.insert(route, week_start);
+        Some(SloAlert { target, week })
+    }
+
+    /// the route's attainment within `[start, end)`, `None` for routes without an objective
+    pub fn attainment(&self, route: RouteId, (start, end): (u64, u64)) -> Option<Attainment> {
+        let target = self.target(route)?;
+        let (good, bad) = self
+            .buckets
+            .get(&route)
+            .into_iter()
+            .flatten()
+            .filter(|bucket| bucket.start >= start && bucket.start < end)
+            .fold((0, 0), |(good, bad), bucket| {
+                (good + bucket.good, bad + bucket.bad)
+            });
+
+        Some(Attainment::new(good, bad, target.objective))
+    }
+
+    /// every route with an objective, the windows end at `now`
+    pub fn summary(&self, now: u64) -> Vec<RouteSlo> {
+        let window = |seconds: u64, route: RouteId| {
+            self.attainment(route, (now.saturating_sub(seconds), now + 1))
+                .unwrap()
+        };
+
+        self.targets
+            .iter()
+            .map(|target| RouteSlo {
+                route: target.route.label(),
+                latency_ms: target.latency.as_millis() as u64,
+                objective: target.objective * 100.0,
+                last_hour: window(HOUR, target.route),
+                last_day: window(DAY, target.route),
+                last_week: window(WEEK, target.route),
+            })
+            .collect()
+    }
+
+    /// every route's attainment within the digest's week
+    pub fn week(&self, window: (u64, u64)) -> Vec<WeeklySlo> {
+        self.targets
+            .iter()
+            .map(|target| WeeklySlo {
+                route: target.route.label(),
+                objective: target.objective * 100.0,
+                week: self.attainment(target.route, window).unwrap(),
+            })
+            .collect()
+    }
+}
+
+pub fn slo_tracker() -> &'static Mutex<SloTracker> {
+    SLO_TRACKER.get_or_init(|| {
+        let config = Config::new();
+        Mutex::new(SloTracker::new(
+            config.slo_targets,
+            config.digest_utc_offset,
+        ))
+    })
+}
+
+#[cfg(test)]
+const SYNCS: SloTarget = SloTarget {
+    route: RouteId::UpdateUser,
+    latency: Duration::from_secs(2),
+    objective: 0.99,
+};
+
+// Monday 2024-01-08 00:00:00 UTC
+#[cfg(test)]
+const MONDAY: u64 = 1_704_672_000;
+
+#[test]
+fn slo_targets_are_parsed_by_route_label() {
+    assert_eq!(
+        parse_slo_targets("update_user:2000:99, og_update_user:1500:99.5,").unwrap(),
+        vec![
+            SYNCS,
+            SloTarget {
+                route: RouteId::OgUpdateUser,
+                latency: Duration::from_millis(1500),
+                objective: 0.995,
+            },
+        ]
+    );
+    assert_eq!(parse_slo_targets("").unwrap(), vec![]);
+    assert!(parse_slo_targets("sync:2000:99").is_err());
+    assert!(parse_slo_targets("update_user:2000").is_err());
+    assert!(parse_slo_targets("update_user:2s:99").is_err());
+    assert!(parse_slo_targets("update_user:2000:100").is_err());
+}
+
+#[test]
+fn slow_and_failed_responses_count_against_the_objective() {
+    let mut tracker = SloTracker::new(vec![SYNCS], 0);
+    let now = MONDAY + 10 * HOUR;
+
+    for _ in 0..397 {
+        tracker.record(RouteId::UpdateUser, Duration::from_millis(300), true, now);
+    }
+    // exactly at the threshold is still good
+    tracker.record(RouteId::UpdateUser, Duration::from_secs(2), true, now);
+    tracker.record(RouteId::UpdateUser, Duration::from_millis(2001), true, now);
+    tracker.record(RouteId::UpdateUser, Duration::from_millis(10), false, now);
+    // routes without an objective aren't tracked
+    assert_eq!(
+        tracker.record(RouteId::GetUser, Duration::from_secs(60), false, now),
+        None
+    );
+
+    let attainment = tracker
+        .attainment(RouteId::UpdateUser, (MONDAY, now + 1))
+        .unwrap();
+    assert_eq!((attainment.good, attainment.bad), (398, 2));
+    assert_eq!(attainment.attainment, 0.995);
+    // 1% of 400 responses is a budget of 4 bad ones
+    assert!((attainment.budget_burn - 0.5).abs() < 1e-9);
+    assert!(!attainment.is_exhausted());
+    assert_eq!(tracker.attainment(RouteId::GetUser, (MONDAY, now)), None);
+
+    let summary = tracker.summary(now + 2 * HOUR);
+    assert_eq!(summary[0].route, "update_user");
+    assert_eq!(summary[0].objective, 99.0);
+    assert_eq!(summary[0].last_hour, Attainment::new(0, 0, 0.99));
+    assert_eq!(summary[0].last_day, attainment);
+    // the buckets are gone once they're more than 8 days old
+    tracker.record(RouteId::UpdateUser, Duration::ZERO, true, now + KEPT_FOR);
+    assert_eq!(tracker.summary(now + KEPT_FOR)[0].last_week.good, 1);
+    assert_eq!(tracker.buckets[&RouteId::UpdateUser].len(), 1);
+}
+
+#[test]
+fn an_exhausted_budget_is_reported_once_a_week() {
+    let mut tracker = SloTracker::new(vec![SYNCS], 0);
+    let now = MONDAY + DAY;
+    for _ in 0..200 {
+        assert_eq!(
+            tracker.record(RouteId::UpdateUser, Duration::ZERO, true, now),
+            None
+        );
+    }
+
+    // a budget of 2 bad responses, the third exhausts it
+    let slow = Duration::from_secs(3);
+    assert_eq!(tracker.record(RouteId::UpdateUser, slow, true, now), None);
+    assert_eq!(tracker.record(RouteId::UpdateUser, slow, true, now), None);
+    let alert = tracker
+        .record(RouteId::UpdateUser, slow, true, now)
+        .unwrap();
+    assert_eq!((alert.week.good, alert.week.bad), (200, 3));
+    assert!(alert.message().starts_with(
+        "update_user used up its error budget for the week, 98.52% of its 203 responses"
+    ));
+    for _ in 0..10 {
+        assert_eq!(tracker.record(RouteId::UpdateUser, slow, false, now), None);
+    }
+
+    // the next week starts over, its first failure is already over its budget
+    let next_week = MONDAY + WEEK + HOUR;
+    assert!(tracker
+        .record(RouteId::UpdateUser, slow, false, next_week)
+        .is_some());
+    assert_eq!(
+        tracker.record(RouteId::UpdateUser, slow, false, next_week),
+        None
+    );
+}
@@ -0,0 +1,243 @@
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use std::fmt::Write;
+
+/// what the deploy checks can't do yet, these are listed so nobody mistakes them for passes
+const SKIPPED_STEPS: [(&str, &str); 5] = [
+    ("version", "the API has no version endpoint"),
+    (
+        "create user",
+        "there's no sandbox mode, it would link a real account",
+    ),
+    (
+        "update user",
+        "there's no sandbox mode, it would grant Discord roles",
+    ),
+    (
+        "fetch user",
+        "needs the user the create step would have made",
+    ),
+    (
+        "delete user",
+        "there's no sandbox mode, it would delete a real account",
+    ),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SmokeOutcome {
+    Passed,
+    Failed(String),
+    Skipped(&'static str),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SmokeStep {
+    pub name: &'static str,
+    pub outcome: SmokeOutcome,
+}
+
+/// the response has the status and is a JSON object with every field
+fn require_object(status: StatusCode, body: &Value, fields: &[&str]) -> Result<(), String> {
+    if status != StatusCode::OK {
+        return Err(format!("answered with {}", status));
+    }
+    let object = body.as_object().ok_or("the body isn't a JSON object")?;
+    match fields.iter().find(|field| !object.contains_key(**field)) {
+        Some(field) => Err(format!("the body has no `{}`", field)),
+        None => Ok(()),
+    }
+}
+
+async fn fetch(request: reqwest::RequestBuilder) -> Result<(StatusCode, Value), String> {
+    let response = request.send().await.map_err(|error| error.to_string())?;
+    let status = response.status();
+    let body = response
+        .json()
+        .await
+        .map_err(|_| format!("answered with {} and a body that isn't JSON", status))?;
+
+    Ok((status, body))
+}
+
+async fn check_health(client: &Client, base_url: &str) -> Result<(), String> {
+    let (status, body) = fetch(client.get(format!("{}/health/ready", base_url))).await?;
+    require_object(status, &body, &["database", "discord_paused_for"])
+}
+
+async fn check_status(client: &Client, base_url: &str) -> Result<(), String> {
+    let (status, body) = fetch(client.get(format!("{}/status", base_url))).await?;
+    require_object(
+        status,
+        &body,
+        &["state", "components", "incidents_last_24h"],
+    )
+}
+
+/// previewing roles writes nothing and doesn't contact Discord, so it's safe against production
+async fn check_preview_roles(client: &Client, base_url: &str) -> Result<(), String> {
+    let payload = json!({
+        "data": {
+            "metabits": 0,
+            "dino_rank": 0,
+            "prestige_rank": 0,
+            "beyond_rank": 0,
+            "singularity_speedrun_time": null,
+            "all_sharks_obtained": false,
+            "all_hidden_achievements_obtained": false
+        }
+    });
+    let (status, body) = fetch(
+        client
+            .post(format!("{}/user/roles/preview", base_url))
+            .json(&payload),
+    )
+    .await?;
+    if status != StatusCode::OK {
+        return Err(format!("answered with {}", status));
+    }
+
+    let roles = body.as_array().ok_or("the body isn't a JSON array")?;
+    for role in roles {
+        require_object(status, role, &["id", "name"])?;
+    }
+    Ok(())
+}
+
+/// Runs the deploy checks against the API at `base_url`. Only steps that neither write data nor
+/// contact Discord are run.
+pub async fn run_smoke(client: &Client, base_url: &str) -> Vec<SmokeStep> {
+    let base_url = base_url.trim_end_matches('/');
+    let outcome = |result: Result<(), String>| match result {
+        Ok(()) => SmokeOutcome::Passed,
+        Err(reason) => SmokeOutcome::Failed(reason),
+    };
+
+    let mut steps = vec![
+        SmokeStep {
+            name: "health",
+            outcome: outcome(check_health(client, base_url).await),
+        },
+        SmokeStep {
+            name: "status",
+            outcome: outcome(check_status(client, base_url).await),
+        },
+        SmokeStep {
+            name: "preview roles",
+            outcome: outcome(check_preview_roles(client, base_url).await),
+        },
+    ];
+    steps.extend(SKIPPED_STEPS.iter().map(|&(name, reason)| SmokeStep {
+        name,
+        outcome: SmokeOutcome::Skipped(reason),
+    }));
+
+    steps
+}
+
+pub fn all_passed(steps: &[SmokeStep]) -> bool {
+    !steps
+        .iter()
+        .any(|step| matches!(step.outcome, SmokeOutcome::Failed(_)))
+}
+
+pub fn render_table(steps: &[SmokeStep]) -> String {
+    let width = steps.iter().map(|step| step.name.len()).max().unwrap_or(0);
+    let mut table = String::new();
+    for step in steps {
+        let (result, detail) = match &step.outcome {
+            SmokeOutcome::Passed => ("PASS", ""),
+            SmokeOutcome::Failed(reason) => ("FAIL", reason.as_str()),
+            SmokeOutcome::Skipped(reason) => ("SKIP", *reason),
+        };
+        let _ = writeln!(
+            table,
+            "{:<width$}  {}  {}",
+            step.name,
+            result,
+            detail,
+            width = width
+        );
+    }
+
+    table
+}
+
+/// `discord-link smoke <base url>`, exits with 1 when a step failed
+pub async fn run_smoke_command(base_url: &str) -> i32 {
+    let steps = run_smoke(&Client::new(), base_url).await;
+    print!("{}", render_table(&steps));
+
+    if all_passed(&steps) {
+        0
+    } else {
+        1
+    }
+}
+
+#[test]
+#[ignore = "needs the webhook's environment variables, the health checks read them"]
+fn the_smoke_sequence_runs_against_a_test_server() {
+    use actix_web::{rt, web, App, HttpServer};
+
+    use crate::handlers::{get_status, preview_roles, ready};
+    use crate::schema_compat::SchemaState;
+
+    actix_web::rt::System::new().block_on(async {
+        // nothing listens on the port, so the database shows up as down
+        let pg = deadpool_postgres::Config {
+            host: Some("127.0.0.1".to_owned()),
+            port: Some(1),
+            dbname: Some("postgres".to_owned()),
+            ..deadpool_postgres::Config::default()
+        };
+        let pool = pg
+            .create_pool(
+                Some(deadpool_postgres::Runtime::Tokio1),
+                tokio_postgres::NoTls,
+            )
+            .unwrap();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(SchemaState {
+                    expected_version: 1,
+                    applied_version: 1,
+                    compatibility_mode: false,
+                }))
+                .service(web::scope("/health").service(ready))
+                .service(get_status)
+                .service(web::scope("/user").service(preview_roles))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let base_url = format!("http://{}/", server.addrs()[0]);
+        let server = server.run();
+        let handle = server.handle();
+        rt::spawn(server);
+
+        let steps = run_smoke(&Client::new(), &base_url).await;
+        handle.stop(false).await;
+
+        let outcome = |name: &str| {
+            steps
+                .iter()
+                .find(|step| step.name == name)
+                .unwrap()
+                .outcome
+                .clone()
+        };
+        assert_eq!(
+            outcome("health"),
+            SmokeOutcome::Failed("answered with 503 Service Unavailable".to_owned())
+        );
+        assert_eq!(outcome("status"), SmokeOutcome::Passed);
+        assert_eq!(outcome("preview roles"), SmokeOutcome::Passed);
+        assert!(matches!(outcome("create user"), SmokeOutcome::Skipped(_)));
+        assert!(!all_passed(&steps));
+
+        let table = render_table(&steps);
+        assert!(table.contains("preview roles  PASS"));
+        assert!(table.contains("health         FAIL  answered with 503"));
+    });
+}
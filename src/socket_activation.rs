@@ -0,0 +1,135 @@
+use std::{io, net::TcpListener};
+
+/// the first file descriptor systemd passes, see sd_listen_fds(3)
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// where the server listens, the mode is logged on startup
+pub enum ServerListener {
+    /// the socket systemd passed through `LISTEN_FDS`, it's kept open over restarts
+    Inherited(TcpListener),
+    /// `SERVER_ADDR`, bound by the server itself
+    Bound(String),
+}
+
+impl ServerListener {
+    /// the socket systemd passed when there is one, `server_addr` otherwise
+    pub fn from_env(server_addr: &str) -> io::Result<Self> {
+        let fds = passed_fds(
+            std::env::var("LISTEN_PID").ok().as_deref(),
+            std::env::var("LISTEN_FDS").ok().as_deref(),
+            std::process::id(),
+        );
+        // the variables were meant for this process only, not for anything it starts
+        for key in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            std::env::remove_var(key);
+        }
+
+        if fds == 0 {
+            return Ok(ServerListener::Bound(server_addr.to_owned()));
+        }
+        if fds > 1 {
+            println!(
+                "systemd passed {} sockets, only the first one is listened on",
+                fds
+            );
+        }
+        inherit_listener(SD_LISTEN_FDS_START).map(ServerListener::Inherited)
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            ServerListener::Inherited(listener) => match listener.local_addr() {
+                Ok(addr) => format!("http://{}/ on the socket systemd passed", addr),
+                Err(_) => "the socket systemd passed".to_owned(),
+            },
+            ServerListener::Bound(server_addr) => format!("http://{}/", server_addr),
+        }
+    }
+}
+
+/// How many sockets systemd passed to this process. `LISTEN_PID` has to be ours, otherwise the
+/// variables were inherited from a parent that was socket activated.
+pub fn passed_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> usize {
+    match (
+        listen_pid.and_then(|listen_pid| listen_pid.parse::<u32>().ok()),
+        listen_fds,
+    ) {
+        (Some(listen_pid), Some(listen_fds)) if listen_pid == pid => {
+            listen_fds.parse().unwrap_or(0)
+        }
+        _ => 0,
+    }
+}
+
+/// takes ownership of a passed socket, it has to be a listening TCP socket nothing else owns
+#[cfg(unix)]
+pub fn inherit_listener(fd: i32) -> io::Result<TcpListener> {
+    use std::os::unix::io::FromRawFd;
+
+    // SAFETY: systemd hands the descriptors over to this process and they're only taken once,
+    // `from_env` removes the variables that point at them
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    // fails when the descriptor isn't a socket
+    listener.local_addr()?;
+    listener.set_nonblocking(true)?;
+
+    Ok(listener)
+}
+
+#[cfg(not(unix))]
+pub fn inherit_listener(_fd: i32) -> io::Result<TcpListener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "socket activation is only supported on unix",
+    ))
+}
+
+#[test]
+fn only_sockets_passed_to_this_process_are_used() {
+    assert_eq!(passed_fds(Some("42"), Some("1"), 42), 1);
+    assert_eq!(passed_fds(Some("42"), Some("2"), 42), 2);
+    // meant for the parent
+    assert_eq!(passed_fds(Some("41"), Some("1"), 42), 0);
+    assert_eq!(passed_fds(None, Some("1"), 42), 0);
+    assert_eq!(passed_fds(Some("42"), None, 42), 0);
+    assert_eq!(passed_fds(Some("42"), Some("many"), 42), 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn requests_are_served_over_an_inherited_listener() {
+    use actix_web::{rt, web, App, HttpResponse, HttpServer};
+    use std::os::unix::io::IntoRawFd;
+
+    actix_web::rt::System::new().block_on(async {
+        let pre_bound = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let addr = pre_bound.local_addr().unwrap();
+        let listener = inherit_listener(pre_bound.into_raw_fd()).unwrap();
+        assert_eq!(
+            ServerListener::Inherited(listener.try_clone().unwrap()).describe(),
+            format!("http://{}/ on the socket systemd passed", addr)
+        );
+
+        let server =
+            HttpServer::new(|| App::new().route("/status", web::get().to(HttpResponse::Ok)))
+                .workers(1)
+                .listen(listener)
+                .unwrap()
+                .run();
+        let handle = server.handle();
+        rt::spawn(server);
+
+        let response = reqwest::get(format!("http://{}/status", addr))
+            .await
+            .unwrap();
+        handle.stop(false).await;
+
+        assert_eq!(response.status(), 200);
+    });
+}
+
+#[test]
+fn servers_bind_their_own_listener_without_systemd() {
+    let listener = ServerListener::Bound("0.0.0.0:8080".to_owned());
+    assert_eq!(listener.describe(), "http://0.0.0.0:8080/");
+}
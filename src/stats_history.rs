@@ -0,0 +1,162 @@
+use actix_web::rt::{self, time};
+use deadpool_postgres::Pool;
+use serde::Serialize;
+use std::time::{Duration, SystemTime};
+
+use crate::{
+    constants::{ErrorLogType, LOG},
+    db,
+    digest::unix_now,
+    errors::{ConvertResultErrorToMyError, LogMyError, MyError},
+    webhook_logging::webhook_log,
+};
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// the aggregates that are snapshotted every day, the same ones the weekly digest reads from the
+/// database
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatsMetric {
+    TotalLinked,
+    /// the accounts that were linked for the first time that day
+    NewLinks,
+}
+
+impl StatsMetric {
+    pub const ALL: [StatsMetric; 2] = [StatsMetric::TotalLinked, StatsMetric::NewLinks];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StatsMetric::TotalLinked => "total_linked",
+            StatsMetric::NewLinks => "new_links",
+        }
+    }
+
+    /// only the names in `ALL` are metrics, they're what's put into the query
+    pub fn from_name(name: &str) -> Option<StatsMetric> {
+        StatsMetric::ALL
+            .into_iter()
+            .find(|metric| metric.name() == name)
+    }
+}
+
+/// a day of the history, `value` is `None` for a day nothing was snapshotted on
+#[derive(Serialize, Debug, PartialEq)]
+pub struct StatsPoint {
+    /// the start of the UTC day
+    #[serde(serialize_with = "crate::timestamps::unix_seconds::serialize")]
+    pub day: u64,
+    pub value: Option<i64>,
+}
+
+/// the UTC day `unix_seconds` is in, counted from the unix epoch
+pub fn unix_day(unix_seconds: u64) -> i64 {
+    (unix_seconds / DAY) as i64
+}
+
+/// Every day from `first_day` to `last_day` with its snapshot, days without one are left as
+/// `None` instead of being filled in from their neighbours.
+pub fn daily_series(snapshots: &[(i64, i64)], first_day: i64, last_day: i64) -> Vec<StatsPoint> {
+    (first_day..=last_day)
+        .map(|day| StatsPoint {
+            day: day.max(0) as u64 * DAY,
+            value: snapshots
+                .iter()
+                .find(|(snapshot_day, _)| *snapshot_day == day)
+                .map(|(_, value)| *value),
+        })
+        .collect()
+}
+
+/// snapshots every metric for the UTC day `day`, new links are counted within that day
+pub async fn snapshot_stats(pool: &Pool, day: i64) -> Result<(), MyError> {
+    let client = pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "snapshotting stats failed at creating database client",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(day as u64 * DAY);
+    let total_linked = db::count_userdata(&client)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at counting the linked users for the stats snapshot",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+    let new_links = db::count_new_links(&client, &start, &(start + Duration::from_secs(DAY)))
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at counting the new links for the stats snapshot",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let metrics = StatsMetric::ALL.map(StatsMetric::name);
+    let values = StatsMetric::ALL.map(|metric| match metric {
+        StatsMetric::TotalLinked => total_linked,
+        StatsMetric::NewLinks => new_links,
+    });
+    db::upsert_stats_snapshot(&client, day, &metrics, &values)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at writing the stats snapshot",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await
+}
+
+/// snapshots the day that just ended every night at UTC midnight
+pub fn spawn_stats_snapshot_scheduler(pool: Pool) {
+    rt::spawn(async move {
+        loop {
+            let now = unix_now();
+            time::sleep(Duration::from_secs(DAY - now % DAY)).await;
+
+            let ended_day = unix_day(unix_now()) - 1;
+            if let Err(error) = snapshot_stats(&pool, ended_day).await {
+                webhook_log(
+                    format!("the daily stats snapshot failed: {}", error),
+                    LOG::FAILURE,
+                )
+                .await;
+            }
+        }
+    });
+}
+
+#[test]
+fn only_whitelisted_metrics_are_known() {
+    assert_eq!(
+        StatsMetric::from_name("total_linked"),
+        Some(StatsMetric::TotalLinked)
+    );
+    assert_eq!(
+        StatsMetric::from_name("new_links"),
+        Some(StatsMetric::NewLinks)
+    );
+    for name in ["", "TOTAL_LINKED", "total_linked; DROP TABLE", "deletions"] {
+        assert_eq!(StatsMetric::from_name(name), None);
+    }
+}
+
+#[test]
+fn missing_days_are_nulls() {
+    let series = daily_series(&[(100, 10), (103, 14)], 99, 103);
+
+    assert_eq!(
+        series
+            .iter()
+            .map(|point| point.value)
+            .collect::<Vec<Option<i64>>>(),
+        vec![None, Some(10), None, None, Some(14)]
+    );
+    assert_eq!(series[1].day, 100 * DAY);
+    assert_eq!(
+        serde_json::to_value(&series[2]).unwrap(),
+        serde_json::json!({ "day": "1970-04-12T00:00:00.000Z", "value": null })
+    );
+}
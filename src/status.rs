@@ -0,0 +1,418 @@
+use deadpool_postgres::Pool;
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    discord_pause::discord_pause, failover::read_only_state, webhook_logging::webhook_health,
+};
+
+/// how long a status summary is served before the components are checked again
+pub const STATUS_CACHE_TTL: Duration = Duration::from_secs(30);
+/// how far back incidents are counted, in seconds
+pub const INCIDENT_WINDOW: u64 = 24 * 60 * 60;
+
+static STATUS_HISTORY: OnceLock<Mutex<StatusHistory>> = OnceLock::new();
+static STATUS_CACHE: OnceLock<Mutex<Option<(Instant, StatusSummary)>>> = OnceLock::new();
+
+/// the parts of the API the status page reports on, nothing more specific than these is shown
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Component {
+    Database,
+    Discord,
+    WebhookDelivery,
+}
+
+impl Component {
+    pub const ALL: [Component; 3] = [
+        Component::Database,
+        Component::Discord,
+        Component::WebhookDelivery,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Component::Database => "Database",
+            Component::Discord => "Discord",
+            Component::WebhookDelivery => "Webhook delivery",
+        }
+    }
+}
+
+/// ordered from best to worst, the overall state is the worst of the components'
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum ComponentState {
+    Operational,
+    Degraded,
+    Down,
+}
+
+impl ComponentState {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ComponentState::Operational => "Operational",
+            ComponentState::Degraded => "Degraded",
+            ComponentState::Down => "Down",
+        }
+    }
+}
+
+/// What a health check saw. It's only flags on purpose, so hostnames, pool sizes and the like
+/// have no way into the public summary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Observation {
+    pub database: bool,
+    /// a write was still refused as read only after retrying it, a failover is going on
+    pub database_read_only: bool,
+    /// Discord calls are paused after a global rate limit
+    pub discord_paused: bool,
+    pub webhook_dead: bool,
+}
+
+impl Observation {
+    pub fn state(&self, component: Component) -> ComponentState {
+        let healthy = match component {
+            Component::Database => self.database,
+            Component::Discord => !self.discord_paused,
+            Component::WebhookDelivery => !self.webhook_dead,
+        };
+
+        match (component, healthy) {
+            (Component::Database, true) if self.database_read_only => ComponentState::Degraded,
+            (_, true) => ComponentState::Operational,
+            // syncs still work without Discord, the roles just follow once the pause is over
+            (Component::Discord, false) => ComponentState::Degraded,
+            (_, false) => ComponentState::Down,
+        }
+    }
+}
+
+struct Transition {
+    component: Component,
+    from: ComponentState,
+    to: ComponentState,
+    /// seconds since the unix epoch
+    at: u64,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ComponentStatus {
+    pub component: Component,
+    pub state: ComponentState,
+    /// seconds since the unix epoch the component has been in its state since
+    #[serde(serialize_with = "crate::timestamps::unix_seconds::serialize")]
+    pub since: u64,
+    pub for_seconds: u64,
+    pub incidents_last_24h: u32,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct StatusSummary {
+    pub state: ComponentState,
+    pub components: Vec<ComponentStatus>,
+    pub incidents_last_24h: u32,
+}
+
+/// The state transitions of every component, transitions older than `INCIDENT_WINDOW` are dropped.
+pub struct StatusHistory {
+    current: Vec<(Component, ComponentState, u64)>,
+    transitions: VecDeque<Transition>,
+}
+
+impl StatusHistory {
+    /// everything starts out operational
+    pub fn new(now: u64) -> Self {
+        StatusHistory {
+            current: Component::ALL
+                .into_iter()
+                .map(|component| (component, ComponentState::Operational, now))
+                .collect(),
+            transitions: VecDeque::new(),
+        }
+    }
+
+    pub fn observe(&mut self, observation: &Observation, now: u64) {
+        for component in Component::ALL {
+            self.record(component, observation.state(component), now);
+        }
+    }
+
+    pub fn record(&mut self, component: Component, state: ComponentState, now: u64) {
+        if let Some((_, current, since)) = self
+            .current
+            .iter_mut()
+            .find(|(current_component, _, _)| *current_component == component)
+        {
+            if *current != state {
+                self.transitions.push_back(Transition {
+                    component,
+                    from: *current,
+                    to: state,
+                    at: now,
+                });
+                *current = state;
+                *since = now;
+            }
+        }
+
+        while let Some(oldest) = self.transitions.front() {
+            if now.saturating_sub(oldest.at) < INCIDENT_WINDOW {
+                break;
+            }
+            self.transitions.pop_front();
+        }
+    }
+
+    /// an incident starts whenever an operational component stops being operational
+    fn incidents(&self, component: Component, now: u64) -> u32 {
+        self.transitions
+            .iter()
+            .filter(|transition| {
+                transition.component == component
+                    && transition.from == ComponentState::Operational
+                    && transition.to != ComponentState::Operational
+                    && now.saturating_sub(transition.at) < INCIDENT_WINDOW
+            })
+            .count() as u32
+    }
+
+    pub fn summary(&self, now: u64) -> StatusSummary {
+        let components = self
+            .current
+            .iter()
+            .map(|(component, state, since)| ComponentStatus {
+                component: *component,
+                state: *state,
+                since: *since,
+                for_seconds: now.saturating_sub(*since),
+                incidents_last_24h: self.incidents(*component, now),
+            })
+            .collect::<Vec<ComponentStatus>>();
+
+        StatusSummary {
+            state: components
+                .iter()
+                .map(|component| component.state)
+                .max()
+                .unwrap_or(ComponentState::Operational),
+            incidents_last_24h: components
+                .iter()
+                .map(|component| component.incidents_last_24h)
+                .sum(),
+            components,
+        }
+    }
+}
+
+pub fn status_history() -> &'static Mutex<StatusHistory> {
+    STATUS_HISTORY.get_or_init(|| Mutex::new(StatusHistory::new(crate::digest::unix_now())))
+}
+
+fn status_cache() -> &'static Mutex<Option<(Instant, StatusSummary)>> {
+    STATUS_CACHE.get_or_init(|| Mutex::new(None))
+}
+
+/// checks every component, the health check and the status page both go through this
+pub async fn observe(pool: &Pool) -> Observation {
+    let observation = Observation {
+        database: pool.get().await.is_ok(),
+        database_read_only: read_only_state()
+            .lock()
+            .unwrap()
+            .degraded_for(Instant::now())
+            .is_some(),
+        discord_paused: discord_pause()
+            .lock()
+            .unwrap()
+            .remaining(Instant::now())
+            .is_some(),
+        webhook_dead: webhook_health().lock().unwrap().status().dead,
+    };
+    status_history()
+        .lock()
+        .unwrap()
+        .observe(&observation, crate::digest::unix_now());
+
+    observation
+}
+
+/// the summary served publicly, the components are checked at most once per `STATUS_CACHE_TTL`
+pub async fn current_status(pool: &Pool) -> StatusSummary {
+    if let Some((checked_at, summary)) = status_cache().lock().unwrap().as_ref() {
+        if checked_at.elapsed() < STATUS_CACHE_TTL {
+            return summary.clone();
+        }
+    }
+
+    observe(pool).await;
+    let summary = status_history()
+        .lock()
+        .unwrap()
+        .summary(crate::digest::unix_now());
+    *status_cache().lock().unwrap() = Some((Instant::now(), summary.clone()));
+
+    summary
+}
+
+fn describe_duration(seconds: u64) -> String {
+    match seconds {
+        0..=119 => format!("{} seconds", seconds),
+        120..=7_199 => format!("{} minutes", seconds / 60),
+        7_200..=172_799 => format!("{} hours", seconds / (60 * 60)),
+        _ => format!("{} days", seconds / (24 * 60 * 60)),
+    }
+}
+
+/// a tiny page for people who'd rather not read JSON, everything in it comes from the summary
+pub fn render_status_html(summary: &StatusSummary) -> String {
+    let rows = summary
+        .components
+        .iter()
+        .map(|component| {
+            format!(
+                "<tr><td>{}</td><td class=\"{:?}\">{}</td><td>for {}</td><td>{}</td></tr>",
+                component.component.label(),
+                component.state,
+                component.state.label(),
+                describe_duration(component.for_seconds),
+                component.incidents_last_24h
+            )
+        })
+        .collect::<String>();
+
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Link API status</title>\
+         <style>.Operational{{color:green}}.Degraded{{color:orange}}.Down{{color:red}}</style></head>\
+         <body><h1>Link API: <span class=\"{:?}\">{}</span></h1>\
+         <table><tr><th>Component</th><th>State</th><th>Since</th><th>Incidents (24h)</th></tr>{}</table>\
+         </body></html>",
+        summary.state,
+        summary.state.label(),
+        rows
+    )
+}
+
+#[cfg(test)]
+const HEALTHY: Observation = Observation {
+    database: true,
+    database_read_only: false,
+    discord_paused: false,
+    webhook_dead: false,
+};
+
+#[test]
+fn incidents_are_counted_from_transitions() {
+    let mut history = StatusHistory::new(0);
+    let database_down = Observation {
+        database: false,
+        ..HEALTHY
+    };
+
+    // the database goes down twice, being down for several checks is still one incident
+    history.observe(&database_down, 100);
+    history.observe(&database_down, 160);
+    history.observe(&HEALTHY, 200);
+    history.observe(&database_down, 1_000);
+    history.observe(&HEALTHY, 1_100);
+    // Discord is paused once and still is
+    history.observe(
+        &Observation {
+            discord_paused: true,
+            ..HEALTHY
+        },
+        1_200,
+    );
+
+    let summary = history.summary(1_500);
+    assert_eq!(summary.state, ComponentState::Degraded);
+    assert_eq!(summary.incidents_last_24h, 3);
+    assert_eq!(
+        summary.components[0],
+        ComponentStatus {
+            component: Component::Database,
+            state: ComponentState::Operational,
+            since: 1_100,
+            for_seconds: 400,
+            incidents_last_24h: 2,
+        }
+    );
+    assert_eq!(summary.components[1].state, ComponentState::Degraded);
+    assert_eq!(summary.components[1].for_seconds, 300);
+    assert_eq!(summary.components[2].incidents_last_24h, 0);
+
+    // a day later only the Discord incident that's still going on is in the window
+    let summary = history.summary(1_000 + INCIDENT_WINDOW);
+    assert_eq!(summary.components[0].incidents_last_24h, 0);
+    assert_eq!(summary.incidents_last_24h, 1);
+    history.observe(&HEALTHY, 1_200 + INCIDENT_WINDOW);
+    assert_eq!(history.transitions.len(), 1);
+}
+
+#[test]
+fn a_read_only_database_is_degraded() {
+    let read_only = Observation {
+        database_read_only: true,
+        ..HEALTHY
+    };
+    assert_eq!(
+        read_only.state(Component::Database),
+        ComponentState::Degraded
+    );
+    // being unreachable is worse than being read only
+    assert_eq!(
+        Observation {
+            database: false,
+            ..read_only
+        }
+        .state(Component::Database),
+        ComponentState::Down
+    );
+}
+
+#[test]
+fn the_summary_shows_nothing_internal() {
+    let mut history = StatusHistory::new(0);
+    history.observe(
+        &Observation {
+            webhook_dead: true,
+            ..HEALTHY
+        },
+        10,
+    );
+    let summary = history.summary(70);
+
+    let json = serde_json::to_value(&summary).unwrap();
+    let mut keys = json.as_object().unwrap().keys().collect::<Vec<&String>>();
+    keys.sort();
+    assert_eq!(keys, ["components", "incidents_last_24h", "state"]);
+    let mut component_keys = json["components"][2]
+        .as_object()
+        .unwrap()
+        .keys()
+        .collect::<Vec<&String>>();
+    component_keys.sort();
+    assert_eq!(
+        component_keys,
+        [
+            "component",
+            "for_seconds",
+            "incidents_last_24h",
+            "since",
+            "state"
+        ]
+    );
+    assert_eq!(json["components"][2]["component"], "webhookDelivery");
+    assert_eq!(json["state"], "down");
+
+    let html = render_status_html(&summary);
+    assert!(html.contains("<tr><td>Webhook delivery</td><td class=\"Down\">Down</td><td>for 60 seconds</td><td>1</td></tr>"));
+    for internal in ["host", "pool", "webhook_id", "token"] {
+        assert!(!json.to_string().contains(internal), "{}", internal);
+        assert!(!html.to_lowercase().contains(internal), "{}", internal);
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use actix_web::Error;
+use deadpool_postgres::Client;
+
+use crate::{
+    constants::ErrorLogType,
+    db,
+    errors::{ConvertResultErrorToMyError, LogMyError, MyError},
+    ttl_map::TtlMap,
+    utilities::{safe_basic_auth_decoder, AuthData, DerivedTokens},
+};
+
+/// how long a resolved support code is trusted before it's looked up again
+pub const SUPPORT_CODE_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+static SUPPORT_CODE_CACHE: OnceLock<Mutex<TtlMap<String, DerivedTokens>>> = OnceLock::new();
+
+/// The tokens a support code resolved to, put in the request extensions by the authorization
+/// middleware since there's no email and token to derive them from.
+#[derive(Clone)]
+pub struct ResolvedSupportCode(pub DerivedTokens);
+
+/// the schemes that are accepted in the authorization header
+#[derive(Debug, PartialEq)]
+pub enum AuthScheme {
+    /// `"Basic {base64(email:player_token)}"`
+    Basic(AuthData),
+    /// `"SupportCode {code}"`, the code the game shows on platforms that hide the email and token
+    SupportCode(String),
+}
+
+pub fn parse_auth_scheme(auth_header: &str) -> Result<AuthScheme, Error> {
+    match auth_header.split_whitespace().collect::<Vec<_>>()[..] {
+        ["SupportCode", code] => Ok(AuthScheme::SupportCode(code.to_owned())),
+        _ => safe_basic_auth_decoder(auth_header).map(AuthScheme::Basic),
+    }
+}
+
+pub fn support_code_cache() -> &'static Mutex<TtlMap<String, DerivedTokens>> {
+    SUPPORT_CODE_CACHE.get_or_init(|| Mutex::new(TtlMap::new(SUPPORT_CODE_CACHE_TTL)))
+}
+
+/// drops the cached resolution of a code, this has to happen whenever its mapping changes
+pub fn invalidate_support_code(cache: &Mutex<TtlMap<String, DerivedTokens>>, code: &str) {
+    cache.lock().unwrap().remove(&code.to_owned());
+}
+
+/// Resolves a support code to the tokens of the credentials it was registered with, `None` means
+/// the code is unknown.
+pub async fn resolve_support_code(
+    client: &Client,
+    cache: &Mutex<TtlMap<String, DerivedTokens>>,
+    code: &str,
+) -> Result<Option<DerivedTokens>, MyError> {
+    let code = code.to_owned();
+    if let Some(user_tokens) = cache.lock().unwrap().get(&code, Instant::now()) {
+        return Ok(Some(user_tokens.clone()));
+    }
+
+    let user_tokens = match db::get_support_code(client, &code).await {
+        Ok(record) => record.user_tokens(),
+        Err(tokio_pg_mapper::Error::ColumnNotFound) => return Ok(None),
+        Err(error) => {
+            return Err(error)
+                .make_response(MyError::InternalError(
+                    "Failed at looking up the support code, please try again",
+                ))
+                .make_log(ErrorLogType::INTERNAL)
+                .await
+        }
+    };
+
+    cache
+        .lock()
+        .unwrap()
+        .insert(code, user_tokens.clone(), Instant::now());
+    Ok(Some(user_tokens))
+}
+
+#[test]
+fn parses_both_authorization_schemes() {
+    assert_eq!(
+        parse_auth_scheme("SupportCode ABC123").unwrap(),
+        AuthScheme::SupportCode("ABC123".to_owned())
+    );
+    assert_eq!(
+        parse_auth_scheme(&format!("Basic {}", base64::encode("me@example.com:token"))).unwrap(),
+        AuthScheme::Basic(AuthData {
+            email: "me@example.com".to_owned(),
+            token: "token".to_owned(),
+        })
+    );
+    assert!(parse_auth_scheme("SupportCode").is_err());
+    assert!(parse_auth_scheme("Bearer ABC123").is_err());
+}
+
+#[test]
+fn changed_mappings_are_not_served_from_the_cache() {
+    let cache = Mutex::new(TtlMap::new(SUPPORT_CODE_CACHE_TTL));
+    let code = "ABC123".to_owned();
+    let now = Instant::now();
+    cache.lock().unwrap().insert(
+        code.clone(),
+        crate::utilities::derive_user_tokens("old@example.com", "old", "secret"),
+        now,
+    );
+    assert!(cache.lock().unwrap().get(&code, now).is_some());
+
+    invalidate_support_code(&cache, &code);
+
+    assert!(cache.lock().unwrap().get(&code, now).is_none());
+}
@@ -0,0 +1,273 @@
+use deadpool_postgres::Client;
+use serde::Serialize;
+use std::borrow::Cow;
+
+use crate::{
+    constants::ErrorLogType,
+    db,
+    errors::{ConvertResultErrorToMyError, LogMyError, MyError, DISCORD_ID_NOT_LINKED},
+    role_handling::EarnedRole,
+};
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// How many weeks in a row a user synced at least once. Weeks start on Monday at midnight in a
+/// timezone with a fixed offset from UTC, so every week is exactly seven days long and daylight
+/// saving changes can't make a week shorter, longer or disappear.
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SyncStreak {
+    /// the week of the last sync, weeks are counted from the unix epoch
+    #[serde(skip)]
+    pub last_week: Option<i64>,
+    pub current: u32,
+    pub longest: u32,
+}
+
+impl SyncStreak {
+    /// the streak after a sync in `week`, more syncs in the same week don't count again
+    pub fn record(self, week: i64) -> SyncStreak {
+        let current = match self.last_week {
+            Some(last_week) if week <= last_week => return self,
+            Some(last_week) if week == last_week + 1 => self.current + 1,
+            _ => 1,
+        };
+
+        SyncStreak {
+            last_week: Some(week),
+            current,
+            longest: self.longest.max(current),
+        }
+    }
+
+    /// the streak from its `UserData` columns
+    pub fn from_columns(last_week: Option<i64>, current: i32, longest: i32) -> SyncStreak {
+        SyncStreak {
+            last_week,
+            current: current.max(0) as u32,
+            longest: longest.max(0) as u32,
+        }
+    }
+
+    /// the streak as it stands in `week`, it's broken once a whole week passed without a sync
+    pub fn as_of(self, week: i64) -> SyncStreak {
+        match self.last_week {
+            Some(last_week) if week > last_week + 1 => SyncStreak { current: 0, ..self },
+            _ => self,
+        }
+    }
+}
+
+/// a role for syncing at least once a week for `weeks` weeks in a row
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreakRule {
+    pub weeks: u32,
+    pub role_id: u64,
+    pub name: String,
+}
+
+/// parses `{weeks}:{role_id}:{name}` entries separated by commas, malformed entries are skipped
+pub fn parse_streak_rules(streak_rules: &str) -> Vec<StreakRule> {
+    streak_rules
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.splitn(3, ':');
+            let weeks = parts.next()?.trim().parse::<u32>().ok()?;
+            let role_id = parts
+                .next()?
+                .trim()
+                .parse::<u64>()
+                .ok()
+                .filter(|id| *id != 0)?;
+            let name = parts.next()?.trim().to_owned();
+            Some(StreakRule {
+                weeks,
+                role_id,
+                name,
+            })
+        })
+        .collect()
+}
+
+/// the week `now` is in, counted from the Monday before the unix epoch in the given timezone
+pub fn sync_week(now: u64, utc_offset_hours: i64) -> i64 {
+    let local_days = (now as i64 + utc_offset_hours * 60 * 60).div_euclid(DAY);
+    // the unix epoch was a Thursday
+    (local_days + 3).div_euclid(7)
+}
+
+/// The streak roles a user qualifies for. They're earned through the longest streak, so like the
+/// other milestone roles they aren't taken away again once a streak breaks.
+pub fn streak_roles(rules: &[StreakRule], streak: &SyncStreak) -> Vec<EarnedRole> {
+    rules
+        .iter()
+        .filter(|rule| streak.longest >= rule.weeks)
+        .map(|rule| EarnedRole {
+            id: rule.role_id,
+            name: Cow::Owned(rule.name.clone()),
+            promo_rule: None,
+        })
+        .collect()
+}
+
+pub async fn get_sync_streak(client: &Client, discord_id: &str) -> Result<SyncStreak, MyError> {
+    let (last_week, current, longest) = db::get_sync_streak(client, discord_id)
+        .await
+        .make_response(MyError::InternalError(
+            "Failed at retrieving the sync streak",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?
+        .ok_or(MyError::NotFound(DISCORD_ID_NOT_LINKED))?;
+
+    Ok(SyncStreak::from_columns(last_week, current, longest))
+}
+
+/// counts a successful sync in `week` towards the user's streak
+pub async fn record_sync(
+    client: &Client,
+    discord_id: &str,
+    week: i64,
+) -> Result<SyncStreak, MyError> {
+    let previous = get_sync_streak(client, discord_id).await?;
+    let streak = previous.record(week);
+    if streak == previous {
+        return Ok(streak);
+    }
+
+    db::update_sync_streak(
+        client,
+        discord_id,
+        &week,
+        &(streak.current as i32),
+        &(streak.longest as i32),
+    )
+    .await
+    .make_response(MyError::InternalError(
+        "Failed at recording the sync streak",
+    ))
+    .make_log(ErrorLogType::INTERNAL)
+    .await?;
+
+    Ok(streak)
+}
+
+/// counts a touch in `week` towards the streak the touch read, unlike syncs it doesn't log
+pub async fn record_touch(
+    client: &Client,
+    discord_id: &str,
+    previous: SyncStreak,
+    week: i64,
+) -> Result<SyncStreak, MyError> {
+    let streak = previous.record(week);
+    if streak == previous {
+        return Ok(streak);
+    }
+
+    db::update_sync_streak(
+        client,
+        discord_id,
+        &week,
+        &(streak.current as i32),
+        &(streak.longest as i32),
+    )
+    .await
+    .make_response(MyError::InternalError(
+        "Failed at recording the sync streak",
+    ))?;
+
+    Ok(streak)
+}
+
+#[cfg(test)]
+fn replay(syncs: &[u64], utc_offset_hours: i64) -> SyncStreak {
+    syncs.iter().fold(SyncStreak::default(), |streak, now| {
+        streak.record(sync_week(*now, utc_offset_hours))
+    })
+}
+
+#[test]
+fn weeks_start_on_local_monday() {
+    // Sunday 2024-01-07 23:30 UTC and Monday 2024-01-08 00:30 UTC
+    assert_eq!(sync_week(1_704_670_200, 0) + 1, sync_week(1_704_673_800, 0));
+    // in UTC-5 both are still on Sunday
+    assert_eq!(sync_week(1_704_670_200, -5), sync_week(1_704_673_800, -5));
+}
+
+#[test]
+fn several_syncs_in_a_week_count_once() {
+    // Monday 2024-01-01 00:00 UTC
+    let monday = 1_704_067_200;
+    let day = DAY as u64;
+
+    let streak = replay(
+        &[monday, monday + day, monday + 6 * day, monday + 7 * day],
+        0,
+    );
+
+    assert_eq!(streak.current, 2);
+    assert_eq!(streak.longest, 2);
+    // syncing twice inside a week changes nothing
+    assert_eq!(streak.record(streak.last_week.unwrap()), streak);
+}
+
+#[test]
+fn a_missed_week_breaks_the_streak() {
+    let monday = 1_704_067_200;
+    let week = 7 * DAY as u64;
+
+    let streak = replay(
+        &[monday, monday + week, monday + 2 * week, monday + 4 * week],
+        0,
+    );
+
+    assert_eq!(streak.current, 1);
+    assert_eq!(streak.longest, 3);
+    // the streak is only reported as broken once a whole week passed without a sync
+    let last_week = streak.last_week.unwrap();
+    assert_eq!(streak.as_of(last_week + 1).current, 1);
+    assert_eq!(streak.as_of(last_week + 2).current, 0);
+    assert_eq!(streak.as_of(last_week + 2).longest, 3);
+}
+
+#[test]
+fn streaks_survive_daylight_saving_changes() {
+    // Europe switched to summer time on Sunday 2024-03-31, a player in UTC+1 syncs every Sunday at
+    // 23:30 local winter time, which is Monday 00:30 once summer time started
+    let sunday_before = 1_711_319_400; // Sunday 2024-03-24 22:30 UTC
+    let week = 7 * DAY as u64;
+
+    let streak = replay(
+        &[
+            sunday_before - week,
+            sunday_before,
+            sunday_before + week,
+            sunday_before + 2 * week,
+        ],
+        1,
+    );
+
+    // the fixed offset keeps every sync in a week of its own
+    assert_eq!(streak.current, 4);
+    assert_eq!(streak.longest, 4);
+}
+
+#[test]
+fn streak_roles_follow_the_longest_streak() {
+    let rules = parse_streak_rules("4:1000:Regular, 8:1001:Devoted, broken, 2:0:Zero");
+    assert_eq!(rules.len(), 2);
+
+    let streak = SyncStreak {
+        last_week: Some(10),
+        current: 1,
+        longest: 5,
+    };
+
+    assert_eq!(
+        streak_roles(&rules, &streak),
+        vec![EarnedRole {
+            id: 1000,
+            name: Cow::Borrowed("Regular"),
+            promo_rule: None,
+        }]
+    );
+}
@@ -0,0 +1,315 @@
+use serde::Serialize;
+use std::{
+    future::Future,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use crate::route_limits::{RateLimiter, RouteClass};
+
+/// how often a user can see the timings of their syncs, the numbers say a lot about our setup
+pub const DEBUG_TIMINGS_PER_HOUR: u32 = 10;
+const DEBUG_TIMINGS_WINDOW: Duration = Duration::from_secs(60 * 60);
+/// the upper bounds of the histograms' buckets, the last bucket has everything slower
+pub const BUCKET_BOUNDS_MS: [u64; 10] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+static SYNC_TIMINGS: OnceLock<Mutex<SyncTimingHistograms>> = OnceLock::new();
+static DEBUG_TIMINGS_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+
+/// the parts of a sync that can be slow, everything else is a rounding error next to them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncStage {
+    /// waiting for a connection of the pool
+    DbAcquire,
+    /// looking up the token and the user's stored progress
+    DbRead,
+    /// the update, the snapshot and the sync streak
+    DbWrite,
+    /// evaluating the role rules against the progress and the member's roles
+    RoleCompute,
+    /// fetching the member and updating their roles and nickname
+    Discord,
+}
+
+/// Where the time of a sync went. It's measured for every sync and fed to the histograms, the
+/// client only sees it with `debug_timing=true`.
+#[derive(Clone, Debug)]
+pub struct SyncTimings {
+    started: Instant,
+    stages: [Duration; 5],
+}
+
+impl SyncTimings {
+    pub fn start(now: Instant) -> Self {
+        SyncTimings {
+            started: now,
+            stages: [Duration::ZERO; 5],
+        }
+    }
+
+    /// stages can be entered more than once, e.g. reading the user and then their streak
+    pub fn record(&mut self, stage: SyncStage, elapsed: Duration) {
+        self.stages[stage as usize] += elapsed;
+    }
+
+    pub async fn time<F: Future>(&mut self, stage: SyncStage, future: F) -> F::Output {
+        let started = Instant::now();
+        let output = future.await;
+        self.record(stage, started.elapsed());
+        output
+    }
+
+    pub fn measure<T>(&mut self, stage: SyncStage, measured: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let output = measured();
+        self.record(stage, started.elapsed());
+        output
+    }
+
+    pub fn report(&self, now: Instant) -> TimingsReport {
+        let ms = |stage: SyncStage| self.stages[stage as usize].as_millis() as u64;
+        TimingsReport {
+            db_acquire_ms: ms(SyncStage::DbAcquire),
+            db_read_ms: ms(SyncStage::DbRead),
+            db_write_ms: ms(SyncStage::DbWrite),
+            role_compute_ms: ms(SyncStage::RoleCompute),
+            discord_ms: ms(SyncStage::Discord),
+            total_ms: now.saturating_duration_since(self.started).as_millis() as u64,
+        }
+    }
+}
+
+/// the `timings` of a sync's response, the stages don't quite add up to the total since parsing
+/// the payload and logging aren't stages
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct TimingsReport {
+    pub db_acquire_ms: u64,
+    pub db_read_ms: u64,
+    pub db_write_ms: u64,
+    pub role_compute_ms: u64,
+    pub discord_ms: u64,
+    pub total_ms: u64,
+}
+
+impl TimingsReport {
+    pub fn stages_ms(&self) -> u64 {
+        self.db_acquire_ms
+            + self.db_read_ms
+            + self.db_write_ms
+            + self.role_compute_ms
+            + self.discord_ms
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Histogram {
+    /// the observations per bucket of `BUCKET_BOUNDS_MS`, the last one is everything slower
+    pub buckets: [u64; BUCKET_BOUNDS_MS.len() + 1],
+    pub count: u64,
+    pub sum_ms: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, ms: u64) {
+        let bucket = BUCKET_BOUNDS_MS
+            .iter()
+            .position(|bound| ms <= *bound)
+            .unwrap_or(BUCKET_BOUNDS_MS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum_ms += ms;
+    }
+}
+
+/// every sync's timings since the service started, for GET admin/sync-timings
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct SyncTimingHistograms {
+    pub bucket_bounds_ms: [u64; BUCKET_BOUNDS_MS.len()],
+    pub db_acquire: Histogram,
+    pub db_read: Histogram,
+    pub db_write: Histogram,
+    pub role_compute: Histogram,
+    pub discord: Histogram,
+    pub total: Histogram,
+}
+
+impl SyncTimingHistograms {
+    pub fn observe(&mut self, report: &TimingsReport) {
+        self.bucket_bounds_ms = BUCKET_BOUNDS_MS;
+        self.db_acquire.observe(report.db_acquire_ms);
+        self.db_read.observe(report.db_read_ms);
+        self.db_write.observe(report.db_write_ms);
+        self.role_compute.observe(report.role_compute_ms);
+        self.discord.observe(report.discord_ms);
+        self.total.observe(report.total_ms);
+    }
+}
+
+pub fn sync_timing_histograms() -> &'static Mutex<SyncTimingHistograms> {
+    SYNC_TIMINGS.get_or_init(|| {
+        Mutex::new(SyncTimingHistograms {
+            bucket_bounds_ms: BUCKET_BOUNDS_MS,
+            ..SyncTimingHistograms::default()
+        })
+    })
+}
+
+/// Feeds the sync's timings to the histograms and returns them when the client asked for them.
+/// Asking more than `DEBUG_TIMINGS_PER_HOUR` times an hour quietly leaves them out.
+pub fn finish_sync_timings(
+    timings: &SyncTimings,
+    debug_timing: bool,
+    discord_id: &str,
+    now: Instant,
+) -> Option<TimingsReport> {
+    let report = timings.report(now);
+    sync_timing_histograms().lock().unwrap().observe(&report);
+
+    let limiter = DEBUG_TIMINGS_LIMITER.get_or_init(|| RateLimiter::new(DEBUG_TIMINGS_WINDOW));
+    requested_timings(report, debug_timing, limiter, discord_id, now)
+}
+
+fn requested_timings(
+    report: TimingsReport,
+    debug_timing: bool,
+    limiter: &RateLimiter,
+    discord_id: &str,
+    now: Instant,
+) -> Option<TimingsReport> {
+    (debug_timing
+        && limiter.try_acquire(
+            RouteClass::Mutation,
+            discord_id,
+            DEBUG_TIMINGS_PER_HOUR,
+            now,
+        ))
+    .then_some(report)
+}
+
+#[cfg(test)]
+fn timed_fake_sync(delays_ms: [u64; 5]) -> (SyncTimings, Instant) {
+    use tokio::time::sleep;
+
+    let stages = [
+        SyncStage::DbAcquire,
+        SyncStage::DbRead,
+        SyncStage::DbWrite,
+        SyncStage::RoleCompute,
+        SyncStage::Discord,
+    ];
+    actix_web::rt::System::new().block_on(async move {
+        let mut timings = SyncTimings::start(Instant::now());
+        for (stage, delay) in stages.into_iter().zip(delays_ms) {
+            timings
+                .time(stage, sleep(Duration::from_millis(delay)))
+                .await;
+        }
+        (timings, Instant::now())
+    })
+}
+
+#[test]
+fn stages_add_up_to_the_total() {
+    let (timings, now) = timed_fake_sync([5, 10, 20, 1, 40]);
+    let report = timings.report(now);
+
+    assert!(report.db_acquire_ms >= 5);
+    assert!(report.db_write_ms >= 20);
+    assert!(report.discord_ms >= 40);
+    assert!(report.stages_ms() >= 76);
+    assert!(report.total_ms >= report.stages_ms());
+    // only the loop itself isn't a stage
+    assert!(report.total_ms - report.stages_ms() <= 10, "{:?}", report);
+}
+
+#[test]
+fn stages_entered_twice_are_summed() {
+    let mut timings = SyncTimings::start(Instant::now());
+    timings.record(SyncStage::DbRead, Duration::from_millis(3));
+    timings.record(SyncStage::DbRead, Duration::from_millis(4));
+    assert_eq!(timings.measure(SyncStage::RoleCompute, || 42), 42);
+
+    assert_eq!(timings.report(Instant::now()).db_read_ms, 7);
+}
+
+#[test]
+fn timings_are_only_returned_when_asked_for() {
+    let limiter = RateLimiter::new(DEBUG_TIMINGS_WINDOW);
+    let report = TimingsReport {
+        discord_ms: 120,
+        total_ms: 130,
+        ..TimingsReport::default()
+    };
+    let now = Instant::now();
+
+    assert_eq!(requested_timings(report, false, &limiter, "1", now), None);
+    assert_eq!(
+        requested_timings(report, true, &limiter, "1", now),
+        Some(report)
+    );
+
+    // a user asking for them on every sync stops getting them for the rest of the hour
+    for _ in 1..DEBUG_TIMINGS_PER_HOUR {
+        requested_timings(report, true, &limiter, "1", now);
+    }
+    assert_eq!(requested_timings(report, true, &limiter, "1", now), None);
+    assert_eq!(
+        requested_timings(report, true, &limiter, "2", now),
+        Some(report)
+    );
+}
+
+#[test]
+fn every_sync_feeds_the_histograms() {
+    let mut histograms = SyncTimingHistograms::default();
+    histograms.observe(&TimingsReport {
+        db_read_ms: 3,
+        discord_ms: 700,
+        total_ms: 9000,
+        ..TimingsReport::default()
+    });
+
+    assert_eq!(histograms.bucket_bounds_ms, BUCKET_BOUNDS_MS);
+    assert_eq!(histograms.db_read.buckets[0], 1);
+    assert_eq!(histograms.discord.buckets[7], 1);
+    assert_eq!(histograms.total.buckets[BUCKET_BOUNDS_MS.len()], 1);
+    assert_eq!(histograms.total.sum_ms, 9000);
+}
+
+#[test]
+fn only_responses_that_asked_have_timings() {
+    use crate::models::UserResponse;
+
+    let response = |timings: Option<TimingsReport>| {
+        serde_json::to_value(UserResponse {
+            message: "Synced".to_owned(),
+            roles: Vec::new(),
+            gained_roles: Vec::new(),
+            discord_id: "1".to_owned(),
+            withheld: Vec::new(),
+            skipped: Vec::new(),
+            closest_miss: None,
+            removed_roles: Vec::new(),
+            roles_pending: false,
+            timings,
+        })
+        .unwrap()
+    };
+
+    assert!(response(None).get("timings").is_none());
+    assert_eq!(
+        response(Some(TimingsReport {
+            db_read_ms: 4,
+            total_ms: 12,
+            ..TimingsReport::default()
+        }))["timings"],
+        serde_json::json!({
+            "db_acquire_ms": 0,
+            "db_read_ms": 4,
+            "db_write_ms": 0,
+            "role_compute_ms": 0,
+            "discord_ms": 0,
+            "total_ms": 12,
+        })
+    );
+}
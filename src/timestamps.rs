@@ -0,0 +1,405 @@
+use serde::{
+    de::{self, MapAccess, Visitor},
+    Deserializer, Serializer,
+};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+/// Every timestamp in a response is an RFC 3339 string in UTC with milliseconds, the format
+/// JavaScript's `Date.toISOString` produces, e.g. `2024-01-02T03:04:05.678Z`. Fields go through
+/// one of the modules below instead of serializing `SystemTime` or unix numbers directly.
+pub fn format_rfc3339(unix_millis: i64) -> String {
+    let days = unix_millis.div_euclid(DAY_MILLIS);
+    let millis_of_day = unix_millis.rem_euclid(DAY_MILLIS);
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        millis_of_day / (60 * 60 * 1000),
+        millis_of_day / (60 * 1000) % 60,
+        millis_of_day / 1000 % 60,
+        millis_of_day % 1000
+    )
+}
+
+/// reads `YYYY-MM-DDTHH:MM:SS[.fraction](Z|±HH:MM)` into milliseconds since the unix epoch
+pub fn parse_rfc3339(value: &str) -> Option<i64> {
+    let number = |part: &str| -> Option<i64> {
+        part.bytes()
+            .all(|byte| byte.is_ascii_digit())
+            .then(|| part.parse().ok())
+            .flatten()
+    };
+    let (date, time) = value.split_once(['T', 't', ' '])?;
+    let mut date_parts = date.splitn(3, '-');
+    let (year, month, day) = (
+        number(date_parts.next()?)?,
+        number(date_parts.next()?)?,
+        number(date_parts.next()?)?,
+    );
+
+    let (clock, offset_minutes) = if let Some(clock) = time.strip_suffix(['Z', 'z']) {
+        (clock, 0)
+    } else {
+        let sign_at = time.rfind(['+', '-'])?;
+        let (hours, minutes) = time[sign_at + 1..].split_once(':')?;
+        let offset = number(hours)? * 60 + number(minutes)?;
+        let sign = if time[sign_at..].starts_with('-') {
+            -1
+        } else {
+            1
+        };
+        (&time[..sign_at], sign * offset)
+    };
+    let (clock, fraction) = clock.split_once('.').unwrap_or((clock, ""));
+    let mut clock_parts = clock.splitn(3, ':');
+    let (hour, minute, second) = (
+        number(clock_parts.next()?)?,
+        number(clock_parts.next()?)?,
+        number(clock_parts.next()?)?,
+    );
+    let millis = match fraction {
+        "" => 0,
+        fraction => number(&format!("{:0<3}", &fraction[..fraction.len().min(3)]))?,
+    };
+
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    Some(
+        days_from_civil(year, month, day) * DAY_MILLIS
+            + ((hour * 60 + minute - offset_minutes) * 60 + second) * 1000
+            + millis,
+    )
+}
+
+const DAY_MILLIS: i64 = 24 * 60 * 60 * 1000;
+
+/// the date of a day counted from the unix epoch, in the proleptic Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let shifted = days + 719_468;
+    let era = shifted.div_euclid(146_097);
+    let day_of_era = shifted.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn system_time_millis(time: &SystemTime) -> i64 {
+    match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since) => since.as_millis() as i64,
+        Err(error) => -(error.duration().as_millis() as i64),
+    }
+}
+
+fn system_time_from_millis(unix_millis: i64) -> SystemTime {
+    let offset = Duration::from_millis(unix_millis.unsigned_abs());
+    if unix_millis < 0 {
+        SystemTime::UNIX_EPOCH - offset
+    } else {
+        SystemTime::UNIX_EPOCH + offset
+    }
+}
+
+/// for `#[serde(with)]` on `SystemTime` fields, the `{ secs_since_epoch, nanos_since_epoch }`
+/// serde used to write for them is still read
+pub mod system_time {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_rfc3339(system_time_millis(time)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        deserializer.deserialize_any(SystemTimeVisitor)
+    }
+
+    pub fn serialize_option<S: Serializer>(
+        time: &Option<SystemTime>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match time {
+            Some(time) => serialize(time, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+/// for `#[serde(serialize_with)]` on `u64` fields holding seconds since the unix epoch
+pub mod unix_seconds {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(seconds: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_rfc3339(*seconds as i64 * 1000))
+    }
+
+    pub fn serialize_option<S: Serializer>(
+        seconds: &Option<u64>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match seconds {
+            Some(seconds) => serialize(seconds, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    /// maps like the granted roles, whose values are when each key happened
+    pub fn serialize_map<S: Serializer>(
+        map: &BTreeMap<String, Option<u64>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(map.iter().map(|(key, seconds)| {
+            (
+                key,
+                seconds.map(|seconds| format_rfc3339(seconds as i64 * 1000)),
+            )
+        }))
+    }
+
+    /// reads back what `serialize_map` wrote
+    pub fn deserialize_map<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<String, Option<u64>>, D::Error> {
+        let map: BTreeMap<String, Option<String>> = serde::Deserialize::deserialize(deserializer)?;
+        map.into_iter()
+            .map(|(key, timestamp)| {
+                let seconds = match timestamp {
+                    Some(timestamp) => Some(
+                        parse_rfc3339(&timestamp)
+                            .and_then(|millis| u64::try_from(millis / 1000).ok())
+                            .ok_or_else(|| {
+                                de::Error::custom("the string isn't an RFC 3339 timestamp")
+                            })?,
+                    ),
+                    None => None,
+                };
+                Ok((key, seconds))
+            })
+            .collect()
+    }
+}
+
+/// for `#[serde(serialize_with)]` on `u64` fields holding milliseconds since the unix epoch
+pub mod unix_millis {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(millis: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_rfc3339(*millis as i64))
+    }
+}
+
+struct SystemTimeVisitor;
+
+impl<'de> Visitor<'de> for SystemTimeVisitor {
+    type Value = SystemTime;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an RFC 3339 timestamp")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<SystemTime, E> {
+        parse_rfc3339(value)
+            .map(system_time_from_millis)
+            .ok_or_else(|| E::custom("the string isn't an RFC 3339 timestamp"))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<SystemTime, A::Error> {
+        let (mut seconds, mut nanos) = (None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "secs_since_epoch" => seconds = Some(map.next_value::<u64>()?),
+                "nanos_since_epoch" => nanos = Some(map.next_value::<u32>()?),
+                _ => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+
+        match (seconds, nanos) {
+            (Some(seconds), Some(nanos)) => {
+                Ok(SystemTime::UNIX_EPOCH + Duration::new(seconds, nanos))
+            }
+            _ => Err(de::Error::custom(
+                "expected secs_since_epoch and nanos_since_epoch",
+            )),
+        }
+    }
+}
+
+/// the keys responses keep timestamps under, other than the `*_at` and `*_timestamp` ones
+#[cfg(test)]
+const TIMESTAMP_KEYS: [&str; 4] = ["timestamp", "since", "window_start", "window_end"];
+
+/// maps whose values are timestamps
+#[cfg(test)]
+const TIMESTAMP_MAP_KEYS: [&str; 1] = ["granted_roles"];
+
+#[cfg(test)]
+fn is_rfc3339_timestamp(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Null => true,
+        serde_json::Value::String(value) => {
+            parse_rfc3339(value).map(format_rfc3339).as_deref() == Some(value.as_str())
+        }
+        _ => false,
+    }
+}
+
+/// Walks a response's JSON and lists the timestamps that aren't written the way
+/// `format_rfc3339` writes them, with the path to each.
+#[cfg(test)]
+pub fn timestamp_violations(value: &serde_json::Value, path: &str) -> Vec<String> {
+    match value {
+        serde_json::Value::Object(object) => object
+            .iter()
+            .flat_map(|(key, value)| {
+                let path = format!("{}.{}", path, key);
+                let mut violations = Vec::new();
+                let is_timestamp = key.ends_with("_at")
+                    || key.ends_with("_timestamp")
+                    || TIMESTAMP_KEYS.contains(&key.as_str());
+                if is_timestamp && !is_rfc3339_timestamp(value) {
+                    violations.push(format!("{} is {}", path, value));
+                }
+                if TIMESTAMP_MAP_KEYS.contains(&key.as_str()) {
+                    for (entry, value) in value.as_object().into_iter().flatten() {
+                        if !is_rfc3339_timestamp(value) {
+                            violations.push(format!("{}.{} is {}", path, entry, value));
+                        }
+                    }
+                }
+                violations.extend(timestamp_violations(value, &path));
+                violations
+            })
+            .collect(),
+        serde_json::Value::Array(values) => values
+            .iter()
+            .enumerate()
+            .flat_map(|(index, value)| timestamp_violations(value, &format!("{}[{}]", path, index)))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// enum values are camelCase, e.g. `notMet`
+#[cfg(test)]
+pub fn is_camel_case(value: &str) -> bool {
+    value.starts_with(|c: char| c.is_ascii_lowercase())
+        && value.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+#[test]
+fn timestamps_are_written_like_javascript_does() {
+    assert_eq!(format_rfc3339(0), "1970-01-01T00:00:00.000Z");
+    assert_eq!(
+        format_rfc3339(1_700_000_000_123),
+        "2023-11-14T22:13:20.123Z"
+    );
+    // leap days and times before the epoch
+    assert_eq!(format_rfc3339(951_782_400_000), "2000-02-29T00:00:00.000Z");
+    assert_eq!(format_rfc3339(-1), "1969-12-31T23:59:59.999Z");
+
+    for millis in [0, -1, 951_782_400_000, 1_700_000_000_123, 4_102_444_800_000] {
+        assert_eq!(parse_rfc3339(&format_rfc3339(millis)), Some(millis));
+    }
+}
+
+#[test]
+fn other_rfc3339_spellings_are_read() {
+    assert_eq!(
+        parse_rfc3339("2023-11-14T22:13:20Z"),
+        Some(1_700_000_000_000)
+    );
+    assert_eq!(
+        parse_rfc3339("2023-11-15T00:13:20.5+02:00"),
+        Some(1_700_000_000_500)
+    );
+    assert_eq!(
+        parse_rfc3339("2023-11-14T21:13:20.123456-01:00"),
+        Some(1_700_000_000_123)
+    );
+    for invalid in [
+        "",
+        "1700000000",
+        "2023-13-01T00:00:00Z",
+        "2023-11-14T22:13:20",
+    ] {
+        assert_eq!(parse_rfc3339(invalid), None, "{}", invalid);
+    }
+}
+
+#[cfg(test)]
+#[derive(serde::Deserialize, serde::Serialize, Debug, PartialEq)]
+struct Edited {
+    #[serde(with = "system_time")]
+    edited_timestamp: SystemTime,
+}
+
+#[test]
+fn system_times_round_trip_and_old_json_is_still_read() {
+    let edited = Edited {
+        edited_timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+    };
+    let json = serde_json::to_string(&edited).unwrap();
+    assert_eq!(json, "{\"edited_timestamp\":\"2023-11-14T22:13:20.123Z\"}");
+    assert_eq!(serde_json::from_str::<Edited>(&json).unwrap(), edited);
+
+    let old =
+        "{\"edited_timestamp\":{\"secs_since_epoch\":1700000000,\"nanos_since_epoch\":123000000}}";
+    assert_eq!(serde_json::from_str::<Edited>(old).unwrap(), edited);
+}
+
+#[test]
+fn unix_numbers_are_caught_by_the_walk() {
+    let json = serde_json::json!({
+        "refreshed_at": 1_700_000_000,
+        "rules": [{ "resume_at": "2023-11-14T22:13:20Z" }],
+        "granted_roles": { "1": null, "2": "2023-11-14T22:13:20.000Z", "3": 1_700_000_000 },
+        "since": "2023-11-14T22:13:20.000Z",
+    });
+    assert_eq!(
+        timestamp_violations(&json, "response"),
+        vec![
+            "response.granted_roles.3 is 1700000000",
+            "response.refreshed_at is 1700000000",
+            "response.rules[0].resume_at is \"2023-11-14T22:13:20Z\"",
+        ]
+    );
+
+    assert!(is_camel_case("notMet"));
+    assert!(!is_camel_case("not_met"));
+    assert!(!is_camel_case("NOT_MET"));
+    assert!(!is_camel_case("not met"));
+}
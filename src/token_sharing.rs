@@ -0,0 +1,177 @@
+use actix_web::rt::{self, time};
+use deadpool_postgres::Pool;
+use std::time::{Duration, SystemTime};
+
+use crate::{
+    constants::{ErrorLogType, LOG},
+    db,
+    errors::{ConvertResultErrorToMyError, LogMyError, MyError},
+    models::TokenUse,
+    webhook_logging::webhook_log,
+};
+
+/// how far back the tokens a discord id synced with are looked at
+pub const TOKEN_SHARING_WINDOW: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const TOKEN_SHARING_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// a discord id that synced with more tokens than the threshold allows
+#[derive(Clone, Debug, PartialEq)]
+pub struct SharedAccount {
+    pub discord_id: String,
+    /// the tokens that count towards the threshold, oldest first
+    pub tokens: Vec<TokenUse>,
+}
+
+/// Finds the discord ids whose data was changed by more than `threshold` distinct tokens. Tokens
+/// an admin flow moved the discord id to are whitelisted and don't count. `uses` has to be
+/// ordered by discord id, the way `db::get_token_uses` returns it.
+pub fn detect(uses: Vec<TokenUse>, threshold: usize) -> Vec<SharedAccount> {
+    let mut accounts: Vec<SharedAccount> = Vec::new();
+    for token_use in uses.into_iter().filter(|token_use| !token_use.whitelisted) {
+        match accounts.last_mut() {
+            Some(account) if account.discord_id == token_use.discord_id => {
+                account.tokens.push(token_use)
+            }
+            _ => accounts.push(SharedAccount {
+                discord_id: token_use.discord_id.clone(),
+                tokens: vec![token_use],
+            }),
+        }
+    }
+
+    accounts.retain(|account| account.tokens.len() > threshold);
+    accounts
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// the review message, tokens are only shown by their fingerprint
+pub fn review_message(account: &SharedAccount) -> String {
+    let tokens = account
+        .tokens
+        .iter()
+        .map(|token_use| {
+            format!(
+                "`{}` from <t:{}:f> to <t:{}:f>",
+                token_use.token_fingerprint,
+                unix_seconds(token_use.first_seen),
+                unix_seconds(token_use.last_seen)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    format!(
+        "{} was synced with {} different tokens in the last 7 days and was flagged for review:\n{}",
+        account.discord_id,
+        account.tokens.len(),
+        tokens
+    )
+}
+
+/// looks for shared accounts every hour, each one is only reported when it's first flagged
+pub fn spawn_token_sharing_scheduler(pool: Pool, threshold: usize) {
+    rt::spawn(async move {
+        let mut interval = time::interval(TOKEN_SHARING_INTERVAL);
+        loop {
+            interval.tick().await;
+            let _ = run_detection(&pool, threshold).await;
+        }
+    });
+}
+
+async fn run_detection(pool: &Pool, threshold: usize) -> Result<(), MyError> {
+    let client = pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "token sharing detection failed at creating database client",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    let uses = db::get_token_uses(&client, &(SystemTime::now() - TOKEN_SHARING_WINDOW))
+        .await
+        .make_response(MyError::InternalError(
+            "token sharing detection failed at getting the token uses",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+    let accounts = detect(uses, threshold);
+    if accounts.is_empty() {
+        return Ok(());
+    }
+
+    let discord_ids = accounts
+        .iter()
+        .map(|account| account.discord_id.clone())
+        .collect::<Vec<String>>();
+    let flagged = db::flag_for_review(&client, &discord_ids)
+        .await
+        .make_response(MyError::InternalError(
+            "token sharing detection failed at flagging accounts for review",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    for account in accounts
+        .iter()
+        .filter(|account| flagged.contains(&account.discord_id))
+    {
+        webhook_log(review_message(account), LOG::FAILURE).await;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn token_use(discord_id: &str, token: &str, day: u64, whitelisted: bool) -> TokenUse {
+    let seen = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + day * 24 * 60 * 60);
+    TokenUse {
+        discord_id: discord_id.to_owned(),
+        token_fingerprint: token.to_owned(),
+        first_seen: seen,
+        last_seen: seen + Duration::from_secs(60),
+        whitelisted,
+    }
+}
+
+#[test]
+fn accounts_over_the_threshold_are_detected() {
+    let uses = vec![
+        token_use("1", "aaaaaaaa", 0, false),
+        token_use("1", "bbbbbbbb", 1, false),
+        token_use("1", "cccccccc", 2, false),
+        token_use("1", "dddddddd", 3, false),
+        token_use("2", "eeeeeeee", 0, false),
+        token_use("2", "ffffffff", 1, false),
+        token_use("2", "gggggggg", 2, false),
+    ];
+
+    let accounts = detect(uses, 3);
+    assert_eq!(accounts.len(), 1);
+    assert_eq!(accounts[0].discord_id, "1");
+    assert_eq!(accounts[0].tokens.len(), 4);
+
+    let message = review_message(&accounts[0]);
+    assert!(message.starts_with("1 was synced with 4 different tokens"));
+    assert!(message.contains("`aaaaaaaa` from <t:1700000000:f> to <t:1700000060:f>"));
+    assert!(message.contains("`dddddddd` from <t:1700259200:f>"));
+}
+
+#[test]
+fn admin_transitions_are_not_counted() {
+    let uses = vec![
+        token_use("1", "aaaaaaaa", 0, false),
+        token_use("1", "bbbbbbbb", 1, true),
+        token_use("1", "cccccccc", 2, true),
+        token_use("1", "dddddddd", 3, false),
+    ];
+
+    assert_eq!(detect(uses.clone(), 2), vec![]);
+    assert_eq!(detect(uses, 1)[0].tokens.len(), 2);
+}
@@ -0,0 +1,129 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// A map whose entries expire `ttl` after they were inserted.
+///
+/// Every method takes the current time so callers (and tests) control the clock.
+pub struct TtlMap<K, V> {
+    ttl: Duration,
+    entries: HashMap<K, (Instant, V)>,
+}
+
+impl<K: Eq + Hash, V> TtlMap<K, V> {
+    pub fn new(ttl: Duration) -> Self {
+        TtlMap {
+            ttl,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn get(&self, key: &K, now: Instant) -> Option<&V> {
+        self.entries
+            .get(key)
+            .filter(|(inserted_at, _)| now.duration_since(*inserted_at) < self.ttl)
+            .map(|(_, value)| value)
+    }
+
+    pub fn get_mut(&mut self, key: &K, now: Instant) -> Option<&mut V> {
+        let ttl = self.ttl;
+        self.entries
+            .get_mut(key)
+            .filter(|(inserted_at, _)| now.duration_since(*inserted_at) < ttl)
+            .map(|(_, value)| value)
+    }
+
+    pub fn contains_key(&self, key: &K, now: Instant) -> bool {
+        self.get(key, now).is_some()
+    }
+
+    /// inserts the value, restarting the key's lifetime from `now`
+    pub fn insert(&mut self, key: K, value: V, now: Instant) -> Option<V> {
+        self.entries
+            .insert(key, (now, value))
+            .map(|(_, value)| value)
+    }
+
+    /// inserts a value that only has `remaining` of its lifetime left, e.g. one kept over a restart
+    pub fn insert_remaining(&mut self, key: K, value: V, remaining: Duration, now: Instant) {
+        let lived = self.ttl.saturating_sub(remaining);
+        // the clock can't go back further than the process started, those keep a bit more lifetime
+        let inserted_at = now.checked_sub(lived).unwrap_or(now);
+        self.entries.insert(key, (inserted_at, value));
+    }
+
+    /// how much lifetime the key has left, `None` when it's missing or expired
+    pub fn remaining(&self, key: &K, now: Instant) -> Option<Duration> {
+        let (inserted_at, _) = self.entries.get(key)?;
+        let remaining = self.ttl.checked_sub(now.duration_since(*inserted_at))?;
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    /// the entries that haven't expired with the lifetime they have left
+    pub fn live_entries(&self, now: Instant) -> Vec<(&K, &V, Duration)> {
+        self.entries
+            .iter()
+            .filter_map(|(key, (inserted_at, value))| {
+                let remaining = self.ttl.checked_sub(now.duration_since(*inserted_at))?;
+                (!remaining.is_zero()).then_some((key, value, remaining))
+            })
+            .collect()
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.entries.remove(key).map(|(_, value)| value)
+    }
+
+    /// drops every expired entry, this should be called now and then so the map doesn't grow forever
+    pub fn purge_expired(&mut self, now: Instant) {
+        let ttl = self.ttl;
+        self.entries
+            .retain(|_, (inserted_at, _)| now.duration_since(*inserted_at) < ttl);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[test]
+fn entries_expire_after_ttl() {
+    let start = Instant::now();
+    let mut map = TtlMap::new(Duration::from_secs(10));
+    map.insert("key", 1, start);
+
+    assert_eq!(map.get(&"key", start + Duration::from_secs(9)), Some(&1));
+    assert_eq!(map.get(&"key", start + Duration::from_secs(10)), None);
+
+    map.purge_expired(start + Duration::from_secs(10));
+    assert!(map.is_empty());
+}
+
+#[test]
+fn entries_keep_their_remaining_lifetime() {
+    let start = Instant::now();
+    let mut map = TtlMap::new(Duration::from_secs(10));
+    map.insert("fresh", 1, start);
+    map.insert_remaining("old", 2, Duration::from_secs(4), start);
+
+    let mut entries = map.live_entries(start + Duration::from_secs(1));
+    entries.sort();
+    assert_eq!(
+        entries,
+        vec![
+            (&"fresh", &1, Duration::from_secs(9)),
+            (&"old", &2, Duration::from_secs(3)),
+        ]
+    );
+    assert_eq!(map.get(&"old", start + Duration::from_secs(4)), None);
+}
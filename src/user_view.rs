@@ -0,0 +1,158 @@
+use serde::Serialize;
+use std::time::SystemTime;
+
+use crate::{
+    fields::DEPRECATED_FIELDS,
+    models::UserData,
+    role_pauses::RolePauses,
+    role_rules::{
+        closest_miss, compute_earned_roles_with_trace, milestones_completed, ClosestMiss, RoleRule,
+    },
+    sync_streaks::{streak_roles, StreakRule, SyncStreak},
+};
+
+/// Bumped whenever a computed field changes its meaning or goes away, adding a field doesn't bump
+/// it. Clients check it before relying on the fields.
+pub const COMPUTED_FIELDS_VERSION: u32 = 1;
+
+/// the values clients used to work out from the raw fields themselves
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ComputedFields {
+    pub version: u32,
+    /// the milestone, configured and streak roles the progress qualifies for, promo roles aren't
+    /// progress and are left out
+    pub roles_earned: usize,
+    /// rounded down, a milestone a higher tier replaces counts as done
+    pub milestones_completed_percent: u8,
+    pub streak_weeks: u32,
+    pub longest_streak_weeks: u32,
+    /// the unearned role the user is closest to, like in a sync that gains nothing
+    pub next_role: Option<ClosestMiss>,
+}
+
+/// Works the computed fields out from the stored progress alone, pauses and promo rules change
+/// from one moment to the next and aren't taken into account.
+pub fn compute_fields(
+    user_data: &UserData,
+    role_rules: &[RoleRule],
+    streak_rules: &[StreakRule],
+    streak: &SyncStreak,
+) -> ComputedFields {
+    let trace = compute_earned_roles_with_trace(
+        user_data,
+        role_rules,
+        &[],
+        &RolePauses::default(),
+        &DEPRECATED_FIELDS,
+        SystemTime::now(),
+    );
+    let (completed, milestones) = milestones_completed(&trace.rules);
+
+    ComputedFields {
+        version: COMPUTED_FIELDS_VERSION,
+        roles_earned: trace.earned.len() + streak_roles(streak_rules, streak).len(),
+        milestones_completed_percent: (completed * 100).checked_div(milestones).unwrap_or(0) as u8,
+        streak_weeks: streak.current,
+        longest_streak_weeks: streak.longest,
+        next_role: closest_miss(&trace.rules, user_data),
+    }
+}
+
+/// The user's data as it's stored with the computed fields next to it. They're only worked out
+/// with `?include=computed`, so the default payloads stay as small as they were.
+#[derive(Serialize)]
+pub struct UserDataView<T = UserData> {
+    #[serde(flatten)]
+    pub data: T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub computed: Option<ComputedFields>,
+}
+
+#[cfg(test)]
+fn streak_rules() -> Vec<StreakRule> {
+    crate::sync_streaks::parse_streak_rules("4:2001:Regular,52:2002:Devoted")
+}
+
+#[test]
+fn endgame_players_have_every_milestone() {
+    use crate::constants::{BeyondRequirements, MetabitRequirements};
+
+    let endgame = UserData {
+        beta_tester: true,
+        metabits: MetabitRequirements::RealityLegend as i64,
+        dino_rank: 10 * 50,
+        beyond_rank: BeyondRequirements::PlanetaryExplorer as i32,
+        singularity_speedrun_time: Some(100.0),
+        all_sharks_obtained: true,
+        all_hidden_achievements_obtained: true,
+        ..UserData::default()
+    };
+    let streak = SyncStreak {
+        last_week: Some(2_800),
+        current: 4,
+        longest: 12,
+    };
+
+    assert_eq!(
+        compute_fields(&endgame, &[], &streak_rules(), &streak),
+        ComputedFields {
+            version: COMPUTED_FIELDS_VERSION,
+            // the legends, planetary explorer, the secrets finder, beta tester and the regular
+            roles_earned: 6,
+            milestones_completed_percent: 100,
+            streak_weeks: 4,
+            longest_streak_weeks: 12,
+            next_role: None,
+        }
+    );
+}
+
+#[test]
+fn fresh_players_start_at_nothing() {
+    use crate::constants::roles;
+
+    assert_eq!(
+        compute_fields(
+            &UserData::default(),
+            &[],
+            &streak_rules(),
+            &SyncStreak::default()
+        ),
+        ComputedFields {
+            version: COMPUTED_FIELDS_VERSION,
+            roles_earned: 0,
+            milestones_completed_percent: 0,
+            streak_weeks: 0,
+            longest_streak_weeks: 0,
+            next_role: Some(ClosestMiss {
+                role_id: roles::REALITY_EXPLORER.to_string(),
+                role_name: "Reality Explorer".to_owned(),
+                field: "metabits".to_owned(),
+                remaining: 1_000_000.0,
+            }),
+        }
+    );
+}
+
+#[test]
+fn the_view_only_has_computed_fields_when_asked_for() {
+    let plain = serde_json::to_value(UserDataView {
+        data: UserData::default(),
+        computed: None,
+    })
+    .unwrap();
+    assert_eq!(plain, serde_json::to_value(UserData::default()).unwrap());
+
+    let computed = serde_json::to_value(UserDataView {
+        data: UserData::default(),
+        computed: Some(compute_fields(
+            &UserData::default(),
+            &[],
+            &[],
+            &SyncStreak::default(),
+        )),
+    })
+    .unwrap();
+    assert_eq!(computed["computed"]["version"], COMPUTED_FIELDS_VERSION);
+    assert_eq!(computed["discord_id"], plain["discord_id"]);
+}
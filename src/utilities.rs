@@ -1,5 +1,7 @@
 use actix_web::{http::header::HeaderMap, Error};
-use crypto::{hmac::Hmac, mac::Mac, sha1::Sha1};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::Sha256;
 
 pub trait InvalidItems<T> {
     fn invalid_auth(self) -> Result<T, Error>;
@@ -27,6 +29,7 @@ impl<T, E: core::fmt::Debug> InvalidItems<T> for Result<T, E> {
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct AuthData {
     pub email: String,
     pub token: String,
@@ -80,16 +83,226 @@ impl From<HeaderMap> for AuthData {
     }
 }
 
+/// The legacy token users were stored under, an HMAC-SHA1 of their credentials. Rows still under it
+/// are moved to `encode_user_token_v2` when their user comes by, see `resolve_user_token`.
 pub fn encode_user_token(email: &str, token: &str, userdata_auth: &str) -> String {
-    let mut user_token = Hmac::new(Sha1::new(), userdata_auth.as_bytes());
-    user_token.input(email.as_bytes());
-    user_token.input(token.as_bytes());
-
-    user_token
-        .result()
-        .code()
-        .iter()
-        .map(|byte| format!("{:02x?}", byte))
-        .collect::<Vec<String>>()
-        .join("")
+    let mut user_token = Hmac::<Sha1>::new_from_slice(userdata_auth.as_bytes())
+        .expect("HMAC takes keys of any length");
+    user_token.update(email.as_bytes());
+    user_token.update(token.as_bytes());
+
+    hex_encode(&user_token.finalize().into_bytes())
+}
+
+/// the token users are stored under, an HMAC-SHA256 of their credentials with a `:` between them
+/// so different splits of the same characters can't end up with the same token
+pub fn encode_user_token_v2(email: &str, token: &str, userdata_auth: &str) -> String {
+    let mut user_token = Hmac::<Sha256>::new_from_slice(userdata_auth.as_bytes())
+        .expect("HMAC takes keys of any length");
+    user_token.update(email.as_bytes());
+    user_token.update(b":");
+    user_token.update(token.as_bytes());
+
+    hex_encode(&user_token.finalize().into_bytes())
+}
+
+/// the tokens a request's credentials derive to, lookups try `v2` first and fall back to `legacy`
+#[derive(Clone, Debug, PartialEq)]
+pub struct DerivedTokens {
+    pub v2: String,
+    pub legacy: String,
+}
+
+pub fn derive_user_tokens(email: &str, token: &str, userdata_auth: &str) -> DerivedTokens {
+    DerivedTokens {
+        v2: encode_user_token_v2(email, token, userdata_auth),
+        legacy: encode_user_token(email, token, userdata_auth),
+    }
+}
+
+/// the og endpoint's tokens, derived like `derive_user_tokens` with the player id in place of the
+/// email so users can switch between the endpoints without their row being orphaned
+pub fn derive_og_user_tokens(player_id: &str, player_token: &str, secret: &str) -> DerivedTokens {
+    derive_user_tokens(player_id, player_token, secret)
+}
+
+/// lowercase hex, written straight into a string of the right size since it runs on every request
+pub fn hex_encode(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    let mut encoded = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        encoded.push(DIGITS[(byte >> 4) as usize] as char);
+        encoded.push(DIGITS[(byte & 0x0f) as usize] as char);
+    }
+
+    encoded
+}
+
+/// reads what `hex_encode` wrote, in either case
+pub fn hex_decode(encoded: &str) -> Option<Vec<u8>> {
+    if encoded.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..encoded.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(encoded.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+/// Spellings of an email the same player could have linked with, e.g. when their keyboard
+/// capitalized the first letter. The first variant is the email as it was given.
+pub fn email_variants(email: &str) -> Vec<String> {
+    let trimmed = email.trim();
+    let mut capitalized = trimmed.chars();
+    let capitalized = match capitalized.next() {
+        Some(first) => first.to_uppercase().chain(capitalized).collect(),
+        None => String::new(),
+    };
+
+    let mut variants = Vec::new();
+    for variant in [
+        email.to_owned(),
+        trimmed.to_owned(),
+        trimmed.to_lowercase(),
+        trimmed.to_uppercase(),
+        capitalized,
+    ] {
+        if !variants.contains(&variant) {
+            variants.push(variant);
+        }
+    }
+
+    variants
+}
+
+/// whether `existing_token` belongs to the same player under a differently spelled email
+pub fn is_same_player(existing_token: &str, email: &str, token: &str, userdata_auth: &str) -> bool {
+    email_variants(email).iter().any(|variant| {
+        let derived = derive_user_tokens(variant, token, userdata_auth);
+        derived.v2 == existing_token || derived.legacy == existing_token
+    })
+}
+
+#[test]
+fn differently_cased_emails_are_the_same_player() {
+    let existing_token = encode_user_token("player@example.com", "token", "secret");
+
+    assert!(is_same_player(
+        &existing_token,
+        "Player@Example.COM",
+        "token",
+        "secret"
+    ));
+    assert!(is_same_player(
+        &encode_user_token("Player@example.com", "token", "secret"),
+        " player@example.com",
+        "token",
+        "secret"
+    ));
+}
+
+#[test]
+fn migrated_players_are_the_same_player() {
+    let existing_token = encode_user_token_v2("player@example.com", "token", "secret");
+
+    assert!(is_same_player(
+        &existing_token,
+        "Player@example.com",
+        "token",
+        "secret"
+    ));
+}
+
+#[test]
+fn other_players_are_not_the_same_player() {
+    let existing_token = encode_user_token("someone@example.com", "token", "secret");
+
+    assert!(!is_same_player(
+        &existing_token,
+        "player@example.com",
+        "token",
+        "secret"
+    ));
+    // the same email with another game token is another account
+    assert!(!is_same_player(
+        &encode_user_token("player@example.com", "other-token", "secret"),
+        "Player@example.com",
+        "token",
+        "secret"
+    ));
+}
+
+#[test]
+fn user_tokens_are_unchanged() {
+    // HMAC-SHA1 digests produced by the previous per-byte `format!` encoding
+    assert_eq!(
+        encode_user_token("player@example.com", "token", "secret"),
+        "80d7bf83f257ac0150c1fafc3834cd73ab3879c7"
+    );
+    assert_eq!(
+        encode_user_token("1234567890", "abc", "secret"),
+        "087da7128ed3721581d18fd5e2a5a03ed2255390"
+    );
+    assert_eq!(hex_encode(&[0x00, 0x0f, 0xa0, 0xff]), "000fa0ff");
+    assert_eq!(hex_encode(&[]), "");
+}
+
+#[test]
+fn legacy_tokens_are_unchanged_by_the_new_crates() {
+    // what rust-crypto's HMAC-SHA1 derived, existing rows are still stored under these
+    for (player_id, player_token, secret, legacy) in [
+        (
+            "1234567890",
+            "abc",
+            "secret",
+            "087da7128ed3721581d18fd5e2a5a03ed2255390",
+        ),
+        ("0", "", "", "aae545fa81de97aa9b6fdfc4523e1a038c86c5bc"),
+        (
+            "76561198000000000",
+            "a-long-player-token-with-dashes",
+            "another secret",
+            "ab0295c460f88488c36affe182df7aeab3f28ffa",
+        ),
+        (
+            "player",
+            "tökén",
+            "sécret",
+            "5bdd8f95f37d682d761e71774b934e52b3e4f72e",
+        ),
+    ] {
+        assert_eq!(
+            derive_og_user_tokens(player_id, player_token, secret).legacy,
+            legacy,
+            "{}",
+            player_id
+        );
+    }
+}
+
+#[test]
+fn v2_tokens_are_hmac_sha256() {
+    assert_eq!(
+        derive_user_tokens("player@example.com", "token", "secret"),
+        DerivedTokens {
+            v2: "68df4120afb0b4ee764b8b467173bf149eef2d2c7ad51079e9e68657005da992".to_owned(),
+            legacy: "80d7bf83f257ac0150c1fafc3834cd73ab3879c7".to_owned(),
+        }
+    );
+    assert_eq!(
+        derive_og_user_tokens("1234567890", "abc", "secret").v2,
+        "ba9cee0a2ac8b105193156e04154383fb2afe7947bba9a840006e9712bd2c774"
+    );
+    // the separator keeps moving characters between the email and the token from colliding
+    assert_ne!(
+        encode_user_token_v2("player@example.co", "mtoken", "secret"),
+        encode_user_token_v2("player@example.com", "token", "secret")
+    );
+
+    assert_eq!(hex_decode("000fA0ff"), Some(vec![0x00, 0x0f, 0xa0, 0xff]));
+    assert_eq!(hex_decode("abc"), None);
+    assert_eq!(hex_decode("zz"), None);
+    assert_eq!(hex_decode("é1"), None);
 }
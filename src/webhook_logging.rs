@@ -1,36 +1,264 @@
 use crate::{
     config::Config,
     constants::{self, BACKGROUND, LOG},
+    discord_pause::{ensure_discord_available, record_discord_error},
+    ttl_map::TtlMap,
+};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use twilight_http::{error::ErrorType, Client};
+use twilight_model::{
+    channel::embed::Embed,
+    id::{marker::WebhookMarker, Id},
 };
-use twilight_http::Client;
-use twilight_model::id::{marker::WebhookMarker, Id};
 
-#[allow(unused_must_use)]
+/// how many 401/404 responses in a row it takes before a webhook is considered deleted
+pub const DEAD_WEBHOOK_THRESHOLD: u32 = 3;
 
-pub async fn webhook_log(content: String, log_type: LOG) {
-    let config = Config::new();
-    let client = Client::new(config.discord_token);
-    let webhook_id = Id::<WebhookMarker>::new(config.webhook_id.parse::<u64>().unwrap());
+static WEBHOOK_HEALTH: OnceLock<Mutex<WebhookHealth>> = OnceLock::new();
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct WebhookStatus {
+    pub webhook_id: u64,
+    pub consecutive_failures: u32,
+    pub dead: bool,
+}
+
+/// Keeps track of whether the webhook still exists, a moderator deleting it makes every
+/// delivery fail forever so it gets marked dead and skipped until it's replaced.
+pub struct WebhookHealth {
+    webhook_id: u64,
+    webhook_token: String,
+    consecutive_failures: u32,
+    dead: bool,
+}
+
+impl WebhookHealth {
+    pub fn new(webhook_id: u64, webhook_token: String) -> Self {
+        WebhookHealth {
+            webhook_id,
+            webhook_token,
+            consecutive_failures: 0,
+            dead: false,
+        }
+    }
+
+    /// records the status of a delivery, returns true when this response got the webhook marked dead
+    pub fn record_response(&mut self, status: u16) -> bool {
+        if status != 401 && status != 404 {
+            self.consecutive_failures = 0;
+            return false;
+        }
+
+        self.consecutive_failures += 1;
+        if !self.dead && self.consecutive_failures >= DEAD_WEBHOOK_THRESHOLD {
+            self.dead = true;
+            return true;
+        }
+        false
+    }
+
+    /// swaps in a new webhook, which clears the dead marker
+    pub fn replace(&mut self, webhook_id: u64, webhook_token: String) {
+        *self = WebhookHealth::new(webhook_id, webhook_token);
+    }
 
+    pub fn status(&self) -> WebhookStatus {
+        WebhookStatus {
+            webhook_id: self.webhook_id,
+            consecutive_failures: self.consecutive_failures,
+            dead: self.dead,
+        }
+    }
+}
+
+pub fn webhook_health() -> &'static Mutex<WebhookHealth> {
+    WEBHOOK_HEALTH.get_or_init(|| {
+        let config = Config::new();
+        Mutex::new(WebhookHealth::new(
+            config.webhook_id.parse::<u64>().unwrap(),
+            config.webhook_token,
+        ))
+    })
+}
+
+/// the username and avatar one level's logs are sent with, unset ones are the webhook's own
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WebhookIdentity {
+    pub username: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+/// empty values are unset, avatars have to be https since Discord fetches them
+pub fn parse_webhook_identity(username: &str, avatar_url: &str) -> Result<WebhookIdentity, String> {
+    let avatar_url = avatar_url.trim();
+    if !avatar_url.is_empty() && !avatar_url.starts_with("https://") {
+        return Err(format!(
+            "the webhook avatar {} isn't an https url",
+            avatar_url
+        ));
+    }
+
+    let set = |value: &str| (!value.is_empty()).then(|| value.to_owned());
+    Ok(WebhookIdentity {
+        username: set(username.trim()),
+        avatar_url: set(avatar_url),
+    })
+}
+
+/// how the logs look, e.g. so FAILURE logs stand out from the SUCCESSFUL ones
+#[derive(Clone, Debug, Default)]
+pub struct WebhookStyle {
+    pub successful: WebhookIdentity,
+    pub informational: WebhookIdentity,
+    pub failure: WebhookIdentity,
+    /// put in front of every log, e.g. to tell environments apart
+    pub prefix: String,
+}
+
+impl WebhookStyle {
+    pub fn identity(&self, log_type: &LOG) -> &WebhookIdentity {
+        match log_type {
+            LOG::SUCCESSFUL => &self.successful,
+            LOG::INFORMATIONAL => &self.informational,
+            LOG::FAILURE => &self.failure,
+        }
+    }
+}
+
+/// what a log is sent as
+#[derive(Debug, PartialEq, Eq)]
+pub struct LogPayload<'a> {
+    pub content: String,
+    pub username: Option<&'a str>,
+    pub avatar_url: Option<&'a str>,
+}
+
+/// colors the log by its level and picks the level's identity
+pub fn log_payload<'a>(content: &str, log_type: &LOG, style: &'a WebhookStyle) -> LogPayload<'a> {
     let color = match log_type {
         LOG::SUCCESSFUL => constants::SUCCESSFUL,
         LOG::INFORMATIONAL => constants::INFORMATIONAL,
         LOG::FAILURE => constants::FAILURE,
     };
+    let identity = style.identity(log_type);
+    let content = format!("{}{}", style.prefix, content);
 
-    let formatted_content = format!(
-        "```ansi\n{}{}```",
-        BACKGROUND,
-        content
-            .split(' ')
-            .map(|word| { format!("{}{}", color, word) })
-            .collect::<Vec<String>>()
-            .join(" ")
-    );
+    LogPayload {
+        content: format!(
+            "```ansi\n{}{}```",
+            BACKGROUND,
+            content
+                .split(' ')
+                .map(|word| { format!("{}{}", color, word) })
+                .collect::<Vec<String>>()
+                .join(" ")
+        ),
+        username: identity.username.as_deref(),
+        avatar_url: identity.avatar_url.as_deref(),
+    }
+}
+
+#[allow(unused_must_use)]
+
+pub async fn webhook_log(content: String, log_type: LOG) {
+    let (webhook_id, webhook_token) = {
+        let health = webhook_health().lock().unwrap();
+        if health.dead {
+            return eprintln!(
+                "skipped logging to the dead webhook {}: {}",
+                health.webhook_id, content
+            );
+        }
+        (health.webhook_id, health.webhook_token.clone())
+    };
+    // webhooks count towards Discord's global rate limit like every other call
+    if ensure_discord_available().await.is_err() {
+        return eprintln!("skipped logging while Discord is paused: {}", content);
+    }
+
+    let config = Config::new();
+    let client = Client::new(config.discord_token);
+    let webhook_id = Id::<WebhookMarker>::new(webhook_id);
+    let payload = log_payload(&content, &log_type, &config.webhook_style);
+
+    let mut pre_webhook_execution = match client
+        .execute_webhook(webhook_id, &webhook_token)
+        .content(payload.content.as_str())
+    {
+        Ok(value) => value,
+        Err(err) => {
+            return eprintln!("{:?}", err);
+        }
+    };
+    if let Some(username) = payload.username {
+        pre_webhook_execution = match pre_webhook_execution.username(username) {
+            Ok(value) => value,
+            Err(err) => {
+                return eprintln!("{:?}", err);
+            }
+        };
+    }
+    if let Some(avatar_url) = payload.avatar_url {
+        pre_webhook_execution = pre_webhook_execution.avatar_url(avatar_url);
+    }
+
+    let status = match pre_webhook_execution.exec().await {
+        Ok(response) => response.status().get(),
+        Err(error) => {
+            eprintln!("{:?}", error);
+            record_discord_error(&error).await;
+            match error.kind() {
+                ErrorType::Response { status, .. } => status.get(),
+                _ => return,
+            }
+        }
+    };
+
+    let died = webhook_health().lock().unwrap().record_response(status);
+    if died {
+        eprintln!(
+            "the webhook {} responded with {} {} times in a row and has been marked dead, replace it through the admin webhook endpoint",
+            webhook_id, status, DEAD_WEBHOOK_THRESHOLD
+        );
+    }
+}
+
+/// sends a rich embed through the logging webhook, used for summaries like the weekly digest
+#[allow(unused_must_use)]
+pub async fn webhook_embed(embed: Embed) {
+    let (webhook_id, webhook_token) = {
+        let health = webhook_health().lock().unwrap();
+        if health.dead {
+            return eprintln!(
+                "skipped sending an embed to the dead webhook {}",
+                health.webhook_id
+            );
+        }
+        (health.webhook_id, health.webhook_token.clone())
+    };
+
+    webhook_embed_to(webhook_id, &webhook_token, embed).await;
+}
+
+/// sends a rich embed through any webhook, e.g. the security webhook
+#[allow(unused_must_use)]
+pub async fn webhook_embed_to(webhook_id: u64, webhook_token: &str, embed: Embed) {
+    if ensure_discord_available().await.is_err() {
+        return eprintln!("skipped sending an embed while Discord is paused");
+    }
+
+    let config = Config::new();
+    let client = Client::new(config.discord_token);
+    let embeds = [embed];
 
     let pre_webhook_execution = match client
-        .execute_webhook(webhook_id, &config.webhook_token)
-        .content(formatted_content.as_str())
+        .execute_webhook(Id::<WebhookMarker>::new(webhook_id), webhook_token)
+        .embeds(&embeds)
     {
         Ok(value) => value,
         Err(err) => {
@@ -38,10 +266,203 @@ pub async fn webhook_log(content: String, log_type: LOG) {
         }
     };
 
-    pre_webhook_execution
-        .exec()
-        .await
-        .inspect_err(|error| eprintln!("{:?}", error));
+    if let Err(error) = pre_webhook_execution.exec().await {
+        eprintln!("{:?}", error);
+        record_discord_error(&error).await;
+    }
+}
+
+/// how often the throttled INFORMATIONAL logs get folded into a single aggregate line
+const AGGREGATE_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+static LOG_THROTTLE: OnceLock<Mutex<LogThrottle>> = OnceLock::new();
+
+/// Limits every user to one INFORMATIONAL log per window so a handful of players syncing every
+/// few minutes can't crowd the channel, the suppressed logs are only counted.
+pub struct LogThrottle {
+    recently_logged: TtlMap<String, ()>,
+    suppressed: HashMap<String, u32>,
+    aggregate_started: Instant,
+}
+
+impl LogThrottle {
+    pub fn new(window: Duration, now: Instant) -> Self {
+        LogThrottle {
+            recently_logged: TtlMap::new(window),
+            suppressed: HashMap::new(),
+            aggregate_started: now,
+        }
+    }
+
+    /// decides whether an INFORMATIONAL log about the user should be sent
+    pub fn allow(&mut self, discord_id: &str, now: Instant) -> bool {
+        let discord_id = discord_id.to_owned();
+        if self.recently_logged.contains_key(&discord_id, now) {
+            *self.suppressed.entry(discord_id).or_insert(0) += 1;
+            return false;
+        }
+
+        self.recently_logged.insert(discord_id, (), now);
+        true
+    }
+
+    /// drops everything the throttle knows about the user
+    pub fn forget(&mut self, discord_id: &str) {
+        self.recently_logged.remove(&discord_id.to_owned());
+        self.suppressed.remove(discord_id);
+    }
+
+    /// the line summarizing what was suppressed, handed out at most once per `AGGREGATE_WINDOW`
+    pub fn take_aggregate(&mut self, now: Instant) -> Option<String> {
+        if now.duration_since(self.aggregate_started) < AGGREGATE_WINDOW {
+            return None;
+        }
+        self.aggregate_started = now;
+        self.recently_logged.purge_expired(now);
+
+        if self.suppressed.is_empty() {
+            return None;
+        }
+
+        let mut suppressed = self.suppressed.drain().collect::<Vec<(String, u32)>>();
+        suppressed.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        Some(format!(
+            "throttled {} informational logs from {} users in the last hour: {}",
+            suppressed.iter().map(|(_, count)| count).sum::<u32>(),
+            suppressed.len(),
+            suppressed
+                .iter()
+                .map(|(discord_id, count)| format!("{} ({})", discord_id, count))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ))
+    }
+}
+
+pub fn log_throttle() -> &'static Mutex<LogThrottle> {
+    LOG_THROTTLE.get_or_init(|| {
+        Mutex::new(LogThrottle::new(
+            Duration::from_secs(Config::new().log_throttle_window),
+            Instant::now(),
+        ))
+    })
+}
+
+/// Logs something about a specific user, INFORMATIONAL logs go through the per-user throttle
+/// while SUCCESSFUL and FAILURE logs are always sent. `content` is only built for logs that are sent.
+pub async fn webhook_log_for_user(
+    discord_id: &str,
+    log_type: LOG,
+    content: impl FnOnce() -> String,
+) {
+    if let LOG::INFORMATIONAL = log_type {
+        let (allowed, aggregate) = {
+            let now = Instant::now();
+            let mut throttle = log_throttle().lock().unwrap();
+            (
+                throttle.allow(discord_id, now),
+                throttle.take_aggregate(now),
+            )
+        };
+
+        if let Some(aggregate) = aggregate {
+            webhook_log(aggregate, LOG::INFORMATIONAL).await;
+        }
+        if !allowed {
+            return;
+        }
+    }
+
+    webhook_log(content(), log_type).await;
+}
+
+#[test]
+fn throttle_allows_one_log_per_window() {
+    let start = Instant::now();
+    let mut throttle = LogThrottle::new(Duration::from_secs(60 * 60), start);
+
+    assert!(throttle.allow("123", start));
+    assert!(!throttle.allow("123", start + Duration::from_secs(60)));
+    assert!(!throttle.allow("123", start + Duration::from_secs(60 * 60 - 1)));
+    // other users have their own window
+    assert!(throttle.allow("456", start + Duration::from_secs(60)));
+    // crossing the window boundary lets the user through again
+    assert!(throttle.allow("123", start + Duration::from_secs(60 * 60)));
+}
+
+#[test]
+fn throttled_logs_are_folded_into_the_aggregate() {
+    let start = Instant::now();
+    let mut throttle = LogThrottle::new(Duration::from_secs(60 * 60), start);
+
+    throttle.allow("123", start);
+    throttle.allow("123", start + Duration::from_secs(1));
+    throttle.allow("123", start + Duration::from_secs(2));
+    throttle.allow("456", start + Duration::from_secs(3));
+    throttle.allow("456", start + Duration::from_secs(4));
+
+    assert_eq!(
+        throttle.take_aggregate(start + Duration::from_secs(5)),
+        None
+    );
+    assert_eq!(
+        throttle.take_aggregate(start + AGGREGATE_WINDOW),
+        Some(
+            "throttled 3 informational logs from 2 users in the last hour: 123 (2), 456 (1)"
+                .to_owned()
+        )
+    );
+    // the counts reset once they've been reported
+    assert_eq!(throttle.take_aggregate(start + AGGREGATE_WINDOW * 2), None);
+}
+
+#[test]
+fn forgotten_users_are_not_throttled_or_aggregated() {
+    let start = Instant::now();
+    let mut throttle = LogThrottle::new(Duration::from_secs(60 * 60), start);
+
+    throttle.allow("123", start);
+    throttle.allow("123", start + Duration::from_secs(1));
+    throttle.forget("123");
+
+    assert!(throttle.allow("123", start + Duration::from_secs(2)));
+    assert_eq!(throttle.take_aggregate(start + AGGREGATE_WINDOW), None);
+}
+
+#[test]
+fn webhook_is_marked_dead_after_consecutive_not_found() {
+    let mut health = WebhookHealth::new(1, "token".to_owned());
+
+    assert!(!health.record_response(404));
+    // a successful delivery in between resets the streak
+    assert!(!health.record_response(204));
+    assert!(!health.record_response(404));
+    assert!(!health.record_response(401));
+    assert!(health.record_response(404));
+    assert!(health.status().dead);
+    // it's only reported as newly dead once
+    assert!(!health.record_response(404));
+}
+
+#[test]
+fn replacing_the_webhook_clears_the_dead_marker() {
+    let mut health = WebhookHealth::new(1, "token".to_owned());
+    for _ in 0..DEAD_WEBHOOK_THRESHOLD {
+        health.record_response(404);
+    }
+    assert!(health.status().dead);
+
+    health.replace(2, "new token".to_owned());
+
+    assert_eq!(
+        health.status(),
+        WebhookStatus {
+            webhook_id: 2,
+            consecutive_failures: 0,
+            dead: false,
+        }
+    );
 }
 
 #[tokio::test]
@@ -70,3 +491,66 @@ async fn successful_log() -> () {
     )
     .await;
 }
+
+#[cfg(test)]
+fn style() -> WebhookStyle {
+    WebhookStyle {
+        failure: parse_webhook_identity("Alarm", "https://example.com/alarm.png").unwrap(),
+        informational: parse_webhook_identity("Quiet", "").unwrap(),
+        ..WebhookStyle::default()
+    }
+}
+
+#[test]
+fn log_levels_are_sent_with_their_identity() {
+    let style = style();
+
+    let failure = log_payload("it broke", &LOG::FAILURE, &style);
+    assert_eq!(failure.username, Some("Alarm"));
+    assert_eq!(failure.avatar_url, Some("https://example.com/alarm.png"));
+
+    // unset parts keep the webhook's own
+    let informational = log_payload("synced", &LOG::INFORMATIONAL, &style);
+    assert_eq!(informational.username, Some("Quiet"));
+    assert_eq!(informational.avatar_url, None);
+    let successful = log_payload("gained roles", &LOG::SUCCESSFUL, &style);
+    assert_eq!((successful.username, successful.avatar_url), (None, None));
+}
+
+#[test]
+fn the_prefix_leads_every_log() {
+    let style = WebhookStyle {
+        prefix: "[beta] ".to_owned(),
+        ..style()
+    };
+
+    let payload = log_payload("it broke", &LOG::FAILURE, &style);
+    assert_eq!(
+        payload.content,
+        format!(
+            "```ansi\n{}{}[beta] {}it {}broke```",
+            BACKGROUND,
+            constants::FAILURE,
+            constants::FAILURE,
+            constants::FAILURE
+        )
+    );
+    assert!(
+        log_payload("it broke", &LOG::FAILURE, &WebhookStyle::default())
+            .content
+            .ends_with(&format!(
+                "{}it {}broke```",
+                constants::FAILURE,
+                constants::FAILURE
+            ))
+    );
+}
+
+#[test]
+fn webhook_avatars_have_to_be_https() {
+    assert!(parse_webhook_identity("Alarm", "http://example.com/alarm.png").is_err());
+    assert_eq!(
+        parse_webhook_identity(" ", " ").unwrap(),
+        WebhookIdentity::default()
+    );
+}
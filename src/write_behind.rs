@@ -0,0 +1,324 @@
+use actix_web::rt::time;
+use deadpool_postgres::Pool;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Mutex, OnceLock, RwLock,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+use crate::{
+    abuse_snapshot::write_snapshot,
+    constants::ErrorLogType,
+    db,
+    deprecations::usage_columns,
+    digest::digest_count_columns,
+    errors::{ConvertResultErrorToMyError, LogMyError, MyError},
+};
+
+/// how often the buffered counters are written
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+/// how many distinct keys a table can buffer before it's flushed right away
+pub const MAX_KEYS_PER_TABLE: usize = 10_000;
+
+static WRITE_BEHIND: OnceLock<WriteBehindBuffer> = OnceLock::new();
+
+/// every table that's written through the buffer, each gets one multi-row upsert per flush
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CounterTable {
+    /// how often each milestone role was granted
+    RoleGrantCounts,
+    /// how often each user synced their progress
+    UserActivity,
+    /// how often deprecated features were used, keyed by `{feature}|{unix day}|{fingerprint}`
+    DeprecationUsage,
+    /// what the weekly digest counts per hour, keyed by `{kind}|{unix hour}|{name}`
+    DigestCounts,
+}
+
+impl CounterTable {
+    pub const ALL: [CounterTable; 4] = [
+        CounterTable::RoleGrantCounts,
+        CounterTable::UserActivity,
+        CounterTable::DeprecationUsage,
+        CounterTable::DigestCounts,
+    ];
+}
+
+/// the counts of a table accumulated since the last flush
+#[derive(Debug, PartialEq)]
+pub struct CounterBatch {
+    pub table: CounterTable,
+    pub keys: Vec<String>,
+    pub counts: Vec<i64>,
+}
+
+#[derive(Serialize, Default, Clone, Copy, Debug)]
+pub struct FlushStats {
+    pub flushes: u64,
+    pub failed_flushes: u64,
+    pub last_flush_latency_ms: u64,
+    /// keys that were thrown away because their table was full and hadn't been flushed yet
+    pub dropped_keys: u64,
+}
+
+/// Counters that are accumulated in memory and written to the database in batches by a single
+/// task, instead of every request issuing its own write.
+pub struct WriteBehindBuffer {
+    tables: HashMap<CounterTable, RwLock<HashMap<String, AtomicI64>>>,
+    max_keys: usize,
+    interval: Duration,
+    last_flush: Mutex<Instant>,
+    dropped_keys: AtomicU64,
+    stats: Mutex<FlushStats>,
+    spill: Notify,
+}
+
+impl WriteBehindBuffer {
+    pub fn new(interval: Duration, max_keys: usize, now: Instant) -> Self {
+        WriteBehindBuffer {
+            tables: CounterTable::ALL
+                .into_iter()
+                .map(|table| (table, RwLock::new(HashMap::new())))
+                .collect(),
+            max_keys,
+            interval,
+            last_flush: Mutex::new(now),
+            dropped_keys: AtomicU64::new(0),
+            stats: Mutex::new(FlushStats::default()),
+            spill: Notify::new(),
+        }
+    }
+
+    /// Adds `delta` to the key's count, returns false when the key was dropped because the table
+    /// is full. A table that reaches its key cap wakes up the flusher right away.
+    pub fn add(&self, table: CounterTable, key: &str, delta: i64) -> bool {
+        let counters = &self.tables[&table];
+        if let Some(count) = counters.read().unwrap().get(key) {
+            count.fetch_add(delta, Ordering::Relaxed);
+            return true;
+        }
+
+        let mut counters = counters.write().unwrap();
+        if !counters.contains_key(key) && counters.len() >= self.max_keys {
+            self.dropped_keys.fetch_add(1, Ordering::Relaxed);
+            self.spill.notify_one();
+            return false;
+        }
+        counters
+            .entry(key.to_owned())
+            .or_insert_with(|| AtomicI64::new(0))
+            .fetch_add(delta, Ordering::Relaxed);
+        if counters.len() >= self.max_keys {
+            self.spill.notify_one();
+        }
+
+        true
+    }
+
+    /// drops the key's count that hasn't been flushed yet, e.g. when its user was deleted
+    pub fn forget(&self, table: CounterTable, key: &str) {
+        self.tables[&table].write().unwrap().remove(key);
+    }
+
+    /// how long until the next interval flush is due
+    pub fn until_due(&self, now: Instant) -> Duration {
+        (*self.last_flush.lock().unwrap() + self.interval).saturating_duration_since(now)
+    }
+
+    /// empties every table, tables without any counts are left out
+    pub fn take_batches(&self, now: Instant) -> Vec<CounterBatch> {
+        *self.last_flush.lock().unwrap() = now;
+
+        CounterTable::ALL
+            .into_iter()
+            .filter_map(|table| {
+                let counters = std::mem::take(&mut *self.tables[&table].write().unwrap());
+                let (keys, counts) = counters
+                    .into_iter()
+                    .map(|(key, count)| (key, count.into_inner()))
+                    .filter(|(_, count)| *count != 0)
+                    .unzip::<String, i64, Vec<String>, Vec<i64>>();
+                (!keys.is_empty()).then_some(CounterBatch {
+                    table,
+                    keys,
+                    counts,
+                })
+            })
+            .collect()
+    }
+
+    pub fn stats(&self) -> FlushStats {
+        FlushStats {
+            dropped_keys: self.dropped_keys.load(Ordering::Relaxed),
+            ..*self.stats.lock().unwrap()
+        }
+    }
+}
+
+pub fn write_behind() -> &'static WriteBehindBuffer {
+    WRITE_BEHIND
+        .get_or_init(|| WriteBehindBuffer::new(FLUSH_INTERVAL, MAX_KEYS_PER_TABLE, Instant::now()))
+}
+
+/// Flushes the buffer every `FLUSH_INTERVAL`, or right away when a table fills up. The abuse
+/// counters are snapshotted along with every flush.
+pub fn spawn_flusher(pool: Pool) {
+    actix_web::rt::spawn(async move {
+        let buffer = write_behind();
+        loop {
+            let _ = time::timeout(buffer.until_due(Instant::now()), buffer.spill.notified()).await;
+            let _ = flush(&pool).await;
+            let _ = write_snapshot(&pool).await;
+        }
+    });
+}
+
+/// writes everything that's buffered, this is also awaited once on shutdown
+pub async fn flush(pool: &Pool) -> Result<(), MyError> {
+    let buffer = write_behind();
+    let started = Instant::now();
+    let batches = buffer.take_batches(started);
+    if batches.is_empty() {
+        return Ok(());
+    }
+
+    let result = write_batches(pool, &batches).await;
+    let mut stats = buffer.stats.lock().unwrap();
+    match result {
+        Ok(()) => {
+            stats.flushes += 1;
+            stats.last_flush_latency_ms = started.elapsed().as_millis() as u64;
+        }
+        Err(_) => {
+            stats.failed_flushes += 1;
+            drop(stats);
+            // the counts are put back so the next flush can retry them
+            for batch in &batches {
+                for (key, count) in batch.keys.iter().zip(&batch.counts) {
+                    buffer.add(batch.table, key, *count);
+                }
+            }
+        }
+    }
+
+    result
+}
+
+async fn write_batches(pool: &Pool, batches: &[CounterBatch]) -> Result<(), MyError> {
+    let client = pool
+        .get()
+        .await
+        .make_response(MyError::InternalError(
+            "flushing counters failed at creating database client",
+        ))
+        .make_log(ErrorLogType::INTERNAL)
+        .await?;
+
+    for batch in batches {
+        let result = match batch.table {
+            CounterTable::RoleGrantCounts => {
+                db::upsert_role_grant_counts(&client, &batch.keys, &batch.counts).await
+            }
+            CounterTable::UserActivity => {
+                db::upsert_user_activity(&client, &batch.keys, &batch.counts).await
+            }
+            CounterTable::DeprecationUsage => {
+                let (features, days, fingerprints, uses) =
+                    usage_columns(&batch.keys, &batch.counts);
+                db::upsert_deprecation_usage(&client, &features, &days, &fingerprints, &uses).await
+            }
+            CounterTable::DigestCounts => {
+                let (kinds, hours, names, counts) =
+                    digest_count_columns(&batch.keys, &batch.counts);
+                db::upsert_digest_counts(&client, &kinds, &hours, &names, &counts).await
+            }
+        };
+        result
+            .make_response(MyError::InternalError("flushing counters failed"))
+            .make_log(ErrorLogType::INTERNAL)
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn counters_are_flushed_once_the_interval_passes() {
+    let start = Instant::now();
+    let buffer = WriteBehindBuffer::new(FLUSH_INTERVAL, 10, start);
+
+    buffer.add(CounterTable::UserActivity, "1", 1);
+    buffer.add(CounterTable::UserActivity, "1", 2);
+    buffer.add(CounterTable::RoleGrantCounts, "Beta Tester", 1);
+
+    assert_eq!(
+        buffer.until_due(start + Duration::from_secs(10)),
+        Duration::from_secs(20)
+    );
+    assert_eq!(buffer.until_due(start + FLUSH_INTERVAL), Duration::ZERO);
+
+    let batches = buffer.take_batches(start + FLUSH_INTERVAL);
+    assert_eq!(
+        batches,
+        vec![
+            CounterBatch {
+                table: CounterTable::RoleGrantCounts,
+                keys: vec!["Beta Tester".to_owned()],
+                counts: vec![1],
+            },
+            CounterBatch {
+                table: CounterTable::UserActivity,
+                keys: vec!["1".to_owned()],
+                counts: vec![3],
+            },
+        ]
+    );
+    assert_eq!(buffer.until_due(start + FLUSH_INTERVAL), FLUSH_INTERVAL);
+}
+
+#[test]
+fn shutdown_flush_takes_counts_before_they_are_due() {
+    let start = Instant::now();
+    let buffer = WriteBehindBuffer::new(FLUSH_INTERVAL, 10, start);
+
+    buffer.add(CounterTable::UserActivity, "1", 1);
+
+    assert_eq!(buffer.take_batches(start).len(), 1);
+    assert!(buffer.take_batches(start).is_empty());
+}
+
+#[test]
+fn forgotten_keys_are_not_flushed() {
+    let start = Instant::now();
+    let buffer = WriteBehindBuffer::new(FLUSH_INTERVAL, 10, start);
+
+    buffer.add(CounterTable::UserActivity, "1", 1);
+    buffer.add(CounterTable::UserActivity, "2", 1);
+    buffer.forget(CounterTable::UserActivity, "1");
+
+    let batches = buffer.take_batches(start);
+    assert_eq!(batches.len(), 1);
+    assert_eq!(batches[0].keys, vec!["2".to_owned()]);
+}
+
+#[test]
+fn keys_past_the_cap_are_dropped() {
+    let start = Instant::now();
+    let buffer = WriteBehindBuffer::new(FLUSH_INTERVAL, 2, start);
+
+    assert!(buffer.add(CounterTable::UserActivity, "1", 1));
+    assert!(buffer.add(CounterTable::UserActivity, "2", 1));
+    assert!(!buffer.add(CounterTable::UserActivity, "3", 1));
+    // existing keys and other tables aren't affected
+    assert!(buffer.add(CounterTable::UserActivity, "1", 1));
+    assert!(buffer.add(CounterTable::RoleGrantCounts, "Beta Tester", 1));
+    assert_eq!(buffer.stats().dropped_keys, 1);
+
+    buffer.take_batches(start);
+    assert!(buffer.add(CounterTable::UserActivity, "3", 1));
+}